@@ -1,10 +1,17 @@
 use anyhow::{Context, Result};
-use flintpkg::{bundle::extract_bundle, repo::read_manifest, run::start};
+use flintpkg::{
+    bundle::{
+        check_host_runtime, extract_bundle, portable_data_dir, portable_env, read_bundle_meta,
+    },
+    repo::read_manifest,
+    run::{env::EnvPolicy, start},
+    utils::temp::TempDir,
+};
 use std::{
     env::{self, current_exe},
+    fs,
     process::exit,
 };
-use temp_dir::TempDir;
 
 fn main() -> Result<()> {
     println!("BUNDLE");
@@ -16,14 +23,36 @@ fn main() -> Result<()> {
 
     let manifest = read_manifest(repo_path)?;
     // These have been validated to be there by the builder
-    let package_manifest = manifest.packages.first().unwrap();
-    let entrypoint = package_manifest.commands.first().unwrap();
+    let mut package_manifest = manifest.packages.first().unwrap().clone();
+    let command = package_manifest.commands.first().unwrap().clone();
+
+    // Clearer than the dynamic linker's errors, when built on a newer distribution
+    let entrypoint_path = repo_path
+        .join("installed")
+        .join(&package_manifest.id)
+        .join(command.path.to_string_lossy().trim_start_matches('/'));
+    if let Err(err) = check_host_runtime(&package_manifest.id, &entrypoint_path, repo_path) {
+        eprintln!("{err:#}");
+        exit(1);
+    }
+
+    if read_bundle_meta(repo_path)?.portable {
+        let data_path = portable_data_dir(&bundle_path);
+        fs::create_dir_all(&data_path)
+            .with_context(|| "Could not create the portable data directory")?;
+
+        package_manifest
+            .env
+            .get_or_insert_default()
+            .extend(portable_env(&data_path));
+    }
 
     let exit_code = start(
         repo_path,
-        package_manifest.clone(),
-        entrypoint.to_str().unwrap(),
+        package_manifest,
+        &command.name().unwrap(),
         env::args().collect(),
+        &EnvPolicy::default(),
     )
     .with_context(|| "Could not run bundle")?;
 
@@ -0,0 +1,215 @@
+use anyhow::{Context, Result, bail};
+use std::{
+    fs::{self, File},
+    path::Path,
+};
+
+use crate::{
+    chunks::{missing_chunks, store_chunk},
+    repo::{
+        PackageManifest, insert_package,
+        provenance::{Provenance, sign_provenance, store_provenance, verify_provenance},
+        read_manifest,
+    },
+    utils::temp::TempDir,
+};
+
+/// The package manifest of a build artifact, next to its `chunks/`
+pub const ARTIFACT_MANIFEST: &str = "package.yml";
+/// How the package in a build artifact was built, signed by the builder and recorded when it is inserted
+pub const ARTIFACT_PROVENANCE: &str = "provenance.yml";
+/// The signature of `ARTIFACT_PROVENANCE`
+pub const ARTIFACT_PROVENANCE_SIG: &str = "provenance.yml.sig";
+
+/// Whether an artifact path is written as a single tar, instead of a directory
+fn is_tar(artifact_path: &Path) -> bool {
+    artifact_path
+        .extension()
+        .is_some_and(|extension| extension == "tar")
+}
+
+/// Writes a built package out of the chunk store, without inserting it into any Repository.
+///
+/// The manifest is written as `package.yml`, its provenance signed with the key of `config_path` as `provenance.yml`,
+/// and the chunks under `chunks/`. A path ending in `.tar` is written as a single tar with the same layout,
+/// otherwise as a directory. Either is written next to `artifact_path` first, and moved there once complete.
+///
+/// # Errors
+///
+/// - Chunks missing from the chunk store
+/// - `artifact_path` already exists
+/// - Private key could not be read
+/// - Filesystem errors (Out of space, Permissions)
+pub fn write_artifact(
+    package: &PackageManifest,
+    provenance: &Provenance,
+    chunk_store_path: &Path,
+    artifact_path: &Path,
+    config_path: Option<&Path>,
+) -> Result<()> {
+    if artifact_path.exists() {
+        bail!("{} already exists.", artifact_path.display())
+    }
+    if !missing_chunks(&package.chunks, chunk_store_path).is_empty() {
+        bail!("Chunks of {} are missing from the chunk store.", package.id)
+    }
+
+    let manifest = serde_yaml::to_string(package)?;
+    let (provenance, signature) = sign_provenance(provenance, config_path)?;
+    // Manifest first, so it can be read from a tar before any chunk
+    let files = [
+        (ARTIFACT_MANIFEST, manifest.as_bytes()),
+        (ARTIFACT_PROVENANCE, provenance.as_bytes()),
+        (ARTIFACT_PROVENANCE_SIG, &signature),
+    ];
+
+    let mut tmp_path = artifact_path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = Path::new(&tmp_path);
+
+    let result = (|| -> Result<()> {
+        if is_tar(artifact_path) {
+            let mut archive = tar::Builder::new(File::create(tmp_path)?);
+
+            for (name, contents) in files {
+                let mut header = tar::Header::new_ustar();
+                header.set_size(contents.len() as u64);
+                header.set_mode(0o644);
+                header.set_cksum();
+                archive.append_data(&mut header, name, contents)?;
+            }
+
+            for chunk in &package.chunks {
+                let filename = chunk.filename();
+                archive.append_path_with_name(
+                    chunk_store_path.join(&filename),
+                    Path::new("chunks").join(&filename),
+                )?;
+            }
+
+            archive.into_inner()?.sync_all()?;
+        } else {
+            fs::create_dir_all(tmp_path.join("chunks"))?;
+            for chunk in &package.chunks {
+                let filename = chunk.filename();
+                fs::copy(
+                    chunk_store_path.join(&filename),
+                    tmp_path.join("chunks").join(&filename),
+                )?;
+            }
+            for (name, contents) in files {
+                fs::write(tmp_path.join(name), contents)?;
+            }
+        }
+
+        Ok(())
+    })();
+
+    if let Err(err) = result {
+        let _ = if tmp_path.is_dir() {
+            fs::remove_dir_all(tmp_path)
+        } else {
+            fs::remove_file(tmp_path)
+        };
+        return Err(err);
+    }
+    fs::rename(tmp_path, artifact_path)?;
+
+    Ok(())
+}
+
+/// Inserts a package written by [`write_artifact`] into a Repository, and its chunks into the chunk store.
+///
+/// The Repository's manifest is signed again, so this is done where its key is.
+/// The provenance the builder signed is recorded as is.
+///
+/// # Errors
+///
+/// - Invalid artifact, or files in it that don't belong to one
+/// - Provenance missing, not matching the package, or with an invalid signature
+/// - Chunks missing from the artifact, or not matching their hash
+/// - The package's metadata breaks the Repository's policy
+/// - Filesystem errors (Out of space, Permissions)
+pub fn insert_artifact(
+    artifact_path: &Path,
+    repo_path: &Path,
+    chunk_store_path: &Path,
+    config_path: Option<&Path>,
+) -> Result<PackageManifest> {
+    // Only needed for a tar, removed once inserted
+    let extract = TempDir::new()?;
+    let artifact_dir = if is_tar(artifact_path) {
+        extract_artifact(artifact_path, extract.path())?;
+        extract.path().to_path_buf()
+    } else {
+        artifact_path.to_path_buf()
+    };
+
+    let package: PackageManifest = serde_yaml::from_str(
+        &fs::read_to_string(artifact_dir.join(ARTIFACT_MANIFEST))
+            .with_context(|| format!("{} is not a build artifact", artifact_path.display()))?,
+    )?;
+    let provenance = fs::read_to_string(artifact_dir.join(ARTIFACT_PROVENANCE))
+        .with_context(|| "The artifact has no provenance")?;
+    let signature = fs::read(artifact_dir.join(ARTIFACT_PROVENANCE_SIG))
+        .with_context(|| "The artifact's provenance has no signature")?;
+    let verified = verify_provenance(&provenance, &signature)?;
+    if (&verified.package_id, &verified.build_hash) != (&package.id, &package.build_hash) {
+        bail!("The artifact's provenance is for a different package.")
+    }
+    let hash_kind = read_manifest(repo_path)?.hash_kind;
+
+    fs::create_dir_all(chunk_store_path)?;
+    for chunk in missing_chunks(&package.chunks, chunk_store_path) {
+        let chunk_path = artifact_dir.join("chunks").join(chunk.filename());
+        let data = fs::read(&chunk_path)
+            .with_context(|| format!("Artifact is missing chunk {}", chunk.filename()))?;
+
+        store_chunk(chunk, &data, hash_kind, chunk_store_path).with_context(|| {
+            format!(
+                "The artifact may have been built for a Repository not hashing with {hash_kind}"
+            )
+        })?;
+    }
+
+    insert_package(&package, repo_path, config_path)?;
+    // After inserting, which removes the provenance of the package it replaces
+    store_provenance(repo_path, &package.id, &provenance, &signature)?;
+
+    Ok(package)
+}
+
+/// Extracts an artifact tar, refusing anything but its manifest and chunks
+fn extract_artifact(artifact_path: &Path, extract_path: &Path) -> Result<()> {
+    fs::create_dir_all(extract_path.join("chunks"))?;
+
+    let file = File::open(artifact_path)
+        .with_context(|| format!("Could not open {}", artifact_path.display()))?;
+    let mut archive = tar::Archive::new(file);
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_path_buf();
+
+        // Nothing else is unpacked, so a crafted artifact can't write outside of it
+        let is_manifest = [
+            ARTIFACT_MANIFEST,
+            ARTIFACT_PROVENANCE,
+            ARTIFACT_PROVENANCE_SIG,
+        ]
+        .iter()
+        .any(|name| path == Path::new(name));
+        let is_chunk = path.parent() == Some(Path::new("chunks"))
+            && path
+                .file_name()
+                .is_some_and(|name| !name.to_string_lossy().starts_with('.'));
+
+        if !is_manifest && !is_chunk {
+            bail!("Unexpected file {} in build artifact.", path.display())
+        }
+
+        entry.unpack(extract_path.join(&path))?;
+    }
+
+    Ok(())
+}
@@ -3,7 +3,7 @@ use std::{fs, path::Path};
 use walkdir::WalkDir;
 
 use crate::{
-    bundle::pad_header,
+    bundle::{BUNDLE_META_FILE, BundleMeta, pad_header},
     repo::{get_installed_package, read_manifest},
 };
 
@@ -13,7 +13,7 @@ use crate::{
 ///
 /// - More/Less than one package found
 /// - Filesystem read errors
-pub fn build_bundle(header_path: &Path, repo_path: &Path) -> Result<Vec<u8>> {
+pub fn build_bundle(header_path: &Path, repo_path: &Path, meta: &BundleMeta) -> Result<Vec<u8>> {
     let header = fs::read(header_path)?;
     let mut header = pad_header(header)?;
 
@@ -26,7 +26,7 @@ pub fn build_bundle(header_path: &Path, repo_path: &Path) -> Result<Vec<u8>> {
 
         let _ = get_installed_package(repo_path, &package.id)?;
 
-        let mut tar = compress(repo_path)?;
+        let mut tar = compress(repo_path, meta)?;
         header.append(&mut tar);
 
         Ok(header)
@@ -35,9 +35,16 @@ pub fn build_bundle(header_path: &Path, repo_path: &Path) -> Result<Vec<u8>> {
     }
 }
 
-fn compress(repo_path: &Path) -> Result<Vec<u8>> {
+fn compress(repo_path: &Path, meta: &BundleMeta) -> Result<Vec<u8>> {
     let mut tar = tar::Builder::new(Vec::new());
 
+    let meta = serde_yaml::to_string(meta)?;
+    let mut header = tar::Header::new_ustar();
+    header.set_size(meta.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    tar.append_data(&mut header, BUNDLE_META_FILE, meta.as_bytes())?;
+
     for entry in WalkDir::new(repo_path).min_depth(1) {
         let file = entry?;
         let path = file.path();
@@ -56,6 +63,7 @@ fn compress(repo_path: &Path) -> Result<Vec<u8>> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::bundle::read_bundle_meta;
     use temp_dir::TempDir;
 
     #[test]
@@ -68,12 +76,19 @@ mod tests {
         fs::write(repo_path.join("chunks/file1"), "data1")?;
         fs::write(repo_path.join("manifest.yml"), "test manifest")?;
 
-        let compressed = compress(repo_path)?;
+        let compressed = compress(repo_path, &BundleMeta { portable: true })?;
 
         // Check that it's not empty
         assert!(!compressed.is_empty());
 
-        // TODO: decompress and check
+        let extract_dir = TempDir::new()?;
+        tar::Archive::new(compressed.as_slice()).unpack(extract_dir.path())?;
+        assert_eq!(
+            fs::read_to_string(extract_dir.path().join("chunks/file1"))?,
+            "data1"
+        );
+        assert!(read_bundle_meta(extract_dir.path())?.portable);
+
         Ok(())
     }
 }
@@ -0,0 +1,185 @@
+use anyhow::Result;
+use std::path::Path;
+
+use crate::{
+    chunks::{DownloadEvent, install_tree},
+    crypto::{key::deserialize_verifying_key, signing::verify_signature},
+    repo::{
+        BinaryCache, PackageManifest, RepoManifest, credentials::get_url,
+        manifest_io::parse_manifest,
+    },
+};
+
+/// Fetches a pre-built package from a binary cache, if it has one built from the same `build_hash`.
+/// The cache's manifest must be signed by its key, or by `repo_manifest`'s key if it has none.
+///
+/// # Errors
+///
+/// - Network Unavailable
+/// - Server Unavailable
+/// - Invalid signed data
+/// - Filesystem errors (Out of space, Permissions)
+///
+/// # Returns
+///
+/// The cached package, with its chunks in the chunk store.
+/// `None` if the cache has no matching build.
+pub async fn fetch_cached_build(
+    repo_manifest: &RepoManifest,
+    binary_cache: &BinaryCache,
+    package_id: &str,
+    build_hash: &str,
+    chunk_store_path: &Path,
+    progress: &(dyn Fn(DownloadEvent) + Sync),
+) -> Result<Option<PackageManifest>> {
+    let url = binary_cache.url.trim_end_matches('/');
+
+    let raw_manifest = get_url(&format!("{url}/manifest.yml"), None)
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    let signature = get_url(&format!("{url}/manifest.yml.sig"), None)
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+
+    let public_key = binary_cache
+        .public_key
+        .as_ref()
+        .unwrap_or(&repo_manifest.public_key);
+    verify_signature(
+        &raw_manifest,
+        &signature,
+        deserialize_verifying_key(public_key)?,
+    )?;
+
+    let cache_manifest = parse_manifest(&raw_manifest)?;
+
+    // Chunks hashed differently would not match anything in this Repository
+    if cache_manifest.hash_kind != repo_manifest.hash_kind {
+        return Ok(None);
+    }
+
+    let Some(package) = cache_manifest
+        .packages
+        .into_iter()
+        .find(|package| package.id == package_id && package.build_hash == build_hash)
+    else {
+        return Ok(None);
+    };
+
+    install_tree(
+        &package.chunks,
+        chunk_store_path,
+        &[url.to_string()],
+        cache_manifest.hash_kind,
+        None,
+        progress,
+    )
+    .await?;
+
+    Ok(Some(package))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chunks::save_tree,
+        crypto::signing::sign_detached,
+        repo::{create_repo, read_manifest, serialize_manifest},
+    };
+    use httpmock::prelude::*;
+    use std::fs;
+    use temp_dir::TempDir;
+
+    #[tokio::test]
+    async fn test_fetch_cached_build() -> Result<()> {
+        let cache_repo = TempDir::new()?;
+        let cache_chunks = TempDir::new()?;
+        let tree = TempDir::new()?;
+        let chunk_store = TempDir::new()?;
+
+        create_repo(cache_repo.path(), Some(cache_repo.path()))?;
+        fs::write(tree.path().join("hello"), "hello")?;
+
+        let mut cache_manifest = read_manifest(cache_repo.path())?;
+        cache_manifest.packages.push(PackageManifest {
+            id: "hello".into(),
+            chunks: save_tree(tree.path(), cache_chunks.path(), cache_manifest.hash_kind)?,
+            build_hash: "abc".into(),
+            ..Default::default()
+        });
+        let serialized = serialize_manifest(cache_repo.path(), &cache_manifest)?;
+        let signature = sign_detached(&serialized, Some(cache_repo.path()))?;
+
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.method(GET).path("/manifest.yml");
+            then.status(200).body(&serialized);
+        });
+        server.mock(|when, then| {
+            when.method(GET).path("/manifest.yml.sig");
+            then.status(200).body(signature.to_bytes());
+        });
+        for chunk in &cache_manifest.packages[0].chunks {
+            let data = fs::read(cache_chunks.path().join(chunk.filename()))?;
+            server.mock(|when, then| {
+                when.method(GET)
+                    .path(format!("/chunks/{}", chunk.filename()));
+                then.status(200).body(data);
+            });
+        }
+
+        let binary_cache = BinaryCache {
+            url: server.base_url(),
+            public_key: None,
+        };
+
+        let package = fetch_cached_build(
+            &cache_manifest,
+            &binary_cache,
+            "hello",
+            "abc",
+            chunk_store.path(),
+            &|_| {},
+        )
+        .await?;
+        assert_eq!(package.as_ref(), cache_manifest.packages.first());
+        assert_eq!(fs::read_dir(chunk_store.path())?.count(), 1);
+
+        // A different build has to be compiled locally
+        assert!(
+            fetch_cached_build(
+                &cache_manifest,
+                &binary_cache,
+                "hello",
+                "def",
+                chunk_store.path(),
+                &|_| {},
+            )
+            .await?
+            .is_none()
+        );
+
+        // Signed by some other key
+        let other_repo = TempDir::new()?;
+        create_repo(other_repo.path(), Some(other_repo.path()))?;
+        assert!(
+            fetch_cached_build(
+                &read_manifest(other_repo.path())?,
+                &binary_cache,
+                "hello",
+                "abc",
+                chunk_store.path(),
+                &|_| {},
+            )
+            .await
+            .is_err()
+        );
+
+        Ok(())
+    }
+}
@@ -3,8 +3,7 @@ use std::fs;
 use std::io::Write;
 use std::path::Path;
 
-use super::BuildManifest;
-use crate::repo::{get_package, read_manifest};
+use super::{BuildManifest, resolve_include};
 
 /// Get the `build_hash` of a `build_manifest`
 /// Requires all dependencies to be built and in the Repository beforehand.
@@ -17,10 +16,13 @@ pub fn calc_build_hash(build_manifest_path: &Path, repo_path: &Path) -> Result<S
     let build_manifest_path = build_manifest_path.canonicalize().with_context(
         || "could not canoncicalize build manifest path. Does the build manifest exist?",
     )?;
-    let build_manifest_raw = fs::read_to_string(build_manifest_path)?;
+    let build_manifest_raw = fs::read_to_string(&build_manifest_path)?;
     let build_manifest: BuildManifest = serde_yaml::from_str(&build_manifest_raw)?;
 
-    let repo_manifest = read_manifest(repo_path)?;
+    // Scripts are relative to the build manifest, same as when building
+    let search_path = build_manifest_path
+        .parent()
+        .unwrap_or_else(|| Path::new("/"));
 
     let mut hash = blake3::Hasher::new();
 
@@ -29,7 +31,7 @@ pub fn calc_build_hash(build_manifest_path: &Path, repo_path: &Path) -> Result<S
     // Hash the `includes`
     if let Some(deps) = build_manifest.include {
         for dep in deps {
-            let package = get_package(&repo_manifest, &dep)?;
+            let package = resolve_include(search_path, &dep, repo_path)?;
             hash.write_all(package.build_hash.as_bytes())?;
         }
     }
@@ -37,20 +39,26 @@ pub fn calc_build_hash(build_manifest_path: &Path, repo_path: &Path) -> Result<S
     // Hash the `sdks`
     if let Some(deps) = build_manifest.sdks {
         for dep in deps {
-            let package = get_package(&repo_manifest, &dep)?;
+            let package = resolve_include(search_path, &dep, repo_path)?;
             hash.write_all(package.build_hash.as_bytes())?;
         }
     }
 
     // Hash the `build_script`
     if let Some(build_script) = build_manifest.build_script {
-        let script = fs::read_to_string(build_script)?;
+        let script = fs::read_to_string(search_path.join(build_script))?;
         hash.write_all(script.as_bytes())?;
     }
 
     // Hash the `post_script`
     if let Some(post_script) = build_manifest.post_script {
-        let script = fs::read_to_string(post_script)?;
+        let script = fs::read_to_string(search_path.join(post_script))?;
+        hash.write_all(script.as_bytes())?;
+    }
+
+    // Hash the `test_script`
+    if let Some(test_script) = build_manifest.test_script {
+        let script = fs::read_to_string(search_path.join(test_script))?;
         hash.write_all(script.as_bytes())?;
     }
 
@@ -70,22 +78,23 @@ mod tests {
         let manifest = BuildManifest {
             id: "test_package".into(),
             aliases: Vec::new(),
-            metadata: Metadata {
-                description: None,
-                homepage_url: None,
-                title: None,
-                version: None,
-                license: None,
-            },
+            metadata: Metadata::default(),
             commands: Vec::new(),
             directory: PathBuf::from("."),
             edition: "2025".into(),
             build_script: None,
             post_script: None,
+            test_script: None,
             sources: None,
             include: None,
             sdks: None,
             env: None,
+            dependencies: None,
+            interpreters: None,
+            requirements: None,
+            rpath: None,
+            env_scripts: None,
+            provides: None,
         };
 
         let repo = TempDir::new().unwrap();
@@ -1,22 +1,35 @@
+pub mod artifact;
 pub mod bundle;
+#[cfg(feature = "network")]
+pub mod cache;
 pub mod hash;
+mod relocate;
 mod sources;
 
 use anyhow::{Context, Result, bail};
 use std::{
     collections::HashMap,
     fs,
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
     process::Command,
 };
-use temp_dir::TempDir;
 
 use crate::{
-    chunks::{load_tree, save_tree},
+    chunks::{DownloadEvent, load_tree, save_tree},
     crypto::key::{get_private_key, serialize_verifying_key},
-    repo::{Metadata, PackageManifest, get_package, insert_package, read_manifest},
+    repo::{
+        Interpreter, Metadata, PackageCommand, PackageManifest, Requirements, TestStatus,
+        get_package, get_provider, insert_package,
+        manifest_io::has_manifest,
+        provenance::{ProvenanceSource, new_provenance, now, write_provenance},
+        read_manifest,
+        settings::get_settings,
+    },
+    utils::temp::{TempDir, get_temp_root},
 };
+use artifact::write_artifact;
 use hash::calc_build_hash;
+use relocate::{LibraryDir, set_runpaths};
 use sources::get_sources;
 
 #[derive(serde::Deserialize, serde::Serialize, Clone)]
@@ -28,9 +41,9 @@ struct BuildManifest {
     aliases: Vec<String>,
     /// Package Metadata
     metadata: Metadata,
-    /// A list of commands that this will give access to
+    /// A list of commands that this will give access to, as paths or with a name, args and working dir
     #[serde(default)]
-    commands: Vec<PathBuf>,
+    commands: Vec<PackageCommand>,
     /// Directory relative to the manifest
     directory: PathBuf,
     /// Edition
@@ -39,6 +52,9 @@ struct BuildManifest {
     build_script: Option<PathBuf>,
     /// Script to be run after `build_script` but before packaging
     post_script: Option<PathBuf>,
+    /// Script to be run against the staged output, after `post_script`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    test_script: Option<PathBuf>,
     /// Sources to pull when building
     sources: Option<Vec<Source>>,
     /// ``SubPackages`` to be included directly into the output AND at build time.
@@ -48,6 +64,24 @@ struct BuildManifest {
     sdks: Option<Vec<String>>,
     /// RUNTIME environment variables
     env: Option<HashMap<String, String>>,
+    /// IDs of packages in the same Repository needed at runtime
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dependencies: Option<Vec<String>>,
+    /// Script and ELF interpreters to point at dependencies on install
+    #[serde(skip_serializing_if = "Option::is_none")]
+    interpreters: Option<Vec<Interpreter>>,
+    /// What the host needs to run the package, checked on install and run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    requirements: Option<Requirements>,
+    /// Library directories to set as the RUNPATH of every dynamic binary, needs patchelf
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rpath: Option<Vec<LibraryDir>>,
+    /// Shell snippets sourced by `flint env` and `flint shell`, relative to `directory`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    env_scripts: Option<Vec<PathBuf>>,
+    /// Virtual package names this package satisfies, eg: `python3`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    provides: Option<Vec<String>>,
 }
 
 #[derive(serde::Deserialize, serde::Serialize, Clone)]
@@ -62,62 +96,311 @@ struct Source {
     commit: Option<String>,
 }
 
-/// Builds and inserts a package into a Repository from a `build_manifest`
+impl From<&Source> for ProvenanceSource {
+    fn from(source: &Source) -> Self {
+        Self {
+            kind: source.kind.clone(),
+            url: source.url.clone(),
+            commit: source.commit.clone(),
+        }
+    }
+}
+
+/// A finished build, and what it worked around on the way
+#[derive(Debug)]
+pub struct Built {
+    pub package: PackageManifest,
+    /// Repositories next to the target one that could not be read, so `include`s and `sdks`
+    /// were resolved without them
+    pub skipped_repos: Vec<SkippedRepo>,
+    /// Why the binary cache could not be used, so the package was built locally
+    pub cache_error: Option<anyhow::Error>,
+    /// Where the build directory was kept, with `keep_build_dir`
+    pub kept_build_dir: Option<PathBuf>,
+}
+
+/// A Repository left out when resolving `include`s and `sdks`, and why
+#[derive(Debug)]
+pub struct SkippedRepo {
+    pub path: PathBuf,
+    pub error: anyhow::Error,
+}
+
+/// Builds and inserts a package into a Repository from a `build_manifest`, unless it is up to date.
+///
+/// If the Repository has a binary cache with a package built from the same `build_hash`, that is used instead.
+/// A cache that can't be reached is skipped, with its error in [`Built::cache_error`].
 ///
 /// # Errors
 ///
 /// - Filesystem (Out of Space, Permissions)
 /// - Build Script Failure
+#[cfg_attr(not(feature = "network"), allow(unused_variables))]
 pub async fn build(
     build_manifest_path: &Path,
     repo_path: &Path,
     config_path: Option<&Path>,
     chunk_store_path: &Path,
-) -> Result<PackageManifest> {
+    skip_tests: bool,
+    keep_build_dir: bool,
+    progress: &(dyn Fn(DownloadEvent) + Sync),
+) -> Result<Built> {
     let repo = read_manifest(repo_path)?;
     let build_manifest: BuildManifest =
         serde_yaml::from_str(&fs::read_to_string(build_manifest_path)?)?;
 
-    if let Ok(package) = get_package(&repo, &build_manifest.id) {
-        let next_build_hash = calc_build_hash(build_manifest_path, repo_path)?;
-        if package.build_hash == next_build_hash {
-            return Ok(package);
-        }
+    let next_build_hash = calc_build_hash(build_manifest_path, repo_path)?;
+
+    if let Ok(package) = get_package(&repo, &build_manifest.id)
+        && package.build_hash == next_build_hash
+    {
+        return Ok(Built {
+            package,
+            skipped_repos: skipped_repos(&build_manifest, repo_path)?,
+            cache_error: None,
+            kept_build_dir: None,
+        });
     }
 
     let our_public_key = serialize_verifying_key(get_private_key(None)?.verifying_key())?;
-    if repo.public_key != our_public_key {
+    if !repo.trusted_keys().contains(&our_public_key.as_str()) {
         bail!(
             "You do not have the correct signing key to resign this Repository.\nIf you are certain, use --force, but be aware you will not be able to update this Repository from the remote source again."
         );
     }
 
-    force_build(
+    #[cfg(feature = "network")]
+    let cache_error = if let Some(binary_cache) = &repo.binary_cache {
+        match cache::fetch_cached_build(
+            &repo,
+            binary_cache,
+            &build_manifest.id,
+            &next_build_hash,
+            chunk_store_path,
+            progress,
+        )
+        .await
+        {
+            Ok(Some(package)) => {
+                insert_package(&package, repo_path, config_path)?;
+                return Ok(Built {
+                    package,
+                    skipped_repos: skipped_repos(&build_manifest, repo_path)?,
+                    cache_error: None,
+                    kept_build_dir: None,
+                });
+            }
+            Ok(None) => None,
+            Err(err) => Some(err),
+        }
+    } else {
+        None
+    };
+    #[cfg(not(feature = "network"))]
+    let cache_error = None;
+
+    let built = force_build(
         build_manifest_path,
         repo_path,
         config_path,
         chunk_store_path,
+        skip_tests,
+        keep_build_dir,
     )
-    .await
+    .await?;
+
+    Ok(Built {
+        cache_error,
+        ..built
+    })
 }
 
-/// Builds and inserts a package into a Repository from a `build_manifest`
+/// Builds and inserts a package into a Repository from a `build_manifest`.
+///
+/// If the build fails, its build directory is kept for debugging and its path added to the error.
+/// `keep_build_dir` keeps it after successful builds too, see [`Built::kept_build_dir`].
+/// Build directories go in the temp dir of the config at `config_path`.
 ///
 /// # Errors
 ///
 /// - Filesystem (Out of Space, Permissions)
 /// - Build Script Failure
+/// - Test Script Failure (unless `skip_tests` is set)
 pub async fn force_build(
     build_manifest_path: &Path,
     repo_path: &Path,
     config_path: Option<&Path>,
     chunk_store_path: &Path,
-) -> Result<PackageManifest> {
-    let build_dir = TempDir::new()?;
+    skip_tests: bool,
+    keep_build_dir: bool,
+) -> Result<Built> {
+    build_with_dir(
+        build_manifest_path,
+        repo_path,
+        BuildOutput::Repository(config_path),
+        chunk_store_path,
+        skip_tests,
+        keep_build_dir,
+    )
+    .await
+}
+
+/// Builds a package from a `build_manifest` into an artifact at `artifact_path`, without inserting it anywhere.
+///
+/// See [`artifact::write_artifact`] for its layout, and [`artifact::insert_artifact`] to publish it later.
+///
+/// The Repository is only read, for `include`s, `sdks` and its hash kind, so its signing key isn't needed.
+/// The provenance is signed with the key of `config_path` and carried in the artifact, to be recorded when it is inserted.
+///
+/// # Errors
+///
+/// - `artifact_path` already exists
+/// - Filesystem (Out of Space, Permissions)
+/// - Build Script Failure
+/// - Test Script Failure (unless `skip_tests` is set)
+pub async fn build_artifact(
+    build_manifest_path: &Path,
+    repo_path: &Path,
+    artifact_path: &Path,
+    config_path: Option<&Path>,
+    chunk_store_path: &Path,
+    skip_tests: bool,
+    keep_build_dir: bool,
+) -> Result<Built> {
+    if artifact_path.exists() {
+        bail!("{} already exists.", artifact_path.display())
+    }
+
+    build_with_dir(
+        build_manifest_path,
+        repo_path,
+        BuildOutput::Artifact {
+            path: artifact_path,
+            config_path,
+        },
+        chunk_store_path,
+        skip_tests,
+        keep_build_dir,
+    )
+    .await
+}
+
+/// Where a finished build goes
+#[derive(Clone, Copy)]
+enum BuildOutput<'a> {
+    /// Inserted into the Repository, signed with the key of `config_path`
+    Repository(Option<&'a Path>),
+    /// Written as an artifact at `path`, its provenance signed with the key of `config_path`
+    Artifact {
+        path: &'a Path,
+        config_path: Option<&'a Path>,
+    },
+}
+
+impl<'a> BuildOutput<'a> {
+    const fn config_path(self) -> Option<&'a Path> {
+        match self {
+            Self::Repository(config_path) | Self::Artifact { config_path, .. } => config_path,
+        }
+    }
+}
+
+async fn build_with_dir(
+    build_manifest_path: &Path,
+    repo_path: &Path,
+    output: BuildOutput<'_>,
+    chunk_store_path: &Path,
+    skip_tests: bool,
+    keep_build_dir: bool,
+) -> Result<Built> {
+    // Under the temp dir of the config the build is signed with
+    let build_dir = TempDir::new_in(&get_temp_root(output.config_path())?)
+        .with_context(|| "Could not create the build directory")?;
     let build_manifest_path = &build_manifest_path.canonicalize()?;
 
     let build_manifest: BuildManifest =
         serde_yaml::from_str(&fs::read_to_string(build_manifest_path)?)?;
+    let package_id = build_manifest.id.clone();
+    let skipped_repos = skipped_repos(&build_manifest, repo_path)?;
+
+    let result = build_in(
+        build_dir.path(),
+        build_manifest,
+        build_manifest_path,
+        repo_path,
+        output,
+        chunk_store_path,
+        skip_tests,
+    )
+    .await;
+
+    let (package, kept_build_dir) = match result {
+        Err(err) => {
+            return match retain_build_dir(build_dir, &package_id) {
+                Ok(kept_path) => {
+                    Err(err.context(format!("Build directory kept at {}", kept_path.display())))
+                }
+                Err(_) => Err(err),
+            };
+        }
+        Ok(package) if keep_build_dir => (package, Some(retain_build_dir(build_dir, &package_id)?)),
+        Ok(package) => (package, None),
+    };
+
+    Ok(Built {
+        package,
+        skipped_repos,
+        cache_error: None,
+        kept_build_dir,
+    })
+}
+
+/// How many kept build directories are retained, older ones are removed
+const KEPT_BUILD_DIRS: usize = 5;
+
+/// Moves a build directory to `kept-builds/` in the temp root, where stale temp cleanup leaves it alone.
+/// Only the newest `KEPT_BUILD_DIRS` are retained.
+fn retain_build_dir(build_dir: TempDir, package_id: &str) -> Result<PathBuf> {
+    // Next to the build directory, so it is a rename on the same filesystem
+    let kept_root = build_dir
+        .path()
+        .parent()
+        .unwrap_or_else(|| Path::new("/"))
+        .join("kept-builds");
+    fs::create_dir_all(&kept_root)?;
+
+    // Timestamp first, so names sort oldest first
+    let name = format!("{}-{package_id}", now()?);
+    let mut kept_path = kept_root.join(&name);
+    let mut suffix = 1;
+    while kept_path.exists() {
+        kept_path = kept_root.join(format!("{name}.{suffix}"));
+        suffix += 1;
+    }
+
+    fs::rename(build_dir.keep(), &kept_path)?;
+
+    let mut kept: Vec<PathBuf> = fs::read_dir(&kept_root)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()?;
+    kept.sort();
+    for old in &kept[..kept.len().saturating_sub(KEPT_BUILD_DIRS)] {
+        fs::remove_dir_all(old)?;
+    }
+
+    Ok(kept_path)
+}
+
+async fn build_in(
+    build_dir: &Path,
+    build_manifest: BuildManifest,
+    build_manifest_path: &Path,
+    repo_path: &Path,
+    output: BuildOutput<'_>,
+    chunk_store_path: &Path,
+    skip_tests: bool,
+) -> Result<PackageManifest> {
+    let started_at = now()?;
 
     let repo_manifest =
         read_manifest(repo_path).with_context(|| "The target Repostiory does not exist")?;
@@ -126,9 +409,13 @@ pub async fn force_build(
         .parent()
         .unwrap_or_else(|| Path::new("/"));
 
-    if let Some(sources) = build_manifest.sources {
-        get_sources(build_dir.path(), search_path, &sources).await?;
-    }
+    let dependencies = build_manifest.dependencies.unwrap_or_default();
+    let interpreters = build_manifest.interpreters.unwrap_or_default();
+    let rpath = build_manifest.rpath.unwrap_or_default();
+    check_dependencies(&interpreters, &rpath, &dependencies)?;
+
+    let sources = build_manifest.sources.unwrap_or_default();
+    get_sources(build_dir, search_path, &sources).await?;
 
     let mut envs = build_manifest.env.unwrap_or_default();
 
@@ -136,7 +423,7 @@ pub async fn force_build(
         include_all(
             packages,
             search_path,
-            build_dir.path(),
+            build_dir,
             repo_path,
             chunk_store_path,
             &mut envs,
@@ -147,7 +434,7 @@ pub async fn force_build(
         include_all(
             packages,
             search_path,
-            build_dir.path(),
+            build_dir,
             repo_path,
             chunk_store_path,
             &mut envs,
@@ -155,10 +442,10 @@ pub async fn force_build(
     }
 
     if let Some(script) = build_manifest.build_script {
-        run_script(build_dir.path(), search_path, &script).with_context(|| "build_script")?;
+        run_script(build_dir, search_path, &script).with_context(|| "build_script")?;
     }
 
-    let out_dir = build_dir.path().join(&build_manifest.directory);
+    let out_dir = build_dir.join(&build_manifest.directory);
 
     if let Some(script) = build_manifest.post_script {
         run_script(&out_dir, search_path, &script).with_context(|| "post_script")?;
@@ -177,6 +464,26 @@ pub async fn force_build(
         }
     }
 
+    if !rpath.is_empty() {
+        set_runpaths(&out_dir, &rpath).with_context(|| "Failed to set RUNPATHs")?;
+    }
+
+    check_env_scripts(&out_dir, build_manifest.env_scripts.as_deref())?;
+    check_commands(&build_manifest.commands)?;
+
+    // Tests run against the staged output, with all `include`s in place
+    let tests = if let Some(script) = build_manifest.test_script {
+        if skip_tests {
+            Some(TestStatus::Skipped)
+        } else {
+            run_script(&out_dir, search_path, &script)
+                .with_context(|| "test_script failed. Use --skip-tests to ignore.")?;
+            Some(TestStatus::Passed)
+        }
+    } else {
+        None
+    };
+
     let chunks = save_tree(&out_dir, chunk_store_path, repo_manifest.hash_kind)?;
 
     included_chunks.extend(chunks);
@@ -189,17 +496,135 @@ pub async fn force_build(
         chunks: included_chunks,
         env: None,
         build_hash: calc_build_hash(build_manifest_path, repo_path)?,
+        tests,
+        dependencies,
+        interpreters,
+        requirements: build_manifest.requirements,
+        env_scripts: build_manifest.env_scripts.unwrap_or_default(),
+        provides: build_manifest.provides.unwrap_or_default(),
     };
 
     if !envs.is_empty() {
         package_manifest.env = Some(envs);
     }
 
-    insert_package(&package_manifest, repo_path, config_path)?;
+    output_build(
+        output,
+        &package_manifest,
+        &sources,
+        started_at,
+        repo_path,
+        chunk_store_path,
+    )?;
 
     Ok(package_manifest)
 }
 
+/// Inserts a finished build into the Repository, or writes it as an artifact, along with its provenance
+fn output_build(
+    output: BuildOutput<'_>,
+    package_manifest: &PackageManifest,
+    sources: &[Source],
+    started_at: u64,
+    repo_path: &Path,
+    chunk_store_path: &Path,
+) -> Result<()> {
+    let (BuildOutput::Repository(config_path) | BuildOutput::Artifact { config_path, .. }) = output;
+    let provenance = new_provenance(
+        &package_manifest.id,
+        &package_manifest.build_hash,
+        sources.iter().map(ProvenanceSource::from).collect(),
+        started_at,
+        config_path,
+    )?;
+
+    match output {
+        BuildOutput::Repository(config_path) => {
+            insert_package(package_manifest, repo_path, config_path)?;
+            write_provenance(repo_path, &provenance, config_path)
+        }
+        BuildOutput::Artifact { path, config_path } => write_artifact(
+            package_manifest,
+            &provenance,
+            chunk_store_path,
+            path,
+            config_path,
+        ),
+    }
+}
+
+/// Interpreters and libraries can only come from packages that will be installed alongside.
+fn check_dependencies(
+    interpreters: &[Interpreter],
+    rpath: &[LibraryDir],
+    dependencies: &[String],
+) -> Result<()> {
+    for interpreter in interpreters {
+        if !dependencies.contains(&interpreter.package) {
+            bail!(
+                "Interpreter {} comes from {}, which is not a dependency.",
+                interpreter.shebang,
+                interpreter.package
+            );
+        }
+    }
+
+    for library_dir in rpath {
+        if let Some(package) = &library_dir.package
+            && !dependencies.contains(package)
+        {
+            bail!(
+                "Library directory {} comes from {package}, which is not a dependency.",
+                library_dir.path.display()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Gets every path a build depends on: the manifest, its scripts and any local sources.
+/// Useful for rebuilding automatically when one of them changes.
+///
+/// # Errors
+///
+/// - Invalid build manifest
+pub fn watched_paths(build_manifest_path: &Path) -> Result<Vec<PathBuf>> {
+    let build_manifest_path = build_manifest_path.canonicalize()?;
+    let build_manifest: BuildManifest =
+        serde_yaml::from_str(&fs::read_to_string(&build_manifest_path)?)?;
+    let search_path = build_manifest_path
+        .parent()
+        .unwrap_or_else(|| Path::new("/"));
+
+    let mut paths = vec![build_manifest_path.clone()];
+
+    for script in [
+        build_manifest.build_script,
+        build_manifest.post_script,
+        build_manifest.test_script,
+    ]
+    .into_iter()
+    .flatten()
+    {
+        paths.push(search_path.join(script));
+    }
+
+    // Local sources copy the whole directory of the manifest
+    if build_manifest
+        .sources
+        .unwrap_or_default()
+        .iter()
+        .any(|source| source.kind == "local")
+    {
+        paths.push(search_path.to_path_buf());
+    }
+
+    paths.retain(|path| path.exists());
+
+    Ok(paths)
+}
+
 fn include_all(
     packages: &Vec<String>,
     search_path: &Path,
@@ -223,6 +648,61 @@ fn include_all(
     Ok(())
 }
 
+/// Errors out if an env script is not part of the built package
+fn check_env_scripts(out_dir: &Path, env_scripts: Option<&[PathBuf]>) -> Result<()> {
+    for script in env_scripts.unwrap_or_default() {
+        if !out_dir
+            .join(script.strip_prefix("/").unwrap_or(script))
+            .is_file()
+        {
+            bail!(
+                "env_script {} is not part of the package.",
+                script.display()
+            )
+        }
+    }
+
+    Ok(())
+}
+
+/// Errors out if a command's name can't be quicklaunched, or it runs outside of the package.
+/// Only names given in the build manifest are checked, entrypoint filenames are used as they are.
+fn check_commands(commands: &[PackageCommand]) -> Result<()> {
+    for command in commands {
+        if let Some(name) = &command.name {
+            if name.starts_with('.')
+                || !name
+                    .chars()
+                    .all(|char| char.is_ascii_alphanumeric() || "-_.+".contains(char))
+            {
+                bail!("Command name {name} can only use letters, digits and -_.+")
+            }
+            if commands
+                .iter()
+                .filter(|other| other.name().as_ref() == Some(name))
+                .count()
+                > 1
+            {
+                bail!("Two commands are named {name}.")
+            }
+        }
+
+        if let Some(working_dir) = &command.working_dir
+            && working_dir
+                .components()
+                .any(|component| component == Component::ParentDir)
+        {
+            bail!(
+                "The working_dir of {}, {}, is outside of the package.",
+                command.path.display(),
+                working_dir.display()
+            )
+        }
+    }
+
+    Ok(())
+}
+
 /// This requires the dependency to be build first
 // Perhaps a future improvement would be to recursively build if not already built? (TODO)
 fn include(
@@ -232,11 +712,7 @@ fn include(
     repo_path: &Path,
     chunk_store_path: &Path,
 ) -> Result<HashMap<String, String>> {
-    let dependency_build_manifest_path = search_path.join(dependency);
-    let dependency_build_manifest: BuildManifest =
-        serde_yaml::from_str(&fs::read_to_string(dependency_build_manifest_path)?)?;
-    let repo_manifest = read_manifest(repo_path)?;
-    let dependency_manifest = get_package(&repo_manifest, &dependency_build_manifest.id)?;
+    let dependency_manifest = resolve_include(search_path, dependency, repo_path)?;
 
     load_tree(
         path_to_include_at,
@@ -247,6 +723,93 @@ fn include(
     Ok(dependency_manifest.env.unwrap_or_default())
 }
 
+/// The Repositories next to `repo_path` that [`resolve_include`] leaves out because they can't be
+/// read. Empty when the build manifest has no `include`s or `sdks`.
+fn skipped_repos(build_manifest: &BuildManifest, repo_path: &Path) -> Result<Vec<SkippedRepo>> {
+    let includes = build_manifest.include.iter().chain(&build_manifest.sdks);
+    if includes.flatten().next().is_none() {
+        return Ok(Vec::new());
+    }
+
+    let mut skipped = Vec::new();
+    for other_repo_path in sibling_repos(repo_path)? {
+        if let Err(error) =
+            read_manifest(&other_repo_path).and_then(|_| get_settings(&other_repo_path))
+        {
+            skipped.push(SkippedRepo {
+                path: other_repo_path,
+                error,
+            });
+        }
+    }
+
+    Ok(skipped)
+}
+
+/// Repositories next to the one at `repo_path`
+fn sibling_repos(repo_path: &Path) -> Result<Vec<PathBuf>> {
+    let Some(repos_path) = repo_path.parent() else {
+        return Ok(Vec::new());
+    };
+
+    let mut repos = Vec::new();
+    for entry in fs::read_dir(repos_path)? {
+        let other_repo_path = entry?.path();
+        if other_repo_path != repo_path && has_manifest(&other_repo_path) {
+            repos.push(other_repo_path);
+        }
+    }
+
+    Ok(repos)
+}
+
+/// The package an `include` or `sdks` entry refers to: the package of a build manifest
+/// next to this one, or else any package with or providing that name, see [`get_provider`].
+/// Providers in this Repository win over ones in the other Repositories next to it,
+/// which are picked by priority. Ones that can't be read are skipped, see [`skipped_repos`].
+fn resolve_include(
+    search_path: &Path,
+    dependency: &str,
+    repo_path: &Path,
+) -> Result<PackageManifest> {
+    let repo_manifest = read_manifest(repo_path)?;
+
+    let dependency_build_manifest_path = search_path.join(dependency);
+    if dependency_build_manifest_path.is_file() {
+        let dependency_build_manifest: BuildManifest =
+            serde_yaml::from_str(&fs::read_to_string(dependency_build_manifest_path)?)?;
+
+        return get_package(&repo_manifest, &dependency_build_manifest.id);
+    }
+
+    if let Ok(package) = get_provider(&repo_manifest, dependency) {
+        return Ok(package);
+    }
+
+    let mut providers = Vec::new();
+    for other_repo_path in sibling_repos(repo_path)? {
+        let (Ok(other_manifest), Ok(settings)) = (
+            read_manifest(&other_repo_path),
+            get_settings(&other_repo_path),
+        ) else {
+            continue;
+        };
+
+        if let Ok(package) = get_provider(&other_manifest, dependency) {
+            providers.push((settings.priority, other_repo_path, package));
+        }
+    }
+
+    providers.sort_by(|(priority, path, _), (other_priority, other_path, _)| {
+        other_priority.cmp(priority).then(path.cmp(other_path))
+    });
+    providers
+        .into_iter()
+        .next()
+        .map(|(_, _, package)| package)
+        .with_context(|| format!("No Repository has or provides {dependency}, build it first."))
+}
+
 /// Runs a script (typically `post_script` or `build_script`)
 fn run_script(cwd: &Path, search_path: &Path, script: &Path) -> Result<()> {
     let script_path = search_path.join(script);
@@ -263,3 +826,250 @@ fn run_script(cwd: &Path, search_path: &Path, script: &Path) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::{create_repo, provenance::read_provenance};
+    use std::os::unix::fs::PermissionsExt;
+    use temp_dir::TempDir;
+
+    #[test]
+    fn test_watched_paths() -> Result<()> {
+        let manifest_dir = TempDir::new()?;
+        let manifest_dir_path = &manifest_dir.path().canonicalize()?;
+        let build_manifest_path = manifest_dir_path.join("build_manifest.yml");
+
+        fs::write(manifest_dir_path.join("build.sh"), "")?;
+        fs::write(
+            &build_manifest_path,
+            "id: test\nedition: 2025\nmetadata: {}\ndirectory: .\nbuild_script: build.sh\n",
+        )?;
+
+        assert_eq!(
+            watched_paths(&build_manifest_path)?,
+            vec![
+                build_manifest_path.clone(),
+                manifest_dir_path.join("build.sh")
+            ]
+        );
+
+        fs::write(
+            &build_manifest_path,
+            "id: test\nedition: 2025\nmetadata: {}\ndirectory: .\nsources:\n  - kind: local\n    url: ./\n",
+        )?;
+
+        assert_eq!(
+            watched_paths(&build_manifest_path)?,
+            vec![build_manifest_path.clone(), manifest_dir_path.clone()]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_retain_build_dir() -> Result<()> {
+        let temp_root = TempDir::new()?;
+
+        let mut kept = Vec::new();
+        for _ in 0..=KEPT_BUILD_DIRS {
+            let build_dir = crate::utils::temp::TempDir::new_in(temp_root.path())?;
+            fs::write(build_dir.path().join("build.log"), "failed")?;
+            kept.push(retain_build_dir(build_dir, "test")?);
+        }
+
+        // Same second, different directories
+        assert_eq!(
+            fs::read_to_string(kept.last().unwrap().join("build.log"))?,
+            "failed"
+        );
+        assert!(!kept[0].exists());
+        assert!(kept[1..].iter().all(|path| path.exists()));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failing_test_script() -> Result<()> {
+        let repo = TempDir::new()?;
+        let repo_path = repo.path();
+        let chunks = TempDir::new()?;
+        let manifest_dir = TempDir::new()?;
+        let temp_root = TempDir::new()?;
+        create_repo(repo_path, Some(repo_path))?;
+        // Kept build directories stay out of the real cache directory
+        fs::write(
+            repo_path.join("config.yml"),
+            format!("temp_dir: {}\n", temp_root.path().display()),
+        )?;
+        let kept_root = temp_root.path().join("kept-builds");
+
+        let script_path = manifest_dir.path().join("test.sh");
+        fs::write(&script_path, "#!/bin/sh\nexit 1\n")?;
+        fs::set_permissions(&script_path, fs::Permissions::from_mode(0o755))?;
+
+        let build_manifest_path = manifest_dir.path().join("build_manifest.yml");
+        fs::write(
+            &build_manifest_path,
+            "id: test\nedition: 2025\nmetadata: {}\ndirectory: .\ntest_script: test.sh\n",
+        )?;
+
+        let result = force_build(
+            &build_manifest_path,
+            repo_path,
+            Some(repo_path),
+            chunks.path(),
+            false,
+            false,
+        )
+        .await;
+        let err = format!("{:#}", result.unwrap_err());
+        assert!(err.contains(&format!("Build directory kept at {}", kept_root.display())));
+        assert!(get_package(&read_manifest(repo_path)?, "test").is_err());
+
+        let built = force_build(
+            &build_manifest_path,
+            repo_path,
+            Some(repo_path),
+            chunks.path(),
+            true,
+            true,
+        )
+        .await?;
+        assert_eq!(built.package.tests, Some(TestStatus::Skipped));
+        assert!(
+            built
+                .kept_build_dir
+                .is_some_and(|path| path.starts_with(&kept_root))
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_build_artifact() -> Result<()> {
+        let root = TempDir::new()?;
+        let repo_path = &root.path().join("ci/repo");
+        let publish_path = &root.path().join("publish/repo");
+        create_repo(repo_path, Some(repo_path))?;
+        create_repo(publish_path, Some(publish_path))?;
+
+        let script_path = root.path().join("build.sh");
+        fs::write(&script_path, "#!/bin/sh\necho hello > hello\n")?;
+        fs::set_permissions(&script_path, fs::Permissions::from_mode(0o755))?;
+        let build_manifest_path = root.path().join("build_manifest.yml");
+        fs::write(
+            &build_manifest_path,
+            "id: hello\nedition: 2025\nmetadata: {}\ndirectory: .\nbuild_script: build.sh\n",
+        )?;
+
+        for artifact_path in [root.path().join("hello.tar"), root.path().join("hello")] {
+            let chunk_store = TempDir::new()?;
+            let package = build_artifact(
+                &build_manifest_path,
+                repo_path,
+                &artifact_path,
+                Some(repo_path),
+                chunk_store.path(),
+                false,
+                false,
+            )
+            .await?
+            .package;
+            assert!(get_package(&read_manifest(repo_path)?, "hello").is_err());
+
+            // Published elsewhere, with an empty chunk store
+            let publish_store = TempDir::new()?;
+            let inserted = artifact::insert_artifact(
+                &artifact_path,
+                publish_path,
+                publish_store.path(),
+                Some(publish_path),
+            )?;
+            assert_eq!(inserted, package);
+            assert_eq!(
+                get_package(&read_manifest(publish_path)?, "hello")?,
+                package
+            );
+            assert!(
+                crate::chunks::missing_chunks(&package.chunks, publish_store.path()).is_empty()
+            );
+            // Signed where it was built, not where it was published
+            assert_eq!(
+                read_provenance(publish_path, "hello")?.builder_key,
+                read_manifest(repo_path)?.public_key
+            );
+
+            let mut tmp_path = artifact_path.into_os_string();
+            tmp_path.push(".tmp");
+            assert!(!Path::new(&tmp_path).exists());
+        }
+
+        // Never overwritten
+        assert!(
+            build_artifact(
+                &build_manifest_path,
+                repo_path,
+                &root.path().join("hello.tar"),
+                Some(repo_path),
+                root.path(),
+                false,
+                false,
+            )
+            .await
+            .is_err()
+        );
+
+        // Only a manifest and chunks may be in an artifact
+        let evil = root.path().join("evil.tar");
+        let mut builder = tar::Builder::new(fs::File::create(&evil)?);
+        builder.append_path_with_name(&script_path, "installed/hello")?;
+        builder.into_inner()?;
+        assert!(
+            artifact::insert_artifact(&evil, publish_path, root.path(), Some(publish_path))
+                .is_err()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_include() -> Result<()> {
+        let root = TempDir::new()?;
+        let repo_path = &root.path().join("repos/main");
+        let other_path = &root.path().join("repos/other");
+        create_repo(repo_path, Some(repo_path))?;
+        create_repo(other_path, Some(other_path))?;
+
+        let package = PackageManifest {
+            id: "cpython".into(),
+            provides: vec!["python3".into()],
+            ..Default::default()
+        };
+        insert_package(&package, other_path, Some(other_path))?;
+
+        // Provided by another Repository
+        assert_eq!(
+            resolve_include(root.path(), "python3", repo_path)?.id,
+            "cpython"
+        );
+        assert!(resolve_include(root.path(), "ruby", repo_path).is_err());
+
+        // Repositories that can't be read are left out, and reported
+        let broken_path = &root.path().join("repos/broken");
+        create_repo(broken_path, Some(broken_path))?;
+        fs::write(broken_path.join("manifest.yml.sig"), "not a signature")?;
+        assert_eq!(
+            resolve_include(root.path(), "python3", repo_path)?.id,
+            "cpython"
+        );
+        let build_manifest: BuildManifest = serde_yaml::from_str(
+            "id: app\nedition: 2025\nmetadata: {}\ndirectory: .\nsdks: [python3]\n",
+        )?;
+        let skipped = skipped_repos(&build_manifest, repo_path)?;
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(&skipped[0].path, broken_path);
+
+        Ok(())
+    }
+}
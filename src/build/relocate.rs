@@ -0,0 +1,108 @@
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use crate::utils::elf::{patchelf, read_elf_info};
+
+/// A directory of shared libraries, in this package or a dependency.
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct LibraryDir {
+    /// The dependency the directory is in, this package if unset
+    pub package: Option<String>,
+    /// Path inside the package, eg: `lib`
+    pub path: PathBuf,
+}
+
+/// Points the RUNPATH of every dynamically linked binary in `out_dir` at `library_dirs`,
+/// relative to `$ORIGIN` so the package works wherever its Repository is.
+///
+/// # Errors
+///
+/// - patchelf is not installed, or failed
+/// - Filesystem errors (Permissions, Out of space)
+///
+/// # Returns
+///
+/// The number of patched binaries
+pub fn set_runpaths(out_dir: &Path, library_dirs: &[LibraryDir]) -> Result<usize> {
+    let mut patched = 0;
+
+    for entry in WalkDir::new(out_dir) {
+        let entry = entry?;
+        let path = entry.path();
+
+        if !entry.file_type().is_file()
+            || !read_elf_info(path)?.is_some_and(|elf_info| elf_info.dynamic)
+        {
+            continue;
+        }
+
+        let relative_path = path.strip_prefix(out_dir)?;
+        patchelf(
+            path,
+            &["--set-rpath", &runpath(relative_path, library_dirs)],
+        )?;
+
+        patched += 1;
+    }
+
+    Ok(patched)
+}
+
+/// The RUNPATH for a binary at `relative_path` inside the package.
+/// Installed packages live at `<repo>/versions/<id>-<hash>/`, next to `<repo>/installed/`.
+fn runpath(relative_path: &Path, library_dirs: &[LibraryDir]) -> String {
+    let depth = relative_path
+        .parent()
+        .map_or(0, |parent| parent.components().count());
+
+    library_dirs
+        .iter()
+        .map(|library_dir| {
+            let mut runpath = PathBuf::from("$ORIGIN");
+
+            if let Some(package) = &library_dir.package {
+                for _ in 0..depth + 2 {
+                    runpath.push("..");
+                }
+                runpath.push("installed");
+                runpath.push(package);
+            } else {
+                for _ in 0..depth {
+                    runpath.push("..");
+                }
+            }
+
+            runpath.join(&library_dir.path).display().to_string()
+        })
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_runpath() {
+        let library_dirs = [
+            LibraryDir {
+                package: None,
+                path: PathBuf::from("lib"),
+            },
+            LibraryDir {
+                package: Some("glibc".into()),
+                path: PathBuf::from("lib64"),
+            },
+        ];
+
+        assert_eq!(
+            runpath(Path::new("bin/app"), &library_dirs),
+            "$ORIGIN/../lib:$ORIGIN/../../../installed/glibc/lib64"
+        );
+        assert_eq!(
+            runpath(Path::new("app"), &library_dirs),
+            "$ORIGIN/lib:$ORIGIN/../../installed/glibc/lib64"
+        );
+    }
+}
@@ -161,6 +161,7 @@ fn unwrap_tar_contents(temp_dir: &Path, target_path: &Path) -> Result<()> {
 
 #[cfg(feature = "network")]
 async fn pull_tar(source: &Source, target_path: &Path) -> Result<()> {
+    use crate::utils::temp::TempDir;
     use anyhow::bail;
     use bzip2::read::BzDecoder;
     use flate2::read::GzDecoder;
@@ -168,7 +169,6 @@ async fn pull_tar(source: &Source, target_path: &Path) -> Result<()> {
     use std::fs::File;
     use std::io::Read;
     use tar::Archive;
-    use temp_dir::TempDir;
 
     // downloads/gets the cache
     let get_cache_path = try_pull_cache(&source.url).await?;
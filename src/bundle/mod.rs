@@ -1,9 +1,17 @@
+pub mod verify;
+
 use anyhow::{Result, bail};
 use std::{
+    collections::HashMap,
     fs,
     io::{Cursor, Read},
-    os::unix::fs::PermissionsExt,
-    path::Path,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    repo::Requirements,
+    run::requirements::check_requirements,
+    utils::{elf::read_elf_info, platform::set_mode},
 };
 
 /// How big of "chunks" do we search for a tar?
@@ -14,6 +22,109 @@ const CHUNK_SIZE: usize = 64 * 1024;
 /// To get this number, (Intended max chunk size) / `CHUNK_SIZE`
 const MAX_CHUNKS: usize = 32;
 
+/// Where the bundle's own settings live, at the root of its tar
+pub const BUNDLE_META_FILE: &str = "bundle.yml";
+
+/// Settings for how a bundle runs, decided when it is created
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct BundleMeta {
+    /// Keep the app's writable data in `<bundle>.data/`, next to the bundle
+    #[serde(default)]
+    pub portable: bool,
+}
+
+/// Reads the bundle settings from an extracted bundle, older bundles have none.
+///
+/// # Errors
+///
+/// - Filesystem errors
+/// - Invalid bundle settings
+pub fn read_bundle_meta(extract_path: &Path) -> Result<BundleMeta> {
+    let path = extract_path.join(BUNDLE_META_FILE);
+
+    if path.exists() {
+        Ok(serde_yaml::from_str(&fs::read_to_string(path)?)?)
+    } else {
+        Ok(BundleMeta::default())
+    }
+}
+
+/// The writable data directory of a portable bundle: `<bundle>.data/`
+#[must_use]
+pub fn portable_data_dir(bundle_path: &Path) -> PathBuf {
+    let mut path = bundle_path.as_os_str().to_owned();
+    path.push(".data");
+
+    PathBuf::from(path)
+}
+
+/// Environment pointing the home and XDG directories into a portable data directory.
+/// `FLINT_BUNDLE_DATA` is set too, for apps that want to know where it is.
+#[must_use]
+pub fn portable_env(data_path: &Path) -> HashMap<String, String> {
+    [
+        ("FLINT_BUNDLE_DATA", ""),
+        ("HOME", ""),
+        ("XDG_CONFIG_HOME", ".config"),
+        ("XDG_DATA_HOME", ".local/share"),
+        ("XDG_STATE_HOME", ".local/state"),
+        ("XDG_CACHE_HOME", ".cache"),
+    ]
+    .into_iter()
+    .map(|(key, dir)| {
+        (
+            key.to_string(),
+            data_path
+                .join(dir)
+                .to_string_lossy()
+                .trim_end_matches('/')
+                .to_string(),
+        )
+    })
+    .collect()
+}
+
+/// Errors out if this system can't run the bundled binary at `path`, explaining what is missing.
+///
+/// Bundles built on a newer distribution otherwise fail with the dynamic linker's own errors.
+/// Scripts and binaries using a dynamic linker from the bundle itself are not checked.
+///
+/// # Errors
+///
+/// - The binary's dynamic linker is not on this system
+/// - The system's glibc is older than the binary needs
+/// - Filesystem errors (Permissions)
+pub fn check_host_runtime(package_id: &str, path: &Path, extract_path: &Path) -> Result<()> {
+    if !path.is_file() {
+        return Ok(());
+    }
+
+    let Some(info) = read_elf_info(path)? else {
+        return Ok(());
+    };
+    let Some(interpreter) = info.interpreter else {
+        return Ok(());
+    };
+
+    if Path::new(&interpreter).starts_with(extract_path) {
+        return Ok(());
+    }
+
+    if !Path::new(&interpreter).exists() {
+        bail!(
+            "{package_id} needs the dynamic linker {interpreter}, which this system does not have. It was likely built for a distribution with a different C library."
+        )
+    }
+
+    check_requirements(
+        package_id,
+        &Requirements {
+            min_glibc: info.min_glibc,
+            ..Requirements::default()
+        },
+    )
+}
+
 /// Rips the tar from the header
 ///
 /// # Errors
@@ -84,9 +195,7 @@ pub fn extract_bundle(bundle_path: &Path, extract_path: &Path) -> Result<()> {
         fs::create_dir_all(extracted_path.parent().unwrap())?;
         fs::write(&extracted_path, contents)?;
 
-        let mut perms = fs::metadata(&extracted_path)?.permissions();
-        perms.set_mode(mode & 0o777);
-        fs::set_permissions(&extracted_path, perms)?;
+        set_mode(&extracted_path, mode)?;
     }
 
     Ok(())
@@ -105,4 +214,40 @@ mod tests {
         assert!(padded[3..].iter().all(|&x| x == 4));
         Ok(())
     }
+
+    #[test]
+    fn test_check_host_runtime() -> Result<()> {
+        let temp_dir = temp_dir::TempDir::new()?;
+        let script = temp_dir.path().join("hello");
+        fs::write(&script, "#!/bin/sh\necho hello\n")?;
+
+        assert!(check_host_runtime("hello", &script, temp_dir.path()).is_ok());
+        assert!(
+            check_host_runtime("hello", &temp_dir.path().join("missing"), temp_dir.path()).is_ok()
+        );
+
+        // Whatever runs the tests, this system can run
+        let current_exe = std::env::current_exe()?;
+        assert!(check_host_runtime("hello", &current_exe, temp_dir.path()).is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_portable_data() -> Result<()> {
+        let data_path = portable_data_dir(Path::new("/media/usb/app.flint"));
+        assert_eq!(data_path, Path::new("/media/usb/app.flint.data"));
+
+        let env = portable_env(&data_path);
+        assert_eq!(env["HOME"], "/media/usb/app.flint.data");
+        assert_eq!(env["FLINT_BUNDLE_DATA"], "/media/usb/app.flint.data");
+        assert_eq!(env["XDG_CONFIG_HOME"], "/media/usb/app.flint.data/.config");
+
+        let temp_dir = temp_dir::TempDir::new()?;
+        assert!(!read_bundle_meta(temp_dir.path())?.portable);
+        fs::write(temp_dir.path().join(BUNDLE_META_FILE), "portable: true")?;
+        assert!(read_bundle_meta(temp_dir.path())?.portable);
+
+        Ok(())
+    }
 }
@@ -0,0 +1,164 @@
+use anyhow::{Result, bail};
+use sha2::{Digest, Sha256};
+use std::{
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    crypto::{
+        key::deserialize_verifying_key,
+        signing::{sign_detached, verify_signature},
+    },
+    policy::read_policy,
+};
+
+/// `<bundle>.sha256`, in the same format as `sha256sum`
+#[must_use]
+pub fn checksum_path(bundle_path: &Path) -> PathBuf {
+    sibling_path(bundle_path, ".sha256")
+}
+
+/// `<bundle>.sig`, a detached signature of the checksum file
+#[must_use]
+pub fn signature_path(bundle_path: &Path) -> PathBuf {
+    sibling_path(bundle_path, ".sig")
+}
+
+fn sibling_path(bundle_path: &Path, extension: &str) -> PathBuf {
+    let mut path = bundle_path.as_os_str().to_owned();
+    path.push(extension);
+
+    PathBuf::from(path)
+}
+
+/// The checksum file line for a bundle: `<sha256>  <file name>`
+#[must_use]
+pub fn checksum_line(bundle_path: &Path, bundle: &[u8]) -> String {
+    let mut line = String::new();
+    for byte in Sha256::digest(bundle) {
+        // Writing to a String can't fail
+        let _ = write!(line, "{byte:02x}");
+    }
+
+    let filename = bundle_path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    format!("{line}  {filename}\n")
+}
+
+/// Writes `<bundle>.sha256` next to a bundle, and `<bundle>.sig` signing it if `sign` is set.
+///
+/// # Errors
+///
+/// - Private key could not be read
+/// - Filesystem errors (Permissions)
+pub fn write_checksums(
+    bundle_path: &Path,
+    bundle: &[u8],
+    sign: bool,
+    config_path: Option<&Path>,
+) -> Result<()> {
+    let checksum = checksum_line(bundle_path, bundle);
+    fs::write(checksum_path(bundle_path), &checksum)?;
+
+    if sign {
+        let signature = sign_detached(&checksum, config_path)?;
+        fs::write(signature_path(bundle_path), signature.to_bytes())?;
+    }
+
+    Ok(())
+}
+
+/// Checks a bundle against its `<bundle>.sha256`, and `<bundle>.sig`.
+/// The signature must come from one of `public_keys`. Without any, unsigned bundles are accepted.
+///
+/// # Errors
+///
+/// - Missing checksum file
+/// - Bundle does not match its checksum
+/// - Signature is missing, invalid, or not from any of `public_keys`
+/// - Bundle is unsigned, and the machine's policy requires signed bundles
+///
+/// # Returns
+///
+/// The key the bundle is signed with, `None` if it is unsigned
+pub fn verify_bundle<'a>(bundle_path: &Path, public_keys: &'a [String]) -> Result<Option<&'a str>> {
+    let Ok(checksum) = fs::read_to_string(checksum_path(bundle_path)) else {
+        bail!(
+            "No checksum file found at {}",
+            checksum_path(bundle_path).display()
+        )
+    };
+
+    let actual = checksum_line(bundle_path, &fs::read(bundle_path)?);
+    if checksum.split_whitespace().next() != actual.split_whitespace().next() {
+        bail!("Bundle does not match its checksum, it may be corrupt or tampered with.")
+    }
+
+    let Ok(signature) = fs::read(signature_path(bundle_path)) else {
+        if !public_keys.is_empty() {
+            bail!(
+                "No signature found at {}",
+                signature_path(bundle_path).display()
+            )
+        }
+
+        read_policy(None)?.check_bundle(false)?;
+        return Ok(None);
+    };
+
+    for public_key in public_keys {
+        if verify_signature(
+            &checksum,
+            &signature,
+            deserialize_verifying_key(public_key)?,
+        )
+        .is_ok()
+        {
+            return Ok(Some(public_key));
+        }
+    }
+
+    bail!("Bundle is signed, but not by any trusted key.")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::{create_repo, read_manifest};
+    use temp_dir::TempDir;
+
+    #[test]
+    fn test_verify_bundle() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let repo_path = &temp_dir.path().join("repo");
+        create_repo(repo_path, Some(repo_path))?;
+        let public_key = read_manifest(repo_path)?.public_key;
+
+        let bundle_path = &temp_dir.path().join("app.flint");
+        fs::write(bundle_path, "bundle")?;
+
+        assert!(verify_bundle(bundle_path, &[]).is_err());
+
+        write_checksums(bundle_path, b"bundle", false, None)?;
+        assert_eq!(verify_bundle(bundle_path, &[])?, None);
+        // Expected to be signed
+        assert!(verify_bundle(bundle_path, std::slice::from_ref(&public_key)).is_err());
+        assert!(fs::read_to_string(checksum_path(bundle_path))?.ends_with("  app.flint\n"));
+
+        write_checksums(bundle_path, b"bundle", true, Some(repo_path))?;
+        assert_eq!(
+            verify_bundle(bundle_path, std::slice::from_ref(&public_key))?,
+            Some(public_key.as_str())
+        );
+        assert!(verify_bundle(bundle_path, &[]).is_err());
+
+        fs::write(bundle_path, "tampered")?;
+        assert!(verify_bundle(bundle_path, std::slice::from_ref(&public_key)).is_err());
+
+        Ok(())
+    }
+}
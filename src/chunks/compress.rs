@@ -0,0 +1,179 @@
+use anyhow::{Result, bail};
+use std::{
+    borrow::Cow,
+    fs::{self, File},
+    io::{ErrorKind, Read},
+    path::Path,
+};
+
+/// Starts every compressed chunk: an empty zstd skippable frame, so `zstd -d` still reads them.
+/// Chunks without it are stored as is, like every chunk from before compression.
+const MARKER: [u8; 12] = [
+    0x50, 0x2A, 0x4D, 0x18, 0x04, 0x00, 0x00, 0x00, b'f', b'l', b'n', b't',
+];
+
+/// Compresses a chunk's contents for mirrors to serve.
+///
+/// # Errors
+///
+/// - Compression failed (Out of memory)
+///
+/// # Returns
+///
+/// `None` if compressing doesn't save at least an eighth, then the chunk is served uncompressed
+pub fn compress_chunk(data: &[u8]) -> Result<Option<Vec<u8>>> {
+    let mut compressed = MARKER.to_vec();
+    compressed.extend(zstd::stream::encode_all(data, 0)?);
+
+    // Raw contents starting with the marker would be mistaken for a compressed chunk
+    if compressed.len() < data.len() - data.len() / 8 || data.starts_with(&MARKER) {
+        Ok(Some(compressed))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Whether a chunk's stored contents are compressed
+#[must_use]
+pub fn is_compressed(data: &[u8]) -> bool {
+    data.starts_with(&MARKER)
+}
+
+/// Gets a chunk's actual contents from its stored or downloaded contents.
+///
+/// # Errors
+///
+/// - Invalid compressed data
+/// - Decompresses to more than `max_size` bytes
+pub fn decompress_chunk(data: &[u8], max_size: u64) -> Result<Cow<'_, [u8]>> {
+    let Some(compressed) = data.strip_prefix(&MARKER) else {
+        return Ok(Cow::Borrowed(data));
+    };
+
+    let mut contents = Vec::new();
+    zstd::stream::read::Decoder::with_buffer(compressed)?
+        .take(max_size.saturating_add(1))
+        .read_to_end(&mut contents)?;
+
+    if contents.len() as u64 > max_size {
+        bail!("Compressed chunk is larger than expected.")
+    }
+
+    Ok(Cow::Owned(contents))
+}
+
+/// Reads a chunk from the chunk store, decompressed.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions, Missing chunk)
+/// - Invalid compressed data
+pub fn read_chunk(chunk_path: &Path) -> Result<Vec<u8>> {
+    let data = fs::read(chunk_path)?;
+
+    Ok(decompress_chunk(&data, u64::MAX)?.into_owned())
+}
+
+/// Reads a chunk from the chunk store as a mirror serves it: compressed when `compressed` and worth it,
+/// otherwise its raw contents, which every client understands.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions, Missing chunk)
+/// - Invalid compressed data
+pub fn serve_chunk(chunk_path: &Path, compressed: bool) -> Result<Vec<u8>> {
+    let contents = read_chunk(chunk_path)?;
+
+    if compressed && let Some(compressed) = compress_chunk(&contents)? {
+        return Ok(compressed);
+    }
+
+    Ok(contents)
+}
+
+/// Whether a chunk in the chunk store is compressed, only reading the start of it.
+/// Chunks are stored decompressed, but chunk stores written while they weren't may still have some.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions, Missing chunk)
+pub fn is_compressed_file(chunk_path: &Path) -> Result<bool> {
+    let mut start = [0; MARKER.len()];
+
+    match File::open(chunk_path)?.read_exact(&mut start) {
+        Ok(()) => Ok(start == MARKER),
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Decompresses a compressed chunk from the chunk store into a new file at `out_path`.
+///
+/// # Errors
+///
+/// - Filesystem errors (Out of space, Permissions)
+/// - Invalid compressed data
+pub fn decompress_file(chunk_path: &Path, out_path: &Path) -> Result<()> {
+    let mut chunk = File::open(chunk_path)?;
+    let mut start = [0; MARKER.len()];
+    chunk.read_exact(&mut start)?;
+
+    if start != MARKER {
+        bail!("{} is not a compressed chunk.", chunk_path.display())
+    }
+
+    zstd::stream::copy_decode(chunk, File::create(out_path)?)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compress_chunk() -> Result<()> {
+        let text = "compressible ".repeat(100);
+        let compressed = compress_chunk(text.as_bytes())?.expect("Text compresses well");
+        assert!(is_compressed(&compressed));
+        assert!(compressed.len() < text.len() / 4);
+        assert_eq!(decompress_chunk(&compressed, 1300)?, text.as_bytes());
+        // Never trusts a chunk to stay within its size
+        assert!(decompress_chunk(&compressed, 1299).is_err());
+
+        // Standard zstd skips the marker
+        assert_eq!(
+            zstd::stream::decode_all(compressed.as_slice())?,
+            text.as_bytes()
+        );
+
+        // Too small to be worth it, stored and served as is
+        assert_eq!(compress_chunk(b"tiny")?, None);
+        assert_eq!(decompress_chunk(b"tiny", 4)?, &b"tiny"[..]);
+
+        // Unless it could be mistaken for a compressed chunk
+        let lookalike = [MARKER.as_slice(), b"x"].concat();
+        let compressed = compress_chunk(&lookalike)?.expect("Always compressed");
+        assert_eq!(decompress_chunk(&compressed, 13)?, lookalike.as_slice());
+
+        let dir = temp_dir::TempDir::new()?;
+        let chunk_path = dir.path().join("chunk");
+        let out_path = dir.path().join("out");
+        fs::write(
+            &chunk_path,
+            compress_chunk(text.as_bytes())?.unwrap_or_default(),
+        )?;
+        assert!(is_compressed_file(&chunk_path)?);
+        assert_eq!(read_chunk(&chunk_path)?, text.as_bytes());
+        decompress_file(&chunk_path, &out_path)?;
+        assert_eq!(fs::read_to_string(&out_path)?, text);
+        assert!(!is_compressed_file(&out_path)?);
+
+        // Only served compressed to clients that understand it
+        fs::write(&chunk_path, &text)?;
+        assert_eq!(serve_chunk(&chunk_path, false)?, text.as_bytes());
+        assert!(is_compressed(&serve_chunk(&chunk_path, true)?));
+
+        Ok(())
+    }
+}
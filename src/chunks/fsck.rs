@@ -0,0 +1,334 @@
+use anyhow::Result;
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs,
+    path::{Path, PathBuf},
+};
+
+#[cfg(feature = "network")]
+use crate::chunks::DownloadEvent;
+use crate::{
+    chunks::{Chunk, VerifyProgress, missing_chunks, store_chunk, verify_chunk_set},
+    repo::{
+        InstallMeta, RepoManifest, installed::get_installed, manifest_io::has_manifest,
+        read_manifest,
+    },
+};
+
+/// The outcome of checking the whole chunk store
+#[derive(serde::Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct FsckReport {
+    /// Number of chunks that matched their hash
+    pub verified: usize,
+    /// Hashes of chunks whose contents did not match, eg: truncated. These have been removed from the chunk store
+    pub corrupt: Vec<String>,
+    /// Hashes of chunks used by installed packages, missing from the chunk store
+    pub missing: Vec<String>,
+    /// Hashes of corrupt or missing chunks put back into the chunk store
+    pub repaired: Vec<String>,
+    /// Repositories that were skipped because they can't be read, and mirrors that chunks couldn't be downloaded from
+    pub warnings: Vec<String>,
+}
+
+impl FsckReport {
+    /// Hashes of corrupt or missing chunks that are still not in the chunk store
+    #[must_use]
+    pub fn unrepaired(&self) -> Vec<&str> {
+        self.corrupt
+            .iter()
+            .chain(&self.missing)
+            .filter(|hash| !self.repaired.contains(hash))
+            .map(String::as_str)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+
+    fn mark_repaired(&mut self, chunk: &Chunk) {
+        if !self.repaired.iter().any(|hash| hash == chunk.hash()) {
+            self.repaired.push(chunk.hash().to_string());
+        }
+    }
+}
+
+/// Checks every chunk any Repository uses against its hash, removing corrupt ones from the chunk store.
+///
+/// Also finds chunks installed packages use that are missing from it.
+/// Chunks of packages that are not installed are never downloaded, so those aren't reported missing.
+/// Chunks shared by Repositories are only hashed once, and Repositories that can't be read are skipped with a warning.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+pub fn fsck_chunks(
+    repos_path: &Path,
+    chunk_store_path: &Path,
+    progress: &(dyn Fn(VerifyProgress) + Sync),
+) -> Result<FsckReport> {
+    let mut report = FsckReport::default();
+    let mut all_chunks = HashMap::new();
+    let mut installed = Vec::new();
+
+    for repo_path in repo_paths(repos_path)? {
+        let (repo_manifest, repo_installed) = match read_repo(&repo_path) {
+            Ok(read) => read,
+            Err(err) => {
+                report.warnings.push(format!(
+                    "Skipped {}, it can't be read: {err:#}",
+                    repo_path.display()
+                ));
+                continue;
+            }
+        };
+
+        for chunk in repo_manifest
+            .packages
+            .iter()
+            .flat_map(|package| &package.chunks)
+        {
+            all_chunks
+                .entry((chunk.hash().to_string(), chunk.permissions()))
+                .or_insert(repo_manifest.hash_kind);
+        }
+        installed.extend(repo_installed);
+    }
+
+    let verify_report = verify_chunk_set(all_chunks, chunk_store_path, false, progress)?;
+    report.verified = verify_report.verified;
+    report.corrupt = verify_report.corrupt;
+
+    // After verifying, so corrupt chunks that were removed count as missing too
+    let missing: BTreeSet<String> = installed
+        .iter()
+        .flat_map(|install_meta| missing_chunks(&install_meta.package.chunks, chunk_store_path))
+        .map(|chunk| chunk.hash().to_string())
+        .collect();
+    report.missing = missing.into_iter().collect();
+
+    Ok(report)
+}
+
+/// Puts corrupt and missing chunks back into the chunk store from the installed trees still holding them.
+///
+/// Every file is checked against its chunk's hash first, so modified files are never used.
+///
+/// # Errors
+///
+/// - Filesystem errors (Out of space, Permissions)
+pub fn rederive_chunks(
+    repos_path: &Path,
+    chunk_store_path: &Path,
+    report: &mut FsckReport,
+) -> Result<()> {
+    for repo_path in repo_paths(repos_path)? {
+        // Already reported by `fsck_chunks`
+        let Ok((repo_manifest, installed)) = read_repo(&repo_path) else {
+            continue;
+        };
+
+        for install_meta in installed {
+            let installed_path = repo_path.join("installed").join(&install_meta.package.id);
+
+            for chunk in unrepaired_chunks(&install_meta.package.chunks, chunk_store_path, report) {
+                // Images that aren't mounted leave an empty tree
+                let Ok(data) = fs::read(installed_path.join(chunk.path())) else {
+                    continue;
+                };
+
+                if store_chunk(chunk, &data, repo_manifest.hash_kind, chunk_store_path).is_ok() {
+                    report.mark_repaired(chunk);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Downloads corrupt and missing chunks again from the mirrors of the Repositories using them.
+///
+/// Mirrors that fail are added to the report's warnings, another Repository may still have the chunks.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+#[cfg(feature = "network")]
+pub async fn redownload_chunks(
+    repos_path: &Path,
+    chunk_store_path: &Path,
+    report: &mut FsckReport,
+    progress: &(dyn Fn(DownloadEvent) + Sync),
+) -> Result<()> {
+    use crate::{
+        chunks::network::install_chunks,
+        repo::{credentials::get_repo_auth_for, mirrors::get_mirrors},
+    };
+
+    for repo_path in repo_paths(repos_path)? {
+        // Already reported by `fsck_chunks`
+        let Ok((repo_manifest, _)) = read_repo(&repo_path) else {
+            continue;
+        };
+
+        let mut chunks: Vec<Chunk> = repo_manifest
+            .packages
+            .iter()
+            .flat_map(|package| unrepaired_chunks(&package.chunks, chunk_store_path, report))
+            .cloned()
+            .collect();
+        chunks.sort_by_key(Chunk::filename);
+        chunks.dedup_by_key(|chunk| chunk.filename());
+
+        if chunks.is_empty() {
+            continue;
+        }
+
+        let chunk_refs: Vec<&Chunk> = chunks.iter().collect();
+        let downloaded = async {
+            install_chunks(
+                &chunk_refs,
+                &get_mirrors(&repo_path, &repo_manifest)?,
+                repo_manifest.hash_kind,
+                chunk_store_path,
+                get_repo_auth_for(&repo_path)?.as_ref(),
+                progress,
+            )
+            .await
+        }
+        .await;
+
+        if let Err(err) = downloaded {
+            report.warnings.push(format!(
+                "Could not download chunks from the mirrors of {}: {err:#}",
+                repo_path.display()
+            ));
+        }
+
+        for chunk in &chunks {
+            if chunk_store_path.join(chunk.filename()).exists() {
+                report.mark_repaired(chunk);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn repo_paths(repos_path: &Path) -> Result<Vec<PathBuf>> {
+    if !repos_path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut repo_paths = Vec::new();
+    for entry in repos_path.read_dir()? {
+        let repo_path = entry?.path();
+        if repo_path.is_dir() && has_manifest(&repo_path) {
+            repo_paths.push(repo_path);
+        }
+    }
+
+    Ok(repo_paths)
+}
+
+/// A Repository's manifest, and its installed packages whose trees come from the chunk store, so not dev installs
+fn read_repo(repo_path: &Path) -> Result<(RepoManifest, Vec<InstallMeta>)> {
+    let repo_manifest = read_manifest(repo_path)?;
+    let installed = get_installed(repo_path)?
+        .into_iter()
+        .filter(|install_meta| !install_meta.dev_install)
+        .collect();
+
+    Ok((repo_manifest, installed))
+}
+
+/// Chunks of `chunks` the report lists as corrupt or missing, that are still not in the chunk store
+fn unrepaired_chunks<'a>(
+    chunks: &'a [Chunk],
+    chunk_store_path: &Path,
+    report: &FsckReport,
+) -> Vec<&'a Chunk> {
+    let unrepaired = report.unrepaired();
+
+    missing_chunks(chunks, chunk_store_path)
+        .into_iter()
+        .filter(|chunk| unrepaired.contains(&chunk.hash()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chunks::{HashKind, save_tree},
+        repo::{
+            PackageManifest, create_repo, insert_package,
+            versions::{install_version, switch_version},
+        },
+    };
+    use temp_dir::TempDir;
+
+    #[test]
+    fn test_fsck_chunks() -> Result<()> {
+        let root = TempDir::new()?;
+        let repos_path = &root.path().join("repos");
+        let repo_path = &repos_path.join("main");
+        let chunk_store = &root.path().join("chunks");
+        let tree = &root.path().join("tree");
+        create_repo(repo_path, Some(repo_path))?;
+
+        fs::create_dir_all(tree)?;
+        fs::write(tree.join("hello"), "hello")?;
+        fs::write(tree.join("text"), "compressible ".repeat(100))?;
+        let package = PackageManifest {
+            id: "hello".into(),
+            chunks: save_tree(tree, chunk_store, HashKind::Blake3)?,
+            ..Default::default()
+        };
+        insert_package(&package, repo_path, Some(repo_path))?;
+        let hash = install_version(repo_path, "hello", chunk_store)?;
+        switch_version(repo_path, &hash, "hello")?;
+
+        assert_eq!(
+            fsck_chunks(repos_path, chunk_store, &|_| {})?
+                .unrepaired()
+                .len(),
+            0
+        );
+
+        // Replaced by a truncated copy in the chunk store, the installed file is still intact
+        let text = package
+            .chunks
+            .iter()
+            .find(|chunk| chunk.path() == Path::new("text"))
+            .expect("Saved");
+        fs::remove_file(chunk_store.join(text.filename()))?;
+        fs::write(chunk_store.join(text.filename()), "compr")?;
+
+        // Shares every chunk, which is only hashed once. Broken ones are skipped
+        let copy_path = &repos_path.join("copy");
+        create_repo(copy_path, Some(copy_path))?;
+        insert_package(&package, copy_path, Some(copy_path))?;
+        let broken_path = &repos_path.join("broken");
+        create_repo(broken_path, Some(broken_path))?;
+        fs::write(broken_path.join("manifest.yml.sig"), "not a signature")?;
+        fs::create_dir(repos_path.join("not a repo"))?;
+
+        let mut report = fsck_chunks(repos_path, chunk_store, &|_| {})?;
+        assert_eq!(report.verified, package.chunks.len() - 1);
+        assert_eq!(report.warnings.len(), 1);
+        assert!(report.warnings[0].contains("broken"));
+        assert_eq!(report.corrupt, vec![text.hash().to_string()]);
+        assert_eq!(report.missing, vec![text.hash().to_string()]);
+        assert!(!chunk_store.join(text.filename()).exists());
+
+        rederive_chunks(repos_path, chunk_store, &mut report)?;
+        assert!(report.unrepaired().is_empty());
+        assert!(
+            fsck_chunks(repos_path, chunk_store, &|_| {})?
+                .corrupt
+                .is_empty()
+        );
+
+        Ok(())
+    }
+}
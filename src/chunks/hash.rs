@@ -1,7 +1,8 @@
-use std::fmt;
-
-/// WARNING: Only Blake3 is currently implemented for the time being.
+use anyhow::{Result, bail};
+use sha2::{Digest, Sha256, Sha512};
+use std::{fmt, fmt::Write, str::FromStr};
 
+/// How a Repository hashes its chunks, chosen when it is created
 #[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum HashKind {
     Blake3,
@@ -19,15 +20,40 @@ impl fmt::Display for HashKind {
     }
 }
 
+impl FromStr for HashKind {
+    type Err = anyhow::Error;
+
+    fn from_str(hash_kind: &str) -> Result<Self> {
+        match hash_kind.to_ascii_lowercase().as_str() {
+            "blake3" => Ok(Self::Blake3),
+            "sha512" => Ok(Self::Sha512),
+            "sha256" => Ok(Self::Sha256),
+            _ => bail!("Unknown hash kind {hash_kind}, expected blake3, sha256 or sha512."),
+        }
+    }
+}
+
+/// Hashes `data`, as lowercase hex
 #[must_use]
 pub fn hash(hash_kind: HashKind, data: &[u8]) -> String {
     match hash_kind {
         HashKind::Blake3 => blake3::hash(data).to_hex().to_string(),
-        HashKind::Sha512 => todo!(),
-        HashKind::Sha256 => todo!(),
+        HashKind::Sha512 => to_hex(&Sha512::digest(data)),
+        HashKind::Sha256 => to_hex(&Sha256::digest(data)),
     }
 }
 
+/// Lowercase hex of `bytes`
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        // Writing to a String can't fail
+        let _ = write!(hex, "{byte:02x}");
+    }
+
+    hex
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -41,15 +67,19 @@ mod tests {
     }
 
     #[test]
-    #[should_panic(expected = "not yet implemented")]
-    fn test_hash_sha512_panics() {
-        let _ = hash(HashKind::Sha512, b"test");
+    fn test_hash_sha512() {
+        assert_eq!(
+            hash(HashKind::Sha512, b"hello world"),
+            "309ecc489c12d6eb4cc40f50c902f2b4d0ed77ee511a7c7a9bcd3ca86d4cd86f989dd35bc5ff499670da34255b45b0cfd830e81f605dcf7dc5542e93ae9cd76f"
+        );
     }
 
     #[test]
-    #[should_panic(expected = "not yet implemented")]
-    fn test_hash_sha256_panics() {
-        let _ = hash(HashKind::Sha256, b"test");
+    fn test_hash_sha256() {
+        assert_eq!(
+            hash(HashKind::Sha256, b"hello world"),
+            "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9"
+        );
     }
 
     #[test]
@@ -58,4 +88,15 @@ mod tests {
         assert_eq!(format!("{}", HashKind::Sha512), "Sha512");
         assert_eq!(format!("{}", HashKind::Sha256), "Sha256");
     }
+
+    #[test]
+    fn test_hash_kind_from_str() -> Result<()> {
+        for hash_kind in [HashKind::Blake3, HashKind::Sha512, HashKind::Sha256] {
+            assert_eq!(hash_kind.to_string().parse::<HashKind>()?, hash_kind);
+        }
+        assert_eq!("sha256".parse::<HashKind>()?, HashKind::Sha256);
+        assert!("md5".parse::<HashKind>().is_err());
+
+        Ok(())
+    }
 }
@@ -1,17 +1,19 @@
+pub mod compress;
+pub mod fsck;
 pub mod hash;
 #[cfg(feature = "network")]
 pub mod network;
 mod tree;
 pub mod utils;
+mod verify;
 pub use hash::HashKind;
 pub use tree::*;
+pub use verify::*;
 
-use std::collections::HashSet;
+use std::borrow::Cow;
 use std::fs;
 use std::path::{Path, PathBuf};
 
-use crate::repo::read_manifest;
-
 #[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq, Eq)]
 pub struct Chunk {
     /// Path
@@ -23,59 +25,124 @@ pub struct Chunk {
     /// Unix mode permissions
     permissions: u32,
 
-    /// Expected size in kilobytes, rounded.
+    /// Expected size in kilobytes, rounded down. Still written for older clients.
     size: u64,
+
+    /// Exact expected size in bytes. Missing from manifests written before it existed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    bytes: Option<u64>,
 }
 
-/// Verify all chunks in a repository
-///
-/// # Errors
-///
-/// - Filesystem errors
-/// - Invalid manifests
-pub fn verify_all_chunks(repo_path: &Path, chunk_store_path: &Path) -> anyhow::Result<()> {
-    let repo_manifest = read_manifest(repo_path)?;
-    let mut all_chunks = HashSet::new();
-
-    for package in repo_manifest.packages {
-        for chunk in package.chunks {
-            all_chunks.insert((chunk.hash.clone(), chunk.permissions));
-        }
+impl Chunk {
+    /// Path of this chunk inside the package tree
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    #[must_use]
+    pub fn hash(&self) -> &str {
+        &self.hash
     }
 
-    let mut verified = 0;
-    let mut failed = 0;
-
-    for (expected_hash, perms) in &all_chunks {
-        let chunk_path = chunk_store_path.join(get_chunk_filename(expected_hash, *perms));
-        if !chunk_path.exists() {
-            eprintln!("Missing chunk: {expected_hash}");
-            failed += 1;
-            continue;
-        }
-
-        let contents = fs::read(&chunk_path)?;
-        let computed_hash = hash::hash(repo_manifest.hash_kind, &contents);
-
-        if computed_hash == *expected_hash {
-            verified += 1;
-        } else {
-            eprintln!(
-                "Hash mismatch for chunk: {expected_hash} (expected {expected_hash}, got {computed_hash})"
-            );
-            failed += 1;
-            // delete the invalid chunk
-            fs::remove_file(&chunk_path)?;
-        }
+    /// Unix mode permissions
+    #[must_use]
+    pub const fn permissions(&self) -> u32 {
+        self.permissions
     }
 
-    println!("Verified {verified} chunks, {failed} failed");
+    /// Expected size in bytes. Only accurate to the kilobyte if `has_exact_size` is false.
+    #[must_use]
+    pub fn size(&self) -> u64 {
+        self.bytes.unwrap_or(self.size * 1024)
+    }
+
+    /// The most bytes this chunk can have, to bound decompressing it
+    #[must_use]
+    pub fn max_size(&self) -> u64 {
+        self.bytes.unwrap_or(self.size * 1024 + 1023)
+    }
+
+    /// Whether this chunk's exact size is known, rather than rounded to the kilobyte
+    #[must_use]
+    pub const fn has_exact_size(&self) -> bool {
+        self.bytes.is_some()
+    }
+
+    /// Whether a file of `len` bytes matches the expected size
+    #[must_use]
+    pub fn matches_size(&self, len: u64) -> bool {
+        self.bytes
+            .map_or(self.size == len / 1024, |bytes| bytes == len)
+    }
 
-    if failed > 0 {
-        anyhow::bail!("Some chunks failed verification");
+    /// Records the exact size of this chunk, eg: when migrating an older manifest
+    pub const fn set_exact_size(&mut self, len: u64) {
+        self.size = len / 1024;
+        self.bytes = Some(len);
     }
 
-    Ok(())
+    /// The filename of this chunk inside a chunk store
+    #[must_use]
+    pub fn filename(&self) -> String {
+        get_chunk_filename(&self.hash, self.permissions)
+    }
+}
+
+/// Verifies and writes a chunk's contents into the chunk store.
+/// `data` may be compressed, eg: as served by a mirror. Chunks are always stored decompressed,
+/// so installs can hard link them.
+///
+/// # Errors
+///
+/// - `data` does not match the chunk's hash
+/// - Filesystem errors (Out of space, Permissions)
+///
+/// # Returns
+///
+/// Whether the chunk is new to the chunk store, `false` if it was already there,
+/// eg: stored by another download of it meanwhile
+pub fn store_chunk(
+    chunk: &Chunk,
+    data: &[u8],
+    hash_kind: HashKind,
+    chunk_store_path: &Path,
+) -> anyhow::Result<bool> {
+    let chunk_name = chunk.filename();
+    let chunk_path = chunk_store_path.join(&chunk_name);
+    let tmp_chunk_path = tmp_chunk_path(chunk_store_path, &chunk_name);
+
+    let contents = match compress::decompress_chunk(data, chunk.max_size()) {
+        Ok(contents) if hash::hash(hash_kind, &contents) == chunk.hash => contents,
+        // Raw contents that only look compressed
+        _ if hash::hash(hash_kind, data) == chunk.hash => Cow::Borrowed(data),
+        _ => anyhow::bail!("Invalid chunk data for {}.", chunk.hash),
+    };
+
+    if tmp_chunk_path.exists() {
+        fs::remove_file(&tmp_chunk_path)?;
+    }
+    fs::write(&tmp_chunk_path, contents)?;
+    let stored_before = chunk_path.exists();
+    fs::rename(&tmp_chunk_path, &chunk_path)?;
+
+    Ok(!stored_before)
+}
+
+/// Where this process writes a chunk before moving it into place.
+/// Named after the process, so processes storing the same chunk don't overwrite each other's,
+/// and cleaning up after one only touches its own.
+pub(crate) fn tmp_chunk_path(chunk_store_path: &Path, chunk_name: &str) -> PathBuf {
+    chunk_store_path.join(format!("{chunk_name}.{}.tmp", std::process::id()))
+}
+
+/// The process that wrote a partial chunk, `None` for ones from before they were named after it.
+pub(crate) fn tmp_chunk_owner(tmp_chunk_path: &Path) -> Option<u32> {
+    tmp_chunk_path
+        .file_stem()
+        .map(Path::new)
+        .and_then(Path::extension)
+        .and_then(|pid| pid.to_str()?.parse().ok())
 }
 
 fn get_chunk_filename(hash: &str, permissions: u32) -> String {
@@ -1,100 +1,158 @@
-use crate::chunks::{Chunk, HashKind, get_chunk_filename, hash::hash};
-use anyhow::{Result, bail};
+use crate::{
+    chunks::{Chunk, DownloadEvent, HashKind, InstallStats, store_chunk},
+    repo::credentials::{RepoAuth, get_url},
+};
+use anyhow::{Result, anyhow, bail};
 use futures_util::{StreamExt, TryStreamExt};
-use reqwest;
 use std::{fs, path::Path};
 
-/// Installs a particular chunk from a particular mirror
+/// Installs a particular chunk from a particular mirror, authenticated with `auth` if given.
+/// `progress` is told about every part of it that arrives.
 ///
 /// # Errors
 ///
 /// - The internet sent back corrupt/malicious data, timed out, or is blatently not working.
 /// - Filesystem out of space
+///
+/// # Returns
+///
+/// The number of bytes downloaded, `None` if the chunk was already in the chunk store,
+/// or got there from another download first
 pub async fn install_chunk(
     chunk: &Chunk,
     mirror: &str,
     hash_kind: HashKind,
     chunk_store_path: &Path,
-) -> Result<()> {
-    let chunk_name = get_chunk_filename(&chunk.hash, chunk.permissions);
+    auth: Option<&RepoAuth>,
+    progress: &(dyn Fn(DownloadEvent) + Sync),
+) -> Result<Option<u64>> {
+    let chunk_name = chunk.filename();
     let chunk_path = chunk_store_path.join(&chunk_name);
-    let tmp_chunk_path = chunk_store_path.join(format!("{}.tmp", &chunk_name));
 
     if chunk_path.exists() {
-        return Ok(());
+        return Ok(None);
     }
 
     let url = format!("{mirror}/chunks/{chunk_name}");
-    let request = reqwest::get(url).await?;
-    let body = request.bytes().await?;
-
-    let hash = hash(hash_kind, &body);
-
-    if hash == chunk.hash {
-        // TODO: POTENTIAL ISSUE IF MULTIPLE PROCESSES TRY INSTALLING SAME CHUNK!
-        if tmp_chunk_path.exists() {
-            fs::remove_file(&tmp_chunk_path)?;
-        }
-        fs::write(&tmp_chunk_path, body)?;
-        fs::rename(&tmp_chunk_path, &chunk_path)?;
-
-        Ok(())
-    } else {
-        bail!("Invalid chunk data returned.")
+    let mut response = get_url(&url, auth).await?;
+    let total = response.content_length();
+
+    let capacity = total.unwrap_or_default().min(chunk.size());
+    let mut body = Vec::with_capacity(usize::try_from(capacity).unwrap_or_default());
+    while let Some(piece) = response.chunk().await? {
+        body.extend_from_slice(&piece);
+        progress(DownloadEvent::Progress {
+            chunk,
+            downloaded: body.len() as u64,
+            total,
+        });
     }
+
+    let stored = store_chunk(chunk, &body, hash_kind, chunk_store_path)
+        .map_err(|_| anyhow!("Invalid chunk data returned."))?;
+
+    Ok(stored.then_some(body.len() as u64))
 }
 
-/// Installs all chunks from a list of mirrors
+/// Installs all chunks from a list of mirrors, telling `progress` what every download is doing.
 /// NOTE: Chunks will be installed out of order, and any mirror potentially.
 ///
 /// # Errors
 ///
 /// - The internet sent back corrupt/malicious data, timed out, or is blatently not working.
 /// - Filesystem out of space
+///
+/// # Returns
+///
+/// What was downloaded, and from which mirrors
 pub async fn install_chunks(
     chunks: &[&Chunk],
     mirrors: &[String],
     hash_kind: HashKind,
     chunk_store_path: &Path,
-) -> Result<()> {
+    auth: Option<&RepoAuth>,
+    progress: &(dyn Fn(DownloadEvent) + Sync),
+) -> Result<InstallStats> {
     fs::create_dir_all(chunk_store_path)?;
 
-    tokio_stream::iter(chunks.iter()) // clone so each task owns its Chunk
+    // clone so each task owns its Chunk, which also keeps the future Send
+    let chunks: Vec<Chunk> = chunks.iter().map(|chunk| (*chunk).clone()).collect();
+
+    progress(DownloadEvent::Started {
+        chunks: chunks.len(),
+        bytes: chunks.iter().map(Chunk::size).sum(),
+    });
+
+    let stats = tokio_stream::iter(chunks)
         .map(|chunk| {
             let mirrors = mirrors.to_vec();
             let chunk_store_path = chunk_store_path.to_path_buf();
+            let auth = auth.cloned();
 
             async move {
-                println!("Downloading chunk {}", chunk.hash);
+                let mut stats = InstallStats::default();
 
                 for mirror in mirrors {
-                    match install_chunk(chunk, &mirror, hash_kind, &chunk_store_path).await {
-                        Ok(()) => {
-                            println!("Downloaded chunk {}", chunk.hash);
-                            return Ok(());
+                    match install_chunk(
+                        &chunk,
+                        &mirror,
+                        hash_kind,
+                        &chunk_store_path,
+                        auth.as_ref(),
+                        progress,
+                    )
+                    .await
+                    {
+                        // Already stored, or another download of the same chunk finished first
+                        Ok(None) => {
+                            progress(DownloadEvent::Finished { chunk: &chunk });
+                            stats.chunks_reused = 1;
+                            stats.bytes_reused = chunk.size();
+                            return Ok(stats);
+                        }
+                        Ok(Some(bytes)) => {
+                            progress(DownloadEvent::Finished { chunk: &chunk });
+                            stats.chunks_fetched = 1;
+                            stats.bytes_downloaded = bytes;
+                            let mirror_stats = stats.mirrors.entry(mirror).or_default();
+                            mirror_stats.chunks = 1;
+                            mirror_stats.bytes = bytes;
+                            return Ok(stats);
                         }
                         Err(err) => {
-                            eprintln!(
-                                "Failed to fetch chunk {} from mirror {mirror}: {err}",
-                                &chunk.hash
-                            );
+                            progress(DownloadEvent::Failed {
+                                chunk: &chunk,
+                                mirror: &mirror,
+                                error: &err,
+                            });
+                            stats.mirrors.entry(mirror).or_default().failures += 1;
                         }
                     }
                 }
 
+                progress(DownloadEvent::Finished { chunk: &chunk });
                 bail!("All mirrors failed for chunk {}", &chunk.hash);
             }
         })
         .buffer_unordered(8) // run up to 8 downloads at once
-        .try_collect::<()>() // fail-fast on first error
-        .await?;
+        .try_fold(InstallStats::default(), |mut total, stats| async move {
+            total.merge(stats);
+            Ok(total)
+        }) // fail-fast on first error
+        .await;
 
-    Ok(())
+    progress(DownloadEvent::Done);
+
+    stats
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        chunks::{compress::compress_chunk, get_chunk_filename, hash::hash},
+        repo::credentials::AuthMethod,
+    };
     use httpmock::prelude::*;
     use std::path::PathBuf;
     use temp_dir::TempDir;
@@ -119,6 +177,7 @@ mod tests {
                 hash,
                 path: PathBuf::new(),
                 size: 1,
+                bytes: None,
                 permissions: 0o644,
             };
 
@@ -133,14 +192,148 @@ mod tests {
             });
 
             // Run function
-            install_chunk(&chunk, &server.base_url(), hash_kind, chunk_store_path)
-                .await
-                .unwrap();
+            let bytes = install_chunk(
+                &chunk,
+                &server.base_url(),
+                hash_kind,
+                chunk_store_path,
+                None,
+                &|_| {},
+            )
+            .await
+            .unwrap();
+            assert_eq!(bytes, Some(data.len() as u64));
 
             // Verify file exists
             let path = chunk_store_path.join(get_chunk_filename(&chunk.hash, chunk.permissions));
             let saved = fs::read(path).unwrap();
             assert_eq!(saved, data);
+
+            // Reused, not downloaded again
+            let bytes = install_chunk(
+                &chunk,
+                &server.base_url(),
+                hash_kind,
+                chunk_store_path,
+                None,
+                &|_| {},
+            )
+            .await
+            .unwrap();
+            assert_eq!(bytes, None);
+        });
+    }
+
+    #[test]
+    fn test_install_chunk_compressed() {
+        run_async_test(async {
+            let temp_dir = TempDir::new().unwrap();
+            let chunk_store_path = temp_dir.path();
+
+            let data = "compressible ".repeat(100);
+            let compressed = compress_chunk(data.as_bytes()).unwrap().unwrap();
+            let hash_kind = HashKind::Blake3;
+            let mut chunk = Chunk {
+                hash: hash(hash_kind, data.as_bytes()),
+                path: PathBuf::new(),
+                size: 1,
+                bytes: None,
+                permissions: 0o644,
+            };
+            chunk.set_exact_size(data.len() as u64);
+
+            // Served by a mirror of a Repository of the compressed chunks edition
+            let server = MockServer::start();
+            let _mock = server.mock(|when, then| {
+                when.path(format!("/chunks/{}", chunk.filename()));
+                then.status(200).body(&compressed);
+            });
+
+            let bytes = install_chunk(
+                &chunk,
+                &server.base_url(),
+                hash_kind,
+                chunk_store_path,
+                None,
+                &|_| {},
+            )
+            .await
+            .unwrap();
+            assert_eq!(bytes, Some(compressed.len() as u64));
+
+            // Stored decompressed, so installs can hard link it
+            let path = chunk_store_path.join(chunk.filename());
+            assert_eq!(fs::read(&path).unwrap(), data.as_bytes());
+        });
+    }
+
+    #[test]
+    fn test_install_chunk_authenticated() {
+        run_async_test(async {
+            let temp_dir = TempDir::new().unwrap();
+            let chunk_store_path = temp_dir.path();
+
+            let data = b"private";
+            let hash_kind = HashKind::Blake3;
+            let chunk = Chunk {
+                hash: hash(hash_kind, data),
+                path: PathBuf::new(),
+                size: 1,
+                bytes: None,
+                permissions: 0o644,
+            };
+
+            // Only answers requests carrying the token
+            let server = MockServer::start();
+            let _mock = server.mock(|when, then| {
+                when.header("authorization", "Bearer t0ken");
+                then.status(200).body(data);
+            });
+
+            assert!(
+                install_chunk(
+                    &chunk,
+                    &server.base_url(),
+                    hash_kind,
+                    chunk_store_path,
+                    None,
+                    &|_| {},
+                )
+                .await
+                .is_err()
+            );
+
+            // Issued for another host
+            let auth = RepoAuth {
+                host: "example.com".into(),
+                method: AuthMethod::Bearer("t0ken".into()),
+            };
+            assert!(
+                install_chunk(
+                    &chunk,
+                    &server.base_url(),
+                    hash_kind,
+                    chunk_store_path,
+                    Some(&auth),
+                    &|_| {},
+                )
+                .await
+                .is_err()
+            );
+
+            let auth =
+                RepoAuth::new(&server.base_url(), AuthMethod::Bearer("t0ken".into())).unwrap();
+            install_chunk(
+                &chunk,
+                &server.base_url(),
+                hash_kind,
+                chunk_store_path,
+                Some(&auth),
+                &|_| {},
+            )
+            .await
+            .unwrap();
+            assert!(chunk_store_path.join(chunk.filename()).exists());
         });
     }
 
@@ -160,6 +353,7 @@ mod tests {
                 hash,
                 path: PathBuf::new(),
                 size: 1,
+                bytes: None,
                 permissions: 0o644,
             };
 
@@ -172,8 +366,15 @@ mod tests {
                 then.status(200).body(bad_data);
             });
 
-            let result =
-                install_chunk(&chunk, &server.base_url(), hash_kind, chunk_store_path).await;
+            let result = install_chunk(
+                &chunk,
+                &server.base_url(),
+                hash_kind,
+                chunk_store_path,
+                None,
+                &|_| {},
+            )
+            .await;
 
             assert!(result.is_err(), "Expected corrupt data to fail");
         });
@@ -193,6 +394,7 @@ mod tests {
                 hash,
                 path: PathBuf::new(),
                 size: 1,
+                bytes: None,
                 permissions: 0o644,
             };
 
@@ -214,11 +416,13 @@ mod tests {
             });
 
             // Run function
-            install_chunks(
+            let stats = install_chunks(
                 std::slice::from_ref(&&chunk),
                 &[bad_server.base_url(), good_server.base_url()],
                 hash_kind,
                 chunk_store_path,
+                None,
+                &|_| {},
             )
             .await
             .unwrap();
@@ -227,6 +431,11 @@ mod tests {
             let path = chunk_store_path.join(get_chunk_filename(&chunk.hash, chunk.permissions));
             let saved = fs::read(path).unwrap();
             assert_eq!(saved, data);
+
+            assert_eq!(stats.chunks_fetched, 1);
+            assert_eq!(stats.bytes_downloaded, data.len() as u64);
+            assert_eq!(stats.mirrors[&bad_server.base_url()].failures, 1);
+            assert_eq!(stats.mirrors[&good_server.base_url()].chunks, 1);
         });
     }
 }
@@ -1,14 +1,34 @@
 use anyhow::{Context, Result};
+use serde::Serialize;
 use std::{
-    fs,
-    os::unix::fs::PermissionsExt,
+    collections::{BTreeMap, HashSet},
+    fs::{self, File},
     path::{Path, PathBuf},
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    thread,
 };
 use walkdir::WalkDir;
 
-use crate::chunks::{Chunk, HashKind, get_chunk_filename, hash::hash};
+use crate::{
+    chunks::{
+        Chunk, HashKind,
+        compress::{decompress_file, is_compressed_file},
+        get_chunk_filename,
+        hash::hash,
+        tmp_chunk_path,
+    },
+    config::{get_system_data_dir, read_config},
+    utils::{
+        owner::Owner,
+        platform::{link_count, mode, set_mode, set_owner, sync_dir},
+    },
+};
 
-/// Turns a filesystem tree into a list of chunks
+/// Turns a filesystem tree into a list of chunks, hashing and storing files across all available cores.
+/// Chunks are always listed sorted by path, however the work was split.
 ///
 /// # Errors
 ///
@@ -22,59 +42,129 @@ pub fn save_tree(
     chunk_store_path: &Path,
     hash_kind: HashKind,
 ) -> Result<Vec<Chunk>> {
-    let mut chunks = Vec::new();
-
     if !chunk_store_path.exists() {
         fs::create_dir_all(chunk_store_path)?;
     }
 
+    let claimed = Mutex::new(HashSet::new());
+
     if tree_path.is_file() {
         let path: PathBuf = tree_path.file_name().unwrap().into();
-        let contents = fs::read(tree_path)?;
-        let size = (contents.len() as u64) / 1024;
-        let hash = hash(hash_kind, &contents);
-        let mode = fs::metadata(tree_path)?.permissions().mode() & 0o777;
-
-        let chunk_path = &chunk_store_path.join(get_chunk_filename(&hash, mode));
-        if fs::hard_link(tree_path, chunk_path).is_err() {
-            fs::write(chunk_path, contents)?;
-        }
+        let mode = mode(&fs::metadata(tree_path)?);
 
-        chunks.push(Chunk {
-            hash,
+        return Ok(vec![save_tree_file(
+            tree_path,
             path,
-            size,
-            permissions: mode,
-        });
-    } else {
-        for entry in WalkDir::new(tree_path) {
-            let file = entry?;
+            mode,
+            chunk_store_path,
+            hash_kind,
+            &claimed,
+        )?]);
+    }
 
-            if !file.file_type().is_file() {
-                continue;
-            }
+    let mut files = Vec::new();
+    for entry in WalkDir::new(tree_path).sort_by_file_name() {
+        let file = entry?;
 
+        if file.file_type().is_file() {
             let path = file.path().strip_prefix(tree_path)?.to_path_buf();
-            let contents = fs::read(file.path())?;
-            let size = (contents.len() as u64) / 1024;
-            let hash = hash(hash_kind, &contents);
-            let mode = file.metadata()?.permissions().mode() & 0o777;
-
-            let chunk_path = &chunk_store_path.join(get_chunk_filename(&hash, mode));
-            if fs::hard_link(file.path(), chunk_path).is_err() {
-                fs::write(chunk_path, contents)?;
-            }
+            files.push((file.path().to_path_buf(), path, mode(&file.metadata()?)));
+        }
+    }
 
-            chunks.push(Chunk {
-                hash,
-                path,
-                size,
-                permissions: mode,
-            });
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let workers = thread::available_parallelism().map_or(1, usize::from);
+
+    let mut chunks = thread::scope(|scope| -> Result<Vec<(usize, Chunk)>> {
+        let handles: Vec<_> = (0..workers.min(files.len()))
+            .map(|_| {
+                scope.spawn(|| -> Result<Vec<(usize, Chunk)>> {
+                    let mut saved = Vec::new();
+
+                    while !stop.load(Ordering::Relaxed) {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some((file_path, path, mode)) = files.get(index) else {
+                            break;
+                        };
+
+                        match save_tree_file(
+                            file_path,
+                            path.clone(),
+                            *mode,
+                            chunk_store_path,
+                            hash_kind,
+                            &claimed,
+                        ) {
+                            Ok(chunk) => saved.push((index, chunk)),
+                            Err(err) => {
+                                stop.store(true, Ordering::Relaxed);
+                                return Err(err);
+                            }
+                        }
+                    }
+
+                    Ok(saved)
+                })
+            })
+            .collect();
+
+        let mut chunks = Vec::new();
+        for handle in handles {
+            chunks.extend(
+                handle
+                    .join()
+                    .map_err(|_| anyhow::anyhow!("Chunking thread panicked"))??,
+            );
         }
+
+        Ok(chunks)
+    })?;
+
+    chunks.sort_by_key(|(index, _)| *index);
+
+    Ok(chunks.into_iter().map(|(_, chunk)| chunk).collect())
+}
+
+/// Hashes a file of a tree and stores it as a chunk, unless another file with the same chunk was stored already.
+/// `claimed` holds the filenames of chunks stored so far, so two threads never write the same chunk.
+fn save_tree_file(
+    file_path: &Path,
+    path: PathBuf,
+    mode: u32,
+    chunk_store_path: &Path,
+    hash_kind: HashKind,
+    claimed: &Mutex<HashSet<String>>,
+) -> Result<Chunk> {
+    let contents = fs::read(file_path)?;
+    let bytes = contents.len() as u64;
+    let hash = hash(hash_kind, &contents);
+    let chunk_name = get_chunk_filename(&hash, mode);
+
+    let first = claimed
+        .lock()
+        .map_err(|_| anyhow::anyhow!("Chunking thread panicked"))?
+        .insert(chunk_name.clone());
+    if first {
+        save_file(file_path, &chunk_store_path.join(chunk_name), &contents)?;
+    }
+
+    Ok(Chunk {
+        hash,
+        path,
+        size: bytes / 1024,
+        bytes: Some(bytes),
+        permissions: mode,
+    })
+}
+
+/// Stores a file of a tree as a chunk, hard linked when possible
+fn save_file(file_path: &Path, chunk_path: &Path, contents: &[u8]) -> Result<()> {
+    if fs::hard_link(file_path, chunk_path).is_err() {
+        fs::write(chunk_path, contents)?;
     }
 
-    Ok(chunks)
+    Ok(())
 }
 
 /// Turns a list of chunks into a filesystem tree
@@ -112,19 +202,314 @@ pub fn load_tree_unsafe(load_path: &Path, chunk_store_path: &Path, chunks: &[Chu
             fs::remove_file(&extracted_path)?;
         }
 
-        fs::hard_link(&chunk_path, &extracted_path)
-            .or_else(|_| fs::copy(&chunk_path, &extracted_path).map(|_| ()))
-            .with_context(|| "Could not copy data while extracting")?;
+        // Chunk stores written while chunks were stored compressed can't share those with the tree
+        if is_compressed_file(&chunk_path)
+            .with_context(|| "Could not read chunk while extracting")?
+        {
+            decompress_file(&chunk_path, &extracted_path)
+                .with_context(|| "Could not decompress data while extracting")?;
+        } else {
+            fs::hard_link(&chunk_path, &extracted_path)
+                .or_else(|_| fs::copy(&chunk_path, &extracted_path).map(|_| ()))
+                .with_context(|| "Could not copy data while extracting")?;
+        }
+
+        set_mode(&extracted_path, chunk.permissions)?;
+    }
+
+    Ok(())
+}
+
+/// Set by `--fast`, skips syncing installs to disk whatever the config says
+static FAST_INSTALLS: AtomicBool = AtomicBool::new(false);
+
+/// Skips syncing installs to disk for the rest of the process, for throwaway environments.
+pub fn set_fast_installs(fast: bool) {
+    FAST_INSTALLS.store(fast, Ordering::Relaxed);
+}
+
+/// Whether a tree installed at `load_path` is synced to disk before it is switched to:
+/// `sync_installs` from the config, which defaults to system-wide installs only. Never with `--fast`.
+///
+/// # Errors
+///
+/// - Invalid `config.yml`
+pub fn should_sync_tree(load_path: &Path) -> Result<bool> {
+    if FAST_INSTALLS.load(Ordering::Relaxed) {
+        return Ok(false);
+    }
+
+    Ok(read_config(None)?
+        .sync_installs
+        .unwrap_or_else(|| load_path.starts_with(get_system_data_dir())))
+}
+
+/// Flushes every file and directory of a tree to disk, and the directory it is in.
+///
+/// A crash right after can't leave empty files behind.
+/// Hard linked files share their data with the chunk store, which is flushed with them.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions, IO errors)
+pub fn sync_tree(tree_path: &Path) -> Result<()> {
+    for entry in WalkDir::new(tree_path).contents_first(true) {
+        let entry = entry?;
+
+        if entry.file_type().is_file() {
+            File::open(entry.path())?.sync_all()?;
+        } else if entry.file_type().is_dir() {
+            sync_dir(entry.path())?;
+        }
+    }
+
+    if let Some(parent) = tree_path.parent() {
+        sync_dir(parent)?;
+    }
+
+    Ok(())
+}
+
+/// Makes every directory of a tree `755`, and hands the whole tree to `owner`.
+/// Files keep the mode of their chunk.
+///
+/// Files hard linked into the chunk store are copied before they are handed over,
+/// so chunks other installs share never change owner.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions, eg: changing the owner without root)
+pub fn normalize_tree(tree_path: &Path, owner: Option<Owner>) -> Result<()> {
+    for entry in WalkDir::new(tree_path) {
+        let entry = entry?;
+
+        if entry.file_type().is_dir() {
+            set_mode(entry.path(), 0o755)?;
+        }
+
+        if let Some(owner) = owner {
+            if entry.file_type().is_file() && link_count(&entry.metadata()?) > 1 {
+                unshare_file(entry.path())?;
+            }
 
-        let mut perms = fs::metadata(&extracted_path)?.permissions();
-        perms.set_mode(chunk.permissions & 0o777);
-        fs::set_permissions(&extracted_path, perms)?;
+            set_owner(entry.path(), owner.uid, owner.gid).with_context(|| {
+                format!("Could not change the owner of {}", entry.path().display())
+            })?;
+        }
     }
 
     Ok(())
 }
 
-/// Installs all chunks in a tree
+/// Replaces a hard linked file with a copy of its own, keeping its mode
+fn unshare_file(path: &Path) -> Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+
+    fs::copy(path, &tmp_path)
+        .and_then(|_| fs::rename(&tmp_path, path))
+        .with_context(|| format!("Could not copy {} out of the chunk store", path.display()))
+        .inspect_err(|_| {
+            let _ = fs::remove_file(&tmp_path);
+        })
+}
+
+/// Describes the files at `paths` (relative to `tree_path`) as they are on disk now.
+///
+/// For files changed after the tree was loaded, eg: by rewriting their shebang. Files that are not one of `chunks` are left out, each keeps the permissions of its chunk.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+pub fn rewritten_chunks(
+    tree_path: &Path,
+    chunks: &[Chunk],
+    paths: &[PathBuf],
+    hash_kind: HashKind,
+) -> Result<Vec<Chunk>> {
+    let paths: HashSet<&Path> = paths.iter().map(PathBuf::as_path).collect();
+
+    chunks
+        .iter()
+        .filter(|chunk| paths.contains(chunk.path.as_path()))
+        .map(|chunk| {
+            let contents = fs::read(tree_path.join(&chunk.path))?;
+            let bytes = contents.len() as u64;
+
+            Ok(Chunk {
+                hash: hash(hash_kind, &contents),
+                path: chunk.path.clone(),
+                size: bytes / 1024,
+                bytes: Some(bytes),
+                permissions: chunk.permissions,
+            })
+        })
+        .collect()
+}
+
+/// The chunks of a tree as installed, with the ones in `rewritten` replacing those at the same path
+#[must_use]
+pub fn installed_chunks(chunks: &[Chunk], rewritten: &[Chunk]) -> Vec<Chunk> {
+    chunks
+        .iter()
+        .map(|chunk| {
+            rewritten
+                .iter()
+                .find(|rewritten| rewritten.path == chunk.path)
+                .unwrap_or(chunk)
+                .clone()
+        })
+        .collect()
+}
+
+/// Gets all chunks of a tree that are not in the chunk store yet
+#[must_use]
+pub fn missing_chunks<'a>(chunks: &'a [Chunk], chunk_store_path: &Path) -> Vec<&'a Chunk> {
+    chunks
+        .iter()
+        .filter(|chunk| {
+            !chunk_store_path
+                .join(get_chunk_filename(&chunk.hash, chunk.permissions))
+                .exists()
+        })
+        .collect()
+}
+
+/// Links chunks that are missing from `chunk_store_path` in from a secondary, read-only chunk store.
+///
+/// Hard links are tried first, falling back to a copy across filesystems or when linking isn't permitted.
+///
+/// # Errors
+///
+/// - Filesystem errors (Out of space, Permissions)
+///
+/// # Returns
+///
+/// The number of imported chunks
+pub fn import_chunks(
+    chunks: &[Chunk],
+    chunk_store_path: &Path,
+    secondary_store_path: &Path,
+) -> Result<usize> {
+    let mut imported = 0;
+
+    for chunk in missing_chunks(chunks, chunk_store_path) {
+        let chunk_name = chunk.filename();
+        let source_path = secondary_store_path.join(&chunk_name);
+
+        if !source_path.exists() {
+            continue;
+        }
+
+        let chunk_path = chunk_store_path.join(&chunk_name);
+
+        if fs::hard_link(&source_path, &chunk_path).is_err() {
+            let tmp_chunk_path = tmp_chunk_path(chunk_store_path, &chunk_name);
+
+            fs::copy(&source_path, &tmp_chunk_path)?;
+            fs::rename(&tmp_chunk_path, &chunk_path)?;
+        }
+
+        imported += 1;
+    }
+
+    Ok(imported)
+}
+
+/// Splits chunks into the ones running `entrypoints` likely needs first, and the rest.
+///
+/// The entrypoints themselves come first, along with the files next to them (eg: the rest of
+/// `bin/`), so they can be downloaded before anything else. Entrypoints may start with a `/`.
+#[must_use]
+pub fn entrypoint_chunks<'a>(
+    chunks: &'a [Chunk],
+    entrypoints: &[PathBuf],
+) -> (Vec<&'a Chunk>, Vec<&'a Chunk>) {
+    let entrypoints: Vec<&Path> = entrypoints
+        .iter()
+        .map(|entrypoint| entrypoint.strip_prefix("/").unwrap_or(entrypoint))
+        .collect();
+
+    chunks.iter().partition(|chunk| {
+        entrypoints.iter().any(|entrypoint| {
+            chunk.path == *entrypoint || chunk.path.parent() == entrypoint.parent()
+        })
+    })
+}
+
+/// What a mirror served while installing chunks
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct MirrorStats {
+    pub chunks: usize,
+    pub bytes: u64,
+    /// Chunks that failed to download from this mirror, and were tried on the next one
+    pub failures: usize,
+}
+
+/// What chunk downloads are doing, passed to the `progress` callback of anything that downloads chunks
+#[derive(Debug, Clone, Copy)]
+pub enum DownloadEvent<'a> {
+    /// A batch of chunks starts downloading, `bytes` being the sum of their sizes
+    Started { chunks: usize, bytes: u64 },
+    /// Part of a chunk arrived. `total` is what the mirror announced, less than the chunk's size if it is compressed
+    Progress {
+        chunk: &'a Chunk,
+        downloaded: u64,
+        total: Option<u64>,
+    },
+    /// A mirror failed to serve a chunk, the next one is tried
+    Failed {
+        chunk: &'a Chunk,
+        mirror: &'a str,
+        error: &'a anyhow::Error,
+    },
+    /// A chunk is in the chunk store, or every mirror failed to serve it
+    Finished { chunk: &'a Chunk },
+    /// Every chunk of the batch is done
+    Done,
+}
+
+/// Where the chunks of an install came from, to see how well the chunk store is reused
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct InstallStats {
+    /// Chunks downloaded from a mirror
+    pub chunks_fetched: usize,
+    pub bytes_downloaded: u64,
+    /// Chunks that were already in the chunk store
+    pub chunks_reused: usize,
+    pub bytes_reused: u64,
+    /// Keyed by mirror url
+    pub mirrors: BTreeMap<String, MirrorStats>,
+}
+
+impl InstallStats {
+    /// Stats for chunks that all came from the chunk store
+    #[must_use]
+    pub fn reusing(chunks: &[Chunk]) -> Self {
+        Self {
+            chunks_reused: chunks.len(),
+            bytes_reused: chunks.iter().map(Chunk::size).sum(),
+            ..Self::default()
+        }
+    }
+
+    /// Adds another install's stats to these, eg: for every package of an update
+    pub fn merge(&mut self, other: Self) {
+        self.chunks_fetched += other.chunks_fetched;
+        self.bytes_downloaded += other.bytes_downloaded;
+        self.chunks_reused += other.chunks_reused;
+        self.bytes_reused += other.bytes_reused;
+
+        for (mirror, other) in other.mirrors {
+            let stats = self.mirrors.entry(mirror).or_default();
+            stats.chunks += other.chunks;
+            stats.bytes += other.bytes;
+            stats.failures += other.failures;
+        }
+    }
+}
+
+/// Installs all chunks in a tree, authenticated with `auth` if given, reporting downloads to `progress`
 ///
 /// # Errors
 ///
@@ -136,30 +521,153 @@ pub async fn install_tree(
     chunk_store_path: &Path,
     mirrors: &[String],
     hash_kind: HashKind,
-) -> Result<()> {
+    auth: Option<&crate::repo::credentials::RepoAuth>,
+    progress: &(dyn Fn(DownloadEvent) + Sync),
+) -> Result<InstallStats> {
     use crate::chunks::network::install_chunks;
 
-    let mut not_installed_chunks = Vec::new();
+    let not_installed_chunks = missing_chunks(chunks, chunk_store_path);
+    let not_installed: HashSet<String> = not_installed_chunks
+        .iter()
+        .map(|chunk| chunk.filename())
+        .collect();
+    let reused: Vec<Chunk> = chunks
+        .iter()
+        .filter(|chunk| !not_installed.contains(&chunk.filename()))
+        .cloned()
+        .collect();
+
+    let mut stats = install_chunks(
+        &not_installed_chunks,
+        mirrors,
+        hash_kind,
+        chunk_store_path,
+        auth,
+        progress,
+    )
+    .await?;
+    stats.merge(InstallStats::reusing(&reused));
+
+    Ok(stats)
+}
+
+/// How an on-disk file differs from the chunk it was installed from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TreeDiscrepancy {
+    /// In the chunk list, but not on disk
+    Missing,
+    /// On disk, but not in the chunk list
+    Unexpected,
+    SizeMismatch,
+    PermissionsMismatch,
+}
+
+impl std::fmt::Display for TreeDiscrepancy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Missing => write!(f, "missing"),
+            Self::Unexpected => write!(f, "unexpected"),
+            Self::SizeMismatch => write!(f, "size mismatch"),
+            Self::PermissionsMismatch => write!(f, "permissions mismatch"),
+        }
+    }
+}
+
+/// A file in an installed tree, compared against its chunk list
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeFile {
+    pub path: PathBuf,
+    /// Size in bytes. For missing files, the expected size.
+    pub size: u64,
+    /// Unix mode permissions. For missing files, the expected permissions.
+    pub permissions: u32,
+    pub discrepancy: Option<TreeDiscrepancy>,
+}
+
+/// Lists the files actually in a tree, flagging any that differ from `chunks`.
+/// `install.meta` at the root of the tree is ignored.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+pub fn scan_tree(tree_path: &Path, chunks: &[Chunk]) -> Result<Vec<TreeFile>> {
+    let mut files = Vec::new();
+
+    for entry in WalkDir::new(tree_path).follow_root_links(true) {
+        let file = entry?;
+
+        if !file.file_type().is_file() {
+            continue;
+        }
+
+        let path = file.path().strip_prefix(tree_path)?.to_path_buf();
+        if path == Path::new("install.meta") {
+            continue;
+        }
+
+        let metadata = file.metadata()?;
+        let size = metadata.len();
+        let permissions = mode(&metadata);
+
+        let discrepancy = match chunks.iter().find(|chunk| chunk.path == path) {
+            None => Some(TreeDiscrepancy::Unexpected),
+            Some(chunk) if !chunk.matches_size(size) => Some(TreeDiscrepancy::SizeMismatch),
+            Some(chunk) if chunk.permissions & 0o777 != permissions => {
+                Some(TreeDiscrepancy::PermissionsMismatch)
+            }
+            Some(_) => None,
+        };
+
+        files.push(TreeFile {
+            path,
+            size,
+            permissions,
+            discrepancy,
+        });
+    }
 
     for chunk in chunks {
-        let chunk_path = chunk_store_path.join(get_chunk_filename(&chunk.hash, chunk.permissions));
-        if !chunk_path.exists() {
-            not_installed_chunks.push(chunk);
+        if !tree_path.join(&chunk.path).is_file() {
+            files.push(TreeFile {
+                path: chunk.path.clone(),
+                size: chunk.size(),
+                permissions: chunk.permissions,
+                discrepancy: Some(TreeDiscrepancy::Missing),
+            });
         }
     }
 
-    install_chunks(&not_installed_chunks, mirrors, hash_kind, chunk_store_path).await?;
+    files.sort_by(|a, b| a.path.cmp(&b.path));
 
-    Ok(())
+    Ok(files)
+}
+
+/// Measures a tree's actual size on disk in bytes.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+pub fn measure_tree_size(tree_path: &Path) -> Result<u64> {
+    let mut size: u64 = 0;
+
+    for entry in WalkDir::new(tree_path) {
+        let entry = entry?;
+
+        if entry.file_type().is_file() {
+            size += entry.metadata()?.len();
+        }
+    }
+
+    Ok(size)
 }
 
-/// Returns the tree's estimated size in kilobytes.
+/// Returns the tree's estimated size in bytes.
 #[must_use]
 pub fn estimate_tree_size(chunks: &[Chunk]) -> u64 {
     let mut size: u64 = 0;
 
     for chunk in chunks {
-        size += chunk.size;
+        size += chunk.size();
     }
 
     size
@@ -167,9 +675,10 @@ pub fn estimate_tree_size(chunks: &[Chunk]) -> u64 {
 
 #[cfg(test)]
 mod tests {
-    use std::os::unix::fs::MetadataExt;
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
 
     use super::*;
+    use crate::chunks::{compress::compress_chunk, verify_tree};
 
     use temp_dir::TempDir;
 
@@ -181,6 +690,27 @@ mod tests {
         assert_eq!(get_chunk_filename(hash, permissions), "a8sf799a8s6fa7f5511");
     }
 
+    #[test]
+    fn test_entrypoint_chunks() -> Result<()> {
+        let tree = TempDir::new()?;
+        let chunk_store = TempDir::new()?;
+        fs::create_dir_all(tree.path().join("bin"))?;
+        fs::create_dir_all(tree.path().join("share/doc"))?;
+        fs::write(tree.path().join("bin/app"), "app")?;
+        fs::write(tree.path().join("bin/helper"), "helper")?;
+        fs::write(tree.path().join("share/doc/README"), "readme")?;
+        let chunks = save_tree(tree.path(), chunk_store.path(), HashKind::Blake3)?;
+
+        let (first, rest) = entrypoint_chunks(&chunks, &["/bin/app".into()]);
+        let mut first: Vec<&Path> = first.iter().map(|chunk| chunk.path()).collect();
+        first.sort();
+        assert_eq!(first, vec![Path::new("bin/app"), Path::new("bin/helper")]);
+        assert_eq!(rest.len(), 1);
+        assert_eq!(rest[0].path(), Path::new("share/doc/README"));
+
+        Ok(())
+    }
+
     #[test]
     fn test_save_tree() -> Result<()> {
         let initial_tree_path = TempDir::new()?;
@@ -216,13 +746,83 @@ mod tests {
         assert!(chunk_paths.contains(&"file".to_string()));
         assert!(chunk_paths.contains(&"path/file".to_string()));
 
-        // Check that the estimated size is correct (in KB)
-        let expected_size = (b"Example".len() as u64) / 1024 + (b"Example2".len() as u64) / 1024;
+        // Check that the estimated size is correct
+        let expected_size = (b"Example".len() + b"Example2".len()) as u64;
         assert_eq!(estimate_tree_size(&chunks), expected_size);
 
         Ok(())
     }
 
+    #[test]
+    fn test_save_tree_order() -> Result<()> {
+        let tree = TempDir::new()?;
+        let chunk_store = TempDir::new()?;
+        let mut sorted = Vec::new();
+        for dir in 0..8 {
+            fs::create_dir_all(tree.path().join(format!("dir{dir}")))?;
+            for file in 0..32 {
+                // Every file's contents are also in another directory
+                let path = PathBuf::from(format!("dir{dir}/file{file}"));
+                fs::write(tree.path().join(&path), format!("{} {file}", dir % 4))?;
+                sorted.push(path);
+            }
+        }
+        sorted.sort();
+
+        let chunks = save_tree(tree.path(), chunk_store.path(), HashKind::Blake3)?;
+
+        let paths: Vec<PathBuf> = chunks.iter().map(|chunk| chunk.path.clone()).collect();
+        assert_eq!(paths, sorted);
+        assert_eq!(fs::read_dir(chunk_store.path())?.count(), 4 * 32);
+        assert_eq!(
+            save_tree(tree.path(), chunk_store.path(), HashKind::Blake3)?,
+            chunks
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sha_round_trip() -> Result<()> {
+        for hash_kind in [HashKind::Sha256, HashKind::Sha512] {
+            let tree = TempDir::new()?;
+            let loaded_tree = TempDir::new()?;
+            let chunk_store = TempDir::new()?;
+            fs::write(tree.path().join("file"), "Example")?;
+            fs::write(tree.path().join("text"), "compressible ".repeat(100))?;
+
+            let chunks = save_tree(tree.path(), chunk_store.path(), hash_kind)?;
+            assert!(chunks.iter().all(|chunk| chunk.hash
+                == hash(
+                    hash_kind,
+                    &fs::read(tree.path().join(&chunk.path)).unwrap_or_default()
+                )));
+
+            let loaded_path = loaded_tree.path().join("tree");
+            load_tree(&loaded_path, chunk_store.path(), &chunks)?;
+            verify_tree(&loaded_path, &chunks, hash_kind)?;
+            assert_eq!(fs::read_to_string(loaded_path.join("file"))?, "Example");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync_tree() -> Result<()> {
+        let tree = TempDir::new()?;
+        fs::create_dir(tree.path().join("bin"))?;
+        fs::write(tree.path().join("bin/hello"), "hello")?;
+
+        sync_tree(tree.path())?;
+
+        // Only ever skips syncing, so other tests running meanwhile are unaffected
+        set_fast_installs(true);
+        assert!(!should_sync_tree(&get_system_data_dir().join("repos"))?);
+        set_fast_installs(false);
+
+        Ok(())
+    }
+
     #[test]
     fn test_load_tree() -> Result<()> {
         let initial_tree_path = TempDir::new()?;
@@ -252,6 +852,86 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_compressed_chunks() -> Result<()> {
+        let tree = TempDir::new()?;
+        let loaded_tree = TempDir::new()?;
+        let chunk_store = TempDir::new()?;
+        let text = "compressible ".repeat(100);
+        fs::write(tree.path().join("text"), &text)?;
+        fs::write(tree.path().join("tiny"), "tiny")?;
+
+        let chunks = save_tree(tree.path(), chunk_store.path(), HashKind::Blake3)?;
+        let chunk = |name: &str| {
+            chunks
+                .iter()
+                .find(|chunk| chunk.path == Path::new(name))
+                .map(|chunk| chunk_store.path().join(chunk.filename()))
+        };
+        let text_chunk = chunk("text").expect("Saved");
+        // Stored as is, however well it compresses, so installs share it
+        assert_eq!(fs::read_to_string(&text_chunk)?, text);
+        assert_eq!(estimate_tree_size(&chunks), text.len() as u64 + 4);
+
+        // Chunks stored compressed by an older version are decompressed into the tree instead
+        let tiny_chunk = chunk("tiny").expect("Saved");
+        fs::remove_file(&text_chunk)?;
+        fs::write(
+            &text_chunk,
+            compress_chunk(text.as_bytes())?.expect("Text compresses well"),
+        )?;
+
+        load_tree(loaded_tree.path(), chunk_store.path(), &chunks)?;
+        assert_eq!(fs::read_to_string(loaded_tree.path().join("text"))?, text);
+        assert_eq!(fs::read_to_string(loaded_tree.path().join("tiny"))?, "tiny");
+
+        let inode = |path: &Path| fs::metadata(path).map(|metadata| metadata.ino());
+        assert_ne!(
+            inode(&text_chunk)?,
+            inode(&loaded_tree.path().join("text"))?
+        );
+        assert_eq!(
+            inode(&tiny_chunk)?,
+            inode(&loaded_tree.path().join("tiny"))?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_normalize_tree() -> Result<()> {
+        let tree = TempDir::new()?;
+        let dir_path = tree.path().join("private");
+        fs::create_dir(&dir_path)?;
+        fs::set_permissions(&dir_path, fs::Permissions::from_mode(0o700))?;
+        fs::write(dir_path.join("file"), "")?;
+        fs::set_permissions(dir_path.join("file"), fs::Permissions::from_mode(0o640))?;
+        let store = TempDir::new()?;
+        fs::write(store.path().join("chunk"), "shared")?;
+        fs::hard_link(store.path().join("chunk"), dir_path.join("linked"))?;
+
+        // Handing files to ourselves works without root
+        let metadata = fs::metadata(tree.path())?;
+        let owner = Owner {
+            uid: Some(metadata.uid()),
+            gid: Some(metadata.gid()),
+        };
+        normalize_tree(tree.path(), Some(owner))?;
+
+        assert_eq!(fs::metadata(&dir_path)?.mode() & 0o777, 0o755);
+        assert_eq!(fs::metadata(dir_path.join("file"))?.mode() & 0o777, 0o640);
+
+        // The chunk store keeps its own copy, whoever the tree was handed to
+        assert_ne!(
+            fs::metadata(store.path().join("chunk"))?.ino(),
+            fs::metadata(dir_path.join("linked"))?.ino()
+        );
+        assert_eq!(fs::read_to_string(dir_path.join("linked"))?, "shared");
+        assert_eq!(fs::metadata(store.path().join("chunk"))?.nlink(), 1);
+
+        Ok(())
+    }
+
     #[test]
     fn test_permissions() -> Result<()> {
         let initial_tree_path = TempDir::new()?;
@@ -305,8 +985,83 @@ mod tests {
 
         let chunks = save_tree(initial_tree_path.path(), chunk_store_path.path(), hash_kind)?;
 
-        // Check that the estimated size is correct (in KB)
-        assert_eq!(estimate_tree_size(&chunks), 5);
+        // Check that the estimated size is correct
+        assert_eq!(estimate_tree_size(&chunks), 5 * 1024);
+        assert_eq!(measure_tree_size(initial_tree_path.path())?, 5 * 1024);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_import_chunks() -> Result<()> {
+        let tree = TempDir::new()?;
+        let system_store = TempDir::new()?;
+        let user_store = TempDir::new()?;
+        fs::write(tree.path().join("shared"), "shared")?;
+
+        let chunks = save_tree(tree.path(), system_store.path(), HashKind::Blake3)?;
+
+        assert_eq!(
+            import_chunks(&chunks, user_store.path(), system_store.path())?,
+            1
+        );
+        assert!(missing_chunks(&chunks, user_store.path()).is_empty());
+        assert_eq!(
+            import_chunks(&chunks, user_store.path(), system_store.path())?,
+            0
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_scan_tree() -> Result<()> {
+        let tree = TempDir::new()?;
+        let chunk_store = TempDir::new()?;
+        fs::write(tree.path().join("kept"), "kept")?;
+        fs::write(tree.path().join("changed"), "changed")?;
+        fs::write(tree.path().join("removed"), "removed")?;
+        fs::write(tree.path().join("resized"), "resized")?;
+
+        let chunks = save_tree(tree.path(), chunk_store.path(), HashKind::Blake3)?;
+
+        fs::set_permissions(
+            tree.path().join("changed"),
+            fs::Permissions::from_mode(0o700),
+        )?;
+        fs::remove_file(tree.path().join("removed"))?;
+        fs::write(tree.path().join("added"), "added")?;
+        fs::write(tree.path().join("install.meta"), "ignored")?;
+        // Far less than a kilobyte, only caught with exact sizes
+        fs::write(tree.path().join("resized"), "resized!")?;
+
+        let files = scan_tree(tree.path(), &chunks)?;
+        let discrepancies: Vec<(&Path, Option<TreeDiscrepancy>)> = files
+            .iter()
+            .map(|file| (file.path.as_path(), file.discrepancy))
+            .collect();
+
+        assert_eq!(
+            discrepancies,
+            vec![
+                (Path::new("added"), Some(TreeDiscrepancy::Unexpected)),
+                (
+                    Path::new("changed"),
+                    Some(TreeDiscrepancy::PermissionsMismatch)
+                ),
+                (Path::new("kept"), None),
+                (Path::new("removed"), Some(TreeDiscrepancy::Missing)),
+                (Path::new("resized"), Some(TreeDiscrepancy::SizeMismatch)),
+            ]
+        );
+
+        // Manifests from before exact sizes only know the size in kilobytes
+        let legacy: Chunk =
+            serde_yaml::from_str("path: resized\nhash: abc\npermissions: 420\nsize: 0\n")?;
+        assert!(!legacy.has_exact_size());
+        assert_eq!(legacy.size(), 0);
+        assert!(legacy.matches_size(8));
+        assert!(!legacy.matches_size(2048));
 
         Ok(())
     }
@@ -1,12 +1,17 @@
-use anyhow::Result;
+use anyhow::{Result, bail};
 use std::{collections::HashSet, fs, path::Path};
 
 use crate::{
     chunks::{Chunk, get_chunk_filename},
-    repo::{get_all_installed_packages, get_all_packages},
+    config::get_system_data_dir,
+    repo::{
+        RepoManifest, get_all_installed_packages, lock::lock_repo,
+        manifest_io::read_manifest_unsigned, read_manifest, revisions::get_revisions,
+        signing_request::pending_manifest,
+    },
 };
 
-/// Removes chunks that aren't actually used by any packages in the Repository
+/// Removes chunks that aren't actually used by any packages in the Repository, or kept superseded builds of them
 /// This is most useful for remote Repository administrators.
 ///
 /// # Errors
@@ -14,20 +19,43 @@ use crate::{
 /// - Filesystem errors (Permissions most likely)
 /// - Repository doesn't exist
 pub fn clean_unused(repos_path: &Path, chunk_store_path: &Path) -> Result<()> {
+    clean(chunk_store_path, &used_chunks(repos_path)?)
+}
+
+/// Every chunk used by a package in any Repository, a kept superseded build of one, or a package waiting to be signed.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions most likely)
+/// - Repository doesn't exist
+pub fn used_chunks(repos_path: &Path) -> Result<Vec<Chunk>> {
     let mut chunks: Vec<Chunk> = Vec::new();
 
     for entry in repos_path.read_dir()? {
         let repo_path = entry?.path();
-        let packages = get_all_packages(&repo_path)?;
-
-        for package in packages {
+        // Whatever the subscription, packages this machine doesn't follow may still be served from it
+        for package in read_manifest(&repo_path)?.packages {
             for chunk in package.chunks.clone() {
                 chunks.push(chunk);
             }
         }
+
+        for revision in get_revisions(&repo_path)? {
+            chunks.extend(revision.chunks);
+        }
+
+        // Not applied until signed elsewhere, by then its chunks must still be here
+        if let Some(pending) = pending_manifest(&repo_path)? {
+            chunks.extend(
+                pending
+                    .packages
+                    .into_iter()
+                    .flat_map(|package| package.chunks),
+            );
+        }
     }
 
-    clean(chunk_store_path, &chunks)
+    Ok(chunks)
 }
 
 /// Removes chunks that are actually used by any packages in the Repository, but aren't installed
@@ -76,6 +104,140 @@ fn clean(chunk_store_path: &Path, allowed_chunks: &[Chunk]) -> Result<()> {
     Ok(())
 }
 
+/// What collecting a Repository's own chunk directory removed, or would remove
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RepoGcReport {
+    /// Chunks still used by the Repository
+    pub kept: usize,
+    /// Filenames of the chunks nothing in the Repository uses
+    pub removed: Vec<String>,
+    /// Bytes taken by the removed chunks
+    pub freed: u64,
+}
+
+/// Removes chunks from a Repository's own chunk directory, eg: the `chunks/` it is served from,
+/// that neither its packages, its kept superseded builds nor a manifest waiting to be signed use.
+///
+/// Only that Repository's manifest counts, including packages its subscription or channel hides,
+/// so `chunks_path` must not be shared with the chunk store, the system chunk store other stores
+/// read from, or another Repository. Files not named like chunks are left alone.
+/// The Repository stays locked throughout, so chunks published meanwhile are never removed.
+///
+/// # Errors
+///
+/// - `chunks_path` is a chunk store, or another Repository's chunk directory
+/// - Invalid manifest signature
+/// - Filesystem errors (Permissions)
+pub fn gc_repo_chunks(
+    repos_path: &Path,
+    repo_path: &Path,
+    chunks_path: &Path,
+    chunk_store_path: &Path,
+    dry_run: bool,
+) -> Result<RepoGcReport> {
+    let target = chunks_path.canonicalize()?;
+    let stores = [
+        chunk_store_path.to_path_buf(),
+        get_system_data_dir().join("chunks"),
+    ];
+    if stores
+        .iter()
+        .any(|store| store.canonicalize().is_ok_and(|store| store == target))
+    {
+        bail!(
+            "{} is a chunk store, which every Repository uses. Use `flint clean` instead.",
+            chunks_path.display()
+        )
+    }
+    for entry in repos_path.read_dir()? {
+        let other_path = entry?.path();
+        if other_path != repo_path
+            && other_path
+                .join("chunks")
+                .canonicalize()
+                .is_ok_and(|other| other == target)
+        {
+            bail!(
+                "{} is shared with the Repository at {}.",
+                chunks_path.display(),
+                other_path.display()
+            )
+        }
+    }
+
+    let _lock = lock_repo(repo_path)?;
+    // Verified first, the unsigned read only skips the subscription
+    read_manifest(repo_path)?;
+    let mut used: HashSet<String> = HashSet::new();
+    for package in read_manifest_unsigned(repo_path)?.packages {
+        used.extend(package.chunks.iter().map(Chunk::filename));
+    }
+    for revision in get_revisions(repo_path)? {
+        used.extend(revision.chunks.iter().map(Chunk::filename));
+    }
+    for package in pending_manifest(repo_path)?
+        .into_iter()
+        .flat_map(|pending| pending.packages)
+    {
+        used.extend(package.chunks.iter().map(Chunk::filename));
+    }
+
+    let mut report = RepoGcReport::default();
+    for entry in fs::read_dir(chunks_path)? {
+        let entry = entry?;
+        let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+            continue;
+        };
+        // Chunk names are a hex hash followed by the permissions, anything else isn't ours
+        if name.is_empty() || !name.chars().all(|char| char.is_ascii_hexdigit()) {
+            continue;
+        }
+
+        if used.contains(&name) {
+            report.kept += 1;
+            continue;
+        }
+
+        report.freed += entry.metadata()?.len();
+        if !dry_run {
+            fs::remove_file(entry.path())?;
+        }
+        report.removed.push(name);
+    }
+    report.removed.sort();
+
+    Ok(report)
+}
+
+/// Records exact sizes for chunks from manifests written before they existed, using the chunk store.
+/// Chunks not in the chunk store keep their rounded size.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+///
+/// # Returns
+///
+/// The number of chunks migrated
+pub fn migrate_chunk_sizes(manifest: &mut RepoManifest, chunk_store_path: &Path) -> Result<usize> {
+    let mut migrated = 0;
+
+    for package in &mut manifest.packages {
+        for chunk in &mut package.chunks {
+            if chunk.has_exact_size() {
+                continue;
+            }
+
+            if let Ok(metadata) = fs::metadata(chunk_store_path.join(chunk.filename())) {
+                chunk.set_exact_size(metadata.len());
+                migrated += 1;
+            }
+        }
+    }
+
+    Ok(migrated)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -94,12 +256,14 @@ mod tests {
                 hash: "hash1".to_string(),
                 permissions: 0o644,
                 size: 1,
+                bytes: None,
             },
             Chunk {
                 path: std::path::PathBuf::from("file2"),
                 hash: "hash2".to_string(),
                 permissions: 0o644,
                 size: 1,
+                bytes: None,
             },
         ];
 
@@ -122,4 +286,109 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_gc_repo_chunks() -> Result<()> {
+        use crate::{
+            chunks::{HashKind, save_tree},
+            repo::{PackageManifest, create_repo, insert_package},
+        };
+
+        let repos = TempDir::new()?;
+        let repo_path = &repos.path().join("main");
+        let chunks_path = &repo_path.join("chunks");
+        let chunk_store = TempDir::new()?;
+        create_repo(repo_path, Some(repo_path))?;
+
+        let tree = TempDir::new()?;
+        fs::write(tree.path().join("hello"), "hello")?;
+        let package = PackageManifest {
+            id: "hello".into(),
+            chunks: save_tree(tree.path(), chunks_path, HashKind::Blake3)?,
+            ..Default::default()
+        };
+        insert_package(&package, repo_path, Some(repo_path))?;
+
+        let stale = get_chunk_filename("abc123", 0o644);
+        fs::write(chunks_path.join(&stale), "stale")?;
+        fs::write(chunks_path.join("README"), "not a chunk")?;
+
+        let report = gc_repo_chunks(
+            repos.path(),
+            repo_path,
+            chunks_path,
+            chunk_store.path(),
+            true,
+        )?;
+        assert_eq!(report.kept, 1);
+        assert_eq!(report.removed, vec![stale.clone()]);
+        assert_eq!(report.freed, 5);
+        assert!(chunks_path.join(&stale).exists());
+
+        gc_repo_chunks(
+            repos.path(),
+            repo_path,
+            chunks_path,
+            chunk_store.path(),
+            false,
+        )?;
+        assert!(!chunks_path.join(&stale).exists());
+        assert!(chunks_path.join(package.chunks[0].filename()).exists());
+        assert!(chunks_path.join("README").exists());
+
+        // Never the store every Repository shares, or another Repository's chunks
+        assert!(gc_repo_chunks(repos.path(), repo_path, chunks_path, chunks_path, true).is_err());
+        let other_path = &repos.path().join("other");
+        create_repo(other_path, Some(other_path))?;
+        std::os::unix::fs::symlink(chunks_path, other_path.join("chunks"))?;
+        assert!(
+            gc_repo_chunks(
+                repos.path(),
+                repo_path,
+                chunks_path,
+                chunk_store.path(),
+                true
+            )
+            .is_err()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_migrate_chunk_sizes() -> Result<()> {
+        let repo = TempDir::new()?;
+        let chunk_store = TempDir::new()?;
+        crate::repo::create_repo(repo.path(), Some(repo.path()))?;
+
+        let mut manifest = crate::repo::read_manifest(repo.path())?;
+        manifest.packages.push(serde_yaml::from_str(
+            "id: legacy
+metadata: {}
+aliases: []
+commands: []
+build_hash: ''
+chunks:
+- { path: stored, hash: hash1, permissions: 420, size: 0 }
+- { path: missing, hash: hash2, permissions: 420, size: 2 }
+",
+        )?);
+        fs::write(
+            chunk_store.path().join(get_chunk_filename("hash1", 420)),
+            "data1",
+        )?;
+
+        assert_eq!(migrate_chunk_sizes(&mut manifest, chunk_store.path())?, 1);
+
+        let chunks = &manifest.packages[0].chunks;
+        assert!(chunks[0].has_exact_size());
+        assert_eq!(chunks[0].size(), 5);
+        assert!(!chunks[1].has_exact_size());
+        assert_eq!(chunks[1].size(), 2048);
+
+        // Nothing left to migrate
+        assert_eq!(migrate_chunk_sizes(&mut manifest, chunk_store.path())?, 0);
+
+        Ok(())
+    }
 }
@@ -0,0 +1,488 @@
+use anyhow::{Result, bail};
+use std::{
+    collections::HashMap,
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    },
+    thread,
+};
+
+use crate::{
+    chunks::{Chunk, HashKind, compress::decompress_chunk, get_chunk_filename, hash, scan_tree},
+    repo::{image::find_image, installed::get_installed, read_manifest},
+    utils::{format_size, platform::same_file},
+};
+
+/// The outcome of verifying a Repository's chunks
+#[derive(serde::Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// Number of chunks that matched their hash
+    pub verified: usize,
+    /// Total size of the verified chunks in bytes
+    pub verified_bytes: u64,
+    /// Hashes of chunks not in the chunk store
+    pub missing: Vec<String>,
+    /// Hashes of chunks whose contents did not match, these have been removed from the chunk store
+    pub corrupt: Vec<String>,
+    /// Stopped at the first failure, so not every chunk was checked
+    pub stopped_early: bool,
+}
+
+impl VerifyReport {
+    #[must_use]
+    pub const fn failed(&self) -> usize {
+        self.missing.len() + self.corrupt.len()
+    }
+}
+
+/// Progress of a running verification, passed to the progress callback after every chunk
+#[derive(Debug, Clone, Copy)]
+pub struct VerifyProgress {
+    pub done: usize,
+    pub total: usize,
+    /// Bytes read so far
+    pub bytes: u64,
+}
+
+/// Verifies every chunk used by a Repository, hashing them across all available cores.
+/// Corrupt chunks are removed from the chunk store, so they can be downloaded again.
+///
+/// With `fail_fast`, stops at the first missing or corrupt chunk.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+/// - Invalid manifests
+pub fn verify_chunks(
+    repo_path: &Path,
+    chunk_store_path: &Path,
+    fail_fast: bool,
+    progress: &(dyn Fn(VerifyProgress) + Sync),
+) -> Result<VerifyReport> {
+    let repo_manifest = read_manifest(repo_path)?;
+    let hash_kind = repo_manifest.hash_kind;
+
+    let all_chunks: HashMap<_, _> = repo_manifest
+        .packages
+        .into_iter()
+        .flat_map(|package| package.chunks)
+        .map(|chunk| ((chunk.hash().to_string(), chunk.permissions()), hash_kind))
+        .collect();
+
+    verify_chunk_set(all_chunks, chunk_store_path, fail_fast, progress)
+}
+
+/// Verifies chunks by their hash and permissions, each hashed with the kind paired with it, like `verify_chunks`.
+/// Every entry is checked, so `all_chunks` should have no duplicates, eg: a map.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+pub fn verify_chunk_set(
+    all_chunks: impl IntoIterator<Item = ((String, u32), HashKind)>,
+    chunk_store_path: &Path,
+    fail_fast: bool,
+    progress: &(dyn Fn(VerifyProgress) + Sync),
+) -> Result<VerifyReport> {
+    let mut report = VerifyReport::default();
+    let mut present = Vec::new();
+
+    // Checking existence is cheap, so missing chunks never wait on hashing
+    for ((expected_hash, permissions), hash_kind) in all_chunks {
+        if chunk_store_path
+            .join(get_chunk_filename(&expected_hash, permissions))
+            .exists()
+        {
+            present.push((expected_hash, permissions, hash_kind));
+        } else {
+            report.missing.push(expected_hash);
+        }
+    }
+
+    if fail_fast && !report.missing.is_empty() {
+        report.stopped_early = true;
+        return Ok(report);
+    }
+
+    let total = present.len();
+    let next = AtomicUsize::new(0);
+    let done = AtomicUsize::new(0);
+    let bytes = AtomicU64::new(0);
+    let stop = AtomicBool::new(false);
+    let corrupt = Mutex::new(Vec::new());
+
+    let workers = thread::available_parallelism().map_or(1, usize::from);
+
+    thread::scope(|scope| -> Result<()> {
+        let handles: Vec<_> = (0..workers.min(total))
+            .map(|_| {
+                scope.spawn(|| -> Result<()> {
+                    while !stop.load(Ordering::Relaxed) {
+                        let Some((expected_hash, permissions, hash_kind)) =
+                            present.get(next.fetch_add(1, Ordering::Relaxed))
+                        else {
+                            break;
+                        };
+
+                        let chunk_path =
+                            chunk_store_path.join(get_chunk_filename(expected_hash, *permissions));
+                        let stored = fs::read(&chunk_path)?;
+                        // The actual contents are hashed, a chunk that doesn't decompress is corrupt too
+                        let contents = decompress_chunk(&stored, u64::MAX).ok();
+                        let len = contents
+                            .as_ref()
+                            .map_or(0, |contents| contents.len() as u64);
+
+                        if !contents.is_some_and(|contents| {
+                            hash::hash(*hash_kind, &contents) == *expected_hash
+                        }) {
+                            fs::remove_file(&chunk_path)?;
+                            corrupt
+                                .lock()
+                                .map_err(|_| anyhow::anyhow!("Verification thread panicked"))?
+                                .push(expected_hash.clone());
+
+                            if fail_fast {
+                                stop.store(true, Ordering::Relaxed);
+                            }
+                        }
+
+                        progress(VerifyProgress {
+                            done: done.fetch_add(1, Ordering::Relaxed) + 1,
+                            total,
+                            bytes: bytes.fetch_add(len, Ordering::Relaxed) + len,
+                        });
+                    }
+
+                    Ok(())
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle
+                .join()
+                .map_err(|_| anyhow::anyhow!("Verification thread panicked"))??;
+        }
+
+        Ok(())
+    })?;
+
+    report.corrupt = corrupt
+        .into_inner()
+        .map_err(|_| anyhow::anyhow!("Verification thread panicked"))?;
+    report.verified = done.into_inner() - report.corrupt.len();
+    report.verified_bytes = bytes.into_inner();
+    report.stopped_early = stop.into_inner() && report.verified + report.corrupt.len() < total;
+
+    report.missing.sort();
+    report.corrupt.sort();
+
+    Ok(report)
+}
+
+/// Verify all chunks in a repository, printing a summary.
+///
+/// # Errors
+///
+/// - Filesystem errors
+/// - Invalid manifests
+/// - Any chunk is missing or corrupt
+pub fn verify_all_chunks(repo_path: &Path, chunk_store_path: &Path) -> Result<()> {
+    let report = verify_chunks(repo_path, chunk_store_path, false, &|_| {})?;
+    print_verify_report(&report);
+
+    if report.failed() > 0 {
+        bail!("Some chunks failed verification");
+    }
+
+    Ok(())
+}
+
+/// Prints each failed chunk, and a summary line
+pub fn print_verify_report(report: &VerifyReport) {
+    for missing in &report.missing {
+        eprintln!("Missing chunk: {missing}");
+    }
+    for corrupt in &report.corrupt {
+        eprintln!("Hash mismatch for chunk: {corrupt}");
+    }
+
+    println!(
+        "Verified {} chunks ({}), {} failed{}",
+        report.verified,
+        format_size(report.verified_bytes),
+        report.failed(),
+        if report.stopped_early {
+            ", stopped at the first failure"
+        } else {
+            ""
+        }
+    );
+}
+
+/// The outcome of scrubbing an installed tree against the chunk store
+#[derive(serde::Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScrubReport {
+    /// Files still hard linked to their chunk, so not read at all
+    pub linked: usize,
+    /// Files that are not hard linked, but still match their chunk's hash
+    pub hashed: usize,
+    /// Files that no longer match their chunk
+    pub modified: Vec<PathBuf>,
+    /// Files missing from the tree
+    pub missing: Vec<PathBuf>,
+}
+
+impl ScrubReport {
+    #[must_use]
+    pub const fn failed(&self) -> usize {
+        self.modified.len() + self.missing.len()
+    }
+}
+
+/// Checks an installed tree against its chunks, much cheaper than hashing every file.
+///
+/// Files that are still hard links to their chunk are trusted without being read,
+/// only other files (eg: copied across filesystems, or replaced) are hashed.
+/// A chunk modified in place is not caught here, that is what [`verify_chunks`] is for.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+pub fn scrub_tree(
+    tree_path: &Path,
+    chunks: &[Chunk],
+    chunk_store_path: &Path,
+    hash_kind: HashKind,
+) -> Result<ScrubReport> {
+    let mut report = ScrubReport::default();
+
+    for chunk in chunks {
+        let file_path = tree_path.join(chunk.path());
+
+        let metadata = match fs::metadata(&file_path) {
+            Ok(metadata) => metadata,
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                report.missing.push(chunk.path().to_path_buf());
+                continue;
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        if let Ok(chunk_metadata) = fs::metadata(chunk_store_path.join(chunk.filename()))
+            && same_file(&metadata, &chunk_metadata)
+        {
+            report.linked += 1;
+            continue;
+        }
+
+        if chunk.matches_size(metadata.len())
+            && hash::hash(hash_kind, &fs::read(&file_path)?) == chunk.hash()
+        {
+            report.hashed += 1;
+        } else {
+            report.modified.push(chunk.path().to_path_buf());
+        }
+    }
+
+    Ok(report)
+}
+
+/// Scrubs every installed package of a Repository with [`scrub_tree`].
+/// Packages linked to a working directory with `flint dev link` are skipped.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+/// - Invalid manifests
+pub fn scrub_installed(
+    repo_path: &Path,
+    chunk_store_path: &Path,
+) -> Result<Vec<(String, ScrubReport)>> {
+    let hash_kind = read_manifest(repo_path)?.hash_kind;
+    let mut reports = Vec::new();
+
+    for install_meta in get_installed(repo_path)? {
+        if install_meta.dev_install {
+            continue;
+        }
+
+        let tree_path = repo_path.join("installed").join(&install_meta.package.id);
+
+        // Images are checked by their own filesystem when mounted
+        if find_image(&tree_path).is_some() {
+            continue;
+        }
+        let report = scrub_tree(
+            &tree_path,
+            &install_meta.installed_chunks(),
+            chunk_store_path,
+            hash_kind,
+        )?;
+
+        reports.push((install_meta.package.id, report));
+    }
+
+    Ok(reports)
+}
+
+/// Checks that a tree holds exactly the files of `chunks`, hashing every one of them.
+///
+/// Unlike [`scrub_tree`], files linked to the chunk store are hashed too, and files
+/// that are not chunks are refused, so nothing about the tree is taken on trust.
+///
+/// # Errors
+///
+/// - A file is missing, unexpected, has other permissions, or doesn't match its hash
+/// - Filesystem errors (Permissions)
+pub fn verify_tree(tree_path: &Path, chunks: &[Chunk], hash_kind: HashKind) -> Result<()> {
+    if let Some(file) = scan_tree(tree_path, chunks)?
+        .into_iter()
+        .find(|file| file.discrepancy.is_some())
+    {
+        bail!(
+            "{} does not match its manifest: {}",
+            file.path.display(),
+            file.discrepancy
+                .map(|discrepancy| discrepancy.to_string())
+                .unwrap_or_default()
+        )
+    }
+
+    for chunk in chunks {
+        if hash::hash(hash_kind, &fs::read(tree_path.join(chunk.path()))?) != chunk.hash() {
+            bail!("{} does not match its hash.", chunk.path().display())
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chunks::{load_tree, save_tree},
+        repo::{PackageManifest, create_repo, insert_package},
+    };
+    use temp_dir::TempDir;
+
+    #[test]
+    fn test_verify_chunks() -> Result<()> {
+        let repo = TempDir::new()?;
+        let chunk_store = TempDir::new()?;
+        let tree = TempDir::new()?;
+        create_repo(repo.path(), Some(repo.path()))?;
+
+        for name in ["a", "b", "c", "d"] {
+            fs::write(tree.path().join(name), name)?;
+        }
+        let chunks = save_tree(
+            tree.path(),
+            chunk_store.path(),
+            read_manifest(repo.path())?.hash_kind,
+        )?;
+        let package = PackageManifest {
+            id: "letters".into(),
+            chunks: chunks.clone(),
+            ..Default::default()
+        };
+        insert_package(&package, repo.path(), Some(repo.path()))?;
+
+        let report = verify_chunks(repo.path(), chunk_store.path(), false, &|_| {})?;
+        assert_eq!(report.verified, 4);
+        assert_eq!(report.verified_bytes, 4);
+        assert_eq!(report.failed(), 0);
+
+        let missing = &chunks[0];
+        let corrupt = &chunks[1];
+        fs::remove_file(chunk_store.path().join(missing.filename()))?;
+        // Replaced rather than written to, it is hard linked to the tree
+        fs::remove_file(chunk_store.path().join(corrupt.filename()))?;
+        fs::write(chunk_store.path().join(corrupt.filename()), "corrupt")?;
+
+        let calls = AtomicUsize::new(0);
+        let report = verify_chunks(repo.path(), chunk_store.path(), false, &|_| {
+            calls.fetch_add(1, Ordering::Relaxed);
+        })?;
+        assert_eq!(calls.into_inner(), 3);
+        assert_eq!(report.verified, 2);
+        assert_eq!(report.missing, vec![missing.hash().to_string()]);
+        assert_eq!(report.corrupt, vec![corrupt.hash().to_string()]);
+        assert!(!report.stopped_early);
+        // Corrupt chunks are removed, to be downloaded again
+        assert!(!chunk_store.path().join(corrupt.filename()).exists());
+
+        let report = verify_chunks(repo.path(), chunk_store.path(), true, &|_| {})?;
+        assert!(report.stopped_early);
+        assert_eq!(report.verified, 0);
+
+        assert!(verify_all_chunks(repo.path(), chunk_store.path()).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_scrub_tree() -> Result<()> {
+        let tree = TempDir::new()?;
+        let chunk_store = TempDir::new()?;
+        let installed = TempDir::new()?;
+        let hash_kind = HashKind::Blake3;
+
+        for name in ["linked", "copied", "modified", "missing"] {
+            fs::write(tree.path().join(name), name)?;
+        }
+        let chunks = save_tree(tree.path(), chunk_store.path(), hash_kind)?;
+        load_tree(installed.path(), chunk_store.path(), &chunks)?;
+
+        // Replaced with copies, so no longer hard links
+        for name in ["copied", "modified"] {
+            let path = installed.path().join(name);
+            let contents = fs::read(&path)?;
+            fs::remove_file(&path)?;
+            fs::write(&path, contents)?;
+        }
+        fs::write(installed.path().join("modified"), "changed")?;
+        fs::remove_file(installed.path().join("missing"))?;
+
+        let report = scrub_tree(installed.path(), &chunks, chunk_store.path(), hash_kind)?;
+        assert_eq!(report.linked, 1);
+        assert_eq!(report.hashed, 1);
+        assert_eq!(report.modified, vec![PathBuf::from("modified")]);
+        assert_eq!(report.missing, vec![PathBuf::from("missing")]);
+
+        Ok(())
+    }
+    #[test]
+    fn test_verify_tree() -> Result<()> {
+        let tree = TempDir::new()?;
+        let chunk_store = TempDir::new()?;
+        let installed = TempDir::new()?;
+        let hash_kind = HashKind::Blake3;
+
+        fs::write(tree.path().join("hello"), "hello")?;
+        let chunks = save_tree(tree.path(), chunk_store.path(), hash_kind)?;
+        load_tree(installed.path(), chunk_store.path(), &chunks)?;
+        verify_tree(installed.path(), &chunks, hash_kind)?;
+
+        // Not in the manifest
+        fs::write(installed.path().join("plugin.so"), "")?;
+        assert!(verify_tree(installed.path(), &chunks, hash_kind).is_err());
+        fs::remove_file(installed.path().join("plugin.so"))?;
+
+        // Same size and permissions, other contents
+        let path = installed.path().join("hello");
+        let permissions = fs::metadata(&path)?.permissions();
+        fs::remove_file(&path)?;
+        fs::write(&path, "jello")?;
+        fs::set_permissions(&path, permissions)?;
+        assert!(verify_tree(installed.path(), &chunks, hash_kind).is_err());
+
+        Ok(())
+    }
+}
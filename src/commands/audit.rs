@@ -0,0 +1,48 @@
+use anyhow::{Result, bail};
+use comfy_table::Table;
+use std::path::Path;
+
+use flintpkg::repo::advisories::audit;
+
+pub fn audit_cmd(base_path: &Path) -> Result<()> {
+    let findings = audit(base_path)?;
+
+    if findings.is_empty() {
+        println!("No installed package has a known vulnerability.");
+        return Ok(());
+    }
+
+    let mut table = Table::new();
+
+    table.set_header(vec![
+        "Repository",
+        "ID",
+        "Version",
+        "Advisory",
+        "Severity",
+        "Fixed In",
+        "Summary",
+    ]);
+
+    for finding in &findings {
+        let advisory = &finding.advisory;
+
+        table.add_row(vec![
+            finding.repo.clone(),
+            finding.package.id.clone(),
+            finding.package.metadata.version.clone().unwrap_or_default(),
+            advisory.id.clone(),
+            advisory.severity.to_string(),
+            advisory.fixed_in.clone().unwrap_or_default(),
+            advisory.summary.clone().unwrap_or_default(),
+        ]);
+    }
+
+    println!("{table}");
+
+    // So scripts and CI can act on it
+    bail!(
+        "{} vulnerable installs, run `flint update` to apply available fixes.",
+        findings.len()
+    )
+}
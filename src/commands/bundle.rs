@@ -1,8 +1,17 @@
-use anyhow::Result;
+use anyhow::{Result, bail};
 use std::{fs, path::Path};
 
-use crate::BundleCommands;
-use flintpkg::{build::bundle::build_bundle, utils::resolve_repo};
+use crate::{BundleCommands, log::verified_bundle};
+use flintpkg::{
+    build::bundle::build_bundle,
+    bundle::{
+        BundleMeta,
+        verify::{signature_path, verify_bundle, write_checksums},
+    },
+    crypto::key::{get_private_key, serialize_verifying_key},
+    repo::read_manifest,
+    utils::resolve_repo,
+};
 
 pub fn bundle_commands(base_path: &Path, command: BundleCommands) -> Result<()> {
     match command {
@@ -11,11 +20,61 @@ pub fn bundle_commands(base_path: &Path, command: BundleCommands) -> Result<()>
             repo_name,
             bundle_path,
             header_path,
+            portable,
+            checksum,
+            sign,
         } => {
-            let bundle = build_bundle(&header_path, &resolve_repo(base_path, &repo_name)?)?;
-            fs::write(bundle_path, bundle)?;
+            let repo_path = &resolve_repo(base_path, &repo_name)?;
+
+            if sign {
+                let our_public_key =
+                    serialize_verifying_key(get_private_key(None)?.verifying_key())?;
+                if !read_manifest(repo_path)?
+                    .trusted_keys()
+                    .contains(&our_public_key.as_str())
+                {
+                    bail!("You do not have the signing key of this Repository.");
+                }
+            }
+
+            let bundle = build_bundle(&header_path, repo_path, &BundleMeta { portable })?;
+            fs::write(&bundle_path, &bundle)?;
+
+            if checksum || sign {
+                write_checksums(&bundle_path, &bundle, sign, None)?;
+            }
+        }
+        BundleCommands::Verify {
+            bundle_path,
+            public_key,
+        } => {
+            // Only an explicit key requires a signature
+            let public_keys = if let Some(public_key) = public_key {
+                vec![public_key]
+            } else if signature_path(&bundle_path).exists() {
+                local_public_keys(base_path)?
+            } else {
+                Vec::new()
+            };
+
+            verified_bundle(&bundle_path, verify_bundle(&bundle_path, &public_keys)?);
         }
     }
 
     Ok(())
 }
+
+/// Public keys of every local Repository
+fn local_public_keys(base_path: &Path) -> Result<Vec<String>> {
+    let mut public_keys = Vec::new();
+
+    if base_path.exists() {
+        for repo_entry in fs::read_dir(base_path)? {
+            if let Ok(repo) = read_manifest(&repo_entry?.path()) {
+                public_keys.extend(repo.trusted_keys().into_iter().map(str::to_string));
+            }
+        }
+    }
+
+    Ok(public_keys)
+}
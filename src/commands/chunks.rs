@@ -0,0 +1,95 @@
+use anyhow::{Result, bail};
+use std::{path::Path, time::Instant};
+
+use crate::{ChunksCommands, log::verify_progress};
+use flintpkg::chunks::fsck::{FsckReport, fsck_chunks, rederive_chunks};
+
+pub async fn chunks_commands(
+    base_path: &Path,
+    chunk_store_path: &Path,
+    command: ChunksCommands,
+) -> Result<()> {
+    match command {
+        ChunksCommands::Fsck { repair, json } => {
+            fsck_cmd(base_path, chunk_store_path, repair, json).await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg_attr(not(feature = "network"), allow(clippy::unused_async))]
+async fn fsck_cmd(
+    base_path: &Path,
+    chunk_store_path: &Path,
+    repair: bool,
+    json: bool,
+) -> Result<()> {
+    let started = Instant::now();
+
+    let mut report = fsck_chunks(base_path, chunk_store_path, &|progress| {
+        if !json
+            && (progress.done % (progress.total / 100).max(1) == 0
+                || progress.done == progress.total)
+        {
+            verify_progress(progress, started.elapsed());
+        }
+    })?;
+
+    if repair {
+        rederive_chunks(base_path, chunk_store_path, &mut report)?;
+
+        #[cfg(feature = "network")]
+        if !report.unrepaired().is_empty() {
+            if let Err(err) = flintpkg::config::require_network() {
+                eprintln!("Not downloading chunks again: {err}");
+            } else {
+                flintpkg::chunks::fsck::redownload_chunks(
+                    base_path,
+                    chunk_store_path,
+                    &mut report,
+                    &crate::progress::download_progress,
+                )
+                .await?;
+            }
+        }
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_fsck_report(&report);
+    }
+
+    if !report.unrepaired().is_empty() {
+        if repair {
+            bail!("Some chunks could not be repaired");
+        }
+        bail!("Some chunks are corrupt or missing, use --repair to put them back");
+    }
+
+    Ok(())
+}
+
+fn print_fsck_report(report: &FsckReport) {
+    for corrupt in &report.corrupt {
+        eprintln!("Hash mismatch for chunk, removed: {corrupt}");
+    }
+    for missing in &report.missing {
+        eprintln!("Missing chunk of an installed package: {missing}");
+    }
+    for warning in &report.warnings {
+        eprintln!("{warning}");
+    }
+    for repaired in &report.repaired {
+        println!("Repaired chunk: {repaired}");
+    }
+
+    println!(
+        "Verified {} chunks, {} corrupt, {} missing, {} repaired",
+        report.verified,
+        report.corrupt.len(),
+        report.missing.len(),
+        report.repaired.len(),
+    );
+}
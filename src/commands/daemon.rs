@@ -0,0 +1,174 @@
+use anyhow::Result;
+use serde::Deserialize;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use tokio::sync::broadcast;
+
+use crate::{
+    commands::main::{install_cmd, remove_cmd},
+    log::daemon_connection_failed,
+};
+use flintpkg::{
+    daemon::{Event, Handler, METHOD_NOT_FOUND, RpcError, list_packages, package_info, run_daemon},
+    repo::installed::is_installed,
+    utils::{choose_package, prompt::NonInteractive},
+};
+
+/// Params of `info`, `install` and `remove`
+#[derive(Deserialize)]
+struct PackageParams {
+    package: String,
+    /// Defaults to whichever Repository has the package
+    #[serde(default)]
+    repo: Option<String>,
+}
+
+/// Runs daemon methods like the matching CLI commands
+struct FlintHandler {
+    base: PathBuf,
+    quicklaunch: PathBuf,
+    chunk_store: PathBuf,
+}
+
+impl Handler for FlintHandler {
+    async fn call(
+        &self,
+        method: &str,
+        params: Value,
+        events: &broadcast::Sender<Event>,
+    ) -> Result<Value, RpcError> {
+        let base_path = &self.base;
+
+        match method {
+            "list" => Ok(serde_json::to_value(list_packages(base_path)?)?),
+
+            "info" => {
+                let params: PackageParams = serde_json::from_value(params)?;
+
+                Ok(serde_json::to_value(package_info(
+                    base_path,
+                    params.repo.as_deref(),
+                    &params.package,
+                )?)?)
+            }
+
+            "install" => {
+                let params: PackageParams = serde_json::from_value(params)?;
+                // Resolved here, as the CLI would prompt when several Repositories have it
+                let repo = package_info(base_path, params.repo.as_deref(), &params.package)?.repo;
+
+                let stats = install_cmd(
+                    base_path,
+                    Some(repo.clone()),
+                    &self.chunk_store,
+                    &params.package,
+                    None,
+                    false,
+                )
+                .await?;
+
+                let _ = events.send(Event::Installed {
+                    repo,
+                    package: params.package,
+                    stats: stats.unwrap_or_default(),
+                });
+                Ok(Value::Null)
+            }
+
+            "remove" => {
+                let params: PackageParams = serde_json::from_value(params)?;
+                let repo = if let Some(repo) = params.repo {
+                    repo
+                } else {
+                    installed_repo(base_path, &params.package)?
+                };
+
+                remove_cmd(base_path, Some(repo.clone()), &params.package)?;
+
+                let _ = events.send(Event::Removed {
+                    repo,
+                    package: params.package,
+                });
+                Ok(Value::Null)
+            }
+
+            "update" => {
+                self.update().await?;
+
+                let _ = events.send(Event::Updated);
+                Ok(Value::Null)
+            }
+
+            _ => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("Unknown method {method}"),
+            )),
+        }
+    }
+
+    fn connection_failed(&self, err: &anyhow::Error) {
+        daemon_connection_failed(err);
+    }
+}
+
+impl FlintHandler {
+    #[cfg(feature = "network")]
+    async fn update(&self) -> Result<()> {
+        use crate::{UpdateArgs, commands::main::update_cmd};
+        use flintpkg::config::require_network;
+
+        require_network()?;
+
+        // Never removes Repositories that are no longer declared, there is nobody to ask
+        update_cmd(
+            &self.base,
+            &self.quicklaunch,
+            &self.chunk_store,
+            UpdateArgs::default(),
+        )
+        .await
+    }
+
+    #[cfg(not(feature = "network"))]
+    #[allow(clippy::unused_async)]
+    async fn update(&self) -> Result<()> {
+        let _ = &self.quicklaunch;
+
+        flintpkg::config::require_network()
+    }
+}
+
+/// The name of the Repository `package_id` is installed from
+fn installed_repo(base_path: &Path, package_id: &str) -> Result<String> {
+    let (repo_path, _) = choose_package(
+        base_path,
+        package_id,
+        |repo_path| is_installed(repo_path, package_id),
+        &NonInteractive,
+    )?;
+
+    Ok(repo_path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string())
+}
+
+pub async fn daemon_cmd(
+    base_path: &Path,
+    quicklaunch_path: &Path,
+    chunk_store_path: &Path,
+    socket_path: &Path,
+) -> Result<()> {
+    println!("Listening on {}", socket_path.display());
+
+    run_daemon(
+        socket_path,
+        FlintHandler {
+            base: base_path.to_path_buf(),
+            quicklaunch: quicklaunch_path.to_path_buf(),
+            chunk_store: chunk_store_path.to_path_buf(),
+        },
+    )
+    .await
+}
@@ -0,0 +1,45 @@
+use anyhow::Result;
+use std::path::Path;
+
+use crate::{DevCommands, commands::main::resolve_repo_and_package, progress::download_progress};
+use flintpkg::{
+    repo::versions::{link_dev, unlink_dev},
+    run::install_package,
+};
+
+pub async fn dev_commands(
+    base_path: &Path,
+    chunk_store_path: &Path,
+    command: DevCommands,
+) -> Result<()> {
+    match command {
+        DevCommands::Link {
+            dir,
+            package,
+            repo_name,
+        } => {
+            let (repo_path, package) = resolve_repo_and_package(base_path, repo_name, &package)?;
+
+            link_dev(&repo_path, &package.id, &dir)?;
+
+            println!("Linked {} to {}", package.id, dir.display());
+        }
+
+        DevCommands::Unlink { package, repo_name } => {
+            let (repo_path, package) = resolve_repo_and_package(base_path, repo_name, &package)?;
+
+            unlink_dev(&repo_path, &package.id)?;
+            install_package(
+                &repo_path,
+                &package.id,
+                chunk_store_path,
+                &download_progress,
+            )
+            .await?;
+
+            println!("Restored {}", package.id);
+        }
+    }
+
+    Ok(())
+}
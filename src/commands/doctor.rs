@@ -0,0 +1,87 @@
+use anyhow::Result;
+use comfy_table::Table;
+use std::path::Path;
+
+use crate::log::describe_operation;
+use flintpkg::{
+    journal::list_journals,
+    repo::installed::{check_install_meta, get_installed},
+};
+
+pub fn doctor_cmd(base_path: &Path) -> Result<()> {
+    list_interrupted(base_path)?;
+    list_modified_install_meta(base_path)
+}
+
+fn list_interrupted(base_path: &Path) -> Result<()> {
+    let journals = list_journals(base_path)?;
+
+    if journals.is_empty() {
+        println!("No interrupted operations.");
+        return Ok(());
+    }
+
+    let mut table = Table::new();
+
+    table.set_header(vec!["Operation", "Started At", "Steps Done", "Status"]);
+
+    for entry in journals {
+        let status = if entry.is_running() {
+            format!("Running (pid {})", entry.pid)
+        } else if let Some(err) = &entry.recovery_error {
+            format!("Recovery failed: {err}")
+        } else {
+            "Interrupted".to_string()
+        };
+
+        table.add_row(vec![
+            describe_operation(&entry),
+            entry.started_at.to_string(),
+            entry.steps.join(", "),
+            status,
+        ]);
+    }
+
+    println!("{table}");
+
+    Ok(())
+}
+
+/// Packages whose `install.meta` was edited since it was installed, which `run` refuses to start
+fn list_modified_install_meta(base_path: &Path) -> Result<()> {
+    let mut table = Table::new();
+
+    table.set_header(vec!["Repository", "Package", "Problem"]);
+
+    if base_path.exists() {
+        for entry in base_path.read_dir()? {
+            let repo_path = entry?.path();
+            if !repo_path.is_dir() {
+                continue;
+            }
+
+            for install_meta in get_installed(&repo_path)? {
+                let package_id = install_meta.package.id;
+                if let Err(err) = check_install_meta(&repo_path, &package_id) {
+                    table.add_row(vec![
+                        repo_path
+                            .file_name()
+                            .unwrap_or_default()
+                            .to_string_lossy()
+                            .to_string(),
+                        package_id,
+                        err.to_string(),
+                    ]);
+                }
+            }
+        }
+    }
+
+    if table.is_empty() {
+        println!("No modified install.meta.");
+    } else {
+        println!("{table}");
+    }
+
+    Ok(())
+}
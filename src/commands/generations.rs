@@ -0,0 +1,36 @@
+use anyhow::Result;
+use comfy_table::Table;
+use std::path::Path;
+
+use crate::GenerationsCommands;
+use flintpkg::generations::{list_generations, record_generation, rollback_generation};
+
+pub fn generations_commands(base_path: &Path, command: &GenerationsCommands) -> Result<()> {
+    match command {
+        GenerationsCommands::List => {
+            let mut table = Table::new();
+
+            table.set_header(vec!["Generation", "Created At", "Packages"]);
+
+            for generation in list_generations(base_path)? {
+                table.add_row(vec![
+                    generation.number.to_string(),
+                    generation.created_at.to_string(),
+                    generation.packages.len().to_string(),
+                ]);
+            }
+
+            println!("{table}");
+        }
+
+        GenerationsCommands::Rollback { generation } => {
+            // Make sure the current state can be returned to
+            record_generation(base_path)?;
+            rollback_generation(base_path, *generation)?;
+
+            println!("Rolled back to generation {generation}");
+        }
+    }
+
+    Ok(())
+}
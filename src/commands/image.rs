@@ -0,0 +1,34 @@
+use anyhow::Result;
+use std::{fs, path::Path};
+
+use crate::{ImageCommands, progress::download_progress};
+use flintpkg::image::{ImageSpec, create_image};
+
+pub async fn image_commands(
+    base_path: &Path,
+    chunk_store_path: &Path,
+    command: ImageCommands,
+) -> Result<()> {
+    match command {
+        ImageCommands::Create { spec_path, output } => {
+            let spec: ImageSpec = serde_yaml::from_str(&fs::read_to_string(spec_path)?)?;
+            let contents = create_image(
+                base_path,
+                &spec,
+                &output,
+                chunk_store_path,
+                &download_progress,
+            )
+            .await?;
+
+            println!(
+                "Created {} with {} packages at {}",
+                contents.name,
+                contents.packages.len(),
+                output.display()
+            );
+        }
+    }
+
+    Ok(())
+}
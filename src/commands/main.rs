@@ -1,19 +1,44 @@
 use anyhow::{Context, Result, bail};
-use dialoguer::{Select, theme::ColorfulTheme};
+use comfy_table::Table;
+use notify::{Event, RecursiveMode, Watcher};
 use std::{
+    collections::BTreeMap,
     fs,
     path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver},
+    time::{Duration, Instant},
 };
 
+use crate::{
+    commands::repo::report_signing_request,
+    log::{
+        binary_cache_unavailable, built_artifact, installed_package, kept_build_dir,
+        signed_request, skipped_include_repo, verify_progress,
+    },
+    progress::download_progress,
+    prompt::prompter,
+};
 use flintpkg::{
-    build::{build, force_build},
-    chunks::{utils::clean_unused, verify_all_chunks},
+    build::{Built, build, build_artifact, force_build, watched_paths},
+    chunks::{
+        InstallStats, ScrubReport, estimate_tree_size, print_verify_report, scan_tree,
+        scrub_installed, utils::clean_unused, verify_chunks,
+    },
+    config::read_config,
+    journal::Journal,
     repo::{
-        PackageManifest, get_package, read_manifest,
+        PackageManifest, dependency_chains, get_all_installed_packages, get_all_packages,
+        get_package, get_provider,
+        installed::{
+            canonical_package_id, get_installed, is_installed, read_install_meta, remove_installed,
+        },
+        installed_dependents,
+        provenance::read_provenance,
+        read_manifest, read_subscribed_manifest,
         versions::{get_versions, remove_version},
     },
-    run::{install_package, start},
-    utils::{resolve_package, resolve_repo},
+    run::{RunningPackage, install_package, install_to_root, profile::env_script, spawn, start},
+    utils::{choose_installed_package, choose_package, format_size, resolve_repo, search_packages},
 };
 
 pub async fn build_cmd(
@@ -22,41 +47,556 @@ pub async fn build_cmd(
     build_manifest_path: &Path,
     chunk_store_path: &Path,
     force: bool,
+    skip_tests: bool,
+    keep_build_dir: bool,
 ) -> Result<()> {
     let repo_path = resolve_repo(base_path, repo_name)?;
 
-    if force {
-        force_build(build_manifest_path, &repo_path, None, chunk_store_path).await?;
+    let built = if force {
+        force_build(
+            build_manifest_path,
+            &repo_path,
+            None,
+            chunk_store_path,
+            skip_tests,
+            keep_build_dir,
+        )
+        .await?
     } else {
-        build(build_manifest_path, &repo_path, None, chunk_store_path).await?;
+        build(
+            build_manifest_path,
+            &repo_path,
+            None,
+            chunk_store_path,
+            skip_tests,
+            keep_build_dir,
+            &download_progress,
+        )
+        .await?
+    };
+    report_built(built);
+
+    clean_unused(base_path, chunk_store_path)?;
+    report_signing_request(&repo_path);
+
+    Ok(())
+}
+
+/// Tells the user what a build worked around
+fn report_built(built: Built) -> PackageManifest {
+    for skipped in &built.skipped_repos {
+        skipped_include_repo(&skipped.path, &skipped.error);
+    }
+    if let Some(err) = &built.cache_error {
+        binary_cache_unavailable(err);
+    }
+    if let Some(path) = &built.kept_build_dir {
+        kept_build_dir(path);
     }
 
+    built.package
+}
+
+pub async fn build_artifact_cmd(
+    base_path: &Path,
+    repo_name: &str,
+    build_manifest_path: &Path,
+    chunk_store_path: &Path,
+    output: &Path,
+    skip_tests: bool,
+    keep_build_dir: bool,
+) -> Result<()> {
+    let repo_path = resolve_repo(base_path, repo_name)?;
+
+    let package = report_built(
+        build_artifact(
+            build_manifest_path,
+            &repo_path,
+            output,
+            None,
+            chunk_store_path,
+            skip_tests,
+            keep_build_dir,
+        )
+        .await?,
+    );
+    built_artifact(&package, output);
+
+    // Its chunks are in the artifact, nothing uses them in the chunk store
     clean_unused(base_path, chunk_store_path)?;
 
     Ok(())
 }
 
+pub async fn watch_cmd(
+    base_path: &Path,
+    repo_name: &str,
+    build_manifest_path: &Path,
+    chunk_store_path: &Path,
+    force: bool,
+    skip_tests: bool,
+    run: Option<String>,
+) -> Result<()> {
+    let repo_path = resolve_repo(base_path, repo_name)?;
+
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+
+    for path in watched_paths(build_manifest_path)? {
+        watcher.watch(&path, RecursiveMode::Recursive)?;
+    }
+
+    let mut child: Option<RunningPackage> = None;
+    // Once a build went through the usual checks, rebuild even if the build hash is unchanged.
+    let mut built_once = false;
+
+    loop {
+        let result = if force || built_once {
+            force_build(
+                build_manifest_path,
+                &repo_path,
+                None,
+                chunk_store_path,
+                skip_tests,
+                false,
+            )
+            .await
+        } else {
+            build(
+                build_manifest_path,
+                &repo_path,
+                None,
+                chunk_store_path,
+                skip_tests,
+                false,
+                &download_progress,
+            )
+            .await
+        };
+
+        match result {
+            Ok(built) => {
+                let package = report_built(built);
+                built_once = true;
+
+                install_package(
+                    &repo_path,
+                    &package.id,
+                    chunk_store_path,
+                    &download_progress,
+                )
+                .await?;
+                clean_unused(base_path, chunk_store_path)?;
+                println!("Rebuilt and installed {}", package.id);
+
+                if let Some(entrypoint) = &run {
+                    if let Some(mut old_child) = child.take() {
+                        let _ = old_child.kill();
+                        let _ = old_child.wait();
+                    }
+
+                    let env_policy = read_config(None)?.env.policy(&package.id, false);
+
+                    child = Some(spawn(
+                        &repo_path,
+                        package,
+                        entrypoint,
+                        Vec::<String>::new(),
+                        &env_policy,
+                    )?);
+                }
+            }
+            Err(err) => eprintln!("Build failed: {err:?}"),
+        }
+
+        println!("Watching for changes...");
+        wait_for_change(&receiver)?;
+    }
+}
+
+/// Blocks until a relevant filesystem change happens, and waits for things to settle down.
+fn wait_for_change(receiver: &Receiver<notify::Result<Event>>) -> Result<()> {
+    loop {
+        let event = receiver.recv()??;
+
+        let only_git = event.paths.iter().all(|path| {
+            path.components()
+                .any(|component| component.as_os_str() == ".git")
+        });
+
+        if !event.kind.is_access() && !only_git {
+            break;
+        }
+    }
+
+    // Debounce, editors and build tools tend to write many files at once
+    while receiver.recv_timeout(Duration::from_millis(300)).is_ok() {}
+
+    Ok(())
+}
+
+/// Installs a package, or into `root`. Returns what was downloaded, except for `root` installs.
 pub async fn install_cmd(
     base_path: &Path,
     repo_name: Option<String>,
     chunk_store_path: &Path,
     package_id: &str,
+    root: Option<PathBuf>,
+    json: bool,
+) -> Result<Option<InstallStats>> {
+    // `package_id` may be a name the package provides, see `PackageManifest::provides`
+    let (target_repo_path, package) = if let Some(repo_name) = repo_name {
+        let repo_path = resolve_repo(base_path, &repo_name)?;
+        let package = get_provider(&read_subscribed_manifest(&repo_path)?, package_id)?;
+
+        (repo_path, package)
+    } else {
+        choose_package(base_path, package_id, |_| true, prompter().as_ref())?
+    };
+    let package_id = package.id.as_str();
+
+    if let Some(root) = root {
+        fs::create_dir_all(&root)?;
+
+        for package in install_to_root(
+            &target_repo_path,
+            package_id,
+            chunk_store_path,
+            &root,
+            &download_progress,
+        )
+        .await?
+        {
+            println!("Installed {} into {}", package.id, root.display());
+        }
+    } else {
+        let mut journal = Journal::begin(
+            base_path,
+            "install",
+            Some(&target_repo_path),
+            Some(package_id),
+        )?;
+        journal.installs(&target_repo_path, package_id)?;
+        let stats = install_package(
+            &target_repo_path,
+            package_id,
+            chunk_store_path,
+            &download_progress,
+        )
+        .await?;
+        journal.commit()?;
+
+        if json {
+            println!("{}", serde_json::to_string_pretty(&stats)?);
+        } else {
+            installed_package(package_id, &stats);
+        }
+
+        return Ok(Some(stats));
+    }
+
+    Ok(None)
+}
+
+pub fn info_cmd(base_path: &Path, repo_name: Option<String>, package_id: &str) -> Result<()> {
+    let (target_repo_path, package) = resolve_repo_and_package(base_path, repo_name, package_id)?;
+
+    let install_meta = read_install_meta(&target_repo_path, &package.id)?;
+
+    let commands: Vec<String> = package.commands.iter().map(ToString::to_string).collect();
+
+    let mut table = Table::new();
+    table.add_row(vec!["ID", &package.id]);
+    table.add_row(vec!["Aliases", &package.aliases.join(", ")]);
+    table.add_row(vec![
+        "Title",
+        &package.metadata.title.clone().unwrap_or_default(),
+    ]);
+    table.add_row(vec![
+        "Description",
+        &package.metadata.description.clone().unwrap_or_default(),
+    ]);
+    table.add_row(vec![
+        "Version",
+        &package.metadata.version.clone().unwrap_or_default(),
+    ]);
+    table.add_row(vec![
+        "License",
+        &package.metadata.license.clone().unwrap_or_default(),
+    ]);
+    table.add_row(vec![
+        "Homepage",
+        &package.metadata.homepage_url.clone().unwrap_or_default(),
+    ]);
+    table.add_row(vec![
+        "Maintainers",
+        &package.metadata.maintainers.join(", "),
+    ]);
+    table.add_row(vec!["Keywords", &package.metadata.keywords.join(", ")]);
+    table.add_row(vec!["Categories", &package.metadata.categories.join(", ")]);
+    table.add_row(vec!["Commands", &commands.join(", ")]);
+    table.add_row(vec![
+        "Size",
+        &format_size(estimate_tree_size(&package.chunks)),
+    ]);
+    table.add_row(vec!["Build Hash", &package.build_hash]);
+    table.add_row(vec![
+        "Tests",
+        &package
+            .tests
+            .map_or_else(|| "None".to_string(), |tests| tests.to_string()),
+    ]);
+    table.add_row(vec![
+        "Installed",
+        if install_meta.is_some() { "Yes" } else { "No" },
+    ]);
+
+    if let Some(install_meta) = install_meta {
+        let optional =
+            |value: Option<u64>| value.map(|value| value.to_string()).unwrap_or_default();
+
+        table.add_row(vec!["Installed At", &optional(install_meta.installed_at)]);
+        table.add_row(vec!["Updated At", &optional(install_meta.updated_at)]);
+        table.add_row(vec![
+            "Size On Disk",
+            &install_meta
+                .measured_size()
+                .map(format_size)
+                .unwrap_or_default(),
+        ]);
+    }
+
+    println!("{table}");
+
+    Ok(())
+}
+
+pub fn provenance_cmd(base_path: &Path, repo_name: Option<String>, package_id: &str) -> Result<()> {
+    let (target_repo_path, package) = resolve_repo_and_package(base_path, repo_name, package_id)?;
+    let repo_manifest = read_manifest(&target_repo_path)?;
+
+    let provenance = read_provenance(&target_repo_path, &package.id)?;
+
+    let sources: Vec<String> = provenance
+        .sources
+        .iter()
+        .map(|source| {
+            source.commit.as_ref().map_or_else(
+                || format!("{} {}", source.kind, source.url),
+                |commit| format!("{} {} ({commit})", source.kind, source.url),
+            )
+        })
+        .collect();
+
+    let mut table = Table::new();
+    table.add_row(vec!["Package", &provenance.package_id]);
+    table.add_row(vec!["Build Hash", &provenance.build_hash]);
+    table.add_row(vec![
+        "Matches Repository",
+        if provenance.build_hash == package.build_hash {
+            "Yes"
+        } else {
+            "No (package was rebuilt since)"
+        },
+    ]);
+    table.add_row(vec![
+        "Signed By Repository Key",
+        if provenance.builder_key == repo_manifest.public_key {
+            "Yes"
+        } else {
+            "No"
+        },
+    ]);
+    table.add_row(vec!["Builder Key", provenance.builder_key.trim()]);
+    table.add_row(vec!["Host", &provenance.host]);
+    table.add_row(vec!["Started At", &provenance.started_at.to_string()]);
+    table.add_row(vec!["Finished At", &provenance.finished_at.to_string()]);
+    table.add_row(vec!["Sources", &sources.join("\n")]);
+
+    println!("{table}");
+
+    Ok(())
+}
+
+pub fn list_cmd(base_path: &Path, only_installed: bool, only_available: bool) -> Result<()> {
+    let mut table = Table::new();
+    table.set_header(vec!["Repository", "ID", "Version", "Size", "State"]);
+
+    for repo_entry in fs::read_dir(base_path)? {
+        let repo_path = repo_entry?.path();
+        let repo_name = repo_path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+
+        let packages = get_all_packages(&repo_path)?;
+        let mut installed = get_installed(&repo_path)?;
+        installed.sort_by(|a, b| a.package.id.cmp(&b.package.id));
+
+        if !only_available {
+            for install_meta in &installed {
+                let package = &install_meta.package;
+                let state = if install_meta.dev_install {
+                    "installed (dev)"
+                } else {
+                    match packages.iter().find(|available| available.id == package.id) {
+                        Some(available) if available == package => "installed",
+                        Some(_) => "installed (update available)",
+                        None => "installed (removed from Repository)",
+                    }
+                };
+
+                let size = install_meta
+                    .measured_size()
+                    .unwrap_or_else(|| estimate_tree_size(&package.chunks));
+
+                table.add_row(vec![
+                    repo_name.clone(),
+                    package.id.clone(),
+                    package.metadata.version.clone().unwrap_or_default(),
+                    format_size(size),
+                    state.to_string(),
+                ]);
+            }
+        }
+
+        if !only_installed {
+            for package in packages {
+                if installed
+                    .iter()
+                    .any(|install_meta| install_meta.package.id == package.id)
+                {
+                    continue;
+                }
+
+                table.add_row(vec![
+                    repo_name.clone(),
+                    package.id,
+                    package.metadata.version.unwrap_or_default(),
+                    format_size(estimate_tree_size(&package.chunks)),
+                    "available".to_string(),
+                ]);
+            }
+        }
+    }
+
+    println!("{table}");
+
+    Ok(())
+}
+
+pub fn search_cmd(base_path: &Path, query: &str) -> Result<()> {
+    let results = search_packages(base_path, query)?;
+
+    if results.is_empty() {
+        println!("No packages match '{query}'.");
+        return Ok(());
+    }
+
+    let mut table = Table::new();
+    table.set_header(vec!["Repository", "ID", "Version", "Title", "Description"]);
+
+    for (repo_name, package) in results {
+        table.add_row(vec![
+            repo_name,
+            package.id,
+            package.metadata.version.unwrap_or_default(),
+            package.metadata.title.unwrap_or_default(),
+            package.metadata.description.unwrap_or_default(),
+        ]);
+    }
+
+    println!("{table}");
+
+    Ok(())
+}
+
+pub fn files_cmd(
+    base_path: &Path,
+    repo_name: Option<String>,
+    package_id: &str,
+    installed: bool,
 ) -> Result<()> {
+    let (target_repo_path, package) = resolve_repo_and_package(base_path, repo_name, package_id)?;
+    let installed_path = target_repo_path.join("installed").join(&package.id);
+
+    // Prefer what was actually installed over what the Repository currently has
+    let mut chunks = if let Some(install_meta) = read_install_meta(&target_repo_path, &package.id)?
+    {
+        install_meta.installed_chunks()
+    } else if installed {
+        bail!("Package '{}' is not installed.", package.id)
+    } else {
+        package.chunks
+    };
+
+    let mut table = Table::new();
+
+    if installed {
+        table.set_header(vec!["Path", "Size", "Mode", "Status"]);
+
+        for file in scan_tree(&installed_path, &chunks)? {
+            table.add_row(vec![
+                file.path.display().to_string(),
+                format_size(file.size),
+                format!("{:o}", file.permissions),
+                file.discrepancy
+                    .map_or_else(|| "ok".to_string(), |discrepancy| discrepancy.to_string()),
+            ]);
+        }
+    } else {
+        table.set_header(vec!["Path", "Size", "Mode"]);
+
+        chunks.sort_by(|a, b| a.path().cmp(b.path()));
+
+        for chunk in chunks {
+            table.add_row(vec![
+                chunk.path().display().to_string(),
+                format_size(chunk.size()),
+                format!("{:o}", chunk.permissions()),
+            ]);
+        }
+    }
+
+    println!("{table}");
+
+    Ok(())
+}
+
+pub fn why_cmd(base_path: &Path, repo_name: Option<String>, package_id: &str) -> Result<()> {
     let target_repo_path: PathBuf = if let Some(repo_name) = repo_name {
         resolve_repo(base_path, &repo_name)?
     } else {
-        let possible_repos = resolve_package(base_path, package_id, |_| true)?;
+        choose_package(
+            base_path,
+            package_id,
+            |repo_path| is_installed(repo_path, package_id),
+            prompter().as_ref(),
+        )?
+        .0
+    };
+    let package_id = &canonical_package_id(&target_repo_path, package_id)?;
 
-        if possible_repos.len() > 1 {
-            choose_repo(possible_repos)?.0
-        } else if let Some(possible_repo) = possible_repos.first() {
-            possible_repo.0.clone()
-        } else {
-            bail!("No Repositories contain that package.")
+    let installed = get_all_installed_packages(&target_repo_path)?;
+    if !installed.iter().any(|package| &package.id == package_id) {
+        bail!("Package '{package_id}' is not installed.");
+    }
+
+    let chains = dependency_chains(&installed, package_id);
+    if chains.is_empty() {
+        println!("{package_id} was installed directly, nothing depends on it.");
+    } else {
+        println!("{package_id} is needed by:");
+        for chain in chains {
+            println!("  {}", chain.join(" -> "));
         }
-    };
+    }
 
-    install_package(&target_repo_path, package_id, chunk_store_path).await?;
+    let dependents = installed_dependents(&installed, package_id);
+    if !dependents.is_empty() {
+        println!("Removing it would break: {}", dependents.join(", "));
+    }
 
     Ok(())
 }
@@ -65,25 +605,82 @@ pub fn remove_cmd(base_path: &Path, repo_name: Option<String>, package_id: &str)
     let target_repo_path: PathBuf = if let Some(repo_name) = repo_name {
         resolve_repo(base_path, &repo_name)?
     } else {
-        let possible_repos = resolve_package(base_path, package_id, |repo_path| {
-            repo_path.join("installed").join(package_id).exists()
-        })?;
-
-        if possible_repos.len() > 1 {
-            choose_repo(possible_repos)?.0
-        } else if let Some(possible_repo) = possible_repos.first() {
-            possible_repo.0.clone()
-        } else {
-            bail!("No Repositories contain that package.")
-        }
+        choose_package(
+            base_path,
+            package_id,
+            |repo_path| is_installed(repo_path, package_id),
+            prompter().as_ref(),
+        )?
+        .0
     };
+    // Installed under its id, whichever alias it was removed by
+    let package_id = &canonical_package_id(&target_repo_path, package_id)?;
 
-    fs::remove_dir_all(target_repo_path.join("installed").join(package_id))?;
+    let mut journal = Journal::begin(
+        base_path,
+        "remove",
+        Some(&target_repo_path),
+        Some(package_id),
+    )?;
+
+    remove_installed(&target_repo_path, package_id)?;
+    journal.step("unlinked")?;
 
     for version in get_versions(&target_repo_path, package_id)? {
         remove_version(&target_repo_path, &version, package_id)?;
     }
 
+    journal.commit()
+}
+
+pub fn login_cmd(mirror: &str, login: Option<String>) -> Result<()> {
+    use crate::log::logged_in;
+    use flintpkg::repo::credentials::{Credentials, store_credentials, url_host};
+
+    let Some(host) = url_host(mirror) else {
+        bail!("{mirror} has no host to log in to.")
+    };
+
+    let prompter = prompter();
+    let login = match login {
+        Some(login) => login,
+        None => prompter.input("Login")?,
+    };
+    let password = prompter.password(&format!("Password for {login}@{host}"))?;
+
+    store_credentials(mirror, &Credentials { login, password })?;
+    logged_in(host);
+
+    Ok(())
+}
+
+#[cfg(feature = "network")]
+pub async fn prefetch_cmd(
+    base_path: &Path,
+    repo_name: Option<String>,
+    chunk_store_path: &Path,
+    package_ids: &[String],
+) -> Result<()> {
+    use crate::log::prefetched_package;
+    use flintpkg::{repo::get_package_closure, run::download_package};
+
+    for package_id in package_ids {
+        let (repo_path, package) =
+            resolve_repo_and_package(base_path, repo_name.clone(), package_id)?;
+
+        for package in get_package_closure(&read_subscribed_manifest(&repo_path)?, &package.id)? {
+            download_package(
+                &repo_path,
+                &package.id,
+                chunk_store_path,
+                &download_progress,
+            )
+            .await?;
+
+            prefetched_package(&package);
+        }
+    }
+
     Ok(())
 }
 
@@ -92,12 +689,35 @@ pub async fn update_cmd(
     base_path: &Path,
     quicklaunch_path: &Path,
     chunk_store_path: &Path,
+    args: crate::UpdateArgs,
 ) -> Result<()> {
-    use flintpkg::run::quicklaunch::update_quicklaunch;
+    use flintpkg::{generations::record_generation, run::quicklaunch::update_quicklaunch};
+
+    use crate::{UpdateMode, update_all_repos};
 
-    use crate::update_all_repos;
+    let mode = if args.download_only {
+        UpdateMode::DownloadOnly
+    } else if args.apply_downloaded {
+        UpdateMode::ApplyDownloaded
+    } else {
+        UpdateMode::Full
+    };
 
-    update_all_repos(base_path, chunk_store_path).await?;
+    update_all_repos(
+        base_path,
+        chunk_store_path,
+        mode,
+        args.ignore_edition,
+        args.accept_new_key,
+        args.yes,
+    )
+    .await?;
+
+    if mode != UpdateMode::DownloadOnly
+        && let Some(generation) = record_generation(base_path)?
+    {
+        println!("Recorded generation {}", generation.number);
+    }
 
     update_quicklaunch(base_path, quicklaunch_path)?;
     clean_unused(base_path, chunk_store_path)
@@ -110,24 +730,17 @@ pub async fn run_cmd(
     package: String,
     entrypoint: Option<String>,
     args: Option<Vec<String>>,
+    inherit_env: bool,
 ) -> Result<()> {
     let (target_repo_path, package_manifest) = if let Some(repo_name) = repo_name {
         // Resolve the path, and then read the package manifest
         let repo_path = resolve_repo(path, &repo_name)?;
-        let repo_manifest = read_manifest(&repo_path)?;
+        let repo_manifest = read_subscribed_manifest(&repo_path)?;
         let package_manifest = get_package(&repo_manifest, &package)?;
 
         (repo_path, package_manifest)
     } else {
-        let possible_repos = resolve_package(path, &package, |_| true)?;
-
-        if possible_repos.len() > 1 {
-            choose_repo(possible_repos)?
-        } else if let Some(possible_repo) = possible_repos.first() {
-            possible_repo.clone()
-        } else {
-            bail!("No Repositories contain that package.")
-        }
+        choose_installed_package(path, &package, prompter().as_ref())?
     };
 
     let entrypoint = if let Some(e) = entrypoint {
@@ -138,23 +751,46 @@ pub async fn run_cmd(
             .first()
             .context("Package has no commands defined")?;
 
-        first_command
-            .to_str()
-            .context("First command path was not valid UTF-8")?
-            .to_string()
+        first_command.name().context("First command has no name")?
     };
 
+    let config = read_config(None)?;
+    let env_policy = config.env.policy(&package_manifest.id, inherit_env);
+
     // Install if not installed
     #[cfg(feature = "network")]
     if !target_repo_path
         .join("installed/")
-        .join(&package)
+        .join(&package_manifest.id)
         .join("install.meta")
         .exists()
     {
-        install_package(&target_repo_path, &package, chunk_store_path)
-            .await
-            .with_context(|| "Failed to install package.")?;
+        // Anything else is installed first, as usual
+        if config.launch_early && flintpkg::run::can_launch_early(&package_manifest) {
+            use flintpkg::run::start_while_installing;
+
+            start_while_installing(
+                &target_repo_path,
+                package_manifest,
+                &entrypoint,
+                args.unwrap_or_default(),
+                &env_policy,
+                chunk_store_path,
+                &download_progress,
+            )
+            .await?;
+
+            return Ok(());
+        }
+
+        install_package(
+            &target_repo_path,
+            &package_manifest.id,
+            chunk_store_path,
+            &download_progress,
+        )
+        .await
+        .with_context(|| "Failed to install package.")?;
     }
 
     start(
@@ -162,38 +798,134 @@ pub async fn run_cmd(
         package_manifest,
         &entrypoint,
         args.unwrap_or_default(),
+        &env_policy,
     )?;
 
     Ok(())
 }
 
-pub fn verify_cmd(base_path: &Path, repo_name: &str, chunk_store_path: &Path) -> Result<()> {
+/// Starts `$SHELL` with the env scripts of installed packages sourced, in a POSIX shell first
+pub fn shell_cmd(base_path: &Path, package_ids: &[String]) -> Result<()> {
+    let script = env_script(base_path, package_ids)?;
+    let shell = std::env::var_os("SHELL").unwrap_or_else(|| "sh".into());
+
+    let status = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(format!("{script}exec \"$0\""))
+        .arg(shell)
+        .status()
+        .with_context(|| "Could not start a shell")?;
+    if !status.success() {
+        bail!("The shell exited with {status}.")
+    }
+
+    Ok(())
+}
+
+pub fn verify_cmd(
+    base_path: &Path,
+    repo_name: &str,
+    chunk_store_path: &Path,
+    fail_fast: bool,
+    json: bool,
+) -> Result<()> {
     let target_repo_path = resolve_repo(base_path, repo_name)?;
-    verify_all_chunks(&target_repo_path, chunk_store_path)?;
+    let started = Instant::now();
+
+    let report = verify_chunks(
+        &target_repo_path,
+        chunk_store_path,
+        fail_fast,
+        &|progress| {
+            // Roughly every percent, so the output keeps up with fast disks
+            if progress.done % (progress.total / 100).max(1) == 0 || progress.done == progress.total
+            {
+                verify_progress(progress, started.elapsed());
+            }
+        },
+    )?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_verify_report(&report);
+    }
+
+    if report.failed() > 0 {
+        bail!("Some chunks failed verification");
+    }
+
     clean_unused(base_path, chunk_store_path)
 }
 
-/// Lets the user choose a Repository from a list
-fn choose_repo(
-    possible_repos: Vec<(PathBuf, PackageManifest)>,
+pub fn scrub_cmd(
+    base_path: &Path,
+    repo_name: &str,
+    chunk_store_path: &Path,
+    json: bool,
+) -> Result<()> {
+    let target_repo_path = resolve_repo(base_path, repo_name)?;
+    let reports = scrub_installed(&target_repo_path, chunk_store_path)?;
+
+    if json {
+        let reports: BTreeMap<&str, &ScrubReport> = reports
+            .iter()
+            .map(|(package_id, report)| (package_id.as_str(), report))
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&reports)?);
+    } else {
+        let mut table = Table::new();
+        table.set_header(vec!["Package", "Path", "Status"]);
+
+        for (package_id, report) in &reports {
+            for path in &report.modified {
+                table.add_row(vec![package_id, &path.display().to_string(), "modified"]);
+            }
+            for path in &report.missing {
+                table.add_row(vec![package_id, &path.display().to_string(), "missing"]);
+            }
+        }
+
+        if !table.is_empty() {
+            println!("{table}");
+        }
+
+        let linked: usize = reports.iter().map(|(_, report)| report.linked).sum();
+        let hashed: usize = reports.iter().map(|(_, report)| report.hashed).sum();
+        println!(
+            "Scrubbed {} packages: {linked} files hard linked, {hashed} hashed",
+            reports.len()
+        );
+    }
+
+    if reports.iter().any(|(_, report)| report.failed() > 0) {
+        bail!("Some installed files no longer match their chunks, reinstall those packages");
+    }
+
+    Ok(())
+}
+
+/// Resolves a package either from the given Repository, or by searching all of them
+pub fn resolve_repo_and_package(
+    base_path: &Path,
+    repo_name: Option<String>,
+    package_id: &str,
 ) -> Result<(PathBuf, PackageManifest)> {
-    let items: Vec<String> = possible_repos
-        .iter()
-        .map(|(path, manifest)| {
-            format!(
-                "{} ({} {})",
-                path.file_name().unwrap().to_string_lossy(),
-                manifest.metadata.title.clone().unwrap_or_default(),
-                manifest.metadata.version.clone().unwrap_or_default()
-            )
-        })
-        .collect();
+    if let Some(repo_name) = repo_name {
+        let repo_path = resolve_repo(base_path, &repo_name)?;
+        let package = get_package(&read_subscribed_manifest(&repo_path)?, package_id)?;
 
-    let selection = Select::with_theme(&ColorfulTheme::default())
-        .with_prompt("Multiple repositories contain this package, pick one")
-        .items(&items)
-        .default(0)
-        .interact()?;
+        Ok((repo_path, package))
+    } else {
+        choose_package(base_path, package_id, |_| true, prompter().as_ref())
+    }
+}
 
-    Ok(possible_repos.into_iter().nth(selection).unwrap())
+pub fn sign_cmd(request_path: &Path) -> Result<()> {
+    use flintpkg::repo::signing_request::sign_request;
+
+    let manifest = sign_request(request_path, None)?;
+    signed_request(manifest.serial, request_path);
+
+    Ok(())
 }
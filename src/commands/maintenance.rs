@@ -0,0 +1,144 @@
+use anyhow::{Context, Result, bail};
+use directories::BaseDirs;
+use std::{
+    env::{args_os, current_exe, var_os},
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+    time::Duration,
+};
+
+use flintpkg::{
+    chunks::{utils::clean_unused, verify_all_chunks},
+    config::{get_build_cache_dir, get_system_data_dir, read_config},
+    generations::prune_generations,
+    maintenance::{evict_build_cache, prune_versions, remove_partial_chunks},
+};
+
+/// Set when maintenance has already been restarted under `nice`/`ionice`
+const NICED_ENV: &str = "FLINT_MAINTENANCE_NICED";
+
+pub fn maintenance_cmd(
+    base_path: &Path,
+    chunk_store_path: &Path,
+    install_timer: bool,
+    no_nice: bool,
+) -> Result<()> {
+    if install_timer {
+        return install_systemd_timer(base_path);
+    }
+
+    if !no_nice && rerun_niced()? {
+        return Ok(());
+    }
+
+    let config = read_config(None)?.maintenance;
+
+    if config.gc {
+        clean_unused(base_path, chunk_store_path)?;
+        println!("Removed unused chunks");
+    }
+
+    if config.scrub {
+        for entry in base_path.read_dir()? {
+            let repo_path = entry?.path();
+
+            // Corrupted chunks are already removed, so keep going with the other tasks
+            if let Err(error) = verify_all_chunks(&repo_path, chunk_store_path) {
+                eprintln!("{}: {error}", repo_path.display());
+            }
+        }
+    }
+
+    if config.prune_versions {
+        let generations = prune_generations(base_path, config.keep_generations)?;
+        let versions = prune_versions(base_path)?;
+        println!("Pruned {generations} generations and {versions} versions");
+    }
+
+    if config.evict_cache {
+        let max_age = Duration::from_secs(config.cache_max_age_days * 24 * 60 * 60);
+        let evicted = evict_build_cache(&get_build_cache_dir()?, max_age)?
+            + remove_partial_chunks(chunk_store_path, None)?;
+        println!("Evicted {evicted} cached files");
+    }
+
+    Ok(())
+}
+
+/// Runs this same command again under `ionice` and `nice`, so maintenance stays out of the way.
+///
+/// # Returns
+///
+/// `false` if already niced, or `ionice` is unavailable and maintenance should run directly.
+fn rerun_niced() -> Result<bool> {
+    if var_os(NICED_ENV).is_some() {
+        return Ok(false);
+    }
+
+    let status = Command::new("ionice")
+        .args(["-c", "3", "nice", "-n", "19"])
+        .arg(current_exe()?)
+        .args(args_os().skip(1))
+        .env(NICED_ENV, "1")
+        .status();
+
+    match status {
+        Ok(status) if status.success() => Ok(true),
+        Ok(status) => bail!("Maintenance failed with {status}"),
+        Err(_) => Ok(false),
+    }
+}
+
+/// Writes a systemd service and daily timer that run `flint maintenance`.
+fn install_systemd_timer(base_path: &Path) -> Result<()> {
+    let system = base_path.starts_with(get_system_data_dir());
+
+    let (unit_dir, scope_arg) = if system {
+        (PathBuf::from("/etc/systemd/system"), " --system")
+    } else {
+        let base_dirs = BaseDirs::new().context("Could not find user directories")?;
+        (base_dirs.config_dir().join("systemd/user"), "")
+    };
+
+    let executable_path = current_exe()
+        .with_context(|| "Could not get current executable path")?
+        .canonicalize()?;
+
+    let service = format!(
+        "[Unit]\n\
+         Description=Flint maintenance\n\n\
+         [Service]\n\
+         Type=oneshot\n\
+         Environment={NICED_ENV}=1\n\
+         Nice=19\n\
+         IOSchedulingClass=idle\n\
+         ExecStart={}{scope_arg} maintenance\n",
+        executable_path.display()
+    );
+    let timer = "[Unit]\n\
+        Description=Run Flint maintenance daily\n\n\
+        [Timer]\n\
+        OnCalendar=daily\n\
+        RandomizedDelaySec=1h\n\
+        Persistent=true\n\n\
+        [Install]\n\
+        WantedBy=timers.target\n";
+
+    fs::create_dir_all(&unit_dir)
+        .with_context(|| format!("Could not create {}. Try sudo?", unit_dir.display()))?;
+    fs::write(unit_dir.join("flint-maintenance.service"), service)?;
+    fs::write(unit_dir.join("flint-maintenance.timer"), timer)?;
+
+    let systemctl = if system {
+        "systemctl"
+    } else {
+        "systemctl --user"
+    };
+    println!(
+        "Wrote flint-maintenance.timer to {}. Enable it with: {systemctl} enable --now flint-maintenance.timer",
+        unit_dir.display()
+    );
+
+    Ok(())
+}
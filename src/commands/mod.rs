@@ -1,28 +1,57 @@
+pub mod audit;
 pub mod bundle;
+pub mod chunks;
+#[cfg(unix)]
+pub mod daemon;
+pub mod dev;
+pub mod doctor;
+pub mod generations;
+pub mod image;
 pub mod main;
+pub mod maintenance;
 pub mod repo;
 
 use anyhow::Result;
-use flintpkg::{chunks::utils::clean_used, run::quicklaunch::update_quicklaunch};
+use flintpkg::{
+    chunks::utils::clean_used,
+    config::require_network,
+    run::{profile::env_script, quicklaunch::update_quicklaunch},
+};
 use std::path::Path;
 
 #[cfg(feature = "network")]
-use crate::commands::main::update_cmd;
+use crate::commands::main::{prefetch_cmd, update_cmd};
 use crate::{
     Command,
     commands::{
+        audit::audit_cmd,
         bundle::bundle_commands,
-        main::{build_cmd, install_cmd, remove_cmd, run_cmd, verify_cmd},
+        chunks::chunks_commands,
+        dev::dev_commands,
+        doctor::doctor_cmd,
+        generations::generations_commands,
+        image::image_commands,
+        main::{
+            build_artifact_cmd, build_cmd, files_cmd, info_cmd, install_cmd, list_cmd, login_cmd,
+            provenance_cmd, remove_cmd, run_cmd, scrub_cmd, search_cmd, shell_cmd, sign_cmd,
+            verify_cmd, watch_cmd, why_cmd,
+        },
+        maintenance::maintenance_cmd,
         repo::repo_commands,
     },
 };
 
+#[allow(clippy::too_many_lines)]
 pub async fn main_commands(
     base_path: &Path,
     quicklaunch_path: &Path,
     chunk_store_path: &Path,
     command: Command,
 ) -> Result<()> {
+    if command.needs_network() {
+        require_network()?;
+    }
+
     match command {
         Command::Repo { command } => {
             repo_commands(base_path, chunk_store_path, command, quicklaunch_path).await?;
@@ -33,19 +62,80 @@ pub async fn main_commands(
             build_manifest_path,
             repo_name,
             force,
+            skip_tests,
+            watch,
+            run,
+            keep_build_dir,
+            output,
         } => {
-            build_cmd(
-                base_path,
-                &repo_name,
-                &build_manifest_path,
-                chunk_store_path,
-                force,
-            )
-            .await?;
+            if let Some(output) = output {
+                build_artifact_cmd(
+                    base_path,
+                    &repo_name,
+                    &build_manifest_path,
+                    chunk_store_path,
+                    &output,
+                    skip_tests,
+                    keep_build_dir,
+                )
+                .await?;
+            } else if watch {
+                watch_cmd(
+                    base_path,
+                    &repo_name,
+                    &build_manifest_path,
+                    chunk_store_path,
+                    force,
+                    skip_tests,
+                    run,
+                )
+                .await?;
+            } else {
+                build_cmd(
+                    base_path,
+                    &repo_name,
+                    &build_manifest_path,
+                    chunk_store_path,
+                    force,
+                    skip_tests,
+                    keep_build_dir,
+                )
+                .await?;
+            }
+        }
+
+        Command::Install {
+            repo_name,
+            package,
+            root,
+            json,
+        } => {
+            install_cmd(base_path, repo_name, chunk_store_path, &package, root, json).await?;
         }
 
-        Command::Install { repo_name, package } => {
-            install_cmd(base_path, repo_name, chunk_store_path, &package).await?;
+        Command::Info { repo_name, package } => info_cmd(base_path, repo_name, &package)?,
+
+        Command::List {
+            installed,
+            available,
+        } => list_cmd(base_path, installed, available)?,
+
+        Command::Search { query } => search_cmd(base_path, &query)?,
+
+        Command::Env { packages } => print!("{}", env_script(base_path, &packages)?),
+
+        Command::Shell { packages } => shell_cmd(base_path, &packages)?,
+
+        Command::Files {
+            repo_name,
+            package,
+            installed,
+        } => files_cmd(base_path, repo_name, &package, installed)?,
+
+        Command::Why { repo_name, package } => why_cmd(base_path, repo_name, &package)?,
+
+        Command::Provenance { repo_name, package } => {
+            provenance_cmd(base_path, repo_name, &package)?;
         }
 
         Command::Remove { repo_name, package } => remove_cmd(base_path, repo_name, &package)?,
@@ -53,10 +143,13 @@ pub async fn main_commands(
         Command::Bundle { command } => bundle_commands(base_path, command)?,
 
         #[cfg(feature = "network")]
-        Command::Update => update_cmd(base_path, quicklaunch_path, chunk_store_path).await?,
+        Command::Update(args) => {
+            update_cmd(base_path, quicklaunch_path, chunk_store_path, args).await?;
+        }
 
         Command::Run {
             repo_name,
+            inherit_env,
             package,
             entrypoint,
             args,
@@ -68,13 +161,143 @@ pub async fn main_commands(
                 package,
                 entrypoint,
                 args,
+                inherit_env,
+            )
+            .await?;
+        }
+
+        Command::X {
+            repo_name,
+            inherit_env,
+            package,
+            args,
+        } => {
+            run_cmd(
+                base_path,
+                repo_name,
+                chunk_store_path,
+                package,
+                None,
+                Some(args),
+                inherit_env,
             )
             .await?;
         }
 
-        Command::VerifyChunks { repo_name } => verify_cmd(base_path, &repo_name, chunk_store_path)?,
+        Command::Generations { command } => generations_commands(base_path, &command)?,
+
+        Command::Image { command } => image_commands(base_path, chunk_store_path, command).await?,
+
+        Command::Dev { command } => dev_commands(base_path, chunk_store_path, command).await?,
+
+        Command::VerifyChunks {
+            repo_name,
+            installed: true,
+            json,
+            ..
+        } => scrub_cmd(base_path, &repo_name, chunk_store_path, json)?,
+
+        Command::VerifyChunks {
+            repo_name,
+            fail_fast,
+            json,
+            ..
+        } => verify_cmd(base_path, &repo_name, chunk_store_path, fail_fast, json)?,
+
+        Command::Sign { request_path } => sign_cmd(&request_path)?,
+
+        Command::Chunks { command } => {
+            chunks_commands(base_path, chunk_store_path, command).await?;
+        }
 
         Command::Clean => clean_used(base_path, chunk_store_path)?,
+
+        Command::Doctor => doctor_cmd(base_path)?,
+
+        Command::Audit => audit_cmd(base_path)?,
+
+        Command::Login { mirror, login } => login_cmd(&mirror, login)?,
+
+        #[cfg(unix)]
+        Command::Daemon { socket } => {
+            use crate::commands::daemon::daemon_cmd;
+
+            daemon_cmd(base_path, quicklaunch_path, chunk_store_path, &socket).await?;
+        }
+
+        Command::Maintenance {
+            install_timer,
+            no_nice,
+        } => maintenance_cmd(base_path, chunk_store_path, install_timer, no_nice)?,
+
+        #[cfg(feature = "serve")]
+        Command::Serve {
+            repo_name,
+            address,
+            maintainers,
+            stats,
+        } => {
+            use flintpkg::{serve::serve, utils::resolve_repo};
+
+            let maintainer_keys: Vec<String> = if let Some(maintainers) = maintainers {
+                serde_yaml::from_str(&std::fs::read_to_string(maintainers)?)?
+            } else {
+                Vec::new()
+            };
+
+            serve(
+                &resolve_repo(base_path, &repo_name)?,
+                chunk_store_path,
+                &address,
+                &maintainer_keys,
+                stats,
+            )?;
+        }
+
+        #[cfg(all(feature = "fuse", target_os = "linux"))]
+        Command::Mount {
+            repo_name,
+            package,
+            mount_path,
+        } => {
+            use crate::commands::main::resolve_repo_and_package;
+            use crate::log::failed_mount_read;
+            use flintpkg::run::mount::mount_package;
+
+            let (repo_path, package) = resolve_repo_and_package(base_path, repo_name, &package)?;
+            for failed in mount_package(&repo_path, package, &mount_path, chunk_store_path).await? {
+                failed_mount_read(&failed.path, &failed.error);
+            }
+        }
+
+        #[cfg(feature = "network")]
+        Command::Prefetch {
+            repo_name,
+            packages,
+        } => prefetch_cmd(base_path, repo_name, chunk_store_path, &packages).await?,
+
+        #[cfg(feature = "network")]
+        Command::Publish {
+            repo_name,
+            package,
+            remote_url,
+        } => {
+            use flintpkg::{repo::network::publish_package, utils::resolve_repo};
+
+            publish_package(
+                &resolve_repo(base_path, &repo_name)?,
+                &package,
+                chunk_store_path,
+                &remote_url,
+                None,
+            )
+            .await?;
+        }
+
+        #[cfg(not(feature = "network"))]
+        Command::Update { .. } | Command::Prefetch { .. } | Command::Publish { .. } => {
+            require_network()?;
+        }
     }
 
     Ok(())
@@ -1,15 +1,56 @@
-use anyhow::{Result, anyhow};
+use anyhow::{Context, Result, anyhow, bail};
 use comfy_table::Table;
-use flintpkg::chunks::utils::clean_unused;
-use std::{fs, os::unix::fs::symlink, path::Path};
+use flintpkg::build::artifact::insert_artifact;
+use flintpkg::chunks::utils::{clean_unused, gc_repo_chunks, migrate_chunk_sizes};
+use std::{fs, path::Path};
 
-use crate::RepoCommands;
+use crate::{
+    ChannelCommands, KeysCommands, MirrorCommands, MirrorsCommands, RepoCommands, RepoUnpackArgs,
+    RepoUpdateArgs,
+    log::{
+        applied_signature, cloned_repo, detached_package, exported_repo, inserted_artifact,
+        packed_repo, pruned_revisions, removing_installed_packages, rotated_key, signing_requested,
+        stale_image,
+    },
+    prompt::prompter,
+};
 use flintpkg::{
-    crypto::signing::sign,
-    repo::{create_repo, read_manifest, remove_package, update_manifest},
-    utils::resolve_repo,
+    crypto::key::{get_private_key, serialize_verifying_key},
+    journal::{Journal, STEP_REMOVING_REPO},
+    repo::{
+        Advisory, BinaryCache, Mirror, RepoManifest,
+        analyze::analyze_repo,
+        channels::{remove_channel, select_channel, set_channel},
+        clone::clone_repo,
+        create_repo_with_hash,
+        credentials::{rename_repo_auth, set_repo_auth},
+        edition::SUPPORTED_EDITIONS,
+        export::export_repo,
+        installed::{detach_installed, get_installed},
+        keys::{add_signing_key, remove_signing_key},
+        metadata_policy::{get_metadata_policy, set_metadata_policy},
+        migrate::migrate_repo,
+        mirrors::{
+            add_local_mirror, add_repo_mirror, get_local_mirrors, normalize_mirror_url,
+            remove_local_mirror, remove_repo_mirror,
+        },
+        pack::{pack_repo, unpack_repo},
+        read_manifest, remove_package,
+        rename::rename_repo,
+        revisions::prune_revisions,
+        rotation::{finish_key_rotation, rotate_key},
+        serialize_manifest,
+        settings::{UpdatePolicy, get_settings, set_settings},
+        signing_request::{SIGNING_REQUEST_FILE, apply_signing_request, sign_manifest},
+        subscription::parse_patterns,
+        usage::{repo_usage, store_usage},
+        verify::verify_repo,
+    },
+    run::quicklaunch::update_quicklaunch,
+    utils::{format_size, platform::symlink_dir, resolve_repo},
 };
 
+#[allow(clippy::too_many_lines)]
 pub async fn repo_commands(
     base_path: &Path,
     chunk_store_path: &Path,
@@ -17,119 +58,1237 @@ pub async fn repo_commands(
     quicklaunch_path: &Path,
 ) -> Result<()> {
     match command {
-        RepoCommands::Create { repo_name } => {
+        RepoCommands::Create { repo_name, hash } => {
             let repo_path = &base_path.join(&repo_name);
 
-            create_repo(repo_path, None)?;
-            symlink(Path::new("../../chunks"), repo_path.join("chunks"))?;
+            create_repo_with_hash(repo_path, None, hash.parse()?)?;
+            symlink_dir(Path::new("../../chunks"), &repo_path.join("chunks"))?;
         }
 
-        RepoCommands::List => {
-            let mut table = Table::new();
+        RepoCommands::List => list_repos(base_path)?,
 
-            table.set_header(vec![
-                "Name",
-                "Title",
-                "Hash Kind",
-                "Homepage",
-                "License",
-                "Version",
-            ]);
+        #[cfg(feature = "network")]
+        RepoCommands::Add(args) => add_repo(base_path, quicklaunch_path, args).await?,
 
-            for repo_entry in fs::read_dir(base_path)? {
-                let repo_dir = repo_entry?;
-                let repo_name = repo_dir.file_name();
-                let repo_name_str = repo_name
-                    .to_str()
-                    .ok_or_else(|| anyhow!("Repository {} is not unicode.", repo_name.display()))?;
+        RepoCommands::Remove {
+            repo_name,
+            keep_installed,
+        } => remove_repo(base_path, &repo_name, keep_installed)?,
 
-                let repo = read_manifest(&repo_dir.path())?;
+        RepoCommands::Update(args) => update_repo(base_path, chunk_store_path, args)?,
 
-                table.add_row(vec![
-                    &repo_name_str,
-                    repo.metadata.title.unwrap_or_default().as_str(),
-                    &repo.hash_kind.to_string(),
-                    &repo.metadata.homepage_url.unwrap_or_default(),
-                    &repo.metadata.license.unwrap_or_default(),
-                    &repo.metadata.version.unwrap_or_default(),
-                ]);
-            }
+        #[cfg(feature = "network")]
+        RepoCommands::Include {
+            repo_name,
+            name,
+            url,
+            public_key,
+        } => include_feed(base_path, &repo_name, &name, &url, public_key.as_deref()).await?,
 
-            println!("{table}");
+        #[cfg(feature = "network")]
+        RepoCommands::Diff {
+            repo_name,
+            ignore_edition,
+            json,
+        } => diff(base_path, &repo_name, ignore_edition, json).await?,
+
+        #[cfg(not(feature = "network"))]
+        RepoCommands::Add(_) | RepoCommands::Include { .. } | RepoCommands::Diff { .. } => {
+            flintpkg::config::require_network()?;
         }
 
-        #[cfg(feature = "network")]
-        RepoCommands::Add {
+        RepoCommands::Exclude { repo_name, name } => exclude_feed(base_path, &repo_name, &name)?,
+
+        RepoCommands::Insert {
             repo_name,
-            remote_url,
+            artifact_path,
         } => {
-            use crate::log::{added_repo, cannot_update_repo, update_redirect};
-            use flintpkg::repo::network::add_repository;
-            use flintpkg::run::quicklaunch::update_quicklaunch;
+            let repo_path = resolve_repo(base_path, &repo_name)?;
+            let package = insert_artifact(&artifact_path, &repo_path, chunk_store_path, None)?;
+            inserted_artifact(&package, &repo_name);
+            report_signing_request(&repo_path);
+        }
 
-            let repo_path = &base_path.join(&repo_name);
-            fs::create_dir_all(repo_path)?;
+        RepoCommands::RemovePackage {
+            repo_name,
+            package_id,
+        } => remove_repo_package(base_path, chunk_store_path, &repo_name, &package_id)?,
 
-            let manifest = add_repository(repo_path, &remote_url, None).await?;
-            added_repo(&repo_name, &manifest.public_key);
+        RepoCommands::Prune { repo_name, keep } => {
+            prune(base_path, chunk_store_path, &repo_name, keep)?;
+        }
 
-            update_quicklaunch(base_path, quicklaunch_path)?;
+        RepoCommands::Gc {
+            repo_name,
+            chunks_dir,
+            dry_run,
+        } => gc(
+            base_path,
+            chunk_store_path,
+            &repo_name,
+            chunks_dir.as_deref(),
+            dry_run,
+        )?,
 
-            if let Some(first_mirror) = manifest.mirrors.first() {
-                if remote_url != *first_mirror {
-                    update_redirect(&repo_name, first_mirror, &remote_url);
-                }
-            } else {
-                cannot_update_repo(&repo_name);
-            }
+        RepoCommands::RotateKey { repo_name, finish } => rotate(base_path, &repo_name, finish)?,
+
+        RepoCommands::SignExternal { repo_name, disable } => {
+            let repo_path = &resolve_repo(base_path, &repo_name)?;
+            let mut settings = get_settings(repo_path)?;
+
+            settings.external_signing = !disable;
+            set_settings(repo_path, &settings)?;
+        }
+
+        RepoCommands::ApplySignature {
+            repo_name,
+            request_path,
+        } => {
+            let repo_path = &resolve_repo(base_path, &repo_name)?;
+            let manifest = apply_signing_request(repo_path, &request_path)?;
+            applied_signature(&repo_name, manifest.serial);
+        }
+
+        RepoCommands::Migrate {
+            repo_name,
+            to,
+            dry_run,
+        } => migrate(
+            base_path,
+            chunk_store_path,
+            &repo_name,
+            to.as_deref(),
+            dry_run,
+        )?,
+
+        RepoCommands::Rename {
+            repo_name,
+            new_name,
+        } => rename(base_path, quicklaunch_path, &repo_name, &new_name)?,
+
+        RepoCommands::Clone {
+            repo_name,
+            new_name,
+            only,
+        } => {
+            let patterns = only.as_deref().map(parse_patterns);
+            let manifest = clone_repo(
+                base_path,
+                chunk_store_path,
+                &repo_name,
+                &new_name,
+                patterns.as_deref(),
+                None,
+            )?;
+            cloned_repo(&repo_name, &new_name, manifest.packages.len());
+        }
+
+        RepoCommands::Priority {
+            repo_name,
+            priority,
+        } => set_priority(base_path, &repo_name, priority)?,
+
+        RepoCommands::Advisories {
+            repo_name,
+            advisories_path,
+        } => publish_advisories(base_path, &repo_name, &advisories_path)?,
+
+        RepoCommands::UpdatePolicy {
+            repo_name,
+            package_id,
+            policy,
+        } => update_policy(base_path, &repo_name, &package_id, policy.as_deref())?,
+
+        RepoCommands::Stats { repo_name } => {
+            stats(base_path, chunk_store_path, repo_name.as_deref())?;
         }
 
-        RepoCommands::Remove { repo_name } => {
-            fs::remove_dir_all(resolve_repo(base_path, &repo_name)?)?;
+        RepoCommands::Analyze {
+            repo_name,
+            threshold,
+        } => analyze(base_path, &repo_name, threshold)?,
+
+        RepoCommands::Export {
+            repo_name,
+            out_path,
+        } => export(base_path, chunk_store_path, &repo_name, &out_path)?,
+
+        RepoCommands::Pack {
+            repo_name,
+            out_path,
+        } => pack(base_path, chunk_store_path, &repo_name, &out_path)?,
+
+        RepoCommands::Unpack(args) => unpack(base_path, chunk_store_path, args)?,
+
+        RepoCommands::Verify { repo_name, json } => {
+            verify(
+                base_path,
+                chunk_store_path,
+                quicklaunch_path,
+                &repo_name,
+                json,
+            )?;
         }
 
-        RepoCommands::Update {
-            homepage_url,
-            license,
-            title,
-            version,
+        RepoCommands::Auth {
             repo_name,
-            mirrors,
+            url,
+            login,
+            remove,
+        } => auth(base_path, &repo_name, url.as_deref(), login, remove)?,
+
+        RepoCommands::Policy {
+            repo_name,
+            require_license,
+            spdx_license,
+            require_maintainers,
+            categories,
+            id_prefixes,
         } => {
             let repo_path = &resolve_repo(base_path, &repo_name)?;
-            let mut repo = read_manifest(repo_path)?;
+            let mut policy = get_metadata_policy(repo_path)?;
 
-            if title.is_some() {
-                repo.metadata.title = title;
+            if require_license.is_none()
+                && spdx_license.is_none()
+                && require_maintainers.is_none()
+                && categories.is_none()
+                && id_prefixes.is_none()
+            {
+                print!("{}", serde_yaml::to_string(&policy)?);
+                return Ok(());
             }
-            if homepage_url.is_some() {
-                repo.metadata.homepage_url = homepage_url;
+
+            if let Some(require_license) = require_license {
+                policy.require_license = require_license;
+            }
+            if let Some(spdx_license) = spdx_license {
+                policy.spdx_license = spdx_license;
             }
-            if license.is_some() {
-                repo.metadata.license = license;
+            if let Some(require_maintainers) = require_maintainers {
+                policy.require_maintainers = require_maintainers;
             }
-            if version.is_some() {
-                repo.metadata.version = version;
+            if let Some(categories) = categories {
+                policy.categories = categories
+                    .into_iter()
+                    .filter(|category| !category.is_empty())
+                    .collect();
             }
-            if let Some(mirrors) = mirrors {
-                repo.mirrors = mirrors
-                    .split(',')
-                    .map(std::string::ToString::to_string)
+            if let Some(id_prefixes) = id_prefixes {
+                policy.id_prefixes = id_prefixes
+                    .into_iter()
+                    .filter(|prefix| !prefix.is_empty())
                     .collect();
             }
+            set_metadata_policy(repo_path, &policy)?;
+        }
+
+        RepoCommands::Channel { command } => channel_commands(base_path, command)?,
+
+        RepoCommands::Mirror { command } => mirror_commands(base_path, command)?,
+
+        RepoCommands::Mirrors { command } => mirrors_commands(base_path, command)?,
+
+        RepoCommands::Keys { command } => keys_commands(base_path, command)?,
+    }
 
-            let manifest_serialized = &serde_yaml::to_string(&repo)?;
-            let signature = sign(repo_path, manifest_serialized, None)?;
+    Ok(())
+}
+
+fn update_repo(base_path: &Path, chunk_store_path: &Path, args: RepoUpdateArgs) -> Result<()> {
+    let repo_path = &resolve_repo(base_path, &args.repo_name)?;
+    let journal = Journal::begin(base_path, "repo update", Some(repo_path), None)?;
+    let mut repo = read_manifest(repo_path)?;
 
-            update_manifest(repo_path, manifest_serialized, &signature.to_bytes())?;
+    if args.title.is_some() {
+        repo.metadata.title = args.title;
+    }
+    if args.homepage_url.is_some() {
+        repo.metadata.homepage_url = args.homepage_url;
+    }
+    if args.license.is_some() {
+        repo.metadata.license = args.license;
+    }
+    if args.version.is_some() {
+        repo.metadata.version = args.version;
+    }
+    if args.min_client_edition.is_some() {
+        repo.min_client_edition = args.min_client_edition;
+    }
+    if let Some(edition) = args.edition {
+        if !SUPPORTED_EDITIONS.contains(&edition.as_str()) {
+            bail!(
+                "Unsupported edition {edition}, expected one of: {}",
+                SUPPORTED_EDITIONS.join(", ")
+            )
         }
+        repo.edition = edition;
+    }
+    if let Some(url) = args.binary_cache {
+        repo.binary_cache = Some(BinaryCache {
+            url,
+            public_key: args.binary_cache_key,
+        });
+    }
+    if let Some(url) = args.updates_url {
+        repo.updates_url = if url.is_empty() {
+            None
+        } else {
+            Some(normalize_mirror_url(&url)?)
+        };
+    }
+    migrate_chunk_sizes(&mut repo, chunk_store_path)?;
 
-        RepoCommands::RemovePackage {
+    resign_manifest(repo_path, &repo)?;
+    journal.commit()
+}
+
+/// Signs a changed manifest with the local key, and replaces the Repository's manifest with it.
+/// With external signing, writes a signing request for it instead
+fn resign_manifest(repo_path: &Path, repo: &RepoManifest) -> Result<()> {
+    let manifest_serialized = &serialize_manifest(repo_path, repo)?;
+
+    if let Some(request_path) = sign_manifest(repo_path, manifest_serialized, None)? {
+        signing_requested(&request_path);
+    }
+
+    Ok(())
+}
+
+/// Tells the user where a change waits to be signed, if the Repository uses external signing
+pub fn report_signing_request(repo_path: &Path) {
+    let request_path = repo_path.join(SIGNING_REQUEST_FILE);
+
+    if request_path.exists() {
+        signing_requested(&request_path);
+    }
+}
+
+#[cfg(feature = "network")]
+async fn include_feed(
+    base_path: &Path,
+    repo_name: &str,
+    name: &str,
+    url: &str,
+    public_key_path: Option<&Path>,
+) -> Result<()> {
+    use flintpkg::repo::{IncludedFeed, network::fetch_public_key};
+
+    let repo_path = &resolve_repo(base_path, repo_name)?;
+    let public_key = match public_key_path {
+        Some(public_key_path) => fs::read_to_string(public_key_path)?,
+        None => fetch_public_key(url.trim_end_matches('/')).await?,
+    };
+    let feed = IncludedFeed::new(name, url, &public_key)?;
+
+    let journal = Journal::begin(base_path, "repo include", Some(repo_path), None)?;
+    let mut repo = read_manifest(repo_path)?;
+
+    repo.includes.retain(|included| included.name != feed.name);
+    repo.includes.push(feed);
+
+    resign_manifest(repo_path, &repo)?;
+    journal.commit()
+}
+
+fn exclude_feed(base_path: &Path, repo_name: &str, name: &str) -> Result<()> {
+    let repo_path = &resolve_repo(base_path, repo_name)?;
+    let journal = Journal::begin(base_path, "repo exclude", Some(repo_path), None)?;
+    let mut repo = read_manifest(repo_path)?;
+
+    if !repo.includes.iter().any(|included| included.name == name) {
+        bail!("{repo_name} does not include {name}.");
+    }
+    repo.includes.retain(|included| included.name != name);
+
+    resign_manifest(repo_path, &repo)?;
+    journal.commit()
+}
+
+#[cfg(feature = "network")]
+async fn add_repo(
+    base_path: &Path,
+    quicklaunch_path: &Path,
+    args: crate::RepoAddArgs,
+) -> Result<()> {
+    use crate::log::{added_repo, cannot_update_repo, repo_preview, update_redirect};
+    use flintpkg::crypto::{key::key_fingerprint, pins::KeyPins};
+    use flintpkg::repo::network::{add_included_feeds, fetch_repository};
+    use flintpkg::repo::{
+        credentials::get_repo_auth, settings::RepoSettings, subscription::set_subscription,
+    };
+
+    let repo_name = &args.repo_name;
+    let remote_url = &args.remote_url;
+    let repo_path = &base_path.join(repo_name);
+    if repo_path.exists() {
+        bail!("A Repository named {repo_name} already exists.")
+    }
+
+    // Nothing is written until the user has seen what they are trusting
+    let fetched = fetch_repository(
+        remote_url,
+        None,
+        args.ignore_edition,
+        get_repo_auth(repo_name, None)?.as_ref(),
+    )
+    .await?;
+    if let Some(channel) = &args.channel
+        && !fetched.manifest.channels.contains_key(channel)
+    {
+        let channels: Vec<&str> = fetched
+            .manifest
+            .channels
+            .keys()
+            .map(String::as_str)
+            .collect();
+        bail!(
+            "{repo_name} has no channel {channel}. Channels: {}",
+            if channels.is_empty() {
+                "none".to_string()
+            } else {
+                channels.join(", ")
+            }
+        )
+    }
+
+    // Trust on first use: a url that served another key before must not swap it silently
+    let mut pins = KeyPins::read(None)?;
+    pins.accept_new_key = args.accept_new_key;
+    fetched.check_pin(&pins, remote_url)?;
+
+    repo_preview(
+        repo_name,
+        &fetched.manifest,
+        &key_fingerprint(&fetched.manifest.public_key)?,
+    );
+
+    if !args.yes && !prompter().confirm("Trust this Repository?", false)? {
+        return Ok(());
+    }
+
+    let journal = Journal::begin(base_path, "repo add", Some(repo_path), None)?;
+    fs::create_dir_all(repo_path)?;
+
+    let saved = args
+        .only
+        .as_deref()
+        .map_or(Ok(()), |only| {
+            set_subscription(repo_path, &parse_patterns(only))
+        })
+        .and_then(|()| {
+            args.channel.as_deref().map_or(Ok(()), |channel| {
+                set_settings(
+                    repo_path,
+                    &RepoSettings {
+                        channel: Some(channel.to_string()),
+                        ..RepoSettings::default()
+                    },
+                )
+            })
+        })
+        .and_then(|()| fetched.save(repo_path));
+    if let Err(err) = saved {
+        fs::remove_dir_all(repo_path)?;
+        return Err(err);
+    }
+    journal.commit()?;
+
+    let manifest = fetched.manifest;
+    pins.pin(remote_url, &manifest.public_key)?;
+    added_repo(repo_name, &manifest);
+
+    for (feed_repo_name, feed_manifest) in
+        add_included_feeds(repo_path, &manifest, args.ignore_edition).await?
+    {
+        added_repo(&feed_repo_name, &feed_manifest);
+    }
+
+    update_quicklaunch(base_path, quicklaunch_path)?;
+
+    if let Some(updates_source) = manifest.updates_source() {
+        if remote_url != updates_source {
+            update_redirect(repo_name, updates_source, remote_url);
+        }
+    } else {
+        cannot_update_repo(repo_name);
+    }
+
+    Ok(())
+}
+
+fn prune(base_path: &Path, chunk_store_path: &Path, repo_name: &str, keep: usize) -> Result<()> {
+    let repo_path = &resolve_repo(base_path, repo_name)?;
+
+    pruned_revisions(repo_name, prune_revisions(repo_path, keep)?);
+    clean_unused(base_path, chunk_store_path)
+}
+
+fn gc(
+    base_path: &Path,
+    chunk_store_path: &Path,
+    repo_name: &str,
+    chunks_dir: Option<&Path>,
+    dry_run: bool,
+) -> Result<()> {
+    let repo_path = &resolve_repo(base_path, repo_name)?;
+    let chunks_path = &chunks_dir.map_or_else(|| repo_path.join("chunks"), Path::to_path_buf);
+    if !chunks_path.is_dir() {
+        bail!(
+            "{} is not a directory, pass the chunks to collect with --chunks-dir.",
+            chunks_path.display()
+        )
+    }
+
+    let report = gc_repo_chunks(base_path, repo_path, chunks_path, chunk_store_path, dry_run)?;
+
+    let verb = if dry_run { "Would remove" } else { "Removed" };
+    for chunk in &report.removed {
+        println!("{verb} {chunk}");
+    }
+    println!(
+        "{verb} {} chunks ({}) from {}, {} still used.",
+        report.removed.len(),
+        format_size(report.freed),
+        chunks_path.display(),
+        report.kept
+    );
+
+    Ok(())
+}
+
+fn rotate(base_path: &Path, repo_name: &str, finish: bool) -> Result<()> {
+    let repo_path = &resolve_repo(base_path, repo_name)?;
+
+    if finish {
+        finish_key_rotation(repo_path, None)
+    } else {
+        rotated_key(repo_name, &rotate_key(repo_path, None)?);
+        Ok(())
+    }
+}
+
+fn export(
+    base_path: &Path,
+    chunk_store_path: &Path,
+    repo_name: &str,
+    out_path: &Path,
+) -> Result<()> {
+    let new_chunks = export_repo(
+        &resolve_repo(base_path, repo_name)?,
+        chunk_store_path,
+        out_path,
+    )?;
+    exported_repo(repo_name, out_path, new_chunks);
+
+    Ok(())
+}
+
+fn pack(base_path: &Path, chunk_store_path: &Path, repo_name: &str, out_path: &Path) -> Result<()> {
+    let chunks = pack_repo(
+        &resolve_repo(base_path, repo_name)?,
+        chunk_store_path,
+        out_path,
+    )?;
+    packed_repo(repo_name, out_path, chunks);
+
+    Ok(())
+}
+
+fn unpack(base_path: &Path, chunk_store_path: &Path, args: RepoUnpackArgs) -> Result<()> {
+    use crate::log::{added_repo, newer_edition_repo, updated_repo};
+    use flintpkg::crypto::key::deserialize_verifying_key;
+
+    let repo_path = &base_path.join(&args.repo_name);
+    let existed = repo_path.exists();
+    let verifying_key = args
+        .public_key
+        .map(|path| deserialize_verifying_key(&fs::read_to_string(path)?))
+        .transpose()?;
+
+    let journal = Journal::begin(base_path, "repo unpack", Some(repo_path), None)?;
+    let unpacked = unpack_repo(
+        &args.archive_path,
+        repo_path,
+        chunk_store_path,
+        verifying_key,
+        args.ignore_edition,
+    );
+
+    let manifest = match unpacked {
+        Ok(manifest) => manifest,
+        Err(err) => {
+            if !existed && repo_path.exists() {
+                fs::remove_dir_all(repo_path)?;
+            }
+            return Err(err);
+        }
+    };
+    journal.commit()?;
+
+    if existed {
+        updated_repo(args.repo_name.as_ref());
+        newer_edition_repo(&args.repo_name, &manifest);
+    } else {
+        added_repo(&args.repo_name, &manifest);
+    }
+
+    Ok(())
+}
+
+fn verify(
+    base_path: &Path,
+    chunk_store_path: &Path,
+    quicklaunch_path: &Path,
+    repo_name: &str,
+    json: bool,
+) -> Result<()> {
+    use crate::log::verify_progress;
+    use std::time::Instant;
+
+    let repo_path = &resolve_repo(base_path, repo_name)?;
+    let started = Instant::now();
+
+    let report = verify_repo(repo_path, chunk_store_path, quicklaunch_path, &|progress| {
+        // Roughly every percent, so the output keeps up with fast disks
+        if progress.done % (progress.total / 100).max(1) == 0 || progress.done == progress.total {
+            verify_progress(progress, started.elapsed());
+        }
+    })?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        let mut table = Table::new();
+        table.set_header(vec!["Check", "Status", "Problem"]);
+
+        for check in &report.checks {
+            table.add_row(vec![
+                check.name.as_str(),
+                if check.problem.is_some() {
+                    "FAIL"
+                } else {
+                    "PASS"
+                },
+                check.problem.as_deref().unwrap_or_default(),
+            ]);
+        }
+
+        println!("{table}");
+        println!("{} checks, {} failed", report.checks.len(), report.failed());
+    }
+
+    if report.failed() > 0 {
+        bail!("Repository {repo_name} failed verification");
+    }
+
+    Ok(())
+}
+
+fn remove_repo_package(
+    base_path: &Path,
+    chunk_store_path: &Path,
+    repo_name: &str,
+    package_id: &str,
+) -> Result<()> {
+    let repo_path = &resolve_repo(base_path, repo_name)?;
+    let journal = Journal::begin(
+        base_path,
+        "repo remove-package",
+        Some(repo_path),
+        Some(package_id),
+    )?;
+
+    remove_package(package_id, repo_path, None)?;
+    journal.commit()?;
+    report_signing_request(repo_path);
+    clean_unused(base_path, chunk_store_path)
+}
+
+/// Removes a Repository, detaching or removing its installed packages first
+pub fn remove_repo(base_path: &Path, repo_name: &str, keep_installed: bool) -> Result<()> {
+    let repo_path = resolve_repo(base_path, repo_name)?;
+
+    if keep_installed {
+        let detached_path = base_path
+            .parent()
+            .unwrap_or(base_path)
+            .join("detached")
+            .join(repo_name);
+
+        for package_id in detach_installed(&repo_path, &detached_path)? {
+            detached_package(&package_id, &detached_path.join(&package_id));
+        }
+    } else {
+        let installed: Vec<String> = get_installed(&repo_path)?
+            .into_iter()
+            .map(|install_meta| install_meta.package.id)
+            .collect();
+
+        if !installed.is_empty() {
+            removing_installed_packages(repo_name, &installed);
+
+            if !prompter().confirm("Remove anyway?", true)? {
+                return Ok(());
+            }
+        }
+    }
+
+    let mut journal = Journal::begin(base_path, "repo remove", Some(&repo_path), None)?;
+    journal.step(STEP_REMOVING_REPO)?;
+    fs::remove_dir_all(&repo_path)?;
+    journal.commit()?;
+
+    // A Repository added later under the same name must not inherit them
+    let repo_name = repo_path.file_name().unwrap_or_default().to_string_lossy();
+    set_repo_auth(&repo_name, None, None)
+}
+
+fn migrate(
+    base_path: &Path,
+    chunk_store_path: &Path,
+    repo_name: &str,
+    to: Option<&str>,
+    dry_run: bool,
+) -> Result<()> {
+    let repo_path = &resolve_repo(base_path, repo_name)?;
+    let journal = (!dry_run)
+        .then(|| Journal::begin(base_path, "repo migrate", Some(repo_path), None))
+        .transpose()?;
+
+    let report = migrate_repo(repo_path, chunk_store_path, to, dry_run, None)?;
+
+    if let Some(journal) = journal {
+        journal.commit()?;
+    }
+
+    if report.is_empty() {
+        println!("{repo_name} is already at edition {}.", report.to);
+        return Ok(());
+    }
+
+    let verb = if dry_run { "Would migrate" } else { "Migrated" };
+    println!(
+        "{verb} {repo_name} from edition {} to {}:",
+        report.from, report.to
+    );
+    for change in &report.changes {
+        println!("  {change}");
+    }
+    if report.chunk_sizes > 0 {
+        println!("  Record the exact size of {} chunks", report.chunk_sizes);
+    }
+
+    Ok(())
+}
+
+fn rename(
+    base_path: &Path,
+    quicklaunch_path: &Path,
+    repo_name: &str,
+    new_name: &str,
+) -> Result<()> {
+    let journal = Journal::begin(
+        base_path,
+        "repo rename",
+        Some(&resolve_repo(base_path, repo_name)?),
+        None,
+    )?;
+
+    for version in rename_repo(base_path, repo_name, new_name)? {
+        stale_image(&version);
+    }
+    rename_repo_auth(repo_name, new_name, None)?;
+
+    journal.commit()?;
+    update_quicklaunch(base_path, quicklaunch_path)
+}
+
+fn list_repos(base_path: &Path) -> Result<()> {
+    let mut table = Table::new();
+
+    table.set_header(vec![
+        "Name",
+        "Title",
+        "Priority",
+        "Hash Kind",
+        "Homepage",
+        "License",
+        "Version",
+    ]);
+
+    for repo_entry in fs::read_dir(base_path)? {
+        let repo_dir = repo_entry?;
+        let repo_name = repo_dir.file_name();
+        let repo_name_str = repo_name
+            .to_str()
+            .ok_or_else(|| anyhow!("Repository {} is not unicode.", repo_name.display()))?;
+
+        let repo = read_manifest(&repo_dir.path())?;
+
+        table.add_row(vec![
+            &repo_name_str,
+            repo.metadata.title.unwrap_or_default().as_str(),
+            &get_settings(&repo_dir.path())?.priority.to_string(),
+            &repo.hash_kind.to_string(),
+            &repo.metadata.homepage_url.unwrap_or_default(),
+            &repo.metadata.license.unwrap_or_default(),
+            &repo.metadata.version.unwrap_or_default(),
+        ]);
+    }
+
+    println!("{table}");
+
+    Ok(())
+}
+
+fn set_priority(base_path: &Path, repo_name: &str, priority: Option<i32>) -> Result<()> {
+    let repo_path = &resolve_repo(base_path, repo_name)?;
+    let mut settings = get_settings(repo_path)?;
+
+    if let Some(priority) = priority {
+        settings.priority = priority;
+        set_settings(repo_path, &settings)?;
+    } else {
+        println!("{}", settings.priority);
+    }
+
+    Ok(())
+}
+
+fn publish_advisories(base_path: &Path, repo_name: &str, advisories_path: &Path) -> Result<()> {
+    let repo_path = &resolve_repo(base_path, repo_name)?;
+    let mut repo = read_manifest(repo_path)?;
+
+    let advisories: Vec<Advisory> = serde_yaml::from_str(&fs::read_to_string(advisories_path)?)?;
+    for advisory in &advisories {
+        if !repo
+            .packages
+            .iter()
+            .any(|package| package.id == advisory.package)
+        {
+            bail!(
+                "Advisory {} is for {}, which is not in this Repository.",
+                advisory.id,
+                advisory.package
+            )
+        }
+    }
+
+    let journal = Journal::begin(base_path, "repo advisories", Some(repo_path), None)?;
+    repo.advisories = advisories;
+    resign_manifest(repo_path, &repo)?;
+    journal.commit()
+}
+
+fn update_policy(
+    base_path: &Path,
+    repo_name: &str,
+    package_id: &str,
+    policy: Option<&str>,
+) -> Result<()> {
+    let repo_path = &resolve_repo(base_path, repo_name)?;
+    let mut settings = get_settings(repo_path)?;
+
+    let Some(policy) = policy else {
+        println!("{}", settings.update_policy(package_id));
+        return Ok(());
+    };
+
+    let policy: UpdatePolicy = policy.parse()?;
+    if policy == UpdatePolicy::Any {
+        settings.update_policies.remove(package_id);
+    } else {
+        settings
+            .update_policies
+            .insert(package_id.to_string(), policy);
+    }
+
+    set_settings(repo_path, &settings)
+}
+
+fn stats(base_path: &Path, chunk_store_path: &Path, repo_name: Option<&str>) -> Result<()> {
+    let mut table = Table::new();
+
+    table.set_header(vec![
+        "Name",
+        "Packages",
+        "Chunks",
+        "Chunk Size",
+        "Installed",
+        "Installed Size",
+    ]);
+
+    let repo_paths = if let Some(repo_name) = repo_name {
+        vec![resolve_repo(base_path, repo_name)?]
+    } else {
+        fs::read_dir(base_path)?
+            .map(|entry| Ok(entry?.path()))
+            .collect::<Result<_>>()?
+    };
+
+    for repo_path in repo_paths {
+        let usage = repo_usage(&repo_path)?;
+
+        table.add_row(vec![
+            repo_path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string(),
+            usage.packages.to_string(),
+            usage.chunks.to_string(),
+            format_size(usage.chunk_bytes),
+            usage.installed_packages.to_string(),
+            format_size(usage.installed_bytes),
+        ]);
+    }
+
+    println!("{table}");
+
+    let store = store_usage(base_path, chunk_store_path)?;
+    println!(
+        "Chunk store: {} chunks, {}",
+        store.chunks,
+        format_size(store.bytes)
+    );
+    println!(
+        "Orphaned: {} chunks, {}",
+        store.orphaned_chunks,
+        format_size(store.orphaned_bytes)
+    );
+
+    Ok(())
+}
+
+fn analyze(base_path: &Path, repo_name: &str, threshold: u64) -> Result<()> {
+    let manifest = read_manifest(&resolve_repo(base_path, repo_name)?)?;
+    let analysis = analyze_repo(&manifest, threshold);
+
+    if analysis.overlaps.is_empty() {
+        println!("No packages share {threshold}% or more of their chunks.");
+        return Ok(());
+    }
+
+    let mut table = Table::new();
+    table.set_header(vec![
+        "Package",
+        "Package",
+        "Shared Chunks",
+        "Shared Size",
+        "Shared",
+    ]);
+    for overlap in &analysis.overlaps {
+        table.add_row(vec![
+            overlap.package.clone(),
+            overlap.other_package.clone(),
+            overlap.shared_chunks.to_string(),
+            format_size(overlap.shared_bytes),
+            format!("{}%", overlap.percent),
+        ]);
+    }
+    println!("{table}");
+
+    for cluster in &analysis.clusters {
+        if cluster.common_chunks == 0 {
+            continue;
+        }
+
+        println!(
+            "Candidate base package for {}: {} common chunks, {}",
+            cluster.packages.join(", "),
+            cluster.common_chunks,
+            format_size(cluster.common_bytes)
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "network")]
+async fn diff(base_path: &Path, repo_name: &str, ignore_edition: bool, json: bool) -> Result<()> {
+    use crate::log::newer_edition_repo;
+    use console::style;
+    use flintpkg::repo::{
+        diff::diff_manifests, network::fetch_remote_manifest, read_subscribed_manifest,
+    };
+
+    let repo_path = &resolve_repo(base_path, repo_name)?;
+    let remote = fetch_remote_manifest(repo_path, ignore_edition).await?;
+    newer_edition_repo(repo_name, &remote);
+    let diff = diff_manifests(&read_subscribed_manifest(repo_path)?, &remote);
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&diff)?);
+        return Ok(());
+    }
+
+    println!("Serial {} -> {}", diff.old_serial, diff.new_serial);
+    if diff.public_key_changed {
+        println!(
+            "{}",
+            style("The Repository is now signed with another key.").yellow()
+        );
+    }
+    if !diff.has_changes() {
+        println!("No packages changed.");
+        return Ok(());
+    }
+
+    let version = |version: &Option<String>| version.clone().unwrap_or_else(|| "?".into());
+    for id in &diff.added {
+        let package = remote.packages.iter().find(|package| &package.id == id);
+        println!(
+            "{} {id} {}",
+            style("+").green(),
+            version(&package.and_then(|package| package.metadata.version.clone()))
+        );
+    }
+    for id in &diff.removed {
+        println!("{} {id}", style("-").red());
+    }
+    for changed in &diff.changed {
+        if changed.old_version == changed.new_version {
+            let what = if changed.files_changed {
+                "rebuilt"
+            } else {
+                "metadata changed"
+            };
+            println!("{} {} ({what})", style("~").yellow(), changed.id);
+        } else {
+            println!(
+                "{} {} {} -> {}",
+                style("~").yellow(),
+                changed.id,
+                version(&changed.old_version),
+                version(&changed.new_version)
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn keys_commands(base_path: &Path, command: KeysCommands) -> Result<()> {
+    match command {
+        KeysCommands::Add {
             repo_name,
-            package_id,
+            public_key,
+        } => add_signing_key(
+            &resolve_repo(base_path, &repo_name)?,
+            &fs::read_to_string(public_key)?,
+            None,
+        )?,
+
+        KeysCommands::Remove {
+            repo_name,
+            public_key,
+        } => remove_signing_key(
+            &resolve_repo(base_path, &repo_name)?,
+            &fs::read_to_string(public_key)?,
+            None,
+        )?,
+
+        KeysCommands::List { repo_name } => {
+            let manifest = read_manifest(&resolve_repo(base_path, &repo_name)?)?;
+            let our_public_key = serialize_verifying_key(get_private_key(None)?.verifying_key())?;
+            let mut table = Table::new();
+
+            table.set_header(vec!["Key", "Role", "Local"]);
+
+            let mut add_row = |key: &str, role: &str| {
+                let local = if key == our_public_key { "Yes" } else { "" };
+                table.add_row(vec![key.trim(), role, local]);
+            };
+
+            add_row(&manifest.public_key, "Repository");
+            if let Some(previous_public_key) = &manifest.previous_public_key {
+                add_row(previous_public_key, "Rotating away from");
+            }
+            for key in &manifest.signing_keys {
+                add_row(key, "Maintainer");
+            }
+
+            println!("{table}");
+        }
+    }
+
+    Ok(())
+}
+
+fn auth(
+    base_path: &Path,
+    repo_name: &str,
+    url: Option<&str>,
+    login: Option<String>,
+    remove: bool,
+) -> Result<()> {
+    use flintpkg::repo::credentials::{AuthMethod, Credentials, RepoAuth};
+
+    if remove {
+        return set_repo_auth(repo_name, None, None);
+    }
+
+    // The url the Repository was added from, unless it isn't added yet
+    let url = match url {
+        Some(url) => url.to_string(),
+        None => resolve_repo(base_path, repo_name)
+            .and_then(|repo_path| read_manifest(&repo_path))
+            .ok()
+            .and_then(|manifest| manifest.updates_url)
+            .with_context(|| format!("Pass the url of {repo_name} with --url."))?,
+    };
+
+    let prompter = prompter();
+    let method = if let Some(login) = login {
+        let password = prompter.password(&format!("Password for {login}"))?;
+        AuthMethod::Basic(Credentials { login, password })
+    } else {
+        AuthMethod::Bearer(prompter.password("Token")?)
+    };
+
+    set_repo_auth(repo_name, Some(RepoAuth::new(&url, method)?), None)
+}
+
+fn channel_commands(base_path: &Path, command: ChannelCommands) -> Result<()> {
+    match command {
+        ChannelCommands::Set {
+            repo_name,
+            channel,
+            packages,
         } => {
-            remove_package(&package_id, &resolve_repo(base_path, &repo_name)?, None)?;
-            clean_unused(base_path, chunk_store_path)?;
+            let repo_path = &resolve_repo(base_path, &repo_name)?;
+
+            let journal = Journal::begin(base_path, "repo channel set", Some(repo_path), None)?;
+            let mut repo = read_manifest(repo_path)?;
+            set_channel(&mut repo, &channel, &packages)?;
+            resign_manifest(repo_path, &repo)?;
+            journal.commit()?;
+        }
+
+        ChannelCommands::Remove { repo_name, channel } => {
+            let repo_path = &resolve_repo(base_path, &repo_name)?;
+
+            let journal = Journal::begin(base_path, "repo channel remove", Some(repo_path), None)?;
+            let mut repo = read_manifest(repo_path)?;
+            remove_channel(&mut repo, &channel)?;
+            resign_manifest(repo_path, &repo)?;
+            journal.commit()?;
+        }
+
+        ChannelCommands::List { repo_name } => {
+            let repo_path = &resolve_repo(base_path, &repo_name)?;
+            let selected = get_settings(repo_path)?.channel;
+
+            let mut table = Table::new();
+            table.set_header(vec!["Channel", "Packages", "Selected"]);
+            for (channel, packages) in read_manifest(repo_path)?.channels {
+                let is_selected = selected.as_deref() == Some(channel.as_str());
+                table.add_row(vec![
+                    channel,
+                    packages.join(", "),
+                    if is_selected { "yes" } else { "" }.to_string(),
+                ]);
+            }
+            println!("{table}");
+        }
+
+        ChannelCommands::Select { repo_name, channel } => {
+            select_channel(&resolve_repo(base_path, &repo_name)?, channel.as_deref())?;
+        }
+    }
+
+    Ok(())
+}
+
+fn mirror_commands(base_path: &Path, command: MirrorCommands) -> Result<()> {
+    match command {
+        MirrorCommands::Add {
+            repo_name,
+            url,
+            priority,
+            weight,
+            region,
+        } => {
+            let repo_path = &resolve_repo(base_path, &repo_name)?;
+            let mirror = Mirror {
+                url: normalize_mirror_url(&url)?,
+                priority,
+                weight,
+                region,
+            };
+
+            let journal = Journal::begin(base_path, "repo mirror add", Some(repo_path), None)?;
+            let mut repo = read_manifest(repo_path)?;
+            add_repo_mirror(&mut repo, mirror);
+            resign_manifest(repo_path, &repo)?;
+            journal.commit()?;
+        }
+
+        MirrorCommands::Remove { repo_name, url } => {
+            let repo_path = &resolve_repo(base_path, &repo_name)?;
+
+            let journal = Journal::begin(base_path, "repo mirror remove", Some(repo_path), None)?;
+            let mut repo = read_manifest(repo_path)?;
+            remove_repo_mirror(&mut repo, &url)?;
+            resign_manifest(repo_path, &repo)?;
+            journal.commit()?;
+        }
+
+        MirrorCommands::List { repo_name } => {
+            let mut mirrors = read_manifest(&resolve_repo(base_path, &repo_name)?)?.mirrors;
+            mirrors.sort_by_key(|mirror| mirror.priority);
+
+            let mut table = Table::new();
+            table.set_header(vec!["Mirror", "Priority", "Weight", "Region"]);
+            for mirror in mirrors {
+                table.add_row(vec![
+                    mirror.url,
+                    mirror.priority.to_string(),
+                    mirror.weight.to_string(),
+                    mirror.region.unwrap_or_default(),
+                ]);
+            }
+
+            println!("{table}");
+        }
+    }
+
+    Ok(())
+}
+
+fn mirrors_commands(base_path: &Path, command: MirrorsCommands) -> Result<()> {
+    match command {
+        MirrorsCommands::Add { repo_name, url } => {
+            add_local_mirror(&resolve_repo(base_path, &repo_name)?, &url)?;
+        }
+
+        MirrorsCommands::Remove { repo_name, url } => {
+            remove_local_mirror(&resolve_repo(base_path, &repo_name)?, &url)?;
+        }
+
+        MirrorsCommands::List { repo_name } => {
+            let repo_path = &resolve_repo(base_path, &repo_name)?;
+            let mut table = Table::new();
+
+            table.set_header(vec!["Mirror", "Source", "Priority", "Weight", "Region"]);
+
+            for mirror in get_local_mirrors(repo_path)? {
+                table.add_row(vec![mirror.as_str(), "Local override", "", "", ""]);
+            }
+
+            let mut mirrors = read_manifest(repo_path)?.mirrors;
+            mirrors.sort_by_key(|mirror| mirror.priority);
+            for mirror in mirrors {
+                table.add_row(vec![
+                    mirror.url,
+                    "Repository".to_string(),
+                    mirror.priority.to_string(),
+                    mirror.weight.to_string(),
+                    mirror.region.unwrap_or_default(),
+                ]);
+            }
+
+            println!("{table}");
         }
     }
 
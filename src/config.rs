@@ -1,7 +1,163 @@
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 use directories::BaseDirs;
+use std::collections::BTreeMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+use crate::{
+    repo::image::ImageFormat,
+    run::env::{EnvPolicy, default_allow_list},
+};
+
+/// User configuration, read from `config.yml` in the config directory.
+/// Every field is optional, missing fields use their defaults.
+#[allow(clippy::struct_excessive_bools)]
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct Config {
+    pub maintenance: MaintenanceConfig,
+    /// Let user installs reuse chunks from the system-wide chunk store instead of downloading them
+    pub share_system_chunks: bool,
+    pub env: EnvConfig,
+    /// Region to prefer mirrors from, eg: `eu`
+    pub region: Option<String>,
+    /// Allow Flint to use the network. Without it, only chunks already downloaded can be installed
+    pub network: bool,
+    /// Store installed versions as compressed images instead of files, mounted when run.
+    /// Saves a lot of space, at the cost of slower first launches.
+    pub image_format: Option<ImageFormat>,
+    /// Where builds and extractions put temporary files, instead of the cache directory.
+    /// `FLINT_TMPDIR` overrides it
+    pub temp_dir: Option<PathBuf>,
+    /// Who owns system-wide installs, as `user`, `user:group` or `:group`. Defaults to whoever runs Flint
+    pub install_owner: Option<String>,
+    /// Link the shell completions installed packages ship into the directories bash, zsh and fish read them from
+    pub export_completions: bool,
+    /// Flush installed files to disk before switching to them, so a crash can't leave empty files behind.
+    /// Defaults to system-wide installs only. `--fast` skips it
+    pub sync_installs: Option<bool>,
+    /// Start a package `flint run` has to install as soon as its entrypoint's directory is downloaded,
+    /// finishing the install while it runs. Only for packages whose entrypoints need nothing else
+    pub launch_early: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            maintenance: MaintenanceConfig::default(),
+            share_system_chunks: true,
+            env: EnvConfig::default(),
+            region: None,
+            network: true,
+            image_format: None,
+            temp_dir: None,
+            install_owner: None,
+            export_completions: false,
+            sync_installs: None,
+            launch_early: false,
+        }
+    }
+}
+
+/// Which host environment variables packages see when run
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct EnvConfig {
+    /// Pass the whole host environment through
+    pub inherit: bool,
+    /// Variables to pass through on top of the defaults. A trailing `*` matches a prefix
+    pub allow: Vec<String>,
+    /// Overrides for single packages, by id
+    pub packages: BTreeMap<String, PackageEnvConfig>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct PackageEnvConfig {
+    /// Overrides the global `inherit`
+    pub inherit: Option<bool>,
+    /// Variables to pass through on top of the global ones
+    pub allow: Vec<String>,
+}
+
+impl EnvConfig {
+    /// The environment policy to start `package_id` with. `inherit` forces the whole environment through.
+    #[must_use]
+    pub fn policy(&self, package_id: &str, inherit: bool) -> EnvPolicy {
+        let package = self.packages.get(package_id);
+
+        if inherit
+            || package
+                .and_then(|package| package.inherit)
+                .unwrap_or(self.inherit)
+        {
+            return EnvPolicy::Inherit;
+        }
+
+        let mut allow = default_allow_list();
+        allow.extend(self.allow.iter().cloned());
+        if let Some(package) = package {
+            allow.extend(package.allow.iter().cloned());
+        }
+
+        EnvPolicy::Clean { allow }
+    }
+}
+
+/// Which tasks `flint maintenance` runs
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq, Eq)]
+#[allow(clippy::struct_excessive_bools)]
+#[serde(default)]
+pub struct MaintenanceConfig {
+    /// Remove chunks no Repository uses
+    pub gc: bool,
+    /// Verify all chunks, removing corrupted ones
+    pub scrub: bool,
+    /// Remove versions that are neither installed nor part of a kept generation
+    pub prune_versions: bool,
+    /// Remove stale build cache entries and interrupted downloads
+    pub evict_cache: bool,
+    /// How many of the latest generations to keep when pruning
+    pub keep_generations: usize,
+    /// How long an unused build cache entry is kept, in days
+    pub cache_max_age_days: u64,
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        Self {
+            gc: true,
+            scrub: true,
+            prune_versions: true,
+            evict_cache: true,
+            keep_generations: 5,
+            cache_max_age_days: 30,
+        }
+    }
+}
+
+/// Reads `config.yml` from `config_path`, or the default config directory.
+/// A missing file is the default config.
+///
+/// # Errors
+///
+/// - No valid home directory path could be retrieved from the operating system.
+/// - Invalid `config.yml`
+pub fn read_config(config_path: Option<&Path>) -> Result<Config> {
+    let config_dir = if let Some(config_path) = config_path {
+        config_path.to_path_buf()
+    } else {
+        get_config_dir()?
+    };
+    let path = config_dir.join("config.yml");
+
+    if !path.exists() {
+        return Ok(Config::default());
+    }
+
+    serde_yaml::from_str(&fs::read_to_string(&path)?)
+        .with_context(|| format!("Invalid config at {}", path.display()))
+}
 
 /// Gets the default/main configuration directory
 ///
@@ -85,6 +241,47 @@ pub fn get_system_chunks_dir() -> Result<PathBuf> {
     Ok(chunks_dir)
 }
 
+/// Gets the system chunk store to read from as a secondary store, when `chunk_store_path` is a
+/// different (user) store and `share_system_chunks` is enabled.
+///
+/// # Errors
+///
+/// - No valid home directory path could be retrieved from the operating system.
+/// - Invalid `config.yml`
+pub fn get_shared_chunks_dir(chunk_store_path: &Path) -> Result<Option<PathBuf>> {
+    let system_chunks_dir = get_system_data_dir().join("chunks");
+
+    if !system_chunks_dir.exists()
+        || chunk_store_path.starts_with(get_system_data_dir())
+        || !read_config(None)?.share_system_chunks
+    {
+        return Ok(None);
+    }
+
+    Ok(Some(system_chunks_dir))
+}
+
+/// Errors out if networking is unavailable, either because this build has no `network` feature,
+/// or because the config disables it.
+///
+/// # Errors
+///
+/// - Networking is disabled
+/// - Invalid `config.yml`
+pub fn require_network() -> Result<()> {
+    if !cfg!(feature = "network") {
+        bail!(
+            "This build of Flint has networking disabled. Rebuild it with the `network` feature to use this."
+        )
+    }
+
+    if !read_config(None)?.network {
+        bail!("Networking is disabled by `network: false` in config.yml.")
+    }
+
+    Ok(())
+}
+
 /// Gets the system-wide quicklaunch path
 ///
 /// # Errors
@@ -118,6 +315,26 @@ pub fn get_user_quicklaunch_dir() -> Result<PathBuf> {
     Ok(quicklaunch_dir)
 }
 
+/// Gets the directory shells read completions from, for the scope `quicklaunch_path` belongs to:
+/// `~/.local/share` for user installs, `/usr/local/share` for system-wide ones.
+///
+/// # Errors
+///
+/// - No valid home directory path could be retrieved from the operating system.
+pub fn get_completions_dir(quicklaunch_path: &Path) -> Result<PathBuf> {
+    if quicklaunch_path.starts_with(get_system_data_dir()) {
+        #[cfg(unix)]
+        return Ok(PathBuf::from("/usr/local/share"));
+
+        #[cfg(not(unix))]
+        return Ok(get_system_data_dir().join("share"));
+    }
+
+    let base_dirs = BaseDirs::new().context("Could not find user directories")?;
+
+    Ok(base_dirs.data_dir().to_path_buf())
+}
+
 /// Gets the build cache directory
 ///
 /// # Errors
@@ -138,7 +355,7 @@ pub fn get_build_cache_dir() -> Result<PathBuf> {
 
 #[must_use]
 /// Gets the SYSTEM-WIDE Repositorys path
-fn get_system_data_dir() -> PathBuf {
+pub fn get_system_data_dir() -> PathBuf {
     #[cfg(target_os = "linux")]
     {
         PathBuf::from("/var/lib/flint")
@@ -171,3 +388,45 @@ fn get_user_data_dir() -> Result<PathBuf> {
 
     Ok(data_dir)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use temp_dir::TempDir;
+
+    #[test]
+    fn test_partial_config() -> Result<()> {
+        let config_dir = TempDir::new()?;
+        assert_eq!(read_config(Some(config_dir.path()))?, Config::default());
+
+        fs::write(
+            config_dir.path().join("config.yml"),
+            "maintenance:\n  scrub: false\n",
+        )?;
+        let config = read_config(Some(config_dir.path()))?;
+
+        assert!(!config.maintenance.scrub);
+        assert!(config.maintenance.gc);
+        assert_eq!(config.maintenance.keep_generations, 5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_env_policy() -> Result<()> {
+        let config: EnvConfig = serde_yaml::from_str(
+            "allow: [PYTHONPATH]\npackages:\n  legacy:\n    inherit: true\n  game:\n    allow: [SDL_*]\n",
+        )?;
+
+        let policy = config.policy("game", false);
+        assert!(policy.allows("PYTHONPATH"));
+        assert!(policy.allows("SDL_VIDEODRIVER"));
+        assert!(!policy.allows("LD_PRELOAD"));
+        assert!(!config.policy("other", false).allows("SDL_VIDEODRIVER"));
+
+        assert_eq!(config.policy("legacy", false), EnvPolicy::Inherit);
+        assert_eq!(config.policy("other", true), EnvPolicy::Inherit);
+
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{Result, bail};
 use ed25519_dalek::{
     SigningKey, VerifyingKey,
     pkcs8::{
@@ -6,14 +6,14 @@ use ed25519_dalek::{
         spki::der::pem::LineEnding,
     },
 };
+use sha2::{Digest, Sha256};
 use std::{
     fs::{self, create_dir_all},
-    os::unix::fs::PermissionsExt,
     path::{Path, PathBuf},
 };
 
 use super::generate_signing_key;
-use crate::config::get_config_dir;
+use crate::{config::get_config_dir, utils::platform::set_mode};
 
 /// Returns private key, generating it if necessary
 pub fn get_private_key(config_path: Option<&Path>) -> Result<SigningKey> {
@@ -31,9 +31,7 @@ pub fn get_private_key(config_path: Option<&Path>) -> Result<SigningKey> {
             .map_err(|e| anyhow::anyhow!("failed to encode private key: {e}"))?;
         fs::write(&path, pem)?;
 
-        let mut perms = fs::metadata(&path)?.permissions();
-        perms.set_mode(0o600);
-        fs::set_permissions(&path, perms)?;
+        set_mode(&path, 0o600)?;
     }
 
     let pem_str = fs::read_to_string(&path)?;
@@ -43,6 +41,52 @@ pub fn get_private_key(config_path: Option<&Path>) -> Result<SigningKey> {
     Ok(key)
 }
 
+/// Returns the key the private key was last rotated away from, if it ever was
+///
+/// # Errors
+///
+/// - No valid config directory
+/// - Filesystem errors (Permissions)
+/// - Invalid private key
+pub fn get_previous_private_key(config_path: Option<&Path>) -> Result<Option<SigningKey>> {
+    let path = unwrap_config_path(config_path)?.join("id_ed25519.previous");
+
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let key = SigningKey::from_pkcs8_pem(&fs::read_to_string(path)?)
+        .map_err(|e| anyhow::anyhow!("failed to decode previous private key: {e}"))?;
+
+    Ok(Some(key))
+}
+
+/// Replaces the private key with a newly generated one, keeping the current one as the previous key.
+/// The private key is shared by every Repository signed on this machine.
+///
+/// # Errors
+///
+/// - A previous key is still kept from an earlier rotation
+/// - Filesystem errors (Permissions)
+pub fn rotate_private_key(config_path: Option<&Path>) -> Result<SigningKey> {
+    // Makes sure there is a current key to rotate away from
+    get_private_key(config_path)?;
+
+    let config_path = unwrap_config_path(config_path)?;
+    let previous_path = config_path.join("id_ed25519.previous");
+
+    if previous_path.exists() {
+        bail!(
+            "A previous key is still kept at {}. Remove it once no Repository is rotating away from it.",
+            previous_path.display()
+        )
+    }
+
+    fs::rename(config_path.join("id_ed25519"), previous_path)?;
+
+    get_private_key(Some(&config_path))
+}
+
 fn unwrap_config_path(config_path: Option<&Path>) -> Result<PathBuf> {
     let path = if let Some(config_path) = config_path {
         config_path.to_path_buf()
@@ -68,6 +112,23 @@ pub fn deserialize_verifying_key(verifying_key_serialized: &str) -> Result<Verif
     Ok(verifying_key)
 }
 
+/// A short fingerprint of a public key, to compare keys by eye: `SHA256:ab:cd:...`
+///
+/// # Errors
+///
+/// - Invalid public key
+pub fn key_fingerprint(verifying_key_serialized: &str) -> Result<String> {
+    let verifying_key = deserialize_verifying_key(verifying_key_serialized)?;
+    let digest = Sha256::digest(verifying_key.to_bytes());
+
+    let hex: Vec<String> = digest[..16]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+
+    Ok(format!("SHA256:{}", hex.join(":")))
+}
+
 #[cfg(test)]
 mod tests {
     use temp_dir::TempDir;
@@ -94,6 +155,22 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_key_fingerprint() -> Result<()> {
+        let temp = TempDir::new().unwrap();
+        let public_key =
+            serialize_verifying_key(get_private_key(Some(temp.path()))?.verifying_key())?;
+
+        let fingerprint = key_fingerprint(&public_key)?;
+        assert!(fingerprint.starts_with("SHA256:"));
+        assert_eq!(fingerprint.split(':').count(), 17);
+        assert_eq!(key_fingerprint(&public_key)?, fingerprint);
+
+        assert!(key_fingerprint("not a key").is_err());
+
+        Ok(())
+    }
+
     #[test]
     fn test_generate_private_key_permissions() -> Result<()> {
         let temp = TempDir::new().unwrap();
@@ -105,8 +182,33 @@ mod tests {
         // Generate new private key
         let _ = get_private_key(Some(path))?;
 
-        let permissions = fs::metadata(path.join("id_ed25519"))?.permissions().mode();
-        assert_eq!(permissions & 0o777, 0o600);
+        let permissions = crate::utils::platform::mode(&fs::metadata(path.join("id_ed25519"))?);
+        assert_eq!(permissions, 0o600);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rotate_private_key() -> Result<()> {
+        let temp = TempDir::new()?;
+        let config_dir = temp.path();
+
+        assert!(get_previous_private_key(Some(config_dir))?.is_none());
+        let old_key = get_private_key(Some(config_dir))?;
+
+        let new_key = rotate_private_key(Some(config_dir))?;
+        assert_ne!(new_key.to_bytes(), old_key.to_bytes());
+        assert_eq!(
+            get_private_key(Some(config_dir))?.to_bytes(),
+            new_key.to_bytes()
+        );
+        assert_eq!(
+            get_previous_private_key(Some(config_dir))?.map(|key| key.to_bytes()),
+            Some(old_key.to_bytes())
+        );
+
+        // The previous key would be lost
+        assert!(rotate_private_key(Some(config_dir)).is_err());
 
         Ok(())
     }
@@ -1,6 +1,7 @@
 use ed25519_dalek::{SecretKey, SigningKey};
 
 pub mod key;
+pub mod pins;
 pub mod signing;
 
 fn generate_signing_key() -> SigningKey {
@@ -0,0 +1,192 @@
+use anyhow::{Context, Result, bail};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    config::get_config_dir,
+    crypto::{
+        key::{deserialize_verifying_key, key_fingerprint},
+        signing::verify_signature,
+    },
+    repo::{RepoManifest, manifest_io::atomic_replace},
+};
+
+/// Keys of remote Repositories, by the url they were first fetched from. Kept in the config directory.
+const PINS_FILE: &str = "pinned_keys.yml";
+
+/// Trust on first use for remote Repositories: the key a url served first is pinned,
+/// and a different key from that url is refused until the user accepts it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyPins {
+    path: PathBuf,
+    pins: BTreeMap<String, String>,
+    /// Replace a pin with whatever key the url serves now, instead of refusing it
+    pub accept_new_key: bool,
+}
+
+impl KeyPins {
+    /// Reads the pins in `config_path`, or the default config directory.
+    /// A missing file has no pins.
+    ///
+    /// # Errors
+    ///
+    /// - No valid home directory path could be retrieved from the operating system.
+    /// - Invalid pins file
+    pub fn read(config_path: Option<&Path>) -> Result<Self> {
+        let config_dir = if let Some(config_path) = config_path {
+            config_path.to_path_buf()
+        } else {
+            get_config_dir()?
+        };
+        let path = config_dir.join(PINS_FILE);
+
+        let pins = if path.exists() {
+            serde_yaml::from_str(&std::fs::read_to_string(&path)?)
+                .with_context(|| format!("Invalid pinned keys at {}", path.display()))?
+        } else {
+            BTreeMap::new()
+        };
+
+        Ok(Self {
+            path,
+            pins,
+            accept_new_key: false,
+        })
+    }
+
+    /// The key pinned for `url`, if any
+    #[must_use]
+    pub fn get(&self, url: &str) -> Option<&str> {
+        self.pins.get(normalize_url(url)).map(String::as_str)
+    }
+
+    /// Errors out if a manifest fetched from `url` may not be trusted under the key pinned for it.
+    ///
+    /// A new key is allowed when the pinned key signed the manifest too, as during a key rotation,
+    /// or with `accept_new_key`. Urls without a pin allow any key.
+    ///
+    /// # Errors
+    ///
+    /// - The manifest is signed with another key than the pinned one
+    /// - Invalid keys
+    pub fn check(
+        &self,
+        url: &str,
+        manifest: &RepoManifest,
+        raw_manifest: &[u8],
+        signature: &[u8],
+    ) -> Result<()> {
+        let Some(pinned) = self.get(url) else {
+            return Ok(());
+        };
+
+        // Compare the keys themselves, PEM formatting may differ
+        let pinned_key = deserialize_verifying_key(pinned)?;
+        if self.accept_new_key
+            || deserialize_verifying_key(&manifest.public_key)? == pinned_key
+            || verify_signature(raw_manifest, signature, pinned_key).is_ok()
+        {
+            return Ok(());
+        }
+
+        bail!(
+            "{url} is now signed with {}, but {} was pinned when it was first added. \
+            If the Repository really moved to a new key, run again with --accept-new-key.",
+            key_fingerprint(&manifest.public_key)?,
+            key_fingerprint(pinned)?
+        )
+    }
+
+    /// Pins `public_key` for `url`, replacing any earlier pin, and saves the pins.
+    ///
+    /// # Errors
+    ///
+    /// - Filesystem errors (Permissions)
+    pub fn pin(&mut self, url: &str, public_key: &str) -> Result<()> {
+        if self.get(url) == Some(public_key) {
+            return Ok(());
+        }
+
+        self.pins
+            .insert(normalize_url(url).to_string(), public_key.to_string());
+
+        let (Some(dir), Some(filename)) = (self.path.parent(), self.path.file_name()) else {
+            bail!("Invalid pinned keys path {}", self.path.display())
+        };
+        atomic_replace(
+            dir,
+            &filename.to_string_lossy(),
+            serde_yaml::to_string(&self.pins)?.as_bytes(),
+        )
+    }
+}
+
+/// `https://example.com/repo/` and `https://example.com/repo` are the same Repository
+fn normalize_url(url: &str) -> &str {
+    url.trim_end_matches('/')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        crypto::signing::sign_detached,
+        repo::{create_repo, read_manifest},
+    };
+    use std::fs;
+    use temp_dir::TempDir;
+
+    #[test]
+    fn test_key_pins() -> Result<()> {
+        let root = TempDir::new()?;
+        let config = &root.path().join("config");
+        let repo_path = &root.path().join("repo");
+        fs::create_dir(config)?;
+        create_repo(repo_path, Some(repo_path))?;
+
+        let manifest = read_manifest(repo_path)?;
+        let raw_manifest = fs::read(repo_path.join("manifest.yml"))?;
+        let signature = fs::read(repo_path.join("manifest.yml.sig"))?;
+        let url = "https://example.com/repo";
+
+        let mut pins = KeyPins::read(Some(config))?;
+        pins.check(url, &manifest, &raw_manifest, &signature)?;
+        pins.pin(url, &manifest.public_key)?;
+
+        let mut pins = KeyPins::read(Some(config))?;
+        assert_eq!(
+            pins.get("https://example.com/repo/"),
+            Some(&*manifest.public_key)
+        );
+        pins.check(url, &manifest, &raw_manifest, &signature)?;
+
+        // Another Repository, with its own key, served from the same url
+        let other_path = &root.path().join("other");
+        let other_key = &root.path().join("other_key");
+        fs::create_dir(other_key)?;
+        create_repo(other_path, Some(other_key))?;
+        let other = read_manifest(other_path)?;
+        let other_raw = fs::read(other_path.join("manifest.yml"))?;
+        let other_signature = fs::read(other_path.join("manifest.yml.sig"))?;
+        assert!(
+            pins.check(url, &other, &other_raw, &other_signature)
+                .is_err()
+        );
+
+        // Still signed by the pinned key, as mid key rotation
+        let rotating_signature = sign_detached(&other_raw, Some(repo_path))?;
+        pins.check(url, &other, &other_raw, &rotating_signature.to_bytes())?;
+
+        pins.accept_new_key = true;
+        pins.check(url, &other, &other_raw, &other_signature)?;
+        pins.pin(url, &other.public_key)?;
+        assert_eq!(
+            KeyPins::read(Some(config))?.get(url),
+            Some(&*other.public_key)
+        );
+
+        Ok(())
+    }
+}
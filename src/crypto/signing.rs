@@ -1,42 +1,160 @@
-use anyhow::Result;
-use ed25519_dalek::{Signature, VerifyingKey, ed25519::signature::Signer};
-use std::{fs, path::Path};
+use anyhow::{Result, bail};
+use ed25519_dalek::{Signature, SigningKey, VerifyingKey, ed25519::signature::Signer};
+use std::{collections::BTreeMap, path::Path};
+
+use crate::{
+    crypto::key::{
+        deserialize_verifying_key, get_previous_private_key, get_private_key,
+        serialize_verifying_key,
+    },
+    repo::{
+        edition::{publishes_binary_manifest, raw_edition},
+        manifest_io::{ManifestFormat, encode_binary_manifest},
+    },
+};
+
+/// The signature files of a manifest, by filename, eg: `manifest.yml.sig`
+pub type ManifestSignatures = BTreeMap<String, Vec<u8>>;
+
+/// Signs a manifest with the local key, without writing anything to the filesystem.
+///
+/// While the manifest is rotating away from a `previous_public_key`, `manifest.yml.sig` is signed with
+/// that key so clients only trusting it can follow, and `manifest.yml.sig.next` with the current one.
+///
+/// Manifests of an edition with binary manifests also get `manifest.cbor`'s signatures, made the same way.
+/// Write them with [`update_manifest_signed`](crate::repo::manifest_io::update_manifest_signed).
+///
+/// # Errors
+///
+/// - Private key could not be read or generated
+/// - Invalid manifest
+pub fn sign(manifest_serialized: &str, config_path: Option<&Path>) -> Result<ManifestSignatures> {
+    let previous_key = rotating_from(manifest_serialized, config_path)?;
+    let mut signatures = ManifestSignatures::new();
+
+    add_signatures(
+        &mut signatures,
+        ManifestFormat::Yaml,
+        manifest_serialized.as_bytes(),
+        previous_key.as_ref(),
+        config_path,
+    )?;
+
+    if publishes_binary(manifest_serialized) {
+        add_signatures(
+            &mut signatures,
+            ManifestFormat::Cbor,
+            &encode_binary_manifest(manifest_serialized)?,
+            previous_key.as_ref(),
+            config_path,
+        )?;
+    }
+
+    Ok(signatures)
+}
+
+/// Whether the manifest's edition also publishes `manifest.cbor`
+fn publishes_binary(manifest_serialized: &str) -> bool {
+    serde_yaml::from_str(manifest_serialized)
+        .ok()
+        .and_then(|manifest| raw_edition(&manifest))
+        .is_some_and(|edition| publishes_binary_manifest(&edition))
+}
 
-use crate::crypto::key::get_private_key;
+/// Signs one encoding of the manifest, see [`sign`].
+fn add_signatures(
+    signatures: &mut ManifestSignatures,
+    format: ManifestFormat,
+    data: &[u8],
+    previous_key: Option<&SigningKey>,
+    config_path: Option<&Path>,
+) -> Result<()> {
+    let signature = sign_detached(data, config_path)?.to_bytes().to_vec();
+
+    if let Some(previous_key) = previous_key {
+        signatures.insert(format.next_signature_filename(), signature);
+        signatures.insert(
+            format.signature_filename().to_string(),
+            previous_key.sign(data).to_bytes().to_vec(),
+        );
+    } else {
+        signatures.insert(format.signature_filename().to_string(), signature);
+    }
+
+    Ok(())
+}
 
-/// Signs and inserts the signature into the filesystem.
-pub fn sign(
-    repo_path: &Path,
+/// The previous private key, if the manifest is rotating away from it.
+/// Other maintainers signing mid rotation don't have it, and only sign with their own key.
+fn rotating_from(
     manifest_serialized: &str,
     config_path: Option<&Path>,
-) -> Result<Signature> {
-    let signing_key = get_private_key(config_path)?;
-    let signature = signing_key.sign(manifest_serialized.as_bytes());
+) -> Result<Option<SigningKey>> {
+    let Ok(manifest) = serde_yaml::from_str::<serde_yaml::Value>(manifest_serialized) else {
+        return Ok(None);
+    };
+    let Some(previous_public_key) = manifest
+        .get("previous_public_key")
+        .and_then(serde_yaml::Value::as_str)
+    else {
+        return Ok(None);
+    };
+
+    match get_previous_private_key(config_path)? {
+        Some(key) if serialize_verifying_key(key.verifying_key())? == previous_public_key => {
+            Ok(Some(key))
+        }
+        _ => Ok(None),
+    }
+}
 
-    fs::write(repo_path.join("manifest.yml.sig"), signature.to_bytes())?;
+/// Signs arbitrary data with the local key, without writing anything to the filesystem.
+///
+/// # Errors
+///
+/// - Private key could not be read or generated
+pub fn sign_detached(data: impl AsRef<[u8]>, config_path: Option<&Path>) -> Result<Signature> {
+    let signing_key = get_private_key(config_path)?;
+    let signature = signing_key.sign(data.as_ref());
 
-    verify_signature(
-        manifest_serialized,
-        &signature.to_bytes(),
-        signing_key.verifying_key(),
-    )?;
+    verify_signature(&data, &signature.to_bytes(), signing_key.verifying_key())?;
 
     Ok(signature)
 }
 
 /// Verifies the signature, and errors out if its incorrect
 pub fn verify_signature(
-    manifest_serialized: &str,
+    manifest_serialized: impl AsRef<[u8]>,
     signature: &[u8],
     verifying_key: VerifyingKey,
 ) -> Result<()> {
     let signature = Signature::try_from(signature)?;
 
-    verifying_key.verify_strict(manifest_serialized.as_bytes(), &signature)?;
+    verifying_key.verify_strict(manifest_serialized.as_ref(), &signature)?;
 
     Ok(())
 }
 
+/// Verifies the signature against each of `public_keys`, succeeding if any of them made it.
+///
+/// # Errors
+///
+/// - None of `public_keys` made the signature
+/// - Invalid public key
+pub fn verify_signature_any(
+    data: impl AsRef<[u8]>,
+    signature: &[u8],
+    public_keys: &[&str],
+) -> Result<()> {
+    for public_key in public_keys {
+        if verify_signature(&data, signature, deserialize_verifying_key(public_key)?).is_ok() {
+            return Ok(());
+        }
+    }
+
+    bail!("Signature is not from any trusted key.")
+}
+
 #[cfg(test)]
 mod tests {
     use super::super::generate_signing_key;
@@ -0,0 +1,531 @@
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::{
+    fs,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream, unix::OwnedWriteHalf},
+    sync::{Mutex, broadcast},
+};
+
+use crate::{
+    chunks::InstallStats,
+    repo::{
+        InstallMeta, PackageManifest, get_all_packages, get_package,
+        installed::{get_installed, read_install_meta},
+        read_subscribed_manifest,
+    },
+    utils::{choose_package, prompt::NonInteractive, resolve_repo},
+};
+
+/// Invalid JSON
+pub const PARSE_ERROR: i64 = -32700;
+/// Valid JSON, but not a JSON-RPC 2.0 request
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+/// The method ran, but failed
+pub const OPERATION_FAILED: i64 = -32000;
+
+/// A JSON-RPC 2.0 error
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    #[must_use]
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<anyhow::Error> for RpcError {
+    fn from(err: anyhow::Error) -> Self {
+        Self::new(OPERATION_FAILED, format!("{err:#}"))
+    }
+}
+
+impl From<serde_json::Error> for RpcError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::new(INVALID_PARAMS, err.to_string())
+    }
+}
+
+/// Something that changed, sent to every connection that called `subscribe`
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Event {
+    Installed {
+        repo: String,
+        package: String,
+        /// What was downloaded, and what the chunk store already had
+        stats: InstallStats,
+    },
+    Removed {
+        repo: String,
+        package: String,
+    },
+    Updated,
+}
+
+/// Runs the methods of the daemon. `subscribe` is handled by the daemon itself.
+pub trait Handler: Send + Sync + 'static {
+    /// Runs `method`, sending an [`Event`] for everything it changes.
+    fn call(
+        &self,
+        method: &str,
+        params: Value,
+        events: &broadcast::Sender<Event>,
+    ) -> impl Future<Output = Result<Value, RpcError>> + Send;
+
+    /// Told about a connection that failed, eg: a client that went away mid-response.
+    /// The daemon keeps serving the others either way.
+    fn connection_failed(&self, _err: &anyhow::Error) {}
+}
+
+#[derive(Deserialize)]
+struct Request {
+    jsonrpc: String,
+    /// Notifications have no id, and get no response
+    #[serde(default)]
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+/// Listens on a unix socket for JSON-RPC 2.0 requests, one JSON object per line.
+/// Methods run one at a time, so two clients can't install into the same Repository at once.
+///
+/// # Errors
+///
+/// - Another daemon is already listening on `socket_path`
+/// - Could not bind to `socket_path`
+pub async fn run_daemon<H: Handler>(socket_path: &Path, handler: H) -> Result<()> {
+    if socket_path.exists() {
+        if UnixStream::connect(socket_path).await.is_ok() {
+            bail!("A daemon is already listening on {}", socket_path.display())
+        }
+
+        // Left behind by a daemon that didn't shut down cleanly
+        fs::remove_file(socket_path)?;
+    }
+
+    let listener = UnixListener::bind(socket_path)?;
+    // Only the user may install and remove packages through it
+    fs::set_permissions(socket_path, fs::Permissions::from_mode(0o600))?;
+
+    let handler = Arc::new(handler);
+    let lock = Arc::new(Mutex::new(()));
+    let (events, _) = broadcast::channel(64);
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let handler = handler.clone();
+        let lock = lock.clone();
+        let events = events.clone();
+
+        tokio::spawn(async move {
+            if let Err(err) = handle_connection(stream, &*handler, &lock, &events).await {
+                handler.connection_failed(&err);
+            }
+        });
+    }
+}
+
+async fn handle_connection<H: Handler>(
+    stream: UnixStream,
+    handler: &H,
+    lock: &Mutex<()>,
+    events: &broadcast::Sender<Event>,
+) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    let mut subscription = None;
+
+    loop {
+        tokio::select! {
+            line = lines.next_line() => {
+                let Some(line) = line? else {
+                    return Ok(());
+                };
+
+                if line.trim().is_empty() {
+                    continue;
+                }
+
+                if let Some(response) =
+                    respond(&line, handler, lock, events, &mut subscription).await
+                {
+                    write_line(&mut writer, &response).await?;
+                }
+            }
+            Some(event) = next_event(&mut subscription) => {
+                let notification = json!({ "jsonrpc": "2.0", "method": "event", "params": event });
+                write_line(&mut writer, &notification).await?;
+            }
+        }
+    }
+}
+
+/// Handles one line, returning the response to send back, if any.
+async fn respond<H: Handler>(
+    line: &str,
+    handler: &H,
+    lock: &Mutex<()>,
+    events: &broadcast::Sender<Event>,
+    subscription: &mut Option<broadcast::Receiver<Event>>,
+) -> Option<Value> {
+    let request: Request = match serde_json::from_str::<Value>(line) {
+        Err(err) => {
+            return Some(response(
+                None,
+                Err(RpcError::new(PARSE_ERROR, err.to_string())),
+            ));
+        }
+        Ok(value) => match serde_json::from_value(value) {
+            Ok(request) => request,
+            Err(err) => {
+                return Some(response(
+                    None,
+                    Err(RpcError::new(INVALID_REQUEST, err.to_string())),
+                ));
+            }
+        },
+    };
+
+    let result = if request.jsonrpc != "2.0" {
+        Err(RpcError::new(
+            INVALID_REQUEST,
+            "Only JSON-RPC 2.0 is supported",
+        ))
+    } else if request.method == "subscribe" {
+        *subscription = Some(events.subscribe());
+        Ok(Value::Bool(true))
+    } else {
+        let _guard = lock.lock().await;
+        handler.call(&request.method, request.params, events).await
+    };
+
+    request.id.as_ref().map(|id| response(Some(id), result))
+}
+
+/// The next event of a subscription, never resolving without one
+async fn next_event(subscription: &mut Option<broadcast::Receiver<Event>>) -> Option<Event> {
+    let Some(receiver) = subscription else {
+        return std::future::pending().await;
+    };
+
+    loop {
+        match receiver.recv().await {
+            Ok(event) => return Some(event),
+            // Slow clients miss events rather than holding everyone up
+            Err(broadcast::error::RecvError::Lagged(_)) => {}
+            Err(broadcast::error::RecvError::Closed) => return std::future::pending().await,
+        }
+    }
+}
+
+/// A JSON-RPC 2.0 response to the request with `id`
+fn response(id: Option<&Value>, result: Result<Value, RpcError>) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(error) => json!({ "jsonrpc": "2.0", "id": id, "error": error }),
+    }
+}
+
+async fn write_line(writer: &mut OwnedWriteHalf, value: &Value) -> Result<()> {
+    let mut line = serde_json::to_vec(value)?;
+    line.push(b'\n');
+    writer.write_all(&line).await?;
+
+    Ok(())
+}
+
+/// A package, as returned by `list`
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct PackageStatus {
+    pub repo: String,
+    pub id: String,
+    pub title: Option<String>,
+    pub version: Option<String>,
+    pub installed: bool,
+    /// Installed, and the Repository has a different build
+    pub update_available: bool,
+}
+
+/// Lists the packages of every Repository, and whether they are installed.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+/// - A Repository contains invalid data/signature
+pub fn list_packages(repos_path: &Path) -> Result<Vec<PackageStatus>> {
+    let mut statuses = Vec::new();
+
+    for entry in fs::read_dir(repos_path)? {
+        let repo_path = entry?.path();
+        let repo = repo_path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        let installed = get_installed(&repo_path)?;
+
+        for package in get_all_packages(&repo_path)? {
+            let install_meta = installed
+                .iter()
+                .find(|install_meta| install_meta.package.id == package.id);
+
+            statuses.push(PackageStatus {
+                repo: repo.clone(),
+                id: package.id.clone(),
+                title: package.metadata.title.clone(),
+                version: package.metadata.version.clone(),
+                installed: install_meta.is_some(),
+                update_available: install_meta.is_some_and(|install_meta| {
+                    !install_meta.dev_install && install_meta.package != package
+                }),
+            });
+        }
+    }
+
+    statuses.sort_by(|a, b| (&a.repo, &a.id).cmp(&(&b.repo, &b.id)));
+
+    Ok(statuses)
+}
+
+/// A package, as returned by `info`
+#[derive(Serialize, Debug, Clone)]
+pub struct PackageInfo {
+    pub repo: String,
+    pub repo_path: PathBuf,
+    pub package: PackageManifest,
+    pub installed: Option<InstallMeta>,
+}
+
+/// Looks a package up in `repo`, or whichever Repository has it.
+/// Never prompts, ambiguous packages without a clear priority are an error.
+///
+/// # Errors
+///
+/// - No such package or Repository
+/// - A Repository contains invalid data/signature
+pub fn package_info(
+    repos_path: &Path,
+    repo: Option<&str>,
+    package_id: &str,
+) -> Result<PackageInfo> {
+    let (repo_path, package) = if let Some(repo) = repo {
+        let repo_path = resolve_repo(repos_path, repo)?;
+        let package = get_package(&read_subscribed_manifest(&repo_path)?, package_id)?;
+        (repo_path, package)
+    } else {
+        choose_package(repos_path, package_id, |_| true, &NonInteractive)?
+    };
+
+    Ok(PackageInfo {
+        repo: repo_path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string(),
+        installed: read_install_meta(&repo_path, &package.id)?,
+        repo_path,
+        package,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::{Metadata, create_repo, insert_package};
+    use temp_dir::TempDir;
+    use tokio::{
+        io::Lines,
+        net::unix::{OwnedReadHalf, OwnedWriteHalf},
+    };
+
+    struct TestHandler;
+
+    impl Handler for TestHandler {
+        async fn call(
+            &self,
+            method: &str,
+            params: Value,
+            events: &broadcast::Sender<Event>,
+        ) -> Result<Value, RpcError> {
+            match method {
+                "echo" => Ok(params),
+                "install" => {
+                    let _ = events.send(Event::Installed {
+                        repo: "main".into(),
+                        package: "hello".into(),
+                        stats: InstallStats::default(),
+                    });
+                    Ok(Value::Null)
+                }
+                "fail" => Err(anyhow::anyhow!("Nope").into()),
+                _ => Err(RpcError::new(METHOD_NOT_FOUND, method)),
+            }
+        }
+    }
+
+    async fn read(lines: &mut Lines<BufReader<OwnedReadHalf>>) -> Result<Value> {
+        Ok(serde_json::from_str(
+            &lines.next_line().await?.unwrap_or_default(),
+        )?)
+    }
+
+    async fn call(
+        writer: &mut OwnedWriteHalf,
+        lines: &mut Lines<BufReader<OwnedReadHalf>>,
+        request: &str,
+    ) -> Result<Value> {
+        writer.write_all(format!("{request}\n").as_bytes()).await?;
+        read(lines).await
+    }
+
+    #[tokio::test]
+    async fn test_daemon() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let socket_path = temp_dir.path().join("flint.sock");
+
+        let daemon_socket_path = socket_path.clone();
+        tokio::spawn(async move { run_daemon(&daemon_socket_path, TestHandler).await });
+        while UnixStream::connect(&socket_path).await.is_err() {
+            tokio::task::yield_now().await;
+        }
+
+        let (reader, mut writer) = UnixStream::connect(&socket_path).await?.into_split();
+        let writer = &mut writer;
+        let lines = &mut BufReader::new(reader).lines();
+
+        assert_eq!(
+            call(
+                writer,
+                lines,
+                r#"{"jsonrpc":"2.0","id":1,"method":"echo","params":[1]}"#
+            )
+            .await?,
+            json!({ "jsonrpc": "2.0", "id": 1, "result": [1] })
+        );
+
+        let failed = call(writer, lines, r#"{"jsonrpc":"2.0","id":2,"method":"fail"}"#).await?;
+        assert_eq!(failed["error"]["code"], OPERATION_FAILED);
+        assert_eq!(failed["error"]["message"], "Nope");
+
+        let missing = call(
+            writer,
+            lines,
+            r#"{"jsonrpc":"2.0","id":3,"method":"missing"}"#,
+        )
+        .await?;
+        assert_eq!(missing["error"]["code"], METHOD_NOT_FOUND);
+
+        assert_eq!(
+            call(writer, lines, "{").await?["error"]["code"],
+            PARSE_ERROR
+        );
+        assert_eq!(
+            call(writer, lines, r#"{"jsonrpc":"1.0","id":4,"method":"echo"}"#).await?["error"]["code"],
+            INVALID_REQUEST
+        );
+
+        // Notifications get no response, the next line answers the next request
+        writer
+            .write_all(b"{\"jsonrpc\":\"2.0\",\"method\":\"echo\"}\n")
+            .await?;
+
+        // Subscribers are sent events after the response that caused them
+        assert_eq!(
+            call(
+                writer,
+                lines,
+                r#"{"jsonrpc":"2.0","id":5,"method":"subscribe"}"#
+            )
+            .await?["result"],
+            true
+        );
+        assert_eq!(
+            call(
+                writer,
+                lines,
+                r#"{"jsonrpc":"2.0","id":6,"method":"install"}"#
+            )
+            .await?["id"],
+            6
+        );
+        assert_eq!(
+            read(lines).await?,
+            json!({
+                "jsonrpc": "2.0",
+                "method": "event",
+                "params": {
+                    "kind": "installed",
+                    "repo": "main",
+                    "package": "hello",
+                    "stats": {
+                        "chunks_fetched": 0,
+                        "bytes_downloaded": 0,
+                        "chunks_reused": 0,
+                        "bytes_reused": 0,
+                        "mirrors": {},
+                    },
+                },
+            })
+        );
+
+        assert!(run_daemon(&socket_path, TestHandler).await.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_packages() -> Result<()> {
+        let repos = TempDir::new()?;
+        let config = TempDir::new()?;
+        let repo_path = &repos.path().join("main");
+        create_repo(repo_path, Some(config.path()))?;
+
+        let package = PackageManifest {
+            metadata: Metadata {
+                title: Some("Hello".into()),
+                version: Some("1.0".into()),
+                ..Default::default()
+            },
+            id: "hello".into(),
+            ..Default::default()
+        };
+        insert_package(&package, repo_path, Some(config.path()))?;
+
+        assert_eq!(
+            list_packages(repos.path())?,
+            vec![PackageStatus {
+                repo: "main".into(),
+                id: "hello".into(),
+                title: Some("Hello".into()),
+                version: Some("1.0".into()),
+                installed: false,
+                update_available: false,
+            }]
+        );
+
+        let info = package_info(repos.path(), None, "hello")?;
+        assert_eq!(info.repo, "main");
+        assert!(info.installed.is_none());
+        assert!(package_info(repos.path(), Some("main"), "missing").is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,293 @@
+use anyhow::{Context, Result, bail};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::repo::{
+    installed::remove_installed,
+    manifest_io::atomic_replace,
+    provenance::now,
+    versions::{get_current_version, switch_version, version_exists},
+};
+
+/// A snapshot of every installed package version, across all Repositories.
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Generation {
+    pub number: u64,
+    /// Seconds since the UNIX epoch
+    pub created_at: u64,
+    pub packages: Vec<GenerationEntry>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct GenerationEntry {
+    pub repo: String,
+    pub package: String,
+    /// Version hash the `installed/` symlink pointed at
+    pub hash: String,
+}
+
+/// Generations live next to the Repositories directory, like the chunk store.
+fn generations_dir(repos_path: &Path) -> PathBuf {
+    repos_path
+        .parent()
+        .unwrap_or(repos_path)
+        .join("generations")
+}
+
+/// Lists all recorded generations, oldest first.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+/// - Invalid generation files
+pub fn list_generations(repos_path: &Path) -> Result<Vec<Generation>> {
+    let dir = generations_dir(repos_path);
+    let mut generations = Vec::new();
+
+    if !dir.exists() {
+        return Ok(generations);
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.extension().is_some_and(|extension| extension == "yml") {
+            generations.push(serde_yaml::from_str(&fs::read_to_string(path)?)?);
+        }
+    }
+
+    generations.sort_by_key(|generation: &Generation| generation.number);
+
+    Ok(generations)
+}
+
+/// Snapshots the currently installed versions of every package.
+fn current_entries(repos_path: &Path) -> Result<Vec<GenerationEntry>> {
+    let mut entries = Vec::new();
+
+    for repo_entry in fs::read_dir(repos_path)? {
+        let repo_entry = repo_entry?;
+        let repo_path = repo_entry.path();
+        let installed_path = repo_path.join("installed");
+
+        if !installed_path.exists() {
+            continue;
+        }
+
+        for package_entry in fs::read_dir(installed_path)? {
+            let package = package_entry?.file_name().to_string_lossy().to_string();
+
+            if let Some(hash) = get_current_version(&repo_path, &package)? {
+                entries.push(GenerationEntry {
+                    repo: repo_entry.file_name().to_string_lossy().to_string(),
+                    package,
+                    hash,
+                });
+            }
+        }
+    }
+
+    entries.sort_by(|a, b| (&a.repo, &a.package).cmp(&(&b.repo, &b.package)));
+
+    Ok(entries)
+}
+
+/// Records the current state as a new generation, unless nothing changed since the last one.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions, Out of space)
+///
+/// # Returns
+///
+/// The new generation, if one was recorded
+pub fn record_generation(repos_path: &Path) -> Result<Option<Generation>> {
+    let packages = current_entries(repos_path)?;
+    let generations = list_generations(repos_path)?;
+
+    if generations
+        .last()
+        .is_some_and(|latest| latest.packages == packages)
+    {
+        return Ok(None);
+    }
+
+    let generation = Generation {
+        number: generations.last().map_or(1, |latest| latest.number + 1),
+        created_at: now()?,
+        packages,
+    };
+
+    let dir = generations_dir(repos_path);
+    fs::create_dir_all(&dir)?;
+    atomic_replace(
+        &dir,
+        &format!("{}.yml", generation.number),
+        serde_yaml::to_string(&generation)?.as_bytes(),
+    )?;
+
+    Ok(Some(generation))
+}
+
+/// Renames a Repository in every recorded generation, so rollbacks keep finding it.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+/// - Invalid generation files
+pub fn rename_generations_repo(repos_path: &Path, old_name: &str, new_name: &str) -> Result<()> {
+    let dir = generations_dir(repos_path);
+
+    for mut generation in list_generations(repos_path)? {
+        let mut changed = false;
+
+        for entry in &mut generation.packages {
+            if entry.repo == old_name {
+                entry.repo = new_name.to_string();
+                changed = true;
+            }
+        }
+
+        if changed {
+            generation
+                .packages
+                .sort_by(|a, b| (&a.repo, &a.package).cmp(&(&b.repo, &b.package)));
+            atomic_replace(
+                &dir,
+                &format!("{}.yml", generation.number),
+                serde_yaml::to_string(&generation)?.as_bytes(),
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Deletes all but the latest `keep` generations.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+///
+/// # Returns
+///
+/// The number of deleted generations
+pub fn prune_generations(repos_path: &Path, keep: usize) -> Result<usize> {
+    let generations = list_generations(repos_path)?;
+    let prune_count = generations.len().saturating_sub(keep);
+    let dir = generations_dir(repos_path);
+
+    for generation in &generations[..prune_count] {
+        fs::remove_file(dir.join(format!("{}.yml", generation.number)))?;
+    }
+
+    Ok(prune_count)
+}
+
+/// Restores every package to the version it had in generation `number`.
+/// Packages that were not installed in that generation are unlinked, but their versions are kept.
+///
+/// Nothing is changed unless every version in the generation is still available.
+///
+/// # Errors
+///
+/// - Generation doesn't exist
+/// - A version from the generation has since been removed
+/// - Filesystem errors (Permissions)
+pub fn rollback_generation(repos_path: &Path, number: u64) -> Result<()> {
+    let generation = list_generations(repos_path)?
+        .into_iter()
+        .find(|generation| generation.number == number)
+        .with_context(|| format!("Generation {number} does not exist."))?;
+
+    let missing: Vec<String> = generation
+        .packages
+        .iter()
+        .filter(|entry| !version_exists(&repos_path.join(&entry.repo), &entry.hash, &entry.package))
+        .map(|entry| format!("{}/{}", entry.repo, entry.package))
+        .collect();
+
+    if !missing.is_empty() {
+        bail!(
+            "Cannot roll back, these package versions no longer exist: {}",
+            missing.join(", ")
+        )
+    }
+
+    for entry in current_entries(repos_path)? {
+        let still_installed = generation
+            .packages
+            .iter()
+            .any(|target| target.repo == entry.repo && target.package == entry.package);
+
+        if !still_installed {
+            remove_installed(&repos_path.join(&entry.repo), &entry.package)?;
+        }
+    }
+
+    for entry in &generation.packages {
+        switch_version(&repos_path.join(&entry.repo), &entry.hash, &entry.package)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use temp_dir::TempDir;
+
+    fn install_fake_version(repo_path: &Path, package_id: &str, hash: &str) -> Result<()> {
+        fs::create_dir_all(
+            repo_path
+                .join("versions")
+                .join(format!("{package_id}-{hash}")),
+        )?;
+        switch_version(repo_path, hash, package_id)
+    }
+
+    #[test]
+    fn test_record_and_rollback() -> Result<()> {
+        let root = TempDir::new()?;
+        let repos_path = &root.path().join("repos");
+        let repo_path = &repos_path.join("main");
+        fs::create_dir_all(repo_path)?;
+
+        install_fake_version(repo_path, "pkg", "aaa")?;
+        let first = record_generation(repos_path)?.unwrap();
+        assert_eq!(first.number, 1);
+
+        // Nothing changed
+        assert!(record_generation(repos_path)?.is_none());
+
+        install_fake_version(repo_path, "pkg", "bbb")?;
+        install_fake_version(repo_path, "other", "ccc")?;
+        assert_eq!(record_generation(repos_path)?.unwrap().number, 2);
+
+        rollback_generation(repos_path, 1)?;
+        assert_eq!(
+            get_current_version(repo_path, "pkg")?,
+            Some("aaa".to_string())
+        );
+        assert!(!repo_path.join("installed/other").exists());
+        assert!(repo_path.join("versions/other-ccc").exists());
+
+        // Rolling back to a generation with deleted versions changes nothing
+        rollback_generation(repos_path, 2)?;
+        fs::remove_dir_all(repo_path.join("versions/pkg-aaa"))?;
+        assert!(rollback_generation(repos_path, 1).is_err());
+        assert_eq!(
+            get_current_version(repo_path, "pkg")?,
+            Some("bbb".to_string())
+        );
+
+        // Broken links are ignored
+        symlink("../versions/nothing", repo_path.join("installed/broken"))?;
+        assert!(record_generation(repos_path).is_ok());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,262 @@
+use anyhow::{Context, Result, bail};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{
+    chunks::DownloadEvent,
+    repo::{PackageManifest, get_package_closure, read_manifest},
+    run::materialize_packages,
+    utils::{resolve_package, resolve_repo, temp::TempDir},
+};
+
+/// Describes an image to compose from Repository packages
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ImageSpec {
+    /// Used as the os-release ID
+    pub name: String,
+    pub version: Option<String>,
+    pub packages: Vec<ImagePackage>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ImagePackage {
+    pub package: String,
+    /// Required if more than one Repository contains the package
+    pub repo: Option<String>,
+}
+
+/// The list of contents written to `etc/flint-image.yml` inside an image
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ImageContents {
+    pub name: String,
+    pub version: Option<String>,
+    pub packages: Vec<ImageContentsEntry>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ImageContentsEntry {
+    pub repo: String,
+    pub id: String,
+    pub version: Option<String>,
+    pub build_hash: String,
+}
+
+/// Builds an image from `spec`.
+///
+/// The output format is chosen by the extension of `output`: `.tar` for a tarball,
+/// `.squashfs`/`.sqfs` for a squashfs image (requires `mksquashfs`), and a plain directory otherwise.
+///
+/// # Errors
+///
+/// - A package doesn't exist, or exists in several Repositories without `repo` set
+/// - Two packages contain different files at the same path
+/// - Output directory is not empty
+/// - `mksquashfs` is unavailable or failed
+/// - Filesystem errors (Out of space, Permissions)
+/// - Network Errors (If network is enabled)
+#[cfg_attr(
+    not(feature = "network"),
+    allow(clippy::unused_async, unused_variables)
+)]
+pub async fn create_image(
+    repos_path: &Path,
+    spec: &ImageSpec,
+    output: &Path,
+    chunk_store_path: &Path,
+    progress: &(dyn Fn(DownloadEvent) + Sync),
+) -> Result<ImageContents> {
+    let packages = resolve_image_packages(repos_path, spec)?;
+
+    #[cfg(feature = "network")]
+    for (repo_path, package) in &packages {
+        crate::run::download_package(repo_path, &package.id, chunk_store_path, progress)
+            .await
+            .with_context(|| format!("Failed to download {}.", package.id))?;
+    }
+
+    let contents = ImageContents {
+        name: spec.name.clone(),
+        version: spec.version.clone(),
+        packages: packages
+            .iter()
+            .map(|(repo_path, package)| ImageContentsEntry {
+                repo: repo_path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+                id: package.id.clone(),
+                version: package.metadata.version.clone(),
+                build_hash: package.build_hash.clone(),
+            })
+            .collect(),
+    };
+
+    let packages: Vec<PackageManifest> = packages.into_iter().map(|(_, package)| package).collect();
+    let extension = output
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_string());
+
+    if let Some("tar" | "squashfs" | "sqfs") = extension.as_deref() {
+        let root = TempDir::new()?;
+        write_root(root.path(), &packages, &contents, chunk_store_path)?;
+
+        if extension.as_deref() == Some("tar") {
+            let mut builder = tar::Builder::new(fs::File::create(output)?);
+            builder.follow_symlinks(false);
+            builder.append_dir_all(".", root.path())?;
+            builder.finish()?;
+        } else {
+            let status = Command::new("mksquashfs")
+                .arg(root.path())
+                .arg(output)
+                .args(["-noappend", "-all-root"])
+                .status()
+                .with_context(|| "Could not run mksquashfs. Is it installed?")?;
+
+            if !status.success() {
+                bail!("mksquashfs failed with {status}")
+            }
+        }
+    } else {
+        if output.exists() && fs::read_dir(output)?.next().is_some() {
+            bail!("Output directory {} is not empty.", output.display())
+        }
+
+        fs::create_dir_all(output)?;
+        write_root(output, &packages, &contents, chunk_store_path)?;
+    }
+
+    Ok(contents)
+}
+
+/// Resolves every package in the spec, plus their dependencies, without duplicates.
+fn resolve_image_packages(
+    repos_path: &Path,
+    spec: &ImageSpec,
+) -> Result<Vec<(PathBuf, PackageManifest)>> {
+    let mut packages: Vec<(PathBuf, PackageManifest)> = Vec::new();
+
+    for image_package in &spec.packages {
+        let repo_path = if let Some(repo) = &image_package.repo {
+            resolve_repo(repos_path, repo)?
+        } else {
+            let possible_repos = resolve_package(repos_path, &image_package.package, |_| true)?;
+
+            match possible_repos.as_slice() {
+                [(repo_path, _)] => repo_path.clone(),
+                [] => bail!("No Repositories contain {}.", image_package.package),
+                _ => bail!(
+                    "Several Repositories contain {}, set `repo` for it.",
+                    image_package.package
+                ),
+            }
+        };
+
+        for package in get_package_closure(&read_manifest(&repo_path)?, &image_package.package)? {
+            let is_duplicate = packages.iter().any(|(existing_repo, existing)| {
+                *existing_repo == repo_path && existing.id == package.id
+            });
+
+            if !is_duplicate {
+                packages.push((repo_path.clone(), package));
+            }
+        }
+    }
+
+    Ok(packages)
+}
+
+fn write_root(
+    root: &Path,
+    packages: &[PackageManifest],
+    contents: &ImageContents,
+    chunk_store_path: &Path,
+) -> Result<()> {
+    materialize_packages(packages, chunk_store_path, root)?;
+
+    let etc = root.join("etc");
+    fs::create_dir_all(&etc)?;
+
+    let version = contents.version.clone().unwrap_or_default();
+    let os_release = format!(
+        "NAME=\"{name}\"\nID={name}\nVERSION_ID=\"{version}\"\nPRETTY_NAME=\"{name} {version}\"\n",
+        name = contents.name,
+    );
+
+    fs::write(etc.join("os-release"), os_release)?;
+    fs::write(
+        etc.join("flint-image.yml"),
+        serde_yaml::to_string(contents)?,
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chunks::{HashKind, save_tree},
+        repo::{Metadata, create_repo, insert_package},
+    };
+    use temp_dir::TempDir;
+
+    #[tokio::test]
+    async fn test_create_image() -> Result<()> {
+        let repos = TempDir::new()?;
+        let repo_path = repos.path().join("main");
+        let chunk_store = TempDir::new()?;
+        let output = TempDir::new()?;
+        create_repo(&repo_path, Some(&repo_path))?;
+
+        let tree = TempDir::new()?;
+        fs::create_dir(tree.path().join("bin"))?;
+        fs::write(tree.path().join("bin/sh"), "shell")?;
+
+        let package = PackageManifest {
+            id: "shell".into(),
+            metadata: Metadata {
+                version: Some("1.0".into()),
+                ..Default::default()
+            },
+            chunks: save_tree(tree.path(), chunk_store.path(), HashKind::Blake3)?,
+            build_hash: "hash".into(),
+            ..Default::default()
+        };
+        insert_package(&package, &repo_path, Some(&repo_path))?;
+
+        let spec = ImageSpec {
+            name: "tiny".into(),
+            version: Some("1".into()),
+            packages: vec![ImagePackage {
+                package: "shell".into(),
+                repo: None,
+            }],
+        };
+
+        let root = output.path().join("root");
+        let contents =
+            create_image(repos.path(), &spec, &root, chunk_store.path(), &|_| {}).await?;
+
+        assert_eq!(contents.packages[0].id, "shell");
+        assert_eq!(fs::read_to_string(root.join("bin/sh"))?, "shell");
+        assert!(fs::read_to_string(root.join("etc/os-release"))?.contains("ID=tiny"));
+        assert!(root.join("etc/flint-image.yml").exists());
+
+        // Refuses to overwrite an existing root
+        assert!(
+            create_image(repos.path(), &spec, &root, chunk_store.path(), &|_| {})
+                .await
+                .is_err()
+        );
+
+        let tarball = output.path().join("image.tar");
+        create_image(repos.path(), &spec, &tarball, chunk_store.path(), &|_| {}).await?;
+        assert!(tarball.exists());
+
+        Ok(())
+    }
+}
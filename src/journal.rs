@@ -0,0 +1,399 @@
+use anyhow::Result;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process,
+};
+
+use crate::{
+    maintenance::remove_partial_chunks,
+    repo::{
+        installed::rescan_installed,
+        manifest_io::{atomic_replace, has_manifest},
+        metadata_policy::check_package_id,
+        provenance::now,
+        versions::{get_versions, version_path},
+    },
+    utils::temp::process_running,
+};
+
+/// Step recorded just before a Repository directory starts being deleted.
+/// Once it is in the journal, recovery finishes the removal instead of keeping a half-deleted Repository.
+pub const STEP_REMOVING_REPO: &str = "removing repository";
+
+/// A multi-step operation in progress. Dropping it without committing leaves the record behind,
+/// which is exactly what a crash does.
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    entry: JournalEntry,
+}
+
+/// The on-disk record of an operation.
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct JournalEntry {
+    /// What is being done, eg: `install`
+    pub operation: String,
+    /// Name of the Repository being changed
+    pub repo: Option<String>,
+    pub package: Option<String>,
+    /// The process doing the operation
+    pub pid: u32,
+    /// Seconds since the UNIX epoch
+    pub started_at: u64,
+    /// Steps completed so far, oldest first
+    pub steps: Vec<String>,
+    /// Versions the operation writes, removed by recovery unless they were finished
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub versions: Vec<PathBuf>,
+    /// Why the last recovery attempt failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recovery_error: Option<String>,
+}
+
+impl JournalEntry {
+    /// Whether the process that started this operation is still running
+    #[must_use]
+    pub fn is_running(&self) -> bool {
+        process_running(self.pid)
+    }
+}
+
+/// Journals live next to the Repositories directory, like generations.
+fn journal_dir(repos_path: &Path) -> PathBuf {
+    repos_path.parent().unwrap_or(repos_path).join("journal")
+}
+
+impl Journal {
+    /// Records the start of an operation, on the Repository at `repo_path` if it changes one.
+    ///
+    /// # Errors
+    ///
+    /// - Filesystem errors (Permissions)
+    pub fn begin(
+        repos_path: &Path,
+        operation: &str,
+        repo_path: Option<&Path>,
+        package: Option<&str>,
+    ) -> Result<Self> {
+        let dir = journal_dir(repos_path);
+        fs::create_dir_all(&dir)?;
+
+        let entry = JournalEntry {
+            operation: operation.to_string(),
+            repo: repo_path
+                .and_then(Path::file_name)
+                .map(|name| name.to_string_lossy().to_string()),
+            package: package.map(str::to_string),
+            pid: process::id(),
+            started_at: now()?,
+            steps: Vec::new(),
+            versions: Vec::new(),
+            recovery_error: None,
+        };
+
+        let mut number = 0;
+        let path = loop {
+            let path = dir.join(format!("{}-{}-{number}.yml", entry.started_at, entry.pid));
+            if !path.exists() {
+                break path;
+            }
+            number += 1;
+        };
+
+        let journal = Self { path, entry };
+        journal.write()?;
+
+        Ok(journal)
+    }
+
+    fn write(&self) -> Result<()> {
+        // Journals are always in a directory, and always have a file name
+        let dir = self.path.parent().unwrap_or(&self.path);
+        let filename = self.path.file_name().unwrap_or_default().to_string_lossy();
+
+        atomic_replace(
+            dir,
+            &filename,
+            serde_yaml::to_string(&self.entry)?.as_bytes(),
+        )
+    }
+
+    /// Records that a step of the operation has finished.
+    ///
+    /// # Errors
+    ///
+    /// - Filesystem errors (Permissions)
+    pub fn step(&mut self, step: &str) -> Result<()> {
+        self.entry.steps.push(step.to_string());
+        self.write()
+    }
+
+    /// Records that the operation is about to install the latest version of a package.
+    ///
+    /// # Errors
+    ///
+    /// - Filesystem errors (Permissions)
+    /// - Invalid Repository/Package manifest
+    pub fn installs(&mut self, repo_path: &Path, package_id: &str) -> Result<()> {
+        self.entry
+            .versions
+            .push(version_path(repo_path, package_id)?);
+        self.write()
+    }
+
+    /// Records that the operation finished, removing the journal.
+    ///
+    /// # Errors
+    ///
+    /// - Filesystem errors (Permissions)
+    pub fn commit(self) -> Result<()> {
+        fs::remove_file(&self.path)?;
+
+        Ok(())
+    }
+}
+
+/// Lists every journal left behind, including operations still running in other processes.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+/// - Invalid journal files
+pub fn list_journals(repos_path: &Path) -> Result<Vec<JournalEntry>> {
+    Ok(read_journals(repos_path)?
+        .into_iter()
+        .map(|(_, entry)| entry)
+        .collect())
+}
+
+fn read_journals(repos_path: &Path) -> Result<Vec<(PathBuf, JournalEntry)>> {
+    let dir = journal_dir(repos_path);
+    let mut journals = Vec::new();
+
+    if !dir.exists() {
+        return Ok(journals);
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.extension().is_some_and(|extension| extension == "yml") {
+            let entry: JournalEntry = serde_yaml::from_str(&fs::read_to_string(&path)?)?;
+            journals.push((path, entry));
+        }
+    }
+
+    journals.sort_by_key(|(_, entry)| entry.started_at);
+
+    Ok(journals)
+}
+
+/// Cleans up after every operation that was interrupted, removing their journals.
+/// Journals that fail to recover are kept, with the error recorded, so `flint doctor` can report them.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+/// - Invalid journal files
+///
+/// # Returns
+///
+/// Every interrupted operation, with the error if it could not be recovered
+pub fn recover_interrupted(
+    repos_path: &Path,
+    chunk_store_path: &Path,
+) -> Result<Vec<(JournalEntry, Option<String>)>> {
+    let journals = read_journals(repos_path)?;
+    let mut recovered = Vec::new();
+
+    for (path, mut entry) in journals {
+        if entry.is_running() {
+            continue;
+        }
+
+        let mut result = recover_entry(repos_path, &entry);
+        // Only the chunks this operation was writing, other processes may be writing theirs
+        if result.is_ok() && chunk_store_path.exists() {
+            result = remove_partial_chunks(chunk_store_path, Some(entry.pid)).map(|_| ());
+        }
+
+        match result {
+            Ok(()) => {
+                fs::remove_file(path)?;
+                recovered.push((entry, None));
+            }
+            Err(err) => {
+                let err = format!("{err:#}");
+                entry.recovery_error = Some(err.clone());
+                fs::write(path, serde_yaml::to_string(&entry)?)?;
+                recovered.push((entry, Some(err)));
+            }
+        }
+    }
+
+    Ok(recovered)
+}
+
+/// Brings the Repository an operation was changing back to a consistent state.
+fn recover_entry(repos_path: &Path, entry: &JournalEntry) -> Result<()> {
+    let Some(repo) = &entry.repo else {
+        return Ok(());
+    };
+    let repo_path = repos_path.join(repo);
+
+    if !repo_path.exists() {
+        return Ok(());
+    }
+
+    // Either halfway through being removed, or never finished being added
+    if entry.steps.iter().any(|step| step == STEP_REMOVING_REPO) || !has_manifest(&repo_path) {
+        fs::remove_dir_all(repo_path)?;
+        return Ok(());
+    }
+
+    // Leftovers from atomic replacements
+    for file in fs::read_dir(&repo_path)? {
+        let path = file?.path();
+
+        if path.extension().is_some_and(|extension| extension == "new") && path.is_file() {
+            fs::remove_file(path)?;
+        }
+    }
+
+    // Half-switched `installed/` links
+    let installed_path = repo_path.join("installed");
+    if installed_path.exists() {
+        for file in fs::read_dir(&installed_path)? {
+            let path = file?.path();
+
+            if path.extension().is_some_and(|extension| extension == "tmp") && path.is_symlink() {
+                fs::remove_file(path)?;
+            }
+        }
+    }
+
+    // Half-written versions never got their `install.meta`
+    for path in written_versions(&repo_path, entry)? {
+        if path.starts_with(&repo_path) && path.is_dir() && !path.join("install.meta").exists() {
+            fs::remove_dir_all(path)?;
+        }
+    }
+
+    rescan_installed(&repo_path)?;
+
+    Ok(())
+}
+
+/// The versions an operation was writing.
+/// Journals from before they were recorded only name the package, whose versions are all checked.
+fn written_versions(repo_path: &Path, entry: &JournalEntry) -> Result<Vec<PathBuf>> {
+    if !entry.versions.is_empty() {
+        return Ok(entry.versions.clone());
+    }
+
+    let Some(package) = &entry.package else {
+        return Ok(Vec::new());
+    };
+    if check_package_id(package).is_err() || !repo_path.join("versions").exists() {
+        return Ok(Vec::new());
+    }
+
+    Ok(get_versions(repo_path, package)?
+        .into_iter()
+        .map(|hash| repo_path.join("versions").join(format!("{package}-{hash}")))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::create_repo;
+    use temp_dir::TempDir;
+
+    #[test]
+    fn test_journal_commit() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let repos_path = &temp_dir.path().join("repos");
+        fs::create_dir_all(repos_path)?;
+
+        let mut journal = Journal::begin(
+            repos_path,
+            "install",
+            Some(&repos_path.join("main")),
+            Some("hello"),
+        )?;
+        journal.step("downloaded")?;
+
+        let journals = list_journals(repos_path)?;
+        assert_eq!(journals.len(), 1);
+        assert_eq!(journals[0].repo.as_deref(), Some("main"));
+        assert_eq!(journals[0].steps, vec!["downloaded"]);
+        // Still running, in this process
+        assert!(journals[0].is_running());
+        assert!(recover_interrupted(repos_path, temp_dir.path())?.is_empty());
+
+        journal.commit()?;
+        assert!(list_journals(repos_path)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_recover_interrupted_install() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let repos_path = &temp_dir.path().join("repos");
+        let repo_path = &repos_path.join("main");
+        let chunks_path = &temp_dir.path().join("chunks");
+        fs::create_dir_all(repo_path)?;
+        fs::create_dir_all(chunks_path)?;
+        create_repo(repo_path, Some(repo_path))?;
+
+        // Crashed halfway through writing a version, and switching to it
+        fs::create_dir_all(repo_path.join("versions/hello-abc/bin"))?;
+        fs::create_dir_all(repo_path.join("versions/other-abc"))?;
+        // Another package, whose name starts with this one's
+        fs::create_dir_all(repo_path.join("versions/hello-world-abc"))?;
+        fs::create_dir_all(repo_path.join("installed"))?;
+        std::os::unix::fs::symlink(
+            "../versions/hello-abc",
+            repo_path.join("installed/hello.tmp"),
+        )?;
+        fs::write(repo_path.join("manifest.yml.new"), "partial")?;
+        fs::write(chunks_path.join(format!("abc.{}.tmp", u32::MAX)), "partial")?;
+        // Written by another process
+        fs::write(chunks_path.join("def.1.tmp"), "partial")?;
+
+        let entry = JournalEntry {
+            operation: "install".to_string(),
+            repo: Some("main".to_string()),
+            package: Some("hello".to_string()),
+            pid: u32::MAX,
+            started_at: 0,
+            steps: Vec::new(),
+            versions: vec![repo_path.join("versions/hello-abc")],
+            recovery_error: None,
+        };
+        fs::create_dir_all(journal_dir(repos_path))?;
+        fs::write(
+            journal_dir(repos_path).join("0.yml"),
+            serde_yaml::to_string(&entry)?,
+        )?;
+
+        let recovered = recover_interrupted(repos_path, chunks_path)?;
+        assert_eq!(recovered, vec![(entry, None)]);
+
+        assert!(!repo_path.join("versions/hello-abc").exists());
+        // Not part of the interrupted operation
+        assert!(repo_path.join("versions/other-abc").exists());
+        assert!(repo_path.join("versions/hello-world-abc").exists());
+        assert!(!repo_path.join("installed/hello.tmp").is_symlink());
+        assert!(!repo_path.join("manifest.yml.new").exists());
+        assert!(repo_path.join("manifest.yml").exists());
+        assert!(!chunks_path.join(format!("abc.{}.tmp", u32::MAX)).exists());
+        assert!(chunks_path.join("def.1.tmp").exists());
+        assert!(list_journals(repos_path)?.is_empty());
+
+        Ok(())
+    }
+}
@@ -3,6 +3,15 @@ pub mod bundle;
 pub mod chunks;
 pub mod config;
 pub mod crypto;
+#[cfg(unix)]
+pub mod daemon;
+pub mod generations;
+pub mod image;
+pub mod journal;
+pub mod maintenance;
+pub mod policy;
 pub mod repo;
 pub mod run;
+#[cfg(feature = "serve")]
+pub mod serve;
 pub mod utils;
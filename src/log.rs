@@ -1,6 +1,14 @@
 use console::style;
-use flintpkg::repo::PackageManifest;
-use std::{env::var_os, ffi::OsStr, path::Path};
+use flintpkg::{
+    chunks::{InstallStats, VerifyProgress},
+    journal::JournalEntry,
+    repo::{
+        PackageManifest, RepoManifest,
+        edition::{CLIENT_EDITION, requires_newer_client},
+    },
+    utils::format_size,
+};
+use std::{env::var_os, ffi::OsStr, path::Path, time::Duration};
 
 pub fn skipped_update_repo(repo_name: &OsStr) {
     println!(
@@ -27,6 +35,67 @@ pub fn updated_package(package: &PackageManifest) {
     );
 }
 
+#[cfg(feature = "network")]
+pub fn held_back_package(
+    package: &PackageManifest,
+    policy: flintpkg::repo::settings::UpdatePolicy,
+) {
+    println!(
+        "[{}] Held back {} {}, its update policy is {policy}",
+        style("HELD").bright().yellow(),
+        style(&package.id).bright().green(),
+        package.metadata.version.as_deref().unwrap_or_default()
+    );
+}
+
+#[cfg(feature = "network")]
+pub fn downloaded_package(package: &PackageManifest) {
+    println!(
+        "[{}] Downloaded update for {}",
+        style("DOWNLOADED").bright().green(),
+        style(&package.id).bright().green(),
+    );
+}
+
+#[cfg(feature = "network")]
+pub fn not_downloaded_package(package: &PackageManifest) {
+    println!(
+        "[{}] Update for {} has not been downloaded yet",
+        style("SKIPPED").bright().black(),
+        style(&package.id).bright().green(),
+    );
+}
+
+pub fn installed_package(package_id: &str, stats: &InstallStats) {
+    println!(
+        "[{}] Installed {}, downloaded {} chunks ({}) and reused {} ({}) from the chunk store",
+        style("INSTALLED").bright().green(),
+        style(package_id).bright().green(),
+        stats.chunks_fetched,
+        format_size(stats.bytes_downloaded),
+        stats.chunks_reused,
+        format_size(stats.bytes_reused),
+    );
+
+    for (mirror, mirror_stats) in &stats.mirrors {
+        println!(
+            "    {mirror}: {} chunks ({}), {} failed",
+            mirror_stats.chunks,
+            format_size(mirror_stats.bytes),
+            mirror_stats.failures
+        );
+    }
+}
+
+#[cfg(feature = "network")]
+pub fn prefetched_package(package: &PackageManifest) {
+    println!(
+        "[{}] Prefetched {}",
+        style("DOWNLOADED").bright().green(),
+        style(&package.id).bright().green(),
+    );
+}
+
 pub fn updated_repo(repo: &OsStr) {
     println!(
         "[{}] Updated Repository {}",
@@ -35,11 +104,216 @@ pub fn updated_repo(repo: &OsStr) {
     );
 }
 
-pub fn added_repo(repo: &str, public_key: &str) {
+pub fn added_repo(repo: &str, manifest: &RepoManifest) {
+    println!(
+        "[{}] Added Repository {} with public key: {}",
+        style("NOTICE").bright().green(),
+        style(repo).bright().green(),
+        manifest.public_key,
+    );
+    newer_edition_repo(repo, manifest);
+}
+
+/// Only says anything for Repositories accepted with `--ignore-edition`
+pub fn newer_edition_repo(repo: &str, manifest: &RepoManifest) {
+    if let Some(min_edition) = requires_newer_client(manifest) {
+        println!(
+            "[{}] {} requires Flint edition {min_edition}, but this is edition {CLIENT_EDITION}. Some packages may not work",
+            style("CAUTION").bright().yellow(),
+            style(repo).bright().green(),
+        );
+    }
+}
+
+#[cfg(feature = "network")]
+pub fn declared_key_mismatch(repo: &str) {
+    println!(
+        "[{}] {} is signed with another key than declared for it, leaving it alone",
+        style("CAUTION").bright().yellow(),
+        style(repo).bright().green(),
+    );
+}
+
+#[cfg(feature = "network")]
+pub fn undeclared_repos(repos: &[String]) {
+    println!(
+        "[{}] No longer declared: {}",
+        style("CAUTION").bright().yellow(),
+        style(repos.join(", ")).bright().green(),
+    );
+}
+
+#[cfg(feature = "network")]
+pub fn kept_undeclared_repos() {
     println!(
-        "[{}] Added Repository {} with public key: {public_key}",
+        "[{}] Kept them, run `flint update --yes` to remove them",
         style("NOTICE").bright().green(),
+    );
+}
+
+#[cfg(feature = "network")]
+pub fn repo_preview(repo: &str, manifest: &RepoManifest, fingerprint: &str) {
+    println!(
+        "[{}] Repository {}",
+        style("PREVIEW").bright().blue(),
+        style(repo).bright().green(),
+    );
+    println!(
+        "  Title:    {}",
+        manifest.metadata.title.as_deref().unwrap_or("Untitled")
+    );
+    println!("  Packages: {}", manifest.packages.len());
+    println!("  Key:      {fingerprint}");
+    for mirror in &manifest.mirrors {
+        println!("  Mirror:   {}", mirror.url);
+    }
+}
+
+pub fn exported_repo(repo: &str, out_path: &Path, new_chunks: usize) {
+    println!(
+        "[{}] Exported Repository {} to {} ({new_chunks} new chunks)",
+        style("EXPORTED").bright().green(),
         style(repo).bright().green(),
+        out_path.display(),
+    );
+}
+
+pub fn cloned_repo(repo: &str, new_repo: &str, packages: usize) {
+    println!(
+        "[{}] Cloned Repository {} into {} ({packages} packages)",
+        style("CLONED").bright().green(),
+        style(repo).bright().green(),
+        style(new_repo).bright().green(),
+    );
+}
+
+pub fn built_artifact(package: &PackageManifest, out_path: &Path) {
+    println!(
+        "[{}] Built {} into {} ({} chunks)",
+        style("BUILT").bright().green(),
+        style(&package.id).bright().green(),
+        out_path.display(),
+        package.chunks.len(),
+    );
+}
+
+pub fn inserted_artifact(package: &PackageManifest, repo: &str) {
+    println!(
+        "[{}] Inserted {} into Repository {}",
+        style("INSERTED").bright().green(),
+        style(&package.id).bright().green(),
+        style(repo).bright().green(),
+    );
+}
+
+pub fn signing_requested(request_path: &Path) {
+    println!(
+        "[{}] Wrote a signing request to {}",
+        style("NOTICE").bright().green(),
+        request_path.display(),
+    );
+    println!(
+        "Sign it with `flint sign` where the key is, then run `flint repo apply-signature` with the signed copy."
+    );
+}
+
+pub fn signed_request(serial: u64, request_path: &Path) {
+    println!(
+        "[{}] Signed manifest serial {} in {}",
+        style("SIGNED").bright().green(),
+        style(serial).bright().green(),
+        request_path.display(),
+    );
+}
+
+pub fn applied_signature(repo: &str, serial: u64) {
+    println!(
+        "[{}] Repository {} is now at manifest serial {}",
+        style("SIGNED").bright().green(),
+        style(repo).bright().green(),
+        style(serial).bright().green(),
+    );
+}
+
+pub fn logged_in(host: &str) {
+    println!(
+        "[{}] Stored credentials for {} in the keyring",
+        style("LOGIN").bright().green(),
+        style(host).bright().green(),
+    );
+}
+
+pub fn packed_repo(repo: &str, out_path: &Path, chunks: usize) {
+    println!(
+        "[{}] Packed Repository {} into {} ({chunks} chunks)",
+        style("PACKED").bright().green(),
+        style(repo).bright().green(),
+        out_path.display(),
+    );
+}
+
+pub fn pruned_revisions(repo: &str, pruned: usize) {
+    println!(
+        "[{}] Pruned {pruned} superseded builds from Repository {}",
+        style("PRUNED").bright().green(),
+        style(repo).bright().green(),
+    );
+}
+
+pub fn rotated_key(repo: &str, public_key: &str) {
+    println!(
+        "[{}] Repository {} is rotating to public key: {public_key}",
+        style("NOTICE").bright().green(),
+        style(repo).bright().green(),
+    );
+    println!("Run `flint repo rotate-key {repo} --finish` once clients have updated.");
+}
+
+pub fn stale_image(version: &str) {
+    println!(
+        "[{}] {} is stored as an image and still uses the old Repository path, reinstall it to fix",
+        style("CAUTION").bright().yellow(),
+        style(version).bright().green(),
+    );
+}
+
+pub fn binary_cache_unavailable(err: &anyhow::Error) {
+    println!(
+        "[{}] Binary cache unavailable, built locally: {err:#}",
+        style("CAUTION").bright().yellow(),
+    );
+}
+
+pub fn kept_build_dir(path: &Path) {
+    println!(
+        "[{}] Build directory kept at {}",
+        style("NOTICE").bright().green(),
+        style(path.display()).bright().green(),
+    );
+}
+
+pub fn skipped_include_repo(repo_path: &Path, err: &anyhow::Error) {
+    println!(
+        "[{}] Left {} out of resolving includes, it can't be read: {err:#}",
+        style("CAUTION").bright().yellow(),
+        style(repo_path.display()).bright().green(),
+    );
+}
+
+#[cfg(all(feature = "fuse", target_os = "linux"))]
+pub fn failed_mount_read(path: &Path, err: &anyhow::Error) {
+    println!(
+        "[{}] Could not read {} while mounted: {err:#}",
+        style("CAUTION").bright().yellow(),
+        style(path.display()).bright().green(),
+    );
+}
+
+#[cfg(unix)]
+pub fn daemon_connection_failed(err: &anyhow::Error) {
+    println!(
+        "[{}] Daemon connection failed: {err:#}",
+        style("CAUTION").bright().yellow(),
     );
 }
 
@@ -51,6 +325,24 @@ pub fn cannot_update_repo(repo: &str) {
     );
 }
 
+pub fn removing_installed_packages(repo: &str, packages: &[String]) {
+    println!(
+        "[{}] Removing {} also removes these installed packages: {} (use --keep-installed to keep them)",
+        style("CAUTION").bright().yellow(),
+        style(&repo).bright().green(),
+        style(packages.join(", ")).bright().green(),
+    );
+}
+
+pub fn detached_package(package_id: &str, path: &Path) {
+    println!(
+        "[{}] Kept {} at {}",
+        style("NOTICE").bright().green(),
+        style(package_id).bright().green(),
+        style(path.display()).bright().green(),
+    );
+}
+
 pub fn update_redirect(repo: &str, old_url: &str, new_url: &str) {
     println!(
         "[{}] Updates will go to {} instead of {} for {}",
@@ -61,6 +353,81 @@ pub fn update_redirect(repo: &str, old_url: &str, new_url: &str) {
     );
 }
 
+pub fn verify_progress(progress: VerifyProgress, elapsed: Duration) {
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )]
+    let per_second = (progress.bytes as f64 / elapsed.as_secs_f64().max(0.001)) as u64;
+
+    eprint!(
+        "\r[{}] {}/{} chunks, {}/s",
+        style("VERIFYING").bright().blue(),
+        progress.done,
+        progress.total,
+        format_size(per_second),
+    );
+    if progress.done == progress.total {
+        eprintln!();
+    }
+}
+
+pub fn verified_bundle(bundle_path: &Path, signed_by: Option<&str>) {
+    match signed_by {
+        Some(public_key) => println!(
+            "[{}] {} matches its checksum, signed with public key: {public_key}",
+            style("VERIFIED").bright().green(),
+            style(bundle_path.display()).bright().green(),
+        ),
+        None => println!(
+            "[{}] {} matches its checksum, but is not signed",
+            style("CAUTION").bright().yellow(),
+            style(bundle_path.display()).bright().green(),
+        ),
+    }
+}
+
+/// Describes a journaled operation, eg: `install hello in main`
+pub fn describe_operation(entry: &JournalEntry) -> String {
+    let mut description = entry.operation.clone();
+
+    if let Some(package) = &entry.package {
+        description.push(' ');
+        description.push_str(package);
+    }
+    if let Some(repo) = &entry.repo {
+        description.push_str(" in ");
+        description.push_str(repo);
+    }
+
+    description
+}
+
+pub fn recovered_operation(entry: &JournalEntry) {
+    println!(
+        "[{}] Cleaned up after an interrupted {}",
+        style("RECOVERED").bright().green(),
+        style(describe_operation(entry)).bright().green(),
+    );
+}
+
+pub fn recovery_failed(entry: &JournalEntry, err: &str) {
+    eprintln!(
+        "[{}] Could not clean up after an interrupted {}: {}\nRun `flint doctor` for details.",
+        style("CAUTION").bright().yellow(),
+        style(describe_operation(entry)).bright().green(),
+        err,
+    );
+}
+
+pub fn recovery_unavailable(err: &anyhow::Error) {
+    eprintln!(
+        "[{}] Could not check for interrupted operations: {err:#}\nRun `flint doctor` for details.",
+        style("CAUTION").bright().yellow(),
+    );
+}
+
 pub fn add_to_path_notice(path: &Path) {
     let shell = var_os("SHELL")
         .and_then(|s| s.into_string().ok())
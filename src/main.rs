@@ -1,5 +1,13 @@
 mod commands;
 mod log;
+#[cfg(feature = "network")]
+mod progress;
+#[cfg(not(feature = "network"))]
+mod progress {
+    /// Nothing is downloaded without the network feature
+    pub const fn download_progress(_: flintpkg::chunks::DownloadEvent) {}
+}
+mod prompt;
 
 use anyhow::Result;
 use clap::{Parser, Subcommand};
@@ -7,15 +15,26 @@ use clap::{Parser, Subcommand};
 use std::path::Path;
 use std::{env::var_os, path::PathBuf};
 
-use crate::{commands::main_commands, log::add_to_path_notice};
+#[cfg(feature = "network")]
+use crate::progress::download_progress;
+use crate::{
+    commands::main_commands,
+    log::{add_to_path_notice, recovered_operation, recovery_failed, recovery_unavailable},
+};
 use flintpkg::config::{
     get_system_chunks_dir, get_system_quicklaunch_dir, get_system_repos_dir, get_user_chunks_dir,
     get_user_quicklaunch_dir, get_user_repos_dir,
 };
+use flintpkg::{
+    journal::recover_interrupted,
+    repo::installed::rescan_installed,
+    utils::temp::{clean_stale_temp_dirs, get_temp_root},
+};
 
 /// Simple program to greet a person
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
+#[allow(clippy::struct_excessive_bools)]
 struct Args {
     /// Install system-wide (requires root)
     #[arg(long, conflicts_with = "user")]
@@ -29,6 +48,14 @@ struct Args {
     #[arg(long)]
     root: Option<PathBuf>,
 
+    /// Rebuild the installed package index of every Repository from disk
+    #[arg(long)]
+    rescan: bool,
+
+    /// Don't flush installed files to disk before switching to them, for throwaway environments
+    #[arg(long)]
+    fast: bool,
+
     #[command(subcommand)]
     command: Command,
 }
@@ -46,6 +73,22 @@ enum Command {
         repo_name: String,
         #[arg(long, short)]
         force: bool,
+        /// Don't run the package's `test_script`
+        #[arg(long)]
+        skip_tests: bool,
+        /// Rebuild and reinstall whenever the manifest, scripts or local sources change
+        #[arg(long)]
+        watch: bool,
+        /// Entrypoint to (re)start after every rebuild while watching
+        #[arg(long, requires = "watch")]
+        run: Option<String>,
+        /// Keep the build directory after a successful build. It is always kept when the build fails
+        #[arg(long, conflicts_with = "watch")]
+        keep_build_dir: bool,
+        /// Write the package's manifest and chunks to this directory (or `.tar`) instead of inserting it.
+        /// Publish it later with `flint repo insert`
+        #[arg(long, conflicts_with_all = ["watch", "force"])]
+        output: Option<PathBuf>,
     },
     /// Install a package
     Install {
@@ -54,6 +97,54 @@ enum Command {
         repo_name: Option<String>,
         /// The package to install
         package: String,
+        /// Install the package and its dependencies into this directory instead, leaving installed packages untouched
+        #[arg(long)]
+        root: Option<PathBuf>,
+        /// Print what was downloaded and reused as JSON
+        #[arg(long, conflicts_with = "root")]
+        json: bool,
+    },
+    /// Show information about a package
+    Info {
+        /// The Repository the package is in
+        #[arg(long)]
+        repo_name: Option<String>,
+        package: String,
+    },
+    /// List packages in every Repository, and whether they are installed
+    List {
+        /// Only list installed packages
+        #[arg(long, conflicts_with = "available")]
+        installed: bool,
+        /// Only list packages that are not installed
+        #[arg(long)]
+        available: bool,
+    },
+    /// Search every Repository for packages by id, alias, title or description
+    Search { query: String },
+    /// List the files in a package
+    Files {
+        /// The Repository the package is in
+        #[arg(long)]
+        repo_name: Option<String>,
+        package: String,
+        /// List the files actually on disk, flagging any that differ from the package
+        #[arg(long)]
+        installed: bool,
+    },
+    /// Explain why a package is installed, and what would break without it
+    Why {
+        /// The Repository the package is in
+        #[arg(long)]
+        repo_name: Option<String>,
+        package: String,
+    },
+    /// Display and verify how a package was built
+    Provenance {
+        /// The Repository the package is in
+        #[arg(long)]
+        repo_name: Option<String>,
+        package: String,
     },
     /// Remove an installed package
     Remove {
@@ -67,14 +158,16 @@ enum Command {
         #[command(subcommand)]
         command: BundleCommands,
     },
-    #[cfg(feature = "network")]
     /// Updates a repository and its packages
-    Update,
+    Update(UpdateArgs),
     /// Run a package's entrypoint
     Run {
         /// The Repository the package is in
         #[arg(long)]
         repo_name: Option<String>,
+        /// Pass the whole environment through, instead of only the allowed variables
+        #[arg(long)]
+        inherit_env: bool,
         /// The package to install
         package: String,
         /// The entrypoint in question. Will default to the first entrypoint
@@ -82,53 +175,568 @@ enum Command {
         /// Extra arguments
         args: Option<Vec<String>>,
     },
+    /// Run a package's default entrypoint, installing it first if needed
+    X {
+        /// The Repository the package is in
+        #[arg(long)]
+        repo_name: Option<String>,
+        /// Pass the whole environment through, instead of only the allowed variables
+        #[arg(long)]
+        inherit_env: bool,
+        /// The package to run, by id or alias
+        package: String,
+        /// Arguments for the package, everything after the package is passed through as is
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// Print a shell script that sources the env scripts of installed packages, for `eval "$(flint env)"`
+    Env {
+        /// Only these packages, by id or alias. Defaults to every installed package
+        packages: Vec<String>,
+    },
+    /// Start your shell with the env scripts of installed packages sourced
+    Shell {
+        /// Only these packages, by id or alias. Defaults to every installed package
+        packages: Vec<String>,
+    },
+    /// Manage snapshots of installed package versions, recorded on every update
+    Generations {
+        #[command(subcommand)]
+        command: GenerationsCommands,
+    },
+    /// Compose images from Repository packages
+    Image {
+        #[command(subcommand)]
+        command: ImageCommands,
+    },
+    /// Link working directories in place of installed packages
+    Dev {
+        #[command(subcommand)]
+        command: DevCommands,
+    },
     /// Verify all chunks in a repository
     VerifyChunks {
         /// The Repository to verify chunks for
         #[arg(long)]
         repo_name: String,
+        /// Stop at the first missing or corrupt chunk
+        #[arg(long)]
+        fail_fast: bool,
+        /// Quickly check installed packages against the chunk store instead,
+        /// only hashing files that are no longer hard links to their chunk
+        #[arg(long, conflicts_with = "fail_fast")]
+        installed: bool,
+        /// Print the report as JSON, listing missing and corrupt chunks
+        #[arg(long)]
+        json: bool,
+    },
+    /// Sign a signing request written by a Repository with external signing, with your key
+    Sign { request_path: PathBuf },
+    /// Check and repair the chunk store shared by every Repository
+    Chunks {
+        #[command(subcommand)]
+        command: ChunksCommands,
     },
     /// Removes all not currently installed chunks, even if they are still in the Repository
     Clean,
+    /// Report operations that were interrupted, and could not be cleaned up automatically
+    Doctor,
+    /// Check installed packages against the security advisories of their Repositories
+    Audit,
+    #[cfg(unix)]
+    /// Listen for JSON-RPC 2.0 requests on a unix socket, for editors, launchers and other long-lived apps.
+    /// Methods: list, info, install, remove, update and subscribe
+    Daemon {
+        /// Path of the socket to create
+        #[arg(long)]
+        socket: PathBuf,
+    },
+    /// Garbage collect, scrub chunks, prune old versions and evict caches, at low priority.
+    /// Tasks can be disabled under `maintenance` in config.yml.
+    Maintenance {
+        /// Write a systemd timer that runs maintenance daily, instead of running it now
+        #[arg(long)]
+        install_timer: bool,
+        /// Don't lower CPU and IO priority
+        #[arg(long)]
+        no_nice: bool,
+    },
+    #[cfg(feature = "serve")]
+    /// Serve a Repository over HTTP, with a JSON API under /api/v1/
+    Serve {
+        repo_name: String,
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        address: String,
+        /// YAML list of maintainer public keys allowed to publish packages
+        #[arg(long)]
+        maintainers: Option<PathBuf>,
+        /// Count downloads per package, anonymously, and serve them at /api/v1/stats
+        #[arg(long)]
+        stats: bool,
+    },
+    #[cfg(all(feature = "fuse", target_os = "linux"))]
+    /// Experimental: expose a package's files at a directory without installing it.
+    /// Files are downloaded the first time they are read, runs until unmounted.
+    Mount {
+        /// The Repository the package is in
+        #[arg(long)]
+        repo_name: Option<String>,
+        package: String,
+        /// An empty directory to mount on
+        mount_path: PathBuf,
+    },
+    /// Download packages and everything they depend on into the chunk store, without installing
+    Prefetch {
+        /// The Repository the packages are in
+        #[arg(long)]
+        repo_name: Option<String>,
+        #[arg(required = true)]
+        packages: Vec<String>,
+    },
+    /// Store credentials for an authenticated mirror in the OS keyring.
+    /// Credentials are also read from ~/.netrc.
+    Login {
+        /// URL or host of the mirror
+        mirror: String,
+        /// Login to use, asked for if not given
+        #[arg(long)]
+        login: Option<String>,
+    },
+    /// Publish a package from a local Repository to a remote `flint serve`
+    Publish {
+        repo_name: String,
+        package: String,
+        /// URL of the remote server
+        remote_url: String,
+    },
+}
+
+impl Command {
+    /// Commands that can't do anything without the network
+    const fn needs_network(&self) -> bool {
+        match self {
+            Self::Update(_) | Self::Prefetch { .. } | Self::Publish { .. } => true,
+            Self::Repo { command } => {
+                matches!(
+                    command,
+                    RepoCommands::Add(_) | RepoCommands::Include { .. } | RepoCommands::Diff { .. }
+                )
+            }
+            _ => false,
+        }
+    }
 }
 
 #[derive(Subcommand)]
 enum RepoCommands {
     /// Creates a new Repository locally
-    Create { repo_name: String },
+    Create {
+        repo_name: String,
+        /// How chunks are hashed: blake3, sha256 or sha512
+        #[arg(long, default_value = "blake3")]
+        hash: String,
+    },
     /// List all Repositories
     List,
     /// Add a Repository from a remote url
-    #[cfg(feature = "network")]
-    Add {
+    Add(RepoAddArgs),
+    /// Remove a Repository, including everything installed from it
+    Remove {
         repo_name: String,
-        remote_url: String,
+        /// Copy installed packages out of the Repository before removing it
+        #[arg(long)]
+        keep_installed: bool,
     },
-    /// Remove a Repository
-    Remove { repo_name: String },
     /// Update a Repositories Metadata
-    Update {
+    Update(RepoUpdateArgs),
+    /// Insert a package built with `flint build --output`, signing the manifest with your key
+    Insert {
+        repo_name: String,
+        artifact_path: PathBuf,
+    },
+    /// Remove a Package from this Repository
+    RemovePackage {
+        repo_name: String,
+        package_id: String,
+    },
+    /// Drop builds replaced by a newer build of the same package, and the chunks only they used
+    Prune {
+        repo_name: String,
+        /// Superseded builds to keep of every package, newest first
+        #[arg(long, default_value_t = 0)]
+        keep: usize,
+    },
+    /// Remove chunks the Repository doesn't use from its own chunk directory, eg: the one it is served from
+    Gc {
+        repo_name: String,
+        /// The directory to collect. Defaults to `chunks/` in the Repository
         #[arg(long)]
-        homepage_url: Option<String>,
+        chunks_dir: Option<PathBuf>,
+        /// Only print what would be removed
         #[arg(long)]
-        license: Option<String>,
+        dry_run: bool,
+    },
+    /// Include another Repository's packages in this one, for clients to see alongside its own
+    Include {
+        repo_name: String,
+        /// Clients add the feed as `<repo_name>.<name>`
+        name: String,
+        url: String,
+        /// PEM file with the key the feed must be signed with. Defaults to the key it is signed with now
         #[arg(long)]
-        title: Option<String>,
+        public_key: Option<PathBuf>,
+    },
+    /// Stop including another Repository in this one
+    Exclude { repo_name: String, name: String },
+    /// Move a Repository to a new signing key. Until finished, it is signed with both keys,
+    /// so clients that only trust the old key can follow along.
+    RotateKey {
+        repo_name: String,
+        /// Stop signing with the old key. Clients that have not updated since will have to add the Repository again.
         #[arg(long)]
-        version: Option<String>,
+        finish: bool,
+    },
+    /// Keep a Repository's key on another machine, eg: an air-gapped one. Changes are then written
+    /// as a signing request to sign there with `flint sign`, instead of being signed here
+    SignExternal {
+        repo_name: String,
+        /// Sign changes with the local key again
         #[arg(long)]
-        /// Comma seperated list of all mirrors
-        mirrors: Option<String>,
-
+        disable: bool,
+    },
+    /// Apply a signing request signed with `flint sign`, replacing the Repository's manifest
+    ApplySignature {
         repo_name: String,
+        request_path: PathBuf,
     },
-    /// Remove a Package from this Repository
-    RemovePackage {
+    /// Upgrade a Repository to a newer edition, re-signing its manifest
+    Migrate {
+        repo_name: String,
+        /// Edition to upgrade to. Defaults to the newest this version of Flint supports
+        #[arg(long)]
+        to: Option<String>,
+        /// Only show what would change
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Rename a Repository, keeping everything installed from it working
+    Rename { repo_name: String, new_name: String },
+    /// Copy a Repository's manifest under a new name, signed with your key, eg: to promote staging to production
+    Clone {
+        repo_name: String,
+        new_name: String,
+        /// Only copy these packages (and their dependencies), comma seperated. Supports `*`, eg: "pkgA,pkgB*"
+        #[arg(long)]
+        only: Option<String>,
+    },
+    /// Show or set which Repository wins when several contain a package. Higher wins, defaults to 0
+    Priority {
+        repo_name: String,
+        #[arg(allow_hyphen_values = true)]
+        priority: Option<i32>,
+    },
+    /// Publish security advisories, replacing the current ones, from a YAML list. See `flint audit`
+    Advisories {
+        repo_name: String,
+        advisories_path: PathBuf,
+    },
+    /// Show or set which updates of a package `flint update` applies: any, same-major or security-only
+    UpdatePolicy {
         repo_name: String,
         package_id: String,
+        policy: Option<String>,
+    },
+    /// Show package counts and disk usage of every Repository, or just one
+    Stats { repo_name: Option<String> },
+    /// Find packages sharing most of their chunks, and the common base packages they could be split into
+    Analyze {
+        repo_name: String,
+        /// How much of their bytes two packages must share to be listed, in percent
+        #[arg(long, default_value_t = 50)]
+        threshold: u64,
+    },
+    /// Show which packages an update would add, remove or change, without applying it
+    Diff {
+        repo_name: String,
+        /// Compare against a Repository that requires a newer Flint edition anyway
+        #[arg(long)]
+        ignore_edition: bool,
+        /// Print the differences as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Write a static mirror of a Repository: its signed manifest and only the chunks it uses
+    Export {
+        repo_name: String,
+        out_path: PathBuf,
+    },
+    /// Pack a Repository into a single .tar.zst: its signed manifest and only the chunks it uses
+    Pack {
+        repo_name: String,
+        out_path: PathBuf,
+    },
+    /// Add or update a Repository from an archive made by `repo pack`, eg: on a machine without network
+    Unpack(RepoUnpackArgs),
+    /// Check a Repository's manifest signature, chunks, installed versions and quicklaunch scripts
+    Verify {
+        repo_name: String,
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Store credentials for a private Repository in the config directory, asking for the token or password.
+    /// Can be done before adding it, with its url. They are only sent to that url's host, over https
+    Auth {
+        repo_name: String,
+        /// Url the credentials are for, the Repository's updates url by default
+        #[arg(long, conflicts_with = "remove")]
+        url: Option<String>,
+        /// Use HTTP basic auth with this login, instead of a bearer token
+        #[arg(long)]
+        login: Option<String>,
+        /// Remove the stored credentials
+        #[arg(long, conflicts_with = "login")]
+        remove: bool,
+    },
+    /// Set the rules packages inserted into a local Repository have to follow, or print them if nothing is set
+    Policy {
+        repo_name: String,
+        /// Require a license
+        #[arg(long)]
+        require_license: Option<bool>,
+        /// Require licenses to be SPDX license expressions, eg: "MIT OR Apache-2.0"
+        #[arg(long)]
+        spdx_license: Option<bool>,
+        /// Require at least one maintainer
+        #[arg(long)]
+        require_maintainers: Option<bool>,
+        /// Only allow these categories, comma seperated. An empty list allows any
+        #[arg(long, value_delimiter = ',')]
+        categories: Option<Vec<String>>,
+        /// Package ids and aliases have to start with one of these, comma seperated, eg: "org.example.". An empty list allows any
+        #[arg(long, value_delimiter = ',')]
+        id_prefixes: Option<Vec<String>>,
+    },
+    /// Manage named sets of packages, eg: stable and testing
+    Channel {
+        #[command(subcommand)]
+        command: ChannelCommands,
+    },
+    /// Manage the Repository's own mirrors, listed in its signed manifest
+    Mirror {
+        #[command(subcommand)]
+        command: MirrorCommands,
+    },
+    /// Manage client-side mirror overrides, tried before the Repository's own mirrors
+    Mirrors {
+        #[command(subcommand)]
+        command: MirrorsCommands,
+    },
+    /// Manage the keys of other maintainers allowed to sign a Repository
+    Keys {
+        #[command(subcommand)]
+        command: KeysCommands,
+    },
+}
+
+#[derive(clap::Args, Default)]
+#[allow(clippy::struct_excessive_bools)]
+struct UpdateArgs {
+    /// Only download new manifests and chunks, without switching installed versions
+    #[arg(long, conflicts_with = "apply_downloaded")]
+    download_only: bool,
+    /// Apply updates previously fetched with --download-only, without using the network
+    #[arg(long)]
+    apply_downloaded: bool,
+    /// Update Repositories that require a newer Flint edition anyway
+    #[arg(long)]
+    ignore_edition: bool,
+    /// Trust the keys Repositories are signed with now, even if other keys were pinned for them
+    #[arg(long)]
+    accept_new_key: bool,
+    /// Remove Repositories no longer declared in repos.yml or repos.d without asking
+    #[arg(long, short)]
+    yes: bool,
+}
+
+#[derive(clap::Args)]
+struct RepoUnpackArgs {
+    archive_path: PathBuf,
+    repo_name: String,
+    /// PEM file with the key the Repository must be signed with
+    #[arg(long)]
+    public_key: Option<PathBuf>,
+    /// Unpack the Repository even if it requires a newer Flint edition
+    #[arg(long)]
+    ignore_edition: bool,
+}
+
+#[derive(clap::Args)]
+#[allow(clippy::struct_excessive_bools)]
+struct RepoAddArgs {
+    repo_name: String,
+    remote_url: String,
+    /// Add the Repository even if it requires a newer Flint edition
+    #[arg(long)]
+    ignore_edition: bool,
+    /// Only follow these packages (and their dependencies), comma seperated. Supports `*`, eg: "pkgA,pkgB*"
+    #[arg(long)]
+    only: Option<String>,
+    /// Only follow the packages of this channel, eg: stable
+    #[arg(long)]
+    channel: Option<String>,
+    /// Trust the Repository without showing it first
+    #[arg(long, short)]
+    yes: bool,
+    /// Trust the key the url serves now, even if another key was pinned for it
+    #[arg(long)]
+    accept_new_key: bool,
+}
+
+#[derive(clap::Args)]
+struct RepoUpdateArgs {
+    #[arg(long)]
+    homepage_url: Option<String>,
+    #[arg(long)]
+    license: Option<String>,
+    #[arg(long)]
+    title: Option<String>,
+    #[arg(long)]
+    version: Option<String>,
+    #[arg(long)]
+    /// Oldest Flint edition that can correctly use this Repository
+    min_client_edition: Option<String>,
+    #[arg(long)]
+    /// Manifest edition to publish. From 2026 on, a faster to parse CBOR manifest is published too
+    edition: Option<String>,
+    #[arg(long)]
+    /// URL of a Repository of pre-built packages, tried before building locally
+    binary_cache: Option<String>,
+    #[arg(long, requires = "binary_cache")]
+    /// Key the binary cache is signed with, if not this Repository's
+    binary_cache_key: Option<String>,
+    #[arg(long)]
+    /// URL clients fetch updates from instead of the first mirror. Changing it moves clients there, empty to unset
+    updates_url: Option<String>,
+
+    repo_name: String,
+}
+
+#[derive(Subcommand)]
+enum ChannelCommands {
+    /// Set the packages of a channel, in the Repository's signed manifest
+    Set {
+        repo_name: String,
+        channel: String,
+        #[arg(required = true)]
+        packages: Vec<String>,
+    },
+    /// Remove a channel from the Repository's signed manifest
+    Remove { repo_name: String, channel: String },
+    /// List the Repository's channels
+    List { repo_name: String },
+    /// Only follow the packages of a channel on this machine. Without a channel, follow every package
+    Select {
+        repo_name: String,
+        channel: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum MirrorCommands {
+    /// Add a mirror, or change the options of one already listed
+    Add {
+        repo_name: String,
+        url: String,
+        /// Mirrors with a lower priority are tried first
+        #[arg(long, default_value_t = 0)]
+        priority: u32,
+        /// Relative share of downloads among mirrors of the same priority. 0 is only used as a last resort
+        #[arg(long, default_value_t = 1)]
+        weight: u32,
+        /// Clients in this region try the mirror before others of the same priority, eg: `eu`
+        #[arg(long)]
+        region: Option<String>,
+    },
+    /// Remove a mirror
+    Remove { repo_name: String, url: String },
+    /// List the Repository's mirrors, by priority
+    List { repo_name: String },
+}
+
+#[derive(Subcommand)]
+enum MirrorsCommands {
+    /// Add a mirror override
+    Add { repo_name: String, url: String },
+    /// Remove a mirror override
+    Remove { repo_name: String, url: String },
+    /// List all mirrors, in the order they will be tried
+    List { repo_name: String },
+}
+
+#[derive(Subcommand)]
+enum KeysCommands {
+    /// Allow a maintainer's key to sign the Repository
+    Add {
+        repo_name: String,
+        /// PEM file with the maintainer's public key
+        public_key: PathBuf,
+    },
+    /// Stop allowing a maintainer's key to sign the Repository
+    Remove {
+        repo_name: String,
+        /// PEM file with the maintainer's public key
+        public_key: PathBuf,
+    },
+    /// List every key allowed to sign the Repository
+    List { repo_name: String },
+}
+
+#[derive(Subcommand)]
+enum GenerationsCommands {
+    /// List all generations
+    List,
+    /// Restore every package to the versions of a previous generation
+    Rollback { generation: u64 },
+}
+
+#[derive(Subcommand)]
+enum DevCommands {
+    /// Make an installed package point at a working directory
+    Link {
+        dir: PathBuf,
+        package: String,
+        #[arg(long)]
+        repo_name: Option<String>,
+    },
+    /// Remove a dev link and restore the normal install
+    Unlink {
+        package: String,
+        #[arg(long)]
+        repo_name: Option<String>,
     },
 }
 
+#[derive(Subcommand)]
+enum ChunksCommands {
+    /// Check every chunk against its hash, removing corrupt ones, and find chunks installed packages are missing
+    Fsck {
+        /// Put corrupt and missing chunks back, from installed trees or else the Repositories' mirrors
+        #[arg(long)]
+        repair: bool,
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum ImageCommands {
+    /// Install the packages listed in a spec into a fresh root, as a directory, .tar or .squashfs
+    Create { spec_path: PathBuf, output: PathBuf },
+}
+
 #[derive(Subcommand)]
 enum BundleCommands {
     /// Extract a bundle into a Repository
@@ -138,6 +746,22 @@ enum BundleCommands {
         repo_name: String,
         bundle_path: PathBuf,
         header_path: PathBuf,
+        /// Keep the app's data in `<bundle>.data/` next to the bundle, instead of the home directory
+        #[arg(long)]
+        portable: bool,
+        /// Write `<bundle>.sha256` next to the bundle
+        #[arg(long)]
+        checksum: bool,
+        /// Write `<bundle>.sha256` and sign it as `<bundle>.sig`, with the Repository's key
+        #[arg(long)]
+        sign: bool,
+    },
+    /// Check a bundle against its checksum and signature before running it
+    Verify {
+        bundle_path: PathBuf,
+        /// Key the bundle must be signed with. Without it, a signed bundle is checked against the keys of every local Repository
+        #[arg(long)]
+        public_key: Option<String>,
     },
 }
 
@@ -147,6 +771,17 @@ enum Scope {
     System,
 }
 
+#[cfg(feature = "network")]
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum UpdateMode {
+    /// Download and apply updates
+    Full,
+    /// Download manifests and chunks, but don't switch installed versions
+    DownloadOnly,
+    /// Switch installed versions using only what is already downloaded
+    ApplyDownloaded,
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
@@ -177,6 +812,36 @@ async fn main() -> Result<()> {
         get_system_chunks_dir()?
     };
 
+    // A crashed or killed process can't clean up after itself.
+    // Best effort, a shared temp dir may hold directories of other users.
+    if let Ok(temp_root) = get_temp_root(None) {
+        let _ = clean_stale_temp_dirs(&temp_root);
+    }
+
+    // Whatever is left over, the command itself may still work
+    if base_path.exists() {
+        match recover_interrupted(base_path, chunk_store_path) {
+            Ok(recovered) => {
+                for (entry, err) in recovered {
+                    if let Some(err) = err {
+                        recovery_failed(&entry, &err);
+                    } else {
+                        recovered_operation(&entry);
+                    }
+                }
+            }
+            Err(err) => recovery_unavailable(&err),
+        }
+    }
+
+    flintpkg::chunks::set_fast_installs(args.fast);
+
+    if args.rescan && base_path.exists() {
+        for entry in base_path.read_dir()? {
+            rescan_installed(&entry?.path())?;
+        }
+    }
+
     main_commands(base_path, quicklaunch_path, chunk_store_path, args.command).await?;
 
     if let Some(path) = var_os("PATH")
@@ -190,41 +855,264 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+/// Adds and removes Repositories to match `repos.yml` and `repos.d`, if there are any.
+/// Removing asks first, unless `yes` is set.
+#[cfg(feature = "network")]
+async fn reconcile_declared_repos(
+    base_path: &Path,
+    pins: &mut flintpkg::crypto::pins::KeyPins,
+    allow_newer_edition: bool,
+    yes: bool,
+) -> Result<()> {
+    use crate::{
+        commands::repo::remove_repo,
+        log::{added_repo, declared_key_mismatch, kept_undeclared_repos, undeclared_repos},
+        prompt::prompter,
+    };
+    use flintpkg::journal::Journal;
+    use flintpkg::repo::declared::{
+        add_declared_repo, mark_declared, plan_reconcile, read_declared_repos,
+    };
+
+    let Some(declared) = read_declared_repos(None)? else {
+        return Ok(());
+    };
+    let plan = plan_reconcile(base_path, &declared)?;
+
+    for repo_name in &plan.mismatched {
+        declared_key_mismatch(repo_name);
+    }
+
+    for repo_name in &plan.add {
+        let repo_path = &base_path.join(repo_name);
+        let journal = Journal::begin(base_path, "repo add", Some(repo_path), None)?;
+        let added =
+            add_declared_repo(repo_path, &declared[repo_name], pins, allow_newer_edition).await;
+        // A failed add already removed what it wrote
+        journal.commit()?;
+
+        added_repo(repo_name, &added?);
+    }
+
+    for (repo_name, priority) in &plan.prioritize {
+        mark_declared(&base_path.join(repo_name), *priority)?;
+    }
+
+    if !plan.remove.is_empty() {
+        undeclared_repos(&plan.remove);
+
+        if !yes && !prompter().confirm("Remove them?", false)? {
+            kept_undeclared_repos();
+            return Ok(());
+        }
+    }
+
+    for repo_name in &plan.remove {
+        remove_repo(base_path, repo_name, false)?;
+    }
+
+    Ok(())
+}
+
+/// Updates a Repository's manifest and adds any feeds it newly includes
+#[cfg(feature = "network")]
+async fn update_repo_manifest(
+    repo_path: &Path,
+    repo_name: &std::ffi::OsStr,
+    journal: &mut flintpkg::journal::Journal,
+    pins: &mut flintpkg::crypto::pins::KeyPins,
+    allow_newer_edition: bool,
+) -> Result<()> {
+    use crate::log::{
+        added_repo, newer_edition_repo, skipped_update_repo, update_redirect, updated_repo,
+    };
+    use flintpkg::repo::{
+        network::{add_included_feeds, update_repository},
+        read_manifest,
+    };
+
+    let old_manifest = read_manifest(repo_path)?;
+    let has_changed = update_repository(repo_path, allow_newer_edition, Some(pins)).await?;
+    journal.step("updated manifest")?;
+
+    if has_changed {
+        updated_repo(repo_name);
+    } else {
+        skipped_update_repo(repo_name);
+    }
+
+    let new_manifest = read_manifest(repo_path)?;
+    newer_edition_repo(&repo_name.to_string_lossy(), &new_manifest);
+    if let (Some(old_source), Some(new_source)) =
+        (old_manifest.updates_source(), new_manifest.updates_source())
+        && old_source != new_source
+    {
+        update_redirect(&repo_name.to_string_lossy(), new_source, old_source);
+    }
+
+    for (feed_repo_name, feed_manifest) in
+        add_included_feeds(repo_path, &new_manifest, allow_newer_edition).await?
+    {
+        added_repo(&feed_repo_name, &feed_manifest);
+    }
+
+    Ok(())
+}
+
 #[cfg(feature = "network")]
-async fn update_all_repos(base_path: &Path, chunk_store_path: &Path) -> Result<()> {
-    use crate::log::{skipped_update_repo, updated_package, updated_repo};
+async fn update_all_repos(
+    base_path: &Path,
+    chunk_store_path: &Path,
+    mode: UpdateMode,
+    allow_newer_edition: bool,
+    accept_new_key: bool,
+    yes: bool,
+) -> Result<()> {
+    use crate::log::{downloaded_package, held_back_package};
+    use flintpkg::crypto::pins::KeyPins;
+    use flintpkg::journal::Journal;
     use flintpkg::repo::{
-        get_all_installed_packages, get_package, network::update_repository, read_manifest,
-        remove_package,
+        advisories::fixes_advisory, get_all_installed_packages, get_package,
+        read_subscribed_manifest, remove_package, settings::get_settings, versions::is_dev_install,
     };
-    use flintpkg::run::install_package;
+    use flintpkg::run::download_package;
+
+    let mut pins = KeyPins::read(None)?;
+    pins.accept_new_key = accept_new_key;
+
+    if mode != UpdateMode::ApplyDownloaded {
+        reconcile_declared_repos(base_path, &mut pins, allow_newer_edition, yes).await?;
+    }
 
     for entry in base_path.read_dir()? {
         let repo = entry?;
         let repo_path = repo.path();
         let repo_name = repo.file_name();
+        let mut journal = Journal::begin(base_path, "update", Some(&repo_path), None)?;
 
-        let has_changed = update_repository(&repo_path).await?;
-
-        if has_changed {
-            updated_repo(&repo_name);
-        } else {
-            skipped_update_repo(&repo_name);
+        if mode != UpdateMode::ApplyDownloaded {
+            update_repo_manifest(
+                &repo_path,
+                &repo_name,
+                &mut journal,
+                &mut pins,
+                allow_newer_edition,
+            )
+            .await?;
         }
 
-        let repo_manifest = read_manifest(&repo_path)?;
+        let repo_manifest = read_subscribed_manifest(&repo_path)?;
+        let settings = get_settings(&repo_path)?;
+
+        let mut security_fixes = Vec::new();
+        let mut outdated = Vec::new();
 
         for installed_package in get_all_installed_packages(&repo_path)? {
+            // Dev installs are managed by `flint dev`
+            if is_dev_install(&repo_path, &installed_package.id) {
+                continue;
+            }
+
             if let Ok(repo_package) = get_package(&repo_manifest, &installed_package.id) {
-                if installed_package != repo_package {
-                    updated_package(&repo_package);
+                if installed_package == repo_package {
+                    continue;
+                }
 
-                    install_package(&repo_path, &repo_package.id, chunk_store_path).await?;
+                // Security fixes are applied whatever the update policy, and before other updates
+                let security_fix =
+                    fixes_advisory(&repo_manifest.advisories, &installed_package, &repo_package);
+
+                let policy = settings.update_policy(&repo_package.id);
+                if !security_fix
+                    && !policy.allows(
+                        installed_package.metadata.version.as_deref(),
+                        repo_package.metadata.version.as_deref(),
+                    )
+                {
+                    held_back_package(&repo_package, policy);
+                    continue;
                 }
-            } else {
+
+                if mode == UpdateMode::DownloadOnly {
+                    download_package(
+                        &repo_path,
+                        &repo_package.id,
+                        chunk_store_path,
+                        &download_progress,
+                    )
+                    .await?;
+
+                    downloaded_package(&repo_package);
+                } else if security_fix {
+                    security_fixes.push(repo_package.id);
+                } else {
+                    outdated.push(repo_package.id);
+                }
+            } else if mode != UpdateMode::DownloadOnly {
                 remove_package(&installed_package.id, &repo_path, None)?;
+                journal.step(&format!("removed {}", installed_package.id))?;
             }
         }
+
+        let outdated: Vec<String> = security_fixes.into_iter().chain(outdated).collect();
+
+        apply_updates(
+            &repo_path,
+            &repo_manifest,
+            &outdated,
+            chunk_store_path,
+            mode,
+            &mut journal,
+        )
+        .await?;
+        journal.commit()?;
+    }
+
+    Ok(())
+}
+
+/// Installs the `outdated` packages of a Repository, or only those already downloaded in [`UpdateMode::ApplyDownloaded`]
+#[cfg(feature = "network")]
+async fn apply_updates(
+    repo_path: &Path,
+    repo_manifest: &flintpkg::repo::RepoManifest,
+    outdated: &[String],
+    chunk_store_path: &Path,
+    mode: UpdateMode,
+    journal: &mut flintpkg::journal::Journal,
+) -> Result<()> {
+    use crate::log::{not_downloaded_package, updated_package};
+    use flintpkg::chunks::missing_chunks;
+    use flintpkg::repo::{get_package, group_by_shared_dependencies};
+    use flintpkg::run::install_packages;
+
+    // Packages sharing a runtime are switched together, so they never mix runtime versions
+    for group in group_by_shared_dependencies(repo_manifest, outdated) {
+        let packages = group
+            .iter()
+            .map(|package_id| get_package(repo_manifest, package_id))
+            .collect::<Result<Vec<_>>>()?;
+
+        if mode == UpdateMode::ApplyDownloaded
+            && packages
+                .iter()
+                .any(|package| !missing_chunks(&package.chunks, chunk_store_path).is_empty())
+        {
+            for package in &packages {
+                not_downloaded_package(package);
+            }
+            continue;
+        }
+
+        group
+            .iter()
+            .try_for_each(|package_id| journal.installs(repo_path, package_id))?;
+        install_packages(repo_path, &group, chunk_store_path, &download_progress).await?;
+
+        for package in &packages {
+            updated_package(package);
+            journal.step(&format!("updated {}", package.id))?;
+        }
     }
 
     Ok(())
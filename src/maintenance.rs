@@ -0,0 +1,195 @@
+use anyhow::Result;
+use std::{
+    collections::HashSet,
+    fs,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    chunks::tmp_chunk_owner,
+    generations::list_generations,
+    repo::versions::{get_current_version, get_versions, remove_version},
+    utils::temp::process_running,
+};
+
+/// Removes every version that is neither installed, nor part of a recorded generation.
+/// Call `prune_generations` first to allow older versions to be removed.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+/// - Invalid generation files
+///
+/// # Returns
+///
+/// The number of removed versions
+pub fn prune_versions(repos_path: &Path) -> Result<usize> {
+    let mut kept = HashSet::new();
+
+    for generation in list_generations(repos_path)? {
+        for entry in generation.packages {
+            kept.insert((entry.repo, entry.package, entry.hash));
+        }
+    }
+
+    let mut removed = 0;
+
+    for repo_entry in fs::read_dir(repos_path)? {
+        let repo_entry = repo_entry?;
+        let repo_path = repo_entry.path();
+        let repo_name = repo_entry.file_name().to_string_lossy().to_string();
+        let versions_path = repo_path.join("versions");
+
+        if !versions_path.exists() {
+            continue;
+        }
+
+        let mut package_ids = HashSet::new();
+        for entry in fs::read_dir(versions_path)? {
+            let file_name = entry?.file_name().to_string_lossy().to_string();
+
+            if let Some((package_id, _)) = file_name.rsplit_once('-') {
+                package_ids.insert(package_id.to_string());
+            }
+        }
+
+        for package_id in package_ids {
+            let current = get_current_version(&repo_path, &package_id)?;
+
+            for hash in get_versions(&repo_path, &package_id)? {
+                let is_kept = current.as_ref() == Some(&hash)
+                    || kept.contains(&(repo_name.clone(), package_id.clone(), hash.clone()));
+
+                if !is_kept {
+                    remove_version(&repo_path, &hash, &package_id)?;
+                    removed += 1;
+                }
+            }
+        }
+    }
+
+    Ok(removed)
+}
+
+/// Removes build cache entries that haven't been modified in `max_age`.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+///
+/// # Returns
+///
+/// The number of removed entries
+pub fn evict_build_cache(build_cache_path: &Path, max_age: Duration) -> Result<usize> {
+    let mut removed = 0;
+
+    if !build_cache_path.exists() {
+        return Ok(removed);
+    }
+
+    for entry in fs::read_dir(build_cache_path)? {
+        let entry = entry?;
+        let age = SystemTime::now()
+            .duration_since(entry.metadata()?.modified()?)
+            .unwrap_or_default();
+
+        if entry.file_type()?.is_file() && age > max_age {
+            fs::remove_file(entry.path())?;
+            removed += 1;
+        }
+    }
+
+    Ok(removed)
+}
+
+/// Removes partially written chunks left behind by interrupted downloads.
+/// Only the ones written by `owner`, or if it isn't given, by processes that are no longer running.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+///
+/// # Returns
+///
+/// The number of removed files
+pub fn remove_partial_chunks(chunk_store_path: &Path, owner: Option<u32>) -> Result<usize> {
+    let mut removed = 0;
+
+    for entry in fs::read_dir(chunk_store_path)? {
+        let path = entry?.path();
+
+        if path.extension().is_none_or(|extension| extension != "tmp") {
+            continue;
+        }
+
+        let written_by = tmp_chunk_owner(&path);
+        let is_abandoned = owner.map_or_else(
+            || written_by.is_none_or(|pid| !process_running(pid)),
+            |owner| written_by == Some(owner),
+        );
+
+        if is_abandoned {
+            fs::remove_file(path)?;
+            removed += 1;
+        }
+    }
+
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chunks::tmp_chunk_path, generations::record_generation, repo::versions::switch_version,
+    };
+    use temp_dir::TempDir;
+
+    fn install_fake_version(repo_path: &Path, package_id: &str, hash: &str) -> Result<()> {
+        fs::create_dir_all(
+            repo_path
+                .join("versions")
+                .join(format!("{package_id}-{hash}")),
+        )?;
+        switch_version(repo_path, hash, package_id)
+    }
+
+    #[test]
+    fn test_prune_versions() -> Result<()> {
+        let root = TempDir::new()?;
+        let repos_path = root.path().join("repos");
+        let repo_path = repos_path.join("main");
+
+        install_fake_version(&repo_path, "my-package", "old")?;
+        record_generation(&repos_path)?;
+        install_fake_version(&repo_path, "my-package", "stale")?;
+        install_fake_version(&repo_path, "my-package", "new")?;
+
+        assert_eq!(prune_versions(&repos_path)?, 1);
+
+        let mut versions = get_versions(&repo_path, "my-package")?;
+        versions.sort();
+        assert_eq!(versions, vec!["new", "old"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_remove_partial_chunks() -> Result<()> {
+        let chunk_store = TempDir::new()?;
+        fs::write(chunk_store.path().join("chunk"), "complete")?;
+        fs::write(chunk_store.path().join("chunk.tmp"), "partial")?;
+        fs::write(chunk_store.path().join("other.1.tmp"), "partial")?;
+        // Still being written
+        fs::write(tmp_chunk_path(chunk_store.path(), "running"), "partial")?;
+
+        assert_eq!(remove_partial_chunks(chunk_store.path(), Some(1))?, 1);
+        assert!(chunk_store.path().join("chunk.tmp").exists());
+        assert_eq!(remove_partial_chunks(chunk_store.path(), None)?, 1);
+        assert!(chunk_store.path().join("chunk").exists());
+        assert!(tmp_chunk_path(chunk_store.path(), "running").exists());
+
+        Ok(())
+    }
+}
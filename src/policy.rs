@@ -0,0 +1,128 @@
+use anyhow::{Context, Result, bail};
+use std::{fs, path::Path};
+
+use crate::crypto::key::deserialize_verifying_key;
+
+/// Where administrators put the machine-wide policy
+pub const POLICY_PATH: &str = "/etc/flint/policy.yml";
+
+/// Machine-wide restrictions, set by an administrator in `/etc/flint/policy.yml`.
+/// Unlike `config.yml`, users can't override it. Every field is optional, missing fields allow everything.
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct Policy {
+    /// Only Repositories signed with one of these public keys may be added. Empty allows any key
+    pub allowed_keys: Vec<String>,
+    /// Refuse bundles that aren't signed
+    pub require_signed_bundles: bool,
+    /// Only run packages whose files match a manifest signed by one of `allowed_keys`,
+    /// hashing them on every launch. Meant for kiosks and other locked-down machines
+    pub verified_launch: bool,
+}
+
+impl Policy {
+    /// Errors out if a Repository signed with `public_key` may not be used on this machine.
+    ///
+    /// # Errors
+    ///
+    /// - `public_key` is not in `allowed_keys`
+    /// - Invalid key in the policy
+    pub fn check_repo_key(&self, public_key: &str) -> Result<()> {
+        if self.allowed_keys.is_empty() {
+            return Ok(());
+        }
+
+        // Compare the keys themselves, PEM formatting may differ
+        let key = deserialize_verifying_key(public_key)?;
+        for allowed_key in &self.allowed_keys {
+            if deserialize_verifying_key(allowed_key)
+                .with_context(|| format!("Invalid key in {POLICY_PATH}"))?
+                == key
+            {
+                return Ok(());
+            }
+        }
+
+        bail!("This Repository's key is not allowed by {POLICY_PATH}.")
+    }
+
+    /// Errors out if a bundle may not be used on this machine.
+    ///
+    /// # Errors
+    ///
+    /// - Bundle is unsigned, and `require_signed_bundles` is set
+    pub fn check_bundle(&self, signed: bool) -> Result<()> {
+        if self.require_signed_bundles && !signed {
+            bail!("Unsigned bundles are not allowed by {POLICY_PATH}.")
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads the policy at `policy_path`, or `/etc/flint/policy.yml`.
+/// A missing file allows everything.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+/// - Invalid policy file
+pub fn read_policy(policy_path: Option<&Path>) -> Result<Policy> {
+    let path = policy_path.unwrap_or_else(|| Path::new(POLICY_PATH));
+
+    if !path.exists() {
+        return Ok(Policy::default());
+    }
+
+    serde_yaml::from_str(&fs::read_to_string(path)?)
+        .with_context(|| format!("Invalid policy at {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::{create_repo, read_manifest};
+    use temp_dir::TempDir;
+
+    #[test]
+    fn test_policy() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let policy_path = &temp_dir.path().join("policy.yml");
+
+        // Nothing to enforce without a policy
+        let policy = read_policy(Some(policy_path))?;
+        assert_eq!(policy, Policy::default());
+        assert!(policy.check_repo_key("not even a key").is_ok());
+        assert!(policy.check_bundle(false).is_ok());
+
+        let allowed_repo = &temp_dir.path().join("allowed");
+        let other_repo = &temp_dir.path().join("other");
+        create_repo(allowed_repo, Some(allowed_repo))?;
+        create_repo(other_repo, Some(other_repo))?;
+        let allowed_key = read_manifest(allowed_repo)?.public_key;
+
+        fs::write(
+            policy_path,
+            serde_yaml::to_string(&Policy {
+                allowed_keys: vec![allowed_key.trim_end().to_string()],
+                require_signed_bundles: true,
+                verified_launch: false,
+            })?,
+        )?;
+        let policy = read_policy(Some(policy_path))?;
+
+        assert!(policy.check_repo_key(&allowed_key).is_ok());
+        assert!(
+            policy
+                .check_repo_key(&read_manifest(other_repo)?.public_key)
+                .is_err()
+        );
+        assert!(policy.check_bundle(true).is_ok());
+        assert!(policy.check_bundle(false).is_err());
+
+        fs::write(policy_path, "allowed_keys: nope")?;
+        assert!(read_policy(Some(policy_path)).is_err());
+
+        Ok(())
+    }
+}
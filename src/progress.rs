@@ -0,0 +1,128 @@
+use flintpkg::chunks::{Chunk, DownloadEvent};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex, PoisonError},
+};
+
+// indicatif templates, not format strings
+#[allow(clippy::literal_string_with_formatting_args)]
+const TOTAL_TEMPLATE: &str = "{msg} [{bar:30}] {bytes}/{total_bytes} {bytes_per_sec}, {eta} left";
+#[allow(clippy::literal_string_with_formatting_args)]
+const CHUNK_TEMPLATE: &str = "  {msg:30!} [{bar:30}] {bytes}/{total_bytes}";
+
+/// Progress bars of the chunk downloads running now: one for the whole batch, and one per chunk
+#[derive(Default)]
+struct DownloadBars {
+    multi: MultiProgress,
+    total: Option<ProgressBar>,
+    /// By chunk filename, with how much of the chunk's size is counted on the total bar
+    chunks: HashMap<String, (ProgressBar, u64)>,
+}
+
+impl DownloadBars {
+    fn handle(&mut self, event: DownloadEvent) {
+        match event {
+            DownloadEvent::Started { chunks, bytes } => {
+                let total = self.multi.add(ProgressBar::new(bytes));
+                total.set_style(style(TOTAL_TEMPLATE));
+                total.set_message(format!("Downloading {chunks} chunks"));
+                self.total = Some(total);
+            }
+            DownloadEvent::Progress {
+                chunk,
+                downloaded,
+                total,
+            } => {
+                let (bar, counted) = self.chunk_bar(chunk, total);
+                bar.set_position(downloaded);
+
+                // Compressed chunks download less than their size, so the total counts what they make up of it
+                let length = bar.length().unwrap_or_else(|| chunk.size()).max(1);
+                let progress = (u128::from(downloaded) * u128::from(chunk.size())
+                    / u128::from(length))
+                .min(u128::from(chunk.size()));
+                let progress = u64::try_from(progress).unwrap_or_else(|_| chunk.size());
+
+                let increase = progress.saturating_sub(*counted);
+                *counted = progress;
+                if let Some(total) = &self.total {
+                    total.inc(increase);
+                }
+            }
+            DownloadEvent::Failed {
+                chunk,
+                mirror,
+                error,
+            } => {
+                let _ = self.multi.println(format!(
+                    "Failed to fetch chunk {} from mirror {mirror}: {error}",
+                    chunk.hash()
+                ));
+
+                // Starts over from the next mirror
+                if let Some((bar, counted)) = self.chunks.get_mut(&chunk.filename()) {
+                    bar.reset();
+                    if let Some(total) = &self.total {
+                        total.set_position(total.position().saturating_sub(*counted));
+                    }
+                    *counted = 0;
+                }
+            }
+            DownloadEvent::Finished { chunk } => {
+                let counted = match self.chunks.remove(&chunk.filename()) {
+                    Some((bar, counted)) => {
+                        bar.finish_and_clear();
+                        self.multi.remove(&bar);
+                        counted
+                    }
+                    None => 0,
+                };
+
+                if let Some(total) = &self.total {
+                    total.inc(chunk.size().saturating_sub(counted));
+                }
+            }
+            DownloadEvent::Done => {
+                for (bar, _) in self.chunks.values() {
+                    bar.finish_and_clear();
+                }
+                self.chunks.clear();
+                if let Some(total) = self.total.take() {
+                    total.finish_and_clear();
+                }
+                let _ = self.multi.clear();
+            }
+        }
+    }
+
+    /// The bar of a chunk, added below the total when its first bytes arrive
+    fn chunk_bar(&mut self, chunk: &Chunk, total: Option<u64>) -> &mut (ProgressBar, u64) {
+        self.chunks.entry(chunk.filename()).or_insert_with(|| {
+            let bar = self
+                .multi
+                .add(ProgressBar::new(total.unwrap_or_else(|| chunk.size())));
+            bar.set_style(style(CHUNK_TEMPLATE));
+            bar.set_message(chunk.path().display().to_string());
+
+            (bar, 0)
+        })
+    }
+}
+
+fn style(template: &str) -> ProgressStyle {
+    ProgressStyle::with_template(template)
+        .unwrap_or_else(|_| ProgressStyle::default_bar())
+        .progress_chars("=> ")
+}
+
+static BARS: LazyLock<Mutex<DownloadBars>> = LazyLock::new(Mutex::default);
+
+/// Draws progress bars for chunk downloads, on stderr when it is a terminal.
+///
+/// Passed as the `progress` callback of anything that downloads chunks.
+pub fn download_progress(event: DownloadEvent) {
+    BARS.lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .handle(event);
+}
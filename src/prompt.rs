@@ -0,0 +1,46 @@
+use anyhow::Result;
+use dialoguer::{Confirm, Input, Password, Select, theme::ColorfulTheme};
+use std::io::{IsTerminal, stderr, stdin};
+
+use flintpkg::utils::prompt::{NonInteractive, Prompter};
+
+/// Asks on the terminal with dialoguer
+struct TerminalPrompter;
+
+impl Prompter for TerminalPrompter {
+    fn select(&self, prompt: &str, items: &[String]) -> Result<usize> {
+        Ok(Select::with_theme(&ColorfulTheme::default())
+            .with_prompt(prompt)
+            .items(items)
+            .default(0)
+            .interact()?)
+    }
+
+    fn confirm(&self, prompt: &str, default: bool) -> Result<bool> {
+        Ok(Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt(prompt)
+            .default(default)
+            .interact()?)
+    }
+
+    fn input(&self, prompt: &str) -> Result<String> {
+        Ok(Input::with_theme(&ColorfulTheme::default())
+            .with_prompt(prompt)
+            .interact_text()?)
+    }
+
+    fn password(&self, prompt: &str) -> Result<String> {
+        Ok(Password::with_theme(&ColorfulTheme::default())
+            .with_prompt(prompt)
+            .interact()?)
+    }
+}
+
+/// Prompts on the terminal when there is one, otherwise never asks
+pub fn prompter() -> Box<dyn Prompter> {
+    if stdin().is_terminal() && stderr().is_terminal() {
+        Box::new(TerminalPrompter)
+    } else {
+        Box::new(NonInteractive)
+    }
+}
@@ -0,0 +1,147 @@
+use anyhow::Result;
+use std::{cmp::Reverse, fs, path::Path};
+
+use crate::{
+    repo::{Advisory, PackageManifest, get_all_installed_packages, read_manifest},
+    run::requirements::compare_versions,
+};
+
+impl Advisory {
+    /// Whether `package` has this vulnerability
+    #[must_use]
+    pub fn affects(&self, package: &PackageManifest) -> bool {
+        if self.package != package.id {
+            return false;
+        }
+
+        let version = package.metadata.version.as_deref();
+
+        if !self.versions.is_empty() || !self.build_hashes.is_empty() {
+            return version.is_some_and(|version| self.versions.iter().any(|v| v == version))
+                || self.build_hashes.contains(&package.build_hash);
+        }
+
+        match (version, &self.fixed_in) {
+            (Some(version), Some(fixed_in)) => compare_versions(version, fixed_in).is_lt(),
+            // Nothing to compare against, so every version is affected
+            (None, _) | (_, None) => true,
+        }
+    }
+}
+
+/// Whether updating from `installed` to `available` fixes a vulnerability
+#[must_use]
+pub fn fixes_advisory(
+    advisories: &[Advisory],
+    installed: &PackageManifest,
+    available: &PackageManifest,
+) -> bool {
+    advisories
+        .iter()
+        .any(|advisory| advisory.affects(installed) && !advisory.affects(available))
+}
+
+/// An installed package with a known vulnerability
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditFinding {
+    pub repo: String,
+    pub package: PackageManifest,
+    pub advisory: Advisory,
+}
+
+/// Checks every installed package against the advisories of the Repository it is installed from.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+/// - A Repository contains invalid data/signature
+///
+/// # Returns
+///
+/// Findings, most severe first
+pub fn audit(repos_path: &Path) -> Result<Vec<AuditFinding>> {
+    let mut findings = Vec::new();
+
+    for entry in fs::read_dir(repos_path)? {
+        let repo_path = entry?.path();
+        let advisories = read_manifest(&repo_path)?.advisories;
+
+        if advisories.is_empty() {
+            continue;
+        }
+
+        let repo = repo_path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+
+        for package in get_all_installed_packages(&repo_path)? {
+            for advisory in advisories
+                .iter()
+                .filter(|advisory| advisory.affects(&package))
+            {
+                findings.push(AuditFinding {
+                    repo: repo.clone(),
+                    package: package.clone(),
+                    advisory: advisory.clone(),
+                });
+            }
+        }
+    }
+
+    findings.sort_by_key(|finding| Reverse(finding.advisory.severity));
+
+    Ok(findings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::{Metadata, Severity};
+
+    fn package(version: Option<&str>, build_hash: &str) -> PackageManifest {
+        PackageManifest {
+            metadata: Metadata {
+                version: version.map(str::to_string),
+                ..Default::default()
+            },
+            id: "hello".into(),
+            build_hash: build_hash.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_advisory_affects() {
+        let mut advisory = Advisory {
+            id: "CVE-2026-1".into(),
+            package: "hello".into(),
+            versions: Vec::new(),
+            build_hashes: Vec::new(),
+            severity: Severity::High,
+            fixed_in: Some("1.10".into()),
+            summary: None,
+        };
+
+        assert!(advisory.affects(&package(Some("1.9"), "aaa")));
+        assert!(!advisory.affects(&package(Some("1.10"), "aaa")));
+        assert!(advisory.affects(&package(None, "aaa")));
+        assert!(fixes_advisory(
+            &[advisory.clone()],
+            &package(Some("1.9"), "aaa"),
+            &package(Some("1.10"), "bbb")
+        ));
+
+        advisory.package = "world".into();
+        assert!(!advisory.affects(&package(Some("1.9"), "aaa")));
+
+        // Listed versions and build hashes are exact
+        advisory.package = "hello".into();
+        advisory.versions = vec!["1.2".into()];
+        advisory.build_hashes = vec!["bad".into()];
+        assert!(advisory.affects(&package(Some("1.2"), "aaa")));
+        assert!(advisory.affects(&package(Some("1.3"), "bad")));
+        assert!(!advisory.affects(&package(Some("1.3"), "aaa")));
+    }
+}
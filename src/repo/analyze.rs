@@ -0,0 +1,213 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::repo::RepoManifest;
+
+/// How much two packages of a Repository have in common
+#[derive(serde::Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct PackageOverlap {
+    pub package: String,
+    pub other_package: String,
+    /// Chunks both packages use
+    pub shared_chunks: usize,
+    pub shared_bytes: u64,
+    /// Shared bytes, out of every byte either package uses
+    pub percent: u64,
+}
+
+/// Packages that overlap enough to be worth splitting a common base package out of
+#[derive(serde::Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct PackageCluster {
+    pub packages: Vec<String>,
+    /// Chunks every package of the cluster uses, the candidate base package
+    pub common_chunks: usize,
+    pub common_bytes: u64,
+}
+
+/// What [`analyze_repo`] found
+#[derive(serde::Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct RepoAnalysis {
+    /// Pairs of packages sharing at least the threshold, most similar first
+    pub overlaps: Vec<PackageOverlap>,
+    /// Groups of packages linked by those pairs, largest candidate base first
+    pub clusters: Vec<PackageCluster>,
+}
+
+/// Finds packages of a Repository that share most of their chunks, to guide maintainers
+/// in splitting out common base packages (eg: an SDK) for better deduplication.
+///
+/// Chunks are compared as the chunk store keeps them, so identical files with other
+/// permissions count as different. Packages are clustered when they share at least
+/// `threshold_percent` of their bytes with another package of the cluster.
+#[must_use]
+pub fn analyze_repo(manifest: &RepoManifest, threshold_percent: u64) -> RepoAnalysis {
+    let mut sizes = HashMap::new();
+    let chunk_sets: Vec<(&str, BTreeSet<String>)> = manifest
+        .packages
+        .iter()
+        .map(|package| {
+            let set: BTreeSet<String> = package
+                .chunks
+                .iter()
+                .map(|chunk| {
+                    sizes.insert(chunk.filename(), chunk.size());
+                    chunk.filename()
+                })
+                .collect();
+            (package.id.as_str(), set)
+        })
+        .collect();
+    let bytes_of = |chunks: &mut dyn Iterator<Item = &String>| -> u64 {
+        chunks
+            .map(|chunk| sizes.get(chunk).copied().unwrap_or(0))
+            .sum()
+    };
+
+    let mut analysis = RepoAnalysis::default();
+    // Union-find over package indices, for clustering
+    let mut parents: Vec<usize> = (0..chunk_sets.len()).collect();
+
+    for (index, (package, chunks)) in chunk_sets.iter().enumerate() {
+        for (other_index, (other_package, other_chunks)) in
+            chunk_sets.iter().enumerate().skip(index + 1)
+        {
+            let shared_chunks = chunks.intersection(other_chunks).count();
+            if shared_chunks == 0 {
+                continue;
+            }
+
+            let shared_bytes = bytes_of(&mut chunks.intersection(other_chunks));
+            let union_bytes = bytes_of(&mut chunks.union(other_chunks));
+            let percent = shared_bytes
+                .saturating_mul(100)
+                .checked_div(union_bytes)
+                .unwrap_or(100);
+
+            if percent < threshold_percent {
+                continue;
+            }
+
+            let (root, other_root) = (find(&mut parents, index), find(&mut parents, other_index));
+            parents[other_root] = root;
+
+            analysis.overlaps.push(PackageOverlap {
+                package: (*package).to_string(),
+                other_package: (*other_package).to_string(),
+                shared_chunks,
+                shared_bytes,
+                percent,
+            });
+        }
+    }
+
+    let mut members: HashMap<usize, Vec<usize>> = HashMap::new();
+    for index in 0..chunk_sets.len() {
+        let root = find(&mut parents, index);
+        members.entry(root).or_default().push(index);
+    }
+
+    for indices in members.into_values().filter(|indices| indices.len() > 1) {
+        let mut common = chunk_sets[indices[0]].1.clone();
+        for index in &indices[1..] {
+            common.retain(|chunk| chunk_sets[*index].1.contains(chunk));
+        }
+
+        analysis.clusters.push(PackageCluster {
+            packages: indices
+                .iter()
+                .map(|index| chunk_sets[*index].0.to_string())
+                .collect(),
+            common_chunks: common.len(),
+            common_bytes: bytes_of(&mut common.iter()),
+        });
+    }
+
+    analysis.overlaps.sort_by(|a, b| {
+        b.percent
+            .cmp(&a.percent)
+            .then(b.shared_bytes.cmp(&a.shared_bytes))
+    });
+    analysis.clusters.sort_by(|a, b| {
+        b.common_bytes
+            .cmp(&a.common_bytes)
+            .then(a.packages.cmp(&b.packages))
+    });
+
+    analysis
+}
+
+fn find(parents: &mut [usize], index: usize) -> usize {
+    let mut root = index;
+    while parents[root] != root {
+        root = parents[root];
+    }
+    parents[index] = root;
+
+    root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chunks::save_tree,
+        repo::{PackageManifest, create_repo, insert_package, read_manifest},
+    };
+    use anyhow::Result;
+    use std::fs;
+    use temp_dir::TempDir;
+
+    #[test]
+    fn test_analyze_repo() -> Result<()> {
+        let root = TempDir::new()?;
+        let repo_path = &root.path().join("repo");
+        let chunk_store = &root.path().join("chunks");
+        create_repo(repo_path, Some(repo_path))?;
+
+        let sdk = "x".repeat(900);
+        for (id, files) in [
+            ("app", vec![("sdk", sdk.as_str()), ("app", "app")]),
+            ("tool", vec![("sdk", sdk.as_str()), ("tool", "tool")]),
+            ("other", vec![("other", "other")]),
+        ] {
+            let tree = root.path().join("trees").join(id);
+            fs::create_dir_all(&tree)?;
+            for (name, contents) in files {
+                fs::write(tree.join(name), contents)?;
+            }
+
+            let package = PackageManifest {
+                id: id.into(),
+                chunks: save_tree(&tree, chunk_store, read_manifest(repo_path)?.hash_kind)?,
+                ..Default::default()
+            };
+            insert_package(&package, repo_path, Some(repo_path))?;
+        }
+
+        let analysis = analyze_repo(&read_manifest(repo_path)?, 50);
+        assert_eq!(
+            analysis.overlaps,
+            vec![PackageOverlap {
+                package: "app".into(),
+                other_package: "tool".into(),
+                shared_chunks: 1,
+                shared_bytes: 900,
+                percent: 900 * 100 / 907,
+            }]
+        );
+        assert_eq!(
+            analysis.clusters,
+            vec![PackageCluster {
+                packages: vec!["app".into(), "tool".into()],
+                common_chunks: 1,
+                common_bytes: 900,
+            }]
+        );
+
+        assert_eq!(
+            analyze_repo(&read_manifest(repo_path)?, 100),
+            RepoAnalysis::default()
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,140 @@
+use anyhow::{Result, bail};
+use std::path::Path;
+
+use crate::repo::{
+    RepoManifest,
+    manifest_io::read_manifest_unsigned,
+    settings::{get_settings, set_settings},
+};
+
+/// Sets the packages of a channel in a Repository's own manifest, creating it if needed.
+/// The manifest still has to be signed again.
+///
+/// # Errors
+///
+/// - No packages, or a package is not in the Repository
+pub fn set_channel(
+    manifest: &mut RepoManifest,
+    channel: &str,
+    package_ids: &[String],
+) -> Result<()> {
+    if channel.is_empty() {
+        bail!("A channel needs a name.")
+    }
+    if package_ids.is_empty() {
+        bail!("A channel needs at least one package.")
+    }
+    for package_id in package_ids {
+        if !manifest
+            .packages
+            .iter()
+            .any(|package| &package.id == package_id)
+        {
+            bail!("{package_id} is not a package of this Repository.")
+        }
+    }
+
+    manifest
+        .channels
+        .insert(channel.to_string(), package_ids.to_vec());
+
+    Ok(())
+}
+
+/// Removes a channel from a Repository's own manifest.
+/// The manifest still has to be signed again.
+///
+/// # Errors
+///
+/// - The channel is not one of the Repository's
+pub fn remove_channel(manifest: &mut RepoManifest, channel: &str) -> Result<()> {
+    if manifest.channels.remove(channel).is_none() {
+        bail!("{channel} is not a channel of this Repository.")
+    }
+
+    Ok(())
+}
+
+/// Follows only the packages of `channel` on this machine, or every package with `None`.
+///
+/// # Errors
+///
+/// - The channel is not in the Repository's manifest
+/// - Filesystem errors (Permissions)
+pub fn select_channel(repo_path: &Path, channel: Option<&str>) -> Result<()> {
+    // Not the filtered manifest, the current channel may be the one that is gone
+    if let Some(channel) = channel
+        && !read_manifest_unsigned(repo_path)?
+            .channels
+            .contains_key(channel)
+    {
+        bail!("Repository has no channel {channel}.")
+    }
+
+    let mut settings = get_settings(repo_path)?;
+    settings.channel = channel.map(str::to_string);
+    set_settings(repo_path, &settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::{
+        PackageManifest, create_repo, insert_package, read_manifest, read_subscribed_manifest,
+        serialize_manifest, signing_request::sign_manifest,
+    };
+    use temp_dir::TempDir;
+
+    #[test]
+    fn test_channels() -> Result<()> {
+        let repo = TempDir::new()?;
+        create_repo(repo.path(), Some(repo.path()))?;
+
+        for (id, dependencies) in [
+            ("runtime", Vec::new()),
+            ("app", vec!["runtime".to_string()]),
+            ("app-beta", vec!["runtime".to_string()]),
+        ] {
+            let package = PackageManifest {
+                id: id.into(),
+                dependencies,
+                ..Default::default()
+            };
+            insert_package(&package, repo.path(), Some(repo.path()))?;
+        }
+
+        let mut manifest = read_manifest(repo.path())?;
+        set_channel(&mut manifest, "stable", &["app".into()])?;
+        set_channel(&mut manifest, "testing", &["app".into(), "app-beta".into()])?;
+        assert!(set_channel(&mut manifest, "broken", &["missing".into()]).is_err());
+        let serialized = serialize_manifest(repo.path(), &manifest)?;
+        sign_manifest(repo.path(), &serialized, Some(repo.path()))?;
+
+        assert!(select_channel(repo.path(), Some("nightly")).is_err());
+        select_channel(repo.path(), Some("stable"))?;
+        let mut ids: Vec<String> = read_subscribed_manifest(repo.path())?
+            .packages
+            .into_iter()
+            .map(|package| package.id)
+            .collect();
+        ids.sort();
+        assert_eq!(ids, vec!["app", "runtime"]);
+
+        // Re-signed in full, whichever channel the maintainer follows
+        let mut manifest = read_manifest(repo.path())?;
+        assert_eq!(manifest.packages.len(), 3);
+
+        // A channel that disappears is an error, not an empty Repository
+        remove_channel(&mut manifest, "stable")?;
+        assert!(remove_channel(&mut manifest, "stable").is_err());
+        let serialized = serialize_manifest(repo.path(), &manifest)?;
+        sign_manifest(repo.path(), &serialized, Some(repo.path()))?;
+        assert!(read_subscribed_manifest(repo.path()).is_err());
+        assert_eq!(read_manifest(repo.path())?.packages.len(), 3);
+
+        select_channel(repo.path(), None)?;
+        assert_eq!(read_subscribed_manifest(repo.path())?.packages.len(), 3);
+
+        Ok(())
+    }
+}
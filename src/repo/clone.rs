@@ -0,0 +1,288 @@
+use anyhow::{Result, bail};
+use std::{fs, path::Path};
+
+use crate::{
+    chunks::{Chunk, store_chunk},
+    crypto::key::{get_private_key, serialize_verifying_key},
+    repo::{
+        PackageManifest, RepoManifest, get_package_closure, manifest_io::serialize_manifest,
+        read_manifest, signing_request::sign_manifest, subscription::matches_pattern,
+    },
+    utils::resolve_repo,
+};
+
+/// Copies a Repository's manifest to a new Repository next to it, signed with the local key,
+/// eg: to promote packages from a staging Repository to a production one.
+///
+/// With `patterns`, only the packages matching one of them (and their dependencies) are copied.
+/// Chunks are shared through the chunk store. Ones only in the source's own `chunks/` directory
+/// are copied into it first. The clone starts without mirrors, an updates url or the source's
+/// signing keys, those belong to the source.
+///
+/// # Errors
+///
+/// - Source doesn't exist, or `new_name` is taken or not a plain name
+/// - A pattern matches no package, or a dependency is missing
+/// - A chunk of a copied package is neither in the chunk store nor the source's `chunks/`
+/// - Private key could not be read
+/// - Filesystem errors (Permissions)
+///
+/// # Returns
+///
+/// The manifest of the new Repository
+pub fn clone_repo(
+    repos_path: &Path,
+    chunk_store_path: &Path,
+    repo_name: &str,
+    new_name: &str,
+    patterns: Option<&[String]>,
+    config_path: Option<&Path>,
+) -> Result<RepoManifest> {
+    if new_name.is_empty() || new_name.contains('/') || new_name == "." || new_name == ".." {
+        bail!("Invalid Repository name {new_name}.")
+    }
+
+    let repo_path = resolve_repo(repos_path, repo_name)?;
+    let new_path = repos_path.join(new_name);
+    if new_path.exists() || new_path.is_symlink() {
+        bail!("A Repository named {new_name} already exists.")
+    }
+
+    let mut manifest = read_manifest(&repo_path)?;
+
+    if let Some(patterns) = patterns {
+        let mut packages: Vec<PackageManifest> = Vec::new();
+
+        for pattern in patterns {
+            let matching: Vec<String> = manifest
+                .packages
+                .iter()
+                .filter(|package| matches_pattern(pattern, &package.id))
+                .map(|package| package.id.clone())
+                .collect();
+            if matching.is_empty() {
+                bail!("No package in {repo_name} matches {pattern}.")
+            }
+
+            for package_id in matching {
+                for package in get_package_closure(&manifest, &package_id)? {
+                    if !packages.iter().any(|kept| kept.id == package.id) {
+                        packages.push(package);
+                    }
+                }
+            }
+        }
+
+        // Keep the source's order
+        manifest
+            .packages
+            .retain(|package| packages.iter().any(|kept| kept.id == package.id));
+    }
+
+    manifest.advisories.retain(|advisory| {
+        manifest
+            .packages
+            .iter()
+            .any(|package| package.id == advisory.package)
+    });
+    manifest.public_key = serialize_verifying_key(get_private_key(config_path)?.verifying_key())?;
+    manifest.previous_public_key = None;
+    manifest.signing_keys = Vec::new();
+    manifest.mirrors = Vec::new();
+    manifest.updates_url = None;
+    manifest.serial = 0;
+
+    store_missing_chunks(&repo_path, chunk_store_path, &manifest)?;
+
+    fs::create_dir_all(&new_path)?;
+    let written = serialize_manifest(&new_path, &manifest).and_then(|manifest_serialized| {
+        sign_manifest(&new_path, &manifest_serialized, config_path)
+    });
+    if let Err(err) = written {
+        fs::remove_dir_all(&new_path)?;
+        return Err(err);
+    }
+
+    read_manifest(&new_path)
+}
+
+/// Stores chunks of `manifest`'s packages missing from the chunk store from the `chunks/` the
+/// Repository at `repo_path` is served from, refusing any that are in neither.
+fn store_missing_chunks(
+    repo_path: &Path,
+    chunk_store_path: &Path,
+    manifest: &RepoManifest,
+) -> Result<()> {
+    let missing: Vec<(&str, &Chunk)> = manifest
+        .packages
+        .iter()
+        .flat_map(|package| {
+            package
+                .chunks
+                .iter()
+                .map(|chunk| (package.id.as_str(), chunk))
+        })
+        .filter(|(_, chunk)| !chunk_store_path.join(chunk.filename()).exists())
+        .collect();
+
+    if let Some((package_id, _)) = missing
+        .iter()
+        .find(|(_, chunk)| !repo_path.join("chunks").join(chunk.filename()).exists())
+    {
+        bail!(
+            "Chunks of {package_id} are not in the chunk store. Install it, or fetch its chunks, before cloning."
+        )
+    }
+
+    fs::create_dir_all(chunk_store_path)?;
+    for (_, chunk) in missing {
+        let data = fs::read(repo_path.join("chunks").join(chunk.filename()))?;
+        store_chunk(chunk, &data, manifest.hash_kind, chunk_store_path)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chunks::{HashKind, save_tree},
+        repo::{create_repo, insert_package, test_package},
+    };
+    use temp_dir::TempDir;
+
+    #[test]
+    fn test_clone_repo() -> Result<()> {
+        let root = TempDir::new()?;
+        let repos_path = &root.path().join("repos");
+        let chunk_store = &root.path().join("store");
+        let staging = &repos_path.join("staging");
+        let config = &root.path().join("config");
+        let other_config = &root.path().join("other");
+        fs::create_dir_all(other_config)?;
+        create_repo(staging, Some(config))?;
+
+        insert_package(&test_package("app", &["lib"]), staging, Some(config))?;
+        insert_package(&test_package("lib", &[]), staging, Some(config))?;
+        insert_package(&test_package("tool", &[]), staging, Some(config))?;
+
+        let production = clone_repo(
+            repos_path,
+            chunk_store,
+            "staging",
+            "production",
+            Some(&["app".into()]),
+            Some(other_config),
+        )?;
+        let ids: Vec<&str> = production
+            .packages
+            .iter()
+            .map(|package| package.id.as_str())
+            .collect();
+        assert_eq!(ids, vec!["app", "lib"]);
+        assert_ne!(production.public_key, read_manifest(staging)?.public_key);
+        // Signed with the local key, so it can be published to
+        insert_package(
+            &test_package("new", &[]),
+            &repos_path.join("production"),
+            Some(other_config),
+        )?;
+
+        assert!(
+            clone_repo(
+                repos_path,
+                chunk_store,
+                "staging",
+                "production",
+                None,
+                Some(config)
+            )
+            .is_err()
+        );
+        assert!(
+            clone_repo(
+                repos_path,
+                chunk_store,
+                "staging",
+                "../escape",
+                None,
+                Some(config)
+            )
+            .is_err()
+        );
+        assert!(
+            clone_repo(
+                repos_path,
+                chunk_store,
+                "staging",
+                "empty",
+                Some(&["nope*".into()]),
+                Some(config)
+            )
+            .is_err()
+        );
+        assert!(!repos_path.join("empty").exists());
+
+        assert_eq!(
+            clone_repo(
+                repos_path,
+                chunk_store,
+                "staging",
+                "all",
+                None,
+                Some(config)
+            )?
+            .packages
+            .len(),
+            3
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_clone_repo_chunks() -> Result<()> {
+        let root = TempDir::new()?;
+        let repos_path = &root.path().join("repos");
+        let staging = &repos_path.join("staging");
+        let chunk_store = &root.path().join("store");
+        let config = &root.path().join("config");
+        create_repo(staging, Some(config))?;
+
+        // Chunks only served from the source's own directory end up in the chunk store
+        let tree = TempDir::new()?;
+        fs::write(tree.path().join("hello"), "hello")?;
+        let served = PackageManifest {
+            chunks: save_tree(tree.path(), &staging.join("chunks"), HashKind::Blake3)?,
+            ..test_package("served", &[])
+        };
+        insert_package(&served, staging, Some(config))?;
+        clone_repo(
+            repos_path,
+            chunk_store,
+            "staging",
+            "served",
+            Some(&["served".into()]),
+            Some(config),
+        )?;
+        assert!(chunk_store.join(served.chunks[0].filename()).exists());
+
+        fs::remove_dir_all(staging.join("chunks"))?;
+        fs::remove_dir_all(chunk_store)?;
+        assert!(
+            clone_repo(
+                repos_path,
+                chunk_store,
+                "staging",
+                "missing",
+                None,
+                Some(config)
+            )
+            .is_err()
+        );
+        assert!(!repos_path.join("missing").exists());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,539 @@
+use anyhow::{Context, Result, bail};
+use std::{
+    collections::BTreeMap,
+    env, fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{config::get_config_dir, utils::platform::set_mode};
+
+/// Per-Repository credentials, in the config directory. Only readable by its owner
+const CREDENTIALS_FILE: &str = "credentials.yml";
+
+/// Service name credentials are stored under in the OS keyring, one entry per host
+#[cfg(feature = "keyring")]
+const KEYRING_SERVICE: &str = "flint";
+
+/// A login for an authenticated mirror
+#[derive(serde::Deserialize, serde::Serialize, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub login: String,
+    pub password: String,
+}
+
+// Keep passwords out of logs and error messages
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("login", &self.login)
+            .field("password", &"<redacted>")
+            .finish()
+    }
+}
+
+/// How requests to a private Repository authenticate, only ever sent to the host they were issued for
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct RepoAuth {
+    /// Host of the mirror the credentials are for, eg: `example.com`
+    pub host: String,
+    #[serde(flatten)]
+    pub method: AuthMethod,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthMethod {
+    /// `Authorization: Bearer <token>`
+    Bearer(String),
+    /// HTTP basic auth
+    Basic(Credentials),
+}
+
+impl fmt::Debug for AuthMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bearer(_) => f.write_str("Bearer(<redacted>)"),
+            Self::Basic(credentials) => f.debug_tuple("Basic").field(credentials).finish(),
+        }
+    }
+}
+
+impl RepoAuth {
+    /// Credentials for the host of `url`.
+    ///
+    /// # Errors
+    ///
+    /// - `url` has no host, or isn't https
+    pub fn new(url: &str, method: AuthMethod) -> Result<Self> {
+        let Some(host) = url_host(url) else {
+            bail!("{url} has no host to log in to.")
+        };
+        if !is_secure_url(url) {
+            bail!("Credentials are only sent over https, not to {url}.")
+        }
+
+        Ok(Self {
+            host: host.to_string(),
+            method,
+        })
+    }
+
+    /// Whether these credentials may be sent with a request to `url`
+    #[must_use]
+    pub fn applies_to(&self, url: &str) -> bool {
+        is_secure_url(url) && url_host(url) == Some(self.host.as_str())
+    }
+}
+
+/// Gets the credentials stored for a Repository, by its local name.
+///
+/// # Errors
+///
+/// - No valid config directory
+/// - Invalid credentials file
+pub fn get_repo_auth(repo_name: &str, config_path: Option<&Path>) -> Result<Option<RepoAuth>> {
+    Ok(read_repo_auths(config_path)?.remove(repo_name))
+}
+
+/// Gets the credentials stored for the Repository at `repo_path`, see [`get_repo_auth`].
+///
+/// # Errors
+///
+/// - No valid config directory
+/// - Invalid credentials file
+pub fn get_repo_auth_for(repo_path: &Path) -> Result<Option<RepoAuth>> {
+    let Some(repo_name) = repo_path.file_name() else {
+        return Ok(None);
+    };
+
+    get_repo_auth(&repo_name.to_string_lossy(), None)
+}
+
+/// Stores the credentials for a Repository by its local name, or removes them with `None`.
+/// The Repository doesn't have to be added yet, so a private one can be.
+///
+/// # Errors
+///
+/// - No valid config directory
+/// - Invalid credentials file
+/// - Filesystem errors (Permissions)
+pub fn set_repo_auth(
+    repo_name: &str,
+    auth: Option<RepoAuth>,
+    config_path: Option<&Path>,
+) -> Result<()> {
+    let mut auths = read_repo_auths(config_path)?;
+    let changed = match auth {
+        Some(auth) => auths.insert(repo_name.to_string(), auth.clone()) != Some(auth),
+        None => auths.remove(repo_name).is_some(),
+    };
+
+    if changed {
+        write_repo_auths(&auths, config_path)?;
+    }
+
+    Ok(())
+}
+
+/// Moves the credentials of a Repository to its new name, after it is renamed.
+///
+/// # Errors
+///
+/// - No valid config directory
+/// - Invalid credentials file
+/// - Filesystem errors (Permissions)
+pub fn rename_repo_auth(old_name: &str, new_name: &str, config_path: Option<&Path>) -> Result<()> {
+    let mut auths = read_repo_auths(config_path)?;
+
+    if let Some(auth) = auths.remove(old_name) {
+        auths.insert(new_name.to_string(), auth);
+        write_repo_auths(&auths, config_path)?;
+    }
+
+    Ok(())
+}
+
+fn write_repo_auths(auths: &BTreeMap<String, RepoAuth>, config_path: Option<&Path>) -> Result<()> {
+    let config_path = match config_path {
+        Some(config_path) => config_path.to_path_buf(),
+        None => get_config_dir()?,
+    };
+    let path = config_path.join(CREDENTIALS_FILE);
+    let new_path = config_path.join(format!("{CREDENTIALS_FILE}.new"));
+
+    // Restricted before anything is written to it
+    fs::write(&new_path, "")?;
+    set_mode(&new_path, 0o600)?;
+    fs::write(&new_path, serde_yaml::to_string(auths)?)?;
+    fs::rename(new_path, path)?;
+
+    Ok(())
+}
+
+fn read_repo_auths(config_path: Option<&Path>) -> Result<BTreeMap<String, RepoAuth>> {
+    let path = match config_path {
+        Some(config_path) => config_path.to_path_buf(),
+        None => get_config_dir()?,
+    }
+    .join(CREDENTIALS_FILE);
+
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+
+    serde_yaml::from_str(&fs::read_to_string(&path)?)
+        .with_context(|| format!("Invalid credentials at {}", path.display()))
+}
+
+/// Sends a GET request to `url`, authenticated with `auth` if it was issued for the url's host.
+///
+/// Otherwise the mirror's credentials from the keyring or `~/.netrc` are used, if any.
+/// No credentials are ever sent over plain http, except to this machine.
+///
+/// # Errors
+///
+/// - Network Unavailable
+/// - `~/.netrc` could not be read
+#[cfg(feature = "network")]
+pub async fn get_url(url: &str, auth: Option<&RepoAuth>) -> Result<reqwest::Response> {
+    let request = authenticate(reqwest::Client::new().get(url), url, auth)?;
+
+    Ok(request.send().await?)
+}
+
+/// Sends a POST request with `body` to `url`, authenticated like [`get_url`].
+///
+/// # Errors
+///
+/// - Network Unavailable
+/// - `~/.netrc` could not be read
+#[cfg(feature = "network")]
+pub async fn post_url(
+    url: &str,
+    body: Vec<u8>,
+    auth: Option<&RepoAuth>,
+) -> Result<reqwest::Response> {
+    let request = authenticate(reqwest::Client::new().post(url).body(body), url, auth)?;
+
+    Ok(request.send().await?)
+}
+
+#[cfg(feature = "network")]
+fn authenticate(
+    request: reqwest::RequestBuilder,
+    url: &str,
+    auth: Option<&RepoAuth>,
+) -> Result<reqwest::RequestBuilder> {
+    Ok(match auth.filter(|auth| auth.applies_to(url)) {
+        Some(auth) => match &auth.method {
+            AuthMethod::Bearer(token) => request.bearer_auth(token),
+            AuthMethod::Basic(credentials) => {
+                request.basic_auth(&credentials.login, Some(&credentials.password))
+            }
+        },
+        None if is_secure_url(url) => match get_credentials_cached(url)? {
+            Some(credentials) => request.basic_auth(credentials.login, Some(credentials.password)),
+            None => request,
+        },
+        None => request,
+    })
+}
+
+/// [`get_credentials`], looked up once per host, as a package's chunks are fetched one by one
+#[cfg(feature = "network")]
+fn get_credentials_cached(url: &str) -> Result<Option<Credentials>> {
+    use std::{
+        collections::HashMap,
+        sync::{Mutex, PoisonError},
+    };
+
+    static HOST_CREDENTIALS: Mutex<Option<HashMap<String, Option<Credentials>>>> = Mutex::new(None);
+
+    let Some(host) = url_host(url) else {
+        return Ok(None);
+    };
+
+    if let Some(credentials) = HOST_CREDENTIALS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .as_ref()
+        .and_then(|cache| cache.get(host))
+    {
+        return Ok(credentials.clone());
+    }
+
+    let credentials = get_credentials(url)?;
+    HOST_CREDENTIALS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get_or_insert_default()
+        .insert(host.to_string(), credentials.clone());
+
+    Ok(credentials)
+}
+
+/// Finds the credentials for a mirror, by the host of its url.
+///
+/// The OS keyring (see [`store_credentials`]) is tried first, then `~/.netrc`.
+/// Credentials stored for a single Repository take precedence, see [`get_url`].
+///
+/// # Errors
+///
+/// - `~/.netrc` could not be read
+pub fn get_credentials(url: &str) -> Result<Option<Credentials>> {
+    let Some(host) = url_host(url) else {
+        return Ok(None);
+    };
+
+    #[cfg(feature = "keyring")]
+    if let Some(credentials) = keyring_credentials(host) {
+        return Ok(Some(credentials));
+    }
+
+    let Some(netrc_path) = netrc_path() else {
+        return Ok(None);
+    };
+    if !netrc_path.exists() {
+        return Ok(None);
+    }
+
+    Ok(parse_netrc(&fs::read_to_string(netrc_path)?, host))
+}
+
+/// Stores the credentials for a mirror in the OS keyring (Secret Service, Keychain or Credential Manager).
+///
+/// # Errors
+///
+/// - `url` has no host
+/// - The keyring is unavailable, eg: no Secret Service is running
+/// - Flint was built without keyring support
+pub fn store_credentials(url: &str, credentials: &Credentials) -> Result<()> {
+    let Some(host) = url_host(url) else {
+        bail!("{url} has no host to log in to.")
+    };
+
+    #[cfg(feature = "keyring")]
+    {
+        let secret = serde_json::to_string(credentials)?;
+        with_keyring(|| keyring::Entry::new(KEYRING_SERVICE, host)?.set_password(&secret))?;
+
+        Ok(())
+    }
+
+    #[cfg(not(feature = "keyring"))]
+    {
+        let _ = credentials;
+        bail!(
+            "This build of Flint has no keyring support. Add a `machine {host}` entry to ~/.netrc instead."
+        )
+    }
+}
+
+/// Credentials stored in the OS keyring for `host`. A keyring that isn't available has none.
+#[cfg(feature = "keyring")]
+fn keyring_credentials(host: &str) -> Option<Credentials> {
+    let secret =
+        with_keyring(|| keyring::Entry::new(KEYRING_SERVICE, host)?.get_password()).ok()?;
+
+    serde_json::from_str(&secret).ok()
+}
+
+/// Runs `f` on its own thread. The Secret Service backend blocks on an async runtime of its own,
+/// which can't be started from within Flint's.
+#[cfg(feature = "keyring")]
+fn with_keyring<T: Send>(f: impl FnOnce() -> T + Send) -> T {
+    std::thread::scope(|scope| {
+        scope
+            .spawn(f)
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    })
+}
+
+/// `$NETRC`, or `.netrc` (`_netrc` on Windows) in the home directory
+fn netrc_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("NETRC") {
+        return Some(PathBuf::from(path));
+    }
+
+    let filename = if cfg!(windows) { "_netrc" } else { ".netrc" };
+    directories::BaseDirs::new().map(|dirs| dirs.home_dir().join(filename))
+}
+
+/// Finds the login for `host` in a netrc file, falling back to its `default` entry.
+///
+/// Macros (`macdef`) end the parse, as they can't be told apart from entries without blank lines.
+#[must_use]
+pub fn parse_netrc(contents: &str, host: &str) -> Option<Credentials> {
+    let mut found = None;
+    let mut default = None;
+
+    // The entry being read: whether it applies, and what it has so far
+    let mut current: Option<(bool, Option<String>, Option<String>)> = None;
+    let mut finish = |entry: Option<(bool, Option<String>, Option<String>)>, is_default: bool| {
+        if let Some((true, Some(login), Some(password))) = entry {
+            let credentials = Credentials { login, password };
+            if is_default {
+                default.get_or_insert(credentials);
+            } else {
+                found.get_or_insert(credentials);
+            }
+        }
+    };
+
+    let mut current_is_default = false;
+    let mut tokens = contents.split_whitespace();
+    while let Some(token) = tokens.next() {
+        match token {
+            "machine" => {
+                finish(current.take(), current_is_default);
+                current = Some((tokens.next() == Some(host), None, None));
+                current_is_default = false;
+            }
+            "default" => {
+                finish(current.take(), current_is_default);
+                current = Some((true, None, None));
+                current_is_default = true;
+            }
+            "login" => {
+                let login = tokens.next().map(str::to_string);
+                if let Some(entry) = &mut current {
+                    entry.1 = login;
+                }
+            }
+            "password" => {
+                let password = tokens.next().map(str::to_string);
+                if let Some(entry) = &mut current {
+                    entry.2 = password;
+                }
+            }
+            "account" => {
+                tokens.next();
+            }
+            "macdef" => break,
+            _ => {}
+        }
+    }
+    finish(current, current_is_default);
+
+    found.or(default)
+}
+
+/// Whether credentials can be sent to `url`: https, or plain http that never leaves this machine
+#[must_use]
+pub fn is_secure_url(url: &str) -> bool {
+    let Some((scheme, _)) = url.split_once("://") else {
+        return false;
+    };
+
+    scheme.eq_ignore_ascii_case("https")
+        || (scheme.eq_ignore_ascii_case("http")
+            && matches!(url_host(url), Some("localhost" | "127.0.0.1" | "::1")))
+}
+
+/// The host of a url, without any port or login, eg: `example.com` for `https://user@example.com:8443/repo`
+#[must_use]
+pub fn url_host(url: &str) -> Option<&str> {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host_port = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+
+    let host = if let Some(ipv6) = host_port.strip_prefix('[') {
+        ipv6.split(']').next()?
+    } else {
+        host_port.split(':').next()?
+    };
+
+    (!host.is_empty()).then_some(host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_url_host() {
+        assert_eq!(url_host("https://example.com/repo"), Some("example.com"));
+        assert_eq!(
+            url_host("https://user@mirror.example.com:8443"),
+            Some("mirror.example.com")
+        );
+        assert_eq!(url_host("http://[::1]:8080/repo"), Some("::1"));
+        assert_eq!(url_host("https:///repo"), None);
+    }
+
+    #[test]
+    fn test_repo_auth() -> anyhow::Result<()> {
+        let config = temp_dir::TempDir::new()?;
+        let config_path = Some(config.path());
+
+        assert_eq!(get_repo_auth("private", config_path)?, None);
+
+        let token = RepoAuth::new("https://example.com", AuthMethod::Bearer("t0ken".into()))?;
+        assert!(token.applies_to("https://example.com:8443/manifest.yml"));
+        assert!(!token.applies_to("https://mirror.example.com/manifest.yml"));
+        assert!(!token.applies_to("http://example.com/manifest.yml"));
+        assert!(RepoAuth::new("http://example.com", AuthMethod::Bearer("t0ken".into())).is_err());
+
+        set_repo_auth("private", Some(token.clone()), config_path)?;
+        set_repo_auth(
+            "other",
+            Some(RepoAuth::new(
+                "https://example.com",
+                AuthMethod::Basic(Credentials {
+                    login: "ci".into(),
+                    password: "s3cret".into(),
+                }),
+            )?),
+            config_path,
+        )?;
+        assert_eq!(get_repo_auth("private", config_path)?, Some(token.clone()));
+        assert!(!format!("{:?}", get_repo_auth("private", config_path)?).contains("t0ken"));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let mode = fs::metadata(config.path().join(CREDENTIALS_FILE))?
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        rename_repo_auth("private", "renamed", config_path)?;
+        assert_eq!(get_repo_auth("private", config_path)?, None);
+        assert_eq!(get_repo_auth("renamed", config_path)?, Some(token));
+
+        set_repo_auth("renamed", None, config_path)?;
+        assert_eq!(get_repo_auth("renamed", config_path)?, None);
+        assert!(get_repo_auth("other", config_path)?.is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_netrc() {
+        let netrc = "machine other.example.com login someone password nope\n\
+            machine example.com\n  login ci\n  account team\n  password s3cret\n\
+            default login anonymous password guest\n";
+
+        assert_eq!(
+            parse_netrc(netrc, "example.com"),
+            Some(Credentials {
+                login: "ci".into(),
+                password: "s3cret".into(),
+            })
+        );
+        assert_eq!(
+            parse_netrc(netrc, "unknown.example.com").map(|credentials| credentials.login),
+            Some("anonymous".into())
+        );
+        assert_eq!(
+            parse_netrc("machine example.com login ci", "example.com"),
+            None
+        );
+        assert!(!format!("{:?}", parse_netrc(netrc, "example.com")).contains("s3cret"));
+    }
+}
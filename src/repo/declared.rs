@@ -0,0 +1,297 @@
+use anyhow::{Context, Result, bail};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    config::get_config_dir,
+    crypto::key::deserialize_verifying_key,
+    repo::{
+        manifest_io::has_manifest,
+        read_manifest,
+        settings::{get_settings, set_settings},
+    },
+};
+
+/// Remote Repositories to keep added, in the config directory
+const REPOS_FILE: &str = "repos.yml";
+/// More of them, eg: one file per team, read after `repos.yml` in filename order
+const REPOS_DIR: &str = "repos.d";
+
+/// A remote Repository declared in `repos.yml` or `repos.d/*.yml`, by name
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct DeclaredRepo {
+    pub url: String,
+    /// PEM public key the Repository must be signed with. Without one, the key is trusted on first use
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<String>,
+    /// Priority to give the Repository, see [`crate::repo::settings::RepoSettings::priority`]
+    #[serde(default)]
+    pub priority: i32,
+}
+
+/// What it takes to bring a repos directory in line with the declared Repositories
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReconcilePlan {
+    /// Declared, but not added yet
+    pub add: Vec<String>,
+    /// Added from a declaration that no longer exists
+    pub remove: Vec<String>,
+    /// Added, but with another priority than declared
+    pub prioritize: Vec<(String, i32)>,
+    /// Added, but signed with another key than declared. Left alone
+    pub mismatched: Vec<String>,
+}
+
+/// Reads the declared Repositories from `config_path`, or the default config directory.
+/// A Repository declared again in a later file replaces the earlier declaration.
+///
+/// # Errors
+///
+/// - No valid home directory path could be retrieved from the operating system.
+/// - Invalid files, or invalid Repository names
+///
+/// # Returns
+///
+/// `None` if there is neither `repos.yml` nor `repos.d`, so nothing is managed
+pub fn read_declared_repos(
+    config_path: Option<&Path>,
+) -> Result<Option<BTreeMap<String, DeclaredRepo>>> {
+    let config_dir = if let Some(config_path) = config_path {
+        config_path.to_path_buf()
+    } else {
+        get_config_dir()?
+    };
+
+    let mut files: Vec<PathBuf> = Vec::new();
+    let repos_dir = config_dir.join(REPOS_DIR);
+    if repos_dir.is_dir() {
+        for entry in fs::read_dir(&repos_dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == "yml") {
+                files.push(path);
+            }
+        }
+        files.sort();
+    }
+    if config_dir.join(REPOS_FILE).exists() {
+        files.insert(0, config_dir.join(REPOS_FILE));
+    } else if !repos_dir.is_dir() {
+        return Ok(None);
+    }
+
+    let mut declared = BTreeMap::new();
+    for path in files {
+        let repos: BTreeMap<String, DeclaredRepo> =
+            serde_yaml::from_str(&fs::read_to_string(&path)?)
+                .with_context(|| format!("Invalid Repositories at {}", path.display()))?;
+
+        for name in repos.keys() {
+            if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+                bail!("Invalid Repository name {name} in {}", path.display())
+            }
+        }
+
+        declared.extend(repos);
+    }
+
+    Ok(Some(declared))
+}
+
+/// Compares the Repositories in `base_path` with the declared ones.
+///
+/// Only Repositories added from a declaration are ever removed, so ones the user added
+/// or created by hand are left alone. So are Repositories signed with another key than declared.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+/// - Invalid settings
+pub fn plan_reconcile(
+    base_path: &Path,
+    declared: &BTreeMap<String, DeclaredRepo>,
+) -> Result<ReconcilePlan> {
+    let mut plan = ReconcilePlan::default();
+
+    for (name, repo) in declared {
+        let repo_path = base_path.join(name);
+
+        if !has_manifest(&repo_path) {
+            plan.add.push(name.clone());
+            continue;
+        }
+
+        if let Some(public_key) = &repo.public_key
+            && deserialize_verifying_key(&read_manifest(&repo_path)?.public_key)?
+                != deserialize_verifying_key(public_key)?
+        {
+            plan.mismatched.push(name.clone());
+            continue;
+        }
+
+        if get_settings(&repo_path)?.priority != repo.priority {
+            plan.prioritize.push((name.clone(), repo.priority));
+        }
+    }
+
+    if base_path.exists() {
+        let mut entries: Vec<_> = fs::read_dir(base_path)?.collect::<Result<_, _>>()?;
+        entries.sort_by_key(fs::DirEntry::file_name);
+
+        for entry in entries {
+            let name = entry.file_name().to_string_lossy().to_string();
+
+            if !declared.contains_key(&name) && get_settings(&entry.path())?.declared {
+                plan.remove.push(name);
+            }
+        }
+    }
+
+    Ok(plan)
+}
+
+/// Sets the priority of a Repository, and marks it as added from a declaration.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+/// - Invalid settings
+pub fn mark_declared(repo_path: &Path, priority: i32) -> Result<()> {
+    let mut settings = get_settings(repo_path)?;
+    settings.priority = priority;
+    settings.declared = true;
+
+    set_settings(repo_path, &settings)
+}
+
+/// Adds a declared Repository at `repo_path`, pins its key, and marks it as declared.
+///
+/// # Errors
+///
+/// - Network Unavailable
+/// - Server Unavailable
+/// - Invalid signed data, or not signed with the declared key
+/// - The url served another key before, see [`KeyPins::check`]
+/// - Filesystem errors (Permissions)
+///
+/// # Returns
+///
+/// The manifest of the added Repository
+#[cfg(feature = "network")]
+pub async fn add_declared_repo(
+    repo_path: &Path,
+    declared: &DeclaredRepo,
+    pins: &mut crate::crypto::pins::KeyPins,
+    allow_newer_edition: bool,
+) -> Result<crate::repo::RepoManifest> {
+    let verifying_key = declared
+        .public_key
+        .as_deref()
+        .map(deserialize_verifying_key)
+        .transpose()?;
+
+    let fetched = crate::repo::network::fetch_repository(
+        &declared.url,
+        verifying_key,
+        allow_newer_edition,
+        crate::repo::credentials::get_repo_auth_for(repo_path)?.as_ref(),
+    )
+    .await?;
+    fetched.check_pin(pins, &declared.url)?;
+
+    if let Some(verifying_key) = verifying_key
+        && deserialize_verifying_key(&fetched.manifest.public_key)? != verifying_key
+    {
+        bail!(
+            "{} is signed with another key than declared for it.",
+            declared.url
+        )
+    }
+
+    fs::create_dir_all(repo_path)?;
+    let saved = fetched
+        .save(repo_path)
+        .and_then(|()| mark_declared(repo_path, declared.priority));
+    if let Err(err) = saved {
+        fs::remove_dir_all(repo_path)?;
+        return Err(err);
+    }
+
+    pins.pin(&declared.url, &fetched.manifest.public_key)?;
+
+    Ok(fetched.manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::create_repo;
+    use temp_dir::TempDir;
+
+    #[test]
+    fn test_reconcile() -> Result<()> {
+        let root = TempDir::new()?;
+        let config = &root.path().join("config");
+        let repos = &root.path().join("repos");
+        fs::create_dir_all(config.join(REPOS_DIR))?;
+
+        assert_eq!(read_declared_repos(Some(root.path()))?, None);
+
+        fs::write(
+            config.join(REPOS_FILE),
+            "main:\n  url: https://main.example\n  priority: 10\nold:\n  url: https://old.example\n",
+        )?;
+        fs::write(
+            config.join("repos.d/team.yml"),
+            "team:\n  url: https://team.example\n",
+        )?;
+        // Not a declaration file
+        fs::write(config.join("repos.d/notes.txt"), "team: {}")?;
+
+        let declared = read_declared_repos(Some(config))?.unwrap_or_default();
+        assert_eq!(
+            declared.keys().collect::<Vec<_>>(),
+            vec!["main", "old", "team"]
+        );
+
+        create_repo(&repos.join("main"), Some(config))?;
+        create_repo(&repos.join("old"), Some(config))?;
+        mark_declared(&repos.join("old"), 0)?;
+        // Added by hand
+        create_repo(&repos.join("local"), Some(config))?;
+
+        let mut declared = declared;
+        declared.remove("old");
+        let plan = plan_reconcile(repos, &declared)?;
+        assert_eq!(
+            plan,
+            ReconcilePlan {
+                add: vec!["team".into()],
+                remove: vec!["old".into()],
+                prioritize: vec![("main".into(), 10)],
+                mismatched: Vec::new(),
+            }
+        );
+
+        // Declared with a key it isn't signed with
+        let other_key = &root.path().join("other");
+        fs::create_dir(other_key)?;
+        create_repo(other_key, Some(other_key))?;
+        declared.get_mut("main").expect("declared above").public_key =
+            Some(read_manifest(other_key)?.public_key);
+        let plan = plan_reconcile(repos, &declared)?;
+        assert_eq!(plan.mismatched, vec!["main".to_string()]);
+        // Nothing else about it is touched either
+        assert!(plan.prioritize.is_empty());
+
+        fs::write(
+            config.join("repos.d/bad.yml"),
+            "../escape:\n  url: https://evil.example\n",
+        )?;
+        assert!(read_declared_repos(Some(config)).is_err());
+
+        Ok(())
+    }
+}
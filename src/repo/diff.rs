@@ -0,0 +1,131 @@
+use crate::repo::{PackageManifest, RepoManifest};
+
+/// A package that is in both manifests, but differs
+#[derive(serde::Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ChangedPackage {
+    pub id: String,
+    pub old_version: Option<String>,
+    pub new_version: Option<String>,
+    /// Whether the package's files changed, rather than only its metadata
+    pub files_changed: bool,
+}
+
+/// How a newer manifest of a Repository differs from an older one
+#[derive(serde::Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct RepoDiff {
+    pub old_serial: u64,
+    pub new_serial: u64,
+    pub public_key_changed: bool,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<ChangedPackage>,
+}
+
+impl RepoDiff {
+    /// Whether updating would change any package
+    #[must_use]
+    pub const fn has_changes(&self) -> bool {
+        !self.added.is_empty() || !self.removed.is_empty() || !self.changed.is_empty()
+    }
+}
+
+/// Compares the packages of two manifests of the same Repository, eg: the local copy and a mirror's.
+#[must_use]
+pub fn diff_manifests(old: &RepoManifest, new: &RepoManifest) -> RepoDiff {
+    let mut diff = RepoDiff {
+        old_serial: old.serial,
+        new_serial: new.serial,
+        public_key_changed: old.public_key != new.public_key,
+        ..RepoDiff::default()
+    };
+
+    for package in &new.packages {
+        match find(old, &package.id) {
+            None => diff.added.push(package.id.clone()),
+            Some(old_package) if old_package != package => {
+                diff.changed.push(ChangedPackage {
+                    id: package.id.clone(),
+                    old_version: old_package.metadata.version.clone(),
+                    new_version: package.metadata.version.clone(),
+                    files_changed: old_package.chunks != package.chunks,
+                });
+            }
+            Some(_) => {}
+        }
+    }
+
+    diff.removed = old
+        .packages
+        .iter()
+        .filter(|package| find(new, &package.id).is_none())
+        .map(|package| package.id.clone())
+        .collect();
+
+    diff.added.sort();
+    diff.removed.sort();
+    diff.changed.sort_by(|a, b| a.id.cmp(&b.id));
+
+    diff
+}
+
+fn find<'a>(manifest: &'a RepoManifest, id: &str) -> Option<&'a PackageManifest> {
+    manifest.packages.iter().find(|package| package.id == id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::{Metadata, create_repo, read_manifest};
+    use anyhow::Result;
+    use temp_dir::TempDir;
+
+    #[test]
+    fn test_diff_manifests() -> Result<()> {
+        let repo = TempDir::new()?;
+        create_repo(repo.path(), Some(repo.path()))?;
+
+        let package = |id: &str, version: &str, build_hash: &str| PackageManifest {
+            metadata: Metadata {
+                version: Some(version.into()),
+                ..Default::default()
+            },
+            id: id.into(),
+            build_hash: build_hash.into(),
+            ..Default::default()
+        };
+
+        let mut old = read_manifest(repo.path())?;
+        old.packages = vec![
+            package("kept", "1.0", "a"),
+            package("gone", "1.0", "a"),
+            package("bumped", "1.0", "a"),
+        ];
+        let mut new = old.clone();
+        new.serial += 1;
+        new.packages = vec![
+            package("new", "0.1", "a"),
+            package("bumped", "1.1", "b"),
+            package("kept", "1.0", "a"),
+        ];
+
+        let diff = diff_manifests(&old, &new);
+        assert!(diff.has_changes());
+        assert!(!diff.public_key_changed);
+        assert_eq!(diff.new_serial, diff.old_serial + 1);
+        assert_eq!(diff.added, vec!["new".to_string()]);
+        assert_eq!(diff.removed, vec!["gone".to_string()]);
+        assert_eq!(
+            diff.changed,
+            vec![ChangedPackage {
+                id: "bumped".into(),
+                old_version: Some("1.0".into()),
+                new_version: Some("1.1".into()),
+                files_changed: false,
+            }]
+        );
+
+        assert!(!diff_manifests(&old, &old).has_changes());
+
+        Ok(())
+    }
+}
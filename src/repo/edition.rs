@@ -0,0 +1,267 @@
+use anyhow::{Result, bail};
+use std::{cmp::Ordering, fmt::Write};
+
+use crate::repo::RepoManifest;
+
+/// The newest manifest edition this client understands
+pub const CLIENT_EDITION: &str = "2027";
+
+/// Every manifest edition this client can read
+pub const SUPPORTED_EDITIONS: &[&str] = &["2025", "2026", "2027"];
+
+/// Edition new Repositories start at, so older clients can still use them
+pub const DEFAULT_EDITION: &str = "2025";
+
+/// From this edition on, Repositories also publish their manifest as CBOR, which is much faster to parse
+pub const BINARY_MANIFEST_EDITION: &str = "2026";
+
+/// From this edition on, mirrors serve chunks zstd compressed when it pays off.
+/// Clients from before it would hash the compressed bytes, so they refuse the edition instead.
+pub const COMPRESSED_CHUNKS_EDITION: &str = "2027";
+
+/// Whether Repositories of `edition` publish a CBOR manifest next to the YAML one
+#[must_use]
+pub fn publishes_binary_manifest(edition: &str) -> bool {
+    compare_editions(edition, BINARY_MANIFEST_EDITION) != Ordering::Less
+}
+
+/// Whether mirrors of Repositories of `edition` may serve compressed chunks
+#[must_use]
+pub fn serves_compressed_chunks(edition: &str) -> bool {
+    compare_editions(edition, COMPRESSED_CHUNKS_EDITION) != Ordering::Less
+}
+
+/// Paths of the fields of a raw manifest this client doesn't know, eg: `packages[0].sandbox`.
+/// They are kept when re-signing, but ignored otherwise.
+///
+/// Found by deserializing it into a `RepoManifest`, so nested fields are covered too,
+/// but fields past the first one that fails to parse are not.
+#[must_use]
+pub fn unknown_fields(raw_manifest: &serde_yaml::Value) -> Vec<String> {
+    let mut fields = Vec::new();
+    // A failed parse is reported by the typed parse itself
+    let _ = serde_ignored::deserialize::<_, _, RepoManifest>(raw_manifest.clone(), |path| {
+        fields.push(field_path(&path));
+    });
+
+    fields
+}
+
+/// Formats `path` like `serde_yaml` does in its errors, eg: `packages[0].sandbox`
+fn field_path(path: &serde_ignored::Path) -> String {
+    match path {
+        serde_ignored::Path::Root => String::new(),
+        serde_ignored::Path::Seq { parent, index } => format!("{}[{index}]", field_path(parent)),
+        serde_ignored::Path::Map { parent, key } => {
+            let parent = field_path(parent);
+            if parent.is_empty() {
+                key.clone()
+            } else {
+                format!("{parent}.{key}")
+            }
+        }
+        serde_ignored::Path::Some { parent }
+        | serde_ignored::Path::NewtypeStruct { parent }
+        | serde_ignored::Path::NewtypeVariant { parent } => field_path(parent),
+    }
+}
+
+/// Turns a failed typed parse of a manifest into an error naming the field, its line
+/// when `error` has one, the manifest's edition, and the fields this client doesn't know.
+#[must_use]
+pub fn explain_manifest_error(
+    raw_manifest: &serde_yaml::Value,
+    error: &serde_yaml::Error,
+) -> anyhow::Error {
+    let edition = raw_edition(raw_manifest);
+    let detail = error.to_string();
+    let mut message = format!(
+        "Invalid manifest of edition {}: {detail}",
+        edition.as_deref().unwrap_or("unknown")
+    );
+
+    let unknown = unknown_fields(raw_manifest);
+    if !unknown.is_empty() {
+        // Writing to a String can't fail
+        let _ = write!(
+            message,
+            ". Not part of any edition this version of Flint supports (up to {CLIENT_EDITION}): {}",
+            unknown.join(", ")
+        );
+
+        if let Some(edition) = edition
+            && compare_editions(&edition, CLIENT_EDITION) == Ordering::Greater
+        {
+            let _ = write!(
+                message,
+                ", probably introduced by edition {edition}. Update Flint."
+            );
+        } else {
+            message.push_str(", they may come from a newer version of Flint.");
+        }
+    }
+
+    anyhow::anyhow!(message)
+}
+
+/// Compares two editions. Editions are years, but anything else falls back to string ordering.
+#[must_use]
+pub fn compare_editions(a: &str, b: &str) -> Ordering {
+    match (a.parse::<u32>(), b.parse::<u32>()) {
+        (Ok(a), Ok(b)) => a.cmp(&b),
+        _ => a.cmp(b),
+    }
+}
+
+/// The `edition` of a manifest that hasn't been parsed into a `RepoManifest` yet
+#[must_use]
+pub fn raw_edition(raw_manifest: &serde_yaml::Value) -> Option<String> {
+    match raw_manifest.get("edition")? {
+        serde_yaml::Value::String(edition) => Some(edition.clone()),
+        serde_yaml::Value::Number(edition) => Some(edition.to_string()),
+        _ => None,
+    }
+}
+
+/// Rejects manifests of an edition this client does not understand.
+/// Unknown fields are fine, an unknown edition means their meaning may have changed.
+///
+/// # Errors
+///
+/// - Unsupported edition
+pub fn check_manifest_edition(raw_manifest: &serde_yaml::Value) -> Result<()> {
+    // Missing editions are reported by the typed parse
+    let Some(edition) = raw_edition(raw_manifest) else {
+        return Ok(());
+    };
+
+    if !SUPPORTED_EDITIONS.contains(&edition.as_str()) {
+        if compare_editions(&edition, CLIENT_EDITION) == Ordering::Greater {
+            bail!(
+                "Manifest edition {edition} is newer than this version of Flint supports ({CLIENT_EDITION}). Update Flint."
+            )
+        }
+
+        bail!("Unsupported manifest edition {edition}.")
+    }
+
+    Ok(())
+}
+
+/// Checks a raw manifest's `min_client_edition` against `CLIENT_EDITION`.
+/// Runs before typed deserialization, so a too-new manifest gives a clear error instead of a serde one.
+///
+/// Manifests let through by `allow_newer` can be told apart later with [`requires_newer_client`].
+///
+/// # Errors
+///
+/// - The Repository requires a newer client, and `allow_newer` is not set
+pub fn check_client_edition(raw_manifest: &serde_yaml::Value, allow_newer: bool) -> Result<()> {
+    let Some(min_edition) = raw_manifest
+        .get("min_client_edition")
+        .and_then(serde_yaml::Value::as_str)
+    else {
+        return Ok(());
+    };
+
+    if !allow_newer && compare_editions(min_edition, CLIENT_EDITION) == Ordering::Greater {
+        bail!(
+            "Repository requires Flint edition {min_edition}, but this is edition {CLIENT_EDITION}. Update Flint, or use --ignore-edition to try anyway."
+        )
+    }
+
+    Ok(())
+}
+
+/// The client edition a Repository requires, if it is newer than `CLIENT_EDITION`.
+/// Some of its packages may not work.
+#[must_use]
+pub fn requires_newer_client(repo_manifest: &RepoManifest) -> Option<&str> {
+    repo_manifest
+        .min_client_edition
+        .as_deref()
+        .filter(|min_edition| compare_editions(min_edition, CLIENT_EDITION) == Ordering::Greater)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_client_edition() {
+        let check = |raw: &str, allow_newer| -> Result<()> {
+            check_client_edition(&serde_yaml::from_str(raw)?, allow_newer)
+        };
+
+        assert!(check("edition: '2025'\n", false).is_ok());
+        assert!(check("min_client_edition: '2025'\n", false).is_ok());
+        assert!(check("min_client_edition: '2099'\n", false).is_err());
+        assert!(check("min_client_edition: '2099'\n", true).is_ok());
+    }
+
+    #[test]
+    fn test_requires_newer_client() -> Result<()> {
+        let repo = temp_dir::TempDir::new()?;
+        crate::repo::create_repo(repo.path(), Some(repo.path()))?;
+        let mut manifest = crate::repo::read_manifest(repo.path())?;
+        assert_eq!(requires_newer_client(&manifest), None);
+
+        manifest.min_client_edition = Some("2099".into());
+        assert_eq!(requires_newer_client(&manifest), Some("2099"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_explain_manifest_error() -> Result<()> {
+        let raw =
+            "edition: '2099'\nfuture_field: 1\npackages:\n  - id: hello\n    sandbox: strict\n";
+        let value: serde_yaml::Value = serde_yaml::from_str(raw)?;
+        assert_eq!(
+            unknown_fields(&value),
+            vec!["future_field", "packages[0].sandbox"]
+        );
+
+        let error =
+            serde_yaml::from_str::<crate::repo::RepoManifest>(raw).expect_err("missing fields");
+        let message = explain_manifest_error(&value, &error).to_string();
+        assert!(message.contains("edition 2099"));
+        assert!(message.contains("packages[0].sandbox"));
+        assert!(message.contains("Update Flint"));
+
+        let raw = "edition: '2025'\npackages:\n  - id: hello\n    chunks: nope\n";
+        let error =
+            serde_yaml::from_str::<crate::repo::RepoManifest>(raw).expect_err("chunks is a list");
+        let message = explain_manifest_error(&serde_yaml::from_str(raw)?, &error).to_string();
+        assert!(message.contains("line 4"), "{message}");
+        assert!(message.contains("edition 2025"), "{message}");
+
+        let raw = "edition: '2025'\nmetadata:\n  rating: 5\npackages:\n  - id: hello\n    requirements:\n      gpu: true\n";
+        assert_eq!(
+            unknown_fields(&serde_yaml::from_str(raw)?),
+            vec!["metadata.rating", "packages[0].requirements.gpu"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_publishes_binary_manifest() {
+        assert!(!publishes_binary_manifest(DEFAULT_EDITION));
+        assert!(publishes_binary_manifest(BINARY_MANIFEST_EDITION));
+        assert!(publishes_binary_manifest("2030"));
+    }
+
+    #[test]
+    fn test_serves_compressed_chunks() {
+        assert!(!serves_compressed_chunks(DEFAULT_EDITION));
+        assert!(!serves_compressed_chunks(BINARY_MANIFEST_EDITION));
+        assert!(serves_compressed_chunks(COMPRESSED_CHUNKS_EDITION));
+    }
+
+    #[test]
+    fn test_compare_editions() {
+        assert_eq!(compare_editions("2025", "2026"), Ordering::Less);
+        assert_eq!(compare_editions("10000", "2025"), Ordering::Greater);
+    }
+}
@@ -0,0 +1,162 @@
+use anyhow::{Result, bail};
+use std::{collections::HashSet, fs, path::Path};
+
+use crate::{
+    chunks::{Chunk, compress::serve_chunk, import_chunks, missing_chunks},
+    repo::{edition::serves_compressed_chunks, manifest_io::ManifestFormat, read_manifest},
+};
+
+/// Writes a static mirror of a Repository into `out_path`: its signed manifest, and only the chunks
+/// its packages use. Chunks left over from earlier exports are removed, so the tree can be synced as is.
+///
+/// Chunks are written compressed if the Repository's edition allows it, otherwise hard linked when possible.
+///
+/// # Errors
+///
+/// - Chunks missing from the chunk store
+/// - Filesystem errors (Out of space, Permissions)
+///
+/// # Returns
+///
+/// The number of chunks newly written
+pub fn export_repo(repo_path: &Path, chunk_store_path: &Path, out_path: &Path) -> Result<usize> {
+    let manifest = read_manifest(repo_path)?;
+    let out_chunks_path = &out_path.join("chunks");
+    fs::create_dir_all(out_chunks_path)?;
+
+    let chunks: Vec<Chunk> = manifest
+        .packages
+        .iter()
+        .flat_map(|package| package.chunks.iter().cloned())
+        .collect();
+
+    let exported = if serves_compressed_chunks(&manifest.edition) {
+        export_compressed_chunks(&chunks, out_chunks_path, chunk_store_path)?
+    } else {
+        import_chunks(&chunks, out_chunks_path, chunk_store_path)?
+    };
+
+    let missing = missing_chunks(&chunks, out_chunks_path);
+    if !missing.is_empty() {
+        bail!(
+            "{} chunks are missing from the chunk store, download every package before exporting.",
+            missing.len()
+        )
+    }
+
+    let used: HashSet<String> = chunks.iter().map(Chunk::filename).collect();
+    for entry in fs::read_dir(out_chunks_path)? {
+        let entry = entry?;
+
+        if !used.contains(&*entry.file_name().to_string_lossy()) {
+            fs::remove_file(entry.path())?;
+        }
+    }
+
+    // Copied as is, re-serializing would break the signature.
+    // The CBOR manifest only exists from its edition on, `.sig.next` only mid key rotation.
+    for format in [ManifestFormat::Yaml, ManifestFormat::Cbor] {
+        let signature_filename = format.signature_filename();
+
+        for filename in [
+            format.filename(),
+            signature_filename,
+            &format!("{signature_filename}.next"),
+        ] {
+            if repo_path.join(filename).exists() {
+                fs::copy(repo_path.join(filename), out_path.join(filename))?;
+            } else if out_path.join(filename).exists() {
+                fs::remove_file(out_path.join(filename))?;
+            }
+        }
+    }
+
+    Ok(exported)
+}
+
+/// Writes chunks missing from a static mirror as [`serve_chunk`] would serve them.
+/// Chunks missing from the chunk store are skipped.
+fn export_compressed_chunks(
+    chunks: &[Chunk],
+    out_chunks_path: &Path,
+    chunk_store_path: &Path,
+) -> Result<usize> {
+    let mut exported = 0;
+
+    for chunk in chunks {
+        let filename = chunk.filename();
+        let chunk_path = chunk_store_path.join(&filename);
+
+        if out_chunks_path.join(&filename).exists() || !chunk_path.exists() {
+            continue;
+        }
+
+        fs::write(
+            out_chunks_path.join(&filename),
+            serve_chunk(&chunk_path, true)?,
+        )?;
+        exported += 1;
+    }
+
+    Ok(exported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chunks::save_tree,
+        repo::{PackageManifest, create_repo, insert_package, subscription::set_subscription},
+    };
+    use temp_dir::TempDir;
+
+    #[test]
+    fn test_export_repo() -> Result<()> {
+        let repo = TempDir::new()?;
+        let chunk_store = TempDir::new()?;
+        let tree = TempDir::new()?;
+        let out = TempDir::new()?;
+        create_repo(repo.path(), Some(repo.path()))?;
+
+        fs::write(tree.path().join("hello"), "hello")?;
+        let hash_kind = read_manifest(repo.path())?.hash_kind;
+        let package = PackageManifest {
+            id: "hello".into(),
+            chunks: save_tree(tree.path(), chunk_store.path(), hash_kind)?,
+            ..Default::default()
+        };
+        insert_package(&package, repo.path(), Some(repo.path()))?;
+
+        // Not used by any package
+        fs::write(chunk_store.path().join("unused"), "unused")?;
+        fs::create_dir_all(out.path().join("chunks"))?;
+        fs::write(out.path().join("chunks/stale"), "stale")?;
+
+        assert_eq!(export_repo(repo.path(), chunk_store.path(), out.path())?, 1);
+
+        // The export is a valid Repository on its own
+        assert_eq!(read_manifest(out.path())?.packages, vec![package.clone()]);
+        let files: Vec<String> = fs::read_dir(out.path().join("chunks"))?
+            .map(|entry| Ok(entry?.file_name().to_string_lossy().to_string()))
+            .collect::<Result<_>>()?;
+        assert_eq!(files, vec![package.chunks[0].filename()]);
+
+        // Exporting again has nothing new to write
+        assert_eq!(export_repo(repo.path(), chunk_store.path(), out.path())?, 0);
+
+        // Whatever the maintainer is subscribed to, the whole Repository is published
+        set_subscription(repo.path(), &["other".into()])?;
+        assert_eq!(export_repo(repo.path(), chunk_store.path(), out.path())?, 0);
+        assert!(
+            out.path()
+                .join("chunks")
+                .join(package.chunks[0].filename())
+                .exists()
+        );
+
+        fs::remove_file(chunk_store.path().join(package.chunks[0].filename()))?;
+        assert!(export_repo(repo.path(), chunk_store.path(), TempDir::new()?.path()).is_err());
+
+        Ok(())
+    }
+}
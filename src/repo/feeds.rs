@@ -0,0 +1,62 @@
+use anyhow::{Result, bail};
+
+use crate::{
+    crypto::key::deserialize_verifying_key,
+    repo::{IncludedFeed, mirrors::normalize_mirror_url},
+};
+
+impl IncludedFeed {
+    /// Validates a feed to include in a Repository.
+    ///
+    /// # Errors
+    ///
+    /// - `name` is not only letters, digits, `-` and `_`
+    /// - Invalid URL
+    /// - Invalid public key
+    pub fn new(name: &str, url: &str, public_key: &str) -> Result<Self> {
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|char| char.is_ascii_alphanumeric() || char == '-' || char == '_')
+        {
+            bail!("Feed names can only contain letters, digits, '-' and '_': {name}")
+        }
+
+        deserialize_verifying_key(public_key)?;
+
+        Ok(Self {
+            name: name.to_string(),
+            url: normalize_mirror_url(url)?,
+            public_key: public_key.to_string(),
+        })
+    }
+
+    /// Name of the local Repository this feed is added as, next to the one including it
+    #[must_use]
+    pub fn repo_name(&self, including_repo_name: &str) -> String {
+        format!("{including_repo_name}.{}", self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::{create_repo, read_manifest};
+    use temp_dir::TempDir;
+
+    #[test]
+    fn test_included_feed() -> Result<()> {
+        let repo = TempDir::new()?;
+        create_repo(repo.path(), Some(repo.path()))?;
+        let public_key = read_manifest(repo.path())?.public_key;
+
+        let feed = IncludedFeed::new("team-a", "https://team-a.example/", &public_key)?;
+        assert_eq!(feed.url, "https://team-a.example");
+        assert_eq!(feed.repo_name("org"), "org.team-a");
+
+        assert!(IncludedFeed::new("../escape", "https://team-a.example", &public_key).is_err());
+        assert!(IncludedFeed::new("team-a", "https://team-a.example", "not a key").is_err());
+
+        Ok(())
+    }
+}
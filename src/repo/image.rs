@@ -0,0 +1,277 @@
+use anyhow::{Context, Result, bail};
+use std::{
+    ffi::OsStr,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::utils::platform::same_device;
+
+/// Compressed read-only image formats a package version can be stored as, instead of a tree of files.
+/// Images are mounted over the version's directory when the package is run.
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    /// Needs `mksquashfs`, and `squashfuse` to mount without root
+    Squashfs,
+    /// Needs `mkfs.erofs`, and `erofsfuse` to mount without root
+    Erofs,
+}
+
+impl ImageFormat {
+    const ALL: [Self; 2] = [Self::Squashfs, Self::Erofs];
+
+    const fn extension(self) -> &'static str {
+        match self {
+            Self::Squashfs => "squashfs",
+            Self::Erofs => "erofs",
+        }
+    }
+}
+
+/// `<version>.<format>`, next to the version's directory
+#[must_use]
+pub fn image_path(version_path: &Path, format: ImageFormat) -> PathBuf {
+    let mut path = version_path.as_os_str().to_owned();
+    path.push(".");
+    path.push(format.extension());
+
+    PathBuf::from(path)
+}
+
+/// Finds the image a version is stored as, following `installed/<package_id>` links.
+#[must_use]
+pub fn find_image(version_path: &Path) -> Option<(PathBuf, ImageFormat)> {
+    let version_path = version_path.canonicalize().ok()?;
+
+    ImageFormat::ALL
+        .into_iter()
+        .map(|format| (image_path(&version_path, format), format))
+        .find(|(path, _)| path.is_file())
+}
+
+/// Packs a version's tree into an image, then empties the tree.
+/// `install.meta` is kept in both, so it can be read whether or not the image is mounted.
+///
+/// # Errors
+///
+/// - The image tools are not installed, or failed
+/// - Filesystem errors (Out of space, Permissions)
+///
+/// # Returns
+///
+/// The size of the image in bytes
+pub fn pack_image(version_path: &Path, format: ImageFormat) -> Result<u64> {
+    let image = image_path(version_path, format);
+    let mut tmp_image = image.as_os_str().to_owned();
+    tmp_image.push(".tmp");
+
+    match format {
+        ImageFormat::Squashfs => run_tool(
+            "mksquashfs",
+            &[
+                version_path.as_os_str(),
+                &tmp_image,
+                "-noappend".as_ref(),
+                "-quiet".as_ref(),
+                "-comp".as_ref(),
+                "zstd".as_ref(),
+            ],
+        ),
+        ImageFormat::Erofs => run_tool(
+            "mkfs.erofs",
+            &["-zlz4hc".as_ref(), &tmp_image, version_path.as_os_str()],
+        ),
+    }
+    .inspect_err(|_| {
+        let _ = fs::remove_file(&tmp_image);
+    })?;
+    fs::rename(&tmp_image, &image)?;
+
+    for entry in fs::read_dir(version_path)? {
+        let entry = entry?;
+
+        if entry.file_name() == "install.meta" {
+            continue;
+        }
+
+        if entry.file_type()?.is_dir() {
+            fs::remove_dir_all(entry.path())?;
+        } else {
+            fs::remove_file(entry.path())?;
+        }
+    }
+
+    Ok(fs::metadata(image)?.len())
+}
+
+/// Whether something is mounted at `path`
+#[must_use]
+pub fn is_mounted(path: &Path) -> bool {
+    let (Ok(metadata), Some(Ok(parent_metadata))) = (
+        fs::metadata(path),
+        path.canonicalize()
+            .ok()
+            .and_then(|path| path.parent().map(fs::metadata)),
+    ) else {
+        return false;
+    };
+
+    !same_device(&metadata, &parent_metadata)
+}
+
+/// Mounts a version's image over its directory, if it is stored as one and not mounted yet.
+/// FUSE is tried first, so no root is needed.
+///
+/// # Errors
+///
+/// - Neither the FUSE tool, nor `mount` could mount the image
+///
+/// # Returns
+///
+/// Whether this call mounted it
+pub fn mount_image(version_path: &Path) -> Result<bool> {
+    let Some((image, format)) = find_image(version_path) else {
+        return Ok(false);
+    };
+    let version_path = &version_path.canonicalize()?;
+
+    if is_mounted(version_path) {
+        return Ok(false);
+    }
+
+    let fuse_tool = match format {
+        ImageFormat::Squashfs => "squashfuse",
+        ImageFormat::Erofs => "erofsfuse",
+    };
+
+    run_tool(fuse_tool, &[image.as_os_str(), version_path.as_os_str()])
+        .or_else(|_| {
+            run_tool(
+                "mount",
+                &[
+                    "-t".as_ref(),
+                    format.extension().as_ref(),
+                    "-o".as_ref(),
+                    "loop,ro".as_ref(),
+                    image.as_os_str(),
+                    version_path.as_os_str(),
+                ],
+            )
+        })
+        .with_context(|| format!("Could not mount {}", image.display()))?;
+
+    Ok(true)
+}
+
+/// Unmounts whatever is mounted over a version's directory
+fn unmount_image(version_path: &Path) -> Result<()> {
+    run_tool("fusermount", &["-u".as_ref(), version_path.as_os_str()])
+        .or_else(|_| run_tool("umount", &[version_path.as_os_str()]))
+        .with_context(|| format!("Could not unmount {}", version_path.display()))
+}
+
+/// Images mounted for as long as something runs from them, unmounted again when dropped.
+/// Images that were mounted already are left as they were.
+#[derive(Debug, Default)]
+#[must_use]
+pub struct MountedImages {
+    paths: Vec<PathBuf>,
+}
+
+impl MountedImages {
+    /// Mounts a version's image with [`mount_image`], to be unmounted when this is dropped.
+    ///
+    /// # Errors
+    ///
+    /// - Neither the FUSE tool, nor `mount` could mount the image
+    pub fn mount(&mut self, version_path: &Path) -> Result<()> {
+        if mount_image(version_path)? {
+            self.paths.push(version_path.canonicalize()?);
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for MountedImages {
+    fn drop(&mut self) {
+        // Fails while another process still uses the image, which then just stays mounted
+        for path in self.paths.iter().rev() {
+            let _ = unmount_image(path);
+        }
+    }
+}
+
+/// Unmounts and removes a version's image, leaving its (emptied) directory behind.
+///
+/// # Errors
+///
+/// - The image is mounted, and could not be unmounted
+/// - Filesystem errors (Permissions)
+pub fn remove_image(version_path: &Path) -> Result<()> {
+    let Some((image, _)) = find_image(version_path) else {
+        return Ok(());
+    };
+    let version_path = &version_path.canonicalize()?;
+
+    if is_mounted(version_path) {
+        unmount_image(version_path)?;
+    }
+
+    fs::remove_file(image)?;
+
+    Ok(())
+}
+
+fn run_tool(program: &str, args: &[&OsStr]) -> Result<()> {
+    let status = Command::new(program)
+        .args(args)
+        .status()
+        .with_context(|| format!("Could not run {program}. Is it installed?"))?;
+
+    if !status.success() {
+        bail!("{program} failed with {status}")
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use temp_dir::TempDir;
+
+    #[test]
+    fn test_find_image() -> Result<()> {
+        let repo = TempDir::new()?;
+        let version_path = &repo.path().join("versions/hello-abc");
+        fs::create_dir_all(version_path)?;
+        fs::create_dir_all(repo.path().join("installed"))?;
+        std::os::unix::fs::symlink(version_path, repo.path().join("installed/hello"))?;
+
+        assert_eq!(find_image(version_path), None);
+        assert!(!is_mounted(version_path));
+        // Nothing to do for plain trees
+        assert!(!mount_image(version_path)?);
+        let mut mounts = MountedImages::default();
+        mounts.mount(version_path)?;
+        assert!(mounts.paths.is_empty());
+        remove_image(version_path)?;
+
+        let image = image_path(version_path, ImageFormat::Erofs);
+        assert!(image.ends_with("versions/hello-abc.erofs"));
+        fs::write(&image, "image")?;
+
+        assert_eq!(
+            find_image(&repo.path().join("installed/hello")),
+            Some((image.canonicalize()?, ImageFormat::Erofs))
+        );
+
+        remove_image(version_path)?;
+        assert!(!image.exists());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,449 @@
+use anyhow::{Result, bail};
+use std::{collections::BTreeMap, fs, path::Path};
+use walkdir::WalkDir;
+
+use crate::{
+    chunks::{HashKind, hash::hash},
+    repo::{
+        InstallMeta, manifest_io::atomic_replace, metadata_policy::check_package_id,
+        versions::get_current_version,
+    },
+};
+
+const INSTALLED_INDEX_FILE: &str = "installed.yml";
+
+/// Every package installed from a Repository, so status queries don't have to read each `install.meta`.
+/// Kept up to date whenever `installed/` changes, and rebuilt from disk when missing.
+#[derive(serde::Deserialize, serde::Serialize, Debug, Default)]
+struct InstalledIndex {
+    packages: BTreeMap<String, InstallMeta>,
+    /// Digest of every `install.meta` Flint wrote, by the directory it is in relative to the Repository,
+    /// eg: `versions/hello-<hash>`. Kept when rescanning, so edits made since can still be caught.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    digests: BTreeMap<String, String>,
+}
+
+fn read_index(repo_path: &Path) -> Result<Option<InstalledIndex>> {
+    let index_path = repo_path.join(INSTALLED_INDEX_FILE);
+
+    if !index_path.exists() {
+        return Ok(None);
+    }
+
+    Ok(Some(serde_yaml::from_str(&fs::read_to_string(
+        index_path,
+    )?)?))
+}
+
+fn write_index(repo_path: &Path, index: &InstalledIndex) -> Result<()> {
+    atomic_replace(
+        repo_path,
+        INSTALLED_INDEX_FILE,
+        serde_yaml::to_string(index)?.as_bytes(),
+    )
+}
+
+/// The id an installed package is installed under, for its id or any of its aliases.
+///
+/// Packages are only ever installed under their id, so aliases are looked up in the installed index,
+/// which also finds packages no longer in the Repository. Anything that isn't an installed alias is returned as is.
+/// Everything else takes ids, so commands resolve what they were given once, with this.
+///
+/// # Errors
+///
+/// - `id` is not a valid package id, eg: `..`, see [`check_package_id`]
+/// - Filesystem errors (Permissions)
+/// - Invalid index or `install.meta`
+pub fn canonical_package_id(repo_path: &Path, id: &str) -> Result<String> {
+    // Whatever is returned is joined onto `installed/` and `versions/`
+    check_package_id(id)?;
+
+    if repo_path.join("installed").join(id).exists() {
+        return Ok(id.to_string());
+    }
+
+    Ok(get_installed(repo_path)?
+        .into_iter()
+        .find(|install_meta| install_meta.package.aliases.iter().any(|alias| alias == id))
+        .map_or_else(|| id.to_string(), |install_meta| install_meta.package.id))
+}
+
+/// Whether a package is installed, by its id or any of its aliases
+#[must_use]
+pub fn is_installed(repo_path: &Path, id: &str) -> bool {
+    canonical_package_id(repo_path, id)
+        .is_ok_and(|id| repo_path.join("installed").join(id).exists())
+}
+
+/// Reads `install.meta` of an installed package, if it is installed.
+///
+/// # Errors
+///
+/// - Invalid package id
+/// - Filesystem errors (Permissions)
+/// - Invalid `install.meta`
+pub fn read_install_meta(repo_path: &Path, package_id: &str) -> Result<Option<InstallMeta>> {
+    check_package_id(package_id)?;
+    let install_meta_path = repo_path
+        .join("installed")
+        .join(package_id)
+        .join("install.meta");
+
+    if !install_meta_path.exists() {
+        return Ok(None);
+    }
+
+    Ok(Some(serde_yaml::from_str(&fs::read_to_string(
+        install_meta_path,
+    )?)?))
+}
+
+/// Writes the `install.meta` of a package into `dir`, and records its digest in the installed index.
+/// `version` is the hash of the version in `versions/` that `dir` is, `None` for dev installs.
+///
+/// # Errors
+///
+/// - Filesystem errors (Out of space, Permissions)
+/// - Invalid index or `install.meta`
+pub fn write_install_meta(
+    repo_path: &Path,
+    dir: &Path,
+    version: Option<&str>,
+    install_meta: &InstallMeta,
+) -> Result<()> {
+    fs::write(
+        dir.join("install.meta"),
+        serde_yaml::to_string(install_meta)?,
+    )?;
+
+    // Without an index, one with only this digest would hide every other installed package
+    let mut index = if let Some(index) = read_index(repo_path)? {
+        index
+    } else {
+        rescan_installed(repo_path)?;
+        read_index(repo_path)?.unwrap_or_default()
+    };
+    index.digests.insert(
+        digest_dir(&install_meta.package.id, version),
+        install_meta_digest(install_meta)?,
+    );
+
+    write_index(repo_path, &index)
+}
+
+/// Errors out if the `install.meta` of an installed package changed since Flint wrote it,
+/// eg: an `env` var pointed somewhere else.
+///
+/// A version without a recorded digest fails too: it was installed before digests
+/// were recorded, or its digest was removed.
+///
+/// This catches stray edits, not an attacker: whoever can write `install.meta` can usually
+/// write the index next to it as well. `verified_launch` is what checks against a signature.
+///
+/// # Errors
+///
+/// - `install.meta` was modified, or has no digest to check it against
+/// - Filesystem errors (Permissions)
+/// - Invalid index or `install.meta`
+pub fn check_install_meta(repo_path: &Path, package_id: &str) -> Result<()> {
+    let Some(install_meta) = read_install_meta(repo_path, package_id)? else {
+        return Ok(());
+    };
+
+    let version = get_current_version(repo_path, package_id)?;
+    let digest = read_index(repo_path)?.and_then(|mut index| {
+        index
+            .digests
+            .remove(&digest_dir(package_id, version.as_deref()))
+    });
+
+    match digest {
+        Some(digest) if digest != install_meta_digest(&install_meta)? => bail!(
+            "install.meta of {package_id} was modified after it was installed. Reinstall it, or remove it."
+        ),
+        None if version.is_some() => bail!(
+            "install.meta of {package_id} has no recorded digest, so it can't be checked. Reinstall it, or remove it."
+        ),
+        // Not from `versions/`, nothing Flint wrote a digest for
+        _ => Ok(()),
+    }
+}
+
+/// Where an `install.meta` is, relative to the Repository
+fn digest_dir(package_id: &str, version: Option<&str>) -> String {
+    version.map_or_else(
+        || format!("installed/{package_id}"),
+        |version| format!("versions/{package_id}-{version}"),
+    )
+}
+
+/// Digest of what an `install.meta` says. Its measured size is left out,
+/// as packing a version into an image changes it.
+fn install_meta_digest(install_meta: &InstallMeta) -> Result<String> {
+    let mut install_meta = install_meta.clone();
+    install_meta.disk_size = None;
+    install_meta.disk_bytes = None;
+
+    // JSON values keep keys sorted, `env` alone would come out in any order
+    let canonical = serde_json::to_value(&install_meta)?.to_string();
+
+    Ok(hash(HashKind::Blake3, canonical.as_bytes()))
+}
+
+/// Rebuilds the installed index by reading every `install.meta`.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+/// - Invalid `install.meta`
+pub fn rescan_installed(repo_path: &Path) -> Result<Vec<InstallMeta>> {
+    let mut index = InstalledIndex::default();
+    let installed_path = repo_path.join("installed");
+
+    // Digests are never taken from disk, that would trust whatever is there now
+    if let Ok(Some(previous)) = read_index(repo_path) {
+        index.digests = previous
+            .digests
+            .into_iter()
+            .filter(|(dir, _)| repo_path.join(dir).join("install.meta").exists())
+            .collect();
+    }
+
+    if installed_path.exists() {
+        for entry in fs::read_dir(installed_path)? {
+            let package_id = entry?.file_name().to_string_lossy().to_string();
+
+            if let Some(install_meta) = read_install_meta(repo_path, &package_id)? {
+                index.packages.insert(package_id, install_meta);
+            }
+        }
+    }
+
+    write_index(repo_path, &index)?;
+
+    Ok(index.packages.into_values().collect())
+}
+
+/// Lists every installed package from the index, rescanning if there is no index yet.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+/// - Invalid index or `install.meta`
+pub fn get_installed(repo_path: &Path) -> Result<Vec<InstallMeta>> {
+    match read_index(repo_path)? {
+        Some(index) => Ok(index.packages.into_values().collect()),
+        None => rescan_installed(repo_path),
+    }
+}
+
+/// Updates a single package's entry in the installed index after `installed/<package_id>` changed.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+/// - Invalid index or `install.meta`
+pub fn reindex_installed(repo_path: &Path, package_id: &str) -> Result<()> {
+    let Some(mut index) = read_index(repo_path)? else {
+        rescan_installed(repo_path)?;
+        return Ok(());
+    };
+
+    if let Some(install_meta) = read_install_meta(repo_path, package_id)? {
+        index.packages.insert(package_id.to_string(), install_meta);
+    } else {
+        index.packages.remove(package_id);
+    }
+
+    write_index(repo_path, &index)
+}
+
+/// Removes `installed/<package_id>`, leaving any versions in place.
+///
+/// # Errors
+///
+/// - Invalid package id
+/// - Filesystem errors (Permissions)
+pub fn remove_installed(repo_path: &Path, package_id: &str) -> Result<()> {
+    check_package_id(package_id)?;
+    let installed_path = repo_path.join("installed").join(package_id);
+
+    if installed_path.is_symlink() {
+        fs::remove_file(&installed_path)?;
+    } else if installed_path.exists() {
+        fs::remove_dir_all(&installed_path)?;
+    }
+
+    reindex_installed(repo_path, package_id)
+}
+
+/// Copies every installed package tree out of a Repository into `detached_path/<package_id>`.
+///
+/// This lets installed software survive the Repository being removed. Files are copied rather than moved, so nothing is left sharing data with the chunk store.
+/// Dev installs are skipped, as their working directories are not part of the Repository.
+///
+/// # Errors
+///
+/// - A package was already detached to `detached_path`
+/// - Filesystem errors (Out of space, Permissions)
+///
+/// # Returns
+///
+/// The IDs of all detached packages
+pub fn detach_installed(repo_path: &Path, detached_path: &Path) -> Result<Vec<String>> {
+    let mut detached = Vec::new();
+
+    for install_meta in rescan_installed(repo_path)? {
+        if install_meta.dev_install {
+            continue;
+        }
+
+        let package_id = install_meta.package.id;
+        let installed_path = repo_path
+            .join("installed")
+            .join(&package_id)
+            .canonicalize()?;
+        let target_path = detached_path.join(&package_id);
+
+        if target_path.exists() {
+            bail!(
+                "Package '{package_id}' has already been detached to {}",
+                target_path.display()
+            )
+        }
+
+        for entry in WalkDir::new(&installed_path) {
+            let entry = entry?;
+            let relative_path = entry.path().strip_prefix(&installed_path)?;
+
+            if entry.file_type().is_dir() {
+                fs::create_dir_all(target_path.join(relative_path))?;
+            } else {
+                fs::copy(entry.path(), target_path.join(relative_path))?;
+            }
+        }
+
+        detached.push(package_id);
+    }
+
+    Ok(detached)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::{PackageManifest, versions::switch_version};
+    use std::collections::HashMap;
+    use temp_dir::TempDir;
+
+    fn fake_install(repo_path: &Path, package_id: &str) -> Result<()> {
+        let version_path = repo_path
+            .join("versions")
+            .join(format!("{package_id}-hash"));
+        fs::create_dir_all(&version_path)?;
+
+        let install_meta = InstallMeta {
+            package: PackageManifest {
+                id: package_id.into(),
+                build_hash: "hash".into(),
+                ..Default::default()
+            },
+            dev_install: false,
+            installed_at: None,
+            updated_at: None,
+            disk_size: None,
+            disk_bytes: None,
+            rewritten: Vec::new(),
+        };
+        write_install_meta(repo_path, &version_path, Some("hash"), &install_meta)?;
+
+        switch_version(repo_path, "hash", package_id)
+    }
+
+    #[test]
+    fn test_installed_index() -> Result<()> {
+        let repo = TempDir::new()?;
+        let repo_path = repo.path();
+
+        fake_install(repo_path, "first")?;
+        fake_install(repo_path, "second")?;
+        assert_eq!(get_installed(repo_path)?.len(), 2);
+
+        // Never a path outside `installed/`
+        for id in ["..", "../..", "first/.."] {
+            assert!(remove_installed(repo_path, id).is_err());
+            assert!(read_install_meta(repo_path, id).is_err());
+            assert!(!is_installed(repo_path, id));
+        }
+        assert!(repo_path.join("installed/first").exists());
+
+        remove_installed(repo_path, "first")?;
+        let installed = get_installed(repo_path)?;
+        assert_eq!(installed.len(), 1);
+        assert_eq!(installed[0].package.id, "second");
+
+        // Changes made behind the index's back need a rescan
+        fs::remove_file(repo_path.join("installed/second"))?;
+        assert_eq!(get_installed(repo_path)?.len(), 1);
+        assert!(rescan_installed(repo_path)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_install_meta() -> Result<()> {
+        let repo = TempDir::new()?;
+        let repo_path = repo.path();
+
+        fake_install(repo_path, "hello")?;
+        check_install_meta(repo_path, "hello")?;
+
+        let install_meta_path = repo_path.join("versions/hello-hash/install.meta");
+        let mut install_meta = read_install_meta(repo_path, "hello")?.expect("Installed");
+
+        // Packing into an image only changes the measured size
+        install_meta.disk_bytes = Some(1024);
+        fs::write(&install_meta_path, serde_yaml::to_string(&install_meta)?)?;
+        check_install_meta(repo_path, "hello")?;
+
+        install_meta.package.env = Some(HashMap::from([(
+            "LD_PRELOAD".to_string(),
+            "/tmp/evil.so".to_string(),
+        )]));
+        fs::write(&install_meta_path, serde_yaml::to_string(&install_meta)?)?;
+        assert!(check_install_meta(repo_path, "hello").is_err());
+
+        // Rescanning doesn't trust the edited file either
+        rescan_installed(repo_path)?;
+        assert!(check_install_meta(repo_path, "hello").is_err());
+
+        // Nor does dropping its digest
+        let mut index = read_index(repo_path)?.expect("Indexed");
+        index.digests.clear();
+        write_index(repo_path, &index)?;
+        assert!(check_install_meta(repo_path, "hello").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_detach_installed() -> Result<()> {
+        let repo = TempDir::new()?;
+        let repo_path = repo.path();
+        let detached = TempDir::new()?;
+
+        fake_install(repo_path, "kept")?;
+        fs::write(repo_path.join("versions/kept-hash/file"), "contents")?;
+
+        assert_eq!(detach_installed(repo_path, detached.path())?, vec!["kept"]);
+        fs::remove_dir_all(repo_path)?;
+
+        assert_eq!(
+            fs::read_to_string(detached.path().join("kept/file"))?,
+            "contents"
+        );
+        assert!(detached.path().join("kept/install.meta").exists());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,148 @@
+use anyhow::{Result, bail};
+use std::path::Path;
+
+use crate::{
+    crypto::key::{deserialize_verifying_key, get_private_key, serialize_verifying_key},
+    repo::{RepoManifest, read_manifest, serialize_manifest, signing_request::sign_manifest},
+};
+
+/// Lets another maintainer sign a Repository from their own machine.
+///
+/// # Errors
+///
+/// - Invalid public key
+/// - The key is already trusted
+/// - The local key is not the Repository's own key
+pub fn add_signing_key(
+    repo_path: &Path,
+    public_key: &str,
+    config_path: Option<&Path>,
+) -> Result<()> {
+    let public_key = normalize_key(public_key)?;
+    let mut manifest = read_manifest(repo_path)?;
+    check_owner(&manifest, config_path)?;
+
+    if manifest.trusted_keys().contains(&public_key.as_str()) {
+        bail!("This key can already sign this Repository.")
+    }
+
+    manifest.signing_keys.push(public_key);
+    resign(repo_path, &manifest, config_path)
+}
+
+/// Stops another maintainer's key from signing a Repository.
+///
+/// # Errors
+///
+/// - Invalid public key
+/// - The key is the Repository's own key, or not one of its signing keys
+/// - The local key is not the Repository's own key
+pub fn remove_signing_key(
+    repo_path: &Path,
+    public_key: &str,
+    config_path: Option<&Path>,
+) -> Result<()> {
+    let public_key = normalize_key(public_key)?;
+    let mut manifest = read_manifest(repo_path)?;
+    check_owner(&manifest, config_path)?;
+
+    if manifest.public_key == public_key {
+        bail!("This is the Repository's own key, rotate it instead.")
+    }
+    if !manifest.signing_keys.contains(&public_key) {
+        bail!("This key is not one of the Repository's signing keys.")
+    }
+
+    manifest.signing_keys.retain(|key| *key != public_key);
+    resign(repo_path, &manifest, config_path)
+}
+
+/// Only the Repository's own key may change who can sign it, see `check_manifest_update`
+fn check_owner(manifest: &RepoManifest, config_path: Option<&Path>) -> Result<()> {
+    let local_key = serialize_verifying_key(get_private_key(config_path)?.verifying_key())?;
+
+    if !manifest.owner_keys().contains(&local_key.as_str()) {
+        bail!("Only the Repository's own key can change its signing keys.")
+    }
+
+    Ok(())
+}
+
+/// Keys are compared as strings, so make sure they are all encoded the same way
+fn normalize_key(public_key: &str) -> Result<String> {
+    serialize_verifying_key(deserialize_verifying_key(public_key.trim())?)
+}
+
+fn resign(repo_path: &Path, manifest: &RepoManifest, config_path: Option<&Path>) -> Result<()> {
+    let manifest_serialized = serialize_manifest(repo_path, manifest)?;
+    sign_manifest(repo_path, &manifest_serialized, config_path)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        crypto::signing::sign_detached,
+        repo::{
+            PackageManifest, create_repo, insert_package,
+            manifest_io::{ManifestFormat, check_manifest_update},
+        },
+    };
+    use temp_dir::TempDir;
+
+    #[test]
+    fn test_signing_keys() -> Result<()> {
+        let repo = TempDir::new()?;
+        let maintainer = TempDir::new()?;
+        create_repo(repo.path(), Some(repo.path()))?;
+
+        let maintainer_key =
+            serialize_verifying_key(get_private_key(Some(maintainer.path()))?.verifying_key())?;
+        let package = PackageManifest {
+            id: "hello".into(),
+            ..Default::default()
+        };
+
+        add_signing_key(repo.path(), &maintainer_key, Some(repo.path()))?;
+        assert!(add_signing_key(repo.path(), &maintainer_key, Some(repo.path())).is_err());
+
+        insert_package(&package, repo.path(), Some(maintainer.path()))?;
+        assert_eq!(read_manifest(repo.path())?.packages, vec![package.clone()]);
+
+        // Other maintainers can't change who is trusted, neither through the API nor by signing it
+        let other = TempDir::new()?;
+        let other_key =
+            serialize_verifying_key(get_private_key(Some(other.path()))?.verifying_key())?;
+        assert!(add_signing_key(repo.path(), &other_key, Some(maintainer.path())).is_err());
+        let manifest = read_manifest(repo.path())?;
+        let mut takeovers = vec![manifest.clone(); 3];
+        takeovers[0].public_key.clone_from(&maintainer_key);
+        takeovers[1].signing_keys.push(other_key);
+        takeovers[2].previous_public_key = Some(maintainer_key.clone());
+        for takeover in takeovers {
+            let serialized = serialize_manifest(repo.path(), &takeover)?;
+            let signature = sign_detached(&serialized, Some(maintainer.path()))?;
+            assert!(
+                check_manifest_update(
+                    repo.path(),
+                    serialized.as_bytes(),
+                    &signature.to_bytes(),
+                    ManifestFormat::Yaml
+                )
+                .is_err()
+            );
+        }
+        assert_eq!(read_manifest(repo.path())?, manifest);
+
+        let own_key = read_manifest(repo.path())?.public_key;
+        assert!(remove_signing_key(repo.path(), &own_key, Some(repo.path())).is_err());
+
+        remove_signing_key(repo.path(), &maintainer_key, Some(repo.path()))?;
+        assert!(read_manifest(repo.path())?.signing_keys.is_empty());
+        assert!(insert_package(&package, repo.path(), Some(maintainer.path())).is_err());
+
+        Ok(())
+    }
+}
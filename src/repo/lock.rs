@@ -0,0 +1,31 @@
+use anyhow::{Context, Result};
+use std::{fs::File, path::Path};
+
+/// File inside a Repository that is locked while its chunks are being changed
+pub const LOCK_FILE: &str = ".lock";
+
+/// Exclusive hold on a Repository, released when dropped.
+///
+/// Taken by anything that stores chunks for packages it is about to insert, or removes
+/// chunks it thinks are unused, so one never sees the other halfway through.
+#[derive(Debug)]
+pub struct RepoLock {
+    _file: File,
+}
+
+/// Waits until no other process holds the Repository at `repo_path`, then holds it.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+pub fn lock_repo(repo_path: &Path) -> Result<RepoLock> {
+    let file = File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(repo_path.join(LOCK_FILE))
+        .with_context(|| format!("Could not lock the Repository at {}", repo_path.display()))?;
+    file.lock()?;
+
+    Ok(RepoLock { _file: file })
+}
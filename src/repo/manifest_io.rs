@@ -0,0 +1,732 @@
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use serde_yaml::Value;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Mutex, PoisonError},
+};
+
+use crate::{
+    crypto::signing::{ManifestSignatures, verify_signature_any},
+    repo::{
+        RepoManifest,
+        edition::{
+            check_manifest_edition, explain_manifest_error, publishes_binary_manifest, raw_edition,
+        },
+    },
+};
+
+/// How a manifest is encoded. YAML is always published, from `BINARY_MANIFEST_EDITION` on CBOR is too.
+/// Each encoding is signed on its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ManifestFormat {
+    Yaml,
+    Cbor,
+}
+
+impl ManifestFormat {
+    #[must_use]
+    pub const fn filename(self) -> &'static str {
+        match self {
+            Self::Yaml => "manifest.yml",
+            Self::Cbor => "manifest.cbor",
+        }
+    }
+
+    #[must_use]
+    pub const fn signature_filename(self) -> &'static str {
+        match self {
+            Self::Yaml => "manifest.yml.sig",
+            Self::Cbor => "manifest.cbor.sig",
+        }
+    }
+
+    /// The signature with the new key while rotating keys, eg: `manifest.yml.sig.next`
+    #[must_use]
+    pub fn next_signature_filename(self) -> String {
+        format!("{}.next", self.signature_filename())
+    }
+
+    /// The best format a Repository of `edition` is published in
+    #[cfg(feature = "network")]
+    #[must_use]
+    pub fn for_edition(edition: &str) -> Self {
+        if crate::repo::edition::publishes_binary_manifest(edition) {
+            Self::Cbor
+        } else {
+            Self::Yaml
+        }
+    }
+
+    /// The format a local Repository's manifest is stored in, CBOR if there are both
+    #[must_use]
+    pub fn of_repo(repo_path: &Path) -> Self {
+        if repo_path.join(Self::Cbor.filename()).exists() {
+            Self::Cbor
+        } else {
+            Self::Yaml
+        }
+    }
+}
+
+/// Decodes a manifest without interpreting it, so unknown fields and editions can be inspected.
+///
+/// # Errors
+///
+/// - Invalid YAML or CBOR
+pub fn decode_manifest(raw_manifest: &[u8], format: ManifestFormat) -> Result<Value> {
+    Ok(match format {
+        ManifestFormat::Yaml => serde_yaml::from_slice(raw_manifest)?,
+        ManifestFormat::Cbor => ciborium::from_reader(raw_manifest)?,
+    })
+}
+
+/// Re-encodes a YAML manifest as CBOR, keeping every field.
+///
+/// # Errors
+///
+/// - Invalid YAML
+pub fn encode_binary_manifest(manifest_serialized: &str) -> Result<Vec<u8>> {
+    let value: Value = serde_yaml::from_str(manifest_serialized)?;
+    let mut binary_manifest = Vec::new();
+    ciborium::into_writer(&value, &mut binary_manifest)?;
+
+    Ok(binary_manifest)
+}
+
+/// Parses a serialized manifest, rejecting editions this client does not understand.
+///
+/// # Errors
+///
+/// - Invalid YAML
+/// - Unsupported edition
+#[cfg(feature = "network")]
+pub fn parse_manifest(manifest_serialized: &str) -> Result<RepoManifest> {
+    parse_manifest_as(manifest_serialized.as_bytes(), ManifestFormat::Yaml)
+}
+
+/// Parses a manifest in either format, rejecting editions this client does not understand.
+///
+/// # Errors
+///
+/// - Invalid YAML or CBOR
+/// - Unsupported edition
+pub fn parse_manifest_as(raw_manifest: &[u8], format: ManifestFormat) -> Result<RepoManifest> {
+    let raw = decode_manifest(raw_manifest, format)?;
+
+    check_manifest_edition(&raw)?;
+
+    RepoManifest::deserialize(&raw).map_err(|err| {
+        // Only parsing the YAML itself gives line numbers
+        let err = match format {
+            ManifestFormat::Yaml => serde_yaml::from_slice::<RepoManifest>(raw_manifest)
+                .err()
+                .unwrap_or(err),
+            ManifestFormat::Cbor => err,
+        };
+
+        explain_manifest_error(&raw, &err)
+    })
+}
+
+/// Whether a Repository has a manifest, in any format
+#[must_use]
+pub fn has_manifest(repo_path: &Path) -> bool {
+    [ManifestFormat::Yaml, ManifestFormat::Cbor]
+        .into_iter()
+        .any(|format| repo_path.join(format.filename()).exists())
+}
+
+/// Removes a manifest in one format, along with its signatures.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+pub fn remove_manifest(repo_path: &Path, format: ManifestFormat) -> Result<()> {
+    let signature_filename = format.signature_filename();
+
+    for filename in [
+        format.filename(),
+        signature_filename,
+        &format.next_signature_filename(),
+    ] {
+        let path = repo_path.join(filename);
+
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes the manifest in every format other than `format`, after a manifest was written as `format`.
+///
+/// Only one format is kept, so a stale manifest is never read.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+pub fn remove_other_manifest_formats(repo_path: &Path, format: ManifestFormat) -> Result<()> {
+    remove_manifest(
+        repo_path,
+        match format {
+            ManifestFormat::Yaml => ManifestFormat::Cbor,
+            ManifestFormat::Cbor => ManifestFormat::Yaml,
+        },
+    )
+}
+
+/// Serializes a manifest to be signed, with a `serial` above the one of the manifest currently in `repo_path`.
+///
+/// Any fields this client doesn't know about are kept from it, so an older client re-signing a manifest doesn't strip them.
+///
+/// # Errors
+///
+/// - Existing manifest is invalid
+pub fn serialize_manifest(repo_path: &Path, manifest: &RepoManifest) -> Result<String> {
+    let mut manifest = manifest.clone();
+    let mut existing = None;
+
+    let format = ManifestFormat::of_repo(repo_path);
+    if let Ok(existing_serialized) = fs::read(repo_path.join(format.filename())) {
+        let raw = decode_manifest(&existing_serialized, format)?;
+        let known: RepoManifest = serde_yaml::from_value(raw.clone())?;
+
+        manifest.serial = manifest.serial.max(known.serial);
+        existing = Some((raw, serde_yaml::to_value(known)?));
+    }
+    manifest.serial += 1;
+
+    let mut value = serde_yaml::to_value(manifest)?;
+    if let Some((raw, known)) = existing {
+        restore_unknown_fields(&mut value, &raw, &known);
+    }
+
+    Ok(serde_yaml::to_string(&value)?)
+}
+
+/// Copies every field of `raw` that is missing from `known` (the typed round trip of `raw`) into `target`.
+fn restore_unknown_fields(target: &mut Value, raw: &Value, known: &Value) {
+    match (target, raw, known) {
+        (Value::Mapping(target), Value::Mapping(raw), Value::Mapping(known)) => {
+            for (key, raw_value) in raw {
+                match (known.get(key), target.get_mut(key)) {
+                    (None, None) => {
+                        target.insert(key.clone(), raw_value.clone());
+                    }
+                    (Some(known_value), Some(target_value)) => {
+                        restore_unknown_fields(target_value, raw_value, known_value);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        (Value::Sequence(target), Value::Sequence(raw), Value::Sequence(known)) => {
+            // Match packages by id, so added or removed packages don't mix up their fields
+            for (raw_item, known_item) in raw.iter().zip(known) {
+                let Some(id) = raw_item.get("id") else {
+                    continue;
+                };
+
+                if let Some(target_item) = target.iter_mut().find(|item| item.get("id") == Some(id))
+                {
+                    restore_unknown_fields(target_item, raw_item, known_item);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Manifests that passed verification, by path. Each entry is only reused while the manifest
+/// and signature still hash the same, so a command verifies each manifest once, however often it
+/// is read, yet never misses an update.
+static VERIFIED_MANIFESTS: Mutex<Option<HashMap<PathBuf, VerifiedManifest>>> = Mutex::new(None);
+
+struct VerifiedManifest {
+    /// Of the manifest and its signature
+    hash: blake3::Hash,
+    manifest: RepoManifest,
+}
+
+/// Reads a manifest and verifys it from the EXISTING key. This is best for GENERAL reading.
+///
+/// Every package is kept, whatever the Repository is subscribed to, see
+/// [`read_subscribed_manifest`](crate::repo::subscription::read_subscribed_manifest).
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions or doesn't exist)
+/// - Invalid signature
+pub fn read_manifest(repo_path: &Path) -> Result<RepoManifest> {
+    let format = ManifestFormat::of_repo(repo_path);
+    let manifest_path = repo_path.join(format.filename());
+    let manifest_serialized = fs::read(&manifest_path)?;
+    let manifest_signature_serialized = fs::read(repo_path.join(format.signature_filename()))?;
+
+    let hash = blake3::Hasher::new()
+        .update(&manifest_serialized)
+        .update(&manifest_signature_serialized)
+        .finalize();
+
+    let cached = VERIFIED_MANIFESTS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .as_ref()
+        .and_then(|cache| cache.get(&manifest_path))
+        .filter(|entry| entry.hash == hash)
+        .map(|entry| entry.manifest.clone());
+
+    if let Some(manifest) = cached {
+        return Ok(manifest);
+    }
+
+    let manifest = parse_manifest_as(&manifest_serialized, format)?;
+
+    verify_signature_any(
+        &manifest_serialized,
+        &manifest_signature_serialized,
+        &manifest.trusted_keys(),
+    )?;
+
+    VERIFIED_MANIFESTS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get_or_insert_default()
+        .insert(
+            manifest_path,
+            VerifiedManifest {
+                hash,
+                manifest: manifest.clone(),
+            },
+        );
+
+    Ok(manifest)
+}
+
+pub fn read_manifest_unsigned(repo_path: &Path) -> Result<RepoManifest> {
+    let format = ManifestFormat::of_repo(repo_path);
+    let manifest_serialized = fs::read(repo_path.join(format.filename()))?;
+
+    let manifest: RepoManifest =
+        serde_yaml::from_value(decode_manifest(&manifest_serialized, format)?)?;
+
+    Ok(manifest)
+}
+
+/// Replaces the existing manifest with another one, and verifies that it is correct
+///
+/// # Errors
+///
+/// - Invalid Signature
+/// - Filesystem error when updating (Out of space, Permissions)
+/// - New manifest is invalid
+pub fn update_manifest(
+    repo_path: &Path,
+    new_manifest_serialized: &str,
+    signature: &[u8],
+) -> Result<RepoManifest> {
+    update_manifest_as(
+        repo_path,
+        new_manifest_serialized.as_bytes(),
+        signature,
+        ManifestFormat::Yaml,
+    )
+}
+
+/// Replaces the existing manifest with one in `format`, and verifies that it is correct.
+/// The manifest in the other format is left alone, see [`remove_manifest`].
+///
+/// # Errors
+///
+/// - Invalid Signature
+/// - New manifest has a lower `serial` than the existing one (a rollback)
+/// - Filesystem error when updating (Out of space, Permissions)
+/// - New manifest is invalid
+pub fn update_manifest_as(
+    repo_path: &Path,
+    new_manifest_serialized: &[u8],
+    signature: &[u8],
+    format: ManifestFormat,
+) -> Result<RepoManifest> {
+    let manifest = check_manifest_update(repo_path, new_manifest_serialized, signature, format)?;
+
+    // Write to a .new, and then rename atomically
+    atomic_replace(repo_path, format.filename(), new_manifest_serialized)?;
+    atomic_replace(repo_path, format.signature_filename(), signature)?;
+
+    Ok(manifest)
+}
+
+/// Replaces the existing manifest with a newly signed one, in every format its edition publishes,
+/// see [`sign`](crate::crypto::signing::sign).
+///
+/// Every signature is checked before anything is written, so a bad one leaves the Repository as it was.
+///
+/// # Errors
+///
+/// - A missing, unexpected or invalid signature
+/// - Any error of [`check_manifest_update`]
+/// - Filesystem error when updating (Out of space, Permissions)
+pub fn update_manifest_signed(
+    repo_path: &Path,
+    new_manifest_serialized: &str,
+    signatures: &ManifestSignatures,
+) -> Result<RepoManifest> {
+    let binary_manifest = serde_yaml::from_str(new_manifest_serialized)
+        .ok()
+        .and_then(|manifest| raw_edition(&manifest))
+        .is_some_and(|edition| publishes_binary_manifest(&edition))
+        .then(|| encode_binary_manifest(new_manifest_serialized))
+        .transpose()?;
+
+    let mut encodings = vec![(ManifestFormat::Yaml, new_manifest_serialized.as_bytes())];
+    if let Some(binary_manifest) = &binary_manifest {
+        encodings.push((ManifestFormat::Cbor, binary_manifest));
+    }
+
+    for filename in signatures.keys() {
+        let known = encodings.iter().any(|(format, _)| {
+            *filename == format.signature_filename()
+                || *filename == format.next_signature_filename()
+        });
+        if !known {
+            bail!("Unexpected signature {filename}.")
+        }
+    }
+
+    let mut manifest = None;
+    for (format, data) in &encodings {
+        let Some(signature) = signatures.get(format.signature_filename()) else {
+            bail!("Missing signature {}.", format.signature_filename())
+        };
+        let checked = check_manifest_update(repo_path, data, signature, *format)?;
+
+        // Made with the key being rotated to
+        if let Some(next_signature) = signatures.get(&format.next_signature_filename()) {
+            verify_signature_any(data, next_signature, &[checked.public_key.as_str()])
+                .with_context(|| format!("Invalid {}", format.next_signature_filename()))?;
+        }
+
+        manifest.get_or_insert(checked);
+    }
+
+    // The YAML manifest last, so it is only replaced once everything else is
+    if binary_manifest.is_none() {
+        remove_manifest(repo_path, ManifestFormat::Cbor)?;
+    }
+    for (format, data) in encodings.into_iter().rev() {
+        atomic_replace(repo_path, format.filename(), data)?;
+
+        for filename in [
+            format.signature_filename().to_string(),
+            format.next_signature_filename(),
+        ] {
+            if let Some(signature) = signatures.get(&filename) {
+                atomic_replace(repo_path, &filename, signature)?;
+            } else if repo_path.join(&filename).exists() {
+                fs::remove_file(repo_path.join(&filename))?;
+            }
+        }
+    }
+
+    manifest.context("Missing manifest")
+}
+
+/// Checks a manifest in `format` could replace the existing one, without writing anything.
+///
+/// A Repository without a manifest yet, eg: one being created, takes any manifest signed with its own keys.
+///
+/// # Errors
+///
+/// - Invalid Signature
+/// - New manifest changes the trusted keys, and is not signed by the Repository's own key
+/// - New manifest has a lower `serial` than the existing one (a rollback)
+/// - New manifest is invalid
+pub fn check_manifest_update(
+    repo_path: &Path,
+    new_manifest_serialized: &[u8],
+    signature: &[u8],
+    format: ManifestFormat,
+) -> Result<RepoManifest> {
+    if !has_manifest(repo_path) {
+        let manifest = parse_manifest_as(new_manifest_serialized, format)?;
+        verify_signature_any(new_manifest_serialized, signature, &manifest.trusted_keys())?;
+
+        return Ok(manifest);
+    }
+
+    let old_manifest = read_manifest_unsigned(repo_path)?;
+
+    // VERIFY. IMPORTANT.
+    verify_signature_any(
+        new_manifest_serialized,
+        signature,
+        &old_manifest.trusted_keys(),
+    )?;
+
+    // Make sure it actually deserializes
+    let manifest = parse_manifest_as(new_manifest_serialized, format)?;
+
+    // Otherwise any delegated signer could make their key the Repository's own
+    if !manifest.same_trust(&old_manifest) {
+        verify_signature_any(
+            new_manifest_serialized,
+            signature,
+            &old_manifest.owner_keys(),
+        )
+        .context("Only the Repository's own key may change which keys can sign it")?;
+    }
+
+    if manifest.serial < old_manifest.serial {
+        bail!(
+            "Refusing a manifest older than the one we have (serial {} < {}). A mirror may be serving stale data.",
+            manifest.serial,
+            old_manifest.serial
+        )
+    }
+
+    Ok(manifest)
+}
+
+pub fn atomic_replace(base_path: &Path, filename: &str, contents: &[u8]) -> Result<()> {
+    let new_path = &base_path.join(filename.to_owned() + ".new");
+
+    fs::write(new_path, contents)?;
+    fs::rename(new_path, base_path.join(filename))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::signing::{sign, sign_detached};
+    use crate::repo::{
+        create_repo,
+        edition::{BINARY_MANIFEST_EDITION, DEFAULT_EDITION},
+    };
+    use temp_dir::TempDir;
+
+    #[test]
+    fn test_atomic_replace_basic() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        fs::write(temp_dir.path().join("file"), "previous_contents")?;
+        atomic_replace(temp_dir.path(), "file", b"new_contents")?;
+
+        assert_eq!(
+            fs::read_to_string(temp_dir.path().join("file"))?,
+            "new_contents"
+        );
+        assert!(!temp_dir.path().join("file.new").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_manifest_valid_and_invalid() -> Result<()> {
+        let repo = TempDir::new()?;
+        let repo_path = repo.path();
+        create_repo(repo_path, Some(repo_path))?;
+
+        let old_manifest = read_manifest(repo_path)?;
+
+        // Build a new manifest with small change
+        let mut new_manifest = old_manifest;
+        new_manifest.metadata.title = Some("NewName".into());
+
+        let serialized = serde_yaml::to_string(&new_manifest)?;
+
+        // Sign it with the right key
+        let signatures = sign(&serialized, Some(repo_path))?;
+
+        // Update should succeed
+        update_manifest_signed(repo_path, &serialized, &signatures)?;
+
+        let updated = read_manifest(repo_path)?;
+        assert_eq!(updated.metadata.title, Some("NewName".into()));
+
+        // Now try with invalid signature
+        let bad_signature = b"garbage_signature";
+        assert!(update_manifest(repo_path, &serialized, bad_signature).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_manifest_cached() -> Result<()> {
+        let repo = TempDir::new()?;
+        let repo_path = repo.path();
+        create_repo(repo_path, Some(repo_path))?;
+
+        let manifest = read_manifest(repo_path)?;
+        assert_eq!(read_manifest(repo_path)?, manifest);
+
+        // Changes are never hidden by the cache
+        let mut tampered = fs::read_to_string(repo_path.join("manifest.yml"))?;
+        tampered.push_str("\n# tampered\n");
+        fs::write(repo_path.join("manifest.yml"), tampered)?;
+        assert!(read_manifest(repo_path).is_err());
+
+        let mut changed = manifest;
+        changed.metadata.title = Some("Changed".into());
+        let serialized = serde_yaml::to_string(&changed)?;
+        let signatures = sign(&serialized, Some(repo_path))?;
+        update_manifest_signed(repo_path, &serialized, &signatures)?;
+        assert_eq!(
+            read_manifest(repo_path)?.metadata.title,
+            Some("Changed".into())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rollback_refused() -> Result<()> {
+        let repo = TempDir::new()?;
+        let repo_path = repo.path();
+        create_repo(repo_path, Some(repo_path))?;
+        let manifest = read_manifest(repo_path)?;
+
+        let older = serialize_manifest(repo_path, &manifest)?;
+        let older_signatures = sign(&older, Some(repo_path))?;
+        update_manifest_signed(repo_path, &older, &older_signatures)?;
+
+        let newer = serialize_manifest(repo_path, &manifest)?;
+        let newer_signatures = sign(&newer, Some(repo_path))?;
+        update_manifest_signed(repo_path, &newer, &newer_signatures)?;
+        assert_eq!(read_manifest(repo_path)?.serial, manifest.serial + 2);
+
+        assert!(update_manifest_signed(repo_path, &older, &older_signatures).is_err());
+        // The same manifest again is fine, eg: a mirror that hasn't synced yet
+        update_manifest_signed(repo_path, &newer, &newer_signatures)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_unsigned_manifest() -> Result<()> {
+        let repo = TempDir::new()?;
+        let repo_path = repo.path();
+        create_repo(repo_path, Some(repo_path))?;
+
+        let manifest = read_manifest(repo_path)?;
+        let manifest_unsigned = read_manifest_unsigned(repo_path)?;
+
+        assert_eq!(manifest.edition, manifest_unsigned.edition);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_fields_preserved() -> Result<()> {
+        let repo = TempDir::new()?;
+        let repo_path = repo.path();
+        create_repo(repo_path, Some(repo_path))?;
+
+        // A newer client publishes fields this one doesn't know about
+        let mut raw: Value =
+            serde_yaml::from_str(&fs::read_to_string(repo_path.join("manifest.yml"))?)?;
+        raw["future_field"] = "kept".into();
+        raw["metadata"]["future_metadata"] = "kept too".into();
+        let serialized = serde_yaml::to_string(&raw)?;
+        let signatures = sign(&serialized, Some(repo_path))?;
+        update_manifest_signed(repo_path, &serialized, &signatures)?;
+
+        // This client modifies and re-signs it
+        let mut manifest = read_manifest(repo_path)?;
+        manifest.metadata.title = Some("Changed".into());
+        let serialized = serialize_manifest(repo_path, &manifest)?;
+
+        let reparsed: Value = serde_yaml::from_str(&serialized)?;
+        assert_eq!(reparsed["future_field"], "kept");
+        assert_eq!(reparsed["metadata"]["future_metadata"], "kept too");
+        assert_eq!(reparsed["metadata"]["title"], "Changed");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_binary_manifest() -> Result<()> {
+        let repo = TempDir::new()?;
+        let client = TempDir::new()?;
+        let repo_path = repo.path();
+        create_repo(repo_path, Some(repo_path))?;
+        assert!(!repo_path.join("manifest.cbor").exists());
+
+        let mut manifest = read_manifest(repo_path)?;
+        manifest.edition = BINARY_MANIFEST_EDITION.into();
+        manifest.metadata.title = Some("Binary".into());
+        let serialized = serialize_manifest(repo_path, &manifest)?;
+        let signatures = sign(&serialized, Some(repo_path))?;
+        update_manifest_signed(repo_path, &serialized, &signatures)?;
+
+        // A client only keeping the CBOR manifest reads it the same
+        for filename in ["manifest.cbor", "manifest.cbor.sig"] {
+            fs::copy(repo_path.join(filename), client.path().join(filename))?;
+        }
+        assert_eq!(ManifestFormat::of_repo(client.path()), ManifestFormat::Cbor);
+        assert_eq!(read_manifest(client.path())?, read_manifest(repo_path)?);
+        assert_eq!(
+            read_manifest(client.path())?.metadata.title,
+            Some("Binary".into())
+        );
+
+        // Each encoding has its own signature
+        let binary = fs::read(repo_path.join("manifest.cbor"))?;
+        let yaml_signature = fs::read(repo_path.join("manifest.yml.sig"))?;
+        assert!(
+            update_manifest_as(
+                client.path(),
+                &binary,
+                &yaml_signature,
+                ManifestFormat::Cbor
+            )
+            .is_err()
+        );
+
+        // Nothing is written unless every encoding is signed correctly
+        manifest.metadata.title = Some("Mismatched".into());
+        let serialized = serialize_manifest(repo_path, &manifest)?;
+        let mut signatures = sign(&serialized, Some(repo_path))?;
+        signatures.insert("manifest.cbor.sig".into(), yaml_signature);
+        assert!(update_manifest_signed(repo_path, &serialized, &signatures).is_err());
+        signatures.remove("manifest.cbor.sig");
+        assert!(update_manifest_signed(repo_path, &serialized, &signatures).is_err());
+        assert_eq!(read_manifest(repo_path)?, read_manifest(client.path())?);
+        assert_eq!(
+            fs::read(repo_path.join("manifest.cbor"))?,
+            fs::read(client.path().join("manifest.cbor"))?
+        );
+
+        // Stops being published on older editions
+        manifest.edition = DEFAULT_EDITION.into();
+        let serialized = serialize_manifest(repo_path, &manifest)?;
+        let signatures = sign(&serialized, Some(repo_path))?;
+        update_manifest_signed(repo_path, &serialized, &signatures)?;
+        assert!(!repo_path.join("manifest.cbor").exists());
+        assert!(!repo_path.join("manifest.cbor.sig").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_edition_rejected() -> Result<()> {
+        let repo = TempDir::new()?;
+        let repo_path = repo.path();
+        create_repo(repo_path, Some(repo_path))?;
+
+        let mut manifest = read_manifest(repo_path)?;
+        manifest.edition = "2099".into();
+        let serialized = serde_yaml::to_string(&manifest)?;
+        let signature = sign_detached(&serialized, Some(repo_path))?;
+
+        assert!(update_manifest(repo_path, &serialized, &signature.to_bytes()).is_err());
+        assert_eq!(read_manifest(repo_path)?.edition, "2025");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,281 @@
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+use crate::repo::{Metadata, PackageManifest};
+
+const POLICY_FILE: &str = "policy.local.yml";
+
+/// Names a package id could collide with, next to installed packages or their versions
+const RESERVED_IDS: &[&str] = &["install.meta"];
+const RESERVED_SUFFIXES: &[&str] = &[".tmp", ".new"];
+
+/// Rules the metadata of every package inserted into a Repository has to follow.
+/// Kept next to the Repository, and never served.
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct MetadataPolicy {
+    /// Packages need a license
+    pub require_license: bool,
+    /// Licenses have to be SPDX license expressions, eg: "MIT OR Apache-2.0"
+    pub spdx_license: bool,
+    /// Packages need at least one maintainer
+    pub require_maintainers: bool,
+    /// The only categories packages may use, any if empty
+    pub categories: Vec<String>,
+    /// Package ids and aliases have to start with one of these, eg: "org.example.". Any if empty
+    pub id_prefixes: Vec<String>,
+}
+
+/// Gets the metadata policy of a Repository, which is empty unless one was set
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+/// - Invalid policy file
+pub fn get_metadata_policy(repo_path: &Path) -> Result<MetadataPolicy> {
+    let path = repo_path.join(POLICY_FILE);
+
+    if !path.exists() {
+        return Ok(MetadataPolicy::default());
+    }
+
+    Ok(serde_yaml::from_str(&fs::read_to_string(path)?)?)
+}
+
+/// Sets the metadata policy of a Repository. Only applies to packages inserted from now on.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+pub fn set_metadata_policy(repo_path: &Path, policy: &MetadataPolicy) -> Result<()> {
+    let path = repo_path.join(POLICY_FILE);
+
+    if policy == &MetadataPolicy::default() {
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        return Ok(());
+    }
+
+    fs::write(path, serde_yaml::to_string(policy)?)?;
+
+    Ok(())
+}
+
+/// Checks a package's metadata against a Repository's policy
+///
+/// # Errors
+///
+/// - Empty or repeated maintainers, keywords or categories
+/// - The metadata breaks one of the policy's rules
+pub fn check_metadata(metadata: &Metadata, policy: &MetadataPolicy) -> Result<()> {
+    for (field, values) in [
+        ("maintainers", &metadata.maintainers),
+        ("keywords", &metadata.keywords),
+        ("categories", &metadata.categories),
+    ] {
+        for (index, value) in values.iter().enumerate() {
+            if value.trim().is_empty() {
+                bail!("Empty entry in {field}.")
+            }
+            if values[..index].contains(value) {
+                bail!("{value} is in {field} twice.")
+            }
+        }
+    }
+
+    match &metadata.license {
+        None if policy.require_license => bail!("A license is required."),
+        Some(license) if policy.spdx_license && !is_spdx_expression(license) => {
+            bail!("{license} is not an SPDX license expression, eg: \"MIT OR Apache-2.0\".")
+        }
+        _ => {}
+    }
+
+    if policy.require_maintainers && metadata.maintainers.is_empty() {
+        bail!("At least one maintainer is required.")
+    }
+
+    if !policy.categories.is_empty()
+        && let Some(category) = metadata
+            .categories
+            .iter()
+            .find(|category| !policy.categories.contains(category))
+    {
+        bail!(
+            "{category} is not an allowed category, expected one of: {}",
+            policy.categories.join(", ")
+        )
+    }
+
+    Ok(())
+}
+
+/// Checks a package id or alias can safely name its directories and symlinks.
+///
+/// Ids are 1 to 128 letters, digits, `.`, `-`, `_` or `+`, starting with a letter or digit,
+/// so never `..`, a hidden file or a path. Names Flint uses itself, eg: `install.meta`, are reserved.
+///
+/// # Errors
+///
+/// - The id is invalid or reserved
+pub fn check_package_id(id: &str) -> Result<()> {
+    if id.is_empty() || id.len() > 128 {
+        bail!("Package ids have to be 1 to 128 characters long.")
+    }
+    if !id.starts_with(|char: char| char.is_ascii_alphanumeric()) {
+        bail!("{id} has to start with a letter or digit.")
+    }
+    if let Some(char) = id
+        .chars()
+        .find(|char| !char.is_ascii_alphanumeric() && !matches!(char, '.' | '-' | '_' | '+'))
+    {
+        bail!(
+            "{id} contains '{char}', package ids can only contain letters, digits, '.', '-', '_' and '+'."
+        )
+    }
+    // Installs and switches go through `<id>.tmp` and `<id>.new` next to the package.
+    // Compared without case, for filesystems that don't tell them apart
+    let lowercase = id.to_ascii_lowercase();
+    if RESERVED_IDS.contains(&lowercase.as_str())
+        || RESERVED_SUFFIXES
+            .iter()
+            .any(|suffix| lowercase.ends_with(suffix))
+    {
+        bail!("{id} is reserved, and can't be used as a package id.")
+    }
+
+    Ok(())
+}
+
+/// Checks the id and aliases of a package, see [`check_package_id`], and that they start with
+/// one of the policy's `id_prefixes`.
+///
+/// # Errors
+///
+/// - An invalid or reserved id or alias
+/// - An id or alias without an allowed prefix
+pub fn check_package_ids(package: &PackageManifest, policy: &MetadataPolicy) -> Result<()> {
+    for id in std::iter::once(&package.id).chain(&package.aliases) {
+        check_package_id(id)?;
+
+        if !policy.id_prefixes.is_empty()
+            && !policy
+                .id_prefixes
+                .iter()
+                .any(|prefix| id.starts_with(prefix.as_str()))
+        {
+            bail!(
+                "{id} doesn't start with an allowed prefix, expected one of: {}",
+                policy.id_prefixes.join(", ")
+            )
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether `expression` is a valid SPDX license expression, eg: "GPL-2.0-or-later WITH Classpath-exception-2.0".
+///
+/// License ids are checked against the SPDX license list.
+/// Anything else can be named with `LicenseRef-`, as SPDX intends.
+#[must_use]
+pub fn is_spdx_expression(expression: &str) -> bool {
+    // The spdx crate takes `LicenseRef-` without an id
+    spdx::Expression::parse(expression).is_ok_and(|expression| {
+        expression
+            .requirements()
+            .all(|requirement| match &requirement.req.license {
+                spdx::LicenseItem::Other { lic_ref, .. } => !lic_ref.is_empty(),
+                spdx::LicenseItem::Spdx { .. } => true,
+            })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_metadata() {
+        for expression in [
+            "MIT",
+            "MIT OR Apache-2.0",
+            "GPL-2.0-or-later WITH Classpath-exception-2.0",
+            "(MIT AND BSD-3-Clause) OR LicenseRef-Proprietary",
+            "Apache-2.0+",
+            "DocumentRef-spdx-tool:LicenseRef-MIT-Style",
+        ] {
+            assert!(is_spdx_expression(expression), "{expression}");
+        }
+        for expression in [
+            "",
+            "MIT License",
+            "MIT OR",
+            "(MIT",
+            "MIT)",
+            "GPL-2.0 WITH MIT",
+            "LicenseRef-",
+            "Totally-Made-Up-1.0",
+        ] {
+            assert!(!is_spdx_expression(expression), "{expression}");
+        }
+
+        let mut metadata = Metadata {
+            license: Some("MIT License".into()),
+            keywords: vec!["editor".into()],
+            categories: vec!["development".into()],
+            ..Default::default()
+        };
+        let mut policy = MetadataPolicy::default();
+        assert!(check_metadata(&metadata, &policy).is_ok());
+
+        policy.spdx_license = true;
+        assert!(check_metadata(&metadata, &policy).is_err());
+        metadata.license = Some("MIT".into());
+        assert!(check_metadata(&metadata, &policy).is_ok());
+
+        policy.require_maintainers = true;
+        assert!(check_metadata(&metadata, &policy).is_err());
+        metadata.maintainers = vec!["Jane Doe <jane@example.com>".into()];
+        assert!(check_metadata(&metadata, &policy).is_ok());
+
+        policy.categories = vec!["games".into()];
+        assert!(check_metadata(&metadata, &policy).is_err());
+        policy.categories.push("development".into());
+        assert!(check_metadata(&metadata, &policy).is_ok());
+
+        metadata.keywords.push("editor".into());
+        assert!(check_metadata(&metadata, &policy).is_err());
+    }
+
+    #[test]
+    fn test_check_package_id() {
+        for id in [
+            "hello",
+            "org.example.hello",
+            "gtk+-3.0",
+            "python3_12",
+            "7zip",
+        ] {
+            assert!(check_package_id(id).is_ok(), "{id}");
+        }
+        for id in [
+            "",
+            "..",
+            ".hidden",
+            "-flag",
+            "a/b",
+            "a b",
+            "héllo",
+            "install.meta",
+            "hello.tmp",
+            "hello.new",
+            "Hello.TMP",
+            &"a".repeat(129),
+        ] {
+            assert!(check_package_id(id).is_err(), "{id}");
+        }
+    }
+}
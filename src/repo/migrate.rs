@@ -0,0 +1,175 @@
+use anyhow::{Result, bail};
+use std::{cmp::Ordering, path::Path};
+
+use crate::{
+    chunks::utils::migrate_chunk_sizes,
+    repo::{
+        RepoManifest,
+        edition::{CLIENT_EDITION, SUPPORTED_EDITIONS, compare_editions},
+        read_manifest, serialize_manifest,
+        signing_request::sign_manifest,
+    },
+};
+
+/// What moving a Repository from one edition to the next changes
+struct Step {
+    from: &'static str,
+    to: &'static str,
+    changes: &'static [&'static str],
+}
+
+/// Every edition upgrade, oldest first. Add a step here whenever an edition is added.
+const STEPS: &[Step] = &[
+    Step {
+        from: "2025",
+        to: "2026",
+        changes: &["Publish manifest.cbor next to manifest.yml, signed on its own"],
+    },
+    Step {
+        from: "2026",
+        to: "2027",
+        changes: &["Serve chunks zstd compressed when it pays off"],
+    },
+];
+
+/// What [`migrate_repo`] changes, or would change when it's a dry run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationReport {
+    pub from: String,
+    pub to: String,
+    /// What each edition step changes, in order
+    pub changes: Vec<String>,
+    /// Chunks that get their exact size recorded
+    pub chunk_sizes: usize,
+}
+
+impl MigrationReport {
+    /// Whether the Repository is already fully migrated
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.changes.is_empty() && self.chunk_sizes == 0
+    }
+}
+
+/// Upgrades a Repository to `target` (defaults to the newest edition), then re-signs and rewrites its manifest.
+/// With `dry_run`, only reports what would change.
+///
+/// # Errors
+///
+/// - `target` is unsupported, or older than the Repository's edition
+/// - The Repository's edition has no migration path
+/// - The Repository is not signed with the local key
+/// - Filesystem errors (Permissions)
+pub fn migrate_repo(
+    repo_path: &Path,
+    chunk_store_path: &Path,
+    target: Option<&str>,
+    dry_run: bool,
+    config_path: Option<&Path>,
+) -> Result<MigrationReport> {
+    let mut manifest = read_manifest(repo_path)?;
+    let report = plan_migration(
+        &mut manifest,
+        chunk_store_path,
+        target.unwrap_or(CLIENT_EDITION),
+    )?;
+
+    if !dry_run && !report.is_empty() {
+        let manifest_serialized = serialize_manifest(repo_path, &manifest)?;
+        sign_manifest(repo_path, &manifest_serialized, config_path)?;
+    }
+
+    Ok(report)
+}
+
+/// Applies every step from the manifest's edition to `target` to `manifest`.
+fn plan_migration(
+    manifest: &mut RepoManifest,
+    chunk_store_path: &Path,
+    target: &str,
+) -> Result<MigrationReport> {
+    if !SUPPORTED_EDITIONS.contains(&target) {
+        bail!(
+            "Unsupported edition {target}, expected one of: {}",
+            SUPPORTED_EDITIONS.join(", ")
+        )
+    }
+
+    if compare_editions(target, &manifest.edition) == Ordering::Less {
+        bail!(
+            "This Repository is already at edition {}, editions can't be downgraded.",
+            manifest.edition
+        )
+    }
+
+    let from = manifest.edition.clone();
+    let mut changes = Vec::new();
+
+    while manifest.edition != target {
+        let Some(step) = STEPS.iter().find(|step| step.from == manifest.edition) else {
+            bail!("No migration from edition {}.", manifest.edition)
+        };
+
+        changes.extend(
+            step.changes
+                .iter()
+                .map(|change| format!("{} -> {}: {change}", step.from, step.to)),
+        );
+        step.to.clone_into(&mut manifest.edition);
+    }
+
+    Ok(MigrationReport {
+        from,
+        to: target.to_string(),
+        changes,
+        chunk_sizes: migrate_chunk_sizes(manifest, chunk_store_path)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::{create_repo, edition::DEFAULT_EDITION};
+    use temp_dir::TempDir;
+
+    #[test]
+    fn test_migrate_repo() -> Result<()> {
+        let repo = TempDir::new()?;
+        let chunks = TempDir::new()?;
+        let repo_path = repo.path();
+        create_repo(repo_path, Some(repo_path))?;
+        let config_path = Some(repo_path);
+
+        assert!(migrate_repo(repo_path, chunks.path(), Some("1999"), false, config_path).is_err());
+
+        let dry_run = migrate_repo(repo_path, chunks.path(), None, true, config_path)?;
+        assert_eq!(dry_run.from, DEFAULT_EDITION);
+        assert_eq!(dry_run.to, CLIENT_EDITION);
+        assert_eq!(dry_run.changes.len(), STEPS.len());
+        assert_eq!(read_manifest(repo_path)?.edition, DEFAULT_EDITION);
+        assert!(!repo_path.join("manifest.cbor").exists());
+
+        assert_eq!(
+            migrate_repo(repo_path, chunks.path(), None, false, config_path)?,
+            dry_run
+        );
+        assert_eq!(read_manifest(repo_path)?.edition, CLIENT_EDITION);
+        assert!(repo_path.join("manifest.cbor").exists());
+
+        assert!(migrate_repo(repo_path, chunks.path(), None, false, config_path)?.is_empty());
+        assert!(migrate_repo(repo_path, chunks.path(), Some("2025"), false, config_path).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_steps_cover_editions() {
+        // Every supported edition but the newest must be migratable
+        for edition in SUPPORTED_EDITIONS {
+            assert!(
+                *edition == CLIENT_EDITION || STEPS.iter().any(|step| step.from == *edition),
+                "No migration from {edition}"
+            );
+        }
+    }
+}
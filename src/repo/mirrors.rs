@@ -0,0 +1,402 @@
+use anyhow::{Result, bail};
+use std::{fs, path::Path, str::FromStr};
+
+use crate::{
+    config::read_config,
+    repo::{Mirror, RepoManifest, manifest_io::atomic_replace},
+};
+
+/// Client-side mirror overrides. These are never signed, and never leave this machine.
+const LOCAL_MIRRORS_FILE: &str = "mirrors.local.yml";
+
+/// Gets the client-side mirror overrides for a Repository
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+/// - Invalid overrides file
+pub fn get_local_mirrors(repo_path: &Path) -> Result<Vec<String>> {
+    let path = repo_path.join(LOCAL_MIRRORS_FILE);
+
+    if path.exists() {
+        Ok(serde_yaml::from_str(&fs::read_to_string(path)?)?)
+    } else {
+        Ok(Vec::new())
+    }
+}
+
+fn set_local_mirrors(repo_path: &Path, mirrors: &[String]) -> Result<()> {
+    atomic_replace(
+        repo_path,
+        LOCAL_MIRRORS_FILE,
+        serde_yaml::to_string(mirrors)?.as_bytes(),
+    )
+}
+
+/// Adds a client-side mirror override, which will be tried before the manifest's mirrors.
+///
+/// # Errors
+///
+/// - Invalid URL
+/// - Filesystem errors (Permissions)
+pub fn add_local_mirror(repo_path: &Path, url: &str) -> Result<()> {
+    let url = normalize_mirror_url(url)?;
+    let mut mirrors = get_local_mirrors(repo_path)?;
+
+    if !mirrors.contains(&url) {
+        mirrors.push(url);
+    }
+
+    set_local_mirrors(repo_path, &mirrors)
+}
+
+/// Removes a client-side mirror override.
+///
+/// # Errors
+///
+/// - The mirror is not an override for this Repository
+/// - Filesystem errors (Permissions)
+pub fn remove_local_mirror(repo_path: &Path, url: &str) -> Result<()> {
+    let url = url.trim_end_matches('/');
+    let mut mirrors = get_local_mirrors(repo_path)?;
+
+    if !mirrors.iter().any(|mirror| mirror == url) {
+        bail!("{url} is not a mirror override for this Repository.")
+    }
+    mirrors.retain(|mirror| mirror != url);
+
+    set_local_mirrors(repo_path, &mirrors)
+}
+
+/// Adds a mirror to a Repository's own manifest. A mirror with the same URL is replaced,
+/// so changing its priority, weight or region doesn't list it twice.
+/// The manifest still has to be signed again.
+///
+/// # Returns
+///
+/// Whether a mirror was replaced
+pub fn add_repo_mirror(manifest: &mut RepoManifest, mirror: Mirror) -> bool {
+    if let Some(existing) = manifest
+        .mirrors
+        .iter_mut()
+        .find(|existing| existing.url == mirror.url)
+    {
+        *existing = mirror;
+        return true;
+    }
+
+    manifest.mirrors.push(mirror);
+    false
+}
+
+/// Removes a mirror from a Repository's own manifest.
+/// The manifest still has to be signed again.
+///
+/// # Errors
+///
+/// - The mirror is not one of the Repository's
+pub fn remove_repo_mirror(manifest: &mut RepoManifest, url: &str) -> Result<()> {
+    let url = url.trim().trim_end_matches('/');
+
+    if !manifest.mirrors.iter().any(|mirror| mirror.url == url) {
+        bail!("{url} is not a mirror of this Repository.")
+    }
+    manifest.mirrors.retain(|mirror| mirror.url != url);
+
+    Ok(())
+}
+
+/// Gets every mirror to try for a Repository, client-side overrides first.
+/// The Repository's own mirrors follow, ordered by [`order_mirrors`] for the configured region.
+///
+/// # Errors
+///
+/// - Invalid overrides file
+/// - Invalid config
+pub fn get_mirrors(repo_path: &Path, repo_manifest: &RepoManifest) -> Result<Vec<String>> {
+    let mut mirrors = get_local_mirrors(repo_path)?;
+    let region = read_config(None)?.region;
+
+    for mirror in order_mirrors(&repo_manifest.mirrors, region.as_deref(), |bound| {
+        getrandom::u32().unwrap_or_default() % bound
+    }) {
+        if !mirrors.contains(&mirror.url) {
+            mirrors.push(mirror.url.clone());
+        }
+    }
+
+    Ok(mirrors)
+}
+
+/// Gets the URL to fetch manifest updates from: the first client-side override,
+/// then the Repository's `updates_url`, then the first of its own mirrors.
+///
+/// # Errors
+///
+/// - Invalid overrides file
+/// - Invalid config
+pub fn get_updates_url(repo_path: &Path, repo_manifest: &RepoManifest) -> Result<Option<String>> {
+    if let Some(url) = get_local_mirrors(repo_path)?.into_iter().next() {
+        return Ok(Some(url));
+    }
+
+    if let Some(url) = &repo_manifest.updates_url {
+        return Ok(Some(url.clone()));
+    }
+
+    Ok(get_mirrors(repo_path, repo_manifest)?.into_iter().next())
+}
+
+/// Orders mirrors by priority, then mirrors in `region`, then randomly by weight.
+/// `random(bound)` must return a number below `bound`.
+pub fn order_mirrors<'a>(
+    mirrors: &'a [Mirror],
+    region: Option<&str>,
+    mut random: impl FnMut(u32) -> u32,
+) -> Vec<&'a Mirror> {
+    let group_key = |mirror: &Mirror| {
+        (
+            mirror.priority,
+            region.is_none_or(|region| mirror.region.as_deref() != Some(region)),
+        )
+    };
+
+    let mut sorted: Vec<&Mirror> = mirrors.iter().collect();
+    sorted.sort_by_key(|mirror| group_key(mirror));
+
+    let mut groups: Vec<Vec<&Mirror>> = Vec::new();
+    for mirror in sorted {
+        match groups.last_mut() {
+            Some(group) if group_key(group[0]) == group_key(mirror) => group.push(mirror),
+            _ => groups.push(vec![mirror]),
+        }
+    }
+
+    let mut ordered = Vec::new();
+    let mut last_resort = Vec::new();
+
+    for mut group in groups {
+        // Weighted shuffle, each pick is as likely as its share of the remaining weight
+        while !group.is_empty() {
+            let total: u32 = group.iter().map(|mirror| mirror.weight).sum();
+            if total == 0 {
+                last_resort.append(&mut group);
+                break;
+            }
+
+            let mut pick = random(total);
+            let index = group
+                .iter()
+                .position(|mirror| {
+                    if pick < mirror.weight {
+                        true
+                    } else {
+                        pick -= mirror.weight;
+                        false
+                    }
+                })
+                .unwrap_or_default();
+
+            ordered.push(group.remove(index));
+        }
+    }
+
+    ordered.append(&mut last_resort);
+    ordered
+}
+
+/// Validates a mirror URL, and strips any trailing slashes.
+///
+/// # Errors
+///
+/// - URL is not http(s)
+pub fn normalize_mirror_url(url: &str) -> Result<String> {
+    let url = url.trim().trim_end_matches('/');
+
+    if !(url.starts_with("https://") || url.starts_with("http://")) {
+        bail!("Mirror URL must start with http:// or https://: {url}")
+    }
+
+    Ok(url.to_string())
+}
+
+impl FromStr for Mirror {
+    type Err = anyhow::Error;
+
+    /// Parses a mirror URL, optionally followed by `;key=value` options, eg:
+    /// `https://eu.example.com;priority=1;weight=5;region=eu`
+    fn from_str(mirror: &str) -> Result<Self> {
+        let mut parts = mirror.split(';');
+        let mut mirror = Self::new(normalize_mirror_url(parts.next().unwrap_or_default())?);
+
+        for option in parts {
+            match option.trim().split_once('=') {
+                Some(("priority", priority)) => mirror.priority = priority.parse()?,
+                Some(("weight", weight)) => mirror.weight = weight.parse()?,
+                Some(("region", region)) => mirror.region = Some(region.to_string()),
+                _ => bail!("Unknown mirror option: {option}"),
+            }
+        }
+
+        Ok(mirror)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::{create_repo, read_manifest};
+    use temp_dir::TempDir;
+
+    #[test]
+    fn test_local_mirrors() -> Result<()> {
+        let repo = TempDir::new()?;
+        let repo_path = repo.path();
+        create_repo(repo_path, Some(repo_path))?;
+
+        let mut manifest = read_manifest(repo_path)?;
+        manifest.mirrors = vec![Mirror::new("https://upstream.example")];
+
+        add_local_mirror(repo_path, "https://local.example/")?;
+        add_local_mirror(repo_path, "https://local.example")?;
+        assert!(add_local_mirror(repo_path, "ftp://local.example").is_err());
+
+        assert_eq!(
+            get_mirrors(repo_path, &manifest)?,
+            vec!["https://local.example", "https://upstream.example"]
+        );
+
+        remove_local_mirror(repo_path, "https://local.example")?;
+        assert!(remove_local_mirror(repo_path, "https://local.example").is_err());
+        assert_eq!(
+            get_mirrors(repo_path, &manifest)?,
+            vec!["https://upstream.example"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_repo_mirrors() -> Result<()> {
+        let repo = TempDir::new()?;
+        let repo_path = repo.path();
+        create_repo(repo_path, Some(repo_path))?;
+        let mut manifest = read_manifest(repo_path)?;
+
+        assert!(!add_repo_mirror(
+            &mut manifest,
+            "https://eu.example/".parse()?
+        ));
+        assert!(add_repo_mirror(
+            &mut manifest,
+            "https://eu.example;priority=1;region=eu".parse()?
+        ));
+        assert!(!add_repo_mirror(
+            &mut manifest,
+            "https://us.example".parse()?
+        ));
+        assert_eq!(manifest.mirrors.len(), 2);
+        assert_eq!(manifest.mirrors[0].region.as_deref(), Some("eu"));
+
+        remove_repo_mirror(&mut manifest, "https://eu.example/")?;
+        assert!(remove_repo_mirror(&mut manifest, "https://eu.example").is_err());
+        assert_eq!(manifest.mirrors, vec![Mirror::new("https://us.example")]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_updates_url() -> Result<()> {
+        let repo = TempDir::new()?;
+        let repo_path = repo.path();
+        create_repo(repo_path, Some(repo_path))?;
+
+        let mut manifest = read_manifest(repo_path)?;
+        manifest.mirrors = vec![Mirror::new("https://upstream.example")];
+        assert_eq!(
+            get_updates_url(repo_path, &manifest)?.as_deref(),
+            Some("https://upstream.example")
+        );
+
+        manifest.updates_url = Some("https://updates.example".into());
+        assert_eq!(
+            get_updates_url(repo_path, &manifest)?.as_deref(),
+            Some("https://updates.example")
+        );
+        assert_eq!(manifest.updates_source(), Some("https://updates.example"));
+
+        add_local_mirror(repo_path, "https://local.example")?;
+        assert_eq!(
+            get_updates_url(repo_path, &manifest)?.as_deref(),
+            Some("https://local.example")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_order_mirrors() -> Result<()> {
+        let mirrors: Vec<Mirror> = [
+            "https://fallback.example;priority=1",
+            "https://never.example;weight=0",
+            "https://big.example;weight=3",
+            "https://small.example",
+            "https://eu.example;region=eu",
+        ]
+        .iter()
+        .map(|mirror| mirror.parse())
+        .collect::<Result<_>>()?;
+
+        let urls = |region, pick| -> Vec<&str> {
+            order_mirrors(&mirrors, region, |bound| pick % bound)
+                .iter()
+                .map(|mirror| mirror.url.as_str())
+                .collect()
+        };
+
+        assert_eq!(
+            urls(Some("eu"), 0),
+            vec![
+                "https://eu.example",
+                "https://big.example",
+                "https://small.example",
+                "https://fallback.example",
+                "https://never.example",
+            ]
+        );
+        // Picks past a mirror's weight land on the ones after it
+        assert_eq!(
+            urls(None, 3),
+            vec![
+                "https://small.example",
+                "https://eu.example",
+                "https://big.example",
+                "https://fallback.example",
+                "https://never.example",
+            ]
+        );
+
+        assert!("https://a.example;speed=fast".parse::<Mirror>().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mirror_compatibility() -> Result<()> {
+        let mirrors: Vec<Mirror> = serde_yaml::from_str(
+            "- https://old.example\n- url: https://new.example\n  priority: 2\n  region: eu\n",
+        )?;
+
+        assert_eq!(mirrors[0], Mirror::new("https://old.example"));
+        assert_eq!(mirrors[1].weight, 1);
+        assert_eq!(mirrors[1].region.as_deref(), Some("eu"));
+
+        // Plain mirrors stay readable by older clients
+        assert_eq!(
+            serde_yaml::to_string(&mirrors[..1])?,
+            "- https://old.example\n"
+        );
+
+        Ok(())
+    }
+}
@@ -1,50 +1,109 @@
-mod io;
+pub mod advisories;
+pub mod analyze;
+pub mod channels;
+pub mod clone;
+pub mod credentials;
+pub mod declared;
+pub mod diff;
+pub mod edition;
+pub mod export;
+pub mod feeds;
+pub mod image;
+pub mod installed;
+pub mod keys;
+pub mod lock;
+pub(crate) mod manifest_io;
+pub mod metadata_policy;
+pub mod migrate;
+pub mod mirrors;
 #[cfg(feature = "network")]
 pub mod network;
+pub mod pack;
+pub mod provenance;
+pub mod publish;
+pub mod rename;
+pub mod revisions;
+pub mod rotation;
+pub mod settings;
+pub mod shebang;
+pub mod signing_request;
+pub mod subscription;
 mod types;
+pub mod usage;
+pub mod verify;
 pub mod versions;
-pub use io::{read_manifest, update_manifest};
+pub use manifest_io::{read_manifest, serialize_manifest, update_manifest};
+pub use subscription::read_subscribed_manifest;
 pub use types::*;
 
-use anyhow::{Result, bail};
+use anyhow::{Context, Result, bail};
 use std::fs::create_dir_all;
-use std::{fs, path::Path};
+use std::{collections::BTreeMap, fs, path::Path};
 
 use crate::chunks::HashKind;
 use crate::crypto::key::{get_private_key, serialize_verifying_key};
-use crate::crypto::signing::sign;
-
-/// Creates a repository at `repo_path`
+use crate::repo::edition::DEFAULT_EDITION;
+use crate::repo::metadata_policy::{check_metadata, check_package_ids, get_metadata_policy};
+use crate::repo::provenance::remove_provenance;
+use crate::repo::revisions::record_revision;
+use crate::repo::signing_request::sign_manifest;
+use crate::run::profile::is_valid_env_script;
+
+/// Creates a repository at `repo_path`, hashing its chunks with Blake3
 ///
 /// # Errors
 ///
 /// - File permission errors at `repo_path`
 /// - Key generation errors (If you do not already have a key)
 pub fn create_repo(repo_path: &Path, config_path: Option<&Path>) -> Result<()> {
-    if repo_path.join("manifest.yml").exists() {
+    create_repo_with_hash(repo_path, config_path, HashKind::Blake3)
+}
+
+/// Creates a repository at `repo_path`, hashing its chunks with `hash_kind`.
+/// The hash kind can't be changed once packages are inserted.
+///
+/// # Errors
+///
+/// - File permission errors at `repo_path`
+/// - Key generation errors (If you do not already have a key)
+pub fn create_repo_with_hash(
+    repo_path: &Path,
+    config_path: Option<&Path>,
+    hash_kind: HashKind,
+) -> Result<()> {
+    if manifest_io::has_manifest(repo_path) {
         bail!("Repository Already exists")
     }
     create_dir_all(repo_path)?;
 
     let manifest = RepoManifest {
-        edition: "2025".into(),
-        hash_kind: HashKind::Blake3,
+        edition: DEFAULT_EDITION.into(),
+        hash_kind,
+        serial: 0,
+        min_client_edition: None,
+        binary_cache: None,
+        includes: Vec::new(),
+        previous_public_key: None,
+        signing_keys: Vec::new(),
+        advisories: Vec::new(),
+        updates_url: None,
+        channels: BTreeMap::new(),
         metadata: Metadata {
             title: None,
             description: None,
             homepage_url: None,
             version: None,
             license: None,
+            maintainers: Vec::new(),
+            keywords: Vec::new(),
+            categories: Vec::new(),
         },
         mirrors: Vec::new(),
         packages: Vec::new(),
         public_key: serialize_verifying_key(get_private_key(config_path)?.verifying_key())?,
     };
 
-    let manifest_serialized = serde_yaml::to_string(&manifest)?;
-    fs::write(repo_path.join("manifest.yml"), &manifest_serialized)?;
-
-    sign(repo_path, &manifest_serialized, config_path)?;
+    sign_manifest(repo_path, &serde_yaml::to_string(&manifest)?, config_path)?;
 
     Ok(())
 }
@@ -53,16 +112,42 @@ pub fn create_repo(repo_path: &Path, config_path: Option<&Path>) -> Result<()> {
 ///
 /// # Errors
 /// - Repo not signed with local signature
+/// - Invalid package id or alias, see [`metadata_policy::check_package_id`]
+/// - The package's metadata breaks the Repository's policy
+/// - An env script isn't a relative path inside the package
 pub fn insert_package(
     package_manifest: &PackageManifest,
     repo_path: &Path,
     config_path: Option<&Path>,
 ) -> Result<()> {
-    let _ = remove_package(&package_manifest.id, repo_path, config_path);
+    let policy = get_metadata_policy(repo_path)?;
+    check_package_ids(package_manifest, &policy)?;
+    check_metadata(&package_manifest.metadata, &policy)
+        .with_context(|| format!("{} breaks the Repository's policy", package_manifest.id))?;
+    if let Some(env_script) = package_manifest
+        .env_scripts
+        .iter()
+        .find(|env_script| !is_valid_env_script(env_script))
+    {
+        bail!(
+            "Env script {} of {} must be a relative path inside the package.",
+            env_script.display(),
+            package_manifest.id
+        )
+    }
+
+    let superseded = read_manifest(repo_path)?
+        .packages
+        .into_iter()
+        .find(|package| {
+            package.id == package_manifest.id && package.chunks != package_manifest.chunks
+        });
+    let _ = remove_provenance(repo_path, &package_manifest.id);
 
     let mut repo_manifest = read_manifest(repo_path)?;
 
     let mut packages: Vec<PackageManifest> = repo_manifest.packages;
+    packages.retain(|package| package.id != package_manifest.id);
 
     for package in &packages {
         if package.aliases.contains(&package_manifest.id) {
@@ -84,10 +169,13 @@ pub fn insert_package(
     packages.push(package_manifest.clone());
     repo_manifest.packages = packages;
 
-    let repo_manifest_serialized = serde_yaml::to_string(&repo_manifest)?;
+    let repo_manifest_serialized = serialize_manifest(repo_path, &repo_manifest)?;
 
-    let signature = sign(repo_path, &repo_manifest_serialized, config_path)?;
-    update_manifest(repo_path, &repo_manifest_serialized, &signature.to_bytes())?;
+    sign_manifest(repo_path, &repo_manifest_serialized, config_path)?;
+
+    if let Some(superseded) = superseded {
+        record_revision(repo_path, &superseded)?;
+    }
 
     Ok(())
 }
@@ -108,10 +196,11 @@ pub fn remove_package(
         .packages
         .retain(|package| package.id != package_id);
 
-    let repo_manifest_serialized = serde_yaml::to_string(&repo_manifest)?;
+    let repo_manifest_serialized = serialize_manifest(repo_path, &repo_manifest)?;
 
-    let signature = sign(repo_path, &repo_manifest_serialized, config_path)?;
-    update_manifest(repo_path, &repo_manifest_serialized, &signature.to_bytes())?;
+    sign_manifest(repo_path, &repo_manifest_serialized, config_path)?;
+
+    remove_provenance(repo_path, package_id)?;
 
     Ok(())
 }
@@ -134,6 +223,213 @@ pub fn get_package(repo_manifest: &RepoManifest, package_id: &str) -> Result<Pac
     bail!("Could not find package '{package_id}' found in Repository.",);
 }
 
+/// Gets the package an id, alias or virtual name (see [`PackageManifest::provides`]) refers to.
+/// A package with that id or alias wins over packages providing it, then the first provider is used.
+///
+/// # Errors
+///
+/// - No package in the Repository has or provides that name
+pub fn get_provider(repo_manifest: &RepoManifest, name: &str) -> Result<PackageManifest> {
+    if let Ok(package) = get_package(repo_manifest, name) {
+        return Ok(package);
+    }
+
+    repo_manifest
+        .packages
+        .iter()
+        .find(|package| package.provides.iter().any(|provided| provided == name))
+        .cloned()
+        .with_context(|| format!("Nothing in the Repository provides '{name}'."))
+}
+
+/// Gets a package and everything it depends on, transitively, from a single Repository.
+/// The requested package is always first.
+///
+/// # Errors
+///
+/// - ID or any dependency doesn't exist inside the Repository
+pub fn get_package_closure(
+    repo_manifest: &RepoManifest,
+    package_id: &str,
+) -> Result<Vec<PackageManifest>> {
+    let mut closure = vec![get_package(repo_manifest, package_id)?];
+    let mut index = 0;
+
+    while let Some(package) = closure.get(index) {
+        let dependencies = package.dependencies.clone();
+        let package_id = package.id.clone();
+
+        for dependency in dependencies {
+            let dependency = get_package(repo_manifest, &dependency).with_context(|| {
+                format!("Dependency '{dependency}' of '{package_id}' is missing.")
+            })?;
+
+            if !closure.iter().any(|package| package.id == dependency.id) {
+                closure.push(dependency);
+            }
+        }
+
+        index += 1;
+    }
+
+    Ok(closure)
+}
+
+/// Every chain of dependencies leading to `package_id`, each starting at an installed package
+/// nothing else installed depends on, and ending at `package_id`.
+/// Empty if nothing installed depends on it.
+#[must_use]
+pub fn dependency_chains(installed: &[PackageManifest], package_id: &str) -> Vec<Vec<String>> {
+    fn walk(
+        installed: &[PackageManifest],
+        package_id: &str,
+        chain: &mut Vec<String>,
+        chains: &mut Vec<Vec<String>>,
+    ) {
+        let Some(package) = chain
+            .last()
+            .and_then(|last| installed.iter().find(|package| &package.id == last))
+        else {
+            return;
+        };
+
+        for dependency in &package.dependencies {
+            // Cycles would never end
+            if chain.contains(dependency) {
+                continue;
+            }
+
+            chain.push(dependency.clone());
+            if dependency == package_id {
+                chains.push(chain.clone());
+            } else {
+                walk(installed, package_id, chain, chains);
+            }
+            chain.pop();
+        }
+    }
+
+    let mut chains = Vec::new();
+
+    for root in installed.iter().filter(|root| {
+        root.id != package_id
+            && !installed
+                .iter()
+                .any(|package| package.dependencies.contains(&root.id))
+    }) {
+        walk(
+            installed,
+            package_id,
+            &mut vec![root.id.clone()],
+            &mut chains,
+        );
+    }
+
+    chains
+}
+
+/// Every installed package that needs `package_id`, directly or through other packages.
+#[must_use]
+pub fn installed_dependents(installed: &[PackageManifest], package_id: &str) -> Vec<String> {
+    let mut dependents: Vec<String> = Vec::new();
+    let mut index = 0;
+    let mut needed = vec![package_id.to_string()];
+
+    while let Some(needed_id) = needed.get(index).cloned() {
+        for package in installed {
+            if package.dependencies.contains(&needed_id)
+                && package.id != package_id
+                && !dependents.contains(&package.id)
+            {
+                dependents.push(package.id.clone());
+                needed.push(package.id.clone());
+            }
+        }
+
+        index += 1;
+    }
+
+    dependents
+}
+
+/// Groups packages that share a dependency (eg: a runtime), or depend on each other,
+/// so they can be updated in lock-step. Groups keep the order of `package_ids`.
+#[must_use]
+pub fn group_by_shared_dependencies(
+    repo_manifest: &RepoManifest,
+    package_ids: &[String],
+) -> Vec<Vec<String>> {
+    let mut groups: Vec<(Vec<String>, Vec<String>)> = Vec::new();
+
+    for package_id in package_ids {
+        // A broken dependency shouldn't hold back the update of the package itself
+        let closure: Vec<String> = get_package_closure(repo_manifest, package_id).map_or_else(
+            |_| vec![package_id.clone()],
+            |closure| closure.into_iter().map(|package| package.id).collect(),
+        );
+
+        let mut members = vec![package_id.clone()];
+        let mut group_closure = closure;
+
+        // Merge every group this package touches into it
+        let mut index = 0;
+        while index < groups.len() {
+            if groups[index].1.iter().any(|id| group_closure.contains(id)) {
+                let (other_members, other_closure) = groups.remove(index);
+                members.extend(other_members);
+                group_closure.extend(other_closure);
+            } else {
+                index += 1;
+            }
+        }
+
+        groups.push((members, group_closure));
+    }
+
+    let position = |id: &String| package_ids.iter().position(|other| other == id);
+    for (members, _) in &mut groups {
+        members.sort_by_key(position);
+    }
+    groups.sort_by_key(|(members, _)| members.first().and_then(position));
+
+    groups.into_iter().map(|(members, _)| members).collect()
+}
+
+/// Searches a Repository for packages whose id, aliases, title, description, keywords or categories contain `query`.
+/// Matching is case-insensitive, and an empty query matches everything.
+#[must_use]
+pub fn search_packages(repo_manifest: &RepoManifest, query: &str) -> Vec<PackageManifest> {
+    let query = query.to_lowercase();
+
+    repo_manifest
+        .packages
+        .iter()
+        .filter(|package| {
+            let metadata = &package.metadata;
+
+            package.id.to_lowercase().contains(&query)
+                || package
+                    .aliases
+                    .iter()
+                    .any(|alias| alias.to_lowercase().contains(&query))
+                || metadata
+                    .title
+                    .as_ref()
+                    .is_some_and(|title| title.to_lowercase().contains(&query))
+                || metadata
+                    .description
+                    .as_ref()
+                    .is_some_and(|description| description.to_lowercase().contains(&query))
+                || metadata
+                    .keywords
+                    .iter()
+                    .chain(&metadata.categories)
+                    .any(|word| word.to_lowercase().contains(&query))
+        })
+        .cloned()
+        .collect()
+}
+
 /// Gets an installed package manifest from a repository.
 ///
 /// # Errors
@@ -167,14 +463,15 @@ pub fn get_installed_package(repo_path: &Path, id: &str) -> Result<PackageManife
     bail!("No package found in Repository.");
 }
 
-/// Lists all packages from a repository.
+/// Lists all packages a repository is subscribed to.
 ///
 /// # Errors
 ///
 /// - Filesystem errors (Permissions most likely)
 /// - Repository doesn't exist
+/// - The selected channel is not in the manifest
 pub fn get_all_packages(repo_path: &Path) -> Result<Vec<PackageManifest>> {
-    let repo_manifest = read_manifest(repo_path)?;
+    let repo_manifest = read_subscribed_manifest(repo_path)?;
     let mut packages = Vec::new();
 
     // Check ID's and aliases
@@ -192,21 +489,19 @@ pub fn get_all_packages(repo_path: &Path) -> Result<Vec<PackageManifest>> {
 /// - Filesystem errors (Permissions most likely)
 /// - Repository doesn't exist
 pub fn get_all_installed_packages(repo_path: &Path) -> Result<Vec<PackageManifest>> {
-    let mut packages = Vec::new();
-    let installed_path = &repo_path.join("installed");
-
-    if installed_path.exists() {
-        // Check ID's and aliases
-        for entry in fs::read_dir(installed_path)? {
-            let file = entry?.path();
-            let package = serde_yaml::from_str(&fs::read_to_string(file.join("install.meta"))?)?;
-
-            packages.push(package);
-        }
+    Ok(installed::get_installed(repo_path)?
+        .into_iter()
+        .map(|install_meta| install_meta.package)
+        .collect())
+}
 
-        Ok(packages)
-    } else {
-        Ok(Vec::new())
+/// A package with nothing but an id and dependencies, for tests
+#[cfg(test)]
+pub(crate) fn test_package(id: &str, dependencies: &[&str]) -> PackageManifest {
+    PackageManifest {
+        id: id.into(),
+        dependencies: dependencies.iter().map(ToString::to_string).collect(),
+        ..Default::default()
     }
 }
 
@@ -229,17 +524,8 @@ mod tests {
         let package_manifest = PackageManifest {
             aliases: vec!["example_alias".into()],
             id: "test".into(),
-            chunks: vec![],
-            commands: vec![],
-            metadata: Metadata {
-                title: None,
-                description: None,
-                homepage_url: None,
-                version: None,
-                license: None,
-            },
-            env: None,
             build_hash: "Example Build Hash".to_string(),
+            ..Default::default()
         };
 
         insert_package(&package_manifest, repo_path, Some(repo_path))?;
@@ -249,6 +535,23 @@ mod tests {
         remove_package(&package_manifest.id, repo_path, Some(repo_path))?;
         assert!(get_package(&read_manifest(repo_path)?, "test").is_err());
 
+        // Aliases have to be valid ids too, and follow the id prefixes of the policy
+        let mut invalid = package_manifest.clone();
+        invalid.aliases = vec!["../escape".into()];
+        assert!(insert_package(&invalid, repo_path, Some(repo_path)).is_err());
+        metadata_policy::set_metadata_policy(
+            repo_path,
+            &metadata_policy::MetadataPolicy {
+                id_prefixes: vec!["org.example.".into()],
+                ..Default::default()
+            },
+        )?;
+        assert!(insert_package(&package_manifest, repo_path, Some(repo_path)).is_err());
+        let mut prefixed = package_manifest;
+        prefixed.id = "org.example.test".into();
+        prefixed.aliases = vec!["org.example.alias".into()];
+        insert_package(&prefixed, repo_path, Some(repo_path))?;
+
         Ok(())
     }
 
@@ -291,4 +594,89 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_package_closure() -> Result<()> {
+        let repo = TempDir::new()?;
+        let repo_path = repo.path();
+        create_repo(repo_path, Some(repo_path))?;
+
+        let mut repo_manifest = read_manifest(repo_path)?;
+        repo_manifest.packages = vec![
+            test_package("app", &["lib", "runtime"]),
+            test_package("lib", &["runtime"]),
+            // Cycles must not loop forever
+            test_package("runtime", &["app"]),
+            test_package("unrelated", &[]),
+            test_package("broken", &["missing"]),
+        ];
+
+        let closure: Vec<String> = get_package_closure(&repo_manifest, "app")?
+            .into_iter()
+            .map(|package| package.id)
+            .collect();
+        assert_eq!(closure, vec!["app", "lib", "runtime"]);
+
+        assert!(get_package_closure(&repo_manifest, "broken").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_why() {
+        let installed = vec![
+            test_package("editor", &["toolkit", "runtime"]),
+            test_package("toolkit", &["runtime"]),
+            test_package("runtime", &[]),
+            test_package("game", &["runtime"]),
+            test_package("standalone", &[]),
+        ];
+
+        assert_eq!(
+            dependency_chains(&installed, "runtime"),
+            vec![
+                vec!["editor", "toolkit", "runtime"],
+                vec!["editor", "runtime"],
+                vec!["game", "runtime"],
+            ]
+        );
+        assert!(dependency_chains(&installed, "standalone").is_empty());
+
+        assert_eq!(
+            installed_dependents(&installed, "runtime"),
+            vec!["editor", "toolkit", "game"]
+        );
+        assert_eq!(installed_dependents(&installed, "toolkit"), vec!["editor"]);
+        assert!(installed_dependents(&installed, "editor").is_empty());
+    }
+
+    #[test]
+    fn test_group_by_shared_dependencies() -> Result<()> {
+        let repo = TempDir::new()?;
+        let repo_path = repo.path();
+        create_repo(repo_path, Some(repo_path))?;
+
+        let mut repo_manifest = read_manifest(repo_path)?;
+        repo_manifest.packages = vec![
+            test_package("editor", &["runtime"]),
+            test_package("standalone", &[]),
+            test_package("viewer", &["runtime"]),
+            test_package("runtime", &[]),
+            test_package("broken", &["missing"]),
+        ];
+
+        let ids = ["viewer", "standalone", "broken", "runtime", "editor"].map(String::from);
+        let groups = group_by_shared_dependencies(&repo_manifest, &ids);
+
+        assert_eq!(
+            groups,
+            vec![
+                vec!["viewer", "runtime", "editor"],
+                vec!["standalone"],
+                vec!["broken"],
+            ]
+        );
+
+        Ok(())
+    }
 }
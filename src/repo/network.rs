@@ -1,72 +1,410 @@
-use anyhow::Result;
+use anyhow::{Result, bail};
 use ed25519_dalek::VerifyingKey;
-use std::path::Path;
+use std::{fs, path::Path};
 
 use crate::{
-    crypto::{key::deserialize_verifying_key, signing::verify_signature},
-    repo::{RepoManifest, io::atomic_replace, read_manifest, update_manifest},
+    crypto::{
+        key::deserialize_verifying_key,
+        pins::KeyPins,
+        signing::{verify_signature, verify_signature_any},
+    },
+    policy::read_policy,
+    repo::{
+        RepoManifest,
+        credentials::{RepoAuth, get_repo_auth_for, get_url, post_url},
+        edition::check_client_edition,
+        get_package,
+        manifest_io::{
+            ManifestFormat, atomic_replace, check_manifest_update, decode_manifest, has_manifest,
+            parse_manifest, parse_manifest_as, remove_other_manifest_formats, update_manifest_as,
+        },
+        mirrors::get_updates_url,
+        publish::create_publish_archive,
+        read_manifest,
+        subscription::apply_subscription,
+    },
 };
 
+/// How many times in a row a Repository may move its `updates_url` during one update
+const MAX_REDIRECTS: usize = 5;
+
 /// Updates the Repository and returns a list of packages that have changed
 ///
+/// The manifest is fetched from [`get_updates_url`]. When the new, signed manifest moves
+/// `updates_url`, the update is fetched again from there.
+/// With `pins`, every url it is fetched from must still serve the key pinned for it.
+///
 /// # Errors
 ///
 /// - Network Unavailable
 /// - Server Unavailable
 /// - Invalid signed data
-pub async fn update_repository(repo_path: &Path) -> Result<bool> {
+/// - Repository requires a newer client edition, and `allow_newer_edition` is not set
+/// - Repository's key is not allowed by the machine's policy
+/// - Repository moved its `updates_url` too many times in a row
+/// - Repository is signed with another key than the one pinned for its url
+pub async fn update_repository(
+    repo_path: &Path,
+    allow_newer_edition: bool,
+    mut pins: Option<&mut KeyPins>,
+) -> Result<bool> {
     let old_manifest = read_manifest(repo_path)?;
 
-    if let Some(mirror) = old_manifest.mirrors.first() {
-        let res_manifest = reqwest::get(format!("{mirror}/manifest.yml")).await?;
-        let res_manifest_sig = reqwest::get(format!("{mirror}/manifest.yml.sig")).await?;
+    let Some(mut url) = get_updates_url(repo_path, &old_manifest)? else {
+        return Ok(false);
+    };
+    let mut edition = old_manifest.edition.clone();
 
-        let manifest = res_manifest.text().await?;
-        let signature = res_manifest_sig.bytes().await?;
+    for _ in 0..=MAX_REDIRECTS {
+        let new_manifest = fetch_manifest_update(
+            repo_path,
+            &url,
+            &edition,
+            allow_newer_edition,
+            pins.as_deref_mut(),
+        )
+        .await?;
 
-        let new_manifest = update_manifest(repo_path, &manifest, &signature)?;
+        // Signed by a key we trust, so the redirect is the Repository's own
+        match get_updates_url(repo_path, &new_manifest)? {
+            Some(next) if new_manifest.updates_url.is_some() && next != url => {
+                url = next;
+                edition.clone_from(&new_manifest.edition);
+            }
+            _ => return Ok(old_manifest != new_manifest),
+        }
+    }
+
+    bail!("Repository moved its updates_url more than {MAX_REDIRECTS} times, last to {url}.")
+}
 
-        Ok(old_manifest != new_manifest)
-    } else {
-        Ok(false)
+/// Fetches and verifies the manifest a Repository would update to, without saving it,
+/// eg: to review an update before running it.
+///
+/// The Repository's subscription is applied, like on an update. A moved `updates_url`
+/// is not followed, as the keys it would need to be trusted with are not saved.
+///
+/// # Errors
+///
+/// - Repository has no mirrors to update from
+/// - Network Unavailable
+/// - Server Unavailable
+/// - Invalid signed data
+/// - Repository requires a newer client edition, and `allow_newer_edition` is not set
+pub async fn fetch_remote_manifest(
+    repo_path: &Path,
+    allow_newer_edition: bool,
+) -> Result<RepoManifest> {
+    let old_manifest = read_manifest(repo_path)?;
+    let Some(url) = get_updates_url(repo_path, &old_manifest)? else {
+        bail!("Repository has no mirrors to compare against.")
+    };
+
+    let (format, manifest, signature) = fetch_manifest(
+        &url,
+        ManifestFormat::for_edition(&old_manifest.edition),
+        get_repo_auth_for(repo_path)?.as_ref(),
+    )
+    .await?;
+
+    check_client_edition(&decode_manifest(&manifest, format)?, allow_newer_edition)?;
+    let new_manifest = check_manifest_update(repo_path, &manifest, &signature, format)?;
+    read_policy(None)?.check_repo_key(&new_manifest.public_key)?;
+
+    apply_subscription(repo_path, new_manifest)
+}
+
+/// Fetches, verifies and saves the manifest at `url`, checking it against the key pinned for `url`
+async fn fetch_manifest_update(
+    repo_path: &Path,
+    url: &str,
+    edition: &str,
+    allow_newer_edition: bool,
+    pins: Option<&mut KeyPins>,
+) -> Result<RepoManifest> {
+    // The edition we have tells whether the mirror also serves the faster CBOR manifest
+    let (format, manifest, signature) = fetch_manifest(
+        url,
+        ManifestFormat::for_edition(edition),
+        get_repo_auth_for(repo_path)?.as_ref(),
+    )
+    .await?;
+
+    check_client_edition(&decode_manifest(&manifest, format)?, allow_newer_edition)?;
+    let parsed = parse_manifest_as(&manifest, format)?;
+    read_policy(None)?.check_repo_key(&parsed.public_key)?;
+    if let Some(pins) = &pins {
+        pins.check(url, &parsed, &manifest, &signature)?;
+    }
+    // A selected channel that is gone would leave nothing readable, so the old manifest is kept
+    apply_subscription(repo_path, parsed)?;
+
+    let new_manifest = update_manifest_as(repo_path, &manifest, &signature, format)?;
+    if let Some(pins) = pins {
+        pins.pin(url, &new_manifest.public_key)?;
+    }
+
+    remove_other_manifest_formats(repo_path, format)?;
+
+    Ok(new_manifest)
+}
+
+/// Fetches a mirror's manifest and its signature in `format`, authenticated with `auth` if given.
+/// Falls back to YAML if the mirror has no CBOR manifest, eg: the Repository moved back to an older edition.
+async fn fetch_manifest(
+    mirror: &str,
+    mut format: ManifestFormat,
+    auth: Option<&RepoAuth>,
+) -> Result<(ManifestFormat, Vec<u8>, Vec<u8>)> {
+    let mut res_manifest = get_url(&format!("{mirror}/{}", format.filename()), auth).await?;
+
+    if format == ManifestFormat::Cbor && !res_manifest.status().is_success() {
+        format = ManifestFormat::Yaml;
+        res_manifest = get_url(&format!("{mirror}/{}", format.filename()), auth).await?;
+    }
+
+    let res_manifest_sig =
+        get_url(&format!("{mirror}/{}", format.signature_filename()), auth).await?;
+
+    Ok((
+        format,
+        res_manifest.bytes().await?.to_vec(),
+        res_manifest_sig.bytes().await?.to_vec(),
+    ))
+}
+
+/// A Remote Repository's manifest, verified but not written anywhere yet.
+/// Lets the user look at a Repository before trusting it.
+pub struct FetchedRepository {
+    pub manifest: RepoManifest,
+    raw_manifest: String,
+    signature: Vec<u8>,
+}
+
+impl FetchedRepository {
+    /// Errors out if the manifest may not be trusted under the key pinned for `url`, see [`KeyPins::check`].
+    ///
+    /// # Errors
+    ///
+    /// - The manifest is signed with another key than the pinned one
+    pub fn check_pin(&self, pins: &KeyPins, url: &str) -> Result<()> {
+        pins.check(
+            url,
+            &self.manifest,
+            self.raw_manifest.as_bytes(),
+            &self.signature,
+        )
+    }
+
+    /// Writes the manifest to `repo_path`, creating the Repository locally.
+    ///
+    /// # Errors
+    ///
+    /// - Filesystem errors (Permissions)
+    pub fn save(&self, repo_path: &Path) -> Result<()> {
+        // Write to a .new, and then rename atomically
+        atomic_replace(repo_path, "manifest.yml", self.raw_manifest.as_bytes())?;
+        atomic_replace(repo_path, "manifest.yml.sig", &self.signature)
     }
 }
 
 /// Creates a Repository from a Remote Repository.
+/// Requests are authenticated with the credentials stored for its name, if any.
 ///
 /// # Errors
 ///
 /// - Network Unavailable
 /// - Server Unavailable
 /// - Invalid signed data
+/// - Repository requires a newer client edition, and `allow_newer_edition` is not set
 pub async fn add_repository(
     repo_path: &Path,
     mirror: &str,
     verifying_key: Option<VerifyingKey>,
+    allow_newer_edition: bool,
 ) -> Result<RepoManifest> {
-    let res_manifest = reqwest::get(format!("{mirror}/manifest.yml")).await?;
-    let res_manifest_sig = reqwest::get(format!("{mirror}/manifest.yml.sig")).await?;
+    let fetched = fetch_repository(
+        mirror,
+        verifying_key,
+        allow_newer_edition,
+        get_repo_auth_for(repo_path)?.as_ref(),
+    )
+    .await?;
+    fetched.save(repo_path)?;
+
+    Ok(fetched.manifest)
+}
+
+/// Fetches and verifies a Remote Repository's manifest, without adding it.
+/// Requests are authenticated with `auth` if given.
+///
+/// # Errors
+///
+/// - Network Unavailable
+/// - Server Unavailable
+/// - Invalid signed data
+/// - Repository requires a newer client edition, and `allow_newer_edition` is not set
+/// - Repository's key is not allowed by the machine's policy
+pub async fn fetch_repository(
+    mirror: &str,
+    verifying_key: Option<VerifyingKey>,
+    allow_newer_edition: bool,
+    auth: Option<&RepoAuth>,
+) -> Result<FetchedRepository> {
+    let res_manifest = get_url(&format!("{mirror}/manifest.yml"), auth).await?;
+    let res_manifest_sig = get_url(&format!("{mirror}/manifest.yml.sig"), auth).await?;
 
     let raw_manifest = res_manifest.text().await?;
-    let signature = res_manifest_sig.bytes().await?;
+    let mut signature = res_manifest_sig.bytes().await?;
 
-    if let Some(verifying_key) = verifying_key {
+    if let Some(verifying_key) = verifying_key
+        && verify_signature(&raw_manifest, &signature, verifying_key).is_err()
+    {
+        // Mid key rotation, the new key only signs `manifest.yml.sig.next`
+        signature = get_url(&format!("{mirror}/manifest.yml.sig.next"), auth)
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
         verify_signature(&raw_manifest, &signature, verifying_key)?;
     }
 
+    check_client_edition(
+        &decode_manifest(raw_manifest.as_bytes(), ManifestFormat::Yaml)?,
+        allow_newer_edition,
+    )?;
+
     // Make sure it actually deserializes
-    let manifest: RepoManifest = serde_yaml::from_str(&raw_manifest)?;
+    let manifest = parse_manifest(&raw_manifest)?;
 
     // VERIFY IT MATCHES ITSELF. IMPORTANT.
+    verify_signature_any(&raw_manifest, &signature, &manifest.trusted_keys())?;
+    read_policy(None)?.check_repo_key(&manifest.public_key)?;
+
+    Ok(FetchedRepository {
+        manifest,
+        raw_manifest,
+        signature: signature.to_vec(),
+    })
+}
+
+/// Adds every feed `repo_manifest` includes that is not yet a local Repository, next to `repo_path`.
+/// Feeds already added are updated like any other Repository.
+///
+/// # Errors
+///
+/// - Network Unavailable
+/// - Server Unavailable
+/// - A feed is not signed with its pinned key
+/// - A feed already added locally has a different key than the one pinned
+///
+/// # Returns
+///
+/// The names of the newly added Repositories, and their manifests
+pub async fn add_included_feeds(
+    repo_path: &Path,
+    repo_manifest: &RepoManifest,
+    allow_newer_edition: bool,
+) -> Result<Vec<(String, RepoManifest)>> {
+    let repo_name = repo_path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    let repos_path = repo_path.parent().unwrap_or(repo_path);
+    let mut added = Vec::new();
+
+    for feed in &repo_manifest.includes {
+        let feed_repo_name = feed.repo_name(&repo_name);
+        let feed_repo_path = repos_path.join(&feed_repo_name);
+
+        if has_manifest(&feed_repo_path) {
+            if read_manifest(&feed_repo_path)?.public_key != feed.public_key {
+                bail!(
+                    "{feed_repo_name} is signed with a different key than {repo_name} pins for it."
+                )
+            }
+            continue;
+        }
+
+        fs::create_dir_all(&feed_repo_path)?;
+        match add_repository(
+            &feed_repo_path,
+            &feed.url,
+            Some(deserialize_verifying_key(&feed.public_key)?),
+            allow_newer_edition,
+        )
+        .await
+        {
+            Ok(manifest) => added.push((feed_repo_name, manifest)),
+            Err(err) => {
+                fs::remove_dir_all(&feed_repo_path)?;
+                return Err(err);
+            }
+        }
+    }
+
+    Ok(added)
+}
+
+/// Fetches the public key a remote Repository is currently signed with.
+///
+/// # Errors
+///
+/// - Network Unavailable
+/// - Server Unavailable
+/// - Invalid signed data
+pub async fn fetch_public_key(url: &str) -> Result<String> {
+    let raw_manifest = get_url(&format!("{url}/manifest.yml"), None)
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    let signature = get_url(&format!("{url}/manifest.yml.sig"), None)
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+
+    let manifest = parse_manifest(&raw_manifest)?;
     verify_signature(
         &raw_manifest,
         &signature,
         deserialize_verifying_key(&manifest.public_key)?,
     )?;
 
-    // Write to a .new, and then rename atomically
-    atomic_replace(repo_path, "manifest.yml", raw_manifest.as_bytes())?;
-    atomic_replace(repo_path, "manifest.yml.sig", &signature)?;
+    Ok(manifest.public_key)
+}
+
+/// Publishes a package from a local Repository to a remote `flint serve` instance.
+///
+/// # Errors
+///
+/// - Network Unavailable
+/// - Server rejected the package (Not a trusted maintainer, publishing disabled)
+/// - Chunks missing from the local chunk store
+pub async fn publish_package(
+    repo_path: &Path,
+    package_id: &str,
+    chunk_store_path: &Path,
+    remote_url: &str,
+    config_path: Option<&Path>,
+) -> Result<()> {
+    let package = get_package(&read_manifest(repo_path)?, package_id)?;
+    let archive = create_publish_archive(&package, chunk_store_path, config_path)?;
+
+    let response = post_url(
+        &format!("{remote_url}/api/v1/publish"),
+        archive,
+        get_repo_auth_for(repo_path)?.as_ref(),
+    )
+    .await?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        bail!("Server rejected package ({status}): {body}")
+    }
 
-    Ok(manifest)
+    Ok(())
 }
@@ -0,0 +1,287 @@
+use anyhow::{Context, Result, bail};
+use ed25519_dalek::VerifyingKey;
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    path::Path,
+};
+
+use crate::{
+    chunks::{Chunk, missing_chunks, store_chunk},
+    crypto::signing::{verify_signature, verify_signature_any},
+    policy::read_policy,
+    repo::{
+        RepoManifest,
+        edition::check_client_edition,
+        manifest_io::{
+            ManifestFormat, atomic_replace, decode_manifest, has_manifest, parse_manifest_as,
+            remove_other_manifest_formats, update_manifest_as,
+        },
+        read_manifest,
+    },
+    utils::temp::TempDir,
+};
+
+/// Every file of a signed manifest that may exist, in both formats.
+/// The CBOR manifest only exists from its edition on, `.sig.next` only mid key rotation.
+fn manifest_filenames() -> Vec<String> {
+    [ManifestFormat::Yaml, ManifestFormat::Cbor]
+        .into_iter()
+        .flat_map(|format| {
+            [
+                format.filename().to_string(),
+                format.signature_filename().to_string(),
+                format!("{}.next", format.signature_filename()),
+            ]
+        })
+        .collect()
+}
+
+/// The chunks a Repository's packages use, by filename
+fn used_chunks(manifest: &RepoManifest) -> BTreeMap<String, Chunk> {
+    manifest
+        .packages
+        .iter()
+        .flat_map(|package| package.chunks.iter())
+        .map(|chunk| (chunk.filename(), chunk.clone()))
+        .collect()
+}
+
+/// Packs a Repository into a single zstd compressed tar at `out_path`: its signed manifest,
+/// and only the chunks its packages use. Made for moving Repositories between machines without a network.
+///
+/// # Errors
+///
+/// - Chunks missing from the chunk store
+/// - Filesystem errors (Out of space, Permissions)
+///
+/// # Returns
+///
+/// The number of packed chunks
+pub fn pack_repo(repo_path: &Path, chunk_store_path: &Path, out_path: &Path) -> Result<usize> {
+    let chunks = used_chunks(&read_manifest(repo_path)?);
+
+    let chunk_list: Vec<Chunk> = chunks.values().cloned().collect();
+    let missing = missing_chunks(&chunk_list, chunk_store_path);
+    if !missing.is_empty() {
+        bail!(
+            "{} chunks are missing from the chunk store, download every package before packing.",
+            missing.len()
+        )
+    }
+
+    let mut tmp_path = out_path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+
+    let result = (|| -> Result<()> {
+        let encoder = zstd::Encoder::new(File::create(&tmp_path)?, 0)?;
+        let mut archive = tar::Builder::new(encoder);
+
+        // Manifests first, so they can be checked before any chunk is read
+        for filename in manifest_filenames() {
+            if repo_path.join(&filename).exists() {
+                archive.append_path_with_name(repo_path.join(&filename), &filename)?;
+            }
+        }
+
+        for filename in chunks.keys() {
+            archive.append_path_with_name(
+                chunk_store_path.join(filename),
+                Path::new("chunks").join(filename),
+            )?;
+        }
+
+        archive.into_inner()?.finish()?;
+
+        Ok(())
+    })();
+
+    if let Err(err) = result {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(err);
+    }
+    fs::rename(&tmp_path, out_path)?;
+
+    Ok(chunks.len())
+}
+
+/// Unpacks a Repository packed by [`pack_repo`] into `repo_path`, and its chunks into the chunk store.
+///
+/// A new Repository is trusted like one added from a mirror: its manifest must be signed by its own key,
+/// or `verifying_key` if given. An existing one is updated, so the manifest must be signed by a key
+/// it already trusts, and must not be older than the one it has.
+///
+/// # Errors
+///
+/// - Invalid archive, or files in it that don't belong to a Repository
+/// - Invalid signed data, or chunks not matching their hash
+/// - Chunks neither in the archive nor in the chunk store
+/// - Repository requires a newer client edition, and `allow_newer_edition` is not set
+/// - Repository's key is not allowed by the machine's policy
+/// - Filesystem errors (Out of space, Permissions)
+pub fn unpack_repo(
+    archive_path: &Path,
+    repo_path: &Path,
+    chunk_store_path: &Path,
+    verifying_key: Option<VerifyingKey>,
+    allow_newer_edition: bool,
+) -> Result<RepoManifest> {
+    let extract = TempDir::new()?;
+    let extract_path = extract.path();
+    fs::create_dir(extract_path.join("chunks"))?;
+
+    let manifest_filenames = manifest_filenames();
+    let file = File::open(archive_path)
+        .with_context(|| format!("Could not open {}", archive_path.display()))?;
+    let mut archive = tar::Archive::new(zstd::Decoder::new(file)?);
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_path_buf();
+
+        // Nothing else is unpacked, so a crafted archive can't write outside of it
+        let is_manifest = manifest_filenames
+            .iter()
+            .any(|filename| path == Path::new(filename));
+        let is_chunk = path.parent() == Some(Path::new("chunks"))
+            && path
+                .file_name()
+                .is_some_and(|name| !name.to_string_lossy().starts_with('.'));
+
+        if !is_manifest && !is_chunk {
+            bail!("Unexpected file {} in Repository archive.", path.display())
+        }
+
+        entry.unpack(extract_path.join(&path))?;
+    }
+
+    let format = ManifestFormat::of_repo(extract_path);
+    let raw_manifest = fs::read(extract_path.join(format.filename()))
+        .with_context(|| "Repository archive has no manifest")?;
+    let signature = fs::read(extract_path.join(format.signature_filename()))
+        .with_context(|| "Repository archive has no manifest signature")?;
+
+    check_client_edition(
+        &decode_manifest(&raw_manifest, format)?,
+        allow_newer_edition,
+    )?;
+    let manifest = parse_manifest_as(&raw_manifest, format)?;
+
+    if let Some(verifying_key) = verifying_key {
+        verify_signature(&raw_manifest, &signature, verifying_key)?;
+    }
+    // VERIFY IT MATCHES ITSELF. IMPORTANT.
+    verify_signature_any(&raw_manifest, &signature, &manifest.trusted_keys())?;
+    read_policy(None)?.check_repo_key(&manifest.public_key)?;
+
+    // Packed from a Repository missing chunks, or cut short
+    let chunks = used_chunks(&manifest);
+    let missing = chunks
+        .keys()
+        .filter(|filename| {
+            !extract_path.join("chunks").join(filename).exists()
+                && !chunk_store_path.join(filename).exists()
+        })
+        .count();
+    if missing > 0 {
+        bail!("{missing} chunks are missing from the Repository archive.")
+    }
+
+    fs::create_dir_all(chunk_store_path)?;
+    for (filename, chunk) in chunks {
+        let path = extract_path.join("chunks").join(&filename);
+
+        if path.exists() && !chunk_store_path.join(&filename).exists() {
+            store_chunk(
+                &chunk,
+                &fs::read(path)?,
+                manifest.hash_kind,
+                chunk_store_path,
+            )?;
+        }
+    }
+
+    if has_manifest(repo_path) {
+        let manifest = update_manifest_as(repo_path, &raw_manifest, &signature, format)?;
+
+        remove_other_manifest_formats(repo_path, format)?;
+
+        return Ok(manifest);
+    }
+
+    fs::create_dir_all(repo_path)?;
+    atomic_replace(repo_path, format.filename(), &raw_manifest)?;
+    atomic_replace(repo_path, format.signature_filename(), &signature)?;
+
+    read_manifest(repo_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chunks::save_tree,
+        crypto::key::deserialize_verifying_key,
+        repo::{PackageManifest, create_repo, insert_package},
+    };
+
+    #[test]
+    fn test_pack_unpack() -> Result<()> {
+        let root = temp_dir::TempDir::new()?;
+        let repo_path = &root.path().join("repo");
+        let chunk_store = &root.path().join("chunks");
+        let tree = &root.path().join("tree");
+        create_repo(repo_path, Some(repo_path))?;
+
+        fs::create_dir_all(tree)?;
+        fs::write(tree.join("hello"), "hello")?;
+        let package = PackageManifest {
+            id: "hello".into(),
+            chunks: save_tree(tree, chunk_store, read_manifest(repo_path)?.hash_kind)?,
+            ..Default::default()
+        };
+        insert_package(&package, repo_path, Some(repo_path))?;
+
+        let archive = &root.path().join("repo.tar.zst");
+        assert_eq!(pack_repo(repo_path, chunk_store, archive)?, 1);
+
+        // Another machine, with an empty chunk store
+        let other_repo = &root.path().join("other/repo");
+        let other_chunks = &root.path().join("other/chunks");
+        let public_key = deserialize_verifying_key(&read_manifest(repo_path)?.public_key)?;
+        let manifest = unpack_repo(archive, other_repo, other_chunks, Some(public_key), false)?;
+
+        assert_eq!(manifest.packages, vec![package.clone()]);
+        assert!(
+            missing_chunks(&package.chunks, other_chunks).is_empty(),
+            "Chunks should be unpacked into the chunk store"
+        );
+
+        // Unpacking again updates the Repository in place
+        unpack_repo(archive, other_repo, other_chunks, None, false)?;
+
+        // Only Repository files may be in an archive
+        let evil = &root.path().join("evil.tar.zst");
+        let mut builder = tar::Builder::new(zstd::Encoder::new(File::create(evil)?, 0)?);
+        builder.append_path_with_name(tree.join("hello"), "installed/hello")?;
+        builder.into_inner()?.finish()?;
+        assert!(unpack_repo(evil, other_repo, other_chunks, None, false).is_err());
+
+        // A manifest without its chunks
+        let partial = &root.path().join("partial.tar.zst");
+        let mut builder = tar::Builder::new(zstd::Encoder::new(File::create(partial)?, 0)?);
+        for filename in ["manifest.yml", "manifest.yml.sig"] {
+            builder.append_path_with_name(repo_path.join(filename), filename)?;
+        }
+        builder.into_inner()?.finish()?;
+        let empty_chunks = &root.path().join("empty/chunks");
+        let empty_repo = &root.path().join("empty/repo");
+        assert!(unpack_repo(partial, empty_repo, empty_chunks, None, false).is_err());
+        assert!(!has_manifest(empty_repo));
+
+        fs::remove_dir_all(chunk_store)?;
+        assert!(pack_repo(repo_path, chunk_store, archive).is_err());
+
+        Ok(())
+    }
+}
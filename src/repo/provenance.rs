@@ -0,0 +1,217 @@
+use anyhow::{Context, Result, bail};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::crypto::{
+    key::{deserialize_verifying_key, get_private_key, serialize_verifying_key},
+    signing::{sign_detached, verify_signature},
+};
+
+/// A record of how a package was built, signed by whoever built it.
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Provenance {
+    pub package_id: String,
+    pub build_hash: String,
+    /// Public key of the builder, in the same format as `RepoManifest::public_key`
+    pub builder_key: String,
+    pub sources: Vec<ProvenanceSource>,
+    /// Seconds since the UNIX epoch
+    pub started_at: u64,
+    /// Seconds since the UNIX epoch
+    pub finished_at: u64,
+    /// `<arch>-<os>` of the machine that built the package
+    pub host: String,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ProvenanceSource {
+    pub kind: String,
+    pub url: String,
+    pub commit: Option<String>,
+}
+
+/// Returns the current time in seconds since the UNIX epoch
+///
+/// # Errors
+///
+/// - System clock is before 1970
+pub fn now() -> Result<u64> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs())
+}
+
+/// Gets the host this binary was built for, eg: `x86_64-linux`
+#[must_use]
+pub fn host_triple() -> String {
+    format!("{}-{}", std::env::consts::ARCH, std::env::consts::OS)
+}
+
+fn provenance_path(repo_path: &Path, package_id: &str) -> PathBuf {
+    repo_path
+        .join("provenance")
+        .join(format!("{package_id}.yml"))
+}
+
+/// Signs and writes a provenance record next to the Repository manifest.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions, Out of space)
+/// - Private key could not be read
+pub fn write_provenance(
+    repo_path: &Path,
+    provenance: &Provenance,
+    config_path: Option<&Path>,
+) -> Result<()> {
+    let (serialized, signature) = sign_provenance(provenance, config_path)?;
+
+    store_provenance(repo_path, &provenance.package_id, &serialized, &signature)
+}
+
+/// Serializes and signs a provenance record, returning it with its signature.
+///
+/// # Errors
+///
+/// - Private key could not be read
+pub fn sign_provenance(
+    provenance: &Provenance,
+    config_path: Option<&Path>,
+) -> Result<(String, Vec<u8>)> {
+    let serialized = serde_yaml::to_string(provenance)?;
+    let signature = sign_detached(&serialized, config_path)?;
+
+    Ok((serialized, signature.to_bytes().to_vec()))
+}
+
+/// Writes a provenance record signed elsewhere, eg: carried in a build artifact, see [`verify_provenance`].
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions, Out of space)
+pub fn store_provenance(
+    repo_path: &Path,
+    package_id: &str,
+    serialized: &str,
+    signature: &[u8],
+) -> Result<()> {
+    let path = provenance_path(repo_path, package_id);
+
+    fs::create_dir_all(repo_path.join("provenance"))?;
+    fs::write(&path, serialized)?;
+    fs::write(path.with_extension("yml.sig"), signature)?;
+
+    Ok(())
+}
+
+/// Builds a provenance record for a package that has just been built with the local key.
+///
+/// # Errors
+///
+/// - Private key could not be read
+pub fn new_provenance(
+    package_id: &str,
+    build_hash: &str,
+    sources: Vec<ProvenanceSource>,
+    started_at: u64,
+    config_path: Option<&Path>,
+) -> Result<Provenance> {
+    Ok(Provenance {
+        package_id: package_id.to_string(),
+        build_hash: build_hash.to_string(),
+        builder_key: serialize_verifying_key(get_private_key(config_path)?.verifying_key())?,
+        sources,
+        started_at,
+        finished_at: now()?,
+        host: host_triple(),
+    })
+}
+
+/// Reads a provenance record, and verifies it against the builder key it contains.
+///
+/// # Errors
+///
+/// - No provenance recorded for this package
+/// - Invalid signature
+pub fn read_provenance(repo_path: &Path, package_id: &str) -> Result<Provenance> {
+    let path = provenance_path(repo_path, package_id);
+    if !path.exists() {
+        bail!("No provenance recorded for package '{package_id}'.")
+    }
+
+    let serialized = fs::read_to_string(&path)?;
+    let signature = fs::read(path.with_extension("yml.sig"))
+        .with_context(|| "Provenance record has no signature")?;
+    let provenance = verify_provenance(&serialized, &signature)?;
+
+    if provenance.package_id != package_id {
+        bail!("Provenance record is for a different package.")
+    }
+
+    Ok(provenance)
+}
+
+/// Parses a provenance record, and verifies it against the builder key it contains.
+///
+/// # Errors
+///
+/// - Invalid record or signature
+pub fn verify_provenance(serialized: &str, signature: &[u8]) -> Result<Provenance> {
+    let provenance: Provenance = serde_yaml::from_str(serialized)?;
+
+    verify_signature(
+        serialized,
+        signature,
+        deserialize_verifying_key(&provenance.builder_key)?,
+    )
+    .with_context(|| "Provenance signature is invalid")?;
+
+    Ok(provenance)
+}
+
+/// Removes the provenance record of a package, if there is one.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+pub fn remove_provenance(repo_path: &Path, package_id: &str) -> Result<()> {
+    let path = provenance_path(repo_path, package_id);
+
+    if path.exists() {
+        fs::remove_file(&path)?;
+    }
+    if path.with_extension("yml.sig").exists() {
+        fs::remove_file(path.with_extension("yml.sig"))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use temp_dir::TempDir;
+
+    #[test]
+    fn test_provenance_round_trip() -> Result<()> {
+        let repo = TempDir::new()?;
+        let repo_path = repo.path();
+
+        let provenance = new_provenance("test", "hash", Vec::new(), now()?, Some(repo_path))?;
+        write_provenance(repo_path, &provenance, Some(repo_path))?;
+
+        assert_eq!(read_provenance(repo_path, "test")?, provenance);
+
+        // Tampering should be detected
+        let path = provenance_path(repo_path, "test");
+        let tampered = fs::read_to_string(&path)?.replace("hash", "evil");
+        fs::write(&path, tampered)?;
+        assert!(read_provenance(repo_path, "test").is_err());
+
+        remove_provenance(repo_path, "test")?;
+        assert!(read_provenance(repo_path, "test").is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,410 @@
+use anyhow::{Context, Result, bail};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    io::{Cursor, Read},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    chunks::{Chunk, store_chunk},
+    crypto::{
+        key::deserialize_verifying_key,
+        signing::{sign_detached, verify_signature},
+    },
+    repo::{
+        PackageManifest, insert_package, lock::lock_repo, manifest_io::atomic_replace,
+        read_manifest,
+    },
+};
+
+/// When each package of a Repository was last published, in milliseconds since the UNIX epoch.
+/// Never signed and never served, like the other `.local.yml` files.
+const PUBLISHED_FILE: &str = "published.local.yml";
+
+/// What a maintainer signs as `package.yml`.
+///
+/// `published_at` has to be later than that of the package's last publish,
+/// so an archive captured earlier can't be posted again to roll the package back.
+#[derive(serde::Deserialize, serde::Serialize)]
+struct PublishRequest {
+    /// Milliseconds since the UNIX epoch
+    published_at: u64,
+    package: PackageManifest,
+}
+
+/// Packs a package manifest, its signature and its chunks into a tar archive, ready to be
+/// published to a remote Repository.
+///
+/// # Errors
+///
+/// - A chunk is missing from the chunk store
+/// - Private key could not be read
+/// - System clock is before 1970
+pub fn create_publish_archive(
+    package: &PackageManifest,
+    chunk_store_path: &Path,
+    config_path: Option<&Path>,
+) -> Result<Vec<u8>> {
+    let published_at = u64::try_from(SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis())?;
+
+    publish_archive_at(package, published_at, chunk_store_path, config_path)
+}
+
+fn publish_archive_at(
+    package: &PackageManifest,
+    published_at: u64,
+    chunk_store_path: &Path,
+    config_path: Option<&Path>,
+) -> Result<Vec<u8>> {
+    let package_serialized = serde_yaml::to_string(&PublishRequest {
+        published_at,
+        package: package.clone(),
+    })?;
+    let signature = sign_detached(&package_serialized, config_path)?;
+
+    let mut tar = tar::Builder::new(Vec::new());
+
+    append_file(&mut tar, "package.yml", package_serialized.as_bytes())?;
+    append_file(&mut tar, "package.yml.sig", &signature.to_bytes())?;
+
+    for chunk in &package.chunks {
+        let chunk_name = chunk.filename();
+        let chunk_path = chunk_store_path.join(&chunk_name);
+
+        tar.append_path_with_name(&chunk_path, format!("chunks/{chunk_name}"))
+            .with_context(|| format!("Missing chunk {}", chunk.hash()))?;
+    }
+
+    tar.finish()?;
+    Ok(tar.into_inner()?)
+}
+
+fn append_file(tar: &mut tar::Builder<Vec<u8>>, name: &str, contents: &[u8]) -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(contents.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+
+    tar.append_data(&mut header, name, contents)?;
+
+    Ok(())
+}
+
+/// Largest `package.yml` or `package.yml.sig` accepted in a publish archive
+const MAX_PACKAGE_FILE_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Verifies a publish archive against a list of maintainer keys, stores its chunks and
+/// inserts the package into the Repository, signed with the local key.
+///
+/// Only `package.yml`, `package.yml.sig` and the package's chunks under `chunks/` may be in it,
+/// each no larger than the signed manifest says.
+///
+/// # Errors
+///
+/// - Archive is not signed by any of `maintainer_keys`
+/// - Archive is not newer than the last one accepted for the package
+/// - Archive contains any other file, or a file larger than expected
+/// - Chunks are missing or corrupt
+/// - Repo not signed with local signature
+pub fn accept_publish_archive(
+    archive: &[u8],
+    repo_path: &Path,
+    chunk_store_path: &Path,
+    maintainer_keys: &[String],
+    config_path: Option<&Path>,
+) -> Result<PackageManifest> {
+    let mut package_serialized = None;
+    let mut signature = None;
+
+    // Nothing but the signed files is read before the signature is checked
+    for entry in tar::Archive::new(Cursor::new(archive)).entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_string_lossy().to_string();
+
+        match path.as_str() {
+            "package.yml" => {
+                package_serialized = Some(read_entry(&mut entry, &path, MAX_PACKAGE_FILE_BYTES)?);
+            }
+            "package.yml.sig" => {
+                signature = Some(read_entry(&mut entry, &path, MAX_PACKAGE_FILE_BYTES)?);
+            }
+            _ if path.starts_with("chunks/") => {}
+            _ => bail!("Unexpected file {path} in publish archive."),
+        }
+    }
+
+    let package_serialized =
+        String::from_utf8(package_serialized.context("Archive has no package.yml")?)?;
+    let signature = signature.context("Archive has no package.yml.sig")?;
+
+    let trusted = maintainer_keys.iter().any(|key| {
+        deserialize_verifying_key(key)
+            .and_then(|key| verify_signature(&package_serialized, &signature, key))
+            .is_ok()
+    });
+    if !trusted {
+        bail!("Package is not signed by a trusted maintainer.")
+    }
+
+    let PublishRequest {
+        published_at,
+        package,
+    } = serde_yaml::from_str(&package_serialized)?;
+    // Until the package is inserted, a gc of the Repository would see its chunks as unused
+    let _lock = lock_repo(repo_path)?;
+    let repo_manifest = read_manifest(repo_path)?;
+
+    let mut published = get_published(repo_path)?;
+    if published
+        .get(&package.id)
+        .is_some_and(|last| published_at <= *last)
+    {
+        bail!(
+            "{} was already published from a newer archive, refusing to roll it back.",
+            package.id
+        )
+    }
+
+    fs::create_dir_all(chunk_store_path)?;
+
+    let chunks: HashMap<String, &Chunk> = package
+        .chunks
+        .iter()
+        .map(|chunk| (format!("chunks/{}", chunk.filename()), chunk))
+        .collect();
+    let mut stored = HashSet::new();
+
+    for entry in tar::Archive::new(Cursor::new(archive)).entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_string_lossy().to_string();
+        if !path.starts_with("chunks/") {
+            continue;
+        }
+
+        let Some(chunk) = chunks.get(&path) else {
+            bail!("Unexpected file {path} in publish archive.")
+        };
+        if !stored.insert(path.clone()) {
+            bail!("{path} is in the publish archive twice.")
+        }
+
+        let data = read_entry(&mut entry, &path, chunk.max_size())?;
+        store_chunk(chunk, &data, repo_manifest.hash_kind, chunk_store_path)?;
+    }
+
+    for chunk in &package.chunks {
+        if !chunk_store_path.join(chunk.filename()).exists() {
+            bail!("Missing chunk {}", chunk.hash())
+        }
+    }
+
+    insert_package(&package, repo_path, config_path)?;
+
+    published.insert(package.id.clone(), published_at);
+    atomic_replace(
+        repo_path,
+        PUBLISHED_FILE,
+        serde_yaml::to_string(&published)?.as_bytes(),
+    )?;
+
+    Ok(package)
+}
+
+fn get_published(repo_path: &Path) -> Result<BTreeMap<String, u64>> {
+    let path = repo_path.join(PUBLISHED_FILE);
+
+    if path.exists() {
+        Ok(serde_yaml::from_str(&fs::read_to_string(path)?)?)
+    } else {
+        Ok(BTreeMap::new())
+    }
+}
+
+/// Reads a file of a publish archive, refusing it if it is larger than `max_bytes`
+fn read_entry(entry: &mut impl Read, path: &str, max_bytes: u64) -> Result<Vec<u8>> {
+    let mut contents = Vec::new();
+    entry.take(max_bytes + 1).read_to_end(&mut contents)?;
+
+    if contents.len() as u64 > max_bytes {
+        bail!("{path} in publish archive is larger than expected.")
+    }
+
+    Ok(contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chunks::{HashKind, save_tree},
+        crypto::key::{get_private_key, serialize_verifying_key},
+        repo::{create_repo, get_package},
+    };
+    use temp_dir::TempDir;
+
+    #[test]
+    fn test_publish_round_trip() -> Result<()> {
+        let repo = TempDir::new()?;
+        let repo_path = repo.path();
+        let server_chunks = TempDir::new()?;
+        let maintainer = TempDir::new()?;
+        let maintainer_chunks = TempDir::new()?;
+        let tree = TempDir::new()?;
+        create_repo(repo_path, Some(repo_path))?;
+
+        fs::write(tree.path().join("file"), "content")?;
+        let chunks = save_tree(tree.path(), maintainer_chunks.path(), HashKind::Blake3)?;
+
+        let package = PackageManifest {
+            id: "published".into(),
+            chunks,
+            build_hash: "hash".into(),
+            ..Default::default()
+        };
+
+        let archive =
+            create_publish_archive(&package, maintainer_chunks.path(), Some(maintainer.path()))?;
+
+        // Unknown maintainer is rejected
+        let untrusted = serialize_verifying_key(get_private_key(Some(repo_path))?.verifying_key())?;
+        assert!(
+            accept_publish_archive(
+                &archive,
+                repo_path,
+                server_chunks.path(),
+                &[untrusted],
+                Some(repo_path)
+            )
+            .is_err()
+        );
+
+        let trusted =
+            serialize_verifying_key(get_private_key(Some(maintainer.path()))?.verifying_key())?;
+        accept_publish_archive(
+            &archive,
+            repo_path,
+            server_chunks.path(),
+            &[trusted],
+            Some(repo_path),
+        )?;
+
+        let published = get_package(&read_manifest(repo_path)?, "published")?;
+        assert_eq!(published, package);
+        for chunk in &published.chunks {
+            assert!(server_chunks.path().join(chunk.filename()).exists());
+        }
+
+        Ok(())
+    }
+    #[test]
+    fn test_publish_unexpected_files() -> Result<()> {
+        let repo = TempDir::new()?;
+        let repo_path = repo.path();
+        let server_chunks = TempDir::new()?;
+        let maintainer = TempDir::new()?;
+        let maintainer_chunks = TempDir::new()?;
+        let tree = TempDir::new()?;
+        create_repo(repo_path, Some(repo_path))?;
+
+        fs::write(tree.path().join("file"), "content")?;
+        let package = PackageManifest {
+            id: "published".into(),
+            chunks: save_tree(tree.path(), maintainer_chunks.path(), HashKind::Blake3)?,
+            ..Default::default()
+        };
+        let chunk_name = format!("chunks/{}", package.chunks[0].filename());
+        let archive =
+            create_publish_archive(&package, maintainer_chunks.path(), Some(maintainer.path()))?;
+        let trusted =
+            serialize_verifying_key(get_private_key(Some(maintainer.path()))?.verifying_key())?;
+
+        // The signed archive, with one more file
+        let with_extra = |name: &str, contents: &[u8]| -> Result<Vec<u8>> {
+            let mut tar = tar::Builder::new(Vec::new());
+            for entry in tar::Archive::new(Cursor::new(&archive)).entries()? {
+                let mut entry = entry?;
+                let path = entry.path()?.to_string_lossy().to_string();
+                let mut data = Vec::new();
+                entry.read_to_end(&mut data)?;
+                if path != name {
+                    append_file(&mut tar, &path, &data)?;
+                }
+            }
+            append_file(&mut tar, name, contents)?;
+            tar.finish()?;
+            Ok(tar.into_inner()?)
+        };
+
+        for tampered in [
+            with_extra("installed/evil", b"evil")?,
+            with_extra("chunks/unknown", b"evil")?,
+            // Larger than the manifest says the chunk is
+            with_extra(&chunk_name, &[0; 4096])?,
+        ] {
+            assert!(
+                accept_publish_archive(
+                    &tampered,
+                    repo_path,
+                    server_chunks.path(),
+                    std::slice::from_ref(&trusted),
+                    Some(repo_path)
+                )
+                .is_err()
+            );
+        }
+        assert!(get_package(&read_manifest(repo_path)?, "published").is_err());
+
+        Ok(())
+    }
+    #[test]
+    fn test_publish_replay() -> Result<()> {
+        let repo = TempDir::new()?;
+        let repo_path = repo.path();
+        let server_chunks = TempDir::new()?;
+        let maintainer = TempDir::new()?;
+        let maintainer_chunks = TempDir::new()?;
+        let tree = TempDir::new()?;
+        create_repo(repo_path, Some(repo_path))?;
+        let trusted =
+            serialize_verifying_key(get_private_key(Some(maintainer.path()))?.verifying_key())?;
+
+        let mut archives = Vec::new();
+        for (published_at, content) in [(1, "old"), (2, "new")] {
+            fs::write(tree.path().join("file"), content)?;
+            let package = PackageManifest {
+                id: "published".into(),
+                chunks: save_tree(tree.path(), maintainer_chunks.path(), HashKind::Blake3)?,
+                build_hash: content.into(),
+                ..Default::default()
+            };
+            archives.push(publish_archive_at(
+                &package,
+                published_at,
+                maintainer_chunks.path(),
+                Some(maintainer.path()),
+            )?);
+        }
+        let accept = |archive: &[u8]| {
+            accept_publish_archive(
+                archive,
+                repo_path,
+                server_chunks.path(),
+                std::slice::from_ref(&trusted),
+                Some(repo_path),
+            )
+        };
+
+        accept(&archives[1])?;
+        // Captured before, then posted again
+        assert!(accept(&archives[0]).is_err());
+        assert!(accept(&archives[1]).is_err());
+        assert_eq!(
+            get_package(&read_manifest(repo_path)?, "published")?.build_hash,
+            "new"
+        );
+
+        Ok(())
+    }
+}
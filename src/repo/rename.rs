@@ -0,0 +1,180 @@
+use anyhow::{Context, Result, bail};
+use std::{fs, path::Path};
+
+use crate::{
+    chunks::{installed_chunks, rewritten_chunks},
+    generations::rename_generations_repo,
+    repo::{
+        InstallMeta, image::find_image, installed::write_install_meta, read_manifest,
+        shebang::relocate_interpreters,
+    },
+    utils::{
+        platform::{read_dir_link, symlink_dir},
+        resolve_repo,
+    },
+};
+
+/// Renames a Repository without breaking what is installed from it.
+///
+/// `installed/` links into the old path are made relative, shebangs and ELF interpreters
+/// pointing into the old path are moved, and generations are updated to the new name.
+///
+/// # Errors
+///
+/// - Repository doesn't exist, or `new_name` is taken or not a plain name
+/// - patchelf is not installed, but a binary needs relocating
+/// - Filesystem errors (Permissions)
+///
+/// # Returns
+///
+/// Versions stored as images, which keep pointing at the old path until reinstalled
+pub fn rename_repo(repos_path: &Path, old_name: &str, new_name: &str) -> Result<Vec<String>> {
+    if new_name.is_empty() || new_name.contains('/') || new_name == "." || new_name == ".." {
+        bail!("Invalid Repository name {new_name}.")
+    }
+
+    let old_path = resolve_repo(repos_path, old_name)?;
+    let new_path = old_path.with_file_name(new_name);
+
+    if new_path.exists() || new_path.is_symlink() {
+        bail!("A Repository named {new_name} already exists.")
+    }
+
+    fs::rename(&old_path, &new_path)
+        .with_context(|| format!("Could not rename {old_name} to {new_name}"))?;
+
+    let old_installed = old_path.join("installed");
+    let new_installed = new_path.join("installed");
+
+    relink_installed(&new_installed, &old_path)?;
+
+    let mut images = Vec::new();
+    let versions_path = new_path.join("versions");
+    if versions_path.exists() {
+        for entry in fs::read_dir(&versions_path)? {
+            let entry = entry?;
+            let version_path = entry.path();
+
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+
+            let Ok(install_meta) = fs::read_to_string(version_path.join("install.meta")) else {
+                continue;
+            };
+            let mut install_meta: InstallMeta = serde_yaml::from_str(&install_meta)?;
+
+            // Only these were rewritten to point into the Repository
+            if install_meta.package.interpreters.is_empty() {
+                continue;
+            }
+
+            if find_image(&version_path).is_some() {
+                images.push(entry.file_name().to_string_lossy().to_string());
+                continue;
+            }
+
+            let relocated = relocate_interpreters(&version_path, &old_installed, &new_installed)?;
+            if relocated.is_empty() {
+                continue;
+            }
+
+            // What is on disk changed again, so does what checking the tree expects
+            let relocated = rewritten_chunks(
+                &version_path,
+                &install_meta.package.chunks,
+                &relocated,
+                read_manifest(&new_path)?.hash_kind,
+            )?;
+            install_meta.rewritten = installed_chunks(&install_meta.rewritten, &relocated);
+            let version = entry
+                .file_name()
+                .to_string_lossy()
+                .strip_prefix(&format!("{}-", install_meta.package.id))
+                .map(ToString::to_string);
+            write_install_meta(&new_path, &version_path, version.as_deref(), &install_meta)?;
+        }
+    }
+
+    rename_generations_repo(repos_path, old_name, new_name)?;
+
+    Ok(images)
+}
+
+/// Makes `installed/` links pointing into `old_repo_path` relative, so they survive the move.
+fn relink_installed(installed_path: &Path, old_repo_path: &Path) -> Result<()> {
+    if !installed_path.exists() {
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(installed_path)? {
+        let path = entry?.path();
+
+        let Some(target) = read_dir_link(&path) else {
+            continue;
+        };
+        let Ok(rest) = target.strip_prefix(old_repo_path) else {
+            continue;
+        };
+
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+
+        symlink_dir(&Path::new("..").join(rest), Path::new(&tmp_path))?;
+        fs::rename(&tmp_path, &path)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        generations::{list_generations, record_generation},
+        repo::{create_repo, versions::get_current_version},
+    };
+    use std::os::unix::fs::symlink;
+    use temp_dir::TempDir;
+
+    #[test]
+    fn test_rename_repo() -> Result<()> {
+        let root = TempDir::new()?;
+        let repos_path = &root.path().join("repos");
+        let repo_path = &repos_path.join("old");
+        create_repo(repo_path, Some(repo_path))?;
+        let repo_path = &repo_path.canonicalize()?;
+
+        fs::create_dir_all(repo_path.join("versions/hello-aaa"))?;
+        fs::create_dir_all(repo_path.join("versions/world-bbb"))?;
+        fs::create_dir(repo_path.join("installed"))?;
+        symlink("../versions/hello-aaa", repo_path.join("installed/hello"))?;
+        // Linked by hand, with an absolute path
+        symlink(
+            repo_path.join("versions/world-bbb"),
+            repo_path.join("installed/world"),
+        )?;
+        record_generation(repos_path)?;
+        create_repo(&repos_path.join("taken"), Some(&repos_path.join("taken")))?;
+
+        assert!(rename_repo(repos_path, "old", "taken").is_err());
+        assert!(rename_repo(repos_path, "old", "../escape").is_err());
+        assert!(rename_repo(repos_path, "missing", "new").is_err());
+
+        assert!(rename_repo(repos_path, "old", "new")?.is_empty());
+
+        let new_path = &repos_path.join("new");
+        assert!(!repo_path.exists());
+        assert_eq!(get_current_version(new_path, "hello")?, Some("aaa".into()));
+        assert_eq!(get_current_version(new_path, "world")?, Some("bbb".into()));
+        assert!(new_path.join("installed/world").exists());
+        assert!(
+            list_generations(repos_path)?[0]
+                .packages
+                .iter()
+                .all(|entry| entry.repo == "new")
+        );
+
+        Ok(())
+    }
+}
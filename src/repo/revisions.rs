@@ -0,0 +1,161 @@
+use anyhow::Result;
+use std::{fs, path::Path};
+
+use crate::repo::{PackageManifest, manifest_io::atomic_replace, read_manifest};
+
+/// Builds replaced by a newer build of the same package, oldest first.
+/// Their chunks are kept until pruned, so clients still fetching the previous manifest can finish.
+/// Never signed and never served, like the other `.local.yml` files.
+const REVISIONS_FILE: &str = "revisions.local.yml";
+
+/// Gets every superseded build kept in a Repository, oldest first.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+/// - Invalid revisions file
+pub fn get_revisions(repo_path: &Path) -> Result<Vec<PackageManifest>> {
+    let path = repo_path.join(REVISIONS_FILE);
+
+    if path.exists() {
+        Ok(serde_yaml::from_str(&fs::read_to_string(path)?)?)
+    } else {
+        Ok(Vec::new())
+    }
+}
+
+fn write_revisions(repo_path: &Path, revisions: &[PackageManifest]) -> Result<()> {
+    if revisions.is_empty() {
+        let path = repo_path.join(REVISIONS_FILE);
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        return Ok(());
+    }
+
+    atomic_replace(
+        repo_path,
+        REVISIONS_FILE,
+        serde_yaml::to_string(revisions)?.as_bytes(),
+    )
+}
+
+/// Keeps a build that was just replaced, so its chunks survive until it is pruned.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+/// - Invalid revisions file
+pub fn record_revision(repo_path: &Path, package: &PackageManifest) -> Result<()> {
+    let mut revisions = get_revisions(repo_path)?;
+    revisions.push(package.clone());
+
+    write_revisions(repo_path, &revisions)
+}
+
+/// Drops all but the newest `keep` superseded builds of every package.
+/// Builds of packages no longer in the Repository are always dropped.
+/// Their chunks are left for `clean_unused` to remove.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+/// - Invalid revisions file
+///
+/// # Returns
+///
+/// The number of builds dropped
+pub fn prune_revisions(repo_path: &Path, keep: usize) -> Result<usize> {
+    let manifest = read_manifest(repo_path)?;
+    let revisions = get_revisions(repo_path)?;
+    let total = revisions.len();
+
+    let mut kept: Vec<PackageManifest> = Vec::new();
+    // Newest first, so the first `keep` of each package are the ones to keep
+    for revision in revisions.into_iter().rev() {
+        let in_repo = manifest
+            .packages
+            .iter()
+            .any(|package| package.id == revision.id);
+        let newer = kept.iter().filter(|kept| kept.id == revision.id).count();
+
+        if in_repo && newer < keep {
+            kept.push(revision);
+        }
+    }
+    kept.reverse();
+
+    write_revisions(repo_path, &kept)?;
+
+    Ok(total - kept.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chunks::{save_tree, utils::clean_unused},
+        repo::{create_repo, insert_package, remove_package},
+    };
+    use temp_dir::TempDir;
+
+    fn build(
+        id: &str,
+        contents: &str,
+        repo_path: &Path,
+        chunk_store_path: &Path,
+    ) -> Result<PackageManifest> {
+        let tree = TempDir::new()?;
+        fs::write(tree.path().join(id), contents)?;
+
+        let package = PackageManifest {
+            id: id.into(),
+            chunks: save_tree(
+                tree.path(),
+                chunk_store_path,
+                read_manifest(repo_path)?.hash_kind,
+            )?,
+            build_hash: contents.into(),
+            ..Default::default()
+        };
+        insert_package(&package, repo_path, Some(repo_path))?;
+
+        Ok(package)
+    }
+
+    #[test]
+    fn test_prune_revisions() -> Result<()> {
+        let repos = TempDir::new()?;
+        let chunk_store = TempDir::new()?;
+        let repo_path = &repos.path().join("repo");
+        create_repo(repo_path, Some(repo_path))?;
+        let chunk_store_path = chunk_store.path();
+
+        let first = build("hello", "one", repo_path, chunk_store_path)?;
+        build("hello", "two", repo_path, chunk_store_path)?;
+        build("hello", "three", repo_path, chunk_store_path)?;
+        build("world", "four", repo_path, chunk_store_path)?;
+
+        // Replaced builds are kept, with their chunks
+        assert_eq!(get_revisions(repo_path)?.len(), 2);
+        clean_unused(repos.path(), chunk_store_path)?;
+        assert_eq!(fs::read_dir(chunk_store_path)?.count(), 4);
+
+        assert_eq!(prune_revisions(repo_path, 1)?, 1);
+        let revisions = get_revisions(repo_path)?;
+        assert_eq!(revisions.len(), 1);
+        assert_eq!(revisions[0].build_hash, "two");
+
+        clean_unused(repos.path(), chunk_store_path)?;
+        assert_eq!(fs::read_dir(chunk_store_path)?.count(), 3);
+        assert!(!chunk_store_path.join(first.chunks[0].filename()).exists());
+
+        // Removed packages don't keep their old builds around
+        remove_package("hello", repo_path, Some(repo_path))?;
+        assert_eq!(prune_revisions(repo_path, 1)?, 1);
+        assert!(get_revisions(repo_path)?.is_empty());
+        assert!(!repo_path.join(REVISIONS_FILE).exists());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,169 @@
+use anyhow::{Result, bail};
+use std::path::Path;
+
+use crate::{
+    crypto::key::{
+        get_previous_private_key, get_private_key, rotate_private_key, serialize_verifying_key,
+    },
+    repo::{read_manifest, serialize_manifest, signing_request::sign_manifest},
+};
+
+/// Starts rotating a Repository to a new signing key.
+///
+/// The manifest's `public_key` becomes the new key, and the old one is kept as `previous_public_key`.
+/// Until [`finish_key_rotation`], the manifest is signed with both keys, so clients that only trust
+/// the old key can still follow `manifest.yml.sig` and learn the new one.
+///
+/// If another Repository already rotated the local key, this Repository moves to that same new key.
+///
+/// # Errors
+///
+/// - The Repository is already rotating its key
+/// - The Repository is not signed with the local key, or the one it was rotated away from
+/// - Filesystem errors (Permissions)
+///
+/// # Returns
+///
+/// The new public key
+pub fn rotate_key(repo_path: &Path, config_path: Option<&Path>) -> Result<String> {
+    let mut manifest = read_manifest(repo_path)?;
+
+    if manifest.previous_public_key.is_some() {
+        bail!("This Repository is already rotating its key, finish that rotation first.")
+    }
+
+    let current_key = serialize_verifying_key(get_private_key(config_path)?.verifying_key())?;
+    let previous_key = get_previous_private_key(config_path)?
+        .map(|key| serialize_verifying_key(key.verifying_key()))
+        .transpose()?;
+
+    let new_key = if manifest.public_key == current_key {
+        serialize_verifying_key(rotate_private_key(config_path)?.verifying_key())?
+    } else if previous_key.as_ref() == Some(&manifest.public_key) {
+        current_key
+    } else {
+        bail!("This Repository is not signed with the local key.")
+    };
+
+    manifest.previous_public_key =
+        Some(std::mem::replace(&mut manifest.public_key, new_key.clone()));
+
+    let manifest_serialized = serialize_manifest(repo_path, &manifest)?;
+    sign_manifest(repo_path, &manifest_serialized, config_path)?;
+
+    Ok(new_key)
+}
+
+/// Finishes a key rotation, so the manifest is only signed with, and only trusts, the new key.
+/// Clients that have not updated since the rotation started will have to add the Repository again.
+///
+/// # Errors
+///
+/// - The Repository is not rotating its key
+/// - Filesystem errors (Permissions)
+pub fn finish_key_rotation(repo_path: &Path, config_path: Option<&Path>) -> Result<()> {
+    let mut manifest = read_manifest(repo_path)?;
+
+    if manifest.previous_public_key.take().is_none() {
+        bail!("This Repository is not rotating its key.")
+    }
+
+    let manifest_serialized = serialize_manifest(repo_path, &manifest)?;
+    sign_manifest(repo_path, &manifest_serialized, config_path)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        crypto::{key::deserialize_verifying_key, signing::verify_signature},
+        repo::{create_repo, update_manifest},
+    };
+    use std::fs;
+    use temp_dir::TempDir;
+
+    /// Updates `client` from `repo` the way a mirror would
+    fn follow(client: &Path, repo: &Path) -> Result<()> {
+        update_manifest(
+            client,
+            &fs::read_to_string(repo.join("manifest.yml"))?,
+            &fs::read(repo.join("manifest.yml.sig"))?,
+        )?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rotate_key() -> Result<()> {
+        let repo = TempDir::new()?;
+        let client = TempDir::new()?;
+        let config = TempDir::new()?;
+        let config_path = Some(config.path());
+
+        create_repo(repo.path(), config_path)?;
+        let old_key = read_manifest(repo.path())?.public_key;
+        fs::copy(
+            repo.path().join("manifest.yml"),
+            client.path().join("manifest.yml"),
+        )?;
+        fs::copy(
+            repo.path().join("manifest.yml.sig"),
+            client.path().join("manifest.yml.sig"),
+        )?;
+
+        assert!(finish_key_rotation(repo.path(), config_path).is_err());
+
+        let new_key = rotate_key(repo.path(), config_path)?;
+        assert_ne!(new_key, old_key);
+        assert!(rotate_key(repo.path(), config_path).is_err());
+
+        // Signed with both keys
+        let manifest = fs::read_to_string(repo.path().join("manifest.yml"))?;
+        verify_signature(
+            &manifest,
+            &fs::read(repo.path().join("manifest.yml.sig"))?,
+            deserialize_verifying_key(&old_key)?,
+        )?;
+        verify_signature(
+            &manifest,
+            &fs::read(repo.path().join("manifest.yml.sig.next"))?,
+            deserialize_verifying_key(&new_key)?,
+        )?;
+
+        // A client only trusting the old key learns the new one
+        follow(client.path(), repo.path())?;
+        assert_eq!(read_manifest(client.path())?.public_key, new_key);
+
+        finish_key_rotation(repo.path(), config_path)?;
+        assert!(!repo.path().join("manifest.yml.sig.next").exists());
+        assert_eq!(
+            read_manifest(repo.path())?.trusted_keys(),
+            vec![new_key.as_str()]
+        );
+
+        follow(client.path(), repo.path())?;
+        assert_eq!(read_manifest(client.path())?.previous_public_key, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rotate_shared_key() -> Result<()> {
+        let repos = TempDir::new()?;
+        let config = TempDir::new()?;
+        let config_path = Some(config.path());
+        let first = &repos.path().join("first");
+        let second = &repos.path().join("second");
+
+        create_repo(first, config_path)?;
+        create_repo(second, config_path)?;
+
+        // The second Repository moves to the key the first rotated to
+        let new_key = rotate_key(first, config_path)?;
+        assert_eq!(rotate_key(second, config_path)?, new_key);
+
+        Ok(())
+    }
+}
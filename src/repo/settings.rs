@@ -0,0 +1,182 @@
+use anyhow::{Context, Result, bail};
+use std::{collections::BTreeMap, fmt, fs, path::Path, str::FromStr};
+
+use crate::{repo::manifest_io::atomic_replace, run::requirements::compare_versions};
+
+/// Client-side settings of a single Repository. Never signed and never leaves this machine.
+const SETTINGS_FILE: &str = "settings.local.yml";
+
+/// How this machine treats a Repository, set by the user rather than the Repository.
+/// Every field is optional, missing fields use their defaults.
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct RepoSettings {
+    /// When several Repositories contain a package, the highest priority one is used. Defaults to 0
+    pub priority: i32,
+    /// Which updates `flint update` applies, by package id. Packages not listed take any update
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub update_policies: BTreeMap<String, UpdatePolicy>,
+    /// Added from `repos.yml`, and removed by `flint update` once no longer declared there
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub declared: bool,
+    /// Channel of the Repository to follow, only its packages are seen. Follows every package if unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>,
+    /// The Repository's key is kept on another machine, so changes are written as a signing request
+    /// to sign there with `flint sign`, instead of being signed here
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub external_signing: bool,
+}
+
+impl RepoSettings {
+    /// The update policy of a package, [`UpdatePolicy::Any`] unless one was set
+    #[must_use]
+    pub fn update_policy(&self, package_id: &str) -> UpdatePolicy {
+        self.update_policies
+            .get(package_id)
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+/// Which updates of a package `flint update` applies
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum UpdatePolicy {
+    /// Every update
+    #[default]
+    Any,
+    /// Only updates that keep the major version, eg: `1.2` to `1.9`, but not `2.0`
+    SameMajor,
+    /// Only updates that fix a security advisory
+    SecurityOnly,
+}
+
+impl UpdatePolicy {
+    /// Whether a package at `installed` may be updated to `available`, unless the update fixes
+    /// a security advisory, which is always allowed.
+    /// Packages without a version can't be compared, so `same-major` always updates them.
+    #[must_use]
+    pub fn allows(self, installed: Option<&str>, available: Option<&str>) -> bool {
+        match (self, installed, available) {
+            (Self::SameMajor, Some(installed), Some(available)) => {
+                compare_versions(major_version(installed), major_version(available)).is_eq()
+            }
+            (Self::SecurityOnly, ..) => false,
+            _ => true,
+        }
+    }
+}
+
+/// `2` of `v2.1.0`
+fn major_version(version: &str) -> &str {
+    let version = version.trim().trim_start_matches('v');
+
+    version.split('.').next().unwrap_or(version)
+}
+
+impl fmt::Display for UpdatePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::Any => write!(f, "any"),
+            Self::SameMajor => write!(f, "same-major"),
+            Self::SecurityOnly => write!(f, "security-only"),
+        }
+    }
+}
+
+impl FromStr for UpdatePolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(policy: &str) -> Result<Self> {
+        match policy {
+            "any" => Ok(Self::Any),
+            "same-major" => Ok(Self::SameMajor),
+            "security-only" => Ok(Self::SecurityOnly),
+            _ => {
+                bail!("Unknown update policy {policy}, expected any, same-major or security-only.")
+            }
+        }
+    }
+}
+
+/// Gets the settings of a Repository, the defaults if it has none.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+/// - Invalid settings file
+pub fn get_settings(repo_path: &Path) -> Result<RepoSettings> {
+    let path = repo_path.join(SETTINGS_FILE);
+
+    if !path.exists() {
+        return Ok(RepoSettings::default());
+    }
+
+    serde_yaml::from_str(&fs::read_to_string(&path)?)
+        .with_context(|| format!("Invalid settings at {}", path.display()))
+}
+
+/// Replaces the settings of a Repository.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+pub fn set_settings(repo_path: &Path, settings: &RepoSettings) -> Result<()> {
+    atomic_replace(
+        repo_path,
+        SETTINGS_FILE,
+        serde_yaml::to_string(settings)?.as_bytes(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use temp_dir::TempDir;
+
+    #[test]
+    fn test_settings() -> Result<()> {
+        let repo = TempDir::new()?;
+
+        assert_eq!(get_settings(repo.path())?, RepoSettings::default());
+
+        let settings = RepoSettings {
+            priority: 10,
+            update_policies: BTreeMap::from([("hello".into(), UpdatePolicy::SameMajor)]),
+            declared: false,
+            channel: Some("testing".into()),
+            external_signing: true,
+        };
+        set_settings(repo.path(), &settings)?;
+        assert_eq!(get_settings(repo.path())?, settings);
+        assert_eq!(settings.update_policy("hello"), UpdatePolicy::SameMajor);
+        assert_eq!(settings.update_policy("world"), UpdatePolicy::Any);
+
+        fs::write(repo.path().join(SETTINGS_FILE), "priority: high")?;
+        assert!(get_settings(repo.path()).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_policy() -> Result<()> {
+        let same_major: UpdatePolicy = "same-major".parse()?;
+
+        assert!(same_major.allows(Some("1.2"), Some("1.10.1")));
+        assert!(same_major.allows(Some("v1.2"), Some("1.3")));
+        assert!(!same_major.allows(Some("1.9"), Some("2.0")));
+        assert!(same_major.allows(None, Some("2.0")));
+        assert!(UpdatePolicy::Any.allows(Some("1.9"), Some("2.0")));
+
+        assert!(
+            !"security-only"
+                .parse::<UpdatePolicy>()?
+                .allows(Some("1.2"), Some("1.3"))
+        );
+        assert!("latest".parse::<UpdatePolicy>().is_err());
+        assert_eq!(same_major.to_string(), "same-major");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,319 @@
+use anyhow::Result;
+use std::{
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+};
+use walkdir::WalkDir;
+
+use crate::{
+    repo::Interpreter,
+    utils::elf::{patchelf, read_elf_info},
+};
+
+/// Points the shebangs of every script in `tree_path` at interpreters installed by dependencies.
+///
+/// Scripts are replaced rather than edited, as they may be hard links into the chunk store.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions, Out of space)
+///
+/// # Returns
+///
+/// Paths of the rewritten scripts, relative to `tree_path`
+pub fn rewrite_shebangs(
+    tree_path: &Path,
+    repo_path: &Path,
+    interpreters: &[Interpreter],
+) -> Result<Vec<PathBuf>> {
+    if interpreters.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let installed_path = repo_path.canonicalize()?.join("installed");
+    let mut rewritten = Vec::new();
+
+    for entry in WalkDir::new(tree_path) {
+        let entry = entry?;
+        let path = entry.path();
+
+        if !entry.file_type().is_file() || !is_script(path)? {
+            continue;
+        }
+
+        let contents = fs::read(path)?;
+        let line_end = contents
+            .iter()
+            .position(|&byte| byte == b'\n')
+            .unwrap_or(contents.len());
+
+        let Some(shebang) = str::from_utf8(&contents[2..line_end])
+            .ok()
+            .and_then(|line| rewrite_shebang(line, &installed_path, interpreters))
+        else {
+            continue;
+        };
+
+        replace_shebang(path, &shebang, &contents[line_end..])?;
+        rewritten.push(path.strip_prefix(tree_path)?.to_path_buf());
+    }
+
+    Ok(rewritten)
+}
+
+/// Points the dynamic linker of every ELF binary in `tree_path` at interpreters installed by dependencies.
+///
+/// Needs patchelf, but only if a binary actually uses one of `interpreters`.
+///
+/// # Errors
+///
+/// - patchelf is not installed, or failed
+/// - Filesystem errors (Permissions, Out of space)
+///
+/// # Returns
+///
+/// Paths of the patched binaries, relative to `tree_path`
+pub fn rewrite_elf_interpreters(
+    tree_path: &Path,
+    repo_path: &Path,
+    interpreters: &[Interpreter],
+) -> Result<Vec<PathBuf>> {
+    if interpreters.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let installed_path = repo_path.canonicalize()?.join("installed");
+    let mut rewritten = Vec::new();
+
+    for entry in WalkDir::new(tree_path) {
+        let entry = entry?;
+        let path = entry.path();
+
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let Some(mapping) = read_elf_info(path)?
+            .and_then(|elf_info| elf_info.interpreter)
+            .and_then(|interpreter| {
+                interpreters
+                    .iter()
+                    .find(|mapping| mapping.shebang == interpreter)
+            })
+        else {
+            continue;
+        };
+
+        let new_interpreter = installed_path.join(&mapping.package).join(&mapping.path);
+        patchelf(
+            path,
+            &["--set-interpreter".as_ref(), new_interpreter.as_os_str()],
+        )?;
+
+        rewritten.push(path.strip_prefix(tree_path)?.to_path_buf());
+    }
+
+    Ok(rewritten)
+}
+
+/// Moves rewritten shebangs and ELF interpreters from `old_installed` to `new_installed`,
+/// after the Repository they point into was moved.
+///
+/// # Errors
+///
+/// - patchelf is not installed, or failed
+/// - Filesystem errors (Permissions, Out of space)
+///
+/// # Returns
+///
+/// Paths of the relocated files, relative to `tree_path`
+pub fn relocate_interpreters(
+    tree_path: &Path,
+    old_installed: &Path,
+    new_installed: &Path,
+) -> Result<Vec<PathBuf>> {
+    let mut relocated = Vec::new();
+
+    for entry in WalkDir::new(tree_path) {
+        let entry = entry?;
+        let path = entry.path();
+
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        if is_script(path)? {
+            let contents = fs::read(path)?;
+            let line_end = contents
+                .iter()
+                .position(|&byte| byte == b'\n')
+                .unwrap_or(contents.len());
+
+            let Some((interpreter, args)) = str::from_utf8(&contents[2..line_end])
+                .ok()
+                .map(|line| split_first_word(line.trim()))
+            else {
+                continue;
+            };
+            let Ok(rest) = Path::new(interpreter).strip_prefix(old_installed) else {
+                continue;
+            };
+
+            let new_interpreter = new_installed.join(rest);
+            let shebang = if args.is_empty() {
+                new_interpreter.display().to_string()
+            } else {
+                format!("{} {args}", new_interpreter.display())
+            };
+            replace_shebang(path, &shebang, &contents[line_end..])?;
+            relocated.push(path.strip_prefix(tree_path)?.to_path_buf());
+        } else if let Some(interpreter) =
+            read_elf_info(path)?.and_then(|elf_info| elf_info.interpreter)
+        {
+            let Ok(rest) = Path::new(&interpreter).strip_prefix(old_installed) else {
+                continue;
+            };
+
+            let new_interpreter = new_installed.join(rest);
+            patchelf(
+                path,
+                &["--set-interpreter".as_ref(), new_interpreter.as_os_str()],
+            )?;
+            relocated.push(path.strip_prefix(tree_path)?.to_path_buf());
+        }
+    }
+
+    Ok(relocated)
+}
+
+/// Replaces a script with one using `shebang` (without the `#!`), keeping its permissions.
+fn replace_shebang(path: &Path, shebang: &str, rest: &[u8]) -> Result<()> {
+    let mut new_contents = format!("#!{shebang}").into_bytes();
+    new_contents.extend_from_slice(rest);
+
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".shebang");
+
+    fs::write(&tmp_path, new_contents)?;
+    fs::set_permissions(&tmp_path, fs::metadata(path)?.permissions())?;
+    fs::rename(&tmp_path, path)?;
+
+    Ok(())
+}
+
+fn is_script(path: &Path) -> Result<bool> {
+    let mut magic = [0; 2];
+
+    Ok(File::open(path)?.read_exact(&mut magic).is_ok() && &magic == b"#!")
+}
+
+/// Rewrites a shebang line (without the `#!`), if one of `interpreters` matches it.
+fn rewrite_shebang(
+    line: &str,
+    installed_path: &Path,
+    interpreters: &[Interpreter],
+) -> Option<String> {
+    let (interpreter, args) = split_first_word(line.trim());
+
+    // `#!/usr/bin/env python3` looks the command up instead
+    let (command, args) = if interpreter.ends_with("/env") {
+        split_first_word(args)
+    } else {
+        (interpreter, args)
+    };
+
+    let mapping = interpreters
+        .iter()
+        .find(|mapping| !command.is_empty() && mapping.shebang == command)?;
+    let new_interpreter = installed_path.join(&mapping.package).join(&mapping.path);
+
+    Some(if args.is_empty() {
+        new_interpreter.display().to_string()
+    } else {
+        format!("{} {args}", new_interpreter.display())
+    })
+}
+
+fn split_first_word(line: &str) -> (&str, &str) {
+    line.split_once(char::is_whitespace)
+        .map_or((line, ""), |(first, rest)| (first, rest.trim_start()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use temp_dir::TempDir;
+
+    #[test]
+    fn test_rewrite_shebangs() -> Result<()> {
+        let repo = TempDir::new()?;
+        let tree = TempDir::new()?;
+        let installed_path = repo.path().canonicalize()?.join("installed");
+
+        let interpreters = vec![Interpreter {
+            shebang: "/usr/bin/python3".into(),
+            package: "python".into(),
+            path: PathBuf::from("bin/python3"),
+        }];
+        let python = installed_path.join("python/bin/python3");
+
+        fs::create_dir(tree.path().join("bin"))?;
+        fs::write(
+            tree.path().join("bin/tool"),
+            "#!/usr/bin/python3 -u\nprint()\n",
+        )?;
+        fs::set_permissions(
+            tree.path().join("bin/tool"),
+            fs::Permissions::from_mode(0o755),
+        )?;
+        fs::write(tree.path().join("bin/shell"), "#!/bin/sh\necho\n")?;
+        fs::write(tree.path().join("data"), "not a script")?;
+
+        assert_eq!(
+            rewrite_shebangs(tree.path(), repo.path(), &interpreters)?,
+            vec![PathBuf::from("bin/tool")]
+        );
+        assert_eq!(
+            fs::read_to_string(tree.path().join("bin/tool"))?,
+            format!("#!{} -u\nprint()\n", python.display())
+        );
+        assert_eq!(
+            fs::metadata(tree.path().join("bin/tool"))?
+                .permissions()
+                .mode()
+                & 0o777,
+            0o755
+        );
+        assert_eq!(
+            fs::read_to_string(tree.path().join("bin/shell"))?,
+            "#!/bin/sh\necho\n"
+        );
+
+        let env_interpreters = vec![Interpreter {
+            shebang: "python3".into(),
+            ..interpreters[0].clone()
+        }];
+        assert_eq!(
+            rewrite_shebang("/usr/bin/env python3", &installed_path, &env_interpreters),
+            Some(python.display().to_string())
+        );
+        assert_eq!(
+            rewrite_shebang("/usr/bin/env", &installed_path, &env_interpreters),
+            None
+        );
+
+        let moved_path = PathBuf::from("/moved/installed");
+        assert_eq!(
+            relocate_interpreters(tree.path(), &installed_path, &moved_path)?,
+            vec![PathBuf::from("bin/tool")]
+        );
+        assert_eq!(
+            fs::read_to_string(tree.path().join("bin/tool"))?,
+            "#!/moved/installed/python/bin/python3 -u\nprint()\n"
+        );
+
+        Ok(())
+    }
+}
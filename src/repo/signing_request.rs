@@ -0,0 +1,267 @@
+use anyhow::{Context, Result, bail};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    chunks::hash::to_hex,
+    crypto::signing::{ManifestSignatures, sign, verify_signature_any},
+    repo::{
+        RepoManifest,
+        manifest_io::{ManifestFormat, atomic_replace, parse_manifest_as, update_manifest_signed},
+        settings::get_settings,
+    },
+};
+
+/// Where a Repository with external signing keeps the manifest waiting for its signatures
+pub const SIGNING_REQUEST_FILE: &str = "signing_request.yml";
+
+/// A manifest to be signed on another machine, eg: an air-gapped one holding the Repository's key.
+///
+/// Written unsigned by the machine changing the Repository, and carried back once `signatures` are filled in.
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct SigningRequest {
+    /// The serialized manifest, signed exactly as is
+    pub manifest: String,
+    /// Hex signatures, by the file they are written to, eg: `manifest.yml.sig`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub signatures: BTreeMap<String, String>,
+}
+
+/// Signs a changed manifest with the local key and replaces the Repository's manifest with it.
+///
+/// For a Repository with external signing, the manifest is written to [`SIGNING_REQUEST_FILE`] instead,
+/// and only replaces the Repository's once signed, see [`apply_signing_request`].
+///
+/// # Errors
+///
+/// - Another signing request is still waiting for its signatures
+/// - Repo not signed with local signature
+/// - Filesystem errors (Permissions)
+///
+/// # Returns
+///
+/// The path of the signing request, if one was written
+pub fn sign_manifest(
+    repo_path: &Path,
+    manifest_serialized: &str,
+    config_path: Option<&Path>,
+) -> Result<Option<PathBuf>> {
+    if !get_settings(repo_path)?.external_signing {
+        let signatures = sign(manifest_serialized, config_path)?;
+        update_manifest_signed(repo_path, manifest_serialized, &signatures)?;
+
+        return Ok(None);
+    }
+
+    let request_path = repo_path.join(SIGNING_REQUEST_FILE);
+    if request_path.exists() {
+        bail!(
+            "A signing request is still waiting for its signatures at {}. Apply it with `flint repo apply-signature`, or delete it.",
+            request_path.display()
+        )
+    }
+
+    let request = SigningRequest {
+        manifest: manifest_serialized.to_string(),
+        signatures: BTreeMap::new(),
+    };
+    atomic_replace(
+        repo_path,
+        SIGNING_REQUEST_FILE,
+        serde_yaml::to_string(&request)?.as_bytes(),
+    )?;
+
+    Ok(Some(request_path))
+}
+
+/// Fills in the signatures of a signing request with the local key, on the machine holding it.
+///
+/// # Errors
+///
+/// - Invalid signing request, or manifest in it
+/// - The local key is not trusted by the manifest
+/// - Filesystem errors (Permissions)
+///
+/// # Returns
+///
+/// The manifest that was signed
+pub fn sign_request(request_path: &Path, config_path: Option<&Path>) -> Result<RepoManifest> {
+    let mut request = read_request(request_path)?;
+    let manifest = parse_manifest_as(request.manifest.as_bytes(), ManifestFormat::Yaml)?;
+
+    // Signed the same way as a local Repository
+    let signatures = sign(&request.manifest, config_path)?;
+
+    let yaml_signature = signatures
+        .get(ManifestFormat::Yaml.signature_filename())
+        .context("Missing signature")?;
+    verify_signature_any(&request.manifest, yaml_signature, &manifest.trusted_keys())
+        .context("Your key is not one of the Repository's keys")?;
+
+    request.signatures = signatures
+        .iter()
+        .map(|(filename, signature)| (filename.clone(), to_hex(signature)))
+        .collect();
+
+    atomic_replace(
+        request_path.parent().unwrap_or_else(|| Path::new(".")),
+        &request_path
+            .file_name()
+            .context("Invalid signing request path")?
+            .to_string_lossy(),
+        serde_yaml::to_string(&request)?.as_bytes(),
+    )?;
+
+    Ok(manifest)
+}
+
+/// Replaces a Repository's manifest with a signed request, see [`sign_request`].
+///
+/// The pending [`SIGNING_REQUEST_FILE`] is removed once its manifest is applied.
+///
+/// # Errors
+///
+/// - Unsigned or invalid signing request
+/// - Invalid Signature, or a manifest older than the current one
+/// - Filesystem errors (Permissions)
+pub fn apply_signing_request(repo_path: &Path, request_path: &Path) -> Result<RepoManifest> {
+    let request = read_request(request_path)?;
+
+    if !request
+        .signatures
+        .contains_key(ManifestFormat::Yaml.signature_filename())
+    {
+        bail!("Signing request has not been signed yet. Sign it with `flint sign` first.")
+    }
+
+    let mut signatures = ManifestSignatures::new();
+    for (filename, signature) in &request.signatures {
+        signatures.insert(filename.clone(), from_hex(signature)?);
+    }
+
+    // Checks every signature before writing anything, so a bad one leaves the Repository as it was
+    let manifest = update_manifest_signed(repo_path, &request.manifest, &signatures)?;
+
+    let pending_path = repo_path.join(SIGNING_REQUEST_FILE);
+    if read_request(&pending_path).is_ok_and(|pending| pending.manifest == request.manifest) {
+        fs::remove_file(pending_path)?;
+    }
+
+    Ok(manifest)
+}
+
+/// The manifest waiting for its signatures in a Repository with external signing, if there is one.
+/// It is not signed yet, so only use it for what the Repository will contain, eg: chunks to keep.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+/// - Invalid signing request, or manifest in it
+pub fn pending_manifest(repo_path: &Path) -> Result<Option<RepoManifest>> {
+    let request_path = repo_path.join(SIGNING_REQUEST_FILE);
+
+    if !request_path.exists() {
+        return Ok(None);
+    }
+
+    let request = read_request(&request_path)?;
+
+    Ok(Some(parse_manifest_as(
+        request.manifest.as_bytes(),
+        ManifestFormat::Yaml,
+    )?))
+}
+
+fn read_request(request_path: &Path) -> Result<SigningRequest> {
+    serde_yaml::from_str(
+        &fs::read_to_string(request_path)
+            .with_context(|| format!("Could not read {}", request_path.display()))?,
+    )
+    .with_context(|| format!("{} is not a signing request", request_path.display()))
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        bail!("Invalid signature in signing request.")
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| {
+            u8::from_str_radix(&hex[index..index + 2], 16)
+                .context("Invalid signature in signing request.")
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::{
+        create_repo, read_manifest, remove_package,
+        settings::{RepoSettings, set_settings},
+    };
+
+    #[test]
+    fn test_signing_request() -> Result<()> {
+        let root = temp_dir::TempDir::new()?;
+        let repo_path = &root.path().join("repo");
+        let key_holder = &root.path().join("key");
+        let online = &root.path().join("online");
+        create_repo(repo_path, Some(key_holder))?;
+        set_settings(
+            repo_path,
+            &RepoSettings {
+                external_signing: true,
+                ..RepoSettings::default()
+            },
+        )?;
+        let serial = read_manifest(repo_path)?.serial;
+
+        // Nothing changes until it is signed, and nothing else can change meanwhile
+        remove_package("missing", repo_path, Some(online))?;
+        let request_path = &repo_path.join(SIGNING_REQUEST_FILE);
+        assert!(request_path.exists());
+        assert_eq!(read_manifest(repo_path)?.serial, serial);
+        assert!(remove_package("missing", repo_path, Some(online)).is_err());
+        assert!(apply_signing_request(repo_path, request_path).is_err());
+
+        // Signed with a key the Repository doesn't trust
+        let carried = &root.path().join("carried.yml");
+        fs::copy(request_path, carried)?;
+        assert!(sign_request(carried, Some(online)).is_err());
+
+        sign_request(carried, Some(key_holder))?;
+        assert_eq!(
+            apply_signing_request(repo_path, carried)?.serial,
+            serial + 1
+        );
+        assert_eq!(read_manifest(repo_path)?.serial, serial + 1);
+        assert!(!request_path.exists());
+
+        // Tampered with after signing
+        remove_package("missing", repo_path, Some(online))?;
+        sign_request(request_path, Some(key_holder))?;
+        let mut request = read_request(request_path)?;
+        request.manifest.push_str("# changed\n");
+        fs::write(carried, serde_yaml::to_string(&request)?)?;
+        assert!(apply_signing_request(repo_path, carried).is_err());
+        assert_eq!(read_manifest(repo_path)?.serial, serial + 1);
+
+        // Every signature is checked, not just the YAML one
+        let mut request = read_request(request_path)?;
+        let yaml_signature = request.signatures["manifest.yml.sig"].clone();
+        request
+            .signatures
+            .insert("manifest.cbor.sig".into(), yaml_signature);
+        fs::write(carried, serde_yaml::to_string(&request)?)?;
+        assert!(apply_signing_request(repo_path, carried).is_err());
+        assert!(!repo_path.join("manifest.cbor.sig").exists());
+        assert_eq!(read_manifest(repo_path)?.serial, serial + 1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,216 @@
+use anyhow::{Result, bail};
+use std::{fs, path::Path};
+
+use crate::repo::{
+    RepoManifest,
+    manifest_io::{atomic_replace, read_manifest},
+    settings::get_settings,
+};
+
+/// Client-side package filter. Like mirror overrides, this is never signed and never leaves this machine.
+const SUBSCRIPTION_FILE: &str = "subscription.local.yml";
+
+/// Gets the package patterns a Repository is subscribed to, if it only follows a subset.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+/// - Invalid subscription file
+pub fn get_subscription(repo_path: &Path) -> Result<Option<Vec<String>>> {
+    let path = repo_path.join(SUBSCRIPTION_FILE);
+
+    if path.exists() {
+        Ok(Some(serde_yaml::from_str(&fs::read_to_string(path)?)?))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Subscribes a Repository to only the packages matching `patterns`, eg: `hello` or `lib*`.
+///
+/// # Errors
+///
+/// - No patterns
+/// - Filesystem errors (Permissions)
+pub fn set_subscription(repo_path: &Path, patterns: &[String]) -> Result<()> {
+    if patterns.is_empty() {
+        bail!("A subscription needs at least one package pattern.")
+    }
+
+    atomic_replace(
+        repo_path,
+        SUBSCRIPTION_FILE,
+        serde_yaml::to_string(patterns)?.as_bytes(),
+    )
+}
+
+/// Parses a comma separated list of package patterns
+#[must_use]
+pub fn parse_patterns(patterns: &str) -> Vec<String> {
+    patterns
+        .split(',')
+        .map(str::trim)
+        .filter(|pattern| !pattern.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Whether a package id matches a pattern, where `*` matches any run of characters.
+#[must_use]
+pub fn matches_pattern(pattern: &str, package_id: &str) -> bool {
+    let mut parts = pattern.split('*');
+    // `split` always yields at least one part
+    let first = parts.next().unwrap_or_default();
+
+    let Some(mut rest) = package_id.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts: Vec<&str> = parts.collect();
+    let Some(last) = parts.pop() else {
+        // No `*` at all
+        return rest.is_empty();
+    };
+
+    for part in parts {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+
+    rest.ends_with(last)
+}
+
+/// Reads a Repository's manifest with only the packages it is subscribed to, see [`apply_subscription`].
+///
+/// For listing, installing and updating packages. Anything that signs or stores the manifest needs all of it,
+/// from [`read_manifest`].
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions or doesn't exist)
+/// - Invalid signature
+/// - Invalid subscription or settings file
+/// - The selected channel is not in the manifest
+pub fn read_subscribed_manifest(repo_path: &Path) -> Result<RepoManifest> {
+    apply_subscription(repo_path, read_manifest(repo_path)?)
+}
+
+/// Drops every package the Repository is not subscribed to from `manifest`.
+///
+/// Packages outside its selected channel are dropped too.
+/// Dependencies of subscribed packages are always kept, so they can still be installed.
+///
+/// # Errors
+///
+/// - Invalid subscription or settings file
+/// - The selected channel is not in the manifest
+pub fn apply_subscription(repo_path: &Path, mut manifest: RepoManifest) -> Result<RepoManifest> {
+    if let Some(channel) = get_settings(repo_path)?.channel {
+        let Some(package_ids) = manifest.channels.get(&channel).cloned() else {
+            bail!(
+                "Repository has no channel {channel} anymore, select another with `flint repo channel select`."
+            )
+        };
+        retain_with_dependencies(&mut manifest, |package_id| {
+            package_ids.iter().any(|id| id == package_id)
+        });
+    }
+
+    if let Some(patterns) = get_subscription(repo_path)? {
+        retain_with_dependencies(&mut manifest, |package_id| {
+            patterns
+                .iter()
+                .any(|pattern| matches_pattern(pattern, package_id))
+        });
+    }
+
+    Ok(manifest)
+}
+
+/// Keeps only the packages `wanted` returns true for, and everything they depend on
+fn retain_with_dependencies(manifest: &mut RepoManifest, wanted: impl Fn(&str) -> bool) {
+    let mut wanted: Vec<String> = manifest
+        .packages
+        .iter()
+        .filter(|package| wanted(&package.id))
+        .map(|package| package.id.clone())
+        .collect();
+
+    let mut index = 0;
+    while let Some(package_id) = wanted.get(index).cloned() {
+        if let Some(package) = manifest.packages.iter().find(|p| p.id == package_id) {
+            for dependency in &package.dependencies {
+                if !wanted.contains(dependency) {
+                    wanted.push(dependency.clone());
+                }
+            }
+        }
+
+        index += 1;
+    }
+
+    manifest
+        .packages
+        .retain(|package| wanted.contains(&package.id));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::{PackageManifest, create_repo, insert_package, remove_package};
+    use temp_dir::TempDir;
+
+    #[test]
+    fn test_matches_pattern() {
+        assert!(matches_pattern("hello", "hello"));
+        assert!(!matches_pattern("hello", "hello-world"));
+        assert!(matches_pattern("hello*", "hello-world"));
+        assert!(matches_pattern("*world", "hello-world"));
+        assert!(matches_pattern("h*o*d", "hello-world"));
+        assert!(!matches_pattern("h*x*d", "hello-world"));
+        assert!(matches_pattern("*", "anything"));
+
+        assert_eq!(parse_patterns("pkgA, pkgB*,"), vec!["pkgA", "pkgB*"]);
+    }
+
+    #[test]
+    fn test_subscription_filters_manifest() -> Result<()> {
+        let repo = TempDir::new()?;
+        create_repo(repo.path(), Some(repo.path()))?;
+
+        for (id, dependencies) in [
+            ("toolkit", Vec::new()),
+            ("editor", vec!["toolkit".to_string()]),
+            ("game", Vec::new()),
+        ] {
+            let package = PackageManifest {
+                id: id.into(),
+                dependencies,
+                ..Default::default()
+            };
+            insert_package(&package, repo.path(), Some(repo.path()))?;
+        }
+
+        assert_eq!(get_subscription(repo.path())?, None);
+        assert_eq!(read_subscribed_manifest(repo.path())?.packages.len(), 3);
+
+        set_subscription(repo.path(), &parse_patterns("edit*"))?;
+        let mut ids: Vec<String> = read_subscribed_manifest(repo.path())?
+            .packages
+            .into_iter()
+            .map(|package| package.id)
+            .collect();
+        ids.sort();
+        assert_eq!(ids, vec!["editor", "toolkit"]);
+
+        assert!(set_subscription(repo.path(), &[]).is_err());
+
+        // Only ever filtered on the way out, a subscribed maintainer still signs every package
+        assert_eq!(read_manifest(repo.path())?.packages.len(), 3);
+        remove_package("game", repo.path(), Some(repo.path()))?;
+        assert_eq!(read_manifest(repo.path())?.packages.len(), 2);
+
+        Ok(())
+    }
+}
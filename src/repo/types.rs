@@ -1,32 +1,478 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    path::PathBuf,
+};
 
-use crate::chunks::{Chunk, HashKind};
+use crate::chunks::{Chunk, HashKind, installed_chunks};
 
 #[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq, Eq)]
 pub struct RepoManifest {
     pub metadata: Metadata,
     pub packages: Vec<PackageManifest>,
     pub public_key: String,
-    pub mirrors: Vec<String>,
+    pub mirrors: Vec<Mirror>,
     pub edition: String,
     pub hash_kind: HashKind,
+    /// Increased every time the manifest is signed. Clients refuse manifests with a lower serial than
+    /// the one they have, so a mirror can't roll them back to an older, validly signed manifest.
+    #[serde(default)]
+    pub serial: u64,
+    /// Oldest client edition that can correctly use this Repository
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_client_edition: Option<String>,
+    /// Where packages built from this Repository's build manifests can be fetched instead of compiled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub binary_cache: Option<BinaryCache>,
+    /// Other Repositories whose packages clients see alongside this one's
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub includes: Vec<IncludedFeed>,
+    /// Key this Repository is rotating away from. Until the rotation is finished,
+    /// `manifest.yml.sig` is still signed with it, and clients trust both keys.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_public_key: Option<String>,
+    /// Keys of other maintainers, who may sign this manifest from their own machines
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub signing_keys: Vec<String>,
+    /// Known vulnerabilities of this Repository's packages, see `flint audit`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub advisories: Vec<Advisory>,
+    /// Where clients fetch manifest updates from, instead of the first mirror.
+    /// Changing it moves clients to the new URL on their next update.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updates_url: Option<String>,
+    /// Named sets of package ids, eg: `stable` and `testing`.
+    /// Clients that selected a channel only see its packages (and their dependencies).
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub channels: BTreeMap<String, Vec<String>>,
 }
 
+impl RepoManifest {
+    /// Where this Repository wants updates fetched from: `updates_url`, then its first mirror
+    #[must_use]
+    pub fn updates_source(&self) -> Option<&str> {
+        self.updates_url
+            .as_deref()
+            .or_else(|| self.mirrors.first().map(|mirror| mirror.url.as_str()))
+    }
+
+    /// Every key a signature of this Repository may come from
+    #[must_use]
+    pub fn trusted_keys(&self) -> Vec<&str> {
+        let mut keys = vec![self.public_key.as_str()];
+        keys.extend(self.previous_public_key.as_deref());
+        keys.extend(self.signing_keys.iter().map(String::as_str));
+
+        keys
+    }
+
+    /// The Repository's own keys, the only ones that may change which keys are trusted.
+    /// Other maintainers' `signing_keys` only sign changes to everything else.
+    #[must_use]
+    pub fn owner_keys(&self) -> Vec<&str> {
+        let mut keys = vec![self.public_key.as_str()];
+        keys.extend(self.previous_public_key.as_deref());
+
+        keys
+    }
+
+    /// Whether `other` trusts exactly the same keys, in the same roles
+    #[must_use]
+    pub fn same_trust(&self, other: &Self) -> bool {
+        self.public_key == other.public_key
+            && self.previous_public_key == other.previous_public_key
+            && self.signing_keys == other.signing_keys
+    }
+}
+
+/// Another Repository included in this one, added next to it on clients.
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct IncludedFeed {
+    /// Added on clients as `<repository>.<name>`
+    pub name: String,
+    pub url: String,
+    /// The included Repository must be signed with this key
+    pub public_key: String,
+}
+
+/// A place the Repository and its chunks can be downloaded from.
+///
+/// Written as a plain URL when only the URL is set, which is also how older manifests list them.
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(from = "MirrorEntry", into = "MirrorEntry")]
+pub struct Mirror {
+    pub url: String,
+    /// Mirrors with a lower priority are tried first
+    pub priority: u32,
+    /// Relative share of downloads among mirrors of the same priority. 0 is only used as a last resort
+    pub weight: u32,
+    /// Clients in this region try this mirror before others of the same priority
+    pub region: Option<String>,
+}
+
+impl Mirror {
+    /// A mirror with the default priority and weight
+    #[must_use]
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            priority: 0,
+            weight: 1,
+            region: None,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(untagged)]
+enum MirrorEntry {
+    Url(String),
+    Full {
+        url: String,
+        #[serde(default)]
+        priority: u32,
+        #[serde(default = "default_mirror_weight")]
+        weight: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        region: Option<String>,
+    },
+}
+
+const fn default_mirror_weight() -> u32 {
+    1
+}
+
+impl From<MirrorEntry> for Mirror {
+    fn from(entry: MirrorEntry) -> Self {
+        match entry {
+            MirrorEntry::Url(url) => Self::new(url),
+            MirrorEntry::Full {
+                url,
+                priority,
+                weight,
+                region,
+            } => Self {
+                url,
+                priority,
+                weight,
+                region,
+            },
+        }
+    }
+}
+
+impl From<Mirror> for MirrorEntry {
+    fn from(mirror: Mirror) -> Self {
+        if mirror == Mirror::new(mirror.url.clone()) {
+            return Self::Url(mirror.url);
+        }
+
+        Self::Full {
+            url: mirror.url,
+            priority: mirror.priority,
+            weight: mirror.weight,
+            region: mirror.region,
+        }
+    }
+}
+
+/// A Repository of pre-built packages, matched against local builds by `build_hash`.
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct BinaryCache {
+    /// Base URL of the cache, served like any other Repository
+    pub url: String,
+    /// Key the cache's manifest is signed with, if not this Repository's own
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<String>,
+}
+
+/// A known vulnerability of a package.
+/// Installs match when their version or build hash is listed, or, if neither are, when they are older than `fixed_in`.
 #[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Advisory {
+    /// eg: `CVE-2026-1234`
+    pub id: String,
+    pub package: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub versions: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub build_hashes: Vec<String>,
+    pub severity: Severity,
+    /// First version without the vulnerability
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fixed_in: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+}
+
+#[derive(
+    serde::Deserialize, serde::Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::Low => write!(f, "low"),
+            Self::Medium => write!(f, "medium"),
+            Self::High => write!(f, "high"),
+            Self::Critical => write!(f, "critical"),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Default, PartialEq, Eq)]
 pub struct PackageManifest {
     pub metadata: Metadata,
     pub id: String,
     pub aliases: Vec<String>,
     pub chunks: Vec<Chunk>,
-    pub commands: Vec<PathBuf>,
+    pub commands: Vec<PackageCommand>,
     /// Runtime environment variables
     pub env: Option<HashMap<String, String>>,
     #[serde(default = "build_hash_default")]
     pub build_hash: String,
+    /// Outcome of the `test_script` at build time, if the package has one
+    #[serde(default)]
+    pub tests: Option<TestStatus>,
+    /// IDs of packages in the same Repository needed at runtime
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dependencies: Vec<String>,
+    /// Interpreters provided by dependencies, scripts and binaries are pointed at them on install
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub interpreters: Vec<Interpreter>,
+    /// What the host needs to run the package at all
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requirements: Option<Requirements>,
+    /// Shell snippets for `flint env`, `flint shell` and the profile hook, relative to the package.
+    /// For environments that need more than plain variables, eg: `etc/profile.d/hello.sh`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub env_scripts: Vec<PathBuf>,
+    /// Virtual package names this package satisfies, eg: `python3`. Packages in different
+    /// Repositories can provide the same name, and any of them is accepted where it is asked for
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub provides: Vec<String>,
 }
 
-/// All of these are user visible, and should carry no actual weight.
+impl PackageManifest {
+    /// The command `entrypoint`, as given to `flint run`, means: the command of that name,
+    /// or else the first one whose path ends with it, eg: `bin/server`
+    #[must_use]
+    pub fn command(&self, entrypoint: &str) -> Option<&PackageCommand> {
+        self.commands
+            .iter()
+            .find(|command| command.name().as_deref() == Some(entrypoint))
+            .or_else(|| {
+                self.commands
+                    .iter()
+                    .find(|command| command.path.ends_with(entrypoint))
+            })
+    }
+}
+
+/// A command a package gives access to, eg: `bin/server`.
+///
+/// Written as a plain path when only the path is set, which is also how older manifests list them.
 #[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(from = "CommandEntry", into = "CommandEntry")]
+pub struct PackageCommand {
+    /// Entrypoint, relative to the package. May start with a `/`, eg: `/bin/server`
+    pub path: PathBuf,
+    /// What the command is run and quicklaunched as, the entrypoint's filename if not set
+    pub name: Option<String>,
+    /// Passed before any args given when running it. Args starting with `./` are relative to the package
+    pub args: Vec<String>,
+    /// Directory to run in, relative to the package. The current directory if not set
+    pub working_dir: Option<PathBuf>,
+}
+
+impl PackageCommand {
+    /// A command that just runs its entrypoint
+    #[must_use]
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            name: None,
+            args: Vec::new(),
+            working_dir: None,
+        }
+    }
+
+    /// What the command is run and quicklaunched as
+    #[must_use]
+    pub fn name(&self) -> Option<String> {
+        self.name.clone().or_else(|| {
+            self.path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+        })
+    }
+}
+
+impl From<&str> for PackageCommand {
+    fn from(path: &str) -> Self {
+        Self::new(path)
+    }
+}
+
+impl fmt::Display for PackageCommand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(name) = &self.name {
+            write!(f, "{name}: ")?;
+        }
+        write!(f, "{}", self.path.display())?;
+        for arg in &self.args {
+            write!(f, " {arg}")?;
+        }
+        if let Some(working_dir) = &self.working_dir {
+            write!(f, " (in {})", working_dir.display())?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(untagged)]
+enum CommandEntry {
+    Path(PathBuf),
+    Full {
+        path: PathBuf,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        args: Vec<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        working_dir: Option<PathBuf>,
+    },
+}
+
+impl From<CommandEntry> for PackageCommand {
+    fn from(entry: CommandEntry) -> Self {
+        match entry {
+            CommandEntry::Path(path) => Self::new(path),
+            CommandEntry::Full {
+                path,
+                name,
+                args,
+                working_dir,
+            } => Self {
+                path,
+                name,
+                args,
+                working_dir,
+            },
+        }
+    }
+}
+
+impl From<PackageCommand> for CommandEntry {
+    fn from(command: PackageCommand) -> Self {
+        if command == PackageCommand::new(command.path.clone()) {
+            return Self::Path(command.path);
+        }
+
+        Self::Full {
+            path: command.path,
+            name: command.name,
+            args: command.args,
+            working_dir: command.working_dir,
+        }
+    }
+}
+
+/// Host requirements, checked on install and run.
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct Requirements {
+    /// x86-64 microarchitecture level the package is built for, eg: `x86-64-v3`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu: Option<String>,
+    /// Oldest glibc the binaries work with, eg: `2.34`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_glibc: Option<String>,
+    /// Oldest macOS the binaries work with, eg: `13.0`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_macos: Option<String>,
+}
+
+/// Maps an interpreter used in shebangs or by ELF binaries to one shipped by another package.
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Interpreter {
+    /// The interpreter as written in the shebang or ELF header, eg: `/usr/bin/python3`.
+    /// For `#!/usr/bin/env python3` shebangs, just the command: `python3`
+    pub shebang: String,
+    /// The dependency providing the interpreter
+    pub package: String,
+    /// Path of the interpreter inside that package, eg: `bin/python3`
+    pub path: PathBuf,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestStatus {
+    Passed,
+    Skipped,
+}
+
+impl fmt::Display for TestStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::Passed => write!(f, "Passed"),
+            Self::Skipped => write!(f, "Skipped"),
+        }
+    }
+}
+
+/// The contents of `install.meta`, the package manifest plus any install-specific details.
+/// Can always be read as a plain `PackageManifest`.
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct InstallMeta {
+    #[serde(flatten)]
+    pub package: PackageManifest,
+    /// Installed from a working directory with `flint dev link`
+    #[serde(default)]
+    pub dev_install: bool,
+    /// Seconds since the UNIX epoch the package was first installed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub installed_at: Option<u64>,
+    /// Seconds since the UNIX epoch this version was installed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<u64>,
+    /// Measured size on disk in kilobytes, rounded. Only read from metadata written before `disk_bytes`.
+    #[serde(default, skip_serializing)]
+    pub disk_size: Option<u64>,
+    /// Measured size on disk in bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disk_bytes: Option<u64>,
+    /// Files changed after install, eg: shebangs and ELF interpreters pointed at dependencies.
+    /// These replace the package's chunks at the same paths when checking the tree.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rewritten: Vec<Chunk>,
+}
+
+impl InstallMeta {
+    /// The chunks of the installed tree, as they should be on disk
+    #[must_use]
+    pub fn installed_chunks(&self) -> Vec<Chunk> {
+        installed_chunks(&self.package.chunks, &self.rewritten)
+    }
+
+    /// Measured size on disk in bytes, falling back to the rounded size of older metadata
+    #[must_use]
+    pub fn measured_size(&self) -> Option<u64> {
+        self.disk_bytes
+            .or_else(|| self.disk_size.map(|size| size * 1024))
+    }
+}
+
+/// All of these are user visible, and should carry no actual weight.
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Default, PartialEq, Eq)]
 pub struct Metadata {
     pub title: Option<String>,
     pub description: Option<String>,
@@ -35,6 +481,14 @@ pub struct Metadata {
     pub version: Option<String>,
     /// SPDX Identifier
     pub license: Option<String>,
+    /// Who maintains the package, eg: "Jane Doe <jane@example.com>"
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub maintainers: Vec<String>,
+    /// Extra words the package is found by when searching
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keywords: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub categories: Vec<String>,
 }
 
 fn build_hash_default() -> String {
@@ -0,0 +1,147 @@
+use anyhow::Result;
+use std::{collections::HashSet, fs, path::Path};
+
+use crate::{
+    chunks::{Chunk, utils::used_chunks},
+    repo::{installed::get_installed, read_manifest},
+};
+
+/// How much a single Repository holds, and how much of it is installed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RepoUsage {
+    pub packages: usize,
+    /// Distinct chunks used by the Repository's packages
+    pub chunks: usize,
+    /// Size of those chunks, counting chunks shared by several packages once
+    pub chunk_bytes: u64,
+    pub installed_packages: usize,
+    /// Size of the installed packages' files
+    pub installed_bytes: u64,
+}
+
+/// How much the chunk store holds, and how much of it nothing uses
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StoreUsage {
+    pub chunks: usize,
+    pub bytes: u64,
+    /// Chunks no Repository uses, removed by the next `clean_unused`
+    pub orphaned_chunks: usize,
+    pub orphaned_bytes: u64,
+}
+
+/// Counts the packages and chunks of a Repository.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+/// - Repository doesn't exist
+pub fn repo_usage(repo_path: &Path) -> Result<RepoUsage> {
+    let manifest = read_manifest(repo_path)?;
+    let mut usage = RepoUsage {
+        packages: manifest.packages.len(),
+        ..RepoUsage::default()
+    };
+
+    let mut seen = HashSet::new();
+    for chunk in manifest.packages.iter().flat_map(|package| &package.chunks) {
+        if seen.insert(chunk.filename()) {
+            usage.chunks += 1;
+            usage.chunk_bytes += chunk.size();
+        }
+    }
+
+    for install_meta in get_installed(repo_path)? {
+        usage.installed_packages += 1;
+        usage.installed_bytes += install_meta
+            .measured_size()
+            .unwrap_or_else(|| install_meta.package.chunks.iter().map(Chunk::size).sum());
+    }
+
+    Ok(usage)
+}
+
+/// Measures the chunk store, and what in it no Repository in `repos_path` uses.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+pub fn store_usage(repos_path: &Path, chunk_store_path: &Path) -> Result<StoreUsage> {
+    let used: HashSet<String> = used_chunks(repos_path)?
+        .iter()
+        .map(Chunk::filename)
+        .collect();
+    let mut usage = StoreUsage::default();
+
+    for entry in fs::read_dir(chunk_store_path)? {
+        let entry = entry?;
+        let size = entry.metadata()?.len();
+
+        usage.chunks += 1;
+        usage.bytes += size;
+
+        if !used.contains(entry.file_name().to_string_lossy().as_ref()) {
+            usage.orphaned_chunks += 1;
+            usage.orphaned_bytes += size;
+        }
+    }
+
+    Ok(usage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chunks::save_tree,
+        repo::{PackageManifest, create_repo, insert_package},
+    };
+    use temp_dir::TempDir;
+
+    #[test]
+    fn test_usage() -> Result<()> {
+        let repos = TempDir::new()?;
+        let chunk_store = TempDir::new()?;
+        let tree = TempDir::new()?;
+        let repo_path = &repos.path().join("repo");
+        create_repo(repo_path, Some(repo_path))?;
+
+        // The same file twice is stored once
+        fs::write(tree.path().join("a"), "hello")?;
+        fs::write(tree.path().join("b"), "hello")?;
+        fs::write(tree.path().join("c"), "world!")?;
+
+        let package = PackageManifest {
+            id: "hello".into(),
+            chunks: save_tree(
+                tree.path(),
+                chunk_store.path(),
+                read_manifest(repo_path)?.hash_kind,
+            )?,
+            ..Default::default()
+        };
+        insert_package(&package, repo_path, Some(repo_path))?;
+        fs::write(chunk_store.path().join("orphan"), "orphan")?;
+
+        assert_eq!(
+            repo_usage(repo_path)?,
+            RepoUsage {
+                packages: 1,
+                chunks: 2,
+                chunk_bytes: 11,
+                installed_packages: 0,
+                installed_bytes: 0,
+            }
+        );
+        assert_eq!(
+            store_usage(repos.path(), chunk_store.path())?,
+            StoreUsage {
+                chunks: 3,
+                bytes: 17,
+                orphaned_chunks: 1,
+                orphaned_bytes: 6,
+            }
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,266 @@
+use anyhow::Result;
+use std::{fmt::Write, fs, path::Path};
+
+use crate::{
+    chunks::{VerifyProgress, scrub_tree, verify_chunks},
+    repo::{InstallMeta, image::find_image, read_manifest},
+    utils::platform::mode,
+};
+
+/// Failures listed in a problem before the rest are only counted
+const LISTED_FAILURES: usize = 5;
+
+/// A single check of [`verify_repo`]
+#[derive(serde::Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct VerifyCheck {
+    /// What was checked, eg: `chunks` or `version hello-<hash>`
+    pub name: String,
+    /// Why the check failed, `None` if it passed
+    pub problem: Option<String>,
+}
+
+/// Every check of a Repository, in the order they ran
+#[derive(serde::Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct RepoVerifyReport {
+    pub checks: Vec<VerifyCheck>,
+}
+
+impl RepoVerifyReport {
+    fn push(&mut self, name: impl Into<String>, problem: Option<String>) {
+        self.checks.push(VerifyCheck {
+            name: name.into(),
+            problem,
+        });
+    }
+
+    #[must_use]
+    pub fn failed(&self) -> usize {
+        self.checks
+            .iter()
+            .filter(|check| check.problem.is_some())
+            .count()
+    }
+}
+
+/// Checks everything Flint keeps for a Repository, from its manifest down to what is installed.
+///
+/// The manifest signature is checked first, nothing else is trusted without it.
+/// Then every chunk its packages use, with [`verify_chunks`] (corrupt chunks are removed),
+/// every installed version against its `install.meta`, and the quicklaunch scripts of its packages.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+pub fn verify_repo(
+    repo_path: &Path,
+    chunk_store_path: &Path,
+    quicklaunch_path: &Path,
+    progress: &(dyn Fn(VerifyProgress) + Sync),
+) -> Result<RepoVerifyReport> {
+    let mut report = RepoVerifyReport::default();
+
+    let manifest = match read_manifest(repo_path) {
+        Ok(manifest) => {
+            report.push("manifest signature", None);
+            manifest
+        }
+        Err(err) => {
+            report.push("manifest signature", Some(format!("{err:#}")));
+            return Ok(report);
+        }
+    };
+
+    let chunks = verify_chunks(repo_path, chunk_store_path, false, progress)?;
+    report.push(
+        "chunks",
+        (chunks.failed() > 0).then(|| {
+            format!(
+                "{} missing, {} corrupt (removed, download them again)",
+                chunks.missing.len(),
+                chunks.corrupt.len()
+            )
+        }),
+    );
+
+    let versions_path = repo_path.join("versions");
+    if versions_path.exists() {
+        let mut versions: Vec<_> = fs::read_dir(&versions_path)?.collect::<Result<_, _>>()?;
+        versions.sort_by_key(fs::DirEntry::file_name);
+
+        for entry in versions {
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+
+            let version_path = entry.path();
+            let name = format!("version {}", entry.file_name().to_string_lossy());
+
+            // Images are checked by their own filesystem when mounted
+            if find_image(&version_path).is_some() {
+                continue;
+            }
+
+            let install_meta = match fs::read_to_string(version_path.join("install.meta")) {
+                Ok(install_meta) => serde_yaml::from_str::<InstallMeta>(&install_meta),
+                Err(err) => {
+                    report.push(name, Some(format!("Could not read install.meta: {err}")));
+                    continue;
+                }
+            };
+            let install_meta = match install_meta {
+                Ok(install_meta) => install_meta,
+                Err(err) => {
+                    report.push(name, Some(format!("Invalid install.meta: {err}")));
+                    continue;
+                }
+            };
+
+            let scrub = scrub_tree(
+                &version_path,
+                &install_meta.installed_chunks(),
+                chunk_store_path,
+                manifest.hash_kind,
+            )?;
+            let failures: Vec<String> = scrub
+                .modified
+                .iter()
+                .map(|path| format!("{} modified", path.display()))
+                .chain(
+                    scrub
+                        .missing
+                        .iter()
+                        .map(|path| format!("{} missing", path.display())),
+                )
+                .collect();
+
+            report.push(name, summarize(&failures));
+        }
+    }
+
+    let mut broken = Vec::new();
+    for package in &manifest.packages {
+        for command in &package.commands {
+            let Some(command) = command.name() else {
+                continue;
+            };
+
+            if let Some(problem) = check_quicklaunch(&quicklaunch_path.join(&command)) {
+                broken.push(format!("{command} {problem}"));
+            }
+        }
+    }
+    report.push("quicklaunch scripts", summarize(&broken));
+
+    Ok(report)
+}
+
+/// What is wrong with a quicklaunch script, if anything
+fn check_quicklaunch(script_path: &Path) -> Option<&'static str> {
+    let Ok(metadata) = fs::metadata(script_path) else {
+        return Some("missing");
+    };
+    if mode(&metadata) & 0o111 == 0 {
+        return Some("not executable");
+    }
+
+    // `#!/bin/bash\nexec <flint> run <package> -- <command> "$@"`
+    let script = fs::read_to_string(script_path).ok()?;
+    let line = script.lines().nth(1)?;
+    let executable = line
+        .strip_prefix("exec ")
+        .unwrap_or(line)
+        .split(" run ")
+        .next()?;
+    if !Path::new(executable).exists() {
+        return Some("points at a Flint executable that no longer exists");
+    }
+
+    None
+}
+
+/// The first few failures, and how many more there are
+fn summarize(failures: &[String]) -> Option<String> {
+    if failures.is_empty() {
+        return None;
+    }
+
+    let mut summary = failures
+        .iter()
+        .take(LISTED_FAILURES)
+        .cloned()
+        .collect::<Vec<_>>()
+        .join(", ");
+    if failures.len() > LISTED_FAILURES {
+        // Writing to a String can't fail
+        let _ = write!(summary, " and {} more", failures.len() - LISTED_FAILURES);
+    }
+
+    Some(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chunks::{load_tree, save_tree},
+        repo::{PackageManifest, create_repo, insert_package},
+    };
+    use temp_dir::TempDir;
+
+    #[test]
+    fn test_verify_repo() -> Result<()> {
+        let root = TempDir::new()?;
+        let repos_path = &root.path().join("repos");
+        let repo_path = &repos_path.join("repo");
+        let chunk_store = &root.path().join("chunks");
+        let quicklaunch = &root.path().join("quicklaunch");
+        let tree = &root.path().join("tree");
+        create_repo(repo_path, Some(repo_path))?;
+        fs::create_dir_all(quicklaunch)?;
+
+        fs::create_dir_all(tree.join("bin"))?;
+        fs::write(tree.join("bin/hello"), "#!/bin/sh\necho hello\n")?;
+        let package = PackageManifest {
+            id: "hello".into(),
+            chunks: save_tree(tree, chunk_store, read_manifest(repo_path)?.hash_kind)?,
+            commands: vec!["bin/hello".into()],
+            ..Default::default()
+        };
+        insert_package(&package, repo_path, Some(repo_path))?;
+
+        let version_path = &repo_path.join("versions/hello-aaa");
+        load_tree(version_path, chunk_store, &package.chunks)?;
+        fs::write(
+            version_path.join("install.meta"),
+            serde_yaml::to_string(&package)?,
+        )?;
+
+        let report = verify_repo(repo_path, chunk_store, quicklaunch, &|_| {})?;
+        let failed: Vec<&str> = report
+            .checks
+            .iter()
+            .filter(|check| check.problem.is_some())
+            .map(|check| check.name.as_str())
+            .collect();
+        assert_eq!(failed, vec!["quicklaunch scripts"]);
+
+        crate::run::quicklaunch::update_quicklaunch(repos_path, quicklaunch)?;
+        fs::remove_file(version_path.join("bin/hello"))?;
+        let report = verify_repo(repo_path, chunk_store, quicklaunch, &|_| {})?;
+        assert_eq!(report.failed(), 1);
+        assert_eq!(
+            report.checks[2],
+            VerifyCheck {
+                name: "version hello-aaa".into(),
+                problem: Some("bin/hello missing".into()),
+            }
+        );
+
+        fs::write(repo_path.join("manifest.yml.sig"), "forged")?;
+        let report = verify_repo(repo_path, chunk_store, quicklaunch, &|_| {})?;
+        assert_eq!(report.checks.len(), 1);
+        assert_eq!(report.failed(), 1);
+
+        Ok(())
+    }
+}
@@ -1,9 +1,28 @@
-use anyhow::{Context, Result};
-use std::{fs, os::unix::fs::symlink, path::Path};
+use anyhow::{Context, Result, bail};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
 
 use crate::{
-    chunks::{HashKind, hash::hash, load_tree},
-    repo::{PackageManifest, get_package, read_manifest},
+    chunks::{
+        HashKind, hash::hash, load_tree, measure_tree_size, normalize_tree, rewritten_chunks,
+        should_sync_tree, sync_tree,
+    },
+    config::{get_system_data_dir, read_config},
+    repo::{
+        InstallMeta, PackageManifest, get_package,
+        image::{ImageFormat, pack_image, remove_image},
+        installed::{read_install_meta, reindex_installed, remove_installed, write_install_meta},
+        metadata_policy::check_package_id,
+        provenance::now,
+        read_manifest,
+        shebang::{rewrite_elf_interpreters, rewrite_shebangs},
+    },
+    utils::{
+        owner::Owner,
+        platform::{read_dir_link, symlink_dir},
+    },
 };
 
 fn hash_package(package_manifest: &PackageManifest, hash_kind: HashKind) -> Result<String> {
@@ -12,6 +31,24 @@ fn hash_package(package_manifest: &PackageManifest, hash_kind: HashKind) -> Resu
     Ok(hash(hash_kind, hash_str.as_bytes()))
 }
 
+/// Where `install_version` writes the latest version of a package.
+///
+/// # Errors
+///
+/// - Invalid Repository/Package manifest
+pub fn version_path(repo_path: &Path, package_id: &str) -> Result<PathBuf> {
+    let repo_manifest = read_manifest(repo_path)?;
+
+    let package_manifest = get_package(&repo_manifest, package_id)
+        .with_context(|| "Failed to get package from Repository.")?;
+    check_package_id(&package_manifest.id)?;
+    let package_hash = hash_package(&package_manifest, repo_manifest.hash_kind)?;
+
+    Ok(repo_path
+        .join("versions")
+        .join(format!("{}-{}", package_manifest.id, package_hash)))
+}
+
 /// Installs the latest version of a package, assumes all chunks are available.
 /// It is recommended you call `autoclean_versions` after.
 ///
@@ -32,39 +69,222 @@ pub fn install_version(
 
     let package_manifest = get_package(&repo_manifest, package_id)
         .with_context(|| "Failed to get package from Repository.")?;
+    // Manifests from before ids were checked, or from a malicious mirror, can't escape `versions/`
+    check_package_id(&package_manifest.id)?;
     let package_hash = hash_package(&package_manifest, repo_manifest.hash_kind)?;
     let installed_path = &repo_path
         .join("versions")
         .join(format!("{}-{}", package_manifest.id, package_hash));
 
+    // Reinstalling a version stored as an image writes the tree again
+    remove_image(installed_path)?;
+
     load_tree(installed_path, chunk_store_path, &package_manifest.chunks)
         .with_context(|| "Failed to rebuild the tree.")?;
+    let mut rewritten = rewrite_shebangs(installed_path, repo_path, &package_manifest.interpreters)
+        .with_context(|| "Failed to rewrite shebangs.")?;
+    rewritten.extend(
+        rewrite_elf_interpreters(installed_path, repo_path, &package_manifest.interpreters)
+            .with_context(|| "Failed to rewrite ELF interpreters.")?,
+    );
+    // Checking the tree later expects these, not the chunks they were loaded from
+    let rewritten = rewritten_chunks(
+        installed_path,
+        &package_manifest.chunks,
+        &rewritten,
+        repo_manifest.hash_kind,
+    )?;
+
+    // System-wide installs are used by everyone, whatever the umask of whoever installed them
+    if installed_path.starts_with(get_system_data_dir()) {
+        let owner = read_config(None)?
+            .install_owner
+            .as_deref()
+            .map(Owner::parse)
+            .transpose()?;
 
-    fs::write(
-        installed_path.join("install.meta"),
-        serde_yaml::to_string(&package_manifest)?,
+        normalize_tree(installed_path, owner)
+            .with_context(|| "Failed to hand the tree to its owner.")?;
+    }
+
+    let now = now()?;
+    let installed_at = read_install_meta(repo_path, &package_manifest.id)
+        .ok()
+        .flatten()
+        .and_then(|previous| previous.installed_at)
+        .unwrap_or(now);
+
+    let install_meta = InstallMeta {
+        package: package_manifest,
+        dev_install: false,
+        installed_at: Some(installed_at),
+        updated_at: Some(now),
+        disk_size: None,
+        disk_bytes: Some(measure_tree_size(installed_path)?),
+        rewritten,
+    };
+
+    write_install_meta(
+        repo_path,
+        installed_path,
+        Some(&package_hash),
+        &install_meta,
     )?;
 
+    // Before anything switches to it
+    if should_sync_tree(installed_path)? {
+        sync_tree(installed_path).with_context(|| "Failed to sync the tree to disk.")?;
+    }
+
     Ok(package_hash)
 }
 
+/// Packs an installed version into a compressed image, mounted over it when run.
+///
+/// # Errors
+///
+/// - The image tools are not installed, or failed
+/// - Filesystem errors (Out of space, Permissions)
+pub fn pack_version(
+    repo_path: &Path,
+    hash: &str,
+    package_id: &str,
+    format: ImageFormat,
+) -> Result<()> {
+    let version_path = &repo_path.join(format!("versions/{package_id}-{hash}"));
+    let image_size = pack_image(version_path, format)?;
+
+    // The copy inside the image keeps the size of the tree it was packed from
+    let install_meta_path = version_path.join("install.meta");
+    let mut install_meta: InstallMeta =
+        serde_yaml::from_str(&fs::read_to_string(&install_meta_path)?)?;
+    install_meta.disk_bytes = Some(image_size);
+    fs::write(install_meta_path, serde_yaml::to_string(&install_meta)?)?;
+
+    Ok(())
+}
+
 /// Switch to an older version/package hash.
 ///
 /// # Errors
 ///
 /// - Filesystem error during symlink (Within repo directory)
 pub fn switch_version(repo_path: &Path, hash: &str, package_id: &str) -> Result<()> {
+    let versions_path = format!("../versions/{package_id}-{hash}");
+
+    link_installed(repo_path, Path::new(&versions_path), package_id)
+}
+
+/// Atomically points `installed/<package_id>` at `target`
+fn link_installed(repo_path: &Path, target: &Path, package_id: &str) -> Result<()> {
     let target_parent_path = repo_path.join("installed");
     let target_path = target_parent_path.join(package_id);
     let target_tmp_path = target_parent_path.join(format!("{package_id}.tmp"));
     fs::create_dir_all(target_parent_path)?;
 
-    let versions_path = format!("../versions/{package_id}-{hash}");
+    if target_tmp_path.is_symlink() {
+        fs::remove_file(&target_tmp_path)?;
+    }
 
-    symlink(&versions_path, &target_tmp_path)?;
+    symlink_dir(target, &target_tmp_path)?;
     fs::rename(&target_tmp_path, &target_path)?;
 
-    Ok(())
+    reindex_installed(repo_path, package_id)
+}
+
+/// Points `installed/<package_id>` at a working directory, so changes show up without rebuilding.
+/// An `install.meta` marked as a dev install is written into the directory.
+///
+/// # Errors
+///
+/// - Package doesn't exist in the Repository
+/// - Filesystem errors (Directory doesn't exist, Permissions)
+pub fn link_dev(repo_path: &Path, package_id: &str, dir: &Path) -> Result<()> {
+    let dir = dir
+        .canonicalize()
+        .with_context(|| format!("Could not find directory {}", dir.display()))?;
+    let package_manifest = get_package(&read_manifest(repo_path)?, package_id)?;
+
+    let install_meta = InstallMeta {
+        package: package_manifest,
+        dev_install: true,
+        installed_at: Some(now()?),
+        updated_at: None,
+        disk_size: None,
+        disk_bytes: None,
+        rewritten: Vec::new(),
+    };
+
+    write_install_meta(repo_path, &dir, None, &install_meta)?;
+
+    link_installed(repo_path, &dir, &install_meta.package.id)
+}
+
+/// Removes a dev install created by `link_dev`, leaving the package uninstalled.
+///
+/// # Errors
+///
+/// - Package is not a dev install
+/// - Filesystem errors (Permissions)
+pub fn unlink_dev(repo_path: &Path, package_id: &str) -> Result<()> {
+    if !is_dev_install(repo_path, package_id) {
+        bail!("Package '{package_id}' is not a dev install.")
+    }
+
+    let install_meta_path = repo_path
+        .join("installed")
+        .join(package_id)
+        .join("install.meta");
+
+    if install_meta_path.exists() {
+        fs::remove_file(install_meta_path)?;
+    }
+
+    remove_installed(repo_path, package_id)
+}
+
+/// Checks if a package is installed from a working directory with `link_dev`
+#[must_use]
+pub fn is_dev_install(repo_path: &Path, package_id: &str) -> bool {
+    read_install_meta(repo_path, package_id)
+        .ok()
+        .flatten()
+        .is_some_and(|install_meta| install_meta.dev_install)
+}
+
+/// Gets the version hash that `installed/<package_id>` currently points at.
+///
+/// # Errors
+///
+/// - Invalid package id
+/// - Filesystem Read Errors (Permissions, etc)
+///
+/// # Returns
+///
+/// `None` if the package is not installed, or isn't a normal version install
+pub fn get_current_version(repo_path: &Path, package_id: &str) -> Result<Option<String>> {
+    check_package_id(package_id)?;
+    let installed_path = repo_path.join("installed").join(package_id);
+
+    let Some(target) = read_dir_link(&installed_path) else {
+        return Ok(None);
+    };
+    let prefix = format!("../versions/{package_id}-");
+
+    Ok(target
+        .to_str()
+        .and_then(|target| target.strip_prefix(&prefix))
+        .filter(|hash| version_exists(repo_path, hash, package_id))
+        .map(ToString::to_string))
+}
+
+/// Checks if a version of a package is present
+#[must_use]
+pub fn version_exists(repo_path: &Path, hash: &str, package_id: &str) -> bool {
+    repo_path
+        .join("versions")
+        .join(format!("{package_id}-{hash}"))
+        .is_dir()
 }
 
 /// Gets all versions for the `package_id`
@@ -100,15 +320,58 @@ pub fn get_versions(repo_path: &Path, package_id: &str) -> Result<Vec<String>> {
 ///
 /// # Errors
 ///
+/// - Invalid package id
 /// - Version is not installed for the package
 /// - Filesystem Write Errors (Permissions, etc)
 pub fn remove_version(repo_path: &Path, hash: &str, package_id: &str) -> Result<()> {
+    check_package_id(package_id)?;
     let path = repo_path.join(format!("versions/{package_id}-{hash}"));
 
     if path.exists() {
+        remove_image(&path)?;
         fs::remove_dir_all(path)?;
         Ok(())
     } else {
         anyhow::bail!("The version {hash} is not installed for package {package_id}")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::{create_repo, get_installed_package, insert_package};
+    use temp_dir::TempDir;
+
+    #[test]
+    fn test_dev_link_and_unlink() -> Result<()> {
+        let repo = TempDir::new()?;
+        let repo_path = repo.path();
+        let working_dir = TempDir::new()?;
+        create_repo(repo_path, Some(repo_path))?;
+
+        let package = PackageManifest {
+            id: "devpkg".into(),
+            build_hash: "hash".into(),
+            ..Default::default()
+        };
+        insert_package(&package, repo_path, Some(repo_path))?;
+
+        fs::write(working_dir.path().join("file"), "work in progress")?;
+        link_dev(repo_path, "devpkg", working_dir.path())?;
+
+        assert!(is_dev_install(repo_path, "devpkg"));
+        assert_eq!(get_installed_package(repo_path, "devpkg")?, package);
+        assert_eq!(
+            fs::read_to_string(repo_path.join("installed/devpkg/file"))?,
+            "work in progress"
+        );
+
+        unlink_dev(repo_path, "devpkg")?;
+        assert!(!repo_path.join("installed/devpkg").exists());
+        assert!(!working_dir.path().join("install.meta").exists());
+        assert!(working_dir.path().join("file").exists());
+        assert!(unlink_dev(repo_path, "devpkg").is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,160 @@
+use anyhow::Result;
+use std::{collections::HashSet, fs, path::Path};
+
+use crate::{repo::read_manifest, utils::platform::symlink_file};
+
+/// Where packages put shell completion files under `share/`, and where shells read them from
+/// under the completion directory. bash-completion loads these by command name, which is also
+/// the name of the quicklaunch script.
+const COMPLETION_DIRS: &[&str] = &[
+    "bash-completion/completions",
+    "zsh/site-functions",
+    "fish/vendor_completions.d",
+];
+
+/// Links the completion files of every installed package into `completions_path`, eg: `~/.local/share`.
+///
+/// Only links pointing into `repos_path` belong to Flint: links of removed packages are removed,
+/// and files the user put there are never replaced. With `enabled` unset, every link is removed.
+/// When two packages ship the same file, the first one found is linked.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+/// - Bad Repositories
+pub fn export_completions(repos_path: &Path, completions_path: &Path, enabled: bool) -> Result<()> {
+    let mut exported = HashSet::new();
+
+    if enabled {
+        for entry in repos_path.read_dir()? {
+            let repo_path = entry?.path();
+            let manifest = read_manifest(&repo_path)?;
+
+            for package in manifest.packages {
+                let share_path = repo_path.join("installed").join(&package.id).join("share");
+
+                for dir in COMPLETION_DIRS {
+                    // Not installed, or no completions
+                    let Ok(files) = fs::read_dir(share_path.join(dir)) else {
+                        continue;
+                    };
+
+                    let target_dir = completions_path.join(dir);
+                    fs::create_dir_all(&target_dir)?;
+
+                    for file in files {
+                        let name = file?.file_name();
+                        let target = share_path.join(dir).join(&name);
+                        let link = target_dir.join(&name);
+
+                        if exported.contains(&link) {
+                            continue;
+                        }
+
+                        if fs::symlink_metadata(&link).is_ok() {
+                            match fs::read_link(&link) {
+                                Ok(existing) if existing == target => {}
+                                Ok(existing) if existing.starts_with(repos_path) => {
+                                    fs::remove_file(&link)?;
+                                    symlink_file(&target, &link)?;
+                                }
+                                // The user's own
+                                _ => continue,
+                            }
+                        } else {
+                            symlink_file(&target, &link)?;
+                        }
+
+                        exported.insert(link);
+                    }
+                }
+            }
+        }
+    }
+
+    for dir in COMPLETION_DIRS {
+        let Ok(entries) = fs::read_dir(completions_path.join(dir)) else {
+            continue;
+        };
+
+        for entry in entries {
+            let link = entry?.path();
+
+            if fs::read_link(&link).is_ok_and(|target| target.starts_with(repos_path))
+                && !exported.contains(&link)
+            {
+                fs::remove_file(&link)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        repo::{PackageManifest, create_repo, insert_package},
+        utils::platform::symlink_dir,
+    };
+    use temp_dir::TempDir;
+
+    #[test]
+    fn test_export_completions() -> Result<()> {
+        let root = TempDir::new()?;
+        let repos_path = &root.path().join("repos");
+        let repo_path = &repos_path.join("repo");
+        let completions = &root.path().join("share");
+        create_repo(repo_path, Some(repo_path))?;
+
+        let package = PackageManifest {
+            id: "hello".into(),
+            commands: vec!["bin/hello".into()],
+            ..Default::default()
+        };
+        insert_package(&package, repo_path, Some(repo_path))?;
+
+        let version_path = &repo_path.join("versions/hello-aaa");
+        fs::create_dir_all(version_path.join("share/bash-completion/completions"))?;
+        fs::create_dir_all(version_path.join("share/zsh/site-functions"))?;
+        fs::write(
+            version_path.join("share/bash-completion/completions/hello"),
+            "complete -W world hello",
+        )?;
+        fs::write(version_path.join("share/zsh/site-functions/_hello"), "")?;
+        fs::create_dir(repo_path.join("installed"))?;
+        symlink_dir(
+            Path::new("../versions/hello-aaa"),
+            &repo_path.join("installed/hello"),
+        )?;
+
+        // Belongs to the user
+        fs::create_dir_all(completions.join("zsh/site-functions"))?;
+        fs::write(completions.join("zsh/site-functions/_hello"), "mine")?;
+
+        export_completions(repos_path, completions, true)?;
+
+        let bash = &completions.join("bash-completion/completions/hello");
+        assert_eq!(fs::read_to_string(bash)?, "complete -W world hello");
+        assert_eq!(
+            fs::read_to_string(completions.join("zsh/site-functions/_hello"))?,
+            "mine"
+        );
+
+        // Running again keeps the link
+        export_completions(repos_path, completions, true)?;
+        assert!(bash.exists());
+
+        export_completions(repos_path, completions, false)?;
+        assert!(!bash.is_symlink());
+        assert!(completions.join("zsh/site-functions/_hello").exists());
+
+        export_completions(repos_path, completions, true)?;
+        fs::remove_file(repo_path.join("installed/hello"))?;
+        export_completions(repos_path, completions, true)?;
+        assert!(!bash.is_symlink());
+
+        Ok(())
+    }
+}
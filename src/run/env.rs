@@ -0,0 +1,104 @@
+use std::{env, process::Command};
+
+/// Host variables packages see by default: identity, locale, terminal, session and completion plumbing.
+/// Anything that changes how programs load code (`LD_PRELOAD`, `PYTHONPATH`, ...) is left out.
+pub const DEFAULT_ALLOWED_ENV: &[&str] = &[
+    "HOME",
+    "USER",
+    "LOGNAME",
+    "SHELL",
+    "PATH",
+    "PWD",
+    "TMPDIR",
+    "TERM",
+    "COLORTERM",
+    "NO_COLOR",
+    "LANG",
+    "LANGUAGE",
+    "LC_*",
+    "TZ",
+    "DISPLAY",
+    "WAYLAND_DISPLAY",
+    "XAUTHORITY",
+    "DBUS_SESSION_BUS_ADDRESS",
+    "XDG_*",
+    "SSH_AUTH_SOCK",
+    // Set by bash for `complete -C` completers
+    "COMP_*",
+];
+
+/// How much of the caller's environment a package is started with.
+/// Package env is always applied on top.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EnvPolicy {
+    /// Pass everything through
+    Inherit,
+    /// Only pass through the listed variables. A trailing `*` matches a prefix
+    Clean { allow: Vec<String> },
+}
+
+impl Default for EnvPolicy {
+    fn default() -> Self {
+        Self::Clean {
+            allow: default_allow_list(),
+        }
+    }
+}
+
+/// `DEFAULT_ALLOWED_ENV`, ready to be extended
+#[must_use]
+pub fn default_allow_list() -> Vec<String> {
+    DEFAULT_ALLOWED_ENV
+        .iter()
+        .map(ToString::to_string)
+        .collect()
+}
+
+impl EnvPolicy {
+    /// Whether a variable of the caller is passed through
+    #[must_use]
+    pub fn allows(&self, key: &str) -> bool {
+        match self {
+            Self::Inherit => true,
+            Self::Clean { allow } => allow.iter().any(|pattern| {
+                pattern
+                    .strip_suffix('*')
+                    .map_or(pattern == key, |prefix| key.starts_with(prefix))
+            }),
+        }
+    }
+
+    /// Replaces the environment `command` would inherit with what the policy allows.
+    pub fn apply(&self, command: &mut Command) {
+        if *self == Self::Inherit {
+            return;
+        }
+
+        command.env_clear();
+
+        for (key, value) in env::vars_os() {
+            if self.allows(&key.to_string_lossy()) {
+                command.env(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_env_policy_allows() {
+        let policy = EnvPolicy::default();
+
+        assert!(policy.allows("HOME"));
+        assert!(policy.allows("LC_ALL"));
+        assert!(policy.allows("XDG_RUNTIME_DIR"));
+        assert!(!policy.allows("LD_PRELOAD"));
+        assert!(!policy.allows("PYTHONPATH"));
+        assert!(!policy.allows("HOMEDIR"));
+
+        assert!(EnvPolicy::Inherit.allows("LD_PRELOAD"));
+    }
+}
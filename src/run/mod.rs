@@ -1,21 +1,46 @@
+pub mod completions;
+pub mod env;
+#[cfg(all(feature = "fuse", target_os = "linux"))]
+pub mod mount;
+pub mod profile;
 pub mod quicklaunch;
+pub mod requirements;
 
 use anyhow::{Context, Result, bail};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     ffi::OsStr,
-    path::{Path, PathBuf},
-    process::{Command, ExitStatus},
+    ops::{Deref, DerefMut},
+    path::Path,
+    process::{Child, Command, ExitStatus},
 };
 
+use crate::{
+    chunks::{
+        Chunk, DownloadEvent, HashKind, InstallStats, import_chunks, load_tree_unsafe, verify_tree,
+    },
+    config::{get_shared_chunks_dir, read_config},
+    policy::{POLICY_PATH, Policy, read_policy},
+    repo::{
+        PackageManifest, get_package, get_package_closure,
+        image::MountedImages,
+        installed::{check_install_meta, read_install_meta, remove_installed},
+        read_manifest, read_subscribed_manifest,
+        versions::{
+            get_current_version, install_version, is_dev_install, pack_version, switch_version,
+        },
+    },
+    run::{env::EnvPolicy, requirements::check_requirements},
+};
 #[cfg(feature = "network")]
-use crate::chunks::install_tree;
-use crate::repo::{
-    PackageManifest, get_package, read_manifest,
-    versions::{install_version, switch_version},
+use crate::{
+    chunks::{entrypoint_chunks, install_tree, missing_chunks},
+    config::require_network,
+    repo::{credentials::get_repo_auth_for, mirrors::get_mirrors},
+    utils::temp::TempDir,
 };
 
-/// Starts a package from an entrypoint
+/// Starts a package from an entrypoint, and waits for it to exit
 ///
 /// # Errors
 ///
@@ -28,20 +53,115 @@ pub fn start<S: AsRef<OsStr>>(
     package_manifest: PackageManifest,
     entrypoint: &str,
     args: Vec<S>,
+    env_policy: &EnvPolicy,
 ) -> Result<ExitStatus> {
-    let installed_path = &repo_path.join("installed").join(package_manifest.id);
+    Ok(spawn(repo_path, package_manifest, entrypoint, args, env_policy)?.wait()?)
+}
 
-    // Get all matching commands
-    let matches: Vec<&PathBuf> = package_manifest
-        .commands
-        .iter()
-        .filter(|command| command.ends_with(entrypoint))
-        .collect();
+/// A package started by [`spawn`]. The images it and its dependencies run from stay mounted
+/// until this is dropped, so wait for the process (or kill it) first.
+#[derive(Debug)]
+pub struct RunningPackage {
+    child: Child,
+    _mounts: MountedImages,
+}
+
+impl Deref for RunningPackage {
+    type Target = Child;
+
+    fn deref(&self) -> &Child {
+        &self.child
+    }
+}
+
+impl DerefMut for RunningPackage {
+    fn deref_mut(&mut self) -> &mut Child {
+        &mut self.child
+    }
+}
+
+/// Starts a package from an entrypoint, without waiting for it to exit
+///
+/// # Errors
+///
+/// - Specified an entrypoint that doesn't exist
+/// - Filesystem errors (Out of space, Permissions)
+/// - Invalid Repository/Package manifest
+/// - Package is not installed
+/// - Package's `install.meta` was modified after it was installed
+pub fn spawn<S: AsRef<OsStr>>(
+    repo_path: &Path,
+    package_manifest: PackageManifest,
+    entrypoint: &str,
+    args: Vec<S>,
+    env_policy: &EnvPolicy,
+) -> Result<RunningPackage> {
+    if let Some(requirements) = &package_manifest.requirements {
+        check_requirements(&package_manifest.id, requirements)?;
+    }
+
+    let installed_path = &repo_path.join("installed").join(&package_manifest.id);
+    let mounts = mount_closure(repo_path, &package_manifest.id)?;
+    check_install_meta(repo_path, &package_manifest.id)?;
+
+    let policy = read_policy(None)?;
+    if policy.verified_launch {
+        verify_launch(&policy, repo_path, &package_manifest.id).with_context(|| {
+            format!(
+                "Refusing to run {} under {POLICY_PATH}",
+                package_manifest.id
+            )
+        })?;
+    }
+
+    let child = spawn_tree(
+        installed_path,
+        package_manifest,
+        entrypoint,
+        args,
+        env_policy,
+    )?;
+
+    Ok(RunningPackage {
+        child,
+        _mounts: mounts,
+    })
+}
+
+/// Mounts the images of an installed package and of everything it depends on,
+/// following the dependencies each `install.meta` lists.
+fn mount_closure(repo_path: &Path, package_id: &str) -> Result<MountedImages> {
+    let mut mounts = MountedImages::default();
+    let mut pending = vec![package_id.to_string()];
+    let mut seen = HashSet::new();
+
+    while let Some(package_id) = pending.pop() {
+        if !seen.insert(package_id.clone()) {
+            continue;
+        }
+
+        // An image's `install.meta` is inside it, so it is only readable once mounted
+        mounts.mount(&repo_path.join("installed").join(&package_id))?;
+        if let Some(install_meta) = read_install_meta(repo_path, &package_id)? {
+            pending.extend(install_meta.package.dependencies);
+        }
+    }
 
-    // Make sure theres at least a single match
-    if let Some(entrypoint) = matches.first() {
+    Ok(mounts)
+}
+
+/// Starts an entrypoint of a package from the tree at `tree_path`
+fn spawn_tree<S: AsRef<OsStr>>(
+    tree_path: &Path,
+    package_manifest: PackageManifest,
+    entrypoint: &str,
+    args: Vec<S>,
+    env_policy: &EnvPolicy,
+) -> Result<Child> {
+    // Make sure theres a match
+    if let Some(package_command) = package_manifest.command(entrypoint).cloned() {
         // Allow build_manifests to have a / at the start of entrypoints, eg: /bin/bash
-        let entrypoint = entrypoint.to_string_lossy();
+        let entrypoint = package_command.path.to_string_lossy();
         let entrypoint: &str = entrypoint.trim_start_matches('/');
 
         let mut envs: HashMap<String, String> = package_manifest.env.unwrap_or_default();
@@ -55,22 +175,77 @@ pub fn start<S: AsRef<OsStr>>(
 
         for key in keys_to_update {
             if let Some(value) = envs.get_mut(&key) {
-                *value = value.replace("./", &format!("{}/", &installed_path.to_string_lossy()));
+                *value = value.replace("./", &format!("{}/", &tree_path.to_string_lossy()));
             }
         }
 
+        // Default args come first, with paths into the package resolved like env vars
+        let default_args = package_command.args.iter().map(|arg| {
+            arg.strip_prefix("./")
+                .map_or_else(|| arg.into(), |path| tree_path.join(path).into_os_string())
+        });
+
         // Actually run the command
-        let status = Command::new(installed_path.join(entrypoint))
-            .args(args)
-            .envs(envs)
-            .status()?;
+        let mut command = Command::new(tree_path.join(entrypoint));
+        env_policy.apply(&mut command);
+        if let Some(working_dir) = &package_command.working_dir {
+            command
+                .current_dir(tree_path.join(working_dir.strip_prefix("/").unwrap_or(working_dir)));
+        }
+        let child = command.args(default_args).args(args).envs(envs).spawn()?;
 
-        Ok(status)
+        Ok(child)
     } else {
         bail!("Entrypoint does not exist.")
     }
 }
 
+/// Checks an installed package and everything it depends on against the Repository's signed manifest,
+/// for `verified_launch`. Only the builds the manifest lists are allowed, and every file of them is hashed again.
+fn verify_launch(policy: &Policy, repo_path: &Path, package_id: &str) -> Result<()> {
+    if policy.allowed_keys.is_empty() {
+        bail!("verified_launch needs at least one key in allowed_keys.")
+    }
+
+    // Reading the manifest checks its signature
+    let manifest = read_manifest(repo_path)?;
+    policy.check_repo_key(&manifest.public_key)?;
+
+    for signed in get_package_closure(&manifest, package_id)? {
+        verify_installed(repo_path, &signed, manifest.hash_kind)
+            .with_context(|| format!("Could not verify {}", signed.id))?;
+    }
+
+    Ok(())
+}
+
+/// Checks the installed tree of a single package against its signed manifest.
+/// Its image, if it is stored as one, must be mounted already.
+///
+/// Files rewritten on install (shebangs, ELF interpreters) are checked against the hashes
+/// `install.meta` recorded for them, which are only as trustworthy as `install.meta` itself.
+fn verify_installed(repo_path: &Path, signed: &PackageManifest, hash_kind: HashKind) -> Result<()> {
+    let installed_path = repo_path.join("installed").join(&signed.id);
+    if is_dev_install(repo_path, &signed.id) {
+        bail!("Dev installs can't be verified.")
+    }
+
+    check_install_meta(repo_path, &signed.id)?;
+    let Some(install_meta) = read_install_meta(repo_path, &signed.id)? else {
+        bail!("{} is not installed.", signed.id)
+    };
+
+    if install_meta.package.chunks != signed.chunks {
+        bail!("The installed build is not the one in the signed manifest, update it first.")
+    }
+    // Only packages using interpreters of their dependencies have anything rewritten
+    if signed.interpreters.is_empty() && !install_meta.rewritten.is_empty() {
+        bail!("install.meta lists rewritten files, but nothing should have been rewritten.")
+    }
+
+    verify_tree(&installed_path, &install_meta.installed_chunks(), hash_kind)
+}
+
 /// Installs the latest version of a package, assumes all chunks are available.
 /// Will automatically autoclean.
 ///
@@ -79,41 +254,374 @@ pub fn start<S: AsRef<OsStr>>(
 /// - Filesystem errors (Out of space, Permissions)
 /// - Invalid Repository/Package manifest
 /// - Network Errors (If network is enabled)
+///
+/// # Returns
+///
+/// Which chunks were downloaded, and which were already in the chunk store
+#[cfg_attr(
+    not(feature = "network"),
+    allow(clippy::unused_async, unused_variables)
+)]
 pub async fn install_package(
     repo_path: &Path,
     package_id: &str,
     chunk_store_path: &Path,
-) -> Result<()> {
-    let repo_manifest = read_manifest(repo_path)?;
+    progress: &(dyn Fn(DownloadEvent) + Sync),
+) -> Result<InstallStats> {
+    let repo_manifest = read_subscribed_manifest(repo_path)?;
 
     let package_manifest = get_package(&repo_manifest, package_id)
         .with_context(|| "Failed to get package from Repository.")?;
     // Don't just use an alias but actually resolve into a correct package id
     let package_id = &package_manifest.id;
 
+    if let Some(requirements) = &package_manifest.requirements {
+        check_requirements(package_id, requirements)?;
+    }
+
+    import_shared_chunks(&package_manifest.chunks, chunk_store_path)?;
+
     // Get any chunks that are not installed
     #[cfg(feature = "network")]
-    install_tree(
-        &package_manifest.chunks,
-        chunk_store_path,
-        &repo_manifest.mirrors,
-        repo_manifest.hash_kind,
-    )
-    .await
-    .with_context(|| "Failed to install package.")?;
+    let stats = download_package(repo_path, package_id, chunk_store_path, progress)
+        .await
+        .with_context(|| "Failed to install package.")?;
+    #[cfg(not(feature = "network"))]
+    let stats = InstallStats::reusing(&package_manifest.chunks);
 
     let hash = install_version(repo_path, package_id, chunk_store_path)?;
+    pack_configured(repo_path, &hash, package_id)?;
 
     switch_version(repo_path, &hash, package_id)?;
 
+    Ok(stats)
+}
+
+/// Installs the latest versions of several packages as one transaction, eg: a runtime and its users.
+///
+/// Every new version is written before any is switched to, and if switching fails partway
+/// the packages already switched are put back, so the set is never left half-updated.
+///
+/// # Errors
+///
+/// - Filesystem errors (Out of space, Permissions)
+/// - Invalid Repository/Package manifest
+/// - Network Errors (If network is enabled)
+///
+/// # Returns
+///
+/// Which chunks were downloaded for all of the packages, and which were already in the chunk store
+#[cfg_attr(
+    not(feature = "network"),
+    allow(clippy::unused_async, unused_variables)
+)]
+pub async fn install_packages(
+    repo_path: &Path,
+    package_ids: &[String],
+    chunk_store_path: &Path,
+    progress: &(dyn Fn(DownloadEvent) + Sync),
+) -> Result<InstallStats> {
+    let repo_manifest = read_subscribed_manifest(repo_path)?;
+    let mut packages = Vec::new();
+
+    for package_id in package_ids {
+        packages.push(
+            get_package(&repo_manifest, package_id)
+                .with_context(|| "Failed to get package from Repository.")?,
+        );
+    }
+
+    // Before downloading anything, so nothing is left half-installed
+    for package in &packages {
+        if let Some(requirements) = &package.requirements {
+            check_requirements(&package.id, requirements)?;
+        }
+    }
+
+    let mut stats = InstallStats::default();
+    for package in &packages {
+        import_shared_chunks(&package.chunks, chunk_store_path)?;
+
+        #[cfg(feature = "network")]
+        stats.merge(
+            download_package(repo_path, &package.id, chunk_store_path, progress)
+                .await
+                .with_context(|| format!("Failed to download {}.", package.id))?,
+        );
+        #[cfg(not(feature = "network"))]
+        stats.merge(InstallStats::reusing(&package.chunks));
+    }
+
+    let mut new_versions = Vec::new();
+    for package in &packages {
+        let hash = install_version(repo_path, &package.id, chunk_store_path)?;
+        pack_configured(repo_path, &hash, &package.id)?;
+        new_versions.push((package.id.as_str(), hash));
+    }
+
+    let mut switched: Vec<(&str, Option<String>)> = Vec::new();
+    for (package_id, hash) in new_versions {
+        let previous = get_current_version(repo_path, package_id)?;
+
+        if let Err(err) = switch_version(repo_path, &hash, package_id) {
+            for (package_id, previous) in switched.into_iter().rev() {
+                // Best effort, the original error is the one worth reporting
+                let _ = previous.map_or_else(
+                    || remove_installed(repo_path, package_id),
+                    |previous| switch_version(repo_path, &previous, package_id),
+                );
+            }
+
+            return Err(err.context(format!(
+                "Failed to switch {package_id}, the other packages were switched back."
+            )));
+        }
+
+        switched.push((package_id, previous));
+    }
+
+    Ok(stats)
+}
+
+/// Packs a newly installed version into an image, if the config asks for one.
+fn pack_configured(repo_path: &Path, hash: &str, package_id: &str) -> Result<()> {
+    if let Some(format) = read_config(None)?.image_format {
+        pack_version(repo_path, hash, package_id, format)
+            .with_context(|| format!("Failed to pack {package_id} into an image."))?;
+    }
+
+    Ok(())
+}
+
+/// Reuses chunks from the system chunk store in a user install, if enabled.
+fn import_shared_chunks(chunks: &[Chunk], chunk_store_path: &Path) -> Result<()> {
+    if let Some(shared_chunks_path) = get_shared_chunks_dir(chunk_store_path)? {
+        import_chunks(chunks, chunk_store_path, &shared_chunks_path)?;
+    }
+
+    Ok(())
+}
+
+/// Installs a package and everything it depends on directly into `root`, merging their trees.
+/// The Repository's own installed state is left untouched, which is useful for building images.
+///
+/// # Errors
+///
+/// - Two packages contain different files at the same path
+/// - Filesystem errors (Out of space, Permissions)
+/// - Invalid Repository/Package manifest
+/// - Network Errors (If network is enabled)
+///
+/// # Returns
+///
+/// Every package installed into `root`
+#[cfg_attr(not(feature = "network"), allow(unused_variables))]
+pub async fn install_to_root(
+    repo_path: &Path,
+    package_id: &str,
+    chunk_store_path: &Path,
+    root: &Path,
+    progress: &(dyn Fn(DownloadEvent) + Sync),
+) -> Result<Vec<PackageManifest>> {
+    let closure = get_package_closure(&read_subscribed_manifest(repo_path)?, package_id)?;
+
+    for package in &closure {
+        import_shared_chunks(&package.chunks, chunk_store_path)?;
+    }
+
+    #[cfg(feature = "network")]
+    for package in &closure {
+        download_package(repo_path, &package.id, chunk_store_path, progress)
+            .await
+            .with_context(|| format!("Failed to download {}.", package.id))?;
+    }
+
+    materialize_packages(&closure, chunk_store_path, root)?;
+
+    Ok(closure)
+}
+
+/// Writes the trees of all `packages` into `root`, assumes all chunks are available.
+///
+/// # Errors
+///
+/// - Two packages contain different files at the same path
+/// - Filesystem errors (Out of space, Permissions)
+pub fn materialize_packages(
+    packages: &[PackageManifest],
+    chunk_store_path: &Path,
+    root: &Path,
+) -> Result<()> {
+    let mut owners: HashMap<&Path, (&str, &str)> = HashMap::new();
+
+    for package in packages {
+        for chunk in &package.chunks {
+            if let Some((owner, hash)) = owners.insert(chunk.path(), (&package.id, chunk.hash()))
+                && hash != chunk.hash()
+            {
+                bail!(
+                    "{} is provided by both {owner} and {}",
+                    chunk.path().display(),
+                    package.id
+                )
+            }
+        }
+    }
+
+    for package in packages {
+        load_tree_unsafe(root, chunk_store_path, &package.chunks)
+            .with_context(|| format!("Failed to install {} into root.", package.id))?;
+    }
+
     Ok(())
 }
 
+/// Downloads all missing chunks of a package into the chunk store, without installing it.
+///
+/// # Errors
+///
+/// - Filesystem errors (Out of space, Permissions)
+/// - Invalid Repository/Package manifest
+/// - Network Errors
+///
+/// # Returns
+///
+/// Which chunks were downloaded, and which were already in the chunk store
+#[cfg(feature = "network")]
+pub async fn download_package(
+    repo_path: &Path,
+    package_id: &str,
+    chunk_store_path: &Path,
+    progress: &(dyn Fn(DownloadEvent) + Sync),
+) -> Result<InstallStats> {
+    let repo_manifest = read_subscribed_manifest(repo_path)?;
+    let package_manifest = get_package(&repo_manifest, package_id)
+        .with_context(|| "Failed to get package from Repository.")?;
+
+    import_shared_chunks(&package_manifest.chunks, chunk_store_path)?;
+
+    if missing_chunks(&package_manifest.chunks, chunk_store_path).is_empty() {
+        return Ok(InstallStats::reusing(&package_manifest.chunks));
+    }
+    require_network()?;
+
+    // What running the package needs first, so a slow download is usable sooner
+    let mirrors = get_mirrors(repo_path, &repo_manifest)?;
+    let auth = get_repo_auth_for(repo_path)?;
+    let entrypoints: Vec<_> = package_manifest
+        .commands
+        .iter()
+        .map(|command| command.path.clone())
+        .collect();
+    let (first, rest) = entrypoint_chunks(&package_manifest.chunks, &entrypoints);
+    let mut stats = InstallStats::default();
+    for chunks in [&first, &rest] {
+        let chunks: Vec<Chunk> = chunks.iter().map(|chunk| (*chunk).clone()).collect();
+        stats.merge(
+            install_tree(
+                &chunks,
+                chunk_store_path,
+                &mirrors,
+                repo_manifest.hash_kind,
+                auth.as_ref(),
+                progress,
+            )
+            .await?,
+        );
+    }
+
+    Ok(stats)
+}
+
+/// Whether a package can be started before it is installed.
+///
+/// Not if it has dependencies, which aren't there yet, or interpreters, whose paths are only
+/// rewritten into the package on install.
+#[must_use]
+pub const fn can_launch_early(package_manifest: &PackageManifest) -> bool {
+    package_manifest.dependencies.is_empty() && package_manifest.interpreters.is_empty()
+}
+
+/// Starts a package that isn't installed yet as soon as its entrypoint's directory is downloaded.
+///
+/// The install is finished while it runs, and then waits for it to exit.
+/// Only works for entrypoints that need nothing outside their own directory, see `launch_early`.
+///
+/// # Errors
+///
+/// - Specified an entrypoint that doesn't exist
+/// - `verified_launch` is set, as a package can't be verified before it is installed
+/// - The package can't be launched early, see [`can_launch_early`]
+/// - Filesystem errors (Out of space, Permissions)
+/// - Invalid Repository/Package manifest
+/// - Network Errors
+#[cfg(feature = "network")]
+pub async fn start_while_installing<S: AsRef<OsStr>>(
+    repo_path: &Path,
+    package_manifest: PackageManifest,
+    entrypoint: &str,
+    args: Vec<S>,
+    env_policy: &EnvPolicy,
+    chunk_store_path: &Path,
+    progress: &(dyn Fn(DownloadEvent) + Sync),
+) -> Result<ExitStatus> {
+    if let Some(requirements) = &package_manifest.requirements {
+        check_requirements(&package_manifest.id, requirements)?;
+    }
+    if read_policy(None)?.verified_launch {
+        bail!("Can't start a package before it is installed under {POLICY_PATH}.")
+    }
+    if !can_launch_early(&package_manifest) {
+        bail!(
+            "{} needs its dependencies installed before it can start.",
+            package_manifest.id
+        )
+    }
+
+    let repo_manifest = read_subscribed_manifest(repo_path)?;
+    let Some(package_command) = package_manifest.command(entrypoint) else {
+        bail!("Entrypoint does not exist.")
+    };
+    let entrypoints = [package_command.path.clone()];
+    let (first, _) = entrypoint_chunks(&package_manifest.chunks, &entrypoints);
+    let first: Vec<Chunk> = first.into_iter().cloned().collect();
+
+    import_shared_chunks(&first, chunk_store_path)?;
+    if !missing_chunks(&first, chunk_store_path).is_empty() {
+        require_network()?;
+        install_tree(
+            &first,
+            chunk_store_path,
+            &get_mirrors(repo_path, &repo_manifest)?,
+            repo_manifest.hash_kind,
+            get_repo_auth_for(repo_path)?.as_ref(),
+            progress,
+        )
+        .await?;
+    }
+
+    // Only lives until the package exits, the real install replaces it
+    let tree = TempDir::new()?;
+    load_tree_unsafe(tree.path(), chunk_store_path, &first)?;
+
+    let package_id = package_manifest.id.clone();
+    let mut child = spawn_tree(tree.path(), package_manifest, entrypoint, args, env_policy)?;
+    let installed = install_package(repo_path, &package_id, chunk_store_path, progress).await;
+    let status = child.wait()?;
+
+    installed.with_context(|| format!("Failed to finish installing {package_id}."))?;
+
+    Ok(status)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::chunks::save_tree;
-    use crate::repo::{Metadata, create_repo, insert_package};
+    use crate::repo::{
+        Metadata, PackageCommand, create_repo, get_installed_package, insert_package,
+        remove_package, test_package,
+    };
     use std::fs;
     use temp_dir::TempDir;
 
@@ -139,26 +647,21 @@ mod tests {
 
         let package = PackageManifest {
             id: "testpkg".to_string(),
-            aliases: vec![],
             metadata: Metadata {
                 title: Some("Test".to_string()),
-                description: None,
-                homepage_url: None,
-                version: None,
-                license: None,
+                ..Default::default()
             },
             chunks,
-            commands: Vec::new(),
-            env: None,
             // TODO!
             build_hash: "TODO".to_string(),
+            ..Default::default()
         };
 
         // Insert package
         insert_package(&package, repo_path, Some(repo_path))?;
 
         // Now install
-        install_package(repo_path, "testpkg", chunks_path).await?;
+        install_package(repo_path, "testpkg", chunks_path, &|_| {}).await?;
 
         // Check installed
         let installed_path = repo_path.join("installed/testpkg");
@@ -167,6 +670,297 @@ mod tests {
         assert!(installed_path.join("dir/file2").exists());
         assert!(installed_path.join("install.meta").exists());
 
+        // Verified launch
+        let mut policy = Policy::default();
+        assert!(verify_launch(&policy, repo_path, "testpkg").is_err());
+        policy.allowed_keys = vec![read_manifest(repo_path)?.public_key];
+        verify_launch(&policy, repo_path, "testpkg")?;
+        fs::write(installed_path.join("file1"), "tampered")?;
+        assert!(verify_launch(&policy, repo_path, "testpkg").is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_verify_launch_closure() -> Result<()> {
+        use crate::repo::Interpreter;
+        use std::os::unix::fs::PermissionsExt;
+
+        let repo_dir = TempDir::new()?;
+        let repo_path = repo_dir.path();
+        let chunks_dir = TempDir::new()?;
+        let chunks_path = chunks_dir.path();
+        create_repo(repo_path, Some(repo_path))?;
+
+        let python_tree = TempDir::new()?;
+        fs::create_dir(python_tree.path().join("bin"))?;
+        fs::write(python_tree.path().join("bin/python3"), "not really python")?;
+        let tool_tree = TempDir::new()?;
+        fs::create_dir(tool_tree.path().join("bin"))?;
+        fs::write(
+            tool_tree.path().join("bin/tool"),
+            "#!/usr/bin/python3\nprint()\n",
+        )?;
+        fs::set_permissions(
+            tool_tree.path().join("bin/tool"),
+            fs::Permissions::from_mode(0o755),
+        )?;
+
+        let python = PackageManifest {
+            id: "python".to_string(),
+            chunks: save_tree(python_tree.path(), chunks_path, HashKind::Blake3)?,
+            ..Default::default()
+        };
+        let tool = PackageManifest {
+            id: "tool".to_string(),
+            chunks: save_tree(tool_tree.path(), chunks_path, HashKind::Blake3)?,
+            dependencies: vec!["python".to_string()],
+            interpreters: vec![Interpreter {
+                shebang: "/usr/bin/python3".into(),
+                package: "python".into(),
+                path: "bin/python3".into(),
+            }],
+            ..python.clone()
+        };
+        insert_package(&python, repo_path, Some(repo_path))?;
+        insert_package(&tool, repo_path, Some(repo_path))?;
+        // Its shebang only works once rewritten on install
+        assert!(can_launch_early(&python));
+        assert!(!can_launch_early(&tool));
+        install_package(repo_path, "python", chunks_path, &|_| {}).await?;
+        install_package(repo_path, "tool", chunks_path, &|_| {}).await?;
+
+        let policy = Policy {
+            allowed_keys: vec![read_manifest(repo_path)?.public_key],
+            ..Policy::default()
+        };
+
+        // The rewritten shebang is not what the chunk says, but what install.meta recorded
+        let install_meta = read_install_meta(repo_path, "tool")?.context("Not installed")?;
+        assert_eq!(install_meta.rewritten.len(), 1);
+        verify_launch(&policy, repo_path, "tool")?;
+
+        fs::write(repo_path.join("installed/python/bin/python3"), "tampered")?;
+        assert!(verify_launch(&policy, repo_path, "tool").is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_install_by_alias() -> Result<()> {
+        use crate::repo::installed::{canonical_package_id, is_installed, read_install_meta};
+
+        let repo_dir = TempDir::new()?;
+        let repo_path = repo_dir.path();
+        let chunks_dir = TempDir::new()?;
+        let tree = TempDir::new()?;
+        create_repo(repo_path, Some(repo_path))?;
+
+        fs::create_dir(tree.path().join("bin"))?;
+        fs::write(
+            tree.path().join("bin/hello"),
+            "#!/bin/sh\necho hello > \"$1\"\n",
+        )?;
+        crate::utils::platform::set_mode(&tree.path().join("bin/hello"), 0o755)?;
+        let package = PackageManifest {
+            aliases: vec!["hi".to_string()],
+            chunks: save_tree(
+                tree.path(),
+                chunks_dir.path(),
+                crate::chunks::HashKind::Blake3,
+            )?,
+            commands: serde_yaml::from_str("- bin/hello\n")?,
+            ..test_package("hello", &[])
+        };
+        insert_package(&package, repo_path, Some(repo_path))?;
+
+        // Only ever installed under its id
+        install_package(repo_path, "hi", chunks_dir.path(), &|_| {}).await?;
+        assert!(repo_path.join("installed/hello").exists());
+        assert!(!repo_path.join("installed/hi").exists());
+        assert!(is_installed(repo_path, "hi"));
+        assert_eq!(canonical_package_id(repo_path, "hi")?, "hello");
+        assert_eq!(canonical_package_id(repo_path, "missing")?, "missing");
+        assert_eq!(
+            read_install_meta(repo_path, "hello")?.map(|install_meta| install_meta.package.id),
+            Some("hello".to_string())
+        );
+
+        let out = tree.path().join("out.txt");
+        let installed = get_installed_package(repo_path, "hi")?;
+        let status = spawn(
+            repo_path,
+            installed,
+            "hello",
+            vec![&out],
+            &EnvPolicy::default(),
+        )?
+        .wait()?;
+        assert!(status.success());
+        assert_eq!(fs::read_to_string(&out)?, "hello\n");
+
+        // Removed by its alias, even once it is no longer in the Repository
+        remove_package("hello", repo_path, Some(repo_path))?;
+        assert_eq!(canonical_package_id(repo_path, "hi")?, "hello");
+        remove_installed(repo_path, &canonical_package_id(repo_path, "hi")?)?;
+        assert!(!repo_path.join("installed/hello").exists());
+        assert!(!is_installed(repo_path, "hello"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_spawn_tree() -> Result<()> {
+        let tree = TempDir::new()?;
+        fs::create_dir_all(tree.path().join("bin"))?;
+        fs::create_dir_all(tree.path().join("etc"))?;
+        fs::write(
+            tree.path().join("bin/show"),
+            "#!/bin/sh\npwd > out.txt\necho \"$@\" >> out.txt\n",
+        )?;
+        crate::utils::platform::set_mode(&tree.path().join("bin/show"), 0o755)?;
+
+        let commands: Vec<PackageCommand> = serde_yaml::from_str(
+            "- path: /bin/show\n  name: server\n  args: [--config, ./etc/app.yml]\n  working_dir: etc\n- bin/show\n",
+        )?;
+        // Plain commands are still written as paths
+        assert!(serde_yaml::to_string(&commands)?.ends_with("- bin/show\n"));
+
+        let package = PackageManifest {
+            id: "show".to_string(),
+            commands,
+            ..Default::default()
+        };
+        assert_eq!(package.command("show"), Some(&package.commands[1]));
+        assert_eq!(package.command("server"), Some(&package.commands[0]));
+        assert_eq!(package.command("missing"), None);
+
+        let status = spawn_tree(
+            tree.path(),
+            package,
+            "server",
+            vec!["--verbose"],
+            &EnvPolicy::default(),
+        )?
+        .wait()?;
+        assert!(status.success());
+
+        let out = fs::read_to_string(tree.path().join("etc/out.txt"))?;
+        let (working_dir, args) = out.trim().split_once('\n').unwrap_or_default();
+        assert!(working_dir.ends_with("/etc"));
+        assert_eq!(
+            args,
+            format!(
+                "--config {} --verbose",
+                tree.path().join("etc/app.yml").display()
+            )
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_install_to_root() -> Result<()> {
+        let repo_dir = TempDir::new()?;
+        let repo_path = repo_dir.path();
+        let chunks_dir = TempDir::new()?;
+        let chunks_path = chunks_dir.path();
+        let root = TempDir::new()?;
+
+        create_repo(repo_path, Some(repo_path))?;
+
+        let package =
+            |id: &str, file: &str, dependencies: Vec<String>| -> Result<PackageManifest> {
+                let tree = TempDir::new()?;
+                fs::create_dir(tree.path().join("bin"))?;
+                fs::write(tree.path().join("bin").join(file), id)?;
+
+                Ok(PackageManifest {
+                    id: id.to_string(),
+                    chunks: save_tree(tree.path(), chunks_path, crate::chunks::HashKind::Blake3)?,
+                    dependencies,
+                    ..Default::default()
+                })
+            };
+
+        insert_package(
+            &package("app", "app", vec!["lib".into()])?,
+            repo_path,
+            Some(repo_path),
+        )?;
+        insert_package(&package("lib", "lib", vec![])?, repo_path, Some(repo_path))?;
+        insert_package(
+            &package("clash", "app", vec![])?,
+            repo_path,
+            Some(repo_path),
+        )?;
+
+        let installed =
+            install_to_root(repo_path, "app", chunks_path, root.path(), &|_| {}).await?;
+
+        assert_eq!(installed.len(), 2);
+        assert_eq!(fs::read_to_string(root.path().join("bin/app"))?, "app");
+        assert_eq!(fs::read_to_string(root.path().join("bin/lib"))?, "lib");
+        assert!(!repo_path.join("installed").exists());
+
+        // Conflicting files are refused
+        let manifest = read_manifest(repo_path)?;
+        let packages = [
+            get_package(&manifest, "app")?,
+            get_package(&manifest, "clash")?,
+        ];
+        assert!(materialize_packages(&packages, chunks_path, root.path()).is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_install_packages_all_or_nothing() -> Result<()> {
+        let repo_dir = TempDir::new()?;
+        let repo_path = repo_dir.path();
+        let chunks_dir = TempDir::new()?;
+        let chunks_path = chunks_dir.path();
+
+        create_repo(repo_path, Some(repo_path))?;
+
+        for id in ["app", "runtime", "broken"] {
+            let tree = TempDir::new()?;
+            fs::write(tree.path().join(id), id)?;
+
+            insert_package(
+                &PackageManifest {
+                    id: id.to_string(),
+                    chunks: save_tree(tree.path(), chunks_path, crate::chunks::HashKind::Blake3)?,
+                    ..Default::default()
+                },
+                repo_path,
+                Some(repo_path),
+            )?;
+        }
+
+        // Lose a chunk, so "broken" can't be installed
+        let broken = get_package(&read_manifest(repo_path)?, "broken")?;
+        fs::remove_file(chunks_path.join(broken.chunks[0].filename()))?;
+
+        let ids = ["app", "broken"].map(String::from);
+        assert!(
+            install_packages(repo_path, &ids, chunks_path, &|_| {})
+                .await
+                .is_err()
+        );
+        assert!(!repo_path.join("installed/app").exists());
+
+        let ids = ["app", "runtime"].map(String::from);
+        install_packages(repo_path, &ids, chunks_path, &|_| {}).await?;
+        assert_eq!(
+            fs::read_to_string(repo_path.join("installed/app/app"))?,
+            "app"
+        );
+        assert_eq!(
+            fs::read_to_string(repo_path.join("installed/runtime/runtime"))?,
+            "runtime"
+        );
+
         Ok(())
     }
 }
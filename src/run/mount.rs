@@ -0,0 +1,846 @@
+use anyhow::{Context, Result, bail};
+use nix::{
+    errno::Errno,
+    fcntl::OFlag,
+    mount::{MntFlags, MsFlags, mount, umount2},
+    sys::socket::{
+        AddressFamily, ControlMessageOwned, MsgFlags, SockFlag, SockType, recvmsg, socketpair,
+    },
+    unistd::{close, dup3, getgid, getuid},
+};
+use std::{
+    collections::{BTreeMap, HashSet},
+    ffi::{OsStr, OsString},
+    fs::{self, File, OpenOptions},
+    io::{IoSliceMut, Read, Seek, SeekFrom, Write},
+    os::{
+        fd::{AsRawFd, OwnedFd, RawFd},
+        unix::ffi::OsStrExt,
+    },
+    path::{Component, Path, PathBuf},
+    process::{Child, Command},
+    sync::{
+        Condvar, Mutex, PoisonError,
+        mpsc::{self, Receiver, Sender},
+    },
+    thread::{self, ScopedJoinHandle},
+};
+use tokio::runtime::Handle;
+
+use crate::{
+    chunks::{
+        Chunk, HashKind,
+        compress::{is_compressed_file, read_chunk},
+        network::install_chunks,
+    },
+    repo::{
+        PackageManifest, credentials::RepoAuth, credentials::get_repo_auth_for,
+        mirrors::get_mirrors, read_manifest,
+    },
+    run::import_shared_chunks,
+};
+
+const ROOT_ID: u64 = 1;
+const MAX_WRITE: u32 = 128 * 1024;
+/// How long the kernel may cache names and attributes, nothing in a package tree changes
+const TTL_SECONDS: u64 = 3600;
+/// Requests answered at once, so a chunk being fetched only holds up the reads waiting on it
+const WORKERS: usize = 16;
+
+const FUSE_LOOKUP: u32 = 1;
+const FUSE_FORGET: u32 = 2;
+const FUSE_GETATTR: u32 = 3;
+const FUSE_OPEN: u32 = 14;
+const FUSE_READ: u32 = 15;
+const FUSE_STATFS: u32 = 17;
+const FUSE_RELEASE: u32 = 18;
+const FUSE_FLUSH: u32 = 25;
+const FUSE_INIT: u32 = 26;
+const FUSE_OPENDIR: u32 = 27;
+const FUSE_READDIR: u32 = 28;
+const FUSE_RELEASEDIR: u32 = 29;
+const FUSE_INTERRUPT: u32 = 36;
+const FUSE_DESTROY: u32 = 38;
+const FUSE_BATCH_FORGET: u32 = 42;
+
+const FOPEN_KEEP_CACHE: u32 = 1 << 1;
+const S_IFDIR: u32 = 0o040_000;
+const S_IFREG: u32 = 0o100_000;
+
+/// Exposes a package's tree read-only at `mount_path`, without installing it.
+///
+/// Chunks missing from the chunk store are fetched from the Repository's mirrors the first time they are read.
+/// Blocks until the filesystem is unmounted, eg: with `umount` or `fusermount3 -u`.
+/// Mounting directly needs root, otherwise `fusermount3` is used.
+///
+/// # Returns
+///
+/// Files that could not be read while mounted, readers only got an I/O error for them
+///
+/// # Errors
+///
+/// - FUSE is unavailable, or `fusermount3` is not installed
+/// - Filesystem errors (Permissions)
+/// - Invalid Repository/Package manifest
+pub async fn mount_package(
+    repo_path: &Path,
+    package_manifest: PackageManifest,
+    mount_path: &Path,
+    chunk_store_path: &Path,
+) -> Result<Vec<FailedRead>> {
+    let repo_manifest = read_manifest(repo_path)?;
+    import_shared_chunks(&package_manifest.chunks, chunk_store_path)?;
+
+    let filesystem = PackageFs {
+        nodes: build_nodes(&package_manifest.chunks),
+        chunks: package_manifest.chunks,
+        chunk_store_path: chunk_store_path.to_path_buf(),
+        mirrors: get_mirrors(repo_path, &repo_manifest)?,
+        hash_kind: repo_manifest.hash_kind,
+        auth: get_repo_auth_for(repo_path)?,
+        runtime: Handle::current(),
+        uid: getuid().as_raw(),
+        gid: getgid().as_raw(),
+        last_chunk: Mutex::new(None),
+        fetching: Mutex::new(HashSet::new()),
+        fetched: Condvar::new(),
+        failed_reads: Mutex::new(Vec::new()),
+    };
+
+    let mount_path = mount_path.to_path_buf();
+    // Reading from /dev/fuse blocks, and fetching chunks needs the runtime from another thread
+    tokio::task::spawn_blocking(move || {
+        let (device, mounted) = mount_device(&mount_path)?;
+        let served = filesystem.serve(&device);
+        drop(device);
+        mounted.unmount(&mount_path);
+        served?;
+
+        Ok(filesystem
+            .failed_reads
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner))
+    })
+    .await?
+}
+
+/// A file in a mounted package that could not be read, and why
+#[derive(Debug)]
+pub struct FailedRead {
+    pub path: PathBuf,
+    pub error: anyhow::Error,
+}
+
+/// How the filesystem was mounted, so it can be unmounted the same way
+enum Mounted {
+    Direct,
+    /// `fusermount3` unmounts once the socket closes, see `auto_unmount`
+    Fusermount(Child, OwnedFd),
+}
+
+impl Mounted {
+    fn unmount(self, mount_path: &Path) {
+        match self {
+            // Already gone if it was unmounted from outside
+            Self::Direct => drop(umount2(mount_path, MntFlags::MNT_DETACH)),
+            Self::Fusermount(mut child, socket) => {
+                drop(socket);
+                drop(child.wait());
+            }
+        }
+    }
+}
+
+/// Mounts an empty FUSE filesystem, returning the device its requests are read from
+fn mount_device(mount_path: &Path) -> Result<(File, Mounted)> {
+    if let Ok(device) = OpenOptions::new().read(true).write(true).open("/dev/fuse") {
+        let options = format!(
+            "fd={},rootmode=40000,user_id={},group_id={},default_permissions",
+            device.as_raw_fd(),
+            getuid(),
+            getgid()
+        );
+
+        if mount(
+            Some("flint"),
+            mount_path,
+            Some("fuse.flint"),
+            MsFlags::MS_RDONLY | MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+            Some(options.as_str()),
+        )
+        .is_ok()
+        {
+            return Ok((device, Mounted::Direct));
+        }
+    }
+
+    // Not root, fusermount3 mounts it and passes the device back over a socket
+    let (socket, theirs) = socketpair(
+        AddressFamily::Unix,
+        SockType::Stream,
+        None,
+        SockFlag::empty(),
+    )?;
+    let child = Command::new("fusermount3")
+        .env("_FUSE_COMMFD", theirs.as_raw_fd().to_string())
+        .args([
+            "-o",
+            "ro,nosuid,nodev,default_permissions,auto_unmount,fsname=flint,subtype=flint",
+            "--",
+        ])
+        .arg(mount_path)
+        .spawn()
+        .with_context(|| "Could not mount, run as root or install fusermount3.")?;
+    drop(theirs);
+
+    let mut byte = [0];
+    let mut iov = [IoSliceMut::new(&mut byte)];
+    let mut cmsg_buffer = nix::cmsg_space!(RawFd);
+    let message = recvmsg::<()>(
+        socket.as_raw_fd(),
+        &mut iov,
+        Some(&mut cmsg_buffer),
+        MsgFlags::empty(),
+    )?;
+    let fd = message
+        .cmsgs()?
+        .find_map(|cmsg| match cmsg {
+            ControlMessageOwned::ScmRights(fds) => fds.first().copied(),
+            _ => None,
+        })
+        .context("fusermount3 failed to mount.")?;
+
+    // Moves the received descriptor into one owned by a File, so nothing else has to own a raw fd
+    let device = File::open("/dev/null")?;
+    dup3(fd, device.as_raw_fd(), OFlag::O_CLOEXEC)?;
+    close(fd)?;
+
+    Ok((device, Mounted::Fusermount(child, socket)))
+}
+
+enum Node {
+    Dir {
+        parent: u64,
+        children: BTreeMap<OsString, u64>,
+    },
+    /// Index into the package's chunks
+    File(usize),
+}
+
+/// Turns a package's chunks into inodes, the root is `ROOT_ID`, and each inode is its index + 1
+fn build_nodes(chunks: &[Chunk]) -> Vec<Node> {
+    let mut nodes = vec![Node::Dir {
+        parent: ROOT_ID,
+        children: BTreeMap::new(),
+    }];
+
+    for (index, chunk) in chunks.iter().enumerate() {
+        let names: Vec<&OsStr> = chunk
+            .path()
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(name) => Some(name),
+                _ => None,
+            })
+            .collect();
+        let Some((file_name, dirs)) = names.split_last() else {
+            continue;
+        };
+
+        let mut parent = ROOT_ID;
+        for name in dirs {
+            let next = nodes.len() as u64 + 1;
+            let Node::Dir { children, .. } = &mut nodes[inode_index(parent)] else {
+                break;
+            };
+            let child = *children.entry((*name).to_os_string()).or_insert(next);
+            if child == next {
+                nodes.push(Node::Dir {
+                    parent,
+                    children: BTreeMap::new(),
+                });
+            }
+            parent = child;
+        }
+
+        let next = nodes.len() as u64 + 1;
+        if let Node::Dir { children, .. } = &mut nodes[inode_index(parent)]
+            && !children.contains_key(*file_name)
+        {
+            children.insert((*file_name).to_os_string(), next);
+            nodes.push(Node::File(index));
+        }
+    }
+
+    nodes
+}
+
+#[allow(clippy::cast_possible_truncation)]
+const fn inode_index(inode: u64) -> usize {
+    (inode - 1) as usize
+}
+
+enum Reply {
+    /// FORGET and INTERRUPT are never answered
+    None,
+    Data(Vec<u8>),
+    Error(Errno),
+}
+
+struct PackageFs {
+    nodes: Vec<Node>,
+    chunks: Vec<Chunk>,
+    chunk_store_path: PathBuf,
+    mirrors: Vec<String>,
+    hash_kind: HashKind,
+    auth: Option<RepoAuth>,
+    runtime: Handle,
+    uid: u32,
+    gid: u32,
+    /// Hash and contents of the last compressed chunk read
+    last_chunk: Mutex<Option<(String, Vec<u8>)>>,
+    /// Filenames of the chunks being fetched, `fetched` is notified when one is done
+    fetching: Mutex<HashSet<String>>,
+    fetched: Condvar,
+    failed_reads: Mutex<Vec<FailedRead>>,
+}
+
+impl PackageFs {
+    /// Answers requests from the kernel until the filesystem is unmounted.
+    /// Requests are read here and answered by `WORKERS` threads, which may block on fetching chunks.
+    fn serve(&self, device: &File) -> Result<()> {
+        let (sender, receiver) = mpsc::channel();
+        let receiver = Mutex::new(receiver);
+
+        thread::scope(|scope| -> Result<()> {
+            let workers: Vec<_> = (0..WORKERS)
+                .map(|_| scope.spawn(|| self.answer_requests(device, &receiver)))
+                .collect();
+
+            // Stops the workers once the requests read so far are answered
+            let read = read_requests(device, &sender, &workers);
+            drop(sender);
+            for worker in workers {
+                worker
+                    .join()
+                    .map_err(|_| anyhow::anyhow!("FUSE worker thread panicked"))??;
+            }
+
+            read
+        })
+    }
+
+    /// Answers requests from `read_requests` until there are none left
+    fn answer_requests(
+        &self,
+        mut device: &File,
+        requests: &Mutex<Receiver<Vec<u8>>>,
+    ) -> Result<()> {
+        loop {
+            // Only one worker waits on the channel at a time, the rest wait for the lock
+            let Ok(request) = requests
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .recv()
+            else {
+                return Ok(());
+            };
+
+            let unique = u64_at(&request, 8);
+            let reply = self.handle(u32_at(&request, 4), u64_at(&request, 16), &request[40..]);
+
+            let (error, data) = match reply {
+                Reply::None => continue,
+                Reply::Data(data) => (0, data),
+                Reply::Error(errno) => (-(errno as i32), Vec::new()),
+            };
+            let mut out = Vec::with_capacity(16 + data.len());
+            push_u32(&mut out, u32::try_from(16 + data.len())?);
+            out.extend_from_slice(&error.to_le_bytes());
+            push_u64(&mut out, unique);
+            out.extend_from_slice(&data);
+
+            match device.write_all(&out) {
+                // The request was interrupted, and nobody is waiting for the reply anymore
+                Err(err) if err.raw_os_error().map(Errno::from_raw) == Some(Errno::ENOENT) => {}
+                result => result?,
+            }
+        }
+    }
+
+    /// Answers a single request, `body` is everything after the request header
+    fn handle(&self, opcode: u32, inode: u64, body: &[u8]) -> Reply {
+        let result = match opcode {
+            FUSE_INIT => Ok(Self::init(body)),
+            FUSE_LOOKUP => self.lookup(inode, body),
+            FUSE_FORGET | FUSE_BATCH_FORGET | FUSE_INTERRUPT => return Reply::None,
+            FUSE_GETATTR => self.attr(inode).map(|attr| {
+                let mut out = Vec::new();
+                push_u64(&mut out, TTL_SECONDS);
+                push_u32(&mut out, 0);
+                push_u32(&mut out, 0);
+                out.extend_from_slice(&attr);
+                out
+            }),
+            FUSE_OPEN => self.open(inode, body, false),
+            FUSE_OPENDIR => self.open(inode, body, true),
+            FUSE_READ => self.read(inode, u64_at(body, 8), u32_at(body, 16)),
+            FUSE_READDIR => self.readdir(inode, u64_at(body, 8), u32_at(body, 16)),
+            FUSE_RELEASE | FUSE_RELEASEDIR | FUSE_FLUSH | FUSE_DESTROY => Ok(Vec::new()),
+            FUSE_STATFS => {
+                let mut out = Vec::new();
+                for _ in 0..5 {
+                    push_u64(&mut out, 0);
+                }
+                push_u32(&mut out, 4096);
+                push_u32(&mut out, 255);
+                push_u32(&mut out, 4096);
+                out.resize(80, 0);
+                Ok(out)
+            }
+            _ => Err(Errno::ENOSYS),
+        };
+
+        match result {
+            Ok(data) => Reply::Data(data),
+            Err(errno) => Reply::Error(errno),
+        }
+    }
+
+    fn init(body: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        push_u32(&mut out, 7);
+        push_u32(&mut out, 31);
+        push_u32(&mut out, u32_at(body, 8));
+        push_u32(&mut out, 0);
+        // max_background, congestion_threshold
+        out.extend_from_slice(&16u16.to_le_bytes());
+        out.extend_from_slice(&12u16.to_le_bytes());
+        push_u32(&mut out, MAX_WRITE);
+        // time_gran, and everything after it is unused
+        push_u32(&mut out, 1);
+        out.resize(64, 0);
+        out
+    }
+
+    fn node(&self, inode: u64) -> Result<&Node, Errno> {
+        if inode == 0 {
+            return Err(Errno::ENOENT);
+        }
+        self.nodes.get(inode_index(inode)).ok_or(Errno::ENOENT)
+    }
+
+    fn lookup(&self, parent: u64, body: &[u8]) -> Result<Vec<u8>, Errno> {
+        let name = OsStr::from_bytes(body.split(|byte| *byte == 0).next().unwrap_or_default());
+        let Node::Dir { children, .. } = self.node(parent)? else {
+            return Err(Errno::ENOTDIR);
+        };
+        let inode = *children.get(name).ok_or(Errno::ENOENT)?;
+
+        let mut out = Vec::new();
+        push_u64(&mut out, inode);
+        push_u64(&mut out, 0);
+        push_u64(&mut out, TTL_SECONDS);
+        push_u64(&mut out, TTL_SECONDS);
+        push_u32(&mut out, 0);
+        push_u32(&mut out, 0);
+        out.extend_from_slice(&self.attr(inode)?);
+        Ok(out)
+    }
+
+    /// The kernel's `fuse_attr` for an inode
+    fn attr(&self, inode: u64) -> Result<Vec<u8>, Errno> {
+        let (size, mode, nlink) = match self.node(inode)? {
+            Node::Dir { .. } => (0, S_IFDIR | 0o755, 2),
+            Node::File(index) => {
+                let chunk = &self.chunks[*index];
+                (
+                    self.file_size(chunk)?,
+                    S_IFREG | (chunk.permissions() & 0o7777),
+                    1,
+                )
+            }
+        };
+
+        let mut out = Vec::new();
+        push_u64(&mut out, inode);
+        push_u64(&mut out, size);
+        push_u64(&mut out, size.div_ceil(512));
+        // atime, mtime, ctime and their nanoseconds are all the epoch, like an installed tree
+        out.resize(out.len() + 3 * 8 + 3 * 4, 0);
+        push_u32(&mut out, mode);
+        push_u32(&mut out, nlink);
+        push_u32(&mut out, self.uid);
+        push_u32(&mut out, self.gid);
+        // rdev, blksize, flags
+        push_u32(&mut out, 0);
+        push_u32(&mut out, 4096);
+        push_u32(&mut out, 0);
+        Ok(out)
+    }
+
+    /// Older manifests only know sizes to the kilobyte, so those chunks are fetched to be listed
+    fn file_size(&self, chunk: &Chunk) -> Result<u64, Errno> {
+        if chunk.has_exact_size() {
+            return Ok(chunk.size());
+        }
+
+        let chunk_path = self.fetch_chunk(chunk)?;
+        if is_compressed_file(&chunk_path).map_err(|_| Errno::EIO)? {
+            return self.with_contents(chunk, &chunk_path, |contents| contents.len() as u64);
+        }
+
+        Ok(fs::metadata(chunk_path)
+            .map_err(|err| io_errno(&err))?
+            .len())
+    }
+
+    fn open(&self, inode: u64, body: &[u8], dir: bool) -> Result<Vec<u8>, Errno> {
+        match (self.node(inode)?, dir) {
+            (Node::Dir { .. }, false) => return Err(Errno::EISDIR),
+            (Node::File(_), true) => return Err(Errno::ENOTDIR),
+            _ => {}
+        }
+        if u32_at(body, 0) & OFlag::O_ACCMODE.bits().cast_unsigned() != 0 {
+            return Err(Errno::EROFS);
+        }
+
+        let mut out = Vec::new();
+        push_u64(&mut out, 0);
+        push_u32(&mut out, FOPEN_KEEP_CACHE);
+        push_u32(&mut out, 0);
+        Ok(out)
+    }
+
+    fn read(&self, inode: u64, offset: u64, size: u32) -> Result<Vec<u8>, Errno> {
+        let Node::File(index) = self.node(inode)? else {
+            return Err(Errno::EISDIR);
+        };
+
+        let chunk = &self.chunks[*index];
+        let chunk_path = self.fetch_chunk(chunk)?;
+        if is_compressed_file(&chunk_path).map_err(|_| Errno::EIO)? {
+            return self.with_contents(chunk, &chunk_path, |contents| {
+                let start = usize::try_from(offset)
+                    .map_or(contents.len(), |offset| offset.min(contents.len()));
+                let end = start.saturating_add(size as usize).min(contents.len());
+                contents[start..end].to_vec()
+            });
+        }
+
+        let mut file = File::open(chunk_path).map_err(|err| io_errno(&err))?;
+        file.seek(SeekFrom::Start(offset))
+            .map_err(|err| io_errno(&err))?;
+        let mut data = Vec::with_capacity(size as usize);
+        file.take(u64::from(size))
+            .read_to_end(&mut data)
+            .map_err(|err| io_errno(&err))?;
+        Ok(data)
+    }
+
+    fn readdir(&self, inode: u64, offset: u64, size: u32) -> Result<Vec<u8>, Errno> {
+        let Node::Dir { parent, children } = self.node(inode)? else {
+            return Err(Errno::ENOTDIR);
+        };
+
+        let entries = [(OsStr::new("."), inode), (OsStr::new(".."), *parent)]
+            .into_iter()
+            .chain(
+                children
+                    .iter()
+                    .map(|(name, inode)| (name.as_os_str(), *inode)),
+            );
+
+        let mut out = Vec::new();
+        for (index, (name, inode)) in entries
+            .enumerate()
+            .skip(usize::try_from(offset).unwrap_or(usize::MAX))
+        {
+            let kind = match self.node(inode)? {
+                Node::Dir { .. } => S_IFDIR,
+                Node::File(_) => S_IFREG,
+            } >> 12;
+            let name = name.as_bytes();
+            let name_len = u32::try_from(name.len()).map_err(|_| Errno::ENAMETOOLONG)?;
+            let len = (24 + name.len()).next_multiple_of(8);
+            if out.len() + len > size as usize {
+                break;
+            }
+
+            push_u64(&mut out, inode);
+            push_u64(&mut out, index as u64 + 1);
+            push_u32(&mut out, name_len);
+            push_u32(&mut out, kind);
+            out.extend_from_slice(name);
+            out.resize(out.len() + len - 24 - name.len(), 0);
+        }
+        Ok(out)
+    }
+
+    /// The path of a chunk in the chunk store, fetching it first if it is missing
+    fn fetch_chunk(&self, chunk: &Chunk) -> Result<PathBuf, Errno> {
+        let chunk_name = chunk.filename();
+        let chunk_path = self.chunk_store_path.join(&chunk_name);
+        if chunk_path.exists() {
+            return Ok(chunk_path);
+        }
+
+        // Workers fetching the same chunk would write the same temporary file, so the rest wait for the first
+        let mut fetching = self.fetching.lock().unwrap_or_else(PoisonError::into_inner);
+        while fetching.contains(&chunk_name) {
+            fetching = self
+                .fetched
+                .wait(fetching)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        if chunk_path.exists() {
+            return Ok(chunk_path);
+        }
+        fetching.insert(chunk_name.clone());
+        drop(fetching);
+
+        let installed = self.runtime.block_on(install_chunks(
+            &[chunk],
+            &self.mirrors,
+            self.hash_kind,
+            &self.chunk_store_path,
+            self.auth.as_ref(),
+            &|_| {},
+        ));
+        self.fetching
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&chunk_name);
+        self.fetched.notify_all();
+
+        installed.map_err(|err| self.read_failed(chunk, err))?;
+        Ok(chunk_path)
+    }
+
+    /// Keeps why a file could not be read to return once unmounted, the reader only gets `EIO`
+    fn read_failed(&self, chunk: &Chunk, error: anyhow::Error) -> Errno {
+        let mut failed_reads = self
+            .failed_reads
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        // Files are read in many parts, each failing the same way
+        if !failed_reads
+            .iter()
+            .any(|failed| failed.path == chunk.path())
+        {
+            failed_reads.push(FailedRead {
+                path: chunk.path().to_path_buf(),
+                error,
+            });
+        }
+
+        Errno::EIO
+    }
+
+    /// Runs `f` on a compressed chunk's contents.
+    /// Files are read in many small parts, so the last chunk decompressed is kept for the next read.
+    fn with_contents<T>(
+        &self,
+        chunk: &Chunk,
+        chunk_path: &Path,
+        f: impl FnOnce(&[u8]) -> T,
+    ) -> Result<T, Errno> {
+        if let Some((hash, contents)) = self
+            .last_chunk
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_ref()
+            && hash == chunk.hash()
+        {
+            return Ok(f(contents));
+        }
+
+        // Decompressed without holding the lock, so other workers' reads aren't held up
+        let contents = read_chunk(chunk_path).map_err(|err| self.read_failed(chunk, err))?;
+        let result = f(&contents);
+        *self
+            .last_chunk
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Some((chunk.hash().to_string(), contents));
+
+        Ok(result)
+    }
+}
+
+/// Reads requests from the kernel for the workers, until the filesystem is unmounted or a worker stopped
+fn read_requests(
+    mut device: &File,
+    requests: &Sender<Vec<u8>>,
+    workers: &[ScopedJoinHandle<Result<()>>],
+) -> Result<()> {
+    let mut buffer = vec![0; MAX_WRITE as usize + 4096];
+
+    // Workers only stop early when a reply can't be written
+    while !workers.iter().any(ScopedJoinHandle::is_finished) {
+        let len = match device.read(&mut buffer) {
+            Ok(len) => len,
+            // Interrupted before it was read, try again
+            Err(err)
+                if matches!(
+                    err.raw_os_error().map(Errno::from_raw),
+                    Some(Errno::ENOENT | Errno::EINTR | Errno::EAGAIN)
+                ) =>
+            {
+                continue;
+            }
+            Err(err) if err.raw_os_error().map(Errno::from_raw) == Some(Errno::ENODEV) => {
+                return Ok(());
+            }
+            Err(err) => return Err(err.into()),
+        };
+        if len < 40 {
+            bail!("Short FUSE request.")
+        }
+
+        let opcode = u32_at(&buffer, 4);
+        requests.send(buffer[..len].to_vec())?;
+        if opcode == FUSE_DESTROY {
+            return Ok(());
+        }
+    }
+
+    Ok(())
+}
+
+fn io_errno(err: &std::io::Error) -> Errno {
+    err.raw_os_error().map_or(Errno::EIO, Errno::from_raw)
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    bytes.get(offset..offset + 4).map_or(0, |bytes| {
+        u32::from_le_bytes(bytes.try_into().unwrap_or_default())
+    })
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    bytes.get(offset..offset + 8).map_or(0, |bytes| {
+        u64::from_le_bytes(bytes.try_into().unwrap_or_default())
+    })
+}
+
+fn push_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_u64(out: &mut Vec<u8>, value: u64) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunks::save_tree;
+    use std::fs;
+    use temp_dir::TempDir;
+
+    #[tokio::test]
+    async fn test_package_fs() -> Result<()> {
+        let tree = TempDir::new()?;
+        fs::create_dir_all(tree.path().join("bin"))?;
+        fs::write(tree.path().join("bin/hello"), "hello world")?;
+        fs::write(tree.path().join("README"), "readme")?;
+        // Compressed in the chunk store
+        fs::write(tree.path().join("bin/notes"), "notes ".repeat(100))?;
+        let chunk_store = TempDir::new()?;
+        let chunks = save_tree(tree.path(), chunk_store.path(), HashKind::Blake3)?;
+
+        let filesystem = PackageFs {
+            nodes: build_nodes(&chunks),
+            chunks,
+            chunk_store_path: chunk_store.path().to_path_buf(),
+            mirrors: Vec::new(),
+            hash_kind: HashKind::Blake3,
+            auth: None,
+            runtime: Handle::current(),
+            uid: 0,
+            gid: 0,
+            last_chunk: Mutex::new(None),
+            fetching: Mutex::new(HashSet::new()),
+            fetched: Condvar::new(),
+            failed_reads: Mutex::new(Vec::new()),
+        };
+        let data = |reply| match reply {
+            Reply::Data(data) => Ok(data),
+            Reply::Error(errno) => Err(errno),
+            Reply::None => Err(Errno::EINVAL),
+        };
+
+        let bin = data(filesystem.handle(FUSE_LOOKUP, ROOT_ID, b"bin\0"))?;
+        let bin = u64_at(&bin, 0);
+        let hello = data(filesystem.handle(FUSE_LOOKUP, bin, b"hello\0"))?;
+        // nodeid, then the attr's size after 40 bytes of entry_out and 8 of ino
+        assert_eq!(u64_at(&hello, 48), 11);
+        let hello = u64_at(&hello, 0);
+
+        let mut read_in = Vec::new();
+        push_u64(&mut read_in, 0);
+        push_u64(&mut read_in, 6);
+        push_u32(&mut read_in, 4096);
+        assert_eq!(
+            data(filesystem.handle(FUSE_READ, hello, &read_in))?,
+            b"world"
+        );
+
+        let notes = data(filesystem.handle(FUSE_LOOKUP, bin, b"notes\0"))?;
+        assert_eq!(u64_at(&notes, 48), 600);
+        let notes = u64_at(&notes, 0);
+        read_in[8..16].copy_from_slice(&594u64.to_le_bytes());
+        assert_eq!(
+            data(filesystem.handle(FUSE_READ, notes, &read_in))?,
+            b"notes "
+        );
+
+        assert_eq!(
+            data(filesystem.handle(FUSE_LOOKUP, ROOT_ID, b"missing\0")),
+            Err(Errno::ENOENT)
+        );
+        assert_eq!(
+            data(filesystem.handle(FUSE_OPEN, hello, &2u32.to_le_bytes())),
+            Err(Errno::EROFS)
+        );
+
+        // ".", "..", README and bin
+        read_in[8..16].copy_from_slice(&0u64.to_le_bytes());
+        let entries = data(filesystem.handle(FUSE_READDIR, ROOT_ID, &read_in))?;
+        let mut names = Vec::new();
+        let mut offset = 0;
+        while offset < entries.len() {
+            let len = u32_at(&entries, offset + 16) as usize;
+            names.push(
+                String::from_utf8_lossy(&entries[offset + 24..offset + 24 + len]).to_string(),
+            );
+            offset += (24 + len).next_multiple_of(8);
+        }
+        assert_eq!(names, vec![".", "..", "README", "bin"]);
+
+        // Missing from the chunk store, with no mirrors to fetch it from
+        fs::remove_file(chunk_store.path().join(filesystem.chunks[0].filename()))?;
+        let missing = filesystem.chunks[0].path().to_path_buf();
+        // Fetching blocks on the runtime, like the workers do
+        let failed_reads = tokio::task::spawn_blocking(move || -> Result<Vec<FailedRead>> {
+            let mut file = ROOT_ID;
+            for name in filesystem.chunks[0].path() {
+                let mut name = name.as_bytes().to_vec();
+                name.push(0);
+                file = u64_at(&data(filesystem.handle(FUSE_LOOKUP, file, &name))?, 0);
+            }
+            for _ in 0..2 {
+                assert_eq!(
+                    data(filesystem.handle(FUSE_READ, file, &read_in)),
+                    Err(Errno::EIO)
+                );
+            }
+            Ok(filesystem.failed_reads.into_inner()?)
+        })
+        .await??;
+        assert_eq!(failed_reads.len(), 1);
+        assert_eq!(failed_reads[0].path, missing);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,201 @@
+use anyhow::{Result, bail};
+use std::{
+    collections::HashSet,
+    fmt::Write,
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::repo::{
+    get_all_installed_packages,
+    manifest_io::{atomic_replace, has_manifest},
+};
+
+/// Kept next to the quicklaunch directory, for shell profiles to source
+const ENV_SCRIPT: &str = "env.sh";
+
+/// A POSIX shell script sourcing the `env_scripts` of installed packages, eg: for `eval "$(flint env)"`.
+///
+/// Each snippet sees the directory of its package as `FLINT_PACKAGE_PATH`, and is skipped if it
+/// isn't there, eg: in an image that is not mounted, or isn't inside its package.
+/// With `package_ids`, only those packages are included, by id or alias.
+/// Entries of `repos_path` that aren't Repositories are skipped.
+///
+/// # Errors
+///
+/// - A package of `package_ids` is not installed
+/// - Filesystem errors (Permissions)
+/// - Bad Repositories
+pub fn env_script(repos_path: &Path, package_ids: &[String]) -> Result<String> {
+    let mut script = String::new();
+    let mut found = HashSet::new();
+
+    let mut repos: Vec<_> = fs::read_dir(repos_path)?.collect::<Result<_, _>>()?;
+    repos.sort_by_key(fs::DirEntry::file_name);
+
+    for repo in repos {
+        let repo_path = repo.path();
+        if !has_manifest(&repo_path) {
+            continue;
+        }
+
+        for package in get_all_installed_packages(&repo_path)? {
+            if !package_ids.is_empty() {
+                let Some(id) = package_ids
+                    .iter()
+                    .find(|id| **id == package.id || package.aliases.contains(id))
+                else {
+                    continue;
+                };
+                found.insert(id.as_str());
+            }
+
+            let package_path = repo_path.join("installed").join(&package.id);
+            for env_script in &package.env_scripts {
+                if !is_valid_env_script(env_script) {
+                    continue;
+                }
+                let path = package_path.join(env_script);
+
+                // Writing to a String can't fail
+                let _ = writeln!(script, "FLINT_PACKAGE_PATH={}", quote(&package_path));
+                let _ = writeln!(script, "if [ -r {0} ]; then . {0}; fi", quote(&path));
+            }
+        }
+    }
+
+    if let Some(missing) = package_ids.iter().find(|id| !found.contains(id.as_str())) {
+        bail!("{missing} is not installed.")
+    }
+
+    if !script.is_empty() {
+        script.push_str("unset FLINT_PACKAGE_PATH\n");
+    }
+
+    Ok(script)
+}
+
+/// Whether `env_script` is a relative path that stays inside its package, so without `..`
+#[must_use]
+pub fn is_valid_env_script(env_script: &Path) -> bool {
+    env_script.components().next().is_some()
+        && env_script
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+/// Where [`update_env_script`] writes the script for shell profiles, eg: `~/.local/share/flint/env.sh`
+#[must_use]
+pub fn env_script_path(quicklaunch_path: &Path) -> PathBuf {
+    quicklaunch_path.with_file_name(ENV_SCRIPT)
+}
+
+/// Writes the [`env_script`] of every installed package to [`env_script_path`],
+/// so a profile can source it without running Flint on every login.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+/// - Bad Repositories
+pub fn update_env_script(repos_path: &Path, quicklaunch_path: &Path) -> Result<()> {
+    let path = env_script_path(quicklaunch_path);
+    let Some(dir) = path.parent() else {
+        bail!("Invalid quicklaunch path {}", quicklaunch_path.display())
+    };
+
+    let script = format!(
+        "# Generated by Flint, sources the env_scripts of installed packages.\n{}",
+        env_script(repos_path, &[])?
+    );
+
+    atomic_replace(dir, ENV_SCRIPT, script.as_bytes())
+}
+
+/// Quotes a path for a POSIX shell
+fn quote(path: &Path) -> String {
+    format!("'{}'", path.to_string_lossy().replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        repo::{
+            InstallMeta, PackageManifest, create_repo, insert_package, installed::reindex_installed,
+        },
+        utils::platform::symlink_dir,
+    };
+    use std::process::Command;
+    use temp_dir::TempDir;
+
+    #[test]
+    fn test_env_script() -> Result<()> {
+        let root = TempDir::new()?;
+        let repos_path = &root.path().join("repos");
+        let repo_path = &repos_path.join("repo's");
+        create_repo(repo_path, Some(repo_path))?;
+
+        let package = PackageManifest {
+            id: "hello".into(),
+            aliases: vec!["hi".into()],
+            env_scripts: vec!["etc/profile.d/hello.sh".into(), "missing.sh".into()],
+            ..Default::default()
+        };
+        insert_package(&package, repo_path, Some(repo_path))?;
+        // Not a Repository
+        fs::write(repos_path.join("notes.txt"), "")?;
+
+        let version_path = &repo_path.join("versions/hello-aaa");
+        fs::create_dir_all(version_path.join("etc/profile.d"))?;
+        fs::write(
+            version_path.join("etc/profile.d/hello.sh"),
+            "if [ -z \"$GREETING\" ]; then export GREETING=\"hello from $FLINT_PACKAGE_PATH\"; fi\n",
+        )?;
+        fs::write(
+            version_path.join("install.meta"),
+            serde_yaml::to_string(&InstallMeta {
+                package,
+                dev_install: false,
+                installed_at: None,
+                updated_at: None,
+                disk_size: None,
+                disk_bytes: None,
+                rewritten: Vec::new(),
+            })?,
+        )?;
+        fs::create_dir(repo_path.join("installed"))?;
+        symlink_dir(
+            Path::new("../versions/hello-aaa"),
+            &repo_path.join("installed/hello"),
+        )?;
+        reindex_installed(repo_path, "hello")?;
+
+        let script = env_script(repos_path, &["hi".into()])?;
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(format!("{script}echo \"$GREETING\""))
+            .output()?;
+        assert_eq!(
+            String::from_utf8(output.stdout)?.trim(),
+            format!("hello from {}", repo_path.join("installed/hello").display())
+        );
+
+        assert!(env_script(repos_path, &["world".into()]).is_err());
+
+        for env_script in ["/etc/profile", "../escape.sh", "etc/../../escape.sh", ""] {
+            assert!(!is_valid_env_script(Path::new(env_script)), "{env_script}");
+        }
+        let escaping = PackageManifest {
+            id: "escaping".into(),
+            env_scripts: vec!["../../etc/profile".into()],
+            ..Default::default()
+        };
+        assert!(insert_package(&escaping, repo_path, Some(repo_path)).is_err());
+
+        let quicklaunch = &root.path().join("quicklaunch");
+        update_env_script(repos_path, quicklaunch)?;
+        assert!(fs::read_to_string(env_script_path(quicklaunch))?.contains("hello.sh"));
+
+        Ok(())
+    }
+}
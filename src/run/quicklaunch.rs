@@ -2,15 +2,24 @@ use anyhow::{Context, Result, anyhow};
 use std::{
     collections::HashSet,
     env::current_exe,
+    ffi::OsStr,
     fs,
-    os::unix::fs::PermissionsExt,
     path::{Path, PathBuf},
 };
 
-use crate::repo::read_manifest;
+use crate::{
+    config::{get_completions_dir, read_config},
+    repo::{manifest_io::has_manifest, read_manifest},
+    run::{completions::export_completions, profile::update_env_script},
+    utils::platform::set_mode,
+};
 
 /// Removes all nonexistant Quicklaunch items, and adds any missing ones.
 ///
+/// Scripts are named after their command, so completions registered for it keep working,
+/// shell completions of packages are exported if `export_completions` is set,
+/// and the `env.sh` for shell profiles is rewritten.
+///
 /// # Errors
 ///
 /// - Filesystem
@@ -20,16 +29,24 @@ pub fn update_quicklaunch(repos_path: &Path, quicklaunch_path: &Path) -> Result<
 
     for entry in repos_path.read_dir()? {
         let repo_path = entry?.path();
+        if !has_manifest(&repo_path) {
+            continue;
+        }
 
         let manifest = read_manifest(&repo_path)?;
 
         // Write all quicklaunch scripts. This has the unintended sideffect of rewriting existing
         // quicklaunch scripts.
         for package in manifest.packages {
-            for entrypoint in package.commands {
-                let command = entrypoint
-                    .file_name()
+            for package_command in package.commands {
+                let command = package_command
+                    .name()
                     .ok_or_else(|| anyhow!("Could not get entrypoint name"))?;
+                // Names come from the manifest, and must stay inside the quicklaunch directory
+                if command.starts_with('.') || command.contains(['/', '\\']) {
+                    continue;
+                }
+                let command = OsStr::new(&command);
 
                 allowed.insert(command.to_owned());
 
@@ -43,7 +60,7 @@ pub fn update_quicklaunch(repos_path: &Path, quicklaunch_path: &Path) -> Result<
                     .with_context(|| "Could not get current executable path")?
                     .canonicalize()?;
                 let quicklaunch_script = format!(
-                    "#!/bin/bash\n{} run {} -- {} $@",
+                    "#!/bin/bash\nexec {} run {} -- {} \"$@\"",
                     executable_path.display(),
                     package.id,
                     command.display()
@@ -52,10 +69,7 @@ pub fn update_quicklaunch(repos_path: &Path, quicklaunch_path: &Path) -> Result<
                 fs::write(tmp_path, quicklaunch_script)?;
                 fs::rename(tmp_path, &path)?;
 
-                // chmod 755
-                let mut perms = fs::metadata(&path)?.permissions();
-                perms.set_mode(0o755);
-                fs::set_permissions(&path, perms)?;
+                set_mode(&path, 0o755)?;
             }
         }
     }
@@ -69,5 +83,11 @@ pub fn update_quicklaunch(repos_path: &Path, quicklaunch_path: &Path) -> Result<
         }
     }
 
-    Ok(())
+    export_completions(
+        repos_path,
+        &get_completions_dir(quicklaunch_path)?,
+        read_config(None)?.export_completions,
+    )?;
+
+    update_env_script(repos_path, quicklaunch_path)
 }
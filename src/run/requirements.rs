@@ -0,0 +1,201 @@
+use anyhow::{Result, bail};
+use std::{cmp::Ordering, process::Command};
+
+use crate::repo::Requirements;
+
+/// Errors out if this machine can't run a package, explaining what is missing.
+/// Checked on install and run, so packages fail early instead of with `SIGILL` or linker errors.
+///
+/// # Errors
+///
+/// - The CPU lacks features the package was built for
+/// - The system's glibc or macOS is older than the package needs
+pub fn check_requirements(package_id: &str, requirements: &Requirements) -> Result<()> {
+    if let Some(level) = &requirements.cpu {
+        let missing = missing_cpu_features(level)?;
+
+        if !missing.is_empty() {
+            bail!(
+                "{package_id} needs a {level} CPU, this one lacks: {}. Use a build for older CPUs, or another machine.",
+                missing.join(", ")
+            )
+        }
+    }
+
+    if let Some(min_glibc) = &requirements.min_glibc {
+        let Some(glibc) = host_glibc_version() else {
+            bail!(
+                "{package_id} needs glibc {min_glibc} or newer, but this system does not use glibc."
+            )
+        };
+
+        if compare_versions(&glibc, min_glibc) == Ordering::Less {
+            bail!(
+                "{package_id} needs glibc {min_glibc} or newer, this system has {glibc}. Update the system, or use a build linked against an older glibc."
+            )
+        }
+    }
+
+    if let Some(min_macos) = &requirements.min_macos {
+        let Some(macos) = host_macos_version() else {
+            bail!("{package_id} needs macOS {min_macos} or newer.")
+        };
+
+        if compare_versions(&macos, min_macos) == Ordering::Less {
+            bail!("{package_id} needs macOS {min_macos} or newer, this system has {macos}.")
+        }
+    }
+
+    Ok(())
+}
+
+/// Compares dotted versions numerically, eg: `2.9` < `2.34`. Missing parts count as 0.
+#[must_use]
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let parse = |version: &str| -> Vec<u64> {
+        version
+            .split('.')
+            .map(|part| part.trim().parse().unwrap_or(0))
+            .collect()
+    };
+    let (a, b) = (parse(a), parse(b));
+
+    for index in 0..a.len().max(b.len()) {
+        let ordering = a.get(index).unwrap_or(&0).cmp(b.get(index).unwrap_or(&0));
+
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+
+    Ordering::Equal
+}
+
+/// CPU features of an x86-64 microarchitecture level this CPU lacks.
+fn missing_cpu_features(level: &str) -> Result<Vec<&'static str>> {
+    let levels: &[&[&'static str]] = match level {
+        "x86-64" | "x86-64-v1" => &[],
+        "x86-64-v2" => &[&X86_64_V2],
+        "x86-64-v3" => &[&X86_64_V2, &X86_64_V3],
+        "x86-64-v4" => &[&X86_64_V2, &X86_64_V3, &X86_64_V4],
+        _ => bail!("Unknown CPU requirement {level}, expected x86-64-v1 to x86-64-v4."),
+    };
+
+    if !cfg!(target_arch = "x86_64") {
+        bail!("This package is built for x86-64 CPUs.")
+    }
+
+    Ok(levels
+        .iter()
+        .flat_map(|features| features.iter())
+        .copied()
+        .filter(|feature| !has_cpu_feature(feature))
+        .collect())
+}
+
+const X86_64_V2: [&str; 6] = ["cmpxchg16b", "popcnt", "sse3", "sse4.1", "sse4.2", "ssse3"];
+const X86_64_V3: [&str; 8] = [
+    "avx", "avx2", "bmi1", "bmi2", "f16c", "fma", "lzcnt", "movbe",
+];
+const X86_64_V4: [&str; 5] = ["avx512f", "avx512bw", "avx512cd", "avx512dq", "avx512vl"];
+
+#[cfg(target_arch = "x86_64")]
+fn has_cpu_feature(feature: &str) -> bool {
+    match feature {
+        "cmpxchg16b" => std::arch::is_x86_feature_detected!("cmpxchg16b"),
+        "popcnt" => std::arch::is_x86_feature_detected!("popcnt"),
+        "sse3" => std::arch::is_x86_feature_detected!("sse3"),
+        "sse4.1" => std::arch::is_x86_feature_detected!("sse4.1"),
+        "sse4.2" => std::arch::is_x86_feature_detected!("sse4.2"),
+        "ssse3" => std::arch::is_x86_feature_detected!("ssse3"),
+        "avx" => std::arch::is_x86_feature_detected!("avx"),
+        "avx2" => std::arch::is_x86_feature_detected!("avx2"),
+        "bmi1" => std::arch::is_x86_feature_detected!("bmi1"),
+        "bmi2" => std::arch::is_x86_feature_detected!("bmi2"),
+        "f16c" => std::arch::is_x86_feature_detected!("f16c"),
+        "fma" => std::arch::is_x86_feature_detected!("fma"),
+        "lzcnt" => std::arch::is_x86_feature_detected!("lzcnt"),
+        "movbe" => std::arch::is_x86_feature_detected!("movbe"),
+        "avx512f" => std::arch::is_x86_feature_detected!("avx512f"),
+        "avx512bw" => std::arch::is_x86_feature_detected!("avx512bw"),
+        "avx512cd" => std::arch::is_x86_feature_detected!("avx512cd"),
+        "avx512dq" => std::arch::is_x86_feature_detected!("avx512dq"),
+        "avx512vl" => std::arch::is_x86_feature_detected!("avx512vl"),
+        _ => false,
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+const fn has_cpu_feature(_feature: &str) -> bool {
+    false
+}
+
+/// The system's glibc version, `None` if it doesn't use glibc
+fn host_glibc_version() -> Option<String> {
+    // `glibc 2.36`
+    command_output("getconf", &["GNU_LIBC_VERSION"])?
+        .strip_prefix("glibc ")
+        .map(str::to_string)
+}
+
+/// The system's macOS version, `None` if it isn't macOS
+fn host_macos_version() -> Option<String> {
+    if !cfg!(target_os = "macos") {
+        return None;
+    }
+
+    command_output("sw_vers", &["-productVersion"])
+}
+
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_versions() {
+        assert_eq!(compare_versions("2.9", "2.34"), Ordering::Less);
+        assert_eq!(compare_versions("2.34", "2.34.0"), Ordering::Equal);
+        assert_eq!(compare_versions("14.1", "13"), Ordering::Greater);
+    }
+
+    #[test]
+    fn test_check_requirements() {
+        assert!(check_requirements("hello", &Requirements::default()).is_ok());
+
+        let unknown_cpu = Requirements {
+            cpu: Some("pentium".into()),
+            ..Requirements::default()
+        };
+        assert!(check_requirements("hello", &unknown_cpu).is_err());
+
+        // Newer than any system will have
+        let future_glibc = Requirements {
+            min_glibc: Some("99.0".into()),
+            ..Requirements::default()
+        };
+        assert!(check_requirements("hello", &future_glibc).is_err());
+
+        let future_macos = Requirements {
+            min_macos: Some("99.0".into()),
+            ..Requirements::default()
+        };
+        assert!(check_requirements("hello", &future_macos).is_err());
+
+        if cfg!(target_arch = "x86_64") {
+            let baseline = Requirements {
+                cpu: Some("x86-64".into()),
+                ..Requirements::default()
+            };
+            assert!(check_requirements("hello", &baseline).is_ok());
+        }
+    }
+}
@@ -0,0 +1,228 @@
+use serde::Serialize;
+
+use crate::{
+    chunks::estimate_tree_size,
+    repo::{PackageManifest, RepoManifest, get_package, search_packages},
+};
+
+/// A short summary of a package, as returned by the listing and search endpoints.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct PackageSummary {
+    pub id: String,
+    pub aliases: Vec<String>,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub version: Option<String>,
+    pub license: Option<String>,
+    pub homepage_url: Option<String>,
+    pub maintainers: Vec<String>,
+    pub keywords: Vec<String>,
+    pub categories: Vec<String>,
+    /// Estimated size in kilobytes, rounded
+    pub size: u64,
+    /// Estimated size in bytes
+    pub bytes: u64,
+    pub build_hash: String,
+}
+
+impl From<&PackageManifest> for PackageSummary {
+    fn from(package: &PackageManifest) -> Self {
+        Self {
+            id: package.id.clone(),
+            aliases: package.aliases.clone(),
+            title: package.metadata.title.clone(),
+            description: package.metadata.description.clone(),
+            version: package.metadata.version.clone(),
+            license: package.metadata.license.clone(),
+            homepage_url: package.metadata.homepage_url.clone(),
+            maintainers: package.metadata.maintainers.clone(),
+            keywords: package.metadata.keywords.clone(),
+            categories: package.metadata.categories.clone(),
+            size: estimate_tree_size(&package.chunks) / 1024,
+            bytes: estimate_tree_size(&package.chunks),
+            build_hash: package.build_hash.clone(),
+        }
+    }
+}
+
+/// Handles a request to `/api/v1/...`, returning the status code and JSON body.
+/// Returns `None` if the path is not an API endpoint.
+///
+/// # Errors
+///
+/// - Serialization failure
+pub fn api_response(
+    manifest: &RepoManifest,
+    path: &str,
+    query: Option<&str>,
+) -> serde_json::Result<Option<(u16, String)>> {
+    let Some(endpoint) = path.strip_prefix("/api/v1/") else {
+        return Ok(None);
+    };
+
+    let response = match endpoint.trim_end_matches('/') {
+        "packages" => {
+            let summaries: Vec<PackageSummary> =
+                manifest.packages.iter().map(PackageSummary::from).collect();
+
+            (200, serde_json::to_string(&summaries)?)
+        }
+        "search" => {
+            let search_query = query
+                .and_then(|query| get_query_param(query, "q"))
+                .unwrap_or_default();
+            let summaries: Vec<PackageSummary> = search_packages(manifest, &search_query)
+                .iter()
+                .map(PackageSummary::from)
+                .collect();
+
+            (200, serde_json::to_string(&summaries)?)
+        }
+        endpoint => {
+            if let Some(package_id) = endpoint.strip_prefix("packages/")
+                && let Ok(package) = get_package(manifest, &percent_decode(package_id))
+            {
+                (200, serde_json::to_string(&package)?)
+            } else {
+                (404, error_body("Not found")?)
+            }
+        }
+    };
+
+    Ok(Some(response))
+}
+
+/// Builds a JSON error body
+///
+/// # Errors
+///
+/// - Serialization failure
+pub fn error_body(message: &str) -> serde_json::Result<String> {
+    serde_json::to_string(&serde_json::json!({ "error": message }))
+}
+
+/// Gets a single parameter from a query string, eg: `q=example&page=2`
+fn get_query_param(query: &str, key: &str) -> Option<String> {
+    query.split('&').find_map(|pair| {
+        let (pair_key, value) = pair.split_once('=').unwrap_or((pair, ""));
+
+        (pair_key == key).then(|| percent_decode(value))
+    })
+}
+
+/// Decodes `%XX` escapes and `+` as used in URLs.
+fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut idx = 0;
+
+    while idx < bytes.len() {
+        match bytes[idx] {
+            b'+' => decoded.push(b' '),
+            b'%' if idx + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[idx + 1..idx + 3]).unwrap_or_default();
+
+                if let Ok(byte) = u8::from_str_radix(hex, 16) {
+                    decoded.push(byte);
+                    idx += 2;
+                } else {
+                    decoded.push(b'%');
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        idx += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chunks::HashKind,
+        repo::{Metadata, PackageManifest},
+    };
+    use std::collections::BTreeMap;
+
+    fn example_manifest() -> RepoManifest {
+        let package = PackageManifest {
+            aliases: vec!["example_alias".into()],
+            id: "example".into(),
+            metadata: Metadata {
+                title: Some("Example Package".into()),
+                description: Some("Does example things".into()),
+                version: Some("1.0".into()),
+                ..Default::default()
+            },
+            build_hash: "hash".into(),
+            ..Default::default()
+        };
+
+        RepoManifest {
+            metadata: Metadata::default(),
+            packages: vec![package],
+            public_key: String::new(),
+            mirrors: Vec::new(),
+            edition: "2025".into(),
+            hash_kind: HashKind::Blake3,
+            serial: 0,
+            min_client_edition: None,
+            binary_cache: None,
+            includes: Vec::new(),
+            previous_public_key: None,
+            signing_keys: Vec::new(),
+            advisories: Vec::new(),
+            updates_url: None,
+            channels: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_api_packages() -> serde_json::Result<()> {
+        let manifest = example_manifest();
+
+        let (status, body) = api_response(&manifest, "/api/v1/packages", None)?.unwrap();
+        assert_eq!(status, 200);
+        let packages: serde_json::Value = serde_json::from_str(&body)?;
+        assert_eq!(packages[0]["id"], "example");
+
+        let (status, body) =
+            api_response(&manifest, "/api/v1/packages/example_alias", None)?.unwrap();
+        assert_eq!(status, 200);
+        let package: serde_json::Value = serde_json::from_str(&body)?;
+        assert_eq!(package["build_hash"], "hash");
+
+        let (status, _) = api_response(&manifest, "/api/v1/packages/missing", None)?.unwrap();
+        assert_eq!(status, 404);
+
+        assert!(api_response(&manifest, "/manifest.yml", None)?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_api_search() -> serde_json::Result<()> {
+        let manifest = example_manifest();
+
+        let (_, body) =
+            api_response(&manifest, "/api/v1/search", Some("q=EXAMPLE+things"))?.unwrap();
+        let results: Vec<serde_json::Value> = serde_json::from_str(&body)?;
+        assert_eq!(results.len(), 1);
+
+        let (_, body) =
+            api_response(&manifest, "/api/v1/search", Some("q=nothing%20here"))?.unwrap();
+        let results: Vec<serde_json::Value> = serde_json::from_str(&body)?;
+        assert!(results.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("a%20b+c"), "a b c");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz"), "%zz");
+    }
+}
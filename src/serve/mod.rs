@@ -0,0 +1,196 @@
+pub mod api;
+pub mod stats;
+
+use anyhow::{Result, anyhow};
+use std::{fs, io::Read, path::Path};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::{
+    chunks::compress::serve_chunk,
+    repo::{edition::serves_compressed_chunks, publish::accept_publish_archive, read_manifest},
+};
+use api::{api_response, error_body};
+use stats::Stats;
+
+/// Largest publish archive accepted, as it is held in memory until its signature is checked
+pub const MAX_PUBLISH_BYTES: u64 = 1024 * 1024 * 1024;
+
+/// Serves a Repository over HTTP, so it can be used as a mirror.
+///
+/// Static files (`manifest.yml`, its signatures and `chunks/`) are served as-is,
+/// alongside JSON endpoints under `/api/v1/`. Chunks are compressed first if the Repository's edition allows it,
+/// as of when serving started.
+/// Publishing via `POST /api/v1/publish` is only enabled if `maintainer_keys` is not empty.
+/// With `stats`, downloads are counted anonymously and served at `/api/v1/stats`.
+///
+/// # Errors
+///
+/// - Could not bind to `address`
+/// - Could not read the download log
+pub fn serve(
+    repo_path: &Path,
+    chunk_store_path: &Path,
+    address: &str,
+    maintainer_keys: &[String],
+    stats: bool,
+) -> Result<()> {
+    let server = Server::http(address).map_err(|e| anyhow!("Could not bind to {address}: {e}"))?;
+
+    let manifest = read_manifest(repo_path)?;
+    let compress_chunks = serves_compressed_chunks(&manifest.edition);
+    let mut stats = if stats {
+        Some(Stats::open(repo_path, &manifest)?)
+    } else {
+        None
+    };
+
+    println!("Serving {} on http://{address}", repo_path.display());
+
+    for request in server.incoming_requests() {
+        if let Err(err) = handle_request(
+            request,
+            repo_path,
+            chunk_store_path,
+            maintainer_keys,
+            compress_chunks,
+            stats.as_mut(),
+        ) {
+            eprintln!("Failed to respond to request: {err}");
+        }
+    }
+
+    Ok(())
+}
+
+fn handle_request(
+    mut request: Request,
+    repo_path: &Path,
+    chunk_store_path: &Path,
+    maintainer_keys: &[String],
+    compress_chunks: bool,
+    stats: Option<&mut Stats>,
+) -> Result<()> {
+    if *request.method() == Method::Post && request.url() == "/api/v1/publish" {
+        if maintainer_keys.is_empty() {
+            return respond_json(request, 403, &error_body("Publishing is disabled")?);
+        }
+
+        if request
+            .body_length()
+            .is_some_and(|length| length as u64 > MAX_PUBLISH_BYTES)
+        {
+            return respond_json(request, 413, &error_body("Archive is too large")?);
+        }
+
+        // Content-Length may be missing or wrong, so the read is limited too
+        let mut archive = Vec::new();
+        request
+            .as_reader()
+            .take(MAX_PUBLISH_BYTES + 1)
+            .read_to_end(&mut archive)?;
+        if archive.len() as u64 > MAX_PUBLISH_BYTES {
+            return respond_json(request, 413, &error_body("Archive is too large")?);
+        }
+
+        return match accept_publish_archive(
+            &archive,
+            repo_path,
+            chunk_store_path,
+            maintainer_keys,
+            None,
+        ) {
+            Ok(package) => {
+                if let Some(stats) = stats {
+                    stats.refresh(&read_manifest(repo_path)?);
+                }
+
+                respond_json(
+                    request,
+                    200,
+                    &serde_json::to_string(&serde_json::json!({ "published": package.id }))?,
+                )
+            }
+            Err(err) => respond_json(request, 400, &error_body(&err.to_string())?),
+        };
+    }
+
+    if *request.method() != Method::Get {
+        return respond_json(request, 405, &error_body("Method not allowed")?);
+    }
+
+    let url = request.url().to_string();
+    let (path, query) = url
+        .split_once('?')
+        .map_or((url.as_str(), None), |(path, query)| (path, Some(query)));
+
+    if path.trim_end_matches('/') == "/api/v1/stats" {
+        return if let Some(stats) = stats {
+            respond_json(request, 200, &serde_json::to_string(stats.counts())?)
+        } else {
+            respond_json(request, 404, &error_body("Statistics are disabled")?)
+        };
+    }
+
+    if path.starts_with("/api/") {
+        let manifest = read_manifest(repo_path)?;
+
+        return if let Some((status, body)) = api_response(&manifest, path, query)? {
+            respond_json(request, status, &body)
+        } else {
+            respond_json(request, 404, &error_body("Not found")?)
+        };
+    }
+
+    let file_path = match path {
+        "/manifest.yml"
+        | "/manifest.yml.sig"
+        | "/manifest.yml.sig.next"
+        | "/manifest.cbor"
+        | "/manifest.cbor.sig"
+        | "/manifest.cbor.sig.next" => Some(repo_path.join(&path[1..])),
+        path => path
+            .strip_prefix("/chunks/")
+            .filter(|chunk_name| is_safe_filename(chunk_name))
+            .map(|chunk_name| chunk_store_path.join(chunk_name)),
+    };
+
+    match file_path {
+        Some(file_path) if file_path.is_file() => {
+            let data = if path.starts_with("/chunks/") {
+                serve_chunk(&file_path, compress_chunks)?
+            } else {
+                fs::read(file_path)?
+            };
+            request.respond(Response::from_data(data))?;
+
+            if let Some(stats) = stats {
+                if path == "/manifest.yml" || path == "/manifest.cbor" {
+                    stats.record_manifest()?;
+                } else if let Some(chunk_name) = path.strip_prefix("/chunks/") {
+                    stats.record_chunk(chunk_name)?;
+                }
+            }
+        }
+        _ => request.respond(Response::from_string("Not found").with_status_code(404))?,
+    }
+
+    Ok(())
+}
+
+fn respond_json(request: Request, status: u16, body: &str) -> Result<()> {
+    let header = Header::from_bytes("Content-Type", "application/json")
+        .map_err(|()| anyhow!("Invalid header"))?;
+
+    request.respond(
+        Response::from_string(body)
+            .with_status_code(status)
+            .with_header(header),
+    )?;
+
+    Ok(())
+}
+
+/// Makes sure a requested filename cannot escape its directory
+fn is_safe_filename(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\\'])
+}
@@ -0,0 +1,200 @@
+use anyhow::Result;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use crate::repo::{RepoManifest, provenance::now};
+
+/// Download log, one line per download. Only what was downloaded and when, nothing about who.
+pub const DOWNLOADS_LOG_FILE: &str = "downloads.log";
+
+/// Download counts since statistics were enabled
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct DownloadCounts {
+    /// Manifest downloads, roughly one per client update or `repo add`
+    pub manifest: u64,
+    pub chunks: u64,
+    /// Chunk downloads per package. Chunks shared by several packages count for each
+    pub packages: BTreeMap<String, u64>,
+}
+
+/// Anonymous download statistics for a served Repository, kept in memory and logged to disk.
+#[derive(Debug)]
+pub struct Stats {
+    log_path: PathBuf,
+    counts: DownloadCounts,
+    /// Which packages use each chunk, by chunk filename
+    chunk_packages: HashMap<String, Vec<String>>,
+}
+
+impl Stats {
+    /// Opens the download log of a Repository, counting everything already in it.
+    ///
+    /// # Errors
+    ///
+    /// - Filesystem errors (Permissions)
+    pub fn open(repo_path: &Path, manifest: &RepoManifest) -> Result<Self> {
+        let mut stats = Self {
+            log_path: repo_path.join(DOWNLOADS_LOG_FILE),
+            counts: DownloadCounts::default(),
+            chunk_packages: HashMap::new(),
+        };
+        stats.refresh(manifest);
+
+        if stats.log_path.exists() {
+            for line in fs::read_to_string(&stats.log_path)?.lines() {
+                // `<time> manifest` or `<time> chunk <filename>`
+                match line.split(' ').skip(1).collect::<Vec<_>>().as_slice() {
+                    ["manifest"] => stats.count_manifest(),
+                    ["chunk", chunk_name] => stats.count_chunk(chunk_name),
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(stats)
+    }
+
+    /// Updates which packages use which chunks, after the manifest changed.
+    pub fn refresh(&mut self, manifest: &RepoManifest) {
+        self.chunk_packages.clear();
+
+        for package in &manifest.packages {
+            for chunk in &package.chunks {
+                let packages = self.chunk_packages.entry(chunk.filename()).or_default();
+
+                if !packages.contains(&package.id) {
+                    packages.push(package.id.clone());
+                }
+            }
+        }
+    }
+
+    #[must_use]
+    pub const fn counts(&self) -> &DownloadCounts {
+        &self.counts
+    }
+
+    /// Records a download of `manifest.yml`.
+    ///
+    /// # Errors
+    ///
+    /// - Filesystem errors (Permissions)
+    pub fn record_manifest(&mut self) -> Result<()> {
+        self.count_manifest();
+        self.log("manifest")
+    }
+
+    /// Records a download of a chunk, by filename.
+    ///
+    /// # Errors
+    ///
+    /// - Filesystem errors (Permissions)
+    pub fn record_chunk(&mut self, chunk_name: &str) -> Result<()> {
+        self.count_chunk(chunk_name);
+        self.log(&format!("chunk {chunk_name}"))
+    }
+
+    const fn count_manifest(&mut self) {
+        self.counts.manifest += 1;
+    }
+
+    fn count_chunk(&mut self, chunk_name: &str) {
+        self.counts.chunks += 1;
+
+        for package in self.chunk_packages.get(chunk_name).into_iter().flatten() {
+            *self.counts.packages.entry(package.clone()).or_default() += 1;
+        }
+    }
+
+    fn log(&self, entry: &str) -> Result<()> {
+        let mut log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.log_path)?;
+        writeln!(log, "{} {entry}", now()?)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chunks::{Chunk, HashKind, save_tree},
+        repo::{Metadata, PackageManifest, test_package},
+    };
+    use temp_dir::TempDir;
+
+    #[test]
+    fn test_stats_survive_restart() -> Result<()> {
+        let repo = TempDir::new()?;
+        let chunk_store = TempDir::new()?;
+        let tree = |files: &[(&str, &str)]| -> Result<Vec<Chunk>> {
+            let tree = TempDir::new()?;
+            for (path, contents) in files {
+                fs::write(tree.path().join(path), contents)?;
+            }
+
+            save_tree(tree.path(), chunk_store.path(), HashKind::Blake3)
+        };
+        let app_chunks = tree(&[("app", "app"), ("shared", "shared")])?;
+        let tool_chunks = tree(&[("shared", "shared")])?;
+        let chunk_name = |contents: &str| {
+            app_chunks
+                .iter()
+                .find(|chunk| chunk.path().ends_with(contents))
+                .map(Chunk::filename)
+                .unwrap_or_default()
+        };
+
+        let manifest = RepoManifest {
+            metadata: Metadata::default(),
+            packages: vec![
+                PackageManifest {
+                    chunks: app_chunks.clone(),
+                    ..test_package("app", &[])
+                },
+                PackageManifest {
+                    chunks: tool_chunks,
+                    ..test_package("tool", &[])
+                },
+            ],
+            public_key: String::new(),
+            mirrors: Vec::new(),
+            edition: "2025".into(),
+            hash_kind: HashKind::Blake3,
+            serial: 0,
+            min_client_edition: None,
+            binary_cache: None,
+            includes: Vec::new(),
+            previous_public_key: None,
+            signing_keys: Vec::new(),
+            advisories: Vec::new(),
+            updates_url: None,
+            channels: BTreeMap::new(),
+        };
+
+        let mut stats = Stats::open(repo.path(), &manifest)?;
+        stats.record_manifest()?;
+        stats.record_chunk(&chunk_name("app"))?;
+        stats.record_chunk(&chunk_name("shared"))?;
+        stats.record_chunk("unknown")?;
+
+        let expected = DownloadCounts {
+            manifest: 1,
+            chunks: 3,
+            packages: BTreeMap::from([("app".into(), 2), ("tool".into(), 1)]),
+        };
+        assert_eq!(stats.counts(), &expected);
+
+        // Counts are rebuilt from the log
+        assert_eq!(Stats::open(repo.path(), &manifest)?.counts(), &expected);
+
+        Ok(())
+    }
+}
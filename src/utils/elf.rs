@@ -0,0 +1,355 @@
+use anyhow::{Context, Result, bail};
+use std::{
+    ffi::OsStr,
+    fs::{self, File},
+    io::Read,
+    path::Path,
+    process::Command,
+};
+
+use crate::run::requirements::compare_versions;
+
+const PT_DYNAMIC: u64 = 2;
+const PT_INTERP: u64 = 3;
+const SHT_GNU_VERNEED: u64 = 0x6fff_fffe;
+
+/// What Flint cares about in an ELF file's program headers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ElfInfo {
+    /// Dynamic linker requested by the binary, eg: `/lib64/ld-linux-x86-64.so.2`
+    pub interpreter: Option<String>,
+    /// Dynamically linked, so it has a RUNPATH that can be set
+    pub dynamic: bool,
+    /// Newest glibc symbol version the binary needs, eg: `2.34`
+    pub min_glibc: Option<String>,
+}
+
+/// Reads the program headers of an ELF file.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+///
+/// # Returns
+///
+/// `None` if the file is not a (valid) ELF file
+pub fn read_elf_info(path: &Path) -> Result<Option<ElfInfo>> {
+    let mut magic = [0; 4];
+    if File::open(path)?.read_exact(&mut magic).is_err() || &magic != b"\x7fELF" {
+        return Ok(None);
+    }
+
+    Ok(parse_elf(&fs::read(path)?))
+}
+
+fn parse_elf(data: &[u8]) -> Option<ElfInfo> {
+    if data.get(0..4)? != b"\x7fELF" {
+        return None;
+    }
+
+    let is_64 = match data.get(4)? {
+        1 => false,
+        2 => true,
+        _ => return None,
+    };
+    let little_endian = match data.get(5)? {
+        1 => true,
+        2 => false,
+        _ => return None,
+    };
+
+    let read = |offset: u64, size: usize| -> Option<u64> {
+        let offset = usize::try_from(offset).ok()?;
+        let bytes = data.get(offset..offset.checked_add(size)?)?;
+
+        let fold = |value: u64, byte: &u8| (value << 8) | u64::from(*byte);
+        Some(if little_endian {
+            bytes.iter().rev().fold(0, fold)
+        } else {
+            bytes.iter().fold(0, fold)
+        })
+    };
+
+    let (phoff, phentsize, phnum) = if is_64 {
+        (read(0x20, 8)?, read(0x36, 2)?, read(0x38, 2)?)
+    } else {
+        (read(0x1C, 4)?, read(0x2A, 2)?, read(0x2C, 2)?)
+    };
+
+    let mut info = ElfInfo {
+        interpreter: None,
+        dynamic: false,
+        min_glibc: None,
+    };
+
+    for index in 0..phnum {
+        let header = phoff.checked_add(index.checked_mul(phentsize)?)?;
+
+        match read(header, 4)? {
+            PT_DYNAMIC => info.dynamic = true,
+            PT_INTERP => {
+                let (offset, size) = if is_64 {
+                    (read(header + 8, 8)?, read(header + 32, 8)?)
+                } else {
+                    (read(header + 4, 4)?, read(header + 16, 4)?)
+                };
+                let start = usize::try_from(offset).ok()?;
+                let end = start.checked_add(usize::try_from(size).ok()?)?;
+
+                info.interpreter = Some(
+                    String::from_utf8_lossy(data.get(start..end)?)
+                        .trim_end_matches('\0')
+                        .to_string(),
+                );
+            }
+            _ => {}
+        }
+    }
+
+    info.min_glibc = needed_versions(data, is_64, &read)
+        .into_iter()
+        .filter_map(|name| name.strip_prefix("GLIBC_").map(str::to_string))
+        .filter(|version| version.starts_with(|c: char| c.is_ascii_digit()))
+        .max_by(|a, b| compare_versions(a, b));
+
+    Some(info)
+}
+
+/// Names of the symbol versions an ELF file needs from its libraries, eg: `GLIBC_2.34`.
+/// Read from the `.gnu.version_r` section, empty if it has none or it is malformed.
+fn needed_versions(
+    data: &[u8],
+    is_64: bool,
+    read: &impl Fn(u64, usize) -> Option<u64>,
+) -> Vec<String> {
+    let mut names = Vec::new();
+
+    let Some((shoff, shentsize, shnum)) = (if is_64 {
+        read(0x28, 8).zip(read(0x3A, 2)).zip(read(0x3C, 2))
+    } else {
+        read(0x20, 4).zip(read(0x2E, 2)).zip(read(0x30, 2))
+    })
+    .map(|((shoff, shentsize), shnum)| (shoff, shentsize, shnum)) else {
+        return names;
+    };
+
+    // Type, offset, size and linked section of a section header
+    let section = |index: u64| -> Option<(u64, u64, u64, u64)> {
+        let header = shoff.checked_add(index.checked_mul(shentsize)?)?;
+
+        Some(if is_64 {
+            (
+                read(header + 4, 4)?,
+                read(header + 0x18, 8)?,
+                read(header + 0x20, 8)?,
+                read(header + 0x28, 4)?,
+            )
+        } else {
+            (
+                read(header + 4, 4)?,
+                read(header + 0x10, 4)?,
+                read(header + 0x14, 4)?,
+                read(header + 0x18, 4)?,
+            )
+        })
+    };
+
+    let string = |strtab: u64, offset: u64| -> Option<String> {
+        let start = usize::try_from(strtab.checked_add(offset)?).ok()?;
+        let bytes = data.get(start..)?;
+        let end = bytes.iter().position(|byte| *byte == 0)?;
+
+        Some(String::from_utf8_lossy(&bytes[..end]).to_string())
+    };
+
+    for index in 0..shnum {
+        let Some((SHT_GNU_VERNEED, offset, size, link)) = section(index) else {
+            continue;
+        };
+        let Some((_, strtab, _, _)) = section(link) else {
+            continue;
+        };
+        let end = offset.saturating_add(size);
+
+        // `Elf_Verneed` entries, each followed by its `Elf_Vernaux` entries
+        let mut verneed = offset;
+        while verneed < end {
+            let (Some(count), Some(aux), Some(next)) = (
+                read(verneed + 2, 2),
+                read(verneed + 8, 4),
+                read(verneed + 12, 4),
+            ) else {
+                break;
+            };
+
+            let mut vernaux = verneed.saturating_add(aux);
+            for _ in 0..count {
+                let (Some(name), Some(next_aux)) = (read(vernaux + 8, 4), read(vernaux + 12, 4))
+                else {
+                    break;
+                };
+                names.extend(string(strtab, name));
+
+                if next_aux == 0 {
+                    break;
+                }
+                vernaux = vernaux.saturating_add(next_aux);
+            }
+
+            if next == 0 {
+                break;
+            }
+            verneed = verneed.saturating_add(next);
+        }
+    }
+
+    names
+}
+
+/// Runs `patchelf` with `args` on a copy of `path`, then swaps it in.
+/// Files are never edited in place, as they may be hard links into the chunk store.
+///
+/// # Errors
+///
+/// - patchelf is not installed, or failed
+/// - Filesystem errors (Permissions, Out of space)
+pub fn patchelf<S: AsRef<OsStr>>(path: &Path, args: &[S]) -> Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".patchelf");
+
+    fs::copy(path, &tmp_path)?;
+
+    let status = Command::new("patchelf")
+        .args(args)
+        .arg(&tmp_path)
+        .status()
+        .with_context(|| "Could not run patchelf. Is it installed?");
+
+    match status {
+        Ok(status) if status.success() => {
+            fs::rename(&tmp_path, path)?;
+            Ok(())
+        }
+        Ok(status) => {
+            fs::remove_file(&tmp_path)?;
+            bail!("patchelf failed on {} with {status}", path.display())
+        }
+        Err(err) => {
+            fs::remove_file(&tmp_path)?;
+            Err(err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 64-bit little endian ELF header with a `PT_INTERP` and a `PT_DYNAMIC` program header
+    fn fake_elf(interpreter: &str) -> Vec<u8> {
+        let mut data = vec![0; 0x40 + 2 * 0x38];
+        data[0..4].copy_from_slice(b"\x7fELF");
+        data[4] = 2;
+        data[5] = 1;
+        data[0x20..0x28].copy_from_slice(&0x40u64.to_le_bytes());
+        data[0x36..0x38].copy_from_slice(&0x38u16.to_le_bytes());
+        data[0x38..0x3A].copy_from_slice(&2u16.to_le_bytes());
+
+        let interp_offset = data.len() as u64;
+        let interp = 0x40;
+        data[interp..interp + 4].copy_from_slice(&3u32.to_le_bytes());
+        data[interp + 8..interp + 16].copy_from_slice(&interp_offset.to_le_bytes());
+        data[interp + 32..interp + 40]
+            .copy_from_slice(&(interpreter.len() as u64 + 1).to_le_bytes());
+
+        let dynamic = 0x40 + 0x38;
+        data[dynamic..dynamic + 4].copy_from_slice(&2u32.to_le_bytes());
+
+        data.extend_from_slice(interpreter.as_bytes());
+        data.push(0);
+
+        data
+    }
+
+    /// A 64-bit little endian ELF header with a `.gnu.version_r` section needing `versions` from libc
+    #[allow(clippy::cast_possible_truncation)]
+    fn fake_elf_needing(versions: &[&str]) -> Vec<u8> {
+        let mut strtab = b"\0libc.so.6\0".to_vec();
+        let mut names = Vec::new();
+        for version in versions {
+            names.push(strtab.len() as u32);
+            strtab.extend_from_slice(version.as_bytes());
+            strtab.push(0);
+        }
+
+        let mut verneed = Vec::new();
+        verneed.extend_from_slice(&1u16.to_le_bytes());
+        verneed.extend_from_slice(&(versions.len() as u16).to_le_bytes());
+        verneed.extend_from_slice(&1u32.to_le_bytes());
+        verneed.extend_from_slice(&16u32.to_le_bytes());
+        verneed.extend_from_slice(&0u32.to_le_bytes());
+        for (index, name) in names.iter().enumerate() {
+            let next: u32 = if index + 1 == names.len() { 0 } else { 16 };
+            verneed.extend_from_slice(&[0; 8]);
+            verneed.extend_from_slice(&name.to_le_bytes());
+            verneed.extend_from_slice(&next.to_le_bytes());
+        }
+
+        // Header, then the null, version and string table section headers, then their contents
+        let strtab_offset = 0x40 + 3 * 0x40;
+        let verneed_offset = strtab_offset + strtab.len();
+        let mut data = vec![0; strtab_offset];
+        data[0..4].copy_from_slice(b"\x7fELF");
+        data[4] = 2;
+        data[5] = 1;
+        data[0x28..0x30].copy_from_slice(&0x40u64.to_le_bytes());
+        data[0x3A..0x3C].copy_from_slice(&0x40u16.to_le_bytes());
+        data[0x3C..0x3E].copy_from_slice(&3u16.to_le_bytes());
+
+        let section = 0x80;
+        data[section + 4..section + 8].copy_from_slice(&0x6fff_fffeu32.to_le_bytes());
+        data[section + 0x18..section + 0x20]
+            .copy_from_slice(&(verneed_offset as u64).to_le_bytes());
+        data[section + 0x20..section + 0x28].copy_from_slice(&(verneed.len() as u64).to_le_bytes());
+        data[section + 0x28..section + 0x2C].copy_from_slice(&2u32.to_le_bytes());
+
+        let section = 0xC0;
+        data[section + 4..section + 8].copy_from_slice(&3u32.to_le_bytes());
+        data[section + 0x18..section + 0x20].copy_from_slice(&(strtab_offset as u64).to_le_bytes());
+        data[section + 0x20..section + 0x28].copy_from_slice(&(strtab.len() as u64).to_le_bytes());
+
+        data.extend_from_slice(&strtab);
+        data.extend_from_slice(&verneed);
+
+        data
+    }
+
+    #[test]
+    fn test_min_glibc() {
+        let info = parse_elf(&fake_elf_needing(&[
+            "GLIBC_2.2.5",
+            "GLIBC_2.34",
+            "GLIBC_PRIVATE",
+        ]));
+        assert_eq!(info.and_then(|info| info.min_glibc), Some("2.34".into()));
+
+        let info = parse_elf(&fake_elf_needing(&["OPENSSL_3.0.0"]));
+        assert_eq!(info.and_then(|info| info.min_glibc), None);
+    }
+
+    #[test]
+    fn test_parse_elf() {
+        assert_eq!(
+            parse_elf(&fake_elf("/lib64/ld-linux-x86-64.so.2")),
+            Some(ElfInfo {
+                interpreter: Some("/lib64/ld-linux-x86-64.so.2".into()),
+                dynamic: true,
+                min_glibc: None,
+            })
+        );
+
+        assert_eq!(parse_elf(b"#!/bin/sh\n"), None);
+        // Truncated headers are not an ELF file worth patching
+        assert_eq!(parse_elf(&fake_elf("/lib/ld.so")[..0x50]), None);
+    }
+}
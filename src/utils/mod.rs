@@ -1,10 +1,22 @@
-use anyhow::{Context, Result};
+pub mod elf;
+pub mod owner;
+pub mod platform;
+pub mod prompt;
+pub mod temp;
+
+use anyhow::{Context, Result, bail};
 use std::{
     fs,
     path::{Path, PathBuf},
 };
 
-use crate::repo::{PackageManifest, get_package, read_manifest};
+use crate::{
+    repo::{
+        PackageManifest, get_provider, read_manifest, read_subscribed_manifest,
+        settings::get_settings,
+    },
+    utils::prompt::Prompter,
+};
 
 /// Resolve a repo name into a safe absolute path under the given base `path`.
 ///
@@ -29,7 +41,8 @@ pub fn resolve_repo(base: &Path, repo_name: &str) -> Result<PathBuf> {
     Ok(candidate_canon)
 }
 
-/// Search all repositories for one matching a predicate
+/// Search all repositories for one matching a predicate.
+/// Packages providing `package_id` are accepted too, see [`get_provider`].
 ///
 /// # Errors
 ///
@@ -47,9 +60,9 @@ where
 
     for repo_entry in fs::read_dir(path)? {
         let repo_dir = repo_entry?;
-        let repo_manifest = read_manifest(&repo_dir.path())?;
+        let repo_manifest = read_subscribed_manifest(&repo_dir.path())?;
 
-        let package = get_package(&repo_manifest, package_id);
+        let package = get_provider(&repo_manifest, package_id);
 
         if let Ok(package) = package {
             let filtered = filter(&repo_dir.path());
@@ -61,3 +74,306 @@ where
 
     Ok(possible_repos)
 }
+
+/// Searches every Repository for packages whose id, aliases, title, description, keywords or categories
+/// contain `query`, ignoring case.
+///
+/// # Errors
+///
+/// - A Repository contains invalid data/signature
+/// - Filesystem errors
+pub fn search_packages(path: &Path, query: &str) -> Result<Vec<(String, PackageManifest)>> {
+    let query = query.to_lowercase();
+    let matches = |text: &str| text.to_lowercase().contains(&query);
+
+    let mut results = Vec::new();
+
+    for repo_entry in fs::read_dir(path)? {
+        let repo_dir = repo_entry?;
+        let repo_name = repo_dir.file_name().to_string_lossy().to_string();
+
+        for package in read_subscribed_manifest(&repo_dir.path())?.packages {
+            if matches(&package.id)
+                || package.aliases.iter().any(|alias| matches(alias))
+                || package.metadata.title.as_deref().is_some_and(matches)
+                || package.metadata.description.as_deref().is_some_and(matches)
+                || package
+                    .metadata
+                    .keywords
+                    .iter()
+                    .any(|keyword| matches(keyword))
+                || package
+                    .metadata
+                    .categories
+                    .iter()
+                    .any(|category| matches(category))
+            {
+                results.push((repo_name.clone(), package));
+            }
+        }
+    }
+
+    results.sort_by(|(repo_a, a), (repo_b, b)| (&a.id, repo_a).cmp(&(&b.id, repo_b)));
+
+    Ok(results)
+}
+
+/// Finds the one Repository containing a package that matches `filter`.
+/// If there are several, the highest priority one is used, asking through `prompter` on ties.
+///
+/// # Errors
+///
+/// - No Repository contains the package
+/// - The prompter could not choose
+/// - A Repository contains invalid data/signature
+/// - Filesystem errors
+pub fn choose_package<F>(
+    path: &Path,
+    package_id: &str,
+    filter: F,
+    prompter: &dyn Prompter,
+) -> Result<(PathBuf, PackageManifest)>
+where
+    F: Fn(&Path) -> bool,
+{
+    let mut possible_repos = resolve_package(path, package_id, filter)?;
+
+    if possible_repos.len() > 1 {
+        let mut priorities = Vec::new();
+        for (repo_path, _) in &possible_repos {
+            priorities.push(get_settings(repo_path)?.priority);
+        }
+        let highest = priorities.iter().copied().max().unwrap_or_default();
+
+        let mut priorities = priorities.into_iter();
+        possible_repos.retain(|_| priorities.next() == Some(highest));
+    }
+
+    match possible_repos.len() {
+        0 => bail!("No Repositories contain that package."),
+        1 => Ok(possible_repos.remove(0)),
+        _ => {
+            let items: Vec<String> = possible_repos
+                .iter()
+                .map(|(path, manifest)| {
+                    format!(
+                        "{} ({} {})",
+                        path.file_name().unwrap_or_default().to_string_lossy(),
+                        manifest.metadata.title.clone().unwrap_or_default(),
+                        manifest.metadata.version.clone().unwrap_or_default()
+                    )
+                })
+                .collect();
+
+            let selection = prompter.select(
+                "Multiple repositories contain this package, pick one",
+                &items,
+            )?;
+
+            possible_repos
+                .into_iter()
+                .nth(selection)
+                .context("Invalid selection")
+        }
+    }
+}
+
+/// Like [`choose_package`], but only considers Repositories the package is installed from, if there are any.
+/// Only falls back to every Repository containing it when it is not installed at all.
+///
+/// # Errors
+///
+/// - No Repository contains the package
+/// - The prompter could not choose
+/// - A Repository contains invalid data/signature
+/// - Filesystem errors
+pub fn choose_installed_package(
+    path: &Path,
+    package_id: &str,
+    prompter: &dyn Prompter,
+) -> Result<(PathBuf, PackageManifest)> {
+    // By the id of whichever package is or provides `package_id` in that Repository
+    let is_installed = |repo_path: &Path| {
+        read_manifest(repo_path)
+            .and_then(|manifest| get_provider(&manifest, package_id))
+            .is_ok_and(|package| repo_path.join("installed").join(package.id).exists())
+    };
+
+    if resolve_package(path, package_id, is_installed)?.is_empty() {
+        choose_package(path, package_id, |_| true, prompter)
+    } else {
+        choose_package(path, package_id, is_installed, prompter)
+    }
+}
+
+/// Formats a size in bytes with binary units, eg: `512 B`, `1.5 KiB` or `3.2 GiB`
+#[must_use]
+#[allow(clippy::cast_precision_loss)]
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+
+    // Precision loss only matters far beyond any real package size
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::{
+        Metadata, create_repo, insert_package,
+        settings::{RepoSettings, set_settings},
+    };
+    use crate::utils::prompt::NonInteractive;
+    use temp_dir::TempDir;
+
+    struct PickLast;
+
+    impl Prompter for PickLast {
+        fn select(&self, _prompt: &str, items: &[String]) -> Result<usize> {
+            Ok(items.len() - 1)
+        }
+
+        fn confirm(&self, _prompt: &str, default: bool) -> Result<bool> {
+            Ok(default)
+        }
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(5 * 1024 * 1024), "5.0 MiB");
+        assert_eq!(format_size(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    }
+
+    #[test]
+    fn test_choose_package() -> Result<()> {
+        let repos = TempDir::new()?;
+
+        for repo_name in ["a", "b"] {
+            let repo_path = repos.path().join(repo_name);
+            create_repo(&repo_path, Some(&repo_path))?;
+
+            let package = PackageManifest {
+                id: "shared".into(),
+                ..Default::default()
+            };
+            insert_package(&package, &repo_path, Some(&repo_path))?;
+        }
+
+        assert!(choose_package(repos.path(), "shared", |_| true, &NonInteractive).is_err());
+        assert!(choose_package(repos.path(), "missing", |_| true, &PickLast).is_err());
+
+        let mut chosen = Vec::new();
+        for repo_name in ["a", "b"] {
+            let (repo_path, _) = choose_package(
+                repos.path(),
+                "shared",
+                |path| path.ends_with(repo_name),
+                &NonInteractive,
+            )?;
+            chosen.push(repo_path);
+        }
+        assert_ne!(chosen[0], chosen[1]);
+
+        let (repo_path, package) = choose_package(repos.path(), "shared", |_| true, &PickLast)?;
+        assert_eq!(package.id, "shared");
+        assert!(chosen.contains(&repo_path));
+
+        // A higher priority wins without asking
+        set_settings(
+            &repos.path().join("a"),
+            &RepoSettings {
+                priority: 5,
+                ..RepoSettings::default()
+            },
+        )?;
+        let (repo_path, _) = choose_package(repos.path(), "shared", |_| true, &NonInteractive)?;
+        assert!(repo_path.ends_with("a"));
+        set_settings(&repos.path().join("a"), &RepoSettings::default())?;
+
+        // Not installed anywhere, so every Repository is a candidate
+        assert!(choose_installed_package(repos.path(), "shared", &NonInteractive).is_err());
+
+        fs::create_dir_all(repos.path().join("b/installed/shared"))?;
+        let (repo_path, _) = choose_installed_package(repos.path(), "shared", &NonInteractive)?;
+        assert!(repo_path.ends_with("b"));
+
+        // Different packages of different Repositories providing the same name
+        for (repo_name, id) in [("a", "cpython"), ("b", "pypy")] {
+            let repo_path = repos.path().join(repo_name);
+            let mut package = get_provider(&read_manifest(&repo_path)?, "shared")?;
+            package.id = id.into();
+            package.provides = vec!["python3".into()];
+            insert_package(&package, &repo_path, Some(&repo_path))?;
+        }
+        assert_eq!(resolve_package(repos.path(), "python3", |_| true)?.len(), 2);
+        let (_, package) = choose_package(
+            repos.path(),
+            "python3",
+            |path| path.ends_with("b"),
+            &NonInteractive,
+        )?;
+        assert_eq!(package.id, "pypy");
+
+        fs::create_dir_all(repos.path().join("a/installed/cpython"))?;
+        let (repo_path, package) =
+            choose_installed_package(repos.path(), "python3", &NonInteractive)?;
+        assert!(repo_path.ends_with("a"));
+        assert_eq!(package.id, "cpython");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_packages() -> Result<()> {
+        let repos = TempDir::new()?;
+        let repo_path = repos.path().join("main");
+        create_repo(&repo_path, Some(&repo_path))?;
+
+        for (id, alias, description, keyword) in [
+            ("firefox", "browser", "A web browser", "internet"),
+            ("vim", "vi", "A text editor", "terminal"),
+        ] {
+            let package = PackageManifest {
+                aliases: vec![alias.into()],
+                id: id.into(),
+                metadata: Metadata {
+                    description: Some(description.into()),
+                    keywords: vec![keyword.into()],
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            insert_package(&package, &repo_path, Some(&repo_path))?;
+        }
+
+        let ids = |query| -> Result<Vec<String>> {
+            Ok(search_packages(repos.path(), query)?
+                .into_iter()
+                .map(|(repo_name, package)| format!("{repo_name}/{}", package.id))
+                .collect())
+        };
+
+        assert_eq!(ids("VIM")?, vec!["main/vim"]);
+        assert_eq!(ids("browser")?, vec!["main/firefox"]);
+        assert_eq!(ids("editor")?, vec!["main/vim"]);
+        assert_eq!(ids("Terminal")?, vec!["main/vim"]);
+        assert_eq!(ids("a ")?, vec!["main/firefox", "main/vim"]);
+        assert!(ids("emacs")?.is_empty());
+
+        Ok(())
+    }
+}
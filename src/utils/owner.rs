@@ -0,0 +1,107 @@
+use anyhow::{Context, Result, bail};
+use std::{fs, path::Path};
+
+/// A user and/or group files are handed to, from `user`, `user:group` or `:group`.
+/// Both may be names or numeric ids.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Owner {
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+impl Owner {
+    /// Resolves names against `/etc/passwd` and `/etc/group`.
+    ///
+    /// # Errors
+    ///
+    /// - No such user or group
+    pub fn parse(spec: &str) -> Result<Self> {
+        Self::parse_with(spec, Path::new("/etc/passwd"), Path::new("/etc/group"))
+    }
+
+    fn parse_with(spec: &str, passwd_path: &Path, group_path: &Path) -> Result<Self> {
+        let (user, group) = spec.split_once(':').unwrap_or((spec, ""));
+
+        let resolve = |name: &str, database: &Path| -> Result<Option<u32>> {
+            if name.is_empty() {
+                return Ok(None);
+            }
+            if let Ok(id) = name.parse() {
+                return Ok(Some(id));
+            }
+
+            lookup_id(database, name)?
+                .map(Some)
+                .with_context(|| format!("No such user or group {name} in {}", database.display()))
+        };
+
+        let owner = Self {
+            uid: resolve(user, passwd_path)?,
+            gid: resolve(group, group_path)?,
+        };
+
+        if owner.uid.is_none() && owner.gid.is_none() {
+            bail!("Invalid owner {spec}, expected user, user:group or :group.")
+        }
+
+        Ok(owner)
+    }
+}
+
+/// The id of `name` in a `/etc/passwd` style file, where it's the third field
+fn lookup_id(database: &Path, name: &str) -> Result<Option<u32>> {
+    let contents = fs::read_to_string(database)?;
+
+    Ok(contents.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        (fields.next() == Some(name))
+            .then(|| fields.nth(1)?.parse().ok())
+            .flatten()
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use temp_dir::TempDir;
+
+    #[test]
+    fn test_parse_owner() -> Result<()> {
+        let etc = TempDir::new()?;
+        let passwd = &etc.path().join("passwd");
+        let group = &etc.path().join("group");
+        fs::write(
+            passwd,
+            "root:x:0:0::/root:/bin/sh\nflint:x:990:990::/:/bin/false\n",
+        )?;
+        fs::write(group, "root:x:0:\nusers:x:100:flint\n")?;
+
+        let parse = |spec| Owner::parse_with(spec, passwd, group);
+
+        assert_eq!(
+            parse("flint:users")?,
+            Owner {
+                uid: Some(990),
+                gid: Some(100)
+            }
+        );
+        assert_eq!(
+            parse("1000")?,
+            Owner {
+                uid: Some(1000),
+                gid: None
+            }
+        );
+        assert_eq!(
+            parse(":users")?,
+            Owner {
+                uid: None,
+                gid: Some(100)
+            }
+        );
+        assert!(parse("nobody").is_err());
+        assert!(parse(":").is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,263 @@
+//! Filesystem operations that differ between Unix and Windows.
+//! Use these instead of `std::os::unix`, so Flint keeps building on Windows.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// On Windows, where a directory link had to be copied instead, names what it links to
+#[cfg(windows)]
+const LINK_MARKER: &str = ".flint-link";
+
+/// The permission bits of a file, eg: `0o755`.
+/// Windows only has a read-only flag, so files there are `0o444` or `0o644`.
+#[must_use]
+pub fn mode(metadata: &fs::Metadata) -> u32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        metadata.permissions().mode() & 0o777
+    }
+
+    #[cfg(not(unix))]
+    {
+        if metadata.permissions().readonly() {
+            0o444
+        } else {
+            0o644
+        }
+    }
+}
+
+/// Sets the permission bits of a file. Windows only keeps whether the owner may write to it.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions, doesn't exist)
+pub fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o777))
+    }
+
+    #[cfg(not(unix))]
+    {
+        let mut permissions = fs::metadata(path)?.permissions();
+        permissions.set_readonly(mode & 0o200 == 0);
+        fs::set_permissions(path, permissions)
+    }
+}
+
+/// Makes `link` point at the directory `target`, which may be relative to the directory of `link`.
+///
+/// Windows only allows symlinks in Developer Mode or as an administrator. Without either, the
+/// directory is copied instead, and [`read_dir_link`] still knows what it links to.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions, `link` already exists)
+pub fn symlink_dir(target: &Path, link: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink(target, link)
+    }
+
+    #[cfg(windows)]
+    {
+        if std::os::windows::fs::symlink_dir(target, link).is_ok() {
+            return Ok(());
+        }
+
+        let source = link.parent().unwrap_or(Path::new("")).join(target);
+        copy_dir(&source, link)?;
+        fs::write(link.join(LINK_MARKER), target.to_string_lossy().as_bytes())
+    }
+}
+
+#[cfg(windows)]
+fn copy_dir(source: &Path, destination: &Path) -> io::Result<()> {
+    for entry in walkdir::WalkDir::new(source) {
+        let entry = entry?;
+        let path = destination.join(entry.path().strip_prefix(source).unwrap_or(entry.path()));
+
+        if entry.file_type().is_dir() {
+            fs::create_dir_all(path)?;
+        } else {
+            fs::copy(entry.path(), path)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Makes `link` point at the file `target`.
+/// Without the rights for a symlink on Windows, the file is copied instead.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions, `link` already exists)
+pub fn symlink_file(target: &Path, link: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink(target, link)
+    }
+
+    #[cfg(windows)]
+    {
+        if std::os::windows::fs::symlink_file(target, link).is_ok() {
+            return Ok(());
+        }
+
+        fs::copy(target, link).map(|_| ())
+    }
+}
+
+/// Where a link made by [`symlink_dir`] points, `None` if `link` is not one.
+#[must_use]
+pub fn read_dir_link(link: &Path) -> Option<PathBuf> {
+    if link.is_symlink() {
+        return fs::read_link(link).ok();
+    }
+
+    #[cfg(windows)]
+    {
+        fs::read_to_string(link.join(LINK_MARKER))
+            .ok()
+            .map(PathBuf::from)
+    }
+
+    #[cfg(not(windows))]
+    {
+        None
+    }
+}
+
+/// Flushes the entries of a directory to disk, so files created or renamed in it survive a crash.
+/// Windows can't open directories, and flushes their entries with the files, so this does nothing there.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions, IO errors)
+pub fn sync_dir(path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        fs::File::open(path)?.sync_all()
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        Ok(())
+    }
+}
+
+/// Hands a file, or a symlink itself, to another user and/or group.
+/// Windows files have no Unix owner, so this does nothing there.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions, eg: without root)
+pub fn set_owner(path: &Path, uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::lchown(path, uid, gid)
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = (path, uid, gid);
+        Ok(())
+    }
+}
+
+/// Whether two files are hard links to the same data.
+/// Always `false` on Windows, where callers fall back to comparing contents.
+#[must_use]
+pub fn same_file(a: &fs::Metadata, b: &fs::Metadata) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        a.dev() == b.dev() && a.ino() == b.ino()
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = (a, b);
+        false
+    }
+}
+
+/// How many hard links a file has, eg: more than one for files shared with the chunk store.
+/// Always `1` on Windows.
+#[must_use]
+pub fn link_count(metadata: &fs::Metadata) -> u64 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        metadata.nlink()
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        1
+    }
+}
+
+/// Whether two files are on the same filesystem, eg: to tell whether something is mounted over a directory.
+/// Always `true` on Windows, which has no mounts Flint makes.
+#[must_use]
+pub fn same_device(a: &fs::Metadata, b: &fs::Metadata) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        a.dev() == b.dev()
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = (a, b);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use temp_dir::TempDir;
+
+    #[test]
+    fn test_platform() -> io::Result<()> {
+        let root = TempDir::new()?;
+        fs::create_dir(root.path().join("target"))?;
+        fs::write(root.path().join("target/file"), "")?;
+
+        let link = &root.path().join("link");
+        symlink_dir(Path::new("target"), link)?;
+        assert!(link.join("file").exists());
+        assert_eq!(read_dir_link(link), Some(PathBuf::from("target")));
+        assert_eq!(read_dir_link(&root.path().join("target")), None);
+
+        let file_link = &root.path().join("file_link");
+        symlink_file(&root.path().join("target/file"), file_link)?;
+        assert!(file_link.is_file());
+        sync_dir(root.path())?;
+
+        let file = &root.path().join("target/file");
+        set_mode(file, 0o600)?;
+        assert_eq!(mode(&fs::metadata(file)?), 0o600);
+
+        let metadata = fs::metadata(file)?;
+        assert!(same_file(&metadata, &fs::metadata(link.join("file"))?));
+        assert!(same_device(&metadata, &fs::metadata(root.path())?));
+
+        Ok(())
+    }
+}
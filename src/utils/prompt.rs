@@ -0,0 +1,51 @@
+use anyhow::{Result, bail};
+
+/// Asks the user questions. Library code takes one of these instead of using the terminal,
+/// so it can be embedded without a TTY and tested.
+pub trait Prompter {
+    /// Lets the user choose one of `items`, returning its index.
+    ///
+    /// # Errors
+    ///
+    /// - No choice could be made
+    fn select(&self, prompt: &str, items: &[String]) -> Result<usize>;
+
+    /// Asks a yes/no question.
+    ///
+    /// # Errors
+    ///
+    /// - No answer could be given
+    fn confirm(&self, prompt: &str, default: bool) -> Result<bool>;
+
+    /// Asks for a line of text.
+    ///
+    /// # Errors
+    ///
+    /// - No answer could be given
+    fn input(&self, prompt: &str) -> Result<String> {
+        bail!("{prompt}: cannot ask without a terminal.")
+    }
+
+    /// Asks for a secret, without echoing it.
+    ///
+    /// # Errors
+    ///
+    /// - No answer could be given
+    fn password(&self, prompt: &str) -> Result<String> {
+        bail!("{prompt}: cannot ask without a terminal.")
+    }
+}
+
+/// Never asks anything, for scripts and embedding.
+/// Choosing between several options fails, questions are answered with their default.
+pub struct NonInteractive;
+
+impl Prompter for NonInteractive {
+    fn select(&self, prompt: &str, _items: &[String]) -> Result<usize> {
+        bail!("{prompt}: cannot choose without a terminal, specify it explicitly instead.")
+    }
+
+    fn confirm(&self, _prompt: &str, default: bool) -> Result<bool> {
+        Ok(default)
+    }
+}
@@ -0,0 +1,256 @@
+use anyhow::{Context, Result};
+use std::{
+    env::var_os,
+    ffi::OsString,
+    fmt::Write,
+    fs::{self, File, TryLockError},
+    io,
+    path::{Path, PathBuf},
+    process,
+};
+
+use crate::config::{get_build_cache_dir, read_config};
+
+/// A temporary directory under Flint's temp location, removed when dropped.
+/// Use this instead of `/tmp`, which is often a small tmpfs that big builds don't fit in.
+///
+/// Every directory has a lock file next to it, locked for as long as the directory is in use.
+/// It sits outside the directory, so nothing built or extracted in there ever sees it.
+#[derive(Debug)]
+pub struct TempDir {
+    path: PathBuf,
+    /// Held until dropped, see [`clean_stale_temp_dirs`]
+    lock: File,
+}
+
+impl TempDir {
+    /// Creates an empty temporary directory under [`get_temp_root`].
+    ///
+    /// # Errors
+    ///
+    /// - No valid home directory path could be retrieved from the operating system.
+    /// - Invalid `config.yml`
+    /// - Filesystem errors (Permissions)
+    pub fn new() -> Result<Self> {
+        Self::new_in(&get_temp_root(None)?)
+    }
+
+    /// Creates an empty temporary directory under `temp_root`.
+    ///
+    /// # Errors
+    ///
+    /// - Filesystem errors (Permissions)
+    pub fn new_in(temp_root: &Path) -> Result<Self> {
+        fs::create_dir_all(temp_root)?;
+
+        let mut random = [0; 8];
+        getrandom::fill(&mut random)?;
+        let mut name = format!("flint-{}-", process::id());
+        for byte in random {
+            // Writing to a String can't fail
+            let _ = write!(name, "{byte:02x}");
+        }
+
+        let path = temp_root.join(&name);
+
+        // Locked before it is moved into place, so a lock file without its lock is always stale
+        let tmp_lock_path = temp_root.join(format!("{name}.lock.tmp"));
+        let lock = File::create_new(&tmp_lock_path).with_context(|| {
+            format!(
+                "Could not create a temporary directory in {}",
+                temp_root.display()
+            )
+        })?;
+        lock.lock()?;
+        fs::rename(&tmp_lock_path, lock_path(&path))?;
+
+        fs::create_dir(&path).with_context(|| {
+            format!(
+                "Could not create a temporary directory in {}",
+                temp_root.display()
+            )
+        })?;
+
+        Ok(Self { path, lock })
+    }
+
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Stops the directory from being removed when dropped, eg: to look at a failed build.
+    /// It stays locked until this process exits, and is cleaned up after that.
+    #[must_use]
+    pub fn keep(self) -> PathBuf {
+        let path = self.path.clone();
+        std::mem::forget(self);
+
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        // Nothing useful to do if it fails, stale directories are cleaned up on the next start
+        let _ = fs::remove_dir_all(&self.path);
+        let _ = fs::remove_file(lock_path(&self.path));
+        let _ = self.lock.unlock();
+    }
+}
+
+/// The lock file of a temporary directory, `<name>.lock` next to it
+fn lock_path(path: &Path) -> PathBuf {
+    let mut lock_path = OsString::from(path.as_os_str());
+    lock_path.push(".lock");
+
+    PathBuf::from(lock_path)
+}
+
+/// Whether the temporary directory at `path` is still used by a running process.
+/// A directory without a lock file was left by an older Flint, and is never in use.
+fn temp_dir_in_use(path: &Path) -> Result<bool> {
+    let lock = match File::open(lock_path(path)) {
+        Ok(lock) => lock,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(err.into()),
+    };
+
+    match lock.try_lock() {
+        Ok(()) => Ok(false),
+        Err(TryLockError::WouldBlock) => Ok(true),
+        Err(TryLockError::Error(err)) => Err(err.into()),
+    }
+}
+
+/// Where temporary directories go: `FLINT_TMPDIR`, then `temp_dir` in the `config.yml` at `config_path`,
+/// then `<cache dir>/tmp`.
+///
+/// # Errors
+///
+/// - No valid home directory path could be retrieved from the operating system.
+/// - Invalid `config.yml`
+pub fn get_temp_root(config_path: Option<&Path>) -> Result<PathBuf> {
+    if let Some(path) = var_os("FLINT_TMPDIR") {
+        return Ok(PathBuf::from(path));
+    }
+
+    if let Some(path) = read_config(config_path)?.temp_dir {
+        return Ok(path);
+    }
+
+    Ok(get_build_cache_dir()?.join("tmp"))
+}
+
+/// Removes temporary directories left behind by Flint processes that are no longer running,
+/// eg: after a crash or being killed.
+///
+/// A directory is only stale once nothing holds the lock on its lock file,
+/// which the OS releases however its process ended, and whichever PID namespace it ran in.
+///
+/// # Errors
+///
+/// - Filesystem errors (Permissions)
+///
+/// # Returns
+///
+/// The number of removed directories
+pub fn clean_stale_temp_dirs(temp_root: &Path) -> Result<usize> {
+    let mut removed = 0;
+
+    let entries = match fs::read_dir(temp_root) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err.into()),
+    };
+
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().to_string();
+
+        if !name.starts_with("flint-") {
+            continue;
+        }
+
+        // Lock files whose directory is gone, eg: killed while being dropped
+        if let Some(dir_name) = name.strip_suffix(".lock") {
+            let dir_path = temp_root.join(dir_name);
+            if !dir_path.exists() && !temp_dir_in_use(&dir_path)? {
+                remove_lock_file(&path)?;
+            }
+            continue;
+        }
+
+        if !entry.file_type()?.is_dir() || temp_dir_in_use(&path)? {
+            continue;
+        }
+
+        fs::remove_dir_all(&path)?;
+        remove_lock_file(&lock_path(&path))?;
+        removed += 1;
+    }
+
+    Ok(removed)
+}
+
+/// Removes a lock file, which may have been removed already while cleaning up its directory
+fn remove_lock_file(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Whether a process is still running
+#[must_use]
+pub fn process_running(pid: u32) -> bool {
+    pid == process::id() || Path::new("/proc").join(pid.to_string()).exists()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_temp_dir() -> Result<()> {
+        let root = temp_dir::TempDir::new()?;
+
+        let temp = TempDir::new_in(root.path())?;
+        let path = temp.path().to_path_buf();
+        assert!(path.is_dir());
+        assert!(path.starts_with(root.path()));
+        assert_ne!(TempDir::new_in(root.path())?.path(), path);
+
+        drop(temp);
+        assert!(!path.exists());
+
+        let kept = TempDir::new_in(root.path())?.keep();
+        assert!(kept.is_dir());
+        fs::remove_dir(kept)?;
+
+        // Left behind by a process that no longer exists, whatever its pid was
+        let stale = root.path().join(format!("flint-{}-abcd", process::id()));
+        fs::create_dir(&stale)?;
+        fs::write(lock_path(&stale), "")?;
+        // From before lock files
+        let old = root.path().join("flint-1-abcd");
+        fs::create_dir(&old)?;
+        let orphaned_lock = root.path().join("flint-2-abcd.lock");
+        fs::write(&orphaned_lock, "")?;
+        let live = TempDir::new_in(root.path())?;
+        fs::write(root.path().join("unrelated"), "")?;
+
+        assert_eq!(clean_stale_temp_dirs(root.path())?, 2);
+        assert!(!stale.exists());
+        assert!(!lock_path(&stale).exists());
+        assert!(!old.exists());
+        assert!(!orphaned_lock.exists());
+        assert!(live.path().exists());
+        assert!(lock_path(live.path()).exists());
+        assert!(root.path().join("unrelated").exists());
+        assert_eq!(clean_stale_temp_dirs(&root.path().join("missing"))?, 0);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,206 @@
+//! A mock mirror, serving a full signed Repository (manifest, signature, chunks) over HTTP.
+
+use anyhow::Result;
+use flintpkg::{
+    chunks::save_tree,
+    repo::{
+        IncludedFeed, Mirror, PackageManifest, channels, create_repo, insert_package,
+        read_manifest, serialize_manifest, signing_request::sign_manifest,
+    },
+};
+use httpmock::prelude::*;
+use std::fs;
+use temp_dir::TempDir;
+
+/// Ways the mirror can misbehave
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    None,
+    /// The manifest signature has every bit flipped
+    CorruptSignature,
+    /// Chunks are served with their second half missing
+    TruncatedChunks,
+    /// Every request fails with a 500
+    ServerError,
+}
+
+pub struct MockMirror {
+    server: MockServer,
+    repo: TempDir,
+    chunks: TempDir,
+}
+
+impl MockMirror {
+    /// Creates an empty Repository whose only mirror is the mock server, and starts serving it.
+    pub fn start() -> Result<Self> {
+        let server = MockServer::start();
+        let repo = TempDir::new()?;
+        let chunks = TempDir::new()?;
+
+        create_repo(repo.path(), Some(repo.path()))?;
+
+        let mut manifest = read_manifest(repo.path())?;
+        manifest.mirrors = vec![Mirror::new(server.base_url())];
+        let serialized = serialize_manifest(repo.path(), &manifest)?;
+        sign_manifest(repo.path(), &serialized, Some(repo.path()))?;
+
+        let mirror = Self {
+            server,
+            repo,
+            chunks,
+        };
+        mirror.serve(Fault::None)?;
+
+        Ok(mirror)
+    }
+
+    pub fn url(&self) -> String {
+        self.server.base_url()
+    }
+
+    pub fn public_key(&self) -> Result<String> {
+        Ok(read_manifest(self.repo.path())?.public_key)
+    }
+
+    /// Includes another mirror's Repository as a feed, and re-serves the Repository.
+    pub fn include(&self, name: &str, feed: &Self) -> Result<()> {
+        let mut manifest = read_manifest(self.repo.path())?;
+        manifest
+            .includes
+            .push(IncludedFeed::new(name, &feed.url(), &feed.public_key()?)?);
+
+        let serialized = serialize_manifest(self.repo.path(), &manifest)?;
+        sign_manifest(self.repo.path(), &serialized, Some(self.repo.path()))?;
+
+        self.serve(Fault::None)
+    }
+
+    /// Moves the Repository to another manifest edition, and re-serves it.
+    pub fn set_edition(&self, edition: &str) -> Result<()> {
+        let mut manifest = read_manifest(self.repo.path())?;
+        manifest.edition = edition.to_string();
+
+        let serialized = serialize_manifest(self.repo.path(), &manifest)?;
+        sign_manifest(self.repo.path(), &serialized, Some(self.repo.path()))?;
+
+        self.serve(Fault::None)
+    }
+
+    /// Sets a channel to `package_ids`, or removes it with `None`, and re-serves the Repository.
+    pub fn set_channel(&self, channel: &str, package_ids: Option<&[String]>) -> Result<()> {
+        let mut manifest = read_manifest(self.repo.path())?;
+        match package_ids {
+            Some(package_ids) => channels::set_channel(&mut manifest, channel, package_ids)?,
+            None => channels::remove_channel(&mut manifest, channel)?,
+        }
+
+        let serialized = serialize_manifest(self.repo.path(), &manifest)?;
+        sign_manifest(self.repo.path(), &serialized, Some(self.repo.path()))?;
+
+        self.serve(Fault::None)
+    }
+
+    /// Moves the Repository's `updates_url` to a new server, serving the Repository from both.
+    pub fn redirect_updates(&self) -> Result<MockServer> {
+        let new_home = MockServer::start();
+
+        let mut manifest = read_manifest(self.repo.path())?;
+        manifest.updates_url = Some(new_home.base_url());
+
+        let serialized = serialize_manifest(self.repo.path(), &manifest)?;
+        sign_manifest(self.repo.path(), &serialized, Some(self.repo.path()))?;
+
+        self.serve(Fault::None)?;
+        self.serve_on(&new_home, Fault::None)?;
+
+        Ok(new_home)
+    }
+
+    /// Builds a package out of `files` (path, contents), publishes it and re-serves the Repository.
+    pub fn add_package(&self, id: &str, files: &[(&str, &str)]) -> Result<PackageManifest> {
+        let tree = TempDir::new()?;
+        for (path, contents) in files {
+            let path = tree.path().join(path);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(path, contents)?;
+        }
+
+        let hash_kind = read_manifest(self.repo.path())?.hash_kind;
+        let package = PackageManifest {
+            id: id.to_string(),
+            chunks: save_tree(tree.path(), self.chunks.path(), hash_kind)?,
+            ..Default::default()
+        };
+
+        insert_package(&package, self.repo.path(), Some(self.repo.path()))?;
+        self.serve(Fault::None)?;
+
+        Ok(package)
+    }
+
+    /// Replaces every route with the current Repository contents, misbehaving as `fault` says.
+    pub fn serve(&self, fault: Fault) -> Result<()> {
+        self.serve_on(&self.server, fault)
+    }
+
+    /// Like [`Self::serve`], but on another server.
+    pub fn serve_on(&self, server: &MockServer, fault: Fault) -> Result<()> {
+        server.reset();
+
+        if fault == Fault::ServerError {
+            server.mock(|when, then| {
+                when.any_request();
+                then.status(500).body("Internal Server Error");
+            });
+            return Ok(());
+        }
+
+        let manifest = fs::read(self.repo.path().join("manifest.yml"))?;
+        let mut signature = fs::read(self.repo.path().join("manifest.yml.sig"))?;
+        if fault == Fault::CorruptSignature {
+            for byte in &mut signature {
+                *byte = !*byte;
+            }
+        }
+
+        server.mock(|when, then| {
+            when.method(GET).path("/manifest.yml");
+            then.status(200).body(manifest);
+        });
+        server.mock(|when, then| {
+            when.method(GET).path("/manifest.yml.sig");
+            then.status(200).body(signature);
+        });
+
+        // Only published from the edition with binary manifests on
+        for filename in ["manifest.cbor", "manifest.cbor.sig"] {
+            let path = self.repo.path().join(filename);
+
+            if path.exists() {
+                let data = fs::read(path)?;
+                server.mock(|when, then| {
+                    when.method(GET).path(format!("/{filename}"));
+                    then.status(200).body(data);
+                });
+            }
+        }
+
+        for entry in fs::read_dir(self.chunks.path())? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            let mut data = fs::read(entry.path())?;
+            if fault == Fault::TruncatedChunks {
+                data.truncate(data.len() / 2);
+            }
+
+            server.mock(|when, then| {
+                when.method(GET).path(format!("/chunks/{name}"));
+                then.status(200).body(data);
+            });
+        }
+
+        Ok(())
+    }
+}
@@ -5,7 +5,7 @@ use temp_dir::TempDir;
 use flintpkg::{
     build::build,
     repo::{create_repo, get_installed_package},
-    run::{install_package, start},
+    run::{env::EnvPolicy, install_package, start},
 };
 
 #[tokio::test]
@@ -19,18 +19,33 @@ async fn full_workflow_test() -> Result<()> {
     create_repo(repo_path, None)?;
 
     let build_manifest_path = Path::new("build_manifest.yml");
-    build(build_manifest_path, repo_path, None, chunks_path).await?;
-
-    install_package(repo_path, "example", chunks_path).await?;
+    build(
+        build_manifest_path,
+        repo_path,
+        None,
+        chunks_path,
+        false,
+        false,
+        &|_| {},
+    )
+    .await?;
+
+    install_package(repo_path, "example", chunks_path, &|_| {}).await?;
 
     let manifest = get_installed_package(repo_path, "example")?;
 
     let args: Vec<&str> = vec!["--help"];
-    let result = start(repo_path, manifest.clone(), "flint", args)?;
+    let result = start(
+        repo_path,
+        manifest.clone(),
+        "flint",
+        args,
+        &EnvPolicy::default(),
+    )?;
     assert!(result.success());
 
     let args: Vec<&str> = vec![];
-    let result = start(repo_path, manifest, "flint", args)?;
+    let result = start(repo_path, manifest, "flint", args, &EnvPolicy::default())?;
     assert!(!result.success());
 
     Ok(())
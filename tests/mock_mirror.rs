@@ -0,0 +1,302 @@
+#![cfg(feature = "network")]
+
+mod common;
+
+use anyhow::Result;
+use std::fs;
+use temp_dir::TempDir;
+
+use common::{Fault, MockMirror};
+use flintpkg::{
+    repo::{
+        Mirror,
+        channels::select_channel,
+        diff::diff_manifests,
+        edition::{BINARY_MANIFEST_EDITION, DEFAULT_EDITION},
+        get_installed_package,
+        network::{add_included_feeds, add_repository, fetch_remote_manifest, update_repository},
+        read_manifest, read_subscribed_manifest,
+    },
+    run::install_package,
+};
+
+#[tokio::test]
+async fn add_and_install_from_mirror() -> Result<()> {
+    let mirror = MockMirror::start()?;
+    mirror.add_package("hello", &[("bin/hello", "#!/bin/sh\necho hello\n")])?;
+
+    let client = TempDir::new()?;
+    let chunks = TempDir::new()?;
+
+    let manifest = add_repository(client.path(), &mirror.url(), None, false).await?;
+    assert_eq!(manifest.mirrors, vec![Mirror::new(mirror.url())]);
+    assert!(client.path().join("manifest.yml.sig").exists());
+
+    install_package(client.path(), "hello", chunks.path(), &|_| {}).await?;
+
+    assert_eq!(get_installed_package(client.path(), "hello")?.id, "hello");
+    assert_eq!(
+        fs::read_to_string(client.path().join("installed/hello/bin/hello"))?,
+        "#!/bin/sh\necho hello\n"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn update_picks_up_new_packages() -> Result<()> {
+    let mirror = MockMirror::start()?;
+    mirror.add_package("first", &[("first.txt", "first")])?;
+
+    let client = TempDir::new()?;
+    let chunks = TempDir::new()?;
+    add_repository(client.path(), &mirror.url(), None, false).await?;
+
+    assert!(!update_repository(client.path(), false, None).await?);
+
+    mirror.add_package("second", &[("second.txt", "second")])?;
+    assert!(update_repository(client.path(), false, None).await?);
+
+    install_package(client.path(), "second", chunks.path(), &|_| {}).await?;
+    assert_eq!(
+        fs::read_to_string(client.path().join("installed/second/second.txt"))?,
+        "second"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn diff_does_not_apply_the_update() -> Result<()> {
+    let mirror = MockMirror::start()?;
+    mirror.add_package("first", &[("first.txt", "first")])?;
+
+    let client = TempDir::new()?;
+    add_repository(client.path(), &mirror.url(), None, false).await?;
+    mirror.add_package("second", &[("second.txt", "second")])?;
+
+    let local = read_manifest(client.path())?;
+    let diff = diff_manifests(&local, &fetch_remote_manifest(client.path(), false).await?);
+    assert_eq!(diff.added, vec!["second".to_string()]);
+    assert!(diff.removed.is_empty());
+    assert_eq!(read_manifest(client.path())?, local);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn update_follows_updates_url() -> Result<()> {
+    let mirror = MockMirror::start()?;
+    let client = TempDir::new()?;
+    add_repository(client.path(), &mirror.url(), None, false).await?;
+
+    let new_home = mirror.redirect_updates()?;
+    assert!(update_repository(client.path(), false, None).await?);
+    assert_eq!(
+        read_manifest(client.path())?.updates_url,
+        Some(new_home.base_url())
+    );
+
+    // Updates no longer go to the first mirror
+    mirror.serve(Fault::ServerError)?;
+    assert!(!update_repository(client.path(), false, None).await?);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn binary_manifest_follows_edition() -> Result<()> {
+    let mirror = MockMirror::start()?;
+    mirror.add_package("first", &[("first.txt", "first")])?;
+
+    let client = TempDir::new()?;
+    add_repository(client.path(), &mirror.url(), None, false).await?;
+    assert!(!client.path().join("manifest.cbor").exists());
+
+    // The edition is learnt from the YAML manifest, later updates fetch CBOR
+    mirror.set_edition(BINARY_MANIFEST_EDITION)?;
+    assert!(update_repository(client.path(), false, None).await?);
+    assert!(!client.path().join("manifest.cbor").exists());
+
+    mirror.add_package("second", &[("second.txt", "second")])?;
+    assert!(update_repository(client.path(), false, None).await?);
+
+    // The YAML manifest is replaced, not kept around stale
+    assert!(client.path().join("manifest.cbor").exists());
+    assert!(!client.path().join("manifest.yml").exists());
+    assert_eq!(read_manifest(client.path())?.packages.len(), 2);
+
+    // Back to YAML once the mirror stops publishing CBOR
+    mirror.set_edition(DEFAULT_EDITION)?;
+    assert!(update_repository(client.path(), false, None).await?);
+    assert!(!client.path().join("manifest.cbor").exists());
+    assert_eq!(read_manifest(client.path())?.edition, DEFAULT_EDITION);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn removed_channel_keeps_the_old_manifest() -> Result<()> {
+    let mirror = MockMirror::start()?;
+    mirror.add_package("app", &[("app.txt", "app")])?;
+    mirror.add_package("app-beta", &[("app.txt", "beta")])?;
+    mirror.set_channel("stable", Some(&["app".to_string()]))?;
+
+    let client = TempDir::new()?;
+    add_repository(client.path(), &mirror.url(), None, false).await?;
+    select_channel(client.path(), Some("stable"))?;
+    let serial = read_manifest(client.path())?.serial;
+
+    // Refused before anything is written, the Repository stays readable
+    mirror.set_channel("stable", None)?;
+    assert!(update_repository(client.path(), false, None).await.is_err());
+    assert_eq!(read_manifest(client.path())?.serial, serial);
+    assert_eq!(read_subscribed_manifest(client.path())?.packages.len(), 1);
+
+    select_channel(client.path(), None)?;
+    assert!(update_repository(client.path(), false, None).await?);
+    assert_eq!(read_subscribed_manifest(client.path())?.packages.len(), 2);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn corrupt_signature_is_rejected() -> Result<()> {
+    let mirror = MockMirror::start()?;
+    mirror.add_package("first", &[("first.txt", "first")])?;
+    mirror.serve(Fault::CorruptSignature)?;
+
+    let client = TempDir::new()?;
+    assert!(
+        add_repository(client.path(), &mirror.url(), None, false)
+            .await
+            .is_err()
+    );
+    assert!(!client.path().join("manifest.yml").exists());
+
+    // An existing Repository keeps its old manifest
+    mirror.serve(Fault::None)?;
+    add_repository(client.path(), &mirror.url(), None, false).await?;
+
+    mirror.add_package("second", &[("second.txt", "second")])?;
+    mirror.serve(Fault::CorruptSignature)?;
+
+    assert!(update_repository(client.path(), false, None).await.is_err());
+    let manifest = read_manifest(client.path())?;
+    assert_eq!(manifest.packages.len(), 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn truncated_chunks_fail_install() -> Result<()> {
+    let mirror = MockMirror::start()?;
+    mirror.add_package("hello", &[("hello.txt", "hello world, but longer")])?;
+
+    let client = TempDir::new()?;
+    let chunks = TempDir::new()?;
+    add_repository(client.path(), &mirror.url(), None, false).await?;
+
+    mirror.serve(Fault::TruncatedChunks)?;
+    assert!(
+        install_package(client.path(), "hello", chunks.path(), &|_| {})
+            .await
+            .is_err()
+    );
+    assert!(get_installed_package(client.path(), "hello").is_err());
+    assert_eq!(fs::read_dir(chunks.path())?.count(), 0);
+
+    // Once the mirror is fixed, the install goes through
+    mirror.serve(Fault::None)?;
+    install_package(client.path(), "hello", chunks.path(), &|_| {}).await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn server_errors_fail_cleanly() -> Result<()> {
+    let mirror = MockMirror::start()?;
+    mirror.add_package("hello", &[("hello.txt", "hello")])?;
+
+    let client = TempDir::new()?;
+    let chunks = TempDir::new()?;
+
+    mirror.serve(Fault::ServerError)?;
+    assert!(
+        add_repository(client.path(), &mirror.url(), None, false)
+            .await
+            .is_err()
+    );
+
+    mirror.serve(Fault::None)?;
+    add_repository(client.path(), &mirror.url(), None, false).await?;
+
+    mirror.serve(Fault::ServerError)?;
+    assert!(update_repository(client.path(), false, None).await.is_err());
+    assert!(
+        install_package(client.path(), "hello", chunks.path(), &|_| {})
+            .await
+            .is_err()
+    );
+    assert!(get_installed_package(client.path(), "hello").is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn included_feeds_are_added_alongside() -> Result<()> {
+    let team = MockMirror::start()?;
+    team.add_package("tool", &[("tool.txt", "tool")])?;
+    let org = MockMirror::start()?;
+    org.include("team", &team)?;
+
+    let repos = TempDir::new()?;
+    let chunks = TempDir::new()?;
+    let org_path = &repos.path().join("org");
+    fs::create_dir_all(org_path)?;
+
+    let manifest = add_repository(org_path, &org.url(), None, false).await?;
+    let added = add_included_feeds(org_path, &manifest, false).await?;
+    assert_eq!(added.len(), 1);
+    assert_eq!(added[0].0, "org.team");
+
+    let team_path = &repos.path().join("org.team");
+    install_package(team_path, "tool", chunks.path(), &|_| {}).await?;
+    assert_eq!(
+        fs::read_to_string(team_path.join("installed/tool/tool.txt"))?,
+        "tool"
+    );
+
+    // Already added, nothing to do
+    assert!(
+        add_included_feeds(org_path, &manifest, false)
+            .await?
+            .is_empty()
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn included_feeds_need_their_pinned_key() -> Result<()> {
+    let team = MockMirror::start()?;
+    let impostor = MockMirror::start()?;
+    let org = MockMirror::start()?;
+    org.include("team", &team)?;
+
+    let repos = TempDir::new()?;
+    let org_path = &repos.path().join("org");
+    fs::create_dir_all(org_path)?;
+
+    let mut manifest = add_repository(org_path, &org.url(), None, false).await?;
+    manifest.includes[0].public_key = impostor.public_key()?;
+
+    assert!(
+        add_included_feeds(org_path, &manifest, false)
+            .await
+            .is_err()
+    );
+    assert!(!repos.path().join("org.team").exists());
+
+    Ok(())
+}